@@ -17,6 +17,17 @@ Create chart name and version as used by the chart label.
 {{- end }}
 
 
+{{/*
+Name of the ServiceAccount the pods run as.
+*/}}
+{{- define "chart.serviceAccountName" -}}
+{{- if and .Values.serviceAccount .Values.serviceAccount.name -}}
+{{ .Values.serviceAccount.name }}
+{{- else -}}
+{{ .Values.name }}
+{{- end -}}
+{{- end -}}
+
 {{- define "container-env" -}}
 {{- range $k, $v := .plain }}
 - name: {{ $k }}
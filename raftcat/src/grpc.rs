@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
+use tokio::sync::mpsc;
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    protos::raftcat::{
+        self as pb,
+        raftcat_server::{Raftcat, RaftcatServer},
+        EventKind,
+    },
+    Manifest, Result, State,
+};
+
+/// How often `WatchManifests` checks the manifest cache for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+fn internal<E: std::fmt::Display>(e: E) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn to_message(mf: &Manifest) -> std::result::Result<pb::Manifest, Status> {
+    Ok(pb::Manifest {
+        name: mf.name.clone(),
+        region: mf.region.clone(),
+        namespace: mf.namespace.clone(),
+        version: mf.version.clone().unwrap_or_default(),
+        team: mf.metadata.as_ref().map(|m| m.team.clone()).unwrap_or_default(),
+        spec_json: serde_json::to_string(mf).map_err(internal)?,
+    })
+}
+
+async fn current_manifests(state: &State) -> std::result::Result<BTreeMap<String, pb::Manifest>, Status> {
+    let mut res = BTreeMap::new();
+    for (name, mf) in state.get_manifests().await.map_err(internal)? {
+        res.insert(name, to_message(&mf)?);
+    }
+    Ok(res)
+}
+
+/// Events turning the `known` manifests into the `current` manifests
+fn manifest_events(
+    known: &BTreeMap<String, pb::Manifest>,
+    current: &BTreeMap<String, pb::Manifest>,
+) -> Vec<pb::ManifestEvent> {
+    let event = |kind: EventKind, mf: &pb::Manifest| pb::ManifestEvent {
+        kind: kind as i32,
+        manifest: Some(mf.clone()),
+    };
+    let mut events = vec![];
+    for (name, mf) in current {
+        match known.get(name) {
+            None => events.push(event(EventKind::Added, mf)),
+            Some(old) if old != mf => events.push(event(EventKind::Modified, mf)),
+            Some(_) => {}
+        }
+    }
+    for (name, mf) in known {
+        if !current.contains_key(name) {
+            events.push(event(EventKind::Deleted, mf));
+        }
+    }
+    events
+}
+
+/// gRPC interface to the same state as the http handlers
+pub struct RaftcatService {
+    state: State,
+}
+
+#[tonic::async_trait]
+impl Raftcat for RaftcatService {
+    type WatchManifestsStream = mpsc::Receiver<std::result::Result<pb::ManifestEvent, Status>>;
+
+    async fn get_manifest(
+        &self,
+        req: Request<pb::GetManifestRequest>,
+    ) -> std::result::Result<Response<pb::Manifest>, Status> {
+        let name = req.into_inner().name;
+        match self.state.get_manifest(&name).await.map_err(internal)? {
+            Some(crd) => Ok(Response::new(to_message(&crd.spec)?)),
+            None => Err(Status::not_found(format!("no manifest for {}", name))),
+        }
+    }
+
+    async fn list_manifests(
+        &self,
+        req: Request<pb::ListManifestsRequest>,
+    ) -> std::result::Result<Response<pb::ListManifestsResponse>, Status> {
+        let team = req.into_inner().team;
+        let manifests = current_manifests(&self.state)
+            .await?
+            .into_iter()
+            .map(|(_, mf)| mf)
+            .filter(|mf| team.is_empty() || mf.team == team)
+            .collect();
+        Ok(Response::new(pb::ListManifestsResponse { manifests }))
+    }
+
+    async fn get_teams(
+        &self,
+        _req: Request<pb::GetTeamsRequest>,
+    ) -> std::result::Result<Response<pb::GetTeamsResponse>, Status> {
+        let cfg = self.state.get_config().await.map_err(internal)?;
+        let teams = cfg
+            .owners
+            .squads
+            .into_iter()
+            .map(|(_, s)| pb::Team {
+                name: s.name,
+                members: s.members,
+                owners: s.owners,
+            })
+            .collect();
+        Ok(Response::new(pb::GetTeamsResponse { teams }))
+    }
+
+    async fn watch_manifests(
+        &self,
+        _req: Request<pb::WatchManifestsRequest>,
+    ) -> std::result::Result<Response<Self::WatchManifestsStream>, Status> {
+        let (mut tx, rx) = mpsc::channel(64);
+        let state = self.state.clone();
+        // the reflector keeps the manifests up to date, so diff its state to find changes
+        tokio::spawn(async move {
+            let mut known = BTreeMap::new();
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let current = match current_manifests(&state).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+                for ev in manifest_events(&known, &current) {
+                    if tx.send(Ok(ev)).await.is_err() {
+                        debug!("WatchManifests client disconnected");
+                        return;
+                    }
+                }
+                known = current;
+            }
+        });
+        Ok(Response::new(rx))
+    }
+}
+
+/// Serve the gRPC api until it fails
+pub async fn serve(state: State, addr: SocketAddr) -> Result<()> {
+    info!("Starting grpc listening on {}", addr);
+    Server::builder()
+        .add_service(RaftcatServer::new(RaftcatService { state }))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use rusqlite::{params, Connection, NO_PARAMS};
+use schemars::JsonSchema;
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::Result;
+
+/// A version change of a service as observed by raftcat
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct VersionChange {
+    pub service: String,
+    /// Team owning the service at the time of the change
+    pub team: String,
+    /// Version before the change, unknown for the first observation of a service
+    pub from: Option<String>,
+    pub to: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Storage for observed version changes
+///
+/// Methods are blocking, but cheap enough for the handful of changes raftcat sees.
+pub trait HistoryStore: Send + Sync {
+    /// Store a version change
+    fn record(&self, change: &VersionChange) -> Result<()>;
+    /// Version changes of a service, oldest first
+    fn history(&self, service: &str) -> Result<Vec<VersionChange>>;
+    /// Version changes of every service after a point in time, oldest first
+    fn since(&self, at: DateTime<Utc>) -> Result<Vec<VersionChange>>;
+    /// The last recorded version of every service
+    fn latest(&self) -> Result<BTreeMap<String, String>>;
+}
+
+/// History kept in memory, lost on restarts
+#[derive(Default)]
+pub struct MemoryStore {
+    changes: Mutex<Vec<VersionChange>>,
+}
+
+impl HistoryStore for MemoryStore {
+    fn record(&self, change: &VersionChange) -> Result<()> {
+        self.changes.lock().unwrap().push(change.clone());
+        Ok(())
+    }
+
+    fn history(&self, service: &str) -> Result<Vec<VersionChange>> {
+        let changes = self.changes.lock().unwrap();
+        Ok(changes.iter().filter(|c| c.service == service).cloned().collect())
+    }
+
+    fn since(&self, at: DateTime<Utc>) -> Result<Vec<VersionChange>> {
+        let changes = self.changes.lock().unwrap();
+        Ok(changes.iter().filter(|c| c.at > at).cloned().collect())
+    }
+
+    fn latest(&self) -> Result<BTreeMap<String, String>> {
+        let changes = self.changes.lock().unwrap();
+        Ok(changes
+            .iter()
+            .map(|c| (c.service.clone(), c.to.clone()))
+            .collect())
+    }
+}
+
+/// History kept in an embedded sqlite database
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+// Timestamps are stored with a fixed format so that they sort as text
+fn format_time(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_time(at: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(at)?.with_timezone(&Utc))
+}
+
+type ChangeRow = (String, String, Option<String>, String, String);
+
+fn to_change(row: ChangeRow) -> Result<VersionChange> {
+    let (service, team, from, to, at) = row;
+    Ok(VersionChange {
+        service,
+        team,
+        from,
+        to,
+        at: parse_time(&at)?,
+    })
+}
+
+impl SqliteStore {
+    /// Open or create the database at `path`
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS version_changes (
+                service TEXT NOT NULL,
+                team TEXT NOT NULL,
+                previous TEXT,
+                version TEXT NOT NULL,
+                at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS version_changes_by_service ON version_changes (service, at);
+            CREATE INDEX IF NOT EXISTS version_changes_by_time ON version_changes (at);",
+        )?;
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn query(&self, sql: &str, arg: &str) -> Result<Vec<VersionChange>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt
+            .query_map(params![arg], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+            })?
+            .collect::<std::result::Result<Vec<ChangeRow>, _>>()?;
+        rows.into_iter().map(to_change).collect()
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn record(&self, c: &VersionChange) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO version_changes (service, team, previous, version, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![c.service, c.team, c.from, c.to, format_time(&c.at)],
+        )?;
+        Ok(())
+    }
+
+    fn history(&self, service: &str) -> Result<Vec<VersionChange>> {
+        self.query(
+            "SELECT service, team, previous, version, at FROM version_changes
+             WHERE service = ?1 ORDER BY at, rowid",
+            service,
+        )
+    }
+
+    fn since(&self, at: DateTime<Utc>) -> Result<Vec<VersionChange>> {
+        self.query(
+            "SELECT service, team, previous, version, at FROM version_changes
+             WHERE at > ?1 ORDER BY at, rowid",
+            &format_time(&at),
+        )
+    }
+
+    fn latest(&self) -> Result<BTreeMap<String, String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT service, version FROM version_changes ORDER BY at, rowid")?;
+        let rows = stmt
+            .query_map(NO_PARAMS, |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<std::result::Result<Vec<(String, String)>, _>>()?;
+        Ok(rows.into_iter().collect()) // later rows replace earlier versions
+    }
+}
+
+/// Deploys of a team over a period
+#[derive(Serialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeployFrequency {
+    /// Number of version changes of the team's services
+    pub deploys: usize,
+    /// Average deploys per day over the period
+    pub per_day: f64,
+}
+
+/// Deploy frequency per team over the last `days`
+///
+/// First observations of services are not deploys, so they are left out.
+pub fn deploy_frequency(store: &dyn HistoryStore, days: u32) -> Result<BTreeMap<String, DeployFrequency>> {
+    let since = Utc::now() - Duration::days(i64::from(days));
+    let mut res: BTreeMap<String, DeployFrequency> = BTreeMap::new();
+    for c in store.since(since)?.into_iter().filter(|c| c.from.is_some()) {
+        res.entry(c.team).or_default().deploys += 1;
+    }
+    for f in res.values_mut() {
+        f.per_day = f.deploys as f64 / f64::from(days.max(1));
+    }
+    Ok(res)
+}
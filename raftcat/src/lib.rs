@@ -20,5 +20,18 @@ pub mod integrations;
 pub mod state;
 pub use state::State;
 
+/// Version history of services
+pub mod history;
+
+/// Prometheus metrics
+pub mod metrics;
+pub use metrics::Metrics;
+
 pub mod kompass;
 pub mod protos;
+
+/// gRPC api alongside the http api
+pub mod grpc;
+
+/// OpenAPI document of the http api
+pub mod openapi;
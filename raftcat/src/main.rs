@@ -1,15 +1,16 @@
 #![allow(unused_imports, unused_variables)]
 #[macro_use] extern crate log;
 
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
     io,
+    time::Instant,
 };
 
-use chrono::Local;
+use chrono::{Local, Utc};
 use reqwest::Url;
-use shipcat_definitions::Manifest;
+use shipcat_definitions::{request::ApplyRequest, Manifest};
 use std::env;
 
 pub use raftcat::*;
@@ -22,6 +23,7 @@ fn find_team(owners: &Owners, slug: &str) -> Option<Squad> {
 // Web server interface
 use actix_files as fs;
 use actix_web::{
+    dev::Service,
     http::HeaderValue,
     middleware,
     web::{self, Data},
@@ -70,6 +72,148 @@ async fn get_versions(c: Data<State>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(vers))
 }
 
+async fn get_service_history(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    if c.get_manifest(name).await?.is_some() {
+        Ok(HttpResponse::Ok().json(c.get_history(name)?))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+#[derive(Deserialize)]
+struct FrequencyParams {
+    days: Option<u32>,
+}
+
+async fn get_deploy_frequency(c: Data<State>, params: web::Query<FrequencyParams>) -> Result<HttpResponse> {
+    let days = params.days.unwrap_or(30);
+    Ok(HttpResponse::Ok().json(c.get_deploy_frequency(days)?))
+}
+
+/// Tokens of the bots allowed to change the cluster, as `requester:token` pairs separated by commas
+const WRITE_TOKENS: &str = "RAFTCAT_WRITE_TOKENS";
+
+/// Compare two secrets in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The requester owning the bearer token of a request to a write endpoint
+///
+/// Requests are rejected when the token does not belong to anyone,
+/// and writes are disabled entirely when no tokens are configured.
+fn writer(req: &HttpRequest) -> std::result::Result<String, HttpResponse> {
+    let tokens = env::var(WRITE_TOKENS).unwrap_or_default();
+    let tokens: Vec<(&str, &str)> = tokens
+        .split(',')
+        .filter_map(|pair| {
+            let mut kv = pair.trim().splitn(2, ':');
+            match (kv.next(), kv.next()) {
+                (Some(r), Some(t)) if !r.is_empty() && !t.is_empty() => Some((r, t)),
+                _ => None,
+            }
+        })
+        .collect();
+    if tokens.is_empty() {
+        return Err(HttpResponse::Forbidden().finish());
+    }
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // check every token so the time taken does not reveal which one matched
+    let mut requester = None;
+    for (r, t) in tokens {
+        if constant_time_eq(given.as_bytes(), t.as_bytes()) {
+            requester = Some(r.to_string());
+        }
+    }
+    requester.ok_or_else(|| HttpResponse::Unauthorized().finish())
+}
+
+#[derive(Deserialize)]
+struct ApplyParams {
+    version: Option<String>,
+}
+
+fn apply_request(requester: String, service: Option<String>, version: Option<String>) -> ApplyRequest {
+    ApplyRequest {
+        service,
+        version,
+        requester,
+        requested: Utc::now(),
+    }
+}
+
+async fn post_apply(
+    c: Data<State>,
+    params: web::Query<ApplyParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let requester = match writer(&req) {
+        Ok(r) => r,
+        Err(rejection) => return Ok(rejection),
+    };
+    let name = req.match_info().get("name").unwrap();
+    if c.get_manifest(name).await?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let apply = apply_request(requester, Some(name.to_string()), params.version.clone());
+    if c.request_apply(apply.clone()).await? {
+        Ok(HttpResponse::Accepted().json(apply))
+    } else {
+        Ok(HttpResponse::Conflict().finish())
+    }
+}
+
+async fn post_reconcile(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let requester = match writer(&req) {
+        Ok(r) => r,
+        Err(rejection) => return Ok(rejection),
+    };
+    let reconcile = apply_request(requester, None, None);
+    if c.request_apply(reconcile.clone()).await? {
+        Ok(HttpResponse::Accepted().json(reconcile))
+    } else {
+        Ok(HttpResponse::Conflict().finish())
+    }
+}
+
+#[derive(Serialize)]
+struct ApplyStatus {
+    /// Request still waiting for the worker
+    pending: Option<ApplyRequest>,
+    /// Conditions of the last apply, from the manifest status
+    conditions: Option<serde_json::Value>,
+}
+
+async fn get_apply(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    if let Some(mf) = c.get_manifest(name).await? {
+        let conditions = match mf.status {
+            Some(s) => Some(serde_json::to_value(&s.conditions)?),
+            None => None,
+        };
+        let pending = c.get_apply_request(Some(name)).await?;
+        Ok(HttpResponse::Ok().json(ApplyStatus { pending, conditions }))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+async fn get_openapi() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(openapi::document()))
+}
+
+async fn get_metrics(c: Data<State>, m: Data<Metrics>) -> Result<HttpResponse> {
+    m.update(&c.get_manifest_crds().await?);
+    let (body, content_type) = m.render()?;
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
 async fn get_kompass_hub_services(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let req_token = req.headers().get("Authorization");
     if req_token.is_none() {
@@ -176,6 +320,14 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
         ctx.insert("mfenv", &mf.env);
         ctx.insert("mfenvstub", &mfstub.env);
         ctx.insert("mfdeps", &mf.dependencies);
+        if let Some(d) = mf.disabled_in_region() {
+            ctx.insert("disabled", d);
+        }
+        if let Some(s) = &md.sunset {
+            ctx.insert("sunset", s);
+            ctx.insert("sunset_state", &s.state().to_string().to_lowercase());
+            ctx.insert("sunset_days", &s.days_left());
+        }
 
         if let Some(status) = mfobj.status {
             let conds = &status.conditions;
@@ -216,6 +368,9 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
             ctx.insert("cost", &usagen.daily_cost());
             ctx.insert("rollouts", &mf.estimate_rollout_iterations());
         }
+        if let Some(slos) = &mf.slos {
+            ctx.insert("slos", &serde_json::to_string_pretty(slos)?);
+        }
         if let Some(ru) = mf.rollingUpdate {
             ctx.insert("rollingUpdate", &serde_json::to_string_pretty(&ru)?);
         }
@@ -239,7 +394,11 @@ async fn health() -> Result<HttpResponse> {
 
 async fn get_config(c: Data<State>, _req: HttpRequest) -> Result<HttpResponse> {
     let cfg = c.get_config().await?;
-    Ok(HttpResponse::Ok().json(cfg))
+    let gen = c.get_config_generation();
+    Ok(HttpResponse::Ok()
+        .header("X-Config-Generation", gen.generation.to_string())
+        .header("X-Config-Loaded", gen.loaded.to_rfc3339())
+        .json(cfg))
 }
 
 #[derive(Serialize)]
@@ -324,34 +483,72 @@ async fn main() -> io::Result<()> {
         tokio::spawn(kompass::register(kompass_url, region_url));
     }
 
+    let grpc_addr = "0.0.0.0:8081".parse().expect("valid grpc address");
+    let grpc_state = shared_state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = grpc::serve(grpc_state, grpc_addr).await {
+            error!("grpc server failed: {}", e);
+        }
+    });
+
+    let metrics = Metrics::new().expect("Failed to register metrics");
+
     info!("Starting listening on 0.0.0.0:8080");
     HttpServer::new(move || {
+        let m = metrics.clone();
         App::new()
             .data(shared_state.clone())
+            .data(metrics.clone())
             .wrap(
                 middleware::Logger::default()
                     .exclude("/health")
+                    .exclude("/metrics")
                     .exclude("/raftcat/health")
                     .exclude("/favicon.ico")
                     .exclude("/raftcat/static/*.png")
                     .exclude("/raftcat/static/images/*.png"),
             )
-            //.wrap(prometheus.clone())
+            .wrap_fn(move |req, srv| {
+                let start = Instant::now();
+                let path = req.path().to_string();
+                let method = req.method().to_string();
+                let m = m.clone();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await;
+                    if let Ok(r) = &res {
+                        m.observe_request(&path, &method, r.status().as_u16(), start.elapsed());
+                    }
+                    res
+                }
+            })
             //.wrap(sentry_actix...)
             .service(fs::Files::new("/raftcat/static", "./raftcat/static").index_file("index.html"))
             .service(web::resource("/raftcat/config").route(web::get().to(get_config)))
+            .service(web::resource("/raftcat/openapi.json").route(web::get().to(get_openapi)))
             .service(
                 web::resource("/raftcat/manifests/{name}/resources").route(web::get().to(get_resource_usage)),
             )
             .service(web::resource("/raftcat/manifests/{name}").route(web::get().to(get_single_manifest)))
             .service(web::resource("/raftcat/manifests").route(web::get().to(get_all_manifests)))
+            .service(
+                web::resource("/raftcat/services/{name}/history").route(web::get().to(get_service_history)),
+            )
             .service(web::resource("/raftcat/services/{name}").route(web::get().to(get_service)))
             .service(web::resource("/raftcat/teams/{name}").route(web::get().to(get_manifests_for_team)))
             .service(web::resource("/raftcat/teams").route(web::get().to(get_teams)))
             .service(web::resource("/raftcat/health").route(web::get().to(health)))
             .service(web::resource("/raftcat/versions").route(web::get().to(get_versions)))
+            .service(web::resource("/raftcat/deploy-frequency").route(web::get().to(get_deploy_frequency)))
             .service(web::resource("/raftcat/kompass-hub").route(web::get().to(get_kompass_hub_services)))
+            .service(
+                web::resource("/raftcat/apply/{name}")
+                    .route(web::get().to(get_apply))
+                    .route(web::post().to(post_apply)),
+            )
+            .service(web::resource("/raftcat/reconcile").route(web::post().to(post_reconcile)))
             .service(web::resource("/health").route(web::get().to(health))) // redundancy
+            .service(web::resource("/metrics").route(web::get().to(get_metrics)))
             .service(web::resource("/raftcat/").route(web::get().to(index)))
     })
     .bind("0.0.0.0:8080")
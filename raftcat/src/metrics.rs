@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use shipcat_definitions::ShipcatManifest;
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::Result;
+
+/// Prometheus metrics served on `/metrics`
+///
+/// Request latencies are observed by a middleware, everything else is derived
+/// from the manifest cache when scraped.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: HistogramVec,
+    manifests: IntGauge,
+    reconcile_lag: GaugeVec,
+    team_services: IntGaugeVec,
+    team_cpu: GaugeVec,
+    team_memory: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let requests = HistogramVec::new(
+            HistogramOpts::new(
+                "raftcat_http_request_duration_seconds",
+                "Latency of http requests",
+            ),
+            &["endpoint", "method", "status"],
+        )?;
+        let manifests = IntGauge::new("raftcat_manifests_cached", "ShipcatManifests in the cache")?;
+        let reconcile_lag = GaugeVec::new(
+            Opts::new(
+                "raftcat_reconcile_lag_seconds",
+                "Seconds since a manifest was applied without its version rolling out",
+            ),
+            &["service", "team"],
+        )?;
+        let team_services =
+            IntGaugeVec::new(Opts::new("raftcat_team_services", "Services per team"), &["team"])?;
+        let team_cpu = GaugeVec::new(
+            Opts::new("raftcat_team_requested_cpu_cores", "Requested cpu cores per team"),
+            &["team"],
+        )?;
+        let team_memory = GaugeVec::new(
+            Opts::new("raftcat_team_requested_memory_bytes", "Requested memory per team"),
+            &["team"],
+        )?;
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(manifests.clone()))?;
+        registry.register(Box::new(reconcile_lag.clone()))?;
+        registry.register(Box::new(team_services.clone()))?;
+        registry.register(Box::new(team_cpu.clone()))?;
+        registry.register(Box::new(team_memory.clone()))?;
+        Ok(Metrics {
+            registry,
+            requests,
+            manifests,
+            reconcile_lag,
+            team_services,
+            team_cpu,
+            team_memory,
+        })
+    }
+
+    /// Record how long a request took
+    ///
+    /// Unmatched paths share a label, since anyone can request them.
+    pub fn observe_request(&self, path: &str, method: &str, status: u16, took: Duration) {
+        let endpoint = if status == 404 {
+            "unmatched".to_string()
+        } else {
+            endpoint(path)
+        };
+        self.requests
+            .with_label_values(&[&endpoint, method, &status.to_string()])
+            .observe(took.as_secs_f64());
+    }
+
+    /// Recompute the gauges derived from the manifest cache
+    ///
+    /// Gauges are reset first so that deleted services and teams disappear.
+    pub fn update(&self, crds: &[ShipcatManifest]) {
+        let now = Utc::now();
+        self.manifests.set(crds.len() as i64);
+        self.reconcile_lag.reset();
+        self.team_services.reset();
+        self.team_cpu.reset();
+        self.team_memory.reset();
+        let mut teams: BTreeMap<String, (i64, f64, f64)> = BTreeMap::new();
+        for crd in crds {
+            let mf = &crd.spec;
+            let team = mf.metadata.as_ref().map(|md| md.team.clone()).unwrap_or_default();
+            self.reconcile_lag
+                .with_label_values(&[&mf.name, &team])
+                .set(reconcile_lag(crd, now));
+            let entry = teams.entry(team).or_default();
+            entry.0 += 1;
+            match mf.compute_resource_totals() {
+                Ok(totals) => {
+                    entry.1 += totals.base.requests.cpu;
+                    entry.2 += totals.base.requests.memory;
+                }
+                Err(e) => warn!("Unable to compute resources of {}: {}", mf.name, e),
+            }
+        }
+        for (team, (services, cpu, memory)) in teams {
+            self.team_services.with_label_values(&[&team]).set(services);
+            self.team_cpu.with_label_values(&[&team]).set(cpu);
+            self.team_memory.with_label_values(&[&team]).set(memory);
+        }
+    }
+
+    /// Metrics in the prometheus text format, with its content type
+    pub fn render(&self) -> Result<(String, String)> {
+        let encoder = TextEncoder::new();
+        let mut buffer = vec![];
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok((String::from_utf8(buffer)?, encoder.format_type().to_string()))
+    }
+}
+
+/// Seconds since a manifest was applied, while its version has not rolled out
+fn reconcile_lag(crd: &ShipcatManifest, now: DateTime<Utc>) -> f64 {
+    let summary = match crd.status.as_ref().and_then(|s| s.summary.as_ref()) {
+        Some(s) => s,
+        None => return 0.0,
+    };
+    if summary.last_successful_rollout_version == crd.spec.version {
+        return 0.0;
+    }
+    summary
+        .last_apply
+        .as_ref()
+        .and_then(|a| a.parse::<DateTime<Utc>>().ok())
+        .map_or(0.0, |t| (now - t).num_seconds().max(0) as f64)
+}
+
+/// Endpoint label for a request path
+///
+/// Service and team names are replaced to keep the number of label values bounded.
+fn endpoint(path: &str) -> String {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["raftcat", "static", ..] => "/raftcat/static".into(),
+        ["raftcat", kind, _, rest @ ..] if ["manifests", "services", "teams"].contains(kind) => {
+            let mut res = format!("/raftcat/{}/{{name}}", kind);
+            for s in rest {
+                res.push('/');
+                res.push_str(s);
+            }
+            res
+        }
+        _ => path.to_string(),
+    }
+}
@@ -0,0 +1,122 @@
+use schemars::{gen::SchemaSettings, schema::Schema};
+use serde_json::{json, Value};
+use shipcat_definitions::math::ResourceTotals;
+use std::collections::BTreeMap;
+
+use crate::{
+    history::{DeployFrequency, VersionChange},
+    state::VersionMap,
+    Config, Squad,
+};
+
+/// Reference to the free-form manifest component
+///
+/// Manifests are documented by `shipcat schema manifest` rather than here,
+/// as their completed form pulls in kubernetes types without schemas.
+fn manifest_ref() -> Value {
+    json!({ "$ref": "#/components/schemas/Manifest" })
+}
+
+/// A GET operation answering with json
+///
+/// Operations with path parameters answer 404 for unknown names.
+fn get(summary: &str, params: &[Value], schema: Value) -> Value {
+    let mut responses = json!({
+        "200": {
+            "description": "OK",
+            "content": { "application/json": { "schema": schema } }
+        }
+    });
+    if params.iter().any(|p| p["in"] == "path") {
+        responses["404"] = json!({ "description": "Not found" });
+    }
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": params,
+            "responses": responses
+        }
+    })
+}
+
+/// A path parameter
+fn path_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" }
+    })
+}
+
+/// OpenAPI 3 document for the JSON endpoints of raftcat
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let schema = |s: Schema| serde_json::to_value(s).unwrap();
+    let config = schema(gen.subschema_for::<Config>());
+    let resources = schema(gen.subschema_for::<ResourceTotals>());
+    let squads = schema(gen.subschema_for::<BTreeMap<String, Squad>>());
+    let versions = schema(gen.subschema_for::<VersionMap>());
+    let history = schema(gen.subschema_for::<Vec<VersionChange>>());
+    let frequency = schema(gen.subschema_for::<BTreeMap<String, DeployFrequency>>());
+    let team_services = schema(gen.subschema_for::<Vec<String>>());
+
+    let mut components = serde_json::to_value(gen.take_definitions()).unwrap();
+    components["Manifest"] = json!({
+        "type": "object",
+        "description": "A completed shipcat manifest, see `shipcat schema manifest` for its fields",
+        "required": ["name"],
+        "properties": {
+            "name": { "type": "string" },
+            "version": { "type": "string" },
+            "regions": { "type": "array", "items": { "type": "string" } },
+        },
+        "additionalProperties": true
+    });
+
+    let service = path_param("name", "Name of the service");
+    let days = json!({
+        "name": "days",
+        "in": "query",
+        "required": false,
+        "description": "Length of the period in days, defaulting to 30",
+        "schema": { "type": "integer", "minimum": 0 }
+    });
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": "raftcat",
+            "description": "Read api for the shipcat manifests of a region",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/raftcat/manifests": get(
+                "Manifests by service name",
+                &[],
+                json!({ "type": "object", "additionalProperties": manifest_ref() }),
+            ),
+            "/raftcat/manifests/{name}": get("Manifest of a service", &[service.clone()], manifest_ref()),
+            "/raftcat/manifests/{name}/resources": get(
+                "Resources requested by a service",
+                &[service.clone()],
+                resources,
+            ),
+            "/raftcat/config": get("Config of the region", &[], config),
+            "/raftcat/teams": get("Squads by name", &[], squads),
+            "/raftcat/teams/{name}": get(
+                "Services of a team",
+                &[path_param("name", "Name of the squad")],
+                team_services,
+            ),
+            "/raftcat/versions": get("Versions by service name", &[], versions),
+            "/raftcat/services/{name}/history": get(
+                "Version changes of a service, oldest first",
+                &[service],
+                history,
+            ),
+            "/raftcat/deploy-frequency": get("Deploys of every team", &[days], frequency),
+        },
+        "components": { "schemas": components }
+    })
+}
@@ -1,2 +1,7 @@
 pub mod registration_api;
 pub mod services;
+
+/// The raftcat gRPC api generated from `proto/raftcat.proto`
+pub mod raftcat {
+    tonic::include_proto!("raftcat");
+}
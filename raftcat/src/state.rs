@@ -1,20 +1,23 @@
+use chrono::{DateTime, Utc};
 use failure::err_msg;
 use kube::{
-    api::{ListParams, Meta, Resource},
+    api::{Api, ListParams, Meta, PostParams, Resource},
     client::APIClient,
     config::Configuration,
     runtime::Reflector,
 };
-use shipcat_definitions::{ShipcatConfig, ShipcatManifest};
+use shipcat_definitions::{request::ApplyRequest, ShipcatApplyRequest, ShipcatConfig, ShipcatManifest};
 use tera::compile_templates;
 
 use std::{
     collections::BTreeMap,
     env,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use crate::{
+    history::{self, DeployFrequency, HistoryStore, MemoryStore, SqliteStore, VersionChange},
     integrations::{
         newrelic::{self, RelicMap},
         sentryapi::{self, SentryMap},
@@ -25,6 +28,51 @@ use crate::{
 /// Map of service -> versions
 pub type VersionMap = BTreeMap<String, String>;
 
+/// How often the config crd is checked for changes
+const CONFIG_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often manifests are checked for version changes to record
+const HISTORY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The config and region currently served
+///
+/// Swapped as a whole so that handlers never see a config without its region.
+struct LoadedConfig {
+    config: Config,
+    region: Region,
+    /// resourceVersion of the config crd this was loaded from
+    version: String,
+    generation: ConfigGeneration,
+}
+
+/// Which load of the config crd is being served
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigGeneration {
+    /// Number of times the config has been loaded since startup
+    pub generation: u64,
+    /// When this config was loaded
+    pub loaded: DateTime<Utc>,
+}
+
+impl LoadedConfig {
+    fn new(crd: ShipcatConfig, region: &str, generation: u64) -> Result<Self> {
+        let version = Meta::resource_ver(&crd).unwrap_or_default();
+        let config = crd.spec;
+        let region = config
+            .get_region(region)
+            .map_err(|e| err_msg(format!("could not resolve cluster for {}: {}", region, e)))?;
+        Ok(LoadedConfig {
+            config,
+            region,
+            version,
+            generation: ConfigGeneration {
+                generation,
+                loaded: Utc::now(),
+            },
+        })
+    }
+}
+
 /// The canonical shared state for actix
 ///
 /// Consumers of these (http handlers) should use public impls on this struct only.
@@ -34,12 +82,18 @@ pub type VersionMap = BTreeMap<String, String>;
 pub struct State {
     manifests: Reflector<ShipcatManifest>,
     configs: Reflector<ShipcatConfig>,
-    relics: RelicMap,
-    sentries: SentryMap,
+    config: Arc<RwLock<LoadedConfig>>,
+    relics: Arc<RwLock<RelicMap>>,
+    sentries: Arc<RwLock<SentryMap>>,
+    history: Arc<dyn HistoryStore>,
+    /// Versions last recorded in the history
+    recorded: Arc<RwLock<VersionMap>>,
     /// Templates via tera which do not implement clone
     template: Arc<RwLock<tera::Tera>>,
     region: String,
-    config_name: String,
+    /// Client and namespace for requests to the in-cluster worker
+    client: APIClient,
+    namespace: String,
 }
 
 /// Note that these functions unwrap a lot and expect errors to just be caught by sentry.
@@ -64,28 +118,31 @@ impl State {
         let manifests = Reflector::new(client.clone(), lp.clone(), mfresource)
             .init()
             .await?;
-        let configs = Reflector::new(client, lp, cfgresource).init().await?;
-        // Use federated config if available:
-        let is_federated = configs
-            .state()
-            .await?
-            .iter()
-            .any(|crd| Meta::name(crd) == "unionised");
-        let config_name = if is_federated {
-            "unionised".into()
+        let configs = Reflector::new(client.clone(), lp, cfgresource).init().await?;
+        let crd = find_config(&configs, &region).await?;
+        let config = LoadedConfig::new(crd, &region, 1)?;
+        let history: Arc<dyn HistoryStore> = if let Ok(path) = env::var("HISTORY_DB") {
+            info!("Recording version history in {}", path);
+            Arc::new(SqliteStore::open(&path)?)
         } else {
-            region.clone()
+            warn!("No HISTORY_DB set, version history is lost on restarts");
+            Arc::new(MemoryStore::default())
         };
-        let mut res = State {
+        let recorded = history.latest()?;
+        let res = State {
             manifests,
             configs,
             region,
-            config_name,
-            relics: BTreeMap::new(),
-            sentries: BTreeMap::new(),
+            config: Arc::new(RwLock::new(config)),
+            relics: Arc::new(RwLock::new(BTreeMap::new())),
+            sentries: Arc::new(RwLock::new(BTreeMap::new())),
+            history,
+            recorded: Arc::new(RwLock::new(recorded)),
             template: Arc::new(RwLock::new(t)),
+            client,
+            namespace: ns,
         };
-        res.update_slow_cache().await?;
+        res.update_slow_cache().await;
         Ok(res)
     }
 
@@ -109,13 +166,17 @@ impl State {
         Ok(xs)
     }
 
+    /// Cached manifest crds, including their status
+    pub async fn get_manifest_crds(&self) -> Result<Vec<ShipcatManifest>> {
+        Ok(self.manifests.state().await?)
+    }
+
     pub async fn get_config(&self) -> Result<Config> {
-        let cfgs = self.configs.state().await?;
-        if let Some(cfg) = cfgs.into_iter().find(|c| Meta::name(c) == self.config_name) {
-            Ok(cfg.spec)
-        } else {
-            bail!("Failed to find config for {}", self.region);
-        }
+        Ok(self.config.read().unwrap().config.clone())
+    }
+
+    pub fn get_config_generation(&self) -> ConfigGeneration {
+        self.config.read().unwrap().generation.clone()
     }
 
     pub async fn get_versions(&self) -> Result<VersionMap> {
@@ -132,9 +193,7 @@ impl State {
     }
 
     pub async fn get_region(&self) -> Result<Region> {
-        let cfg = self.get_config().await?;
-        cfg.get_region(&self.region)
-            .map_err(|e| err_msg(format!("could not resolve cluster for {}: {}", self.region, e)))
+        Ok(self.config.read().unwrap().region.clone())
     }
 
     pub async fn get_manifest(&self, key: &str) -> Result<Option<ShipcatManifest>> {
@@ -169,12 +228,43 @@ impl State {
         Ok(res)
     }
 
+    pub fn get_history(&self, service: &str) -> Result<Vec<VersionChange>> {
+        self.history.history(service)
+    }
+
+    pub fn get_deploy_frequency(&self, days: u32) -> Result<BTreeMap<String, DeployFrequency>> {
+        history::deploy_frequency(self.history.as_ref(), days)
+    }
+
     pub fn get_newrelic_link(&self, service: &str) -> Option<String> {
-        self.relics.get(service).map(String::to_owned)
+        self.relics.read().unwrap().get(service).map(String::to_owned)
     }
 
     pub fn get_sentry_slug(&self, service: &str) -> Option<String> {
-        self.sentries.get(service).map(String::to_owned)
+        self.sentries.read().unwrap().get(service).map(String::to_owned)
+    }
+
+    /// Ask the in-cluster worker to apply a service, or reconcile the region
+    ///
+    /// Returns false when the same request is already waiting.
+    pub async fn request_apply(&self, req: ApplyRequest) -> Result<bool> {
+        let api: Api<ShipcatApplyRequest> = Api::namespaced(self.client.clone(), &self.namespace);
+        let obj = ShipcatApplyRequest::new(&req.object_name(), req);
+        match api.create(&PostParams::default(), &obj).await {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// A request still waiting for, or being executed by, the in-cluster worker
+    pub async fn get_apply_request(&self, service: Option<&str>) -> Result<Option<ApplyRequest>> {
+        let api: Api<ShipcatApplyRequest> = Api::namespaced(self.client.clone(), &self.namespace);
+        match api.get(&ApplyRequest::name_for(service)).await {
+            Ok(r) => Ok(Some(r.spec)),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
     // Interface for internal thread
@@ -199,16 +289,90 @@ impl State {
                 }
             }
         });
+        // The reflector only reports changes through its state, so check it for config changes.
+        // A config that fails to load is logged, and the last good config stays in use.
+        let c3 = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CONFIG_RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = c3.reload_config().await {
+                    error!("Failed to reload config: {}", e);
+                }
+            }
+        });
+        let c4 = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HISTORY_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = c4.record_versions().await {
+                    error!("Failed to record version history: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Record versions that changed since the last check in the history
+    async fn record_versions(&self) -> Result<()> {
+        let mfs = self.get_manifests().await?;
+        let now = Utc::now();
+        let mut recorded = self.recorded.write().unwrap();
+        for (name, mf) in mfs {
+            let version = match mf.version {
+                Some(v) => v,
+                None => continue,
+            };
+            let from = recorded.get(&name).cloned();
+            if from.as_ref() == Some(&version) {
+                continue;
+            }
+            let change = VersionChange {
+                service: name.clone(),
+                team: mf.metadata.map(|md| md.team).unwrap_or_default(),
+                from,
+                to: version.clone(),
+                at: now,
+            };
+            debug!("Recording {} {:?} -> {}", name, change.from, change.to);
+            self.history.record(&change)?;
+            recorded.insert(name, version);
+        }
         Ok(())
     }
 
-    async fn update_slow_cache(&mut self) -> Result<()> {
-        let region = self.get_region().await?;
+    /// Swap in the config crd if it changed since it was last loaded
+    ///
+    /// Caches built from the old region are dropped and rebuilt from the new one.
+    async fn reload_config(&self) -> Result<()> {
+        let crd = find_config(&self.configs, &self.region).await?;
+        let generation = {
+            let current = self.config.read().unwrap();
+            if Meta::resource_ver(&crd).unwrap_or_default() == current.version {
+                return Ok(());
+            }
+            current.generation.generation + 1
+        };
+        let config = LoadedConfig::new(crd, &self.region, generation)?;
+        info!(
+            "Loaded config generation {} at version {}",
+            generation, config.version
+        );
+        *self.config.write().unwrap() = config;
+        self.relics.write().unwrap().clear();
+        self.sentries.write().unwrap().clear();
+        self.update_slow_cache().await;
+        Ok(())
+    }
+
+    async fn update_slow_cache(&self) {
+        let region = self.config.read().unwrap().region.clone();
         if let Some(s) = region.sentry {
             match sentryapi::get_slugs(&s.url, &region.environment.to_string()).await {
                 Ok(res) => {
-                    self.sentries = res;
-                    info!("Loaded {} sentry slugs", self.sentries.len());
+                    info!("Loaded {} sentry slugs", res.len());
+                    *self.sentries.write().unwrap() = res;
                 }
                 Err(e) => warn!("Unable to load sentry slugs: {}", err_msg(e)),
             }
@@ -217,12 +381,23 @@ impl State {
         }
         match newrelic::get_links(&region.name).await {
             Ok(res) => {
-                self.relics = res;
-                info!("Loaded {} newrelic links", self.relics.len());
+                info!("Loaded {} newrelic links", res.len());
+                *self.relics.write().unwrap() = res;
             }
             Err(e) => warn!("Unable to load newrelic projects. {}", err_msg(e)),
         }
-        Ok(())
+    }
+}
+
+/// Find the config crd for a region, using the federated config if available
+async fn find_config(configs: &Reflector<ShipcatConfig>, region: &str) -> Result<ShipcatConfig> {
+    let cfgs = configs.state().await?;
+    let federated = cfgs.iter().any(|crd| Meta::name(crd) == "unionised");
+    let name = if federated { "unionised" } else { region };
+    if let Some(cfg) = cfgs.into_iter().find(|c| Meta::name(c) == name) {
+        Ok(cfg)
+    } else {
+        bail!("Failed to find config for {}", region);
     }
 }
 
@@ -0,0 +1,63 @@
+use super::Result;
+use crate::{apply::UpgradeInfo, grafana, webhooks::UpgradeState};
+use shipcat_definitions::{sentry::Sentry, Config, Region};
+
+/// Deployment markers posted for an upgrade
+#[derive(Debug, Default)]
+pub struct Markers {
+    /// Id of the grafana annotation
+    pub grafana: Option<u64>,
+    /// Whether a sentry deploy was recorded
+    pub sentry: bool,
+}
+
+/// Record a deploy of a completed upgrade in the service's sentry project
+///
+/// Requires `sentry` configured for the region, and the admin token in vault.
+/// Returns false when there is nothing to mark.
+pub async fn sentry(info: &UpgradeInfo, reg: &Region) -> Result<bool> {
+    if reg.sentry.is_none() {
+        return Ok(false);
+    }
+    let vault = reg.secret_store()?;
+    let client = Sentry::regional(reg, &*vault).await?;
+    let marked = client.deploy(&info.name, &info.version, &reg.name).await?;
+    if !marked {
+        debug!("No sentry project to mark a deploy of {} in", info.name);
+    }
+    Ok(marked)
+}
+
+/// Post deployment markers for an upgrade to every integration the region configures
+///
+/// Grafana is annotated on every outcome, whereas sentry only gets deploys of completed upgrades.
+pub async fn markers(us: &UpgradeState, info: &UpgradeInfo, reg: &Region) -> Result<Markers> {
+    let grafana = grafana::annotate(us, info, reg).await?;
+    let sentry = if *us == UpgradeState::Completed {
+        sentry(info, reg).await?
+    } else {
+        false
+    };
+    Ok(Markers { grafana, sentry })
+}
+
+/// Manually mark a version of a service as deployed
+///
+/// For versions rolled out outside of `shipcat apply`.
+pub async fn manual(svc: &str, version: &str, conf: &Config, reg: &Region) -> Result<Markers> {
+    let mut mf = shipcat_filebacked::load_manifest(svc, conf, reg).await?;
+    mf.version = Some(version.to_string());
+    let mut info = UpgradeInfo::new(&mf);
+    info.cluster = Some(reg.cluster.clone());
+    let res = markers(&UpgradeState::Completed, &info, reg).await?;
+    if let Some(id) = res.grafana {
+        info!("Created grafana annotation {} for {}={}", id, svc, version);
+    }
+    if res.sentry {
+        info!("Marked {} as deployed to {} in sentry", svc, reg.name);
+    }
+    if res.grafana.is_none() && !res.sentry {
+        bail!("No deployment markers were posted for {} in {}", svc, reg.name);
+    }
+    Ok(res)
+}
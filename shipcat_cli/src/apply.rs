@@ -1,18 +1,23 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 use tokio::fs;
 
 use crate::{
-    diff, helm,
-    kubeapi::ShipKube,
-    kubectl, track,
+    annotate, diff, gitops, grafana, helm, hooks,
+    kubeapi::{ShipKube, WorkloadApi},
+    kubectl, queue, smoketest, track,
     webhooks::{self, UpgradeState},
 };
+use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde_json::json;
 
 use shipcat_definitions::{
-    status::{make_date, Condition},
-    structs::{Metadata, NotificationMode},
-    Config, Manifest, PrimaryWorkload, ReconciliationMode, Region,
+    status::{make_date, CanaryStatus, Condition, ContainerChange, RolloutRecord, SmokeTestRun},
+    structs::{Canary, Metadata, NotificationMode},
+    ApplyHookStage, Config, Environment, Manifest, PrimaryWorkload, ReconciliationMode, Region,
 };
 
 use super::{ErrorKind, Result, ResultExt};
@@ -35,8 +40,20 @@ pub struct UpgradeInfo {
     pub region: String,
     /// Validated namespace inferred from region
     pub namespace: String,
+    /// Cluster serving the region (if known)
+    pub cluster: Option<String>,
     /// Computed diff string (if available)
     pub diff: Option<String>,
+    /// Time taken to apply and roll out (if waited for)
+    pub duration: Option<Duration>,
+    /// Whether this is a preview from `apply --dry-run` that sends no events
+    pub dry_run: bool,
+    /// Whether the version is rolled out as a canary
+    pub canary: bool,
+    /// Smoke tests run after the rollout (if any)
+    pub smokeTests: Option<SmokeTestRun>,
+    /// Version changes of sidecars and init containers
+    pub containers: Vec<ContainerChange>,
 }
 
 impl UpgradeInfo {
@@ -57,7 +74,13 @@ impl UpgradeInfo {
             slackMode: mf.upgradeNotifications.clone().unwrap_or_default(),
             region: mf.region.clone(),
             namespace: mf.namespace.clone(),
+            cluster: None,
             diff: None,
+            duration: None,
+            dry_run: false,
+            canary: false,
+            smokeTests: None,
+            containers: vec![],
         }
     }
 }
@@ -82,6 +105,9 @@ impl UpgradeInfo {
 /// It is also entirely responsible for sending webhooks on errors / successes.
 /// As such, it's entirely responsible for not propagating random errors here with `?`
 /// Every error cases is something that might need to be notified.
+///
+/// A `dry_run` goes through the same steps, but only sends server-side dry runs to kube,
+/// prints the diffs, and stops before hooks and queues of the actual apply.
 pub async fn apply(
     svc: String,
     force: bool,
@@ -89,12 +115,88 @@ pub async fn apply(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    dry_run: bool,
 ) -> Result<Option<UpgradeInfo>> {
     match region.reconciliationMode {
-        ReconciliationMode::CrdOwned => apply_kubectl(&svc, force, region, conf, wait, passed_version).await,
+        ReconciliationMode::CrdOwned => {
+            let canary = CanaryAction::Keep;
+            apply_kubectl(&svc, force, region, conf, wait, passed_version, dry_run, canary).await
+        }
     }
 }
 
+/// shipcat apply --canary
+///
+/// Deploys a version as a canary next to the unchanged main workload,
+/// and records the canary in the shipcatmanifest status so kong sends it `weight`% of the traffic.
+/// Applying a new canary over an active one replaces it.
+pub async fn canary(
+    svc: String,
+    weight: u32,
+    version: String,
+    region: &Region,
+    conf: &Config,
+    wait: bool,
+) -> Result<Option<UpgradeInfo>> {
+    let canary = CanaryAction::Start { version, weight };
+    apply_kubectl(&svc, false, region, conf, wait, None, false, canary).await
+}
+
+/// shipcat promote
+///
+/// Upgrades the main workload to the version of the active canary, and removes the canary.
+pub async fn promote(svc: String, region: &Region, conf: &Config, wait: bool) -> Result<Option<UpgradeInfo>> {
+    let s = ShipKube::new_within(&svc, &region.namespace).await?;
+    let active = s.get_minimal().await?.status.and_then(|s| s.canary);
+    let version = match active.and_then(|c| c.version) {
+        Some(v) => v,
+        None => bail!("'{}' has no active canary to promote", svc),
+    };
+    apply_kubectl(
+        &svc,
+        false,
+        region,
+        conf,
+        wait,
+        Some(version),
+        false,
+        CanaryAction::Remove,
+    )
+    .await
+}
+
+/// shipcat abort-canary
+///
+/// Removes the active canary, leaving the main workload as it is.
+pub async fn abort_canary(
+    svc: String,
+    region: &Region,
+    conf: &Config,
+    wait: bool,
+) -> Result<Option<UpgradeInfo>> {
+    apply_kubectl(&svc, false, region, conf, wait, None, false, CanaryAction::Remove).await
+}
+
+/// What an apply does with the canary of a service
+#[derive(Debug)]
+enum CanaryAction {
+    /// Keep running the active canary (if any)
+    Keep,
+    /// Run a version as the canary with a percentage of the traffic
+    Start { version: String, weight: u32 },
+    /// Remove the active canary
+    Remove,
+}
+
+/// Owner uid for the template of a service whose crd a dry run did not create
+const DRY_RUN_UID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Smoke test runs kept in the shipcatmanifest status
+const SMOKE_TEST_HISTORY: usize = 10;
+
+/// Number of finished rollouts kept in the status
+const ROLLOUT_HISTORY: usize = 10;
+
 /// Reason for an apply being allowed through
 ///
 /// Some of these imply others. We pick the strongest one we can.
@@ -110,6 +212,8 @@ pub enum UpgradeReason {
     SecretChecksum,
     /// Regional / Chart changes
     TemplateDiff,
+    /// Canary started, changed, promoted or aborted
+    CanaryChange,
     /// Something failed (e.g. diff failed to return) and apply was with --force
     Forced,
 }
@@ -123,7 +227,7 @@ impl ToString for UpgradeReason {
 /// First version of apply that does not use tiller
 ///
 /// This writes events to uses the shipcatmanifest crd
-#[allow(clippy::cognitive_complexity)] // TODO: refactor this!
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)] // TODO: refactor this!
 async fn apply_kubectl(
     svc: &str,
     force: bool,
@@ -131,6 +235,8 @@ async fn apply_kubectl(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    dry_run: bool,
+    canary: CanaryAction,
 ) -> Result<Option<UpgradeInfo>> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
@@ -138,14 +244,23 @@ async fn apply_kubectl(
     let mfbase = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
 
     // A version is set EITHER via `-t SOMEVER` on CLI, or pinned in manifest
+    // unless gitops writes the new version back over the pin
     if passed_version.is_some() && mfbase.version.is_some() && mfbase.version != passed_version {
-        error!("Overriding a pinned version will be undone at next reconcile");
-        bail!(
-            "Cannot override version for '{}' because it is pinned in manifests",
+        if !gitops::enabled(conf, region) {
+            error!("Overriding a pinned version will be undone at next reconcile");
+            bail!(
+                "Cannot override version for '{}' because it is pinned in manifests",
+                svc
+            );
+        }
+        info!(
+            "Overriding the pinned version of {}, the new pin is written back to git",
             svc
         );
     }
-    let explicit_version = mfbase.version.clone().or(passed_version);
+    // Only versions passed on the CLI can drift from git
+    let unpinned = passed_version.is_some() && mfbase.version != passed_version;
+    let explicit_version = passed_version.or_else(|| mfbase.version.clone());
 
     if !mfbase.regions.contains(&region.name) {
         bail!(
@@ -159,7 +274,7 @@ async fn apply_kubectl(
     // - if the service has been installed before (negates the need for a diff)
     // - if we need to apply a new crd (so we have an atomic change)
     // - if we need to interact with secret-manager TODO: do
-    let s = ShipKube::new(&mfbase).await?;
+    let s = ShipKube::new(&mfbase).await?.dry_run(dry_run);
 
     // Next large batch is working out the reason for the upgrade (if any)
     let mut reason = None;
@@ -202,6 +317,39 @@ async fn apply_kubectl(
     // no shoehorning in illegal versions in the crd!
     region.versioningScheme.verify(&actual_version)?;
 
+    // Work out the canary to run next to the main workload
+    let active_canary = crd
+        .as_ref()
+        .and_then(|o| o.status.as_ref())
+        .and_then(|s| s.canary.clone());
+    let canary_version = match &canary {
+        CanaryAction::Start { version, .. } => Some(version.clone()),
+        _ => None,
+    };
+    let next_canary = match canary {
+        CanaryAction::Keep => active_canary.clone(),
+        CanaryAction::Start { version, weight } => {
+            if crd.is_none() {
+                bail!("Cannot start a canary of '{}' before it is installed", svc);
+            }
+            region.versioningScheme.verify(&version)?;
+            Some(CanaryStatus {
+                service: Canary::name_for(svc),
+                weight,
+                version: Some(version),
+            })
+        }
+        CanaryAction::Remove => {
+            if active_canary.is_none() {
+                bail!("'{}' has no active canary", svc);
+            }
+            None
+        }
+    };
+    if next_canary != active_canary {
+        reason = reason.or(Some(UpgradeReason::CanaryChange));
+    }
+
     // Complete and apply the CRD
     let mfcrd = mfbase.version(actual_version.clone());
     let crd_changed = s.apply(mfcrd.clone()).await?;
@@ -216,15 +364,29 @@ async fn apply_kubectl(
 
     // Prepare for an actual upgrade now..
     let mut ui = UpgradeInfo::new(&mfcrd);
-    webhooks::apply_event(UpgradeState::Pending, &ui, &region, &conf).await;
+    ui.cluster = Some(region.cluster.clone());
+    ui.dry_run = dry_run;
+    if let Some(o) = &crd {
+        ui.containers = ContainerChange::between(false, &o.spec.sidecars, &mfcrd.sidecars);
+        ui.containers.extend(ContainerChange::between(
+            true,
+            &o.spec.initContainers,
+            &mfcrd.initContainers,
+        ));
+    }
+    if let Some(v) = &canary_version {
+        ui.version = v.clone();
+        ui.canary = true;
+    }
+    transition(UpgradeState::Pending, &ui, &s, region, conf).await;
 
     // Fetch all the secrets so we can create a completed manifest
     // TODO: check scp.status.secretChecksum against secret-manager instead
-    let mut mf = match mfcrd.complete(&region).await {
+    let mut mf = match mfcrd.clone().complete(region).await {
         Ok(m) => m,
         Err(e) => {
             // Fire failed events if secrets fail to resolve
-            webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+            transition(UpgradeState::Failed, &ui, &s, region, conf).await;
             s.update_generate_false("SecretFailure", e.description().to_string())
                 .await?;
             return Err(e.into());
@@ -237,32 +399,59 @@ async fn apply_kubectl(
         match s.get().await {
             // fallback to the one we just created
             Ok(o) => o.metadata.uid,
+            // a dry run never created it
+            Err(_) if dry_run => Some(DRY_RUN_UID.into()),
             Err(e) => {
                 debug!("{:?}", e);
                 // Fire failed events if crd could not be fetched after its creation
-                webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+                transition(UpgradeState::Failed, &ui, &s, region, conf).await;
                 s.update_generate_false("CrdFailure", e.description().to_string())
                     .await?;
                 return Err(e);
             }
         }
     };
+    mf.canary = match &next_canary {
+        Some(CanaryStatus {
+            version: Some(v),
+            weight,
+            ..
+        }) => Some(Canary::new(svc, v.clone(), *weight, mf.min_replicas())),
+        Some(c) => {
+            warn!("Not running canary {} without a version", c.service);
+            None
+        }
+        None => None,
+    };
+
+    if let Err(e) = hooks::run(ApplyHookStage::PreTemplate, &mfcrd, conf, None).await {
+        transition(UpgradeState::Failed, &ui, &s, region, conf).await;
+        s.update_generate_false("HookFailure", e.description().to_string())
+            .await?;
+        return Err(e);
+    }
 
     // Create completed kubernetes yaml (via shipcat values | helm template)
     let tfile = format!("{}.kube.gen.yml", svc);
     let tpth = Path::new(".").join(tfile.clone());
-    if let Err(e) = helm::template(&mf, Some(tpth)).await {
+    if let Err(e) = helm::template(&mf, conf, region, Some(tpth)).await {
         // Errors here are obscure, and should not happen, but pass them up anyway
-        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+        transition(UpgradeState::Failed, &ui, &s, region, conf).await;
         s.update_generate_false("ResolveFailure", e.description().to_string())
             .await?;
         return Err(e);
     }
+    if let Err(e) = hooks::run(ApplyHookStage::PostTemplate, &mfcrd, conf, Some(&tfile)).await {
+        transition(UpgradeState::Failed, &ui, &s, region, conf).await;
+        s.update_generate_false("HookFailure", e.description().to_string())
+            .await?;
+        return Err(e);
+    }
 
-    // Attach diff to UpgradeInfo if diffing is possible
-    if can_diff {
+    // Attach diff to UpgradeInfo if diffing is possible (dry runs diff new services in full)
+    if can_diff || dry_run {
         // helm diff only supports diffing if already installed..
-        match diff_kubectl(&mf, &tfile).await {
+        match diff_kubectl(&mf, &tfile, &conf.sensitive_env_regex()).await {
             Ok(Some(kdiff)) => {
                 ui.diff = Some(kdiff);
                 reason = reason.or(Some(UpgradeReason::TemplateDiff));
@@ -271,7 +460,7 @@ async fn apply_kubectl(
                 // If we explicitly received no diff, don't try to upgrade
                 // This is a stronger diff than CRD-only if this succeeds; STOP.
                 info!("{} up to date (full diff check)", svc);
-                webhooks::apply_event(UpgradeState::Cancelled, &ui, &region, &conf).await;
+                transition(UpgradeState::Cancelled, &ui, &s, region, conf).await;
                 s.update_generate_true().await?; // every force reconcile makes one generate cond
                 return Ok(None);
             }
@@ -280,7 +469,7 @@ async fn apply_kubectl(
                 warn!("Unable to diff against {}: {}", svc, e);
                 if !force && reason.is_none() {
                     // pass on a diff failure
-                    webhooks::apply_event(UpgradeState::Cancelled, &ui, &region, &conf).await;
+                    transition(UpgradeState::Cancelled, &ui, &s, region, conf).await;
                     s.update_generate_false("DiffFailure", e.description().to_string())
                         .await?;
                     return Ok(None); // but ultimately ignore this in fast reconciles
@@ -292,61 +481,233 @@ async fn apply_kubectl(
 
     // We cannot be here without a reason now, although you have to convince yourself.
     let ureason = reason.expect("cannot apply without a reason");
-    webhooks::apply_event(UpgradeState::Started, &ui, &region, &conf).await;
-    s.update_generate_true().await?; // if this fails, stop, want .status to be correct
-
-    match upgrade_kubectl(&mf, &tfile).await {
+    if dry_run {
+        upgrade_kubectl(&mf, &tfile, true).await?;
+        info!(
+            "{} would be applied ({}), see the generated template in {}",
+            svc,
+            ureason.to_string(),
+            tfile
+        );
+        return Ok(None);
+    }
+    if let Err(e) = hooks::run(ApplyHookStage::PreApply, &mfcrd, conf, Some(&tfile)).await {
+        transition(UpgradeState::Failed, &ui, &s, region, conf).await;
+        s.update_apply_false(ureason.to_string(), "HookFailure", e.description().to_string())
+            .await?;
+        return Err(e);
+    }
+    // Wait for a rollout slot in regions limiting concurrent rollouts
+    let slot = match queue::acquire(&mf, region, &actual_version).await {
+        Ok(slot) => slot,
         Err(e) => {
-            error!("{} from {}", e, ui.name);
-            webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
-            let reason = e.description().to_string();
-            s.update_apply_false(ureason.to_string(), "ApplyFailure", reason)
-                .await?; // TODO: chain
+            transition(UpgradeState::Failed, &ui, &s, region, conf).await;
+            s.update_apply_false(ureason.to_string(), "QueueFailure", e.description().to_string())
+                .await?;
             return Err(e);
         }
-        Ok(_) => {
-            let _ = s.update_apply_true(ureason.to_string()).await;
-            if !wait {
-                info!("successfully applied {} (without waiting)", ui.name);
-            } else {
-                match track::workload_rollout(&mf, &s).await {
-                    Ok(true) => {
-                        info!("successfully rolled out {}", &ui.name);
-                        webhooks::apply_event(UpgradeState::Completed, &ui, &region, &conf).await;
-                        s.update_rollout_true(&actual_version).await?;
-                    }
-                    Ok(false) => {
-                        let time = mf.estimate_wait_time();
-                        let reason = format!("timed out waiting {}s for rollout", time);
-                        //let _ = kubectl::debug_rollout_status(&mf).await;
-                        let _ = track::debug(&mf, &s).await;
-                        // TODO: collect these for .status call ^?
-                        warn!("failed to roll out {}", &ui.name);
-                        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
-                        s.update_rollout_false("Timeout", reason).await?; // TODO: chain
-                        return Err(ErrorKind::UpgradeTimeout(mf.name.clone(), time).into());
-                    }
-                    Err(e) => {
-                        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
-                        s.update_rollout_false("RolloutTrackFailure", e.description().to_string())
-                            .await?; // TODO: chain
-                        return Err(e);
-                    }
+    };
+    transition(UpgradeState::Started, &ui, &s, region, conf).await;
+    let started = Instant::now();
+    s.update_generate_true().await?; // if this fails, stop, want .status to be correct
+
+    let res: Result<()> = async {
+        match upgrade_kubectl(&mf, &tfile, false).await {
+            Err(e) => {
+                error!("{} from {}", e, ui.name);
+                transition(UpgradeState::Failed, &ui, &s, region, conf).await;
+                let reason = e.description().to_string();
+                s.update_apply_false(ureason.to_string(), "ApplyFailure", reason)
+                    .await?; // TODO: chain
+                return Err(e);
+            }
+            Ok(_) => {
+                let _ = s.update_apply_true(ureason.to_string()).await;
+                if next_canary != active_canary {
+                    s.update_canary(next_canary.as_ref()).await?;
+                }
+                if !wait {
+                    info!("successfully applied {} (without waiting)", ui.name);
+                } else {
+                    let elapsed = started.elapsed();
+                    finish_rollout(&mf, &mfcrd, &mut ui, elapsed, &s, region, conf).await?;
                 }
             }
         }
-    };
+        Ok(())
+    }
+    .await;
+    if let Some(slot) = slot {
+        slot.release().await;
+    }
+    res?;
     // cleanups in non-error cases
     let _ = fs::remove_file(&tfile).await;
+    if unpinned {
+        record_version(&ui, region, conf).await;
+    }
+    Ok(Some(ui))
+}
+
+/// Move an upgrade to its next state
+///
+/// The state is persisted in the shipcatmanifest status before webhooks are notified.
+async fn transition(us: UpgradeState, ui: &UpgradeInfo, s: &ShipKube, region: &Region, conf: &Config) {
+    if let Err(e) = s.update_upgrade(&us, ui).await {
+        warn!("Failed to record the {:?} upgrade of {}: {}", us, ui.name, e);
+    }
+    webhooks::apply_event(us, ui, region, conf).await;
+}
+
+/// Wait for an applied upgrade to roll out, then notify and record how it went
+///
+/// Shared by apply and resume. The `elapsed` time before tracking counts towards `ui.duration`.
+async fn finish_rollout(
+    mf: &Manifest,
+    mfcrd: &Manifest,
+    ui: &mut UpgradeInfo,
+    elapsed: Duration,
+    s: &ShipKube,
+    region: &Region,
+    conf: &Config,
+) -> Result<()> {
+    let version = mfcrd
+        .version
+        .clone()
+        .expect("version must be set before rolling out");
+    let tracking = Instant::now();
+    let last_pull = s.last_image_pull().await;
+    let rollout = if ui.canary {
+        let ck = ShipKube::new_within(&Canary::name_for(&mf.name), &mf.namespace).await?;
+        track::canary_rollout(mf, &ck, last_pull).await
+    } else {
+        let mut workers: Vec<(Manifest, Box<dyn WorkloadApi>)> = vec![];
+        for wmf in track::worker_manifests(mf) {
+            let wk = ShipKube::new_within(&wmf.name, &mf.namespace).await?;
+            workers.push((wmf, Box::new(wk)));
+        }
+        track::service_rollout(mf, s, &workers, last_pull).await
+    };
+    ui.duration = Some(elapsed + tracking.elapsed());
+    match rollout {
+        Ok(tr) if tr.ok => {
+            info!("successfully rolled out {}", &ui.name);
+            // canaries only get a share of the traffic, so only full rollouts are smoke tested
+            if !ui.canary {
+                if let Err(e) = smoke_test(mf, ui, s, region, conf).await {
+                    transition(UpgradeState::Failed, ui, s, region, conf).await;
+                    s.update_rollout_false("SmokeTestFailure", e.description().to_string())
+                        .await?;
+                    record_rollout(UpgradeState::Failed, ui, region, s).await;
+                    return Err(e);
+                }
+            }
+            transition(UpgradeState::Completed, ui, s, region, conf).await;
+            s.update_rollout_true(&version, tr.image_pull_seconds).await?;
+            record_rollout(UpgradeState::Completed, ui, region, s).await;
+            // the rollout has already succeeded, so failing hooks only warn
+            if let Err(e) = hooks::run(ApplyHookStage::PostRollout, mfcrd, conf, None).await {
+                warn!("postRollout hooks failed for {}: {}", ui.name, e);
+            }
+            Ok(())
+        }
+        Ok(tr) => {
+            let time = mf.estimate_wait_time_with_pull(last_pull);
+            let reason = format!(
+                "timed out waiting {}s for rollout of {}",
+                time,
+                tr.failed.join(", ")
+            );
+            //let _ = kubectl::debug_rollout_status(&mf).await;
+            let _ = track::debug(mf, s).await;
+            // TODO: collect these for .status call ^?
+            warn!("failed to roll out {}", &ui.name);
+            transition(UpgradeState::Failed, ui, s, region, conf).await;
+            s.update_rollout_false("Timeout", reason).await?; // TODO: chain
+            record_rollout(UpgradeState::Failed, ui, region, s).await;
+            Err(ErrorKind::UpgradeTimeout(mf.name.clone(), time).into())
+        }
+        Err(e) => {
+            transition(UpgradeState::Failed, ui, s, region, conf).await;
+            s.update_rollout_false("RolloutTrackFailure", e.description().to_string())
+                .await?; // TODO: chain
+            record_rollout(UpgradeState::Failed, ui, region, s).await;
+            Err(e)
+        }
+    }
+}
+
+/// Run the smoke tests of a rolled out service and record them in its status
+///
+/// Failures roll the workload back when its `smokeTests` ask for it.
+async fn smoke_test(
+    mf: &Manifest,
+    ui: &mut UpgradeInfo,
+    s: &ShipKube,
+    region: &Region,
+    conf: &Config,
+) -> Result<()> {
+    let run = match smoketest::run(mf, conf, region).await? {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    if let Err(e) = s.record_smoke_tests(&run).await {
+        warn!("Failed to record the smoke tests of {}: {}", ui.name, e);
+    }
+    let failed = run.results.iter().filter(|r| !r.passed).count();
+    ui.smokeTests = Some(run);
+    if failed == 0 {
+        return Ok(());
+    }
+    if mf.smokeTests.as_ref().map_or(false, |st| st.rollback) {
+        warn!("Rolling back {} after failed smoke tests", ui.name);
+        kubectl::rollout_undo(mf).await?;
+    }
+    Err(ErrorKind::SmokeTestFailure(ui.name.clone(), failed).into())
+}
+
+/// shipcat resume
+///
+/// Re-attaches to an upgrade whose apply process died after it started applying.
+/// The rollout is tracked, notified and recorded like apply would have,
+/// but versions are not written back to git.
+pub async fn resume(svc: String, region: &Region, conf: &Config) -> Result<Option<UpgradeInfo>> {
+    let mfbase = shipcat_filebacked::load_manifest(&svc, conf, region).await?;
+    let s = ShipKube::new(&mfbase).await?;
+    let crd = s.get_minimal().await?;
+    let status = crd.status.unwrap_or_default();
+    let upgrade = match status.upgrade {
+        Some(u) if u.state == UpgradeState::Started => u,
+        Some(u) => bail!("The last upgrade of '{}' is not in progress ({:?})", svc, u.state),
+        None => bail!("'{}' has no recorded upgrade to resume", svc),
+    };
+    let mut mf = mfbase.version(crd.spec.version);
+    if upgrade.canary {
+        let replicas = mf.min_replicas();
+        mf.canary = status
+            .canary
+            .map(|c| Canary::new(&svc, upgrade.version.clone(), c.weight, replicas));
+    }
+    let elapsed = match upgrade.started.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(t)) => Utc::now().signed_duration_since(t).to_std().unwrap_or_default(),
+        _ => Duration::default(),
+    };
+
+    let mut ui = UpgradeInfo::new(&mf);
+    ui.cluster = Some(region.cluster.clone());
+    ui.version = upgrade.version;
+    ui.canary = upgrade.canary;
+    info!("Resuming the upgrade of {} to {}", svc, ui.version);
+    finish_rollout(&mf, &mf, &mut ui, elapsed, &s, region, conf).await?;
     Ok(Some(ui))
 }
 
 /// Shell out to kubectl apply
 ///
 /// Assumes you have written your template file from `helm template`
-async fn upgrade_kubectl(mf: &Manifest, tfile: &str) -> Result<()> {
+async fn upgrade_kubectl(mf: &Manifest, tfile: &str, dry_run: bool) -> Result<()> {
     // upgrade it using the same command
-    let applyvec = vec![
+    let mut applyvec = vec![
         "apply".into(),
         format!("-n={}", mf.namespace),
         "-f".into(),
@@ -355,6 +716,9 @@ async fn upgrade_kubectl(mf: &Manifest, tfile: &str) -> Result<()> {
         // NB: assumes one deploy per namespace
         format!("-l=app.kubernetes.io/name={}", mf.name),
     ];
+    if dry_run {
+        applyvec.push("--dry-run=server".into());
+    }
     info!("kubectl {}", applyvec.join(" "));
     kubectl::kexec(applyvec)
         .await
@@ -365,16 +729,16 @@ async fn upgrade_kubectl(mf: &Manifest, tfile: &str) -> Result<()> {
 /// Minified kubectl diff shell out
 ///
 /// Requires kubernetes 1.13
-pub async fn diff_kubectl(mf: &Manifest, tfile: &str) -> Result<Option<String>> {
+///
+/// Secrets and env values with names matching `sensitive` are masked.
+pub async fn diff_kubectl(mf: &Manifest, tfile: &str, sensitive: &Regex) -> Result<Option<String>> {
     let namespace = mf.namespace.clone();
     let pth = Path::new(tfile);
-    let (kdiffunobfusc, kdifferr, success) = kubectl::diff(pth.to_path_buf(), &namespace).await?;
+    let (kdiffunmasked, kdifferr, success) = kubectl::diff(pth.to_path_buf(), &namespace).await?;
 
-    let kubediff = diff::obfuscate_secrets(
-        kdiffunobfusc, // move this away quickly..
-        mf.get_secrets(),
-    );
-    debug!("Full diff (obfuscated): \n{}", kubediff);
+    let secret_keys = mf.secret_config_keys();
+    let kubediff = diff::mask_secrets(&kdiffunmasked, sensitive, &secret_keys, &mf.get_secrets()); // move this away quickly..
+    debug!("Full diff (masked): \n{}", kubediff);
 
     if !success {
         let err = kdifferr.trim();
@@ -398,24 +762,36 @@ pub async fn diff_kubectl(mf: &Manifest, tfile: &str) -> Result<Option<String>>
     })
 }
 
+/// Pod template annotation recording why workloads were restarted
+pub const RESTART_REASON_ANNOTATION: &str = "shipcat.babylontech.co.uk/restart-reason";
+
 /// Restart the workloads associated with a shipcatmanifest
 ///
+/// A reason is recorded on the pod templates and the shipcatmanifest status,
+/// and is required in prod.
 /// Optionally wait for the main resource
-pub async fn restart(mf: &Manifest, wait: bool) -> Result<()> {
+pub async fn restart(mf: &Manifest, wait: bool, reason: Option<String>) -> Result<()> {
+    if reason.is_none() && mf.environment == Environment::Prod.to_string() {
+        bail!("Restarting {} in {} requires a --reason", mf.name, mf.region);
+    }
     for w in &mf.workers {
         let r = Restartable {
             name: w.container.name.clone(),
             namespace: mf.namespace.clone(),
             workload: PrimaryWorkload::Deployment,
         };
-        trigger_rollout_restart(r).await?; // fire-and-forget for subresources
+        trigger_rollout_restart(r, reason.as_deref()).await?; // fire-and-forget for subresources
     }
     let main = Restartable {
         name: mf.name.clone(),
         namespace: mf.namespace.clone(),
         workload: mf.workload.clone(),
     };
-    trigger_rollout_restart(main).await?;
+    trigger_rollout_restart(main, reason.as_deref()).await?;
+    if let Some(r) = reason {
+        let sk = ShipKube::new(mf).await?;
+        sk.update_restart(r).await?;
+    }
     if !wait {
         info!(
             "successfully triggered a restart of {}/{}",
@@ -426,11 +802,12 @@ pub async fn restart(mf: &Manifest, wait: bool) -> Result<()> {
     }
     let sk = ShipKube::new(&mf).await?;
     // wait for primary if we are waiting
-    if track::workload_rollout(&mf, &sk).await? {
+    let last_pull = sk.last_image_pull().await;
+    if track::workload_rollout(mf, &sk, last_pull).await?.ok {
         info!("successfully restarted {}/{}", mf.workload.to_string(), &mf.name);
         Ok(())
     } else {
-        let time = mf.estimate_wait_time();
+        let time = mf.estimate_wait_time_with_pull(last_pull);
         let reason = format!("timed out waiting {}s for rollout to restart", time);
         //let _ = kubectl::debug_rollout_status(&mf).await;
         let _ = track::debug(&mf, &sk).await;
@@ -445,19 +822,86 @@ struct Restartable {
     workload: PrimaryWorkload,
     namespace: String,
 }
-async fn trigger_rollout_restart(r: Restartable) -> Result<()> {
-    let restartvec = vec![
-        "rollout".into(),
-        format!("-n={}", r.namespace),
-        "restart".into(),
-        format!("{}/{}", r.workload.to_string(), r.name),
-    ];
+
+/// Pod template patch equivalent to `kubectl rollout restart` with a reason
+fn restart_patch(reason: &str, now: &str) -> serde_json::Value {
+    json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": {
+                        "kubectl.kubernetes.io/restartedAt": now,
+                        RESTART_REASON_ANNOTATION: reason,
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn trigger_rollout_restart(r: Restartable, reason: Option<&str>) -> Result<()> {
+    let workload = format!("{}/{}", r.workload.to_string(), r.name);
+    let restartvec = if let Some(reason) = reason {
+        vec![
+            "patch".into(),
+            format!("-n={}", r.namespace),
+            workload,
+            "--type=merge".into(),
+            format!("-p={}", restart_patch(reason, &make_date())),
+        ]
+    } else {
+        vec![
+            "rollout".into(),
+            format!("-n={}", r.namespace),
+            "restart".into(),
+            workload,
+        ]
+    };
     info!("kubectl {}", restartvec.join(" "));
     kubectl::kexec(restartvec)
         .await
         .chain_err(|| ErrorKind::KubectlApplyFailure(r.name))
 }
 
+/// Best effort deployment markers of a finished rollout
+///
+/// The rollout is recorded in the status history along with its grafana annotation id.
+/// Integrations are marked independently, so one failing does not stop the other.
+async fn record_rollout(us: UpgradeState, ui: &UpgradeInfo, region: &Region, s: &ShipKube) {
+    let annotation = match grafana::annotate(&us, ui, region).await {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Failed to annotate grafana for {}: {}", ui.name, e);
+            None
+        }
+    };
+    let record = RolloutRecord {
+        version: ui.version.clone(),
+        time: make_date(),
+        succeeded: us == UpgradeState::Completed,
+        annotation,
+    };
+    if let Err(e) = s.record_rollout(&record).await {
+        warn!("Failed to record rollout of {}: {}", ui.name, e);
+    }
+    if us == UpgradeState::Completed {
+        if let Err(e) = annotate::sentry(ui, region).await {
+            warn!("Failed to mark sentry deploy for {}: {}", ui.name, e);
+        }
+    }
+}
+
+/// Record an applied version in git (if configured)
+///
+/// The apply has already happened, so failures here only warn.
+async fn record_version(ui: &UpgradeInfo, region: &Region, conf: &Config) {
+    match gitops::write_back(ui, region, conf).await {
+        Ok(Some(url)) => info!("Pinned {}={} in git: {}", ui.name, ui.version, url),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to write back {} version to git: {}", ui.name, e),
+    }
+}
+
 /// Uninstall a service
 ///
 /// Not meant to be called if the manifest is still installed in the region
@@ -468,7 +912,8 @@ pub async fn delete(svc: &str, reg: &Region, conf: &Config) -> Result<()> {
     match s.get().await {
         // audit all events if it's possible to deserialize current crd
         Ok(mfk) => {
-            let info = UpgradeInfo::new(&mfk.spec);
+            let mut info = UpgradeInfo::new(&mfk.spec);
+            info.cluster = Some(reg.cluster.clone());
             // We notify before we start, because this is potentially a "panic" type notification.
             webhooks::delete_event(&UpgradeState::Started, &info, &reg, &conf).await;
             match s.delete().await {
@@ -616,11 +1061,11 @@ impl ShipKube {
         self.patch(&data).await
     }
 
-    pub async fn update_rollout_true(&self, version: &str) -> Result<()> {
+    pub async fn update_rollout_true(&self, version: &str, pull_seconds: Option<u32>) -> Result<()> {
         debug!("Setting rolledout true");
         let now = make_date();
         let cond = Condition::ok(&self.applier);
-        let data = json!({
+        let mut data = json!({
             "status": {
                 "conditions": {
                     "rolledout": cond
@@ -634,6 +1079,104 @@ impl ShipKube {
                 }
             }
         });
+        // keep the previous pull time when nothing was pulled (e.g. image already on the nodes)
+        if let Some(secs) = pull_seconds {
+            data["status"]["summary"]["lastImagePullSeconds"] = secs.into();
+        }
         self.patch(&data).await
     }
+
+    pub async fn update_restart(&self, reason: String) -> Result<()> {
+        debug!("Setting last restart");
+        let now = make_date();
+        let data = json!({
+            "status": {
+                "summary": {
+                    "lastRestart": now,
+                    "lastRestartReason": reason,
+                    "lastRestartSource": self.applier,
+                    "lastAction": "Restart",
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_canary(&self, canary: Option<&CanaryStatus>) -> Result<()> {
+        debug!("Setting canary {:?}", canary);
+        let data = json!({
+            "status": {
+                "canary": canary,
+            }
+        });
+        self.patch(&data).await
+    }
+
+    /// Append a smoke test run to the status, keeping the most recent runs
+    pub async fn record_smoke_tests(&self, run: &SmokeTestRun) -> Result<()> {
+        debug!("Recording smoke tests of {}", run.version);
+        let mut runs = self.get_minimal().await?.status.unwrap_or_default().smoke_tests;
+        runs.push(run.clone());
+        let skip = runs.len().saturating_sub(SMOKE_TEST_HISTORY);
+        let data = json!({
+            "status": {
+                "smokeTests": &runs[skip..],
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_upgrade(&self, us: &UpgradeState, ui: &UpgradeInfo) -> Result<()> {
+        debug!("Setting upgrade state {:?}", us);
+        let now = make_date();
+        let mut data = json!({
+            "status": {
+                "upgrade": {
+                    "state": us,
+                    "version": ui.version,
+                    "canary": ui.canary,
+                    "lastTransition": now,
+                    "source": self.applier,
+                    "containers": ui.containers,
+                }
+            }
+        });
+        // the start is kept until the next upgrade
+        match us {
+            UpgradeState::Pending => data["status"]["upgrade"]["started"] = serde_json::Value::Null,
+            UpgradeState::Started => data["status"]["upgrade"]["started"] = now.into(),
+            _ => {}
+        }
+        self.patch(&data).await
+    }
+
+    /// Append a finished rollout to the status, keeping the most recent rollouts
+    pub async fn record_rollout(&self, record: &RolloutRecord) -> Result<()> {
+        debug!("Recording rollout of {}", record.version);
+        let mut rollouts = self.get_minimal().await?.status.unwrap_or_default().rollouts;
+        rollouts.push(record.clone());
+        let skip = rollouts.len().saturating_sub(ROLLOUT_HISTORY);
+        let data = json!({
+            "status": {
+                "rollouts": &rollouts[skip..],
+            }
+        });
+        self.patch(&data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{restart_patch, RESTART_REASON_ANNOTATION};
+
+    #[test]
+    fn restart_patch_annotations() {
+        let patch = restart_patch("leaking memory", "2020-04-01T03:00:00Z");
+        let annotations = &patch["spec"]["template"]["metadata"]["annotations"];
+        assert_eq!(annotations[RESTART_REASON_ANNOTATION], "leaking memory");
+        assert_eq!(
+            annotations["kubectl.kubernetes.io/restartedAt"],
+            "2020-04-01T03:00:00Z"
+        );
+    }
 }
@@ -1,21 +1,112 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, env, path::PathBuf};
 
+use async_trait::async_trait;
 use chrono::{SecondsFormat, Utc};
 use serde::Serialize;
+use shipcat_definitions::{
+    region::audit_context,
+    status::{Applier, ContainerChange},
+};
+use tokio::{fs, io::AsyncWriteExt};
 use url::Url;
 use uuid::Uuid;
 
-use super::{AuditWebhook, ErrorKind, Result, ResultExt};
-use crate::{apply::UpgradeInfo, webhooks::UpgradeState};
+use super::{AuditWebhook, ErrorKind, Region, Result, ResultExt};
+use crate::{apply::UpgradeInfo, freeze, git, permissions, rotate::RotationInfo, webhooks::UpgradeState};
 
 // Webhook Configuration Map
 type WHC = BTreeMap<String, String>;
 
+/// Evar set by `--audit-log` to append events to a file in any region
+pub const AUDIT_LOG_EVAR: &str = "SHIPCAT_AUDIT_LOG";
+
+// ----------------------------------------------------------------------------------
+// Audit sinks
+// ----------------------------------------------------------------------------------
+
+/// Somewhere audit events are delivered to
+#[async_trait]
+pub trait AuditSink: Sync {
+    async fn deliver(&self, event: &serde_json::Value) -> Result<()>;
+}
+
+/// The audit service, authenticated with the webhook token
+#[async_trait]
+impl AuditSink for AuditWebhook {
+    async fn deliver(&self, event: &serde_json::Value) -> Result<()> {
+        let endpoint = &self.url;
+        debug!("event status: {}, url: {:?}", event["status"], endpoint);
+
+        reqwest::Client::new()
+            .post(endpoint.clone())
+            .bearer_auth(self.token.clone())
+            .json(event)
+            .send()
+            .await
+            .chain_err(|| ErrorKind::Url(endpoint.clone()))?;
+        Ok(())
+    }
+}
+
+/// A local file that every event is appended to as a json line
+pub struct AuditLog {
+    pub path: PathBuf,
+}
+
+impl AuditLog {
+    /// The audit log for a region
+    ///
+    /// `--audit-log` takes precedence over the region's `auditLog` config.
+    pub fn regional(reg: &Region) -> Option<AuditLog> {
+        let path = match env::var(AUDIT_LOG_EVAR) {
+            Ok(p) => PathBuf::from(p),
+            Err(_) => reg.auditLog.as_ref()?.path.clone(),
+        };
+        Some(AuditLog { path })
+    }
+
+    /// Context of events written to the log
+    ///
+    /// Like the webhook context, but the revision falls back to the checked out commit,
+    /// since logs are mostly written outside of CI.
+    pub fn context() -> WHC {
+        let mut whc = audit_context();
+        if !whc.contains_key("SHIPCAT_AUDIT_REVISION") {
+            let revision = git::revision().unwrap_or_else(|e| {
+                warn!("Could not find the manifests revision for the audit log: {}", e);
+                "unknown".into()
+            });
+            whc.insert("SHIPCAT_AUDIT_REVISION".into(), revision);
+        }
+        whc
+    }
+}
+
+#[async_trait]
+impl AuditSink for AuditLog {
+    async fn deliver(&self, event: &serde_json::Value) -> Result<()> {
+        debug!("event status: {}, file: {}", event["status"], self.path.display());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let line = serde_json::to_string(event)? + "\n";
+        f.write_all(line.as_bytes()).await?;
+        // tokio files write in the background, so wait for the line to land before dropping
+        f.flush().await?;
+        Ok(())
+    }
+}
+
 // ----------------------------------------------------------------------------------
 // Audit Event definitions and sending
 // ----------------------------------------------------------------------------------
 
-/// Payload that gets sent via audit webhook
+/// Payload that gets sent to audit sinks
 ///
 /// Generic over the payload type, defined below for different domain_types
 #[derive(Serialize, Clone)]
@@ -31,6 +122,14 @@ struct AuditEvent<T: Serialize + Clone> {
     /// Eg a jenkins job url
     #[serde(skip_serializing_if = "Option::is_none")]
     context_link: Option<Url>,
+    /// Who triggered the event, eg a jenkins job or a user
+    applier: Applier,
+    /// Justification for skipping ownership checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    break_glass: Option<String>,
+    /// Reason for applying during a change freeze
+    #[serde(skip_serializing_if = "Option::is_none")]
+    override_freeze: Option<String>,
 
     /// represents a single kubectl apply, kubectl delete, or a reconciliation
     payload: T,
@@ -49,26 +148,15 @@ where
             context_link: whc
                 .get("SHIPCAT_AUDIT_CONTEXT_LINK")
                 .and_then(|l| Url::parse(&l).ok()),
+            applier: Applier::infer(),
+            break_glass: permissions::break_glass(),
+            override_freeze: freeze::override_reason(),
             payload,
         }
     }
 
-    async fn send(&self, audcfg: &AuditWebhook) -> Result<()> {
-        let endpoint = &audcfg.url;
-        debug!(
-            "event status: {}, url: {:?}",
-            serde_json::to_string(&self.status)?,
-            endpoint
-        );
-
-        reqwest::Client::new()
-            .post(endpoint.clone())
-            .bearer_auth(audcfg.token.clone())
-            .json(&self)
-            .send()
-            .await
-            .chain_err(|| ErrorKind::Url(endpoint.clone()))?;
-        Ok(())
+    async fn send(&self, sink: &dyn AuditSink) -> Result<()> {
+        sink.deliver(&serde_json::to_value(self)?).await
     }
 }
 
@@ -81,6 +169,7 @@ enum AuditType {
     Deployment,
     Reconciliation,
     Deletion,
+    Rotation,
 }
 impl ToString for AuditType {
     fn to_string(&self) -> String {
@@ -93,8 +182,13 @@ impl ToString for AuditType {
 struct DeploymentPayload {
     id: String,
     region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
     service: String,
     version: String,
+    /// Version changes of sidecars and init containers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    containers: Vec<ContainerChange>,
     manifests_revision: String,
 }
 impl DeploymentPayload {
@@ -102,8 +196,10 @@ impl DeploymentPayload {
         Self {
             id: Uuid::new_v4().to_string(),
             region: info.region.clone(),
+            cluster: info.cluster.clone(),
             service: info.name.clone(),
             version: info.version.clone(),
+            containers: info.containers.clone(),
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
         }
     }
@@ -131,6 +227,8 @@ impl ReconciliationPayload {
 struct DeletionPayload {
     id: String,
     region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
     service: String,
     manifests_revision: String,
 }
@@ -140,7 +238,36 @@ impl DeletionPayload {
             id: Uuid::new_v4().to_string(),
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
             region: info.region.clone(),
+            cluster: info.cluster.clone(),
+            service: info.name.clone(),
+        }
+    }
+}
+
+// Payload for Rotation (secret rotate) events
+#[derive(Serialize, Clone)]
+struct RotationPayload {
+    id: String,
+    region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
+    service: String,
+    secret: String,
+    /// Vault version of the new value, on KV v2 engines
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    manifests_revision: String,
+}
+impl RotationPayload {
+    fn new(whc: &WHC, info: &RotationInfo) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            region: info.region.clone(),
+            cluster: info.cluster.clone(),
             service: info.name.clone(),
+            secret: info.secret.clone(),
+            version: info.version,
+            manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
         }
     }
 }
@@ -150,26 +277,34 @@ impl DeletionPayload {
 // ----------------------------------------------------------------------------------
 
 /// Apply audit sent by shipcat::aplpy
-pub async fn apply(us: &UpgradeState, u: &UpgradeInfo, audcfg: &AuditWebhook, whc: WHC) -> Result<()> {
+pub async fn apply(us: &UpgradeState, u: &UpgradeInfo, sink: &dyn AuditSink, whc: WHC) -> Result<()> {
     let pl = DeploymentPayload::new(&whc, &u);
     AuditEvent::new(AuditType::Deployment, &whc, &us, pl)
-        .send(&audcfg)
+        .send(sink)
         .await
 }
 
 /// Apply audit sent by shipcat::cluster
-pub async fn reconciliation(us: &UpgradeState, region: &str, audcfg: &AuditWebhook, whc: WHC) -> Result<()> {
+pub async fn reconciliation(us: &UpgradeState, region: &str, sink: &dyn AuditSink, whc: WHC) -> Result<()> {
     let pl = ReconciliationPayload::new(&whc, region);
     AuditEvent::new(AuditType::Reconciliation, &whc, &us, pl)
-        .send(&audcfg)
+        .send(sink)
         .await
 }
 
 /// Delete audit sent by shipcat::cluster
-pub async fn deletion(us: &UpgradeState, ui: &UpgradeInfo, audcfg: &AuditWebhook, whc: WHC) -> Result<()> {
+pub async fn deletion(us: &UpgradeState, ui: &UpgradeInfo, sink: &dyn AuditSink, whc: WHC) -> Result<()> {
     let pl = DeletionPayload::new(&whc, &ui);
     AuditEvent::new(AuditType::Deletion, &whc, &us, pl)
-        .send(&audcfg)
+        .send(sink)
+        .await
+}
+
+/// Secret rotation audit sent by shipcat::rotate
+pub async fn rotation(us: &UpgradeState, ri: &RotationInfo, sink: &dyn AuditSink, whc: WHC) -> Result<()> {
+    let pl = RotationPayload::new(&whc, &ri);
+    AuditEvent::new(AuditType::Rotation, &whc, &us, pl)
+        .send(sink)
         .await
 }
 
@@ -182,7 +317,12 @@ mod tests {
     use std::collections::BTreeMap;
     use url::Url;
 
-    use crate::{apply::UpgradeInfo, audit, AuditWebhook, Manifest, Result, UpgradeState};
+    use crate::{
+        apply::UpgradeInfo,
+        audit::{self, AuditLog},
+        rotate::RotationInfo,
+        AuditWebhook, Manifest, Result, UpgradeState,
+    };
 
     #[tokio::test]
     async fn audit_does_audit_deployment() -> Result<()> {
@@ -223,6 +363,36 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn audit_log_appends_json_lines() -> Result<()> {
+        let mut whc: BTreeMap<String, String> = BTreeMap::default();
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+        let dir = std::env::temp_dir().join(format!("shipcat-{}", uuid::Uuid::new_v4()));
+        let log = AuditLog {
+            path: dir.join("logs").join("audit.jsonl"),
+        };
+        let ud = UpgradeInfo::new(&Manifest::test("fake-svc"));
+        audit::apply(&UpgradeState::Started, &ud, &log, whc.clone()).await?;
+        audit::apply(&UpgradeState::Completed, &ud, &log, whc).await?;
+
+        let data = std::fs::read_to_string(&log.path)?;
+        let events = data
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["status"], "STARTED");
+        assert_eq!(events[1]["status"], "COMPLETED");
+        assert_eq!(events[1]["type"], "deployment");
+        assert_eq!(events[1]["payload"]["service"], "fake-svc");
+        assert_eq!(events[1]["payload"]["manifests_revision"], "egrevision");
+        assert!(events[1]["applier"]["name"].is_string());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
     #[test]
     fn audit_reconciliation_has_type() {
         let mut whc: BTreeMap<String, String> = BTreeMap::default();
@@ -239,4 +409,26 @@ mod tests {
         );
         assert_eq!(ae.domain_type, "reconciliation");
     }
+
+    #[test]
+    fn audit_rotation_has_no_value() {
+        let mut whc: BTreeMap<String, String> = BTreeMap::default();
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+        let ri = RotationInfo {
+            name: "fake-svc".into(),
+            secret: "FAKE_SECRET".into(),
+            region: "dev-uk".into(),
+            cluster: None,
+            version: Some(3),
+        };
+        let pl = audit::RotationPayload::new(&whc, &ri);
+        let ae = audit::AuditEvent::new(audit::AuditType::Rotation, &whc, &UpgradeState::Completed, pl);
+        let event = serde_json::to_value(&ae).unwrap();
+        assert_eq!(event["type"], "rotation");
+        assert_eq!(event["payload"]["secret"], "FAKE_SECRET");
+        assert_eq!(event["payload"]["version"], 3);
+        assert!(event["payload"].get("value").is_none());
+    }
 }
@@ -1,14 +1,13 @@
 use super::{Config, Region, Result};
-use crate::kubectl;
-use std::process::Command;
+use crate::{exec::executor, kubectl};
 
 /// Check if teleport expired
-fn need_teleport_login(url: &str) -> Result<bool> {
+async fn need_teleport_login(url: &str) -> Result<bool> {
     let args = vec!["status".to_string()]; // tsh status doesn't seem to have a nice filtering or yaml output :(
                                            // https://github.com/gravitational/teleport/issues/2869
-    let s = Command::new("tsh").args(&args).output()?;
+    let s = executor().output("tsh", &args).await?;
 
-    let tsh_out = String::from_utf8_lossy(&s.stdout);
+    let tsh_out = s.stdout;
     let lines = tsh_out.lines().collect::<Vec<_>>();
     if let Some(idx) = lines.iter().position(|l| l.contains(url)) {
         let valid_ln = lines[idx + 5]; // idx+5 is Valid until line
@@ -21,9 +20,7 @@ fn need_teleport_login(url: &str) -> Result<bool> {
 }
 
 fn ensure_teleport() -> Result<()> {
-    let s = Command::new("which").args(vec!["tsh"]).output()?;
-    let out = String::from_utf8_lossy(&s.stdout);
-    if out.is_empty() {
+    if !executor().available("tsh") {
         bail!(
             "tsh not found. please install tsh --> https://gravitational.com/teleport/download/
 Download link for MacOS --> https://get.gravitational.com/teleport-v4.4.9-darwin-amd64-bin.tar.gz
@@ -34,6 +31,14 @@ You must install version 4.4.9 and not higher versions"
     Ok(())
 }
 
+/// The kube context that `login` creates or reuses for a region
+pub fn context_name(conf: &Config, region: &Region) -> String {
+    match conf.find_owning_cluster(region) {
+        Some(c) if c.teleport.is_some() => region.name.clone(),
+        _ => region.cluster.clone(),
+    }
+}
+
 /// Login to a region by going through its owning cluster
 ///
 /// This will use teleport to login if a teleport url is set
@@ -42,7 +47,7 @@ pub async fn login(conf: &Config, region: &Region, force: bool) -> Result<()> {
     if let Some(cluster) = conf.find_owning_cluster(&region) {
         if let Some(teleport) = &cluster.teleport {
             ensure_teleport()?;
-            let needs_login = need_teleport_login(&teleport)?;
+            let needs_login = need_teleport_login(teleport).await?;
             if force {
                 let tsh_state_file = dirs::home_dir()
                     .expect("need a homedir")
@@ -59,14 +64,12 @@ pub async fn login(conf: &Config, region: &Region, force: bool) -> Result<()> {
                     "--auth=github".into(),
                 ];
                 info!("tsh {}", tsh_args.join(" "));
-                let s = Command::new("tsh").args(&tsh_args).output()?;
-                let out = String::from_utf8_lossy(&s.stdout);
-                let err = String::from_utf8_lossy(&s.stderr);
-                if !out.is_empty() {
-                    debug!("{}", out);
+                let s = executor().output("tsh", &tsh_args).await?;
+                if !s.stdout.is_empty() {
+                    debug!("{}", s.stdout);
                 }
-                if !s.status.success() {
-                    bail!("tsh login: {}", err);
+                if !s.success {
+                    bail!("tsh login: {}", s.stderr);
                 }
             } else {
                 info!("Reusing active session for {}", teleport);
@@ -97,3 +100,35 @@ pub async fn login(conf: &Config, region: &Region, force: bool) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::login;
+    use crate::{
+        exec::{scope, Replay},
+        kubectl,
+    };
+    use shipcat_filebacked::fixtures::{ConfigBuilder, RegionBuilder};
+    use std::{path::Path, sync::Arc};
+
+    #[tokio::test]
+    async fn teleport_login_replay() {
+        let conf = ConfigBuilder::new()
+            .with(
+                "clusters",
+                "{ops-green: {name: ops-green, api: 'https://api.ops-green.some.domain', \
+                 teleport: teleport.ops-green.some.domain, regions: [dev-ops]}}",
+            )
+            .build();
+        let region = RegionBuilder::new("dev-ops").with("cluster", "ops-green").build();
+        // NB: tests run in the crate root - fixtures are in the workspace tests dir
+        let replay = Arc::new(Replay::read(Path::new("../tests/recordings/teleport-login.yml")).unwrap());
+        let ctx = scope(replay.clone(), async {
+            login(&conf, &region, false).await.unwrap();
+            kubectl::current_context().await.unwrap()
+        })
+        .await;
+        assert_eq!(ctx, "dev-ops");
+        assert_eq!(replay.remaining(), 0);
+    }
+}
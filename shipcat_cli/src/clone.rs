@@ -0,0 +1,151 @@
+use serde_yaml::Value;
+use shipcat_definitions::secretstore;
+use std::path::Path;
+
+use super::{Config, Result};
+
+/// Suffixes of keys whose string values are treated as secrets
+const SECRET_KEYS: [&str; 3] = ["token", "secret", "password"];
+
+/// A value changed while cloning a region
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloneChange {
+    /// Dot separated path to the value
+    pub path: String,
+    /// Original value, or `None` if it was a secret
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// A region derived from another region
+pub struct RegionClone {
+    /// The new region entry for shipcat.conf
+    pub region: Value,
+    /// What was changed from the source region
+    pub changes: Vec<CloneChange>,
+}
+
+impl RegionClone {
+    /// Diff style report of sanitized values
+    pub fn report(&self) -> String {
+        let mut lines = vec![];
+        for c in &self.changes {
+            match &c.old {
+                Some(old) => lines.push(format!("- {}: {}", c.path, old)),
+                None => lines.push(format!("- {}: <secret>", c.path)),
+            }
+            lines.push(format!("+ {}: {}", c.path, c.new));
+        }
+        lines.join("\n")
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEYS.iter().any(|s| key.ends_with(s))
+}
+
+/// Rewrite strings in a region and replace secrets with vault placeholders
+fn sanitize(
+    val: &mut Value,
+    path: &str,
+    key: &str,
+    rules: &[(String, String)],
+    changes: &mut Vec<CloneChange>,
+) {
+    match val {
+        Value::String(s) => {
+            if is_secret_key(key) && !secretstore::is_placeholder(s) {
+                changes.push(CloneChange {
+                    path: path.to_string(),
+                    old: None,
+                    new: "IN_VAULT".into(),
+                });
+                *s = "IN_VAULT".into();
+                return;
+            }
+            let mut new = s.clone();
+            for (from, to) in rules {
+                new = new.replace(from, to);
+            }
+            if &new != s {
+                changes.push(CloneChange {
+                    path: path.to_string(),
+                    old: Some(s.clone()),
+                    new: new.clone(),
+                });
+                *s = new;
+            }
+        }
+        Value::Mapping(m) => {
+            for (k, v) in m.iter_mut() {
+                if let Some(k) = k.as_str() {
+                    let p = if path.is_empty() {
+                        k.to_string()
+                    } else {
+                        format!("{}.{}", path, k)
+                    };
+                    sanitize(v, &p, k, rules, changes);
+                }
+            }
+        }
+        Value::Sequence(xs) => {
+            for (i, x) in xs.iter_mut().enumerate() {
+                sanitize(x, &format!("{}.{}", path, i), key, rules, changes);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Derive a new region from a region entry in a raw shipcat.conf
+///
+/// The clone is renamed to `to`. When sanitizing, every string has the source
+/// region name and the `regionCloneRewrites` of the config replaced, and strings
+/// under secret looking keys are replaced with `IN_VAULT` placeholders.
+pub fn clone_region(
+    conf: &Config,
+    raw: &Value,
+    from: &str,
+    to: &str,
+    sanitize_values: bool,
+) -> Result<RegionClone> {
+    let regions = match raw.get("regions").and_then(Value::as_sequence) {
+        Some(rs) => rs,
+        None => bail!("shipcat.conf has no regions"),
+    };
+    if regions.iter().any(|r| r["name"].as_str() == Some(to)) {
+        bail!("Region {} already exists in shipcat.conf", to);
+    }
+    let mut region = match regions.iter().find(|r| r["name"].as_str() == Some(from)) {
+        Some(r) => r.clone(),
+        None => bail!("Region {} does not exist in shipcat.conf", from),
+    };
+
+    let mut changes = vec![];
+    if sanitize_values {
+        let mut rules = vec![(from.to_string(), to.to_string())];
+        for r in &conf.regionCloneRewrites {
+            rules.push((r.from.clone(), r.to.clone()));
+        }
+        sanitize(&mut region, "", "", &rules, &mut changes);
+    }
+    if let Some(name) = region.get_mut("name") {
+        *name = to.into();
+    }
+    Ok(RegionClone { region, changes })
+}
+
+/// Print a region cloned from another region in shipcat.conf
+///
+/// The region is printed as yaml on stdout, and the report on stderr.
+pub async fn region(conf: &Config, from: &str, to: &str, sanitize_values: bool) -> Result<RegionClone> {
+    let data = tokio::fs::read_to_string(Path::new(".").join("shipcat.conf")).await?;
+    let raw: Value = serde_yaml::from_str(&data)?;
+    let clone = clone_region(conf, &raw, from, to, sanitize_values)?;
+    println!("{}", serde_yaml::to_string(&vec![&clone.region])?);
+    if !clone.changes.is_empty() {
+        eprintln!("{}", clone.report());
+    }
+    Ok(clone)
+}
@@ -4,16 +4,19 @@ use shipcat_filebacked::SimpleManifest;
 
 use super::{kubectl, Error, ErrorKind, Result};
 use crate::{
-    apply, diff, helm,
+    apply, diff, graph, helm,
     kubeapi::ShipKube,
+    plugins::Plugins,
+    shard::{self, Shard, ShardReport},
+    validate,
     webhooks::{self, UpgradeState},
 };
 
-struct DiffResult {
-    name: String,
-    diff: Option<String>,
+pub(crate) struct DiffResult {
+    pub(crate) name: String,
+    pub(crate) diff: Option<String>,
 }
-async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffResult> {
+pub(crate) async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffResult> {
     let mut mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
         .await?
         .complete(&reg)
@@ -24,11 +27,14 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
     mf.version = mf.version.or(crd.spec.version);
     mf.uid = crd.metadata.uid;
     info!("diffing {}", mf.name);
-    let d = if let Some(kdiffunobfusc) = diff::template_vs_kubectl(&mf).await? {
-        let kubediff = diff::obfuscate_secrets(
-            kdiffunobfusc, // move this away quickly..
-            mf.get_secrets(),
-        );
+    let d = if let Some(kdiffunmasked) = diff::template_vs_kubectl(&mf, conf, reg).await? {
+        let sensitive = conf.sensitive_env_regex();
+        let kubediff = diff::mask_secrets(
+            &kdiffunmasked,
+            &sensitive,
+            &mf.secret_config_keys(),
+            &mf.get_secrets(),
+        ); // move this away quickly..
         let smalldiff = diff::minify(&kubediff);
         Some(smalldiff)
     } else {
@@ -40,25 +46,65 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
     })
 }
 
+/// Run a mass operation, recording a report when it only covered a shard
+async fn with_report<F>(
+    command: &str,
+    reg: &Region,
+    shard: Option<&Shard>,
+    svcs: &[SimpleManifest],
+    op: F,
+) -> Result<()>
+where
+    F: std::future::Future<Output = Result<()>>,
+{
+    let names = shard::select(svcs, shard);
+    let res = op.await;
+    if let Some(s) = shard {
+        let mut report = ShardReport::new(command, reg, s, names);
+        report.ok = res.is_ok();
+        report.write()?;
+    }
+    res
+}
+
 /// Diffs all services in a region
 ///
-/// Helper that shells out to kubectl diff in parallel.
-pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
+/// Helper that shells out to kubectl diff in parallel, `n_workers` services at a time.
+/// With a `shard`, only the services of that shard are diffed.
+pub async fn mass_diff(conf: &Config, reg: &Region, n_workers: usize, shard: Option<&Shard>) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf, reg).await?;
+    let op = diff_services(shard::select(&svcs, shard), conf, reg, n_workers);
+    with_report("diff", reg, shard, &svcs, op).await
+}
+
+/// Summarise the services that failed a mass operation
+pub(crate) fn failure_summary(action: &str, mut failed: Vec<String>) -> Error {
+    failed.sort();
+    format!(
+        "Failed to {} {} manifests: {}",
+        action,
+        failed.len(),
+        failed.join(", ")
+    )
+    .into()
+}
+
+async fn diff_services(svcs: Vec<String>, conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
     assert!(conf.has_secrets());
 
     let mut buffered = stream::iter(svcs)
-        .map(move |mf| diff_summary(mf.base.name, &conf, &reg))
-        .buffer_unordered(10);
+        .map(move |svc| async move { (svc.clone(), diff_summary(svc, conf, reg).await) })
+        .buffer_unordered(n_workers);
 
     let mut errs = vec![];
     let mut diffs = vec![];
-    while let Some(r) = buffered.next().await {
+    while let Some((svc, r)) = buffered.next().await {
         match r {
             Ok(dr) => diffs.push(dr),
-            Err(e) => errs.push(e),
+            Err(e) => errs.push((svc, e)),
         }
     }
+    diffs.sort_by(|a, b| a.name.cmp(&b.name));
     for dr in diffs {
         if let Some(diff) = dr.diff {
             info!("{} diff output:\n{}", dr.name, diff);
@@ -67,27 +113,35 @@ pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
         }
     }
     if !errs.is_empty() {
-        for e in &errs {
+        let mut failed = vec![];
+        for (svc, e) in &errs {
             match e {
                 Error(ErrorKind::KubeError(e2), _) => {
-                    warn!("{}", e2); // probably missing service (undiffeable)
+                    warn!("{}: {}", svc, e2); // probably missing service (undiffeable)
                 }
                 Error(ErrorKind::MissingRollingVersion(svc), _) => {
                     // This only happens in rolling envs because version is mandatory in other envs
                     warn!("ignored missing service {}: {}", svc, e.description());
                 }
                 _ => {
-                    error!("{}", e);
+                    error!("{}: {}", svc, e);
                     debug!("{:?}", e);
                 }
             }
+            failed.push(svc.clone());
         }
-        bail!("Failed to diff {} manifests", errs.len());
+        return Err(failure_summary("diff", failed));
     }
     Ok(())
 }
 
-async fn check_summary(svc: String, skipped: &[String], conf: &Config, reg: &Region) -> Result<String> {
+async fn check_summary(
+    svc: String,
+    skipped: &[String],
+    plugins: &Plugins,
+    conf: &Config,
+    reg: &Region,
+) -> Result<String> {
     let mut mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
         .await?
         .stub(&reg)
@@ -96,38 +150,64 @@ async fn check_summary(svc: String, skipped: &[String], conf: &Config, reg: &Reg
     mf.uid = Some("FAKE-GUID".to_string());
 
     info!("verifying template for {}", mf.name);
-    let tpl = helm::template(&mf, None).await?;
-    helm::template_check(&mf, reg, skipped, &tpl)?;
+    plugins.validate(&mf)?;
+    let tpl = helm::template(&mf, conf, reg, None).await?;
+    helm::template_check(&mf, conf, reg, skipped, &tpl)?;
     Ok(mf.name)
 }
 
 /// Verifies all populated templates for all services in a region
 ///
-/// Helper that shells out to helm template in parallel.
-pub async fn mass_template_verify(conf: &Config, reg: &Region, skipped: &[String]) -> Result<()> {
+/// Helper that shells out to helm template in parallel, `n_workers` services at a time.
+/// With a `shard`, only the services of that shard are verified.
+pub async fn mass_template_verify(
+    conf: &Config,
+    reg: &Region,
+    skipped: &[String],
+    n_workers: usize,
+    shard: Option<&Shard>,
+) -> Result<()> {
+    // budgets span every service, so only one shard checks them
+    if shard.map_or(true, |s| s.index == 1) {
+        validate::resource_budgets(conf, reg, None).await?;
+    }
     let svcs = shipcat_filebacked::available(conf, reg).await?;
+    let op = verify_templates(shard::select(&svcs, shard), conf, reg, skipped, n_workers);
+    with_report("check", reg, shard, &svcs, op).await
+}
 
+async fn verify_templates(
+    svcs: Vec<String>,
+    conf: &Config,
+    reg: &Region,
+    skipped: &[String],
+    n_workers: usize,
+) -> Result<()> {
+    let plugins = &Plugins::load(conf)?;
     let mut buffered = stream::iter(svcs)
-        .map(move |mf| check_summary(mf.base.name, &skipped, &conf, &reg))
-        .buffer_unordered(100);
+        .map(move |svc| async move { (svc.clone(), check_summary(svc, skipped, plugins, conf, reg).await) })
+        .buffer_unordered(n_workers);
 
-    let (mut errs, mut passed): (Vec<Error>, Vec<_>) = (vec![], vec![]);
-    while let Some(r) = buffered.next().await {
+    let (mut errs, mut passed): (Vec<(String, Error)>, Vec<_>) = (vec![], vec![]);
+    while let Some((svc, r)) = buffered.next().await {
         match r {
             Ok(p) => passed.push(p),
-            Err(e) => errs.push(e),
+            Err(e) => errs.push((svc, e)),
         }
     }
 
+    passed.sort();
     for n in passed {
         info!("{} verified", n)
     }
     if !errs.is_empty() {
-        for e in &errs {
-            error!("{}", e);
+        let mut failed = vec![];
+        for (svc, e) in &errs {
+            error!("{}: {}", svc, e);
             debug!("{:?}", e);
+            failed.push(svc.clone());
         }
-        bail!("Failed to verify templates for {} manifest", errs.len());
+        return Err(failure_summary("verify templates for", failed));
     }
     Ok(())
 }
@@ -136,7 +216,7 @@ pub async fn mass_template_verify(conf: &Config, reg: &Region, skipped: &[String
 pub async fn crd_install(reg: &Region) -> Result<()> {
     use shipcat_definitions::gen_all_crds;
     for crdef in gen_all_crds() {
-        kubectl::apply_resource(&reg.name, crdef, &reg.namespace).await?;
+        kubectl::apply_resource(&reg.name, crdef, &reg.namespace, false).await?;
     }
     Ok(())
 }
@@ -144,17 +224,30 @@ pub async fn crd_install(reg: &Region) -> Result<()> {
 /// Apply all services in the region
 ///
 /// Helper that shells out to kubectl apply in parallel.
-pub async fn mass_crd(conf_sec: &Config, conf_base: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+/// With a `shard`, only the services of that shard are applied (or removed if excess).
+/// When `ordered`, dependencies are applied before their dependents,
+/// with independent services applied in parallel.
+pub async fn mass_crd(
+    conf_sec: &Config,
+    conf_base: &Config,
+    reg: &Region,
+    n_workers: usize,
+    shard: Option<&Shard>,
+    ordered: bool,
+) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf_base, reg).await?;
-    crd_reconcile(svcs, conf_sec, conf_base, &reg.name, n_workers).await
+    let op = crd_reconcile(&svcs, conf_sec, conf_base, &reg.name, n_workers, shard, ordered);
+    with_report("crd-reconcile", reg, shard, &svcs, op).await
 }
 
 async fn crd_reconcile(
-    svcs: Vec<SimpleManifest>,
+    svcs: &[SimpleManifest],
     config_sec: &Config,
     config_base: &Config,
     region: &str,
     n_workers: usize,
+    shard: Option<&Shard>,
+    ordered: bool,
 ) -> Result<()> {
     // NB: This needs config_base for base crd application
     // shipcatconfig crd should not have secrets when applied
@@ -187,11 +280,17 @@ async fn crd_reconcile(
         config_base.clone()
     }
     .into();
-    kubectl::apply_resource(&region_base.name, applycfg, &region_base.namespace).await?;
+    kubectl::apply_resource(&region_base.name, applycfg, &region_base.namespace, false).await?;
 
     // Single instruction kubectl delete shipcat manifests .... of excess ones
+    // NB: excess is relative to all services, but each shard only removes its own
     let svc_names = svcs.iter().map(|x| x.base.name.to_string()).collect::<Vec<_>>();
-    let excess = kubectl::find_redundant_manifests(&region_sec.namespace, &svc_names).await?;
+    let excess = kubectl::find_redundant_manifests(&region_sec.namespace, &svc_names)
+        .await?
+        .into_iter()
+        .filter(|svc| shard::owns(shard, svc))
+        .collect::<Vec<_>>();
+    let svcs = shard::select(svcs, shard);
     if !excess.is_empty() {
         info!("Will remove excess manifests: {:?}", excess);
     }
@@ -206,25 +305,39 @@ async fn crd_reconcile(
         n_workers
     );
 
+    // NB: only orders within the shard; dependencies in other shards are ignored
+    let waves = if ordered {
+        graph::ordered(&svcs, config_base, &region_base).await?
+    } else {
+        vec![svcs]
+    };
+
     webhooks::reconcile_event(UpgradeState::Started, &region_sec).await;
     // then parallel apply the remaining ones
     let force = std::env::var("SHIPCAT_MASS_RECONCILE").unwrap_or("0".into()) == "1";
     let wait_for_rollout = true;
 
-    let conf = config_sec.clone();
-    let reg = region_sec.clone();
-    let mut buffered = stream::iter(svcs)
-        .map(|mf| {
-            debug!("Running CRD reconcile for {:?}", mf.base.name);
-            apply::apply(mf.base.name, force, &reg, &conf, wait_for_rollout, None)
-        })
-        .buffer_unordered(n_workers);
-
     let mut errs = vec![];
-    while let Some(r) = buffered.next().await {
-        if let Err(e) = r {
-            warn!("{}", e);
-            errs.push(e);
+    for (i, wave) in waves.into_iter().enumerate() {
+        if ordered {
+            info!("Applying dependency wave {}: {:?}", i + 1, wave);
+        }
+        let mut buffered = stream::iter(wave)
+            .map(|svc| {
+                debug!("Running CRD reconcile for {:?}", svc);
+                apply::apply(svc, force, &region_sec, config_sec, wait_for_rollout, None, false)
+            })
+            .buffer_unordered(n_workers);
+
+        while let Some(r) = buffered.next().await {
+            if let Err(e) = r {
+                warn!("{}", e);
+                errs.push(e);
+            }
+        }
+        // dependents of a failed wave should not be applied
+        if ordered && errs.iter().any(|e| !is_ignorable(e)) {
+            break;
         }
     }
 
@@ -251,6 +364,11 @@ async fn crd_reconcile(
     Ok(())
 }
 
+/// Whether a reconcile error should not fail the reconcile
+fn is_ignorable(e: &Error) -> bool {
+    matches!(e, Error(ErrorKind::MissingRollingVersion(_), _))
+}
+
 /// Apply all vault policies in a region
 ///
 /// Generates and writes policies direct to vault using their github team name as auth mappers.
@@ -0,0 +1,234 @@
+use url::Url;
+
+use super::{kubectl, Manifest, Region, Result};
+use shipcat_definitions::structs::InfraDependency;
+
+/// Image for throwaway connectivity pods
+///
+/// Needs `sh` and `nc`; TLS checks also need `openssl`.
+pub const DEFAULT_IMAGE: &str = "alpine:3.12";
+
+/// Prefix of result lines printed by the check script
+const MARKER: &str = "shipcat-check";
+
+/// A resolved infrastructure dependency to check
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub name: String,
+    pub kind: String,
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+}
+
+/// Split a host source (`host`, `host:port`, or a url) into a host and port
+fn host_port(source: &str, default_port: u16) -> Result<(String, u16)> {
+    let (host, port) = if source.contains("://") {
+        let url = Url::parse(source)?;
+        let host = match url.host_str() {
+            Some(h) => h.to_string(),
+            None => bail!("No host in {} url", url.scheme()),
+        };
+        (host, url.port().unwrap_or(default_port))
+    } else {
+        match source.rsplitn(2, ':').collect::<Vec<_>>().as_slice() {
+            [p, h] => (h.to_string(), p.parse()?),
+            _ => (source.to_string(), default_port),
+        }
+    };
+    // hosts end up in a shell script, so keep them boring
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_';
+    if host.is_empty() || !host.chars().all(valid) {
+        bail!("Invalid host '{}'", host);
+    }
+    Ok((host, port))
+}
+
+/// Resolve where an infra dependency of the manifest lives
+///
+/// Environment variables are read from the completed manifest, so secrets must be resolved.
+pub fn target(d: &InfraDependency, mf: &Manifest, reg: &Region) -> Result<Target> {
+    let source = match (&d.env, &d.baseUrl) {
+        (Some(e), _) => mf.env.plain.get(e).or_else(|| mf.secrets.get(e)),
+        (None, Some(b)) => reg.base_urls.get(b),
+        (None, None) => None,
+    };
+    let source = match source {
+        Some(s) => s,
+        None => bail!("Could not find a host for infra dependency {}", d.name()),
+    };
+    let (host, port) = host_port(source, d.default_port())?;
+    Ok(Target {
+        name: d.name(),
+        kind: d.kind.to_string(),
+        host,
+        port,
+        tls: d.tls,
+    })
+}
+
+/// Resolve where each of the manifest's infra dependencies lives
+pub fn resolve(mf: &Manifest, reg: &Region) -> Result<Vec<Target>> {
+    mf.infraDependencies.iter().map(|d| target(d, mf, reg)).collect()
+}
+
+/// Shell script checking every target from inside a pod
+///
+/// Prints one `shipcat-check <name> <ok|fail|skip> <tcp|tls>` line per target.
+pub fn script(targets: &[Target]) -> String {
+    let mut lines = vec![
+        "check() {".to_string(),
+        format!(
+            "  if ! nc -z -w 5 \"$2\" \"$3\" >/dev/null 2>&1; then echo \"{} $1 fail tcp\"; return; fi",
+            MARKER
+        ),
+        format!(
+            "  if [ \"$4\" != 1 ]; then echo \"{} $1 ok tcp\"; return; fi",
+            MARKER
+        ),
+        format!(
+            "  if ! command -v openssl >/dev/null; then echo \"{} $1 skip tls\"; return; fi",
+            MARKER
+        ),
+        "  if echo | openssl s_client -connect \"$2:$3\" -servername \"$2\" >/dev/null 2>&1; then".into(),
+        format!("    echo \"{} $1 ok tls\"", MARKER),
+        "  else".into(),
+        format!("    echo \"{} $1 fail tls\"", MARKER),
+        "  fi".into(),
+        "}".into(),
+    ];
+    for t in targets {
+        lines.push(format!(
+            "check {} {} {} {}",
+            t.name,
+            t.host,
+            t.port,
+            if t.tls { 1 } else { 0 }
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Result of checking one target
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// Reachable, with the check that passed
+    Reachable(String),
+    /// TCP worked, but TLS could not be checked from the pod
+    Unverified(String),
+    /// Not reachable, with the check that failed
+    Unreachable(String),
+    /// The script did not report on the target
+    Unknown,
+}
+
+/// Match script output back to the targets
+pub fn outcomes(targets: &[Target], output: &str) -> Vec<Outcome> {
+    targets
+        .iter()
+        .map(|t| {
+            let line = output.lines().find_map(|l| {
+                let parts = l.split_whitespace().collect::<Vec<_>>();
+                match parts.as_slice() {
+                    [m, name, status, check] if *m == MARKER && *name == t.name => {
+                        Some((status.to_string(), check.to_string()))
+                    }
+                    _ => None,
+                }
+            });
+            match line {
+                Some((s, c)) if s == "ok" => Outcome::Reachable(c),
+                Some((s, c)) if s == "skip" => Outcome::Unverified(c),
+                Some((_, c)) => Outcome::Unreachable(c),
+                None => Outcome::Unknown,
+            }
+        })
+        .collect()
+}
+
+/// Check that a service's infra dependencies are reachable from inside the cluster
+///
+/// Runs a throwaway pod in the service's namespace, or execs into a running pod when `exec` is set.
+pub async fn check(mf: &Manifest, reg: &Region, exec: bool, image: &str) -> Result<()> {
+    let targets = resolve(mf, reg)?;
+    if targets.is_empty() {
+        bail!("{} does not declare any infraDependencies", mf.name);
+    }
+    let script = script(&targets);
+    let output = if exec {
+        kubectl::exec_script(mf, &script).await?
+    } else {
+        let mut pod = format!("shipcat-connectivity-{}", mf.name);
+        pod.truncate(63);
+        kubectl::run_script(&mf.namespace, pod.trim_end_matches('-'), image, &script, None).await?
+    };
+    debug!("connectivity output: {}", output);
+
+    let mut failed = 0;
+    for (t, o) in targets.iter().zip(outcomes(&targets, &output)) {
+        let (mark, detail) = match o {
+            Outcome::Reachable(c) => ("ok", format!("{} reachable", c)),
+            Outcome::Unverified(c) => ("warn", format!("tcp reachable, but no openssl to check {}", c)),
+            Outcome::Unreachable(c) => {
+                failed += 1;
+                ("FAIL", format!("{} unreachable", c))
+            }
+            Outcome::Unknown => {
+                failed += 1;
+                ("FAIL", "no result from pod".to_string())
+            }
+        };
+        println!(
+            "[{:>4}] {} ({} at {}:{}): {}",
+            mark, t.name, t.kind, t.host, t.port, detail
+        );
+    }
+    if failed > 0 {
+        bail!(
+            "{} of {} infra dependencies of {} are unreachable",
+            failed,
+            targets.len(),
+            mf.name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host_port, outcomes, script, Outcome, Target};
+
+    #[test]
+    fn connectivity_host_sources() {
+        let pg = host_port("postgres://user:pw@db.internal:6432/app", 5432).unwrap();
+        assert_eq!(pg, ("db.internal".to_string(), 6432));
+        let redis = host_port("redis://cache.internal", 6379).unwrap();
+        assert_eq!(redis, ("cache.internal".to_string(), 6379));
+        assert_eq!(host_port("mq:5671", 5672).unwrap(), ("mq".to_string(), 5671));
+        assert_eq!(host_port("mq", 5672).unwrap(), ("mq".to_string(), 5672));
+        assert!(host_port("mq;reboot", 5672).is_err());
+        assert!(host_port("mq:port", 5672).is_err());
+    }
+
+    #[test]
+    fn connectivity_outcomes() {
+        let target = |name: &str, tls| Target {
+            name: name.into(),
+            kind: "postgres".into(),
+            host: "db".into(),
+            port: 5432,
+            tls,
+        };
+        let targets = vec![target("db", false), target("cache", true), target("mq", false)];
+        assert!(script(&targets).contains("check cache db 5432 1"));
+
+        let out = "shipcat-check db ok tcp\nshipcat-check cache skip tls\n";
+        assert_eq!(outcomes(&targets, out), vec![
+            Outcome::Reachable("tcp".into()),
+            Outcome::Unverified("tls".into()),
+            Outcome::Unknown,
+        ]);
+        let out = "shipcat-check mq fail tcp\n";
+        assert_eq!(outcomes(&targets, out)[2], Outcome::Unreachable("tcp".into()));
+    }
+}
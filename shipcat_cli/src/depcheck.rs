@@ -0,0 +1,160 @@
+use semver::{Version, VersionReq};
+use shipcat_definitions::Environment;
+use std::{collections::BTreeMap, fmt};
+
+use super::{kubeapi::ShipKube, Config, Manifest, Region, Result};
+
+/// A dependency whose version is outside the range a service declares
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub service: String,
+    pub dependency: String,
+    pub required: VersionReq,
+    pub actual: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requires {} {}, but found {}",
+            self.service, self.dependency, self.required, self.actual
+        )
+    }
+}
+
+/// Compare the dependency ranges of a service against known dependency versions
+///
+/// Dependencies without a range, without a known version, or with a non-semver
+/// version (like a git sha) are not checked.
+pub fn violations(mf: &Manifest, versions: &BTreeMap<String, String>) -> Vec<Violation> {
+    let mut res = vec![];
+    for d in &mf.dependencies {
+        let (req, actual) = match (&d.version, versions.get(&d.name)) {
+            (Some(r), Some(v)) => (r, v),
+            _ => continue,
+        };
+        match Version::parse(actual) {
+            Ok(v) if req.matches(&v) => {}
+            Ok(_) => res.push(Violation {
+                service: mf.name.clone(),
+                dependency: d.name.clone(),
+                required: req.clone(),
+                actual: actual.clone(),
+            }),
+            Err(_) => debug!("Not checking non-semver version {} of {}", actual, d.name),
+        }
+    }
+    res
+}
+
+fn ranged_dependencies(mf: &Manifest) -> Vec<String> {
+    mf.dependencies
+        .iter()
+        .filter(|d| d.version.is_some())
+        .map(|d| d.name.clone())
+        .collect()
+}
+
+/// Versions of a service's ranged dependencies as declared in their manifests
+pub async fn declared_versions(
+    mf: &Manifest,
+    conf: &Config,
+    reg: &Region,
+) -> Result<BTreeMap<String, String>> {
+    let mut versions = BTreeMap::new();
+    for dep in ranged_dependencies(mf) {
+        match shipcat_filebacked::load_manifest(&dep, conf, reg).await {
+            Ok(d) => {
+                if let Some(v) = d.version {
+                    versions.insert(dep, v);
+                }
+            }
+            Err(e) => debug!("No manifest for dependency {} in {}: {}", dep, reg.name, e),
+        }
+    }
+    Ok(versions)
+}
+
+/// Versions of a service's ranged dependencies running in the region
+///
+/// Uses the last successfully rolled out version, falling back to the applied version.
+pub async fn running_versions(mf: &Manifest, reg: &Region) -> Result<BTreeMap<String, String>> {
+    let mut versions = BTreeMap::new();
+    for dep in ranged_dependencies(mf) {
+        let crd = match ShipKube::new_within(&dep, &reg.namespace).await?.get().await {
+            Ok(o) => o,
+            Err(e) => {
+                debug!("Dependency {} not running in {}: {}", dep, reg.name, e);
+                continue;
+            }
+        };
+        let rolled_out = crd
+            .status
+            .and_then(|s| s.summary)
+            .and_then(|s| s.last_successful_rollout_version);
+        if let Some(v) = rolled_out.or(crd.spec.version) {
+            versions.insert(dep, v);
+        }
+    }
+    Ok(versions)
+}
+
+/// Block prod applies of a service whose running dependencies violate its version ranges
+///
+/// Outside prod, violations are only warned about.
+pub async fn strict(mf: &Manifest, reg: &Region) -> Result<()> {
+    let found = violations(mf, &running_versions(mf, reg).await?);
+    for v in &found {
+        warn!("{}", v);
+    }
+    if !found.is_empty() && reg.environment == Environment::Prod {
+        bail!(
+            "{} has {} dependency version violations in {}",
+            mf.name,
+            found.len(),
+            reg.name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::violations;
+    use crate::Manifest;
+    use shipcat_definitions::structs::Dependency;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn dependency_version_ranges() {
+        let mut mf = Manifest::test("fake-ask");
+        let dep = |name: &str, range: Option<&str>| Dependency {
+            name: name.into(),
+            version: range.map(|r| r.parse().unwrap()),
+            ..Dependency::default()
+        };
+        mf.dependencies = vec![
+            dep("auth", Some(">=2.3")),
+            dep("billing", Some("^1.0")),
+            dep("search", Some("^1.0")),
+            dep("unranged", None),
+        ];
+        let mut versions = BTreeMap::new();
+        versions.insert("auth".to_string(), "2.4.1".to_string());
+        versions.insert("billing".to_string(), "2.0.0".to_string());
+        versions.insert(
+            "search".to_string(),
+            "e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19".to_string(),
+        );
+        versions.insert("unranged".to_string(), "0.1.0".to_string());
+
+        let found = violations(&mf, &versions);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].dependency, "billing");
+        assert_eq!(
+            found[0].to_string(),
+            "fake-ask requires billing ^1.0, but found 2.0.0"
+        );
+    }
+}
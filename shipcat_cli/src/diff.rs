@@ -1,8 +1,9 @@
-use super::{Config, ConfigState, Manifest, Region, Result};
+use super::{Config, ConfigState, Error, Manifest, Region, Result};
 use crate::{git, helm, kubectl};
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use shipcat_definitions::ShipcatManifest;
-use std::process::Command;
+use std::{process::Command, str::FromStr};
 
 /// YAML serialisation of a manifest.
 ///
@@ -19,13 +20,34 @@ async fn as_yaml(svc: &str, conf: &Config, region: &Region) -> Result<String> {
     }
 }
 
+/// Output format of diffs between two local renders
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffFormat {
+    /// Unified diff from diff(1)
+    Text,
+    /// Json list of changed paths, see `structured_diff`
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            _ => bail!("Diff format must be text or json"),
+        }
+    }
+}
+
 /// Fast local git compare of the crd
 ///
 /// Should be pretty safe. Stashes existing work, checks out master, compares,
 /// then goes back to previous branch and pops the stash.
 ///
 /// Because this does fiddle with git state while running it is not the default implementation.
-pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
+pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region, fmt: DiffFormat) -> Result<bool> {
     let after = as_yaml(&svc, conf, region).await?;
 
     // move git to get before state:
@@ -48,7 +70,7 @@ pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<
     git::checkout("-")?;
 
     // display diff
-    shell_diff(&before, &after, "before", "after")
+    compare(&before, &after, ("before", "after"), fmt, conf)
 }
 
 /// Fast local compare of shipcat template for two regions
@@ -57,6 +79,7 @@ pub async fn values_vs_region(
     conf: &Config,
     region: &Region,
     ref_region: &Region,
+    fmt: DiffFormat,
 ) -> Result<bool> {
     let before_region = format!("{}.{}", svc, ref_region.name);
     let before_values = as_yaml(svc, conf, ref_region).await?;
@@ -65,20 +88,181 @@ pub async fn values_vs_region(
     let after_values = as_yaml(svc, conf, region).await?;
 
     // display diff
-    shell_diff(&before_values, &after_values, &before_region, &after_region)
+    let names = (before_region.as_str(), after_region.as_str());
+    compare(&before_values, &after_values, names, fmt, conf)
+}
+
+/// Manifest fields that differ between regions by definition
+const REGION_IDENTITY_KEYS: &[&str] = &["region", "environment", "namespace"];
+
+/// Flatten a json value into its leaf values keyed by path
+///
+/// Object keys are joined with dots and array indices are bracketed, like `sidecars[0].image`.
+pub fn flatten_values(value: &serde_json::Value) -> BTreeMap<String, String> {
+    fn walk(prefix: String, value: &serde_json::Value, res: &mut BTreeMap<String, String>) {
+        use serde_json::Value;
+        match value {
+            Value::Object(o) => {
+                for (k, v) in o {
+                    let key = if prefix.is_empty() {
+                        k.clone()
+                    } else {
+                        format!("{}.{}", prefix, k)
+                    };
+                    walk(key, v, res);
+                }
+            }
+            Value::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    walk(format!("{}[{}]", prefix, i), v, res);
+                }
+            }
+            Value::String(s) => {
+                res.insert(prefix, s.clone());
+            }
+            Value::Null => {}
+            v => {
+                res.insert(prefix, v.to_string());
+            }
+        }
+    }
+    let mut res = BTreeMap::new();
+    walk(String::new(), value, &mut res);
+    res
+}
+
+/// A key whose value is not the same in every region
+#[derive(Debug, PartialEq)]
+pub struct KeyDrift {
+    pub key: String,
+    /// Value in every region, None where the key is unset
+    pub values: BTreeMap<String, Option<String>>,
+}
+
+impl KeyDrift {
+    /// Top level manifest field of the key, like `env` or `resources`
+    pub fn group(&self) -> &str {
+        self.key.split(&['.', '['][..]).next().unwrap_or_default()
+    }
+}
+
+/// Structured diff of the flattened values of a service in several regions
+pub fn region_drift(values: &BTreeMap<String, BTreeMap<String, String>>) -> Vec<KeyDrift> {
+    let mut keys = values.values().flat_map(|v| v.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    let mut res = vec![];
+    for key in keys {
+        let by_region = values
+            .iter()
+            .map(|(r, v)| (r.clone(), v.get(key).cloned()))
+            .collect::<BTreeMap<_, _>>();
+        let mut distinct = by_region.values().collect::<Vec<_>>();
+        distinct.dedup();
+        if distinct.len() > 1 {
+            res.push(KeyDrift {
+                key: key.clone(),
+                values: by_region,
+            });
+        }
+    }
+    res.sort_by(|a, b| a.group().cmp(b.group()).then_with(|| a.key.cmp(&b.key)));
+    res
+}
+
+/// Print drifts as a matrix of keys against regions, grouped by manifest field
+fn print_drift(regions: &[String], drift: &[KeyDrift]) {
+    let cell = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".into());
+    let key_width = drift.iter().map(|d| d.key.len()).max().unwrap_or(0).max(3);
+    let widths = regions
+        .iter()
+        .map(|r| {
+            drift
+                .iter()
+                .map(|d| cell(&d.values[r]).len())
+                .max()
+                .unwrap_or(0)
+                .max(r.len())
+        })
+        .collect::<Vec<_>>();
+    let mut header = format!("{:<w$}", "KEY", w = key_width);
+    for (r, w) in regions.iter().zip(&widths) {
+        header += &format!("  {:<w$}", r, w = *w);
+    }
+    println!("{}", header.trim_end());
+    let mut group = "";
+    for d in drift {
+        if d.group() != group {
+            group = d.group();
+            println!("== {}", group);
+        }
+        let mut row = format!("{:<w$}", d.key, w = key_width);
+        for (r, w) in regions.iter().zip(&widths) {
+            row += &format!("  {:<w$}", cell(&d.values[r]), w = *w);
+        }
+        println!("{}", row.trim_end());
+    }
+}
+
+/// Compare the values of a service across every region it is deployed to
+///
+/// Values are stubbed, so secrets never show up as differences.
+/// Prints a matrix of the keys that differ, grouped by manifest field,
+/// to spot version skew, env drift and resource differences at a glance.
+pub async fn values_across_regions(svc: &str, conf: &Config) -> Result<bool> {
+    let base = match shipcat_filebacked::all_unchecked(conf)
+        .await?
+        .into_iter()
+        .find(|b| b.name == svc)
+    {
+        Some(b) => b,
+        None => bail!("Service {} does not exist", svc),
+    };
+    let known = conf.list_regions();
+    let regions = base
+        .regions
+        .iter()
+        .filter(|r| known.contains(r) && !base.disabled_in.iter().any(|d| d.region == **r))
+        .cloned()
+        .collect::<Vec<_>>();
+    if regions.len() < 2 {
+        info!("{} is only deployed to {}", svc, regions.join(", "));
+        return Ok(true);
+    }
+
+    let mut values = BTreeMap::new();
+    for r in &regions {
+        let (rconf, reg) = Config::new(ConfigState::Base, r).await?;
+        let mf = shipcat_filebacked::load_manifest(svc, &rconf, &reg)
+            .await?
+            .stub(&reg)
+            .await?;
+        let flat = flatten_values(&serde_json::to_value(&mf)?)
+            .into_iter()
+            .filter(|(k, _)| !REGION_IDENTITY_KEYS.contains(&k.as_str()))
+            .collect::<BTreeMap<_, _>>();
+        values.insert(r.clone(), flat);
+    }
+    let drift = region_drift(&values);
+    if drift.is_empty() {
+        println!("{} has the same values in {}", svc, regions.join(", "));
+        return Ok(true);
+    }
+    print_drift(&regions, &drift);
+    Ok(false)
 }
 
 /// Fast local git compare of shipcat template
 ///
 /// Because this uses the template in master against local state,
 /// we don't resolve secrets for this (would compare equal values anyway).
-pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
+pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region, fmt: DiffFormat) -> Result<bool> {
     let afterpth = Path::new(".").join("after.shipcat.gen.yml");
     let mf_after = shipcat_filebacked::load_manifest(svc, conf, region)
         .await?
         .stub(region)
         .await?;
-    let _after = helm::template(&mf_after, Some(afterpth.clone())).await?;
+    let after = helm::template(&mf_after, conf, region, Some(afterpth.clone())).await?;
 
     // move git to get before state:
     let merge_base = git::merge_base()?;
@@ -97,7 +281,7 @@ pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Resul
         .await?
         .stub(region)
         .await?;
-    let _before = helm::template(&mf_before, Some(beforepth.clone())).await?;
+    let before = helm::template(&mf_before, conf, region, Some(beforepth.clone())).await?;
 
     // move git back
     if needs_stash {
@@ -107,20 +291,97 @@ pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Resul
 
     // display diff
     // doesn't reuse shell_diff because we already have files from direct::template
-    let args = ["-u", "before.shipcat.gen.yml", "after.shipcat.gen.yml"];
-    debug!("diff {}", args.join(" "));
-    let s = Command::new("diff").args(&args).status()?;
+    let res = match fmt {
+        DiffFormat::Text => {
+            let args = ["-u", "before.shipcat.gen.yml", "after.shipcat.gen.yml"];
+            debug!("diff {}", args.join(" "));
+            Command::new("diff").args(&args).status()?.success()
+        }
+        DiffFormat::Json => print_changes(&before, &after, &conf.sensitive_env_regex())?,
+    };
     // cleanup
     fs::remove_file(beforepth)?;
     fs::remove_file(afterpth)?;
-    Ok(s.success())
+    Ok(res)
 }
 
 use std::{
+    collections::BTreeMap,
+    env,
     fs::{self, File},
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
 };
+use uuid::Uuid;
+
+/// Render a service with the shipcat binary, reading manifests in `dir`
+///
+/// Renders the crd when `crd` is set, otherwise the full kube yaml, without secrets.
+/// Runs as a subprocess so that manifests elsewhere never change the process cwd.
+fn render(dir: &Path, svc: &str, region_name: &str, crd: bool) -> Result<String> {
+    let subcmd = if crd { "crd" } else { "template" };
+    let args = [subcmd, svc, "-r", region_name];
+    debug!("shipcat {} in {}", args.join(" "), dir.display());
+    let s = Command::new(env::current_exe()?)
+        .args(&args)
+        .current_dir(dir)
+        .output()?;
+    if !s.status.success() {
+        let err = String::from_utf8_lossy(&s.stderr);
+        bail!("Failed to render {} in {}: {}", svc, dir.display(), err.trim());
+    }
+    Ok(String::from_utf8_lossy(&s.stdout).into())
+}
+
+/// A temporary git worktree, removed on drop
+struct Worktree {
+    path: PathBuf,
+}
+
+impl Worktree {
+    fn add(reference: &str) -> Result<Worktree> {
+        let path = env::temp_dir().join(format!("shipcat-{}", Uuid::new_v4()));
+        git::worktree_add(&path.to_string_lossy(), reference)?;
+        Ok(Worktree { path })
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        if let Err(e) = git::worktree_remove(&self.path.to_string_lossy()) {
+            warn!("Failed to remove git worktree {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Render a service from the manifests as they were at a git ref
+///
+/// Checks out the ref in a temporary worktree, leaving local state untouched.
+fn render_at_ref(svc: &str, region_name: &str, reference: &str, crd: bool) -> Result<String> {
+    let prefix = git::show_prefix()?;
+    let tree = Worktree::add(reference)?;
+    render(&tree.path.join(prefix), svc, region_name, crd)
+}
+
+/// Compare a service against how it rendered at an arbitrary git ref
+///
+/// Compares the full kube yaml unless `crd` is set.
+/// Uses a git worktree rather than stashing, so it is safe to run with local changes.
+pub async fn vs_ref(
+    svc: &str,
+    conf: &Config,
+    region: &Region,
+    reference: &str,
+    crd: bool,
+    fmt: DiffFormat,
+) -> Result<bool> {
+    let after = render(Path::new("."), svc, &region.name, crd)?;
+    let before = render_at_ref(svc, &region.name, reference, crd)?;
+    let before_name = format!("{}.{}", svc, reference.replace('/', "-"));
+    let after_name = format!("{}.local", svc);
+    let names = (before_name.as_str(), after_name.as_str());
+    compare(&before, &after, names, fmt, conf)
+}
 
 /// Diff values using kubectl diff
 ///
@@ -148,12 +409,12 @@ pub async fn values_vs_kubectl(svc: &str, conf: &Config, region: &Region) -> Res
 ///
 /// Generate template as we write it and pipe it to `kubectl diff -`
 /// Only works on clusters with kubectl 1.13 on the server side, so not available everywhere
-pub async fn template_vs_kubectl(mf: &Manifest) -> Result<Option<String>> {
+pub async fn template_vs_kubectl(mf: &Manifest, conf: &Config, reg: &Region) -> Result<Option<String>> {
     // Generate template in a temp file:
     let tfile = format!("{}.shipcat.tpl.gen.yml", mf.name);
     let pth = Path::new(".").join(tfile);
 
-    let _tpl = helm::template(&mf, Some(pth.clone())).await?;
+    let _tpl = helm::template(mf, conf, reg, Some(pth.clone())).await?;
 
     let (out, err, success) = kubectl::diff(pth.clone(), &mf.namespace).await?;
     // cleanup:
@@ -168,6 +429,135 @@ pub async fn template_vs_kubectl(mf: &Manifest) -> Result<Option<String>> {
     }
 }
 
+/// Kind of change to a path between two renders
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A changed leaf value between two renders
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PathChange {
+    pub path: String,
+    pub change: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+/// Whether the value at a path of a document holds a secret
+///
+/// Mirrors `mask_secrets`: Secret data, sensitive env vars, the manifest's resolved
+/// secrets and the last applied configuration annotation are all masked.
+fn is_secret_path(kind: &str, path: &str, doc: &BTreeMap<String, String>, sensitive: &Regex) -> bool {
+    if kind == "Secret" && (path.starts_with("data.") || path.starts_with("stringData.")) {
+        return true;
+    }
+    if path.contains(LAST_APPLIED) || path.starts_with("secrets.") || path.starts_with("secretFiles.") {
+        return true;
+    }
+    // kube env lists name their vars in a sibling key
+    if path.ends_with(".value") {
+        let name = format!("{}.name", path.trim_end_matches(".value"));
+        if path.contains("env[") && doc.get(&name).map_or(false, |n| sensitive.is_match(n)) {
+            return true;
+        }
+    }
+    // manifest env maps are keyed by the var name
+    let mut segments = path.split('.');
+    segments.any(|s| s == "env") && segments.any(|s| sensitive.is_match(s))
+}
+
+/// Flattened leaf values of every document in a yaml stream, with secrets masked
+///
+/// Kube objects are prefixed with `{kind}/{name}:`, so paths stay stable when objects move around.
+fn flatten_documents(yaml: &str, sensitive: &Regex) -> Result<BTreeMap<String, String>> {
+    let mut docs = vec![];
+    let mut doc = String::new();
+    for line in yaml.lines() {
+        if line.trim_end() == "---" {
+            docs.push(std::mem::take(&mut doc));
+        } else {
+            doc.push_str(line);
+            doc.push('\n');
+        }
+    }
+    docs.push(doc);
+
+    let mut res = BTreeMap::new();
+    for doc in docs {
+        let value: serde_yaml::Value = serde_yaml::from_str(&doc)?;
+        if value.is_null() {
+            continue;
+        }
+        let kind = value["kind"].as_str().unwrap_or_default().to_string();
+        let prefix = match value["metadata"]["name"].as_str() {
+            Some(name) if !kind.is_empty() => format!("{}/{}:", kind, name),
+            _ => String::new(),
+        };
+        let flat = flatten_values(&serde_json::to_value(&value)?);
+        for (k, v) in &flat {
+            let v = if is_secret_path(&kind, k, &flat, sensitive) {
+                masked(v)
+            } else {
+                v.clone()
+            };
+            res.insert(format!("{}{}", prefix, k), v);
+        }
+    }
+    Ok(res)
+}
+
+/// Structured diff of two yaml renders (single manifests or kube yaml streams)
+///
+/// Compares the deserialized trees rather than their text, and returns every added,
+/// removed or modified leaf path sorted by path. Secret values are masked.
+pub fn structured_diff(before: &str, after: &str, sensitive: &Regex) -> Result<Vec<PathChange>> {
+    let before = flatten_documents(before, sensitive)?;
+    let after = flatten_documents(after, sensitive)?;
+    let mut paths = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+    let mut res = vec![];
+    for p in paths {
+        let (old, new) = (before.get(p).cloned(), after.get(p).cloned());
+        let change = match (&old, &new) {
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(_), None) => ChangeKind::Removed,
+            (Some(o), Some(n)) if o != n => ChangeKind::Modified,
+            _ => continue,
+        };
+        res.push(PathChange {
+            path: p.clone(),
+            change,
+            old,
+            new,
+        });
+    }
+    Ok(res)
+}
+
+/// Print the structured diff of two renders as json, returning whether they were equal
+fn print_changes(before: &str, after: &str, sensitive: &Regex) -> Result<bool> {
+    let changes = structured_diff(before, after, sensitive)?;
+    println!("{}", serde_json::to_string_pretty(&changes)?);
+    Ok(changes.is_empty())
+}
+
+/// Show the difference between two renders in the requested format
+///
+/// Returns whether the renders were equal, like diff(1).
+fn compare(before: &str, after: &str, names: (&str, &str), fmt: DiffFormat, conf: &Config) -> Result<bool> {
+    match fmt {
+        DiffFormat::Text => shell_diff(before, after, names.0, names.1),
+        DiffFormat::Json => print_changes(before, after, &conf.sensitive_env_regex()),
+    }
+}
+
 // Compare using diff(1)
 // difference libraries all seemed to be lacking somewhat
 fn shell_diff(before: &str, after: &str, before_name: &str, after_name: &str) -> Result<bool> {
@@ -221,6 +611,13 @@ pub fn minify(diff: &str) -> String {
 
 /// Check if a diff contains only version related changes
 pub fn is_version_only(diff: &str, vers: (&str, &str)) -> bool {
+    is_versions_only(diff, &[vers])
+}
+
+/// Check if a diff contains only changes of any of the given versions
+///
+/// Used when sidecars or init containers changed version along with the service.
+pub fn is_versions_only(diff: &str, vers: &[(&str, &str)]) -> bool {
     let smalldiff = minify(diff);
     trace!("Checking diff for {:?}", vers);
     for l in smalldiff.lines() {
@@ -229,7 +626,7 @@ pub fn is_version_only(diff: &str, vers: (&str, &str)) -> bool {
             continue;
         }
         // ignore all lines that contain one of the versions
-        if l.contains(vers.0) || l.contains(vers.1) {
+        if vers.iter().any(|v| l.contains(v.0) || l.contains(v.1)) {
             continue;
         }
         // any other lines found => not just a version change
@@ -251,23 +648,250 @@ pub fn infer_version_change(diff: &str) -> Option<(String, String)> {
     None
 }
 
-/// Obfuscate a set of secrets from an input string
-pub fn obfuscate_secrets(input: String, secrets: Vec<String>) -> String {
-    let mut out = input;
-    for s in secrets {
-        // If your secret is less than 8 characters, we won't obfuscate it
-        // Mostly for fear of clashing with other parts of the output,
-        // but also because it's an insecure secret anyway
-        if s.len() >= 8 {
-            out = out.replace(&s, "************");
+/// Infer every image version change in a diff, in order
+///
+/// Removed image versions are paired with added ones, so that a diff changing the image
+/// of a sidecar next to the main container yields both changes.
+pub fn infer_version_changes(diff: &str) -> Vec<(String, String)> {
+    let img_re = Regex::new(r"[^:]+:(?P<version>[a-z0-9\.\-]+)").unwrap();
+    let versions = |sign: char| {
+        diff.lines()
+            .filter(|l| l.starts_with(sign))
+            .filter_map(|l| img_re.captures(l).map(|cap| cap["version"].to_string()))
+            .collect::<Vec<_>>()
+    };
+    versions('-').into_iter().zip(versions('+')).collect()
+}
+
+/// Placeholder for a masked value
+///
+/// The hash prefix shows whether a value changed without revealing it.
+fn masked(value: &str) -> String {
+    let hash = Sha256::digest(value.as_bytes());
+    let hex: String = hash.iter().take(4).map(|b| format!("{:02x}", b)).collect();
+    format!("<masked:{}>", hex)
+}
+
+/// Split a unified diff line into its marker and content
+fn split_diff_line(l: &str) -> Option<(&str, &str)> {
+    if l.starts_with("---") || l.starts_with("+++") {
+        return None;
+    }
+    match l.chars().next() {
+        Some(' ') | Some('-') | Some('+') => Some(l.split_at(1)),
+        _ => None,
+    }
+}
+
+fn indent_of(content: &str) -> usize {
+    content.len() - content.trim_start().len()
+}
+
+/// Annotation kubectl stores a full copy of applied objects in
+const LAST_APPLIED: &str = "kubectl.kubernetes.io/last-applied-configuration";
+
+/// Mask secret values in kubectl diff output
+///
+/// Works on the structure of the diffed objects rather than on known values:
+/// - all `data` and `stringData` values of Secret objects are masked
+/// - env values are masked when their name matches `sensitive`
+/// - ConfigMap `data` and `binaryData` values are masked for `secret_keys`
+/// - the last applied configuration annotation is masked, as it embeds all of these
+///
+/// Multi-line values are masked line by line. Known `secrets` values (along with their
+/// base64 forms) are masked wherever else they show up, like in flow style Secret data.
+pub fn mask_secrets(input: &str, sensitive: &Regex, secret_keys: &[String], secrets: &[String]) -> String {
+    // kubectl diffs in $TMPDIR, which is not /tmp on macOS
+    let kind_line = Regex::new(r"^(?:---|\+\+\+) \S*/(?:LIVE|MERGED)-[a-zA-Z0-9]+/([\w\.]+)").unwrap();
+    let key_value = Regex::new(r"^(\s*(?:- )?[^:\s]+:)\s*(.*)$").unwrap();
+
+    // longest first, so values containing other secrets are masked whole
+    let mut secrets = secrets.iter().filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    let mask_values = |l: &str| {
+        secrets
+            .iter()
+            .fold(l.to_string(), |l, s| l.replace(s.as_str(), &masked(s)))
+    };
+
+    let mut res = vec![];
+    let mut in_secret = false;
+    let mut in_configmap = false;
+    // indent of a key whose nested lines are all masked
+    let mut masking_below: Option<usize> = None;
+    // indent of the `data` or `stringData` block being masked
+    let mut data_indent: Option<usize> = None;
+    let mut env_name: Option<String> = None;
+    for l in input.lines() {
+        if let Some(cap) = kind_line.captures(l) {
+            in_secret = cap[1].contains("Secret");
+            in_configmap = cap[1].contains("ConfigMap");
+            masking_below = None;
+            data_indent = None;
+            env_name = None;
+            res.push(l.to_string());
+            continue;
+        }
+        let (marker, content) = match split_diff_line(l) {
+            Some(split) => split,
+            None => {
+                res.push(mask_values(l));
+                continue;
+            }
+        };
+        if content.trim().is_empty() {
+            res.push(mask_values(l));
+            continue;
+        }
+        let indent = indent_of(content);
+
+        // continuation of a multi-line value
+        if let Some(mi) = masking_below {
+            if indent > mi {
+                let pad = &content[..indent];
+                res.push(format!("{}{}{}", marker, pad, masked(content.trim())));
+                continue;
+            }
+            masking_below = None;
+        }
+        if let Some(di) = data_indent {
+            if indent <= di {
+                data_indent = None;
+            }
+        }
+
+        let kv = key_value.captures(content);
+        let (key, value) = match &kv {
+            Some(cap) => (cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str()),
+            None => {
+                res.push(mask_values(l));
+                continue;
+            }
+        };
+        let name = key.trim().trim_start_matches("- ").trim_end_matches(':');
+
+        if name == "name" {
+            env_name = Some(value.trim_matches('"').to_string());
+        }
+        let mask = if name == LAST_APPLIED {
+            true
+        } else if in_secret {
+            data_indent.is_some()
+        } else if in_configmap {
+            // only direct keys of the data block; nested lines of config files are not keys
+            data_indent.map_or(false, |di| indent <= di + 2) && secret_keys.iter().any(|k| k == name)
+        } else {
+            name == "value" && env_name.iter().any(|n| sensitive.is_match(n))
+        };
+
+        let data_block = if in_secret {
+            name == "data" || name == "stringData"
+        } else {
+            in_configmap && (name == "data" || name == "binaryData")
+        };
+        if data_block && value.is_empty() {
+            data_indent = Some(indent);
+            res.push(mask_values(l));
+        } else if mask && !value.is_empty() {
+            if value.starts_with('|') || value.starts_with('>') {
+                masking_below = Some(indent);
+                res.push(mask_values(l));
+            } else {
+                res.push(format!("{}{} {}", marker, key, masked(value)));
+            }
+        } else {
+            res.push(mask_values(l));
         }
     }
-    out
+    res.join("\n")
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{infer_version_change, is_version_only, minify};
+    use super::{
+        flatten_values, infer_version_change, infer_version_changes, is_version_only, is_versions_only,
+        mask_secrets, masked, minify, region_drift, structured_diff, ChangeKind,
+    };
+    use regex::Regex;
+    use shipcat_definitions::DEFAULT_SENSITIVE_ENV;
+
+    #[test]
+    fn region_drift_test() {
+        let dev = serde_json::json!({
+            "version": "1.2.0",
+            "env": { "LOG_LEVEL": "debug", "NAME": "ask" },
+            "sidecars": [{ "name": "redis" }],
+        });
+        let prod = serde_json::json!({
+            "version": "1.1.0",
+            "env": { "NAME": "ask" },
+            "sidecars": [{ "name": "redis" }],
+        });
+        let flat = flatten_values(&dev);
+        assert_eq!(flat["env.LOG_LEVEL"], "debug");
+        assert_eq!(flat["sidecars[0].name"], "redis");
+
+        let mut values = std::collections::BTreeMap::new();
+        values.insert("dev-uk".to_string(), flat);
+        values.insert("prod-uk".to_string(), flatten_values(&prod));
+        let drift = region_drift(&values);
+        let keys = drift.iter().map(|d| d.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["env.LOG_LEVEL", "version"]);
+        assert_eq!(drift[0].group(), "env");
+        assert_eq!(drift[0].values["prod-uk"], None);
+        assert_eq!(drift[1].values["prod-uk"], Some("1.1.0".to_string()));
+    }
+
+    #[test]
+    fn structured_diff_test() {
+        let sensitive = Regex::new(DEFAULT_SENSITIVE_ENV).unwrap();
+        let before = "kind: Deployment
+metadata:
+  name: fake-ask
+spec:
+  replicas: 2
+  env:
+  - name: DB_PASSWORD
+    value: hunter2
+---
+kind: Secret
+metadata:
+  name: fake-ask
+data:
+  token: c2VjcmV0
+";
+        let after = "kind: Secret
+metadata:
+  name: fake-ask
+data:
+  token: bmV3c2VjcmV0
+---
+kind: Deployment
+metadata:
+  name: fake-ask
+spec:
+  replicas: 3
+  env:
+  - name: DB_PASSWORD
+    value: hunter3
+  minReadySeconds: 10
+";
+        let changes = structured_diff(before, after, &sensitive).unwrap();
+        let paths = changes.iter().map(|c| c.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec![
+            "Deployment/fake-ask:spec.env[0].value",
+            "Deployment/fake-ask:spec.minReadySeconds",
+            "Deployment/fake-ask:spec.replicas",
+            "Secret/fake-ask:data.token",
+        ]);
+        assert_eq!(changes[0].change, ChangeKind::Modified);
+        assert_eq!(changes[0].old, Some(masked("hunter2")));
+        assert_eq!(changes[1].change, ChangeKind::Added);
+        assert_eq!(changes[1].old, None);
+        assert_eq!(changes[2].new, Some("3".to_string()));
+        assert_eq!(changes[3].new, Some(masked("bmV3c2VjcmV0")));
+        assert!(structured_diff(before, before, &sensitive).unwrap().is_empty());
+    }
 
     #[test]
     fn version_change_test() {
@@ -341,6 +965,23 @@ mod tests {
         assert!(is_version_only(input, (&new, &old)));
     }
 
+    #[test]
+    fn version_diff_sidecars() {
+        let input = "webapp, Deployment (apps/v1) has changed:
+-         image: \"quay.io/babylonhealth/webapp:1.0.6\"
++         image: \"quay.io/babylonhealth/webapp:1.0.7\"
+-         image: \"envoyproxy/envoy:v1.14.1\"
++         image: \"envoyproxy/envoy:v1.15.0\"";
+        let res = infer_version_changes(input);
+        assert_eq!(res, vec![
+            ("1.0.6".to_string(), "1.0.7".to_string()),
+            ("v1.14.1".to_string(), "v1.15.0".to_string())
+        ]);
+        assert!(!is_version_only(input, ("1.0.6", "1.0.7")));
+        let versions = [("1.0.6", "1.0.7"), ("v1.14.1", "v1.15.0")];
+        assert!(is_versions_only(input, &versions));
+    }
+
     #[test]
     fn kubectl_diff_minify_test() {
         let input = "--- /tmp/LIVE-A9/apps.v1.Deployment.dev.raftcat   2019-09-11 16:12:26.819641578 +0100
@@ -449,4 +1090,136 @@ autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat has changed:
 +  maxReplicas: 4"
         );
     }
+
+    #[test]
+    fn kubectl_diff_mask_secrets() {
+        let input = r#"--- /tmp/LIVE-547038353/apps.v1.Deployment.dev.raftcat   2020-04-07 12:25:26.255493075 +0100
++++ /tmp/MERGED-191875772/apps.v1.Deployment.dev.raftcat 2020-04-07 12:25:26.312159054 +0100
+@@ -45,7 +45,7 @@
+       - env:
+         - name: DB_PASSWORD
+-          value: hunter2
++          value: hunter3
+         - name: LOG_LEVEL
+-          value: INFO
++          value: DEBUG
+--- /tmp/LIVE-422759316/v1.Secret.dev.raftcat-secrets   2020-04-07 12:26:32.618020569 +0100
++++ /tmp/MERGED-101659107/v1.Secret.dev.raftcat-secrets 2020-04-07 12:26:32.664686669 +0100
+@@ -1,9 +1,10 @@
+ apiVersion: v1
+ data:
+-  SENTRY_DSN: aGVsbG8gd29ybGQK==
++  SENTRY_DSN: YUdWc2JHOGdkMjl5YkdRPQ==
++  WOOT: aGk=
+ kind: Secret
+ metadata:
+   name: raftcat-secrets
+ stringData:
+   cert.pem: |
+-    -----BEGIN CERTIFICATE-----
+-    MIIBszCCAVmgAwIBAgIUZ
++    -----BEGIN CERTIFICATE-----
++    MIIBszCCAVmgAwIBAgIUY
+ type: Opaque"#;
+
+        let sensitive = Regex::new(DEFAULT_SENSITIVE_ENV).unwrap();
+        let out = mask_secrets(input, &sensitive, &[], &[]);
+        for secret in &["hunter", "aGVsbG8", "YUdWc2J", "aGk=", "MIIBszCC"] {
+            assert!(!out.contains(secret), "{} leaked", secret);
+        }
+        // non-sensitive values and structure are untouched
+        assert!(out.contains("+          value: DEBUG"));
+        assert!(out.contains(" kind: Secret"));
+        assert!(out.contains("   name: raftcat-secrets"));
+        assert!(out.contains(" type: Opaque"));
+        // changes are visible through the hashes
+        assert!(out.contains(&format!("-          value: {}", masked("hunter2"))));
+        assert!(out.contains(&format!("+          value: {}", masked("hunter3"))));
+        assert!(out.contains(&format!("+  WOOT: {}", masked("aGk="))));
+        assert!(out.contains(&format!("-    {}", masked("MIIBszCCAVmgAwIBAgIUZ"))));
+        assert_eq!(out.lines().count(), input.lines().count());
+    }
+
+    #[test]
+    fn kubectl_diff_mask_secrets_tmpdir() {
+        let input = r#"--- /var/folders/x7/2r1k_0bd3w9_n4qfzd6l8lyr0000gn/T/LIVE-422759316/v1.Secret.dev.raftcat-secrets   2020-04-07 12:26:32.618020569 +0100
++++ /var/folders/x7/2r1k_0bd3w9_n4qfzd6l8lyr0000gn/T/MERGED-101659107/v1.Secret.dev.raftcat-secrets 2020-04-07 12:26:32.664686669 +0100
+@@ -1,4 +1,4 @@
+ apiVersion: v1
+ data:
+-  SENTRY_DSN: aGVsbG8gd29ybGQK==
++  SENTRY_DSN: YUdWc2JHOGdkMjl5YkdRPQ==
+ kind: Secret"#;
+
+        let sensitive = Regex::new(DEFAULT_SENSITIVE_ENV).unwrap();
+        let out = mask_secrets(input, &sensitive, &[], &[]);
+        assert!(!out.contains("aGVsbG8"));
+        assert!(!out.contains("YUdWc2J"));
+        assert!(out.contains(&format!("+  SENTRY_DSN: {}", masked("YUdWc2JHOGdkMjl5YkdRPQ=="))));
+    }
+
+    #[test]
+    fn kubectl_diff_mask_secret_configs() {
+        let input = r#"--- /tmp/LIVE-547038353/v1.ConfigMap.dev.raftcat-config   2020-04-07 12:25:26.255493075 +0100
++++ /tmp/MERGED-191875772/v1.ConfigMap.dev.raftcat-config 2020-04-07 12:25:26.312159054 +0100
+@@ -1,12 +1,12 @@
+ apiVersion: v1
+ binaryData:
+-  keystore.jks: aHVudGVyMg==
++  keystore.jks: aHVudGVyMw==
+ data:
+   database.yml: |-
+     host: db.dev
+-    password: hunter2
++    password: hunter3
+   logging.yml: |-
+-    level: INFO
++    level: DEBUG
+ kind: ConfigMap"#;
+
+        let sensitive = Regex::new(DEFAULT_SENSITIVE_ENV).unwrap();
+        let keys = ["database.yml".to_string(), "keystore.jks".to_string()];
+        let out = mask_secrets(input, &sensitive, &keys, &[]);
+        for secret in &["hunter", "aHVudGVy", "db.dev"] {
+            assert!(!out.contains(secret), "{} leaked", secret);
+        }
+        assert!(out.contains(&format!("-    {}", masked("password: hunter2"))));
+        assert!(out.contains(&format!("+  keystore.jks: {}", masked("aHVudGVyMw=="))));
+        // config files without secrets are untouched
+        assert!(out.contains("+    level: DEBUG"));
+        assert_eq!(out.lines().count(), input.lines().count());
+
+        // nothing is masked without secret keys
+        assert_eq!(mask_secrets(input, &sensitive, &[], &[]), input);
+    }
+
+    #[test]
+    fn kubectl_diff_mask_secret_values() {
+        let input = r#"--- /tmp/LIVE-422759316/v1.Secret.dev.raftcat-config   2020-04-07 12:26:32.618020569 +0100
++++ /tmp/MERGED-101659107/v1.Secret.dev.raftcat-config 2020-04-07 12:26:32.664686669 +0100
+@@ -1,6 +1,6 @@
+ apiVersion: v1
+-data: {database.yml: cGFzc3dvcmQ6IGh1bnRlcjI=}
++data: {database.yml: cGFzc3dvcmQ6IGh1bnRlcjM=}
+ kind: Secret
+ metadata:
+-  annotations: {rotated: hunter2}
++  annotations: {rotated: hunter3}
+   name: raftcat-config"#;
+
+        let sensitive = Regex::new(DEFAULT_SENSITIVE_ENV).unwrap();
+        let secrets = ["hunter2", "hunter3", "password: hunter2", "password: hunter3"]
+            .iter()
+            .flat_map(|s| vec![s.to_string(), base64::encode(s)])
+            .collect::<Vec<_>>();
+        let out = mask_secrets(input, &sensitive, &[], &secrets);
+        for secret in &["hunter", "cGFzc3dvcmQ6"] {
+            assert!(!out.contains(secret), "{} leaked", secret);
+        }
+        let encoded = base64::encode("password: hunter3");
+        assert!(out.contains(&format!("+data: {{database.yml: {}}}", masked(&encoded))));
+        assert!(out.contains(&format!("-  annotations: {{rotated: {}}}", masked("hunter2"))));
+        assert!(out.contains("   name: raftcat-config"));
+        assert_eq!(out.lines().count(), input.lines().count());
+    }
 }
@@ -0,0 +1,331 @@
+use std::{env, path::Path};
+use tokio::process::Command;
+
+use super::{kubectl, Result};
+use shipcat_definitions::{Config, ConfigState, Region, SecretStoreConfig};
+
+/// Outcome of a single doctor check
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Check passed, with some detail
+    Pass(String),
+    /// Not necessarily a problem, but some commands will not work
+    Warn(String),
+    /// Something is broken
+    Fail(String),
+}
+
+/// A named check with an actionable fix for when it does not pass
+struct Check {
+    name: String,
+    outcome: Outcome,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Pass(detail.into()),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Warn(detail.into()),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Fail(detail.into()),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn print(&self) {
+        let (mark, detail) = match &self.outcome {
+            Outcome::Pass(d) => ("ok", d),
+            Outcome::Warn(d) => ("warn", d),
+            Outcome::Fail(d) => ("FAIL", d),
+        };
+        println!("[{:>4}] {}: {}", mark, self.name, detail);
+        if let Some(f) = &self.fix {
+            println!("       fix: {}", f);
+        }
+    }
+}
+
+/// Check that an executable is on the PATH and report its version
+async fn check_binary(name: &str, version_args: &[&str], required: bool, fix: &str) -> Check {
+    if which::which(name).is_err() {
+        let detail = format!("{} not found in PATH", name);
+        return if required {
+            Check::fail(name, detail, fix)
+        } else {
+            Check::warn(name, detail, fix)
+        };
+    }
+    match Command::new(name).args(version_args).output().await {
+        Ok(o) => {
+            let out = String::from_utf8_lossy(&o.stdout);
+            let version = out.lines().next().unwrap_or("unknown version").trim().to_string();
+            Check::pass(name, version)
+        }
+        Err(e) => Check::fail(name, format!("failed to run: {}", e), fix),
+    }
+}
+
+/// Check that an environment variable is set
+fn check_evar(name: &str, required: bool, fix: &str) -> Check {
+    match env::var(name) {
+        Ok(v) if !v.is_empty() => Check::pass(name, "set"),
+        _ if required => Check::fail(name, "not set", fix),
+        _ => Check::warn(name, "not set", fix),
+    }
+}
+
+async fn check_tools(conf: Option<&Config>, reg: Option<&Region>) -> Vec<Check> {
+    let mut checks = vec![
+        check_binary(
+            "kubectl",
+            &["version", "--client", "--short"],
+            true,
+            "install kubectl from https://kubernetes.io/docs/tasks/tools/install-kubectl/",
+        )
+        .await,
+        check_binary(
+            "helm",
+            &["version", "--short"],
+            true,
+            "install helm from https://github.com/helm/helm/releases",
+        )
+        .await,
+    ];
+    // teleport is only needed for clusters behind it
+    let needs_tsh = match (conf, reg) {
+        (Some(c), Some(r)) => c.find_owning_cluster(r).and_then(|c| c.teleport).is_some(),
+        _ => false,
+    };
+    if needs_tsh {
+        checks.push(
+            check_binary(
+                "tsh",
+                &["version"],
+                true,
+                "install teleport from https://gravitational.com/teleport/download/",
+            )
+            .await,
+        );
+    }
+    checks
+}
+
+fn check_evars() -> Vec<Check> {
+    let mut checks = vec![check_evar(
+        "VAULT_ADDR",
+        false,
+        "export VAULT_ADDR to your vault url (see `shipcat get vault-url`)",
+    )];
+    let home_token = dirs::home_dir()
+        .map(|h| h.join(".vault-token").exists())
+        .unwrap_or(false);
+    if home_token {
+        checks.push(Check::pass("VAULT_TOKEN", "using ~/.vault-token"));
+    } else {
+        checks.push(check_evar(
+            "VAULT_TOKEN",
+            false,
+            "run `vault login -method=github token=$GITHUB_PAT` or export VAULT_TOKEN",
+        ));
+    }
+    checks.push(check_evar(
+        "SLACK_SHIPCAT_HOOK_URL",
+        false,
+        "export SLACK_SHIPCAT_HOOK_URL to be able to `shipcat apply`",
+    ));
+    checks.push(check_evar(
+        "SLACK_SHIPCAT_CHANNEL",
+        false,
+        "export SLACK_SHIPCAT_CHANNEL to be able to `shipcat apply`",
+    ));
+    checks
+}
+
+fn check_manifests_dir() -> Check {
+    let name = "manifests";
+    if let Ok(d) = env::var("SHIPCAT_MANIFEST_DIR") {
+        // shipcat::init has already moved here
+        return Check::pass(name, format!("using SHIPCAT_MANIFEST_DIR={}", d));
+    }
+    let fix = "cd into your manifests repo, or export SHIPCAT_MANIFEST_DIR to point to it";
+    if !Path::new("shipcat.conf").is_file() {
+        Check::fail(name, "no shipcat.conf in the current directory", fix)
+    } else if !Path::new("services").is_dir() {
+        Check::fail(name, "no services directory next to shipcat.conf", fix)
+    } else {
+        Check::pass(name, "using the current directory")
+    }
+}
+
+fn check_config(conf: &Config) -> Check {
+    match conf.verify() {
+        Ok(_) => Check::pass("shipcat.conf", "valid"),
+        Err(e) => Check::fail(
+            "shipcat.conf",
+            e.to_string(),
+            "run `shipcat config verify` and fix the reported problem",
+        ),
+    }
+}
+
+fn check_version_pin(conf: &Config, reg: &Region) -> Check {
+    let name = "version pin";
+    match conf.get_appropriate_version_pin(&reg.environment) {
+        Ok(pin) => match Config::bail_on_version_older_than(&pin) {
+            Ok(_) => Check::pass(
+                name,
+                format!("{} satisfies pin {}", env!("CARGO_PKG_VERSION"), pin),
+            ),
+            Err(_) => Check::fail(
+                name,
+                format!("{} is older than pin {}", env!("CARGO_PKG_VERSION"), pin),
+                "run `shipcat self-upgrade`",
+            ),
+        },
+        Err(e) => Check::warn(name, e.to_string(), "pin shipcat versions in shipcat.conf"),
+    }
+}
+
+async fn check_secrets(reg: &Region) -> Check {
+    let name = "secrets";
+    let fix = match reg.secretStore {
+        Some(SecretStoreConfig::AwsSecretsManager { .. }) => {
+            "set AWS credentials for the region and check your secretsmanager permissions"
+        }
+        Some(SecretStoreConfig::Sops { .. }) => {
+            "install sops and check you have the keys to decrypt the region's secret files"
+        }
+        _ => "log in to vault with `vault login -method=github` and check your vault policies",
+    };
+    let client = match reg.secret_store() {
+        Ok(c) => c,
+        Err(e) => return Check::fail(name, e.to_string(), fix),
+    };
+    match client.list(&reg.vault.folder).await {
+        Ok(_) => Check::pass(name, format!("can list secrets under {}", reg.vault.folder)),
+        Err(e) => Check::fail(name, format!("cannot list {} ({})", reg.vault.folder, e), fix),
+    }
+}
+
+async fn check_kube_permissions(reg: &Region) -> Check {
+    let name = "kube permissions";
+    let fix = format!(
+        "run `shipcat login -r {}` or ask for access to {}",
+        reg.name, reg.namespace
+    );
+    let group = Some("babylontech.co.uk");
+    match kubectl::can_i(&reg.namespace, "list", "shipcatmanifests", group).await {
+        Ok(true) => Check::pass(name, format!("can list shipcatmanifests in {}", reg.namespace)),
+        Ok(false) => Check::fail(
+            name,
+            format!("cannot list shipcatmanifests in {}", reg.namespace),
+            fix,
+        ),
+        Err(e) => Check::fail(name, e.to_string(), fix),
+    }
+}
+
+/// Check the environment shipcat needs, and print fixes for anything missing
+///
+/// Resolves the region from the kube context if no region is passed.
+pub async fn run(region: Option<&str>) -> Result<()> {
+    let mut checks = vec![check_manifests_dir()];
+    checks.extend(check_evars());
+
+    let conf = Config::read().await.ok();
+    if let Some(c) = &conf {
+        checks.push(check_config(c));
+    }
+
+    let context = match region {
+        Some(r) => Some(r.to_string()),
+        None => match kubectl::current_context().await {
+            Ok(ctx) if !ctx.is_empty() => {
+                checks.push(Check::pass("kube context", ctx.clone()));
+                Some(ctx)
+            }
+            _ => {
+                checks.push(Check::fail(
+                    "kube context",
+                    "no current kube context",
+                    "run `shipcat login -r <region>` or pass a region with `-r`",
+                ));
+                None
+            }
+        },
+    };
+
+    let resolved = match (&conf, context) {
+        (Some(_), Some(ctx)) => match Config::new(ConfigState::Base, &ctx).await {
+            Ok((c, r)) => {
+                checks.push(Check::pass("region", format!("{} in {}", r.name, r.cluster)));
+                Some((c, r))
+            }
+            Err(e) => {
+                checks.push(Check::fail(
+                    "region",
+                    e.to_string(),
+                    "switch to a kube context defined in shipcat.conf, or pass a region with `-r`",
+                ));
+                None
+            }
+        },
+        _ => None,
+    };
+
+    match &resolved {
+        Some((c, r)) => {
+            checks.extend(check_tools(Some(c), Some(r)).await);
+            checks.push(check_version_pin(c, r));
+            checks.push(check_secrets(r).await);
+            checks.push(check_kube_permissions(r).await);
+        }
+        None => checks.extend(check_tools(None, None).await),
+    }
+
+    for c in &checks {
+        c.print();
+    }
+    let failures = checks
+        .iter()
+        .filter(|c| matches!(c.outcome, Outcome::Fail(_)))
+        .count();
+    if failures > 0 {
+        bail!("{} doctor checks failed", failures);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_binary, check_evar, Outcome};
+
+    #[tokio::test]
+    async fn doctor_checks() {
+        let sh = check_binary("sh", &["-c", "echo fake 1.0"], true, "install sh").await;
+        assert_eq!(sh.outcome, Outcome::Pass("fake 1.0".into()));
+        let missing = check_binary("shipcat-fake-binary", &[], true, "install it").await;
+        assert!(matches!(missing.outcome, Outcome::Fail(_)));
+        assert_eq!(missing.fix, Some("install it".into()));
+        let optional = check_binary("shipcat-fake-binary", &[], false, "install it").await;
+        assert!(matches!(optional.outcome, Outcome::Warn(_)));
+
+        let evar = check_evar("SHIPCAT_FAKE_DOCTOR_EVAR", false, "export it");
+        assert!(matches!(evar.outcome, Outcome::Warn(_)));
+    }
+}
@@ -0,0 +1,192 @@
+use regex::Regex;
+use std::ops::Range;
+
+/// A `key: value` line in a yaml file
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLine {
+    /// Indentation of the line, including any list item dash
+    pub indent: usize,
+    /// Whether the key starts a list item (`- key: value`)
+    pub item: bool,
+    pub key: String,
+    /// Everything after the colon, trimmed
+    pub value: String,
+}
+
+/// Line based editor of yaml files
+///
+/// Edits single lines in place, so comments, ordering and quoting
+/// of everything that is not touched is left as it was.
+/// It only understands block style yaml; flow style collections are left alone.
+pub struct YamlEditor {
+    lines: Vec<String>,
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn is_content(line: &str) -> bool {
+    let t = line.trim();
+    !t.is_empty() && !t.starts_with('#')
+}
+
+impl YamlEditor {
+    pub fn new(contents: &str) -> Self {
+        YamlEditor {
+            lines: contents.lines().map(String::from).collect(),
+        }
+    }
+
+    /// The edited file
+    pub fn contents(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn line(&self, i: usize) -> &str {
+        &self.lines[i]
+    }
+
+    pub fn set_line(&mut self, i: usize, line: String) {
+        self.lines[i] = line;
+    }
+
+    pub fn insert_line(&mut self, i: usize, line: String) {
+        self.lines.insert(i, line);
+    }
+
+    /// Parse line `i` as a `key: value` line
+    pub fn key_line(&self, i: usize) -> Option<KeyLine> {
+        let re = Regex::new(r#"^(\s*)(- )?([A-Za-z0-9_\-\./]+|"[^"]*"|'[^']*'):(?:\s+(.*))?$"#).unwrap();
+        let cap = re.captures(&self.lines[i])?;
+        let item = cap.get(2).is_some();
+        let indent = cap[1].len() + if item { 2 } else { 0 };
+        Some(KeyLine {
+            indent,
+            item,
+            key: cap[3].trim_matches(|c| c == '"' || c == '\'').to_string(),
+            value: cap.get(4).map_or("", |m| m.as_str()).trim().to_string(),
+        })
+    }
+
+    /// Line index of a top level key
+    pub fn find_top_level(&self, key: &str) -> Option<usize> {
+        (0..self.lines.len())
+            .find(|&i| indent_of(&self.lines[i]) == 0 && self.key_line(i).iter().any(|kl| kl.key == key))
+    }
+
+    /// Lines nested below the key on line `i`
+    ///
+    /// Trailing blank lines and comments are not part of the block.
+    pub fn block(&self, i: usize) -> Range<usize> {
+        let parent = match self.key_line(i) {
+            Some(kl) if kl.item => kl.indent - 2,
+            Some(kl) => kl.indent,
+            None => indent_of(&self.lines[i]),
+        };
+        let mut end = i + 1;
+        for j in i + 1..self.lines.len() {
+            let l = &self.lines[j];
+            if !is_content(l) {
+                continue;
+            }
+            // list items may sit at the same indent as their parent key
+            let nested =
+                indent_of(l) > parent || (indent_of(l) == parent && l.trim_start().starts_with("- "));
+            if !nested {
+                break;
+            }
+            end = j + 1;
+        }
+        i + 1..end
+    }
+
+    /// Indent of the direct children in a block
+    pub fn child_indent(&self, block: &Range<usize>) -> Option<usize> {
+        block
+            .clone()
+            .find(|&j| is_content(&self.lines[j]))
+            .map(|j| indent_of(&self.lines[j]))
+    }
+
+    /// Replace the key of a `key: value` line, keeping the rest of the line
+    pub fn rename_key(&mut self, i: usize, new: &str) {
+        if self.key_line(i).is_some() {
+            let line = &self.lines[i];
+            let start = line.find(|c: char| c != ' ' && c != '-').unwrap_or(0);
+            let colon = start + line[start..].find(':').unwrap_or(0);
+            self.lines[i] = format!("{}{}{}", &line[..start], new, &line[colon..]);
+        }
+    }
+
+    /// Replace the value of a `key: value` line, dropping any trailing comment
+    pub fn set_value(&mut self, i: usize, value: &str) {
+        if self.key_line(i).is_some() {
+            let line = &self.lines[i];
+            let start = line.find(|c: char| c != ' ' && c != '-').unwrap_or(0);
+            let colon = start + line[start..].find(':').unwrap_or(0);
+            self.lines[i] = format!("{}: {}", &line[..colon], value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::YamlEditor;
+
+    const DOC: &str = "name: svc # the name
+regions:
+- dev-uk
+# staging
+- dev-global
+
+env:
+  lower: 1
+  UPPER: \"2\"
+sidecars:
+- name: redis
+  env:
+    a: b
+";
+
+    #[test]
+    fn blocks_and_keys() {
+        let ed = YamlEditor::new(DOC);
+        assert_eq!(ed.contents(), DOC);
+        let regions = ed.find_top_level("regions").unwrap();
+        assert_eq!(ed.block(regions), 2..5);
+        let env = ed.find_top_level("env").unwrap();
+        assert_eq!(ed.block(env), 7..9);
+        assert_eq!(ed.child_indent(&ed.block(env)), Some(2));
+
+        let kl = ed.key_line(10).unwrap();
+        assert!(kl.item);
+        assert_eq!(
+            (kl.indent, kl.key.as_str(), kl.value.as_str()),
+            (2, "name", "redis")
+        );
+        assert_eq!(ed.block(10), 11..13);
+        assert_eq!(ed.key_line(0).unwrap().value, "svc # the name");
+    }
+
+    #[test]
+    fn edits_keep_comments() {
+        let mut ed = YamlEditor::new(DOC);
+        ed.rename_key(7, "LOWER");
+        ed.set_value(10, "cache");
+        ed.rename_key(0, "title");
+        let out = ed.contents();
+        assert!(out.contains("  LOWER: 1\n"));
+        assert!(out.contains("- name: cache\n"));
+        assert!(out.contains("title: svc # the name\n"));
+        assert!(out.contains("# staging\n"));
+    }
+}
@@ -0,0 +1,279 @@
+use serde_json::{json, Value};
+use std::net::IpAddr;
+
+use super::{connectivity, Manifest, Region, Result};
+use shipcat_definitions::region::{EgressConfig, EgressPolicyKind};
+
+/// Where an infra dependency lives, as far as it could be resolved
+struct InfraDestination {
+    /// Address of the dependency, when its host is an ip
+    ip: Option<IpAddr>,
+    /// Hostname of the dependency, when its host is not an ip
+    host: Option<String>,
+    port: u16,
+}
+
+/// Destinations of the infra dependencies of a service
+///
+/// Hosts in unresolved secrets (like in stubbed manifests) only restrict the port.
+fn infra_destinations(mf: &Manifest, reg: &Region) -> Vec<InfraDestination> {
+    mf.infraDependencies
+        .iter()
+        .map(|d| match connectivity::target(d, mf, reg) {
+            Ok(t) => InfraDestination {
+                ip: t.host.parse().ok(),
+                host: Some(t.host).filter(|h| h.parse::<IpAddr>().is_err()),
+                port: t.port,
+            },
+            Err(e) => {
+                debug!("Allowing {} on any host for {}: {}", d.name(), mf.name, e);
+                InfraDestination {
+                    ip: None,
+                    host: None,
+                    port: d.default_port(),
+                }
+            }
+        })
+        .collect()
+}
+
+fn cidr(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(_) => format!("{}/32", ip),
+        IpAddr::V6(_) => format!("{}/128", ip),
+    }
+}
+
+/// Values of the `app` label on the pods of a service
+pub(crate) fn app_labels(mf: &Manifest) -> Vec<String> {
+    let mut apps = vec![mf.name.clone(), format!("{}-canary", mf.name)];
+    for w in &mf.workers {
+        apps.push(format!("{}-{}", mf.name, w.container.name));
+    }
+    apps
+}
+
+/// Metadata for a policy owned by the service's ShipcatManifest
+pub(crate) fn metadata(mf: &Manifest, name: &str) -> Value {
+    let mut labels = json!({
+        "app.kubernetes.io/name": mf.name,
+        "app.kubernetes.io/managed-by": "shipcat",
+    });
+    if let Some(v) = &mf.version {
+        labels["app.kubernetes.io/version"] = json!(v);
+    }
+    json!({
+        "name": name,
+        "namespace": mf.namespace,
+        "labels": labels,
+        "ownerReferences": [{
+            "apiVersion": "babylontech.co.uk/v1",
+            "kind": "ShipcatManifest",
+            "name": mf.name,
+            "uid": mf.uid.clone().unwrap_or_default(),
+            "controller": false,
+        }],
+    })
+}
+
+fn network_policy(mf: &Manifest, cfg: &EgressConfig, infra: &[InfraDestination]) -> Value {
+    let dns = json!({
+        "to": [{ "namespaceSelector": {}, "podSelector": { "matchLabels": { "k8s-app": "kube-dns" } } }],
+        "ports": [{ "protocol": "UDP", "port": 53 }, { "protocol": "TCP", "port": 53 }],
+    });
+    let mut egress = vec![dns];
+    if !mf.dependencies.is_empty() {
+        let to = mf
+            .dependencies
+            .iter()
+            .map(|d| json!({ "podSelector": { "matchLabels": { "app": d.name } } }))
+            .collect::<Vec<_>>();
+        egress.push(json!({ "to": to }));
+    }
+    for d in infra {
+        let mut rule = json!({ "ports": [{ "protocol": "TCP", "port": d.port }] });
+        if let Some(ip) = &d.ip {
+            rule["to"] = json!([{ "ipBlock": { "cidr": cidr(ip) } }]);
+        }
+        egress.push(rule);
+    }
+    for e in &cfg.allowed {
+        if let Some(c) = &e.cidr {
+            let mut rule = json!({ "to": [{ "ipBlock": { "cidr": c } }] });
+            if !e.ports.is_empty() {
+                let ports = e.ports.iter().map(|p| json!({ "protocol": "TCP", "port": p }));
+                rule["ports"] = json!(ports.collect::<Vec<_>>());
+            }
+            egress.push(rule);
+        }
+    }
+    json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": metadata(mf, &format!("{}-egress", mf.name)),
+        "spec": {
+            "podSelector": {
+                "matchExpressions": [{ "key": "app", "operator": "In", "values": app_labels(mf) }],
+            },
+            "policyTypes": ["Egress"],
+            "egress": egress,
+        },
+    })
+}
+
+fn cilium_ports(ports: &[u16]) -> Value {
+    let ports = ports
+        .iter()
+        .map(|p| json!({ "port": p.to_string(), "protocol": "TCP" }))
+        .collect::<Vec<_>>();
+    json!([{ "ports": ports }])
+}
+
+fn cilium_policy(mf: &Manifest, cfg: &EgressConfig, infra: &[InfraDestination]) -> Value {
+    // dns goes through the dns proxy so that toFQDNs rules can match
+    let dns = json!({
+        "toEndpoints": [{ "matchLabels": {
+            "k8s:io.kubernetes.pod.namespace": "kube-system",
+            "k8s-app": "kube-dns",
+        } }],
+        "toPorts": [{
+            "ports": [{ "port": "53", "protocol": "ANY" }],
+            "rules": { "dns": [{ "matchPattern": "*" }] },
+        }],
+    });
+    let mut egress = vec![dns];
+    if !mf.dependencies.is_empty() {
+        let to = mf
+            .dependencies
+            .iter()
+            .map(|d| json!({ "matchLabels": { "app": d.name } }))
+            .collect::<Vec<_>>();
+        egress.push(json!({ "toEndpoints": to }));
+    }
+    for d in infra {
+        let mut rule = json!({ "toPorts": cilium_ports(&[d.port]) });
+        if let Some(ip) = &d.ip {
+            rule["toCIDR"] = json!([cidr(ip)]);
+        } else if let Some(h) = &d.host {
+            rule["toFQDNs"] = json!([{ "matchName": h }]);
+        }
+        egress.push(rule);
+    }
+    for e in &cfg.allowed {
+        let mut rule = match (&e.cidr, &e.host) {
+            (Some(c), _) => json!({ "toCIDR": [c] }),
+            (None, Some(h)) => json!({ "toFQDNs": [{ "matchName": h }] }),
+            (None, None) => continue,
+        };
+        if !e.ports.is_empty() {
+            rule["toPorts"] = cilium_ports(&e.ports);
+        }
+        egress.push(rule);
+    }
+    json!({
+        "apiVersion": "cilium.io/v2",
+        "kind": "CiliumNetworkPolicy",
+        "metadata": metadata(mf, &format!("{}-egress", mf.name)),
+        "spec": {
+            "endpointSelector": {
+                "matchExpressions": [{ "key": "app", "operator": "In", "values": app_labels(mf) }],
+            },
+            "egress": egress,
+        },
+    })
+}
+
+/// Render the egress policy of a service with `strictEgress`
+///
+/// Allows DNS, the service's dependencies and infra dependencies, and the region's
+/// `egress.allowed` endpoints. Returns `None` for services with open egress.
+pub fn render(mf: &Manifest, reg: &Region) -> Result<Option<String>> {
+    if !mf.strictEgress {
+        return Ok(None);
+    }
+    let cfg = reg.egress.clone().unwrap_or_default();
+    let infra = infra_destinations(mf, reg);
+    let policy = match cfg.policy {
+        EgressPolicyKind::NetworkPolicy => network_policy(mf, &cfg, &infra),
+        EgressPolicyKind::Cilium => cilium_policy(mf, &cfg, &infra),
+    };
+    Ok(Some(serde_yaml::to_string(&policy)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::{Manifest, Region};
+
+    fn manifest() -> Manifest {
+        let mut mf = Manifest::test("fake-ask");
+        mf.strictEgress = true;
+        mf.dependencies = vec![serde_yaml::from_str("name: fake-storage").unwrap()];
+        mf.infraDependencies = vec![
+            serde_yaml::from_str("{kind: postgres, env: DATABASE_URL}").unwrap(),
+            serde_yaml::from_str("{kind: redis, env: REDIS_HOST}").unwrap(),
+        ];
+        mf.env
+            .plain
+            .insert("DATABASE_URL".into(), "postgres://10.1.2.3:5433/db".into());
+        mf.env.plain.insert("REDIS_HOST".into(), "redis.internal".into());
+        mf
+    }
+
+    fn region(egress: &str) -> Region {
+        let yaml = format!(
+            "name: dev-uk
+namespace: apps
+environment: dev
+cluster: kops-uk
+versioningScheme: Semver
+vault: {{url: http://localhost:8200, folder: dev}}
+egress: {}",
+            egress
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn egress_network_policy() {
+        let reg = region("{allowed: [{name: vpc, cidr: 10.0.0.0/8, ports: [443]}]}");
+        let out = render(&manifest(), &reg).unwrap().unwrap();
+        let policy: serde_yaml::Value = serde_yaml::from_str(&out).unwrap();
+        assert_eq!(policy["kind"].as_str(), Some("NetworkPolicy"));
+        assert_eq!(policy["metadata"]["name"].as_str(), Some("fake-ask-egress"));
+        let egress = policy["spec"]["egress"].as_sequence().unwrap();
+        assert_eq!(egress.len(), 5); // dns, dependencies, postgres, redis, vpc
+        assert_eq!(
+            egress[1]["to"][0]["podSelector"]["matchLabels"]["app"].as_str(),
+            Some("fake-storage")
+        );
+        assert_eq!(
+            egress[2]["to"][0]["ipBlock"]["cidr"].as_str(),
+            Some("10.1.2.3/32")
+        );
+        assert_eq!(egress[2]["ports"][0]["port"].as_u64(), Some(5433));
+        // hostnames can not be matched by a NetworkPolicy, so only the port is restricted
+        assert!(egress[3]["to"].is_null());
+        assert_eq!(egress[3]["ports"][0]["port"].as_u64(), Some(6379));
+        assert_eq!(egress[4]["ports"][0]["port"].as_u64(), Some(443));
+
+        let mut open = manifest();
+        open.strictEgress = false;
+        assert!(render(&open, &reg).unwrap().is_none());
+    }
+
+    #[test]
+    fn egress_cilium_policy() {
+        let reg = region("{policy: cilium}");
+        let out = render(&manifest(), &reg).unwrap().unwrap();
+        let policy: serde_yaml::Value = serde_yaml::from_str(&out).unwrap();
+        assert_eq!(policy["kind"].as_str(), Some("CiliumNetworkPolicy"));
+        let egress = policy["spec"]["egress"].as_sequence().unwrap();
+        assert_eq!(egress[2]["toCIDR"][0].as_str(), Some("10.1.2.3/32"));
+        assert_eq!(
+            egress[3]["toFQDNs"][0]["matchName"].as_str(),
+            Some("redis.internal")
+        );
+        assert_eq!(egress[3]["toPorts"][0]["ports"][0]["port"].as_str(), Some("6379"));
+    }
+}
@@ -1,4 +1,17 @@
-use super::{Config, Region, Result};
+use regex::Regex;
+use shipcat_definitions::secretstore;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::fs;
+
+use super::{Config, Error, Manifest, Region, Result};
+use crate::{
+    edit::{KeyLine, YamlEditor},
+    validate::Fix,
+};
 
 /// Print exports to source from a shell
 pub async fn print_bash(svc: &str, conf: &Config, reg: &Region, mock: bool) -> Result<()> {
@@ -23,3 +36,261 @@ pub async fn print_bash(svc: &str, conf: &Config, reg: &Region, mock: bool) -> R
     }
     Ok(())
 }
+
+/// A sed style `s/old/new/` substitution
+///
+/// Any character after the `s` can be the delimiter. `new` can refer to groups like `$1`.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl FromStr for Substitution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let delim = match (chars.next(), chars.next()) {
+            (Some('s'), Some(d)) => d,
+            _ => bail!("Expected a substitution like s/old/new/, got '{}'", s),
+        };
+        let parts = chars.as_str().split(delim).collect::<Vec<_>>();
+        match parts.as_slice() {
+            [old, new, ""] if !old.is_empty() => match Regex::new(old) {
+                Ok(pattern) => Ok(Substitution {
+                    pattern,
+                    replacement: new.to_string(),
+                }),
+                Err(e) => bail!("Invalid pattern in '{}': {}", s, e),
+            },
+            _ => bail!("Expected a substitution like s/old/new/, got '{}'", s),
+        }
+    }
+}
+
+impl Substitution {
+    fn apply(&self, s: &str) -> String {
+        self.pattern.replace_all(s, self.replacement.as_str()).to_string()
+    }
+}
+
+/// An env var matching `shipcat env grep`
+#[derive(Debug, Clone)]
+pub struct EnvMatch {
+    pub service: String,
+    pub key: String,
+    pub value: String,
+    /// Regions where the service sets the var to this value
+    pub regions: Vec<String>,
+    /// Service files and zero indexed lines setting the var
+    pub locations: Vec<(PathBuf, usize)>,
+}
+
+/// Env vars of a manifest and its workers and sidecars, whose key or value matches
+fn matching_env(mf: &Manifest, re: &Regex) -> Vec<(String, String)> {
+    let containers = mf.workers.iter().map(|w| &w.container).chain(mf.sidecars.iter());
+    std::iter::once(&mf.env)
+        .chain(containers.map(|c| &c.env))
+        .flat_map(|e| e.plain.iter())
+        .filter(|(k, v)| re.is_match(k) || re.is_match(v))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Lines of the direct entries of every `env` block in a yaml file
+fn env_lines(ed: &YamlEditor) -> Vec<(usize, KeyLine)> {
+    let mut res = vec![];
+    for i in 0..ed.len() {
+        match ed.key_line(i) {
+            Some(kl) if kl.key == "env" && kl.value.is_empty() => {}
+            _ => continue,
+        }
+        let block = ed.block(i);
+        if let Some(child) = ed.child_indent(&block) {
+            res.extend(
+                block
+                    .filter_map(|j| ed.key_line(j).map(|kl| (j, kl)))
+                    .filter(|(_, kl)| kl.indent == child && !kl.item),
+            );
+        }
+    }
+    res
+}
+
+/// Find the lines setting an env var in the files of a service for a region
+///
+/// Files are searched in reverse merge order, so the line that wins comes first.
+/// Workers and sidecars setting the same var each have their own line.
+async fn locate(svc: &str, reg: &Region, key: &str) -> Vec<(PathBuf, usize)> {
+    let dir = Path::new(".").join("services").join(svc);
+    let files = vec![
+        format!("{}.yml", reg.name),
+        "overrides.yml.j2".to_string(),
+        format!("{}.yml", reg.environment.to_string()),
+        "manifest.yml".to_string(),
+    ];
+    let mut res = vec![];
+    for f in files {
+        let pth = dir.join(f);
+        let source = match fs::read_to_string(&pth).await {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let ed = YamlEditor::new(&source);
+        for (i, _) in env_lines(&ed).into_iter().filter(|(_, kl)| kl.key == key) {
+            res.push((pth.clone(), i));
+        }
+    }
+    res
+}
+
+/// Rewrite the env lines of a manifest file with a substitution
+///
+/// Keys and values are rewritten in place, so comments and formatting are kept.
+pub fn rewrite_source(source: &str, lines: &BTreeSet<usize>, sub: &Substitution) -> (String, Vec<Fix>) {
+    let mut ed = YamlEditor::new(source);
+    let mut fixes = vec![];
+    for (i, kl) in env_lines(&ed) {
+        if !lines.contains(&i) {
+            continue;
+        }
+        let key = sub.apply(&kl.key);
+        let value = sub.apply(&kl.value);
+        if key == kl.key && value == kl.value {
+            continue;
+        }
+        if key != kl.key && secretstore::is_placeholder(&kl.value) {
+            warn!(
+                "Renamed secret {} must also be moved to {} in the secret store",
+                kl.key, key
+            );
+        }
+        ed.rename_key(i, &key);
+        if value != kl.value {
+            ed.set_value(i, &value);
+        }
+        fixes.push(Fix {
+            line: i,
+            description: format!("{}: {} -> {}: {}", kl.key, kl.value, key, value),
+        });
+    }
+    (ed.contents(), fixes)
+}
+
+/// Search the env vars of services across regions
+///
+/// Matches the pattern against the keys and unresolved values (templates, `IN_VAULT`)
+/// of the merged manifests, and prints where each match is set.
+/// With a substitution, the matching lines are rewritten in the service files.
+pub async fn grep(
+    pattern: &str,
+    conf: &Config,
+    regions: &[String],
+    rewrite: Option<&Substitution>,
+) -> Result<Vec<EnvMatch>> {
+    let re = match Regex::new(pattern) {
+        Ok(re) => re,
+        Err(e) => bail!("Invalid pattern '{}': {}", pattern, e),
+    };
+    let mut found: BTreeMap<(String, String, String), EnvMatch> = BTreeMap::new();
+    let regions = conf
+        .get_regions()
+        .into_iter()
+        .filter(|r| regions.contains(&r.name))
+        .collect::<Vec<_>>();
+    for base in shipcat_filebacked::all(conf).await? {
+        for reg in regions.iter().filter(|r| base.regions.contains(&r.name)) {
+            let mf = shipcat_filebacked::load_manifest(&base.name, conf, reg).await?;
+            for (key, value) in matching_env(&mf, &re) {
+                let locations = locate(&mf.name, reg, &key).await;
+                let entry = found
+                    .entry((mf.name.clone(), key.clone(), value.clone()))
+                    .or_insert_with(|| EnvMatch {
+                        service: mf.name.clone(),
+                        key,
+                        value,
+                        regions: vec![],
+                        locations: vec![],
+                    });
+                if !entry.regions.contains(&reg.name) {
+                    entry.regions.push(reg.name.clone());
+                }
+                for l in locations {
+                    if !entry.locations.contains(&l) {
+                        entry.locations.push(l);
+                    }
+                }
+            }
+        }
+    }
+    let matches = found.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+    for m in &matches {
+        let regions = m.regions.join(", ");
+        if m.locations.is_empty() {
+            println!("{} (defaults): {}={} ({})", m.service, m.key, m.value, regions);
+        }
+        for (pth, i) in &m.locations {
+            println!("{}:{}: {}={} ({})", pth.display(), i + 1, m.key, m.value, regions);
+        }
+    }
+
+    if let Some(sub) = rewrite {
+        let mut files: BTreeMap<PathBuf, BTreeSet<usize>> = BTreeMap::new();
+        for (pth, i) in matches.iter().flat_map(|m| m.locations.clone()) {
+            files.entry(pth).or_default().insert(i);
+        }
+        for (pth, lines) in files {
+            let source = fs::read_to_string(&pth).await?;
+            let (rewritten, fixes) = rewrite_source(&source, &lines, sub);
+            if fixes.is_empty() {
+                continue;
+            }
+            fs::write(&pth, rewritten).await?;
+            for f in &fixes {
+                println!("rewrote {}:{}: {}", pth.display(), f.line + 1, f.description);
+            }
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_source, Substitution};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn env_substitution_rewrite() {
+        assert!("s/old/new".parse::<Substitution>().is_err());
+        assert!("x/old/new/".parse::<Substitution>().is_err());
+        assert!("s/(/new/".parse::<Substitution>().is_err());
+
+        let source = "name: fake-ask
+env:
+  # where auth lives
+  AUTH_URL: http://auth-v1 # old auth
+  AUTH_SECRET: IN_VAULT
+workers:
+- name: fake-ask-worker
+  env:
+    AUTH_URL: http://auth-v1
+";
+        let sub: Substitution = "s|auth-v1|auth-v2|".parse().unwrap();
+        let lines = vec![3, 8].into_iter().collect::<BTreeSet<_>>();
+        let (out, fixes) = rewrite_source(source, &lines, &sub);
+        assert_eq!(fixes.len(), 2);
+        assert!(out.contains("  # where auth lives\n  AUTH_URL: http://auth-v2 # old auth\n"));
+        assert!(out.contains("    AUTH_URL: http://auth-v2\n"));
+
+        // only the given lines are touched
+        let sub: Substitution = "s/^AUTH_/LOGIN_/".parse().unwrap();
+        let lines = vec![4].into_iter().collect::<BTreeSet<_>>();
+        let (out, fixes) = rewrite_source(source, &lines, &sub);
+        assert_eq!(
+            fixes[0].description,
+            "AUTH_SECRET: IN_VAULT -> LOGIN_SECRET: IN_VAULT"
+        );
+        assert!(out.contains("  AUTH_URL: http://auth-v1 # old auth\n  LOGIN_SECRET: IN_VAULT\n"));
+    }
+}
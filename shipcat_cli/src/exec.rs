@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::process::Command;
+
+use super::Result;
+
+/// Captured result of running an external command
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Output {
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    pub success: bool,
+    /// Exit code (if not killed by a signal)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+}
+
+/// Runner of external executables (helm, kubectl, tsh)
+///
+/// Commands go through `executor()` so that tests can swap in a `Replay`.
+#[async_trait]
+pub trait Executor: Send + Sync {
+    /// Whether the program can be run at all
+    fn available(&self, program: &str) -> bool;
+    /// Run a program and capture its output
+    async fn output(&self, program: &str, args: &[String]) -> Result<Output>;
+    /// Run a program with inherited stdio
+    ///
+    /// Output captured on the returned value is empty.
+    async fn status(&self, program: &str, args: &[String]) -> Result<Output>;
+}
+
+/// Executor that runs programs on the host
+pub struct System;
+
+#[async_trait]
+impl Executor for System {
+    fn available(&self, program: &str) -> bool {
+        which::which(program).is_ok()
+    }
+
+    async fn output(&self, program: &str, args: &[String]) -> Result<Output> {
+        let s = Command::new(program).args(args).output().await?;
+        Ok(Output {
+            stdout: String::from_utf8_lossy(&s.stdout).into(),
+            stderr: String::from_utf8_lossy(&s.stderr).into(),
+            success: s.status.success(),
+            code: s.status.code(),
+        })
+    }
+
+    async fn status(&self, program: &str, args: &[String]) -> Result<Output> {
+        let s = Command::new(program).args(args).status().await?;
+        Ok(Output {
+            success: s.success(),
+            code: s.code(),
+            ..Output::default()
+        })
+    }
+}
+
+/// A command and its result, as stored in recordings
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    pub program: String,
+    pub args: Vec<String>,
+    #[serde(flatten)]
+    pub output: Output,
+}
+
+/// Executor that runs programs on the host and records every call
+///
+/// Recordings can be saved and later replayed hermetically with `Replay`.
+#[derive(Default)]
+pub struct Recorder {
+    records: Mutex<Vec<Record>>,
+}
+
+impl Recorder {
+    fn record(&self, program: &str, args: &[String], output: &Output) {
+        self.records.lock().unwrap().push(Record {
+            program: program.into(),
+            args: args.to_vec(),
+            output: output.clone(),
+        });
+    }
+
+    /// Calls made so far
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Write the calls made so far as a yaml recording
+    pub fn save(&self, pth: &Path) -> Result<()> {
+        std::fs::write(pth, serde_yaml::to_string(&self.records())?)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Executor for Recorder {
+    fn available(&self, program: &str) -> bool {
+        System.available(program)
+    }
+
+    async fn output(&self, program: &str, args: &[String]) -> Result<Output> {
+        let out = System.output(program, args).await?;
+        self.record(program, args, &out);
+        Ok(out)
+    }
+
+    async fn status(&self, program: &str, args: &[String]) -> Result<Output> {
+        let out = System.status(program, args).await?;
+        self.record(program, args, &out);
+        Ok(out)
+    }
+}
+
+/// Executor answering calls from a recording
+///
+/// Calls must happen in recorded order with the recorded arguments.
+pub struct Replay {
+    records: Mutex<VecDeque<Record>>,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Self {
+        Replay {
+            records: Mutex::new(records.into()),
+        }
+    }
+
+    /// Load a yaml recording
+    pub fn read(pth: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(pth)?;
+        Ok(Replay::new(serde_yaml::from_str(&data)?))
+    }
+
+    /// Number of recorded calls that have not been made
+    pub fn remaining(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    fn next(&self, program: &str, args: &[String]) -> Result<Output> {
+        let call = format!("{} {}", program, args.join(" "));
+        match self.records.lock().unwrap().pop_front() {
+            Some(r) if r.program == program && r.args == args => Ok(r.output),
+            Some(r) => bail!(
+                "Replay expected `{} {}` but got `{}`",
+                r.program,
+                r.args.join(" "),
+                call
+            ),
+            None => bail!("Replay has no recording left for `{}`", call),
+        }
+    }
+}
+
+#[async_trait]
+impl Executor for Replay {
+    fn available(&self, _program: &str) -> bool {
+        true
+    }
+
+    async fn output(&self, program: &str, args: &[String]) -> Result<Output> {
+        self.next(program, args)
+    }
+
+    async fn status(&self, program: &str, args: &[String]) -> Result<Output> {
+        self.next(program, args)
+    }
+}
+
+tokio::task_local! {
+    static EXECUTOR: Arc<dyn Executor>;
+}
+
+/// The executor for the current task
+///
+/// This is the `System` executor unless running within `scope`.
+pub fn executor() -> Arc<dyn Executor> {
+    EXECUTOR
+        .try_with(|e| e.clone())
+        .unwrap_or_else(|_| Arc::new(System))
+}
+
+/// Run a future with all its external commands going through an executor
+///
+/// NB: tasks spawned from within the future use the `System` executor.
+pub async fn scope<F: Future>(exe: Arc<dyn Executor>, f: F) -> F::Output {
+    EXECUTOR.scope(exe, f).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{executor, scope, Output, Record, Replay};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn exec_replay() {
+        let rec = Record {
+            program: "helm".into(),
+            args: vec!["version".into()],
+            output: Output {
+                stdout: "v3.0.0".into(),
+                success: true,
+                ..Output::default()
+            },
+        };
+        let replay = Arc::new(Replay::new(vec![rec.clone(), rec]));
+        let res = scope(replay.clone(), async {
+            let first = executor().output("helm", &["version".into()]).await;
+            let second = executor().output("helm", &["list".into()]).await;
+            let third = executor().output("helm", &["version".into()]).await;
+            (first, second, third)
+        })
+        .await;
+        assert_eq!(res.0.unwrap().stdout, "v3.0.0");
+        assert!(res.1.is_err()); // arguments do not match the recording
+        assert!(res.2.is_err()); // recording exhausted
+        assert_eq!(replay.remaining(), 0);
+    }
+}
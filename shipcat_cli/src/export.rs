@@ -0,0 +1,120 @@
+use futures::stream::{self, StreamExt};
+use shipcat_definitions::{Config, Region, ShipcatManifest};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+use super::Result;
+use crate::{cluster, git, helm, kubeapi::ShipKube};
+
+/// Render a service into its own folder of the export
+///
+/// The folder contains the ShipcatManifest and one file per templated object.
+/// Versions and uids are those of the ShipcatManifest in the cluster unless pinned in the manifest,
+/// so services need to have been applied once before they can be exported.
+async fn export_service(svc: String, conf: &Config, reg: &Region, dir: PathBuf, secrets: bool) -> Result<()> {
+    let base = shipcat_filebacked::load_manifest(&svc, conf, reg).await?;
+    let crd = ShipKube::new(&base).await?.get().await?;
+    let version = base.version.clone().or(crd.spec.version);
+    if version.is_none() {
+        bail!("{} has no version in its manifest or in the cluster", svc);
+    }
+    let uid = crd.metadata.uid;
+    if uid.is_none() {
+        bail!("{} has no ShipcatManifest uid in {}", svc, reg.name);
+    }
+
+    let mut crd = ShipcatManifest::from(base.clone());
+    crd.spec.version = version.clone();
+    let mut mf = if secrets {
+        base.complete(reg).await?
+    } else {
+        base.stub(reg).await?
+    };
+    mf.version = version;
+    mf.uid = uid;
+
+    let tpl = helm::template(&mf, conf, reg, None).await?;
+    let mut files = helm::template_files(&tpl, &reg.namespace, false)?;
+    if !secrets {
+        // stubbed secrets must not overwrite the real ones in the cluster
+        files = files
+            .into_iter()
+            .filter(|(f, _)| !f.starts_with("secret-"))
+            .collect();
+    }
+    files.insert("shipcatmanifest.yaml".into(), serde_yaml::to_string(&crd)?);
+    helm::write_template_dir(&files, &dir.join(&svc)).await?;
+    info!("exported {}", svc);
+    Ok(())
+}
+
+/// Render every enabled service in a region into a directory tree for a GitOps repo
+///
+/// Services are rendered `n_workers` at a time into `{dir}/{service}/`.
+/// With a git reference in `since`, only the services changed since that reference
+/// (and services missing from the export) are rendered, unless shared files changed.
+/// Folders of services that are no longer enabled in the region are removed.
+pub async fn export_region(
+    conf: &Config,
+    reg: &Region,
+    dir: &Path,
+    since: Option<&str>,
+    secrets: bool,
+    n_workers: usize,
+) -> Result<()> {
+    let svcs = shipcat_filebacked::available(conf, reg)
+        .await?
+        .into_iter()
+        .map(|s| s.base.name)
+        .collect::<BTreeSet<_>>();
+    fs::create_dir_all(dir).await?;
+
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().await?.is_dir() && !name.starts_with('.') && !svcs.contains(&name) {
+            info!("removing {} from the export", name);
+            fs::remove_dir_all(entry.path()).await?;
+        }
+    }
+
+    let changed = match since {
+        Some(reference) => git::changed_services(&git::diff_filenames(reference)?),
+        None => None,
+    };
+    let selected = svcs
+        .into_iter()
+        .filter(|svc| match &changed {
+            Some(c) => c.contains(svc) || !dir.join(svc).exists(),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    info!(
+        "exporting {} services from {} to {}",
+        selected.len(),
+        reg.name,
+        dir.display()
+    );
+
+    let mut buffered = stream::iter(selected)
+        .map(|svc| async move {
+            let res = export_service(svc.clone(), conf, reg, dir.to_path_buf(), secrets).await;
+            (svc, res)
+        })
+        .buffer_unordered(n_workers);
+    let mut failed = vec![];
+    while let Some((svc, r)) = buffered.next().await {
+        if let Err(e) = r {
+            error!("{}: {}", svc, e);
+            debug!("{:?}", e);
+            failed.push(svc);
+        }
+    }
+    if !failed.is_empty() {
+        return Err(cluster::failure_summary("export", failed));
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use chrono::Utc;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::Api;
+use shipcat_definitions::freeze::AdhocFreeze;
+use std::env;
+
+use super::{kubeapi, Config, ErrorKind, Region, Result};
+
+/// Environment variable holding the reason given to `--override-freeze`
+pub const OVERRIDE_FREEZE_EVAR: &str = "SHIPCAT_OVERRIDE_FREEZE";
+
+/// Reason given for applying during a freeze, if any
+pub fn override_reason() -> Option<String> {
+    env::var(OVERRIDE_FREEZE_EVAR).ok().filter(|r| !r.is_empty())
+}
+
+/// Descriptions of the freezes in effect in a region
+///
+/// Combines the scheduled windows in shipcat.conf with the ad-hoc freezes in the freeze ConfigMap.
+pub async fn active(conf: &Config, reg: &Region) -> Result<Vec<String>> {
+    let cfg = match &conf.freezes {
+        Some(f) => f,
+        None => return Ok(vec![]),
+    };
+    let now = Utc::now();
+    let mut frozen = cfg
+        .active(reg, now)?
+        .into_iter()
+        .map(|w| w.describe())
+        .collect::<Vec<_>>();
+
+    let client = kubeapi::make_client().await?;
+    let api: Api<ConfigMap> = Api::namespaced(client, &reg.namespace);
+    match api.get(&cfg.configMap).await {
+        Ok(cm) => {
+            for (name, value) in cm.data.unwrap_or_default() {
+                let freeze = AdhocFreeze::parse(&value);
+                if freeze.active_at(now) {
+                    frozen.push(format!("{}: {}", name, freeze.reason));
+                }
+            }
+        }
+        Err(kube::Error::Api(e)) if e.code == 404 => {
+            debug!("No ad-hoc freezes in {}", reg.name);
+        }
+        Err(e) => return Err(ErrorKind::KubeError(e).into()),
+    }
+    Ok(frozen)
+}
+
+/// Refuse to change a region during a freeze
+///
+/// An `--override-freeze REASON` lets the change through, recording the reason in audit events.
+pub async fn enforce(conf: &Config, reg: &Region) -> Result<()> {
+    let frozen = active(conf, reg).await?;
+    if frozen.is_empty() {
+        return Ok(());
+    }
+    match override_reason() {
+        Some(why) => {
+            warn!(
+                "Overriding freeze of {} ({}): {}",
+                reg.name,
+                frozen.join(", "),
+                why
+            );
+            Ok(())
+        }
+        None => bail!(
+            "{} is frozen ({}), use --override-freeze REASON to apply anyway",
+            reg.name,
+            frozen.join(", ")
+        ),
+    }
+}
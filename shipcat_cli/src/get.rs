@@ -1,8 +1,17 @@
-use super::{Config, Region, Result};
+use super::{helm, Config, Error, Region, Result};
+use chrono::NaiveDate;
 use semver::Version;
-use shipcat_definitions::Environment;
+use shipcat_definitions::{
+    manifest::split_chart,
+    structs::{Dependency, SunsetState},
+    BaseManifest, Environment,
+};
 /// This file contains the `shipcat get` subcommand
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Not,
+    str::FromStr,
+};
 
 // ----------------------------------------------------------------------------
 // Simple reducers
@@ -110,6 +119,611 @@ pub async fn vaultpolicy(conf: &Config, region: &Region, team_name: &str) -> Res
     Ok(output)
 }
 
+/// An Alertmanager route matching on alert labels
+#[derive(Serialize)]
+pub struct AlertRoute {
+    #[serde(rename = "match")]
+    pub matchers: BTreeMap<String, String>,
+    pub receiver: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<AlertRoute>,
+}
+
+/// An Alertmanager receiver posting to a slack channel
+#[derive(Serialize)]
+pub struct AlertReceiver {
+    pub name: String,
+    pub slack_configs: Vec<BTreeMap<String, String>>,
+}
+
+/// Alertmanager routing tree fragment for a region
+#[derive(Serialize)]
+pub struct AlertRoutes {
+    pub routes: Vec<AlertRoute>,
+    pub receivers: Vec<AlertReceiver>,
+}
+
+/// Generate Alertmanager routes for the squads owning alerts in a region
+///
+/// Cross references config.teams with manifest.metadata.team
+/// Alerts are routed on their generated `team` label to the squad's slack alerts channel,
+/// and alerts labelled `page` go to the squad's `pagerduty` receiver when it has one.
+/// The `pagerduty` receivers (with their keys) must be defined alongside this fragment.
+pub async fn alert_routes(conf: &Config, region: &Region) -> Result<AlertRoutes> {
+    let mut squads = BTreeSet::new();
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        if !mf.prometheusAlerts.is_empty() {
+            squads.insert(svc.base.metadata.team);
+        }
+    }
+
+    let mut output = AlertRoutes {
+        routes: vec![],
+        receivers: vec![],
+    };
+    for team in squads {
+        let squad = if let Some(s) = conf.owners.squads.get(&team) {
+            s
+        } else {
+            warn!(
+                "No squad found for {} in teams.yml - not routing its alerts",
+                team
+            );
+            continue;
+        };
+        let channel = squad
+            .slack
+            .alerts
+            .clone()
+            .or_else(|| squad.slack.notifications.clone());
+        let channel = if let Some(c) = channel {
+            c
+        } else {
+            warn!(
+                "Squad '{}' has no slack alerts channel - not routing its alerts",
+                team
+            );
+            continue;
+        };
+        let slack_receiver = format!("{}-slack", team);
+        let mut slack_config = BTreeMap::new();
+        slack_config.insert("channel".to_string(), channel.to_string());
+        output.receivers.push(AlertReceiver {
+            name: slack_receiver.clone(),
+            slack_configs: vec![slack_config],
+        });
+
+        let mut routes = vec![];
+        if let Some(pd) = &squad.pagerduty {
+            let mut matchers = BTreeMap::new();
+            matchers.insert("page".to_string(), "true".to_string());
+            routes.push(AlertRoute {
+                matchers,
+                receiver: pd.clone(),
+                routes: vec![],
+            });
+        }
+        let mut matchers = BTreeMap::new();
+        matchers.insert("team".to_string(), team.clone());
+        output.routes.push(AlertRoute {
+            matchers,
+            receiver: slack_receiver,
+            routes,
+        });
+    }
+    println!("{}", serde_yaml::to_string(&output)?);
+    Ok(output)
+}
+
+/// A chain of latency budgeted dependencies
+#[derive(Serialize, Clone)]
+pub struct LatencyChain {
+    /// Services along the chain, starting with the one called first
+    pub services: Vec<String>,
+    /// Sum of the latency budgets of the dependencies along the chain
+    pub budgetMs: u32,
+    /// Whether every dependency along the chain is critical
+    pub critical: bool,
+    /// Whether the chain exceeds the end-to-end latency budget
+    pub exceeded: bool,
+}
+
+/// Latency budgets summed along the dependency chains of a region
+#[derive(Serialize)]
+pub struct LatencyBudgets {
+    /// End-to-end budget from shipcat.conf
+    pub budgetMs: Option<u32>,
+    pub chains: Vec<LatencyChain>,
+}
+
+fn latency_walk(
+    chain: &mut Vec<String>,
+    budget: u32,
+    critical: bool,
+    deps: &BTreeMap<String, Vec<Dependency>>,
+    out: &mut Vec<LatencyChain>,
+) {
+    let last = chain.last().cloned().unwrap_or_default();
+    let mut extended = false;
+    for d in deps.get(&last).into_iter().flatten() {
+        // only budgeted dependencies take part, and cycles end a chain
+        let ms = match d.latencyBudgetMs {
+            Some(ms) if !chain.contains(&d.name) => ms,
+            _ => continue,
+        };
+        extended = true;
+        chain.push(d.name.clone());
+        latency_walk(chain, budget + ms, critical && d.critical, deps, out);
+        chain.pop();
+    }
+    if !extended && chain.len() > 1 {
+        out.push(LatencyChain {
+            services: chain.clone(),
+            budgetMs: budget,
+            critical,
+            exceeded: false,
+        });
+    }
+}
+
+/// Find the longest chains of latency budgeted dependencies
+///
+/// Chains that are the tail end of a longer chain are left out.
+/// Sorted with the slowest chain first.
+pub fn latency_chains(deps: &BTreeMap<String, Vec<Dependency>>) -> Vec<LatencyChain> {
+    let mut all = vec![];
+    for name in deps.keys() {
+        latency_walk(&mut vec![name.clone()], 0, true, deps, &mut all);
+    }
+    let mut out: Vec<LatencyChain> = all
+        .iter()
+        .filter(|c| {
+            !all.iter()
+                .any(|o| o.services.len() > c.services.len() && o.services.ends_with(&c.services))
+        })
+        .cloned()
+        .collect();
+    out.sort_by_key(|c| std::cmp::Reverse(c.budgetMs));
+    out
+}
+
+/// Sum latency budgets along the dependency chains in a region
+///
+/// Uses `latencyBudgetMs` on dependencies, and flags chains exceeding the
+/// end-to-end `latencyBudgetMs` in shipcat.conf when one is set.
+pub async fn latency_budgets(conf: &Config, region: &Region) -> Result<LatencyBudgets> {
+    let mut deps = BTreeMap::new();
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        deps.insert(mf.name, mf.dependencies);
+    }
+    let mut chains = latency_chains(&deps);
+    if let Some(max) = conf.latencyBudgetMs {
+        for c in chains.iter_mut().filter(|c| c.budgetMs > max) {
+            c.exceeded = true;
+            warn!(
+                "{} adds up to {}ms, exceeding the {}ms budget",
+                c.services.join(" -> "),
+                c.budgetMs,
+                max
+            );
+        }
+    }
+    let output = LatencyBudgets {
+        budgetMs: conf.latencyBudgetMs,
+        chains,
+    };
+    println!("{}", serde_yaml::to_string(&output)?);
+    Ok(output)
+}
+
+/// Egress lockdown status of the services in a region
+#[derive(Serialize, Debug)]
+pub struct EgressReport {
+    /// Services with `strictEgress`
+    pub strict: Vec<String>,
+    /// Services still running with open egress
+    pub open: Vec<String>,
+}
+
+/// Find the services in a region still running with open egress
+///
+/// External services are left out as they have no pods.
+pub async fn egress(conf: &Config, region: &Region) -> Result<EgressReport> {
+    let mut output = EgressReport {
+        strict: vec![],
+        open: vec![],
+    };
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        if svc.external {
+            continue;
+        }
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        if mf.strictEgress {
+            output.strict.push(mf.name);
+        } else {
+            output.open.push(mf.name);
+        }
+    }
+    println!("{}", serde_yaml::to_string(&output)?);
+    Ok(output)
+}
+
+/// How to print ownership reports
+pub enum ReportFormat {
+    /// One row per squad
+    Table,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => bail!("Output format must be table or json"),
+        }
+    }
+}
+
+/// Ownership of the services of a squad
+#[derive(Serialize, Debug, Default)]
+pub struct SquadOwnership {
+    pub tribe: Option<String>,
+    /// Number of owned services in each region
+    pub services: BTreeMap<String, usize>,
+    /// Owned services without a runbook
+    pub missingRunbook: Vec<String>,
+    /// Owned services without a notifications channel, on the service or the squad
+    pub missingNotifications: Vec<String>,
+    /// Owned services without a support channel, on the service or the squad
+    pub missingSupport: Vec<String>,
+}
+
+/// Ownership of the services of a tribe
+#[derive(Serialize, Debug, Default)]
+pub struct TribeOwnership {
+    pub squads: Vec<String>,
+    /// Number of services owned by the tribe's squads in each region
+    pub services: BTreeMap<String, usize>,
+}
+
+/// Ownership health of the services across regions
+#[derive(Serialize, Debug, Default)]
+pub struct OwnershipReport {
+    pub squads: BTreeMap<String, SquadOwnership>,
+    pub tribes: BTreeMap<String, TribeOwnership>,
+    /// Services whose team is not a squad in teams.yml, with the team they name
+    pub orphaned: BTreeMap<String, String>,
+}
+
+/// Cross reference the squads and tribes of teams.yml with the services owned in `regions`
+pub fn ownership(conf: &Config, mfs: &[BaseManifest], regions: &[String]) -> OwnershipReport {
+    let mut report = OwnershipReport::default();
+    for (name, s) in &conf.owners.squads {
+        let tribe = conf.owners.tribes.values().find(|t| t.squads.contains(name));
+        let squad = SquadOwnership {
+            tribe: tribe.map(|t| t.name.clone()),
+            services: regions.iter().map(|r| (r.clone(), 0)).collect(),
+            ..SquadOwnership::default()
+        };
+        report.squads.insert(s.name.clone(), squad);
+    }
+    for mf in mfs {
+        let md = &mf.metadata;
+        let squad = match md.squad.as_ref().and_then(|s| report.squads.get_mut(s)) {
+            Some(s) => s,
+            None => {
+                report.orphaned.insert(mf.name.clone(), md.team.clone());
+                continue;
+            }
+        };
+        for r in mf.regions.iter().filter(|r| regions.contains(r)) {
+            *squad.services.entry(r.clone()).or_default() += 1;
+        }
+        if md.runbook.is_none() {
+            squad.missingRunbook.push(mf.name.clone());
+        }
+        if md.notifications.is_none() {
+            squad.missingNotifications.push(mf.name.clone());
+        }
+        if md.support.is_none() {
+            squad.missingSupport.push(mf.name.clone());
+        }
+    }
+    for t in conf.owners.tribes.values() {
+        let mut tribe = TribeOwnership {
+            squads: t.squads.clone(),
+            services: regions.iter().map(|r| (r.clone(), 0)).collect(),
+        };
+        for s in t.squads.iter().filter_map(|s| report.squads.get(s)) {
+            for (r, n) in &s.services {
+                *tribe.services.entry(r.clone()).or_default() += n;
+            }
+        }
+        report.tribes.insert(t.name.clone(), tribe);
+    }
+    report
+}
+
+fn print_ownership(report: &OwnershipReport) {
+    println!(
+        "{0:<30} {1:<30} {2:<40} {3:<12} {4:<18} {5:<12}",
+        "SQUAD", "TRIBE", "SERVICES", "NO RUNBOOK", "NO NOTIFICATIONS", "NO SUPPORT"
+    );
+    for (name, s) in &report.squads {
+        let services = s
+            .services
+            .iter()
+            .filter(|(_, n)| **n > 0)
+            .map(|(r, n)| format!("{}={}", r, n))
+            .collect::<Vec<_>>();
+        let services = if services.is_empty() {
+            "-".to_string()
+        } else {
+            services.join(" ")
+        };
+        println!(
+            "{0:<30} {1:<30} {2:<40} {3:<12} {4:<18} {5:<12}",
+            name,
+            s.tribe.as_deref().unwrap_or("-"),
+            services,
+            s.missingRunbook.len(),
+            s.missingNotifications.len(),
+            s.missingSupport.len(),
+        );
+    }
+    if !report.orphaned.is_empty() {
+        println!();
+        println!("{0:<40} {1:<30}", "ORPHANED SERVICE", "UNKNOWN TEAM");
+        for (svc, team) in &report.orphaned {
+            println!("{0:<40} {1:<30}", svc, team);
+        }
+    }
+}
+
+/// Ownership health report of the services in `regions`
+///
+/// Lists every squad and tribe with their number of services per region, the services
+/// missing a runbook, notifications or support channel, and services owned by unknown teams.
+pub async fn teams(conf: &Config, regions: &[String], fmt: ReportFormat) -> Result<OwnershipReport> {
+    let mfs = shipcat_filebacked::all_unchecked(conf).await?;
+    let report = ownership(conf, &mfs, regions);
+    match fmt {
+        ReportFormat::Table => print_ownership(&report),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(report)
+}
+
+/// A service with a sunset date, as listed by `shipcat get sunsets`
+#[derive(Serialize, Debug)]
+pub struct SunsetEntry {
+    pub service: String,
+    pub team: String,
+    pub date: NaiveDate,
+    /// Days left until the sunset, negative once it has passed
+    pub daysLeft: i64,
+    pub state: SunsetState,
+    pub replacement: Option<String>,
+    pub acknowledgedBy: Option<String>,
+}
+
+/// Services that are past or approaching their sunset, soonest first
+pub fn due_sunsets(mfs: &[BaseManifest]) -> Vec<SunsetEntry> {
+    let mut res = mfs
+        .iter()
+        .filter_map(|mf| mf.metadata.sunset.as_ref().map(|s| (mf, s)))
+        .filter(|(_, s)| s.state() != SunsetState::Scheduled)
+        .map(|(mf, s)| SunsetEntry {
+            service: mf.name.clone(),
+            team: mf.metadata.team.clone(),
+            date: s.date,
+            daysLeft: s.days_left(),
+            state: s.state(),
+            replacement: s.replacement.clone(),
+            acknowledgedBy: s.acknowledgedBy.clone(),
+        })
+        .collect::<Vec<_>>();
+    res.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.service.cmp(&b.service)));
+    res
+}
+
+/// Services past or approaching their sunset across all regions
+pub async fn sunsets(conf: &Config, fmt: ReportFormat) -> Result<Vec<SunsetEntry>> {
+    let mfs = shipcat_filebacked::all_unchecked(conf).await?;
+    let due = due_sunsets(&mfs);
+    match fmt {
+        ReportFormat::Table => {
+            println!(
+                "{0:<40} {1:<30} {2:<12} {3:<12} {4:<40} {5:<20}",
+                "SERVICE", "TEAM", "SUNSET", "STATE", "REPLACEMENT", "ACKNOWLEDGED BY"
+            );
+            for e in &due {
+                println!(
+                    "{0:<40} {1:<30} {2:<12} {3:<12} {4:<40} {5:<20}",
+                    e.service,
+                    e.team,
+                    e.date.to_string(),
+                    e.state.to_string(),
+                    e.replacement.as_deref().unwrap_or("-"),
+                    e.acknowledgedBy.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&due)?),
+    }
+    Ok(due)
+}
+
+// ----------------------------------------------------------------------------
+// Chart reducers
+
+/// A chart with its version, where known
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChartVersion {
+    /// Chart folder name, or git url of external charts
+    pub chart: String,
+    /// The `ref` of external charts, or the `version` in the `Chart.yaml` of local charts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl ChartVersion {
+    async fn new(chart: &str) -> ChartVersion {
+        if let Some(idx) = chart.find("?ref=") {
+            return ChartVersion {
+                chart: chart[..idx].to_string(),
+                version: Some(chart[idx + 5..].to_string()),
+            };
+        }
+        let (name, pin) = split_chart(chart);
+        let version = match pin {
+            Some(p) => Some(p.to_string()),
+            None => helm::local_chart_version(name).await,
+        };
+        ChartVersion {
+            chart: name.to_string(),
+            version,
+        }
+    }
+}
+
+/// Charts used in a region
+#[derive(Serialize, Debug)]
+pub struct RegionCharts {
+    /// Chart of services that do not set their own
+    pub defaultChart: Option<ChartVersion>,
+    /// Whether the default chart is older than the same chart in another region
+    pub lagging: bool,
+    /// Services using another chart than the default chart
+    pub custom: Vec<String>,
+    /// Chart of every service in the region
+    pub services: BTreeMap<String, ChartVersion>,
+}
+
+/// Charts used across regions
+#[derive(Serialize, Debug)]
+pub struct ChartReport {
+    /// Newest version of every default chart across regions
+    pub latest: BTreeMap<String, String>,
+    pub regions: BTreeMap<String, RegionCharts>,
+}
+
+/// Find the newest version of each default chart, and the regions using an older one
+///
+/// Versions that are not semver (like branch refs) are not compared.
+pub fn lagging_charts(
+    defaults: &BTreeMap<String, ChartVersion>,
+) -> (BTreeMap<String, String>, BTreeSet<String>) {
+    let parse = |cv: &ChartVersion| {
+        let v = cv.version.as_ref()?;
+        Version::parse(v.trim_start_matches('v'))
+            .ok()
+            .map(|sv| (sv, v.clone()))
+    };
+    let mut latest: BTreeMap<String, (Version, String)> = BTreeMap::new();
+    for cv in defaults.values() {
+        if let Some((sv, v)) = parse(cv) {
+            match latest.get(&cv.chart) {
+                Some((newest, _)) if newest >= &sv => {}
+                _ => {
+                    latest.insert(cv.chart.clone(), (sv, v));
+                }
+            }
+        }
+    }
+    let lagging = defaults
+        .iter()
+        .filter(|(_, cv)| match (parse(cv), latest.get(&cv.chart)) {
+            (Some((sv, _)), Some((newest, _))) => &sv < newest,
+            _ => false,
+        })
+        .map(|(r, _)| r.clone())
+        .collect();
+    let latest = latest.into_iter().map(|(c, (_, v))| (c, v)).collect();
+    (latest, lagging)
+}
+
+/// Report the charts used by services across regions
+///
+/// Highlights services not using their region's default chart,
+/// and regions with an older default chart than other regions.
+pub async fn charts(conf: &Config, regions: &[String]) -> Result<ChartReport> {
+    let mut resolved: BTreeMap<String, ChartVersion> = BTreeMap::new();
+    let mut output = BTreeMap::new();
+    let mut defaults = BTreeMap::new();
+    for r in regions {
+        let reg = match conf.get_region_unchecked(r) {
+            Some(reg) => reg,
+            None => continue,
+        };
+        let default_chart = shipcat_filebacked::default_chart(conf, reg)?;
+        let mut charts = default_chart.iter().cloned().collect::<Vec<_>>();
+        let mut services = BTreeMap::new();
+        for svc in shipcat_filebacked::available(conf, reg).await? {
+            let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
+            if let Some(c) = mf.chart {
+                charts.push(c.clone());
+                services.insert(mf.name, c);
+            }
+        }
+        for c in charts {
+            if resolved.contains_key(&c) {
+                continue;
+            }
+            let cv = ChartVersion::new(&c).await;
+            resolved.insert(c, cv);
+        }
+
+        let custom = services
+            .iter()
+            .filter(|(_, c)| Some(*c) != default_chart.as_ref())
+            .map(|(s, _)| s.clone())
+            .collect::<Vec<_>>();
+        for svc in &custom {
+            warn!("{} uses the custom chart {} in {}", svc, services[svc], r);
+        }
+        let default_chart = default_chart.map(|c| resolved[&c].clone());
+        if let Some(cv) = &default_chart {
+            defaults.insert(r.clone(), cv.clone());
+        }
+        output.insert(r.clone(), RegionCharts {
+            defaultChart: default_chart,
+            lagging: false,
+            custom,
+            services: services
+                .into_iter()
+                .map(|(s, c)| (s, resolved[&c].clone()))
+                .collect(),
+        });
+    }
+
+    let (latest, lagging) = lagging_charts(&defaults);
+    for r in lagging {
+        if let Some(rc) = output.get_mut(&r) {
+            let cv = &defaults[&r];
+            warn!(
+                "{} uses {} version {} (latest is {})",
+                r,
+                cv.chart,
+                cv.version.clone().unwrap_or_default(),
+                latest[&cv.chart]
+            );
+            rc.lagging = true;
+        }
+    }
+    let report = ChartReport {
+        latest,
+        regions: output,
+    };
+    println!("{}", serde_yaml::to_string(&report)?);
+    Ok(report)
+}
+
 // ----------------------------------------------------------------------------
 // Reducers for the Config
 
@@ -171,6 +785,11 @@ struct APIServiceParams {
     publiclyAccessible: bool,
     kompassPlugin: bool,
     websockets: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sunset: Option<NaiveDate>,
+    /// Deprecated API that is due to be removed
+    #[serde(skip_serializing_if = "Not::not")]
+    pastSunset: bool,
 }
 
 #[derive(Serialize)]
@@ -202,7 +821,12 @@ pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
                 publiclyAccessible: mf.publiclyAccessible,
                 kompassPlugin: mf.kompass_plugin,
                 websockets: false,
+                sunset: k.deprecation.as_ref().map(|d| d.sunset),
+                pastSunset: k.deprecation.iter().any(|d| d.is_past_sunset()),
             };
+            if params.pastSunset {
+                warn!("{} is deprecated and past its sunset date", k.name);
+            }
             if let Some(g) = &mf.gate {
                 // `manifest.verify` ensures that if there is a gate conf,
                 // `gate.public` must be equal to `publiclyAccessible`.
@@ -215,6 +839,23 @@ pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
             }
             services.insert(k.name, params);
         }
+        for w in mf.workers {
+            if let Some(k) = w.kong {
+                if k.deprecation.iter().any(|d| d.is_past_sunset()) {
+                    warn!("{} is deprecated and past its sunset date", k.name);
+                }
+                services.insert(k.name, APIServiceParams {
+                    uris: k.uris.unwrap_or("".into()),
+                    hosts: k.hosts.join(","),
+                    internal: k.internal,
+                    publiclyAccessible: k.publiclyAccessible,
+                    kompassPlugin: mf.kompass_plugin,
+                    websockets: w.gate.map(|g| g.websockets).unwrap_or_default(),
+                    sunset: k.deprecation.as_ref().map(|d| d.sunset),
+                    pastSunset: k.deprecation.iter().any(|d| d.is_past_sunset()),
+                });
+            }
+        }
     }
 
     // Get extra API Info from Config: TODO: remove
@@ -228,6 +869,8 @@ pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
                 kompassPlugin: false,
                 // TODO [DIP-499]: `extra_apis` do not support `gate` confs
                 websockets: false,
+                sunset: api.deprecation.as_ref().map(|d| d.sunset),
+                pastSunset: api.deprecation.iter().any(|d| d.is_past_sunset()),
             });
         }
     }
@@ -239,7 +882,7 @@ pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
 
 // ----------------------------------------------------------------------------
 // Get Eventstreams and kafka reducers
-use shipcat_definitions::structs::{kafkaresources, EventStream};
+use shipcat_definitions::structs::{kafkaresources, EventStream, SchemaType};
 
 #[derive(Serialize)]
 struct EventStreamsOutput {
@@ -247,7 +890,55 @@ struct EventStreamsOutput {
     eventstreams: BTreeMap<String, EventStream>,
 }
 
-pub async fn eventstreams(conf: &Config, reg: &Region) -> Result<()> {
+/// A schema version as posted to a schema registry subject
+#[derive(Serialize)]
+struct RegistrySchema {
+    schema: String,
+    #[serde(rename = "schemaType")]
+    schema_type: SchemaType,
+}
+
+#[derive(Serialize)]
+struct SchemaRegistryOutput {
+    region: String,
+    subjects: BTreeMap<String, Vec<RegistrySchema>>,
+}
+
+/// Schema registry subjects for eventstreams
+///
+/// Every stream has a `{name}-key` and a `{name}-value` subject, with one version per
+/// event definition, in definition order. Fails listing every schema that is not valid.
+fn registry_subjects(
+    eventstreams: &BTreeMap<String, EventStream>,
+) -> Result<BTreeMap<String, Vec<RegistrySchema>>> {
+    let mut subjects = BTreeMap::new();
+    let mut invalid = vec![];
+    for (name, es) in eventstreams {
+        for (i, def) in es.event_definitions.iter().enumerate() {
+            for (part, schema) in &[("key", &def.key), ("value", &def.value)] {
+                let subject = format!("{}-{}", name, part);
+                let schema_type = match SchemaType::of(schema) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        invalid.push(format!("{} version {}: {}", subject, i + 1, e));
+                        continue;
+                    }
+                };
+                let versions = subjects.entry(subject).or_insert_with(Vec::new);
+                versions.push(RegistrySchema {
+                    schema: schema.to_string(),
+                    schema_type,
+                });
+            }
+        }
+    }
+    if !invalid.is_empty() {
+        bail!("Invalid eventstream schemas:\n{}", invalid.join("\n"));
+    }
+    Ok(subjects)
+}
+
+pub async fn eventstreams(conf: &Config, reg: &Region, registry: bool) -> Result<()> {
     let mut eventstreams = BTreeMap::new();
 
     // Get eventstream Info from Manifests
@@ -259,6 +950,12 @@ pub async fn eventstreams(conf: &Config, reg: &Region) -> Result<()> {
     }
 
     let region = reg.name.clone();
+    if registry {
+        let subjects = registry_subjects(&eventstreams)?;
+        let output = SchemaRegistryOutput { region, subjects };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
     let output = EventStreamsOutput { region, eventstreams };
     println!("{}", serde_json::to_string_pretty(&output)?);
     Ok(())
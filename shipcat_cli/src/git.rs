@@ -1,5 +1,6 @@
 use super::Result;
-use std::process::Command;
+use regex::Regex;
+use std::{collections::BTreeSet, process::Command};
 
 // Dumb git wrapper that validates output or bails
 fn exec(args: &[&str]) -> Result<String> {
@@ -23,6 +24,12 @@ pub fn merge_base() -> Result<String> {
     Ok(out.trim().to_string())
 }
 
+// git rev-parse HEAD
+pub fn revision() -> Result<String> {
+    let out = exec(&["rev-parse", "HEAD"])?;
+    Ok(out.trim().to_string())
+}
+
 // Are there local changes in the index or working copy?
 pub fn needs_stash() -> bool {
     exec(&["diff", "--quiet", "--exit-code"]).is_err()
@@ -39,6 +46,11 @@ pub fn stash_pop() -> Result<String> {
     exec(&["stash", "pop", "--quiet"])
 }
 
+// git pull --ff-only
+pub fn pull() -> Result<String> {
+    exec(&["pull", "--ff-only", "--quiet"])
+}
+
 // git checkout <ref>
 pub fn checkout(reference: &str) -> Result<String> {
     exec(&["checkout", reference, "--quiet"])
@@ -48,3 +60,53 @@ pub fn checkout(reference: &str) -> Result<String> {
 pub fn diff_filenames(reference: &str) -> Result<String> {
     exec(&["diff", "--name-only", reference])
 }
+
+// git rev-parse --show-prefix
+pub fn show_prefix() -> Result<String> {
+    let out = exec(&["rev-parse", "--show-prefix"])?;
+    Ok(out.trim().to_string())
+}
+
+// git worktree add --quiet --detach <path> <ref>
+pub fn worktree_add(path: &str, reference: &str) -> Result<String> {
+    exec(&["worktree", "add", "--quiet", "--detach", path, reference])
+}
+
+// git worktree remove --force <path>
+pub fn worktree_remove(path: &str) -> Result<String> {
+    exec(&["worktree", "remove", "--force", path])
+}
+
+/// Services affected by the changes in a `git diff --name-only`
+///
+/// Returns None when files outside of `services/` changed (config, charts, templates),
+/// as those can affect every service. Markdown files are ignored.
+pub fn changed_services(diff: &str) -> Option<BTreeSet<String>> {
+    let svc_re = Regex::new(r"^services/(?P<svc>[0-9a-z\-]{1,50})/").unwrap();
+    let mut res = BTreeSet::new();
+    for l in diff.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(caps) = svc_re.captures(l) {
+            res.insert(caps["svc"].to_string());
+        } else if !l.ends_with(".md") {
+            debug!("{} changed, which affects every service", l);
+            return None;
+        }
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changed_services;
+
+    #[test]
+    fn git_changed_services() {
+        let diff = "services/fake-ask/manifest.yml\nservices/fake-ask/dev-uk.yml\nREADME.md\n";
+        let svcs = changed_services(diff).unwrap();
+        assert_eq!(svcs.into_iter().collect::<Vec<_>>(), vec!["fake-ask".to_string()]);
+
+        let diff = "services/fake-ask/manifest.yml\ncharts/base/templates/deployment.yaml\n";
+        assert!(changed_services(diff).is_none());
+        assert!(changed_services("").unwrap().is_empty());
+    }
+}
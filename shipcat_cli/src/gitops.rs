@@ -0,0 +1,212 @@
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde_json::json;
+use std::env;
+
+use super::{ErrorKind, Result, ResultExt};
+use crate::apply::UpgradeInfo;
+use shipcat_definitions::{Config, GitopsConfig, Region};
+
+/// Subset of the github contents api response
+#[derive(Deserialize)]
+struct Contents {
+    sha: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct GitRef {
+    object: GitObject,
+}
+
+#[derive(Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct HtmlUrl {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct ContentsUpdate {
+    commit: HtmlUrl,
+}
+
+fn env_token() -> Option<String> {
+    env::var("GITHUB_SHIPCAT_TOKEN").ok()
+}
+
+/// Pin a version in a region override file
+///
+/// Replaces a top level `version` key if present, and appends one otherwise,
+/// leaving the rest of the file as is.
+fn set_version(contents: &str, version: &str) -> String {
+    let pinned = format!("version: {}", version);
+    let mut found = false;
+    let mut lines = contents
+        .lines()
+        .map(|l| {
+            if l.starts_with("version:") {
+                found = true;
+                pinned.clone()
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    if !found {
+        lines.push(pinned);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// A minimal github client for the manifests repository
+struct Github {
+    client: Client,
+    repo: String,
+    token: String,
+}
+
+impl Github {
+    fn req(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = format!("https://api.github.com/repos/{}/{}", self.repo, path);
+        self.client.request(method, &url).bearer_auth(&self.token)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let res = req
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .chain_err(|| ErrorKind::GitopsFailure(self.repo.clone()))?;
+        Ok(res.json().await?)
+    }
+
+    async fn contents(&self, path: &str, branch: &str) -> Result<Option<Contents>> {
+        let req = self
+            .req(Method::GET, &format!("contents/{}", path))
+            .query(&[("ref", branch)]);
+        let res = req
+            .send()
+            .await
+            .chain_err(|| ErrorKind::GitopsFailure(self.repo.clone()))?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = res
+            .error_for_status()
+            .chain_err(|| ErrorKind::GitopsFailure(self.repo.clone()))?;
+        Ok(Some(res.json().await?))
+    }
+
+    async fn create_branch(&self, base: &str, name: &str) -> Result<()> {
+        let base: GitRef = self
+            .send(self.req(Method::GET, &format!("git/ref/heads/{}", base)))
+            .await?;
+        let data = json!({
+            "ref": format!("refs/heads/{}", name),
+            "sha": base.object.sha,
+        });
+        let _: GitRef = self.send(self.req(Method::POST, "git/refs").json(&data)).await?;
+        Ok(())
+    }
+}
+
+/// Whether applied versions are written back to git in a region
+pub fn enabled(conf: &Config, reg: &Region) -> bool {
+    conf.gitops
+        .as_ref()
+        .map_or(false, |g| g.environments.contains(&reg.environment))
+}
+
+/// Pin the version of an applied service in the manifests repository
+///
+/// Commits to the configured branch, or opens a pull request against it.
+/// Does nothing unless `gitops` is configured for the environment of the region.
+/// Returns a link to the commit or pull request if a change was made.
+pub async fn write_back(info: &UpgradeInfo, reg: &Region, conf: &Config) -> Result<Option<String>> {
+    let cfg: &GitopsConfig = match &conf.gitops {
+        Some(g) if g.environments.contains(&reg.environment) => g,
+        _ => return Ok(None),
+    };
+    let token = match env_token() {
+        Some(t) => t,
+        None => {
+            warn!(
+                "Not writing back {} version without GITHUB_SHIPCAT_TOKEN",
+                info.name
+            );
+            return Ok(None);
+        }
+    };
+    let gh = Github {
+        client: Client::builder().user_agent("rust-reqwest/shipcat").build()?,
+        repo: format!("{}/{}", conf.github.organisation, cfg.repo),
+        token,
+    };
+    let path = format!("services/{}/{}.yml", info.name, reg.name);
+    let existing = gh.contents(&path, &cfg.branch).await?;
+    let old = match &existing {
+        Some(c) => {
+            let raw = base64::decode(c.content.replace('\n', ""))
+                .chain_err(|| ErrorKind::GitopsFailure(gh.repo.clone()))?;
+            String::from_utf8_lossy(&raw).to_string()
+        }
+        None => String::new(),
+    };
+    let new = set_version(&old, &info.version);
+    if new == old {
+        debug!("{} already pins {}={}", path, info.name, info.version);
+        return Ok(None);
+    }
+
+    let target = if cfg.pullRequest {
+        let name = format!("shipcat/{}-{}-{}", info.name, reg.name, info.version);
+        gh.create_branch(&cfg.branch, &name).await?;
+        name
+    } else {
+        cfg.branch.clone()
+    };
+    let title = format!("Pin {} to {} in {}", info.name, info.version, reg.name);
+    let mut data = json!({
+        "message": title,
+        "content": base64::encode(&new),
+        "branch": target,
+    });
+    if let Some(c) = existing {
+        data["sha"] = json!(c.sha);
+    }
+    let update: ContentsUpdate = gh
+        .send(gh.req(Method::PUT, &format!("contents/{}", path)).json(&data))
+        .await?;
+    if !cfg.pullRequest {
+        return Ok(Some(update.commit.html_url));
+    }
+
+    let pr = json!({
+        "title": title,
+        "head": target,
+        "base": cfg.branch,
+        "body": format!("{} was applied in {} by shipcat.", info.name, reg.name),
+    });
+    let res: HtmlUrl = gh.send(gh.req(Method::POST, "pulls").json(&pr)).await?;
+    Ok(Some(res.html_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::set_version;
+
+    #[test]
+    fn gitops_set_version() {
+        let pinned = set_version("version: 1.0.0\nreplicaCount: 2\n", "1.1.0");
+        assert_eq!(pinned, "version: 1.1.0\nreplicaCount: 2\n");
+        let appended = set_version("replicaCount: 2\n", "1.1.0");
+        assert_eq!(appended, "replicaCount: 2\nversion: 1.1.0\n");
+        assert_eq!(set_version("", "1.1.0"), "version: 1.1.0\n");
+        // nested keys are not touched
+        let nested = set_version("image:\n  version: 2\n", "1.1.0");
+        assert_eq!(nested, "image:\n  version: 2\nversion: 1.1.0\n");
+    }
+}
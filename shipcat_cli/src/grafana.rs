@@ -0,0 +1,102 @@
+use chrono::Utc;
+use std::env;
+
+use super::{ErrorKind, Result, ResultExt};
+use crate::{apply::UpgradeInfo, webhooks::UpgradeState};
+use shipcat_definitions::Region;
+
+/// Annotation payload for the grafana annotations api
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Annotation {
+    #[serde(rename = "dashboardUID")]
+    dashboard_uid: String,
+    /// Epoch milliseconds
+    time: i64,
+    tags: Vec<String>,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AnnotationResponse {
+    id: u64,
+}
+
+fn env_token() -> Option<String> {
+    env::var("GRAFANA_SHIPCAT_TOKEN").ok()
+}
+
+/// Tags for an annotation of an upgrade
+///
+/// Dashboards can filter deploy markers on these via annotation queries.
+fn annotation_tags(us: &UpgradeState, info: &UpgradeInfo) -> Vec<String> {
+    let outcome = serde_json::to_value(us)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_lowercase))
+        .unwrap_or_else(|| "unknown".into());
+    vec![
+        "shipcat".into(),
+        format!("service:{}", info.name),
+        format!("version:{}", info.version),
+        format!("outcome:{}", outcome),
+    ]
+}
+
+/// Mark an upgrade on the region's services dashboard
+///
+/// Requires `grafana` configured for the region and `GRAFANA_SHIPCAT_TOKEN` in the environment.
+/// Returns the id of the created annotation, if any.
+pub async fn annotate(us: &UpgradeState, info: &UpgradeInfo, reg: &Region) -> Result<Option<u64>> {
+    let grafana = match &reg.grafana {
+        Some(g) => g,
+        None => return Ok(None),
+    };
+    let token = match env_token() {
+        Some(t) => t,
+        None => {
+            debug!("Not annotating grafana without GRAFANA_SHIPCAT_TOKEN");
+            return Ok(None);
+        }
+    };
+    let mut text = format!("{}={} in {}", info.name, info.version, info.region);
+    if let Some(d) = info.duration {
+        text += &format!(" (rollout took {}s)", d.as_secs());
+    }
+    let data = Annotation {
+        dashboard_uid: grafana.services_dashboard_id.clone(),
+        time: Utc::now().timestamp_millis(),
+        tags: annotation_tags(us, info),
+        text,
+    };
+    let url = reqwest::Url::parse(&format!("{}/api/annotations", grafana.url.trim_end_matches('/')))?;
+    let res: AnnotationResponse = reqwest::Client::new()
+        .post(url.clone())
+        .bearer_auth(token)
+        .json(&data)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .chain_err(|| ErrorKind::Url(url.clone()))?
+        .json()
+        .await?;
+    debug!("Created grafana annotation {} for {}", res.id, info.name);
+    Ok(Some(res.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::annotation_tags;
+    use crate::{apply::UpgradeInfo, webhooks::UpgradeState, Manifest};
+
+    #[test]
+    fn grafana_annotation_tags() {
+        let info = UpgradeInfo::new(&Manifest::test("fake-svc"));
+        let tags = annotation_tags(&UpgradeState::Failed, &info);
+        assert_eq!(tags, vec![
+            "shipcat",
+            "service:fake-svc",
+            "version:1.0.0",
+            "outcome:failed"
+        ]);
+    }
+}
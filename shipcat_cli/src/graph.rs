@@ -2,7 +2,10 @@ use petgraph::{
     dot,
     graph::{DiGraph, NodeIndex},
 };
-use std::fmt::{self, Debug};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+};
 
 use super::{
     structs::{Dependency, DependencyProtocol},
@@ -159,16 +162,111 @@ pub async fn full(dot: bool, conf: &Config, reg: &Region) -> Result<CatGraph> {
     Ok(graph)
 }
 
-/// Generate first level reverse dependencies for a service
-pub async fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<String>> {
+/// Manifests of the services in a region depending directly on a service
+pub async fn dependents(service: &str, conf: &Config, reg: &Region) -> Result<Vec<Manifest>> {
     let mut res = vec![];
     for svc in shipcat_filebacked::available(conf, reg).await? {
         let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
-        if mf.dependencies.into_iter().any(|d| d.name == service) {
-            res.push(svc.base.name)
+        if mf.dependencies.iter().any(|d| d.name == service) {
+            res.push(mf)
         }
     }
+    Ok(res)
+}
+
+/// Generate first level reverse dependencies for a service
+pub async fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<String>> {
+    let res = dependents(service, conf, reg)
+        .await?
+        .into_iter()
+        .map(|mf| mf.name)
+        .collect::<Vec<_>>();
     let out = serde_yaml::to_string(&res)?;
     println!("{}", out);
     Ok(res)
 }
+
+/// Group services into waves where every service comes after its dependencies
+///
+/// Services within a wave are independent of each other and can be applied in parallel.
+/// Dependencies on services outside of `deps` are ignored.
+pub fn apply_waves(deps: &BTreeMap<String, BTreeSet<String>>) -> Result<Vec<Vec<String>>> {
+    let mut remaining = deps
+        .iter()
+        .map(|(svc, ds)| {
+            let inside = ds.iter().filter(|d| deps.contains_key(*d) && d != &svc).cloned();
+            (svc.clone(), inside.collect::<BTreeSet<_>>())
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut waves = vec![];
+    while !remaining.is_empty() {
+        let wave = remaining
+            .iter()
+            .filter(|(_, ds)| ds.is_empty())
+            .map(|(svc, _)| svc.clone())
+            .collect::<Vec<_>>();
+        if wave.is_empty() {
+            let cycle = remaining.keys().cloned().collect::<Vec<_>>();
+            bail!("Dependency cycle between {:?}", cycle);
+        }
+        for svc in &wave {
+            remaining.remove(svc);
+        }
+        for ds in remaining.values_mut() {
+            for svc in &wave {
+                ds.remove(svc);
+            }
+        }
+        waves.push(wave);
+    }
+    Ok(waves)
+}
+
+/// Dependency ordered waves of services in a region
+///
+/// Used by `cluster crd reconcile --ordered` to apply dependencies before dependents.
+pub async fn ordered(svcs: &[String], conf: &Config, reg: &Region) -> Result<Vec<Vec<String>>> {
+    let mut deps = BTreeMap::new();
+    for svc in svcs {
+        let mf = shipcat_filebacked::load_manifest(svc, conf, reg).await?;
+        let names = mf
+            .dependencies
+            .into_iter()
+            .map(|d| d.name)
+            .collect::<BTreeSet<_>>();
+        deps.insert(svc.clone(), names);
+    }
+    apply_waves(&deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply_waves;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn deps(edges: &[(&str, &[&str])]) -> BTreeMap<String, BTreeSet<String>> {
+        edges
+            .iter()
+            .map(|(svc, ds)| (svc.to_string(), ds.iter().map(|d| d.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn graph_apply_waves() {
+        let waves = apply_waves(&deps(&[
+            ("fake-ask", &["fake-storage", "external-thing"]),
+            ("fake-storage", &[]),
+            ("fake-web", &["fake-ask", "fake-storage"]),
+            ("fake-lonely", &["fake-lonely"]),
+        ]))
+        .unwrap();
+        assert_eq!(waves, vec![
+            vec!["fake-lonely".to_string(), "fake-storage".to_string()],
+            vec!["fake-ask".to_string()],
+            vec!["fake-web".to_string()],
+        ]);
+
+        let cyclic = deps(&[("a", &["b"]), ("b", &["a"]), ("c", &[])]);
+        assert!(apply_waves(&cyclic).is_err());
+    }
+}
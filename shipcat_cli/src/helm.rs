@@ -6,15 +6,16 @@ use std::{
 use tokio::{
     fs::{self, File},
     prelude::*,
-    process::Command,
 };
 
-use super::Result;
-use shipcat_definitions::{Manifest, ReconciliationMode, Region};
+use super::{egress, exec::executor, networkpolicy, prometheusrule, Result};
+use shipcat_definitions::{
+    manifest::split_chart, ClusterCapabilities, Config, Manifest, ReconciliationMode, Region,
+};
 
 pub fn hexists() -> Result<()> {
-    if which::which("helm").is_err() {
-        bail!("helm executable not found!");
+    if !executor().available("helm") {
+        bail!("helm executable not found! charts are rendered with helm, see doc/building.md");
     }
     Ok(())
 }
@@ -22,19 +23,17 @@ pub fn hexists() -> Result<()> {
 pub async fn hexec(args: Vec<String>) -> Result<()> {
     debug!("helm {}", args.join(" "));
     hexists()?;
-    let s = Command::new("helm").args(&args).status().await?;
-    if !s.success() {
-        bail!("Subprocess failure from helm: {}", s.code().unwrap_or(1001))
+    let s = executor().status("helm", &args).await?;
+    if !s.success {
+        bail!("Subprocess failure from helm: {}", s.code.unwrap_or(1001))
     }
     Ok(())
 }
 pub async fn hout(args: Vec<String>) -> Result<(String, String, bool)> {
     debug!("helm {}", args.join(" "));
     hexists()?;
-    let s = Command::new("helm").args(&args).output().await?;
-    let out: String = String::from_utf8_lossy(&s.stdout).into();
-    let err: String = String::from_utf8_lossy(&s.stderr).into();
-    Ok((out, err, s.status.success()))
+    let s = executor().output("helm", &args).await?;
+    Ok((s.stdout, s.stderr, s.success))
 }
 
 pub async fn clone_chart(repo_url: &str) -> Result<(String, String, bool)> {
@@ -55,14 +54,53 @@ pub async fn clone_chart(repo_url: &str) -> Result<(String, String, bool)> {
             let captures = re.captures(repo_url).unwrap();
             let repo = captures.get(1).map_or("", |m| m.as_str());
             let tag = captures.get(2).map_or("", |m| m.as_str());
-            let args = ["clone", "-b", &tag, &repo, &path];
-            debug!("git clone {}", args.join(" "));
-            let s = Command::new("git").args(&args).output().await?;
-            let out: String = String::from_utf8_lossy(&s.stdout).into();
-            let err: String = String::from_utf8_lossy(&s.stderr).into();
-            Ok((out, err, s.status.success()))
+            let args = vec!["clone".into(), "-b".into(), tag.into(), repo.into(), path];
+            debug!("git {}", args.join(" "));
+            let s = executor().output("git", &args).await?;
+            Ok((s.stdout, s.stderr, s.success))
+        }
+    }
+}
+
+/// Version of a local chart from its `Chart.yaml`
+pub async fn local_chart_version(name: &str) -> Option<String> {
+    let pth = Path::new(".").join("charts").join(name).join("Chart.yaml");
+    let data = fs::read_to_string(&pth).await.ok()?;
+    let chart: serde_yaml::Value = serde_yaml::from_str(&data).ok()?;
+    chart["version"].as_str().map(String::from)
+}
+
+/// Check the version of a local chart against the service's pin and the region
+///
+/// Fails when the chart on disk is not at the pinned version (like in an old checkout),
+/// or when the version in use is not in the region's `allowedChartVersions`.
+pub fn verify_chart_version(mf: &Manifest, reg: &Region, local: Option<&str>) -> Result<()> {
+    let (name, pin) = split_chart(mf.chart.as_ref().unwrap());
+    if let Some(p) = pin {
+        if local != Some(p) {
+            bail!(
+                "{} pins chart {}@{}, but charts/{} is at {} - update your checkout",
+                mf.name,
+                name,
+                p,
+                name,
+                local.unwrap_or("no version")
+            );
+        }
+    }
+    if let Some(allowed) = reg.allowedChartVersions.get(name) {
+        let used = pin.or(local).unwrap_or_default();
+        if !allowed.iter().any(|a| a == used) {
+            bail!(
+                "{} uses chart {} at version {}, which is not allowed in {}",
+                mf.name,
+                name,
+                used,
+                reg.name
+            );
         }
     }
+    Ok(())
 }
 
 /// Create helm values file for a service
@@ -86,8 +124,12 @@ pub async fn values(mf: &Manifest, output: &str) -> Result<()> {
 
 /// Analogue of helm template
 ///
-/// Generates helm values to disk, then passes it to helm template
-pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String> {
+/// Generates helm values to disk, then passes it to helm template.
+/// Capabilities of the region's clusters are passed on for charts to pick apiVersions with.
+/// PrometheusRule objects for the service's alerts are appended when the region wants them,
+/// and so are the egress policy of services with `strictEgress`
+/// and the ingress policy of services with `networkPolicies: generated`.
+pub async fn template(mf: &Manifest, conf: &Config, reg: &Region, output: Option<PathBuf>) -> Result<String> {
     let hfile = format!("{}.helm.gen.yml", mf.name);
     values(&mf, &hfile).await?;
 
@@ -98,20 +140,35 @@ pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String>
             warn!("{} stderr: {}", chart, tplerr);
             bail!("helm failed to fetch template");
         }
+    } else {
+        let local = local_chart_version(split_chart(&chart).0).await;
+        verify_chart_version(mf, reg, local.as_deref())?;
     }
     // helm template with correct params
-    let tplvec = vec![
+    let mut tplvec = vec![
         "template".into(),
-        format!("charts/{}", mf.chart.clone().unwrap()),
+        format!("charts/{}", split_chart(&chart).0),
         "-f".into(),
         hfile.clone(),
     ];
+    if let Some(caps) = conf.region_capabilities(reg) {
+        tplvec.extend(capability_args(&caps));
+    }
     // NB: this call does NOT need --tiller-namespace (offline call)
-    let (tpl, tplerr, success) = hout(tplvec.clone()).await?;
+    let (mut tpl, tplerr, success) = hout(tplvec.clone()).await?;
     if !success {
         warn!("{} stderr: {}", tplvec.join(" "), tplerr);
         bail!("helm template failed");
     }
+    if let Some(rule) = prometheusrule::render(mf, reg)? {
+        tpl = format!("{}\n{}\n", tpl.trim_end(), rule);
+    }
+    if let Some(policy) = egress::render(mf, reg)? {
+        tpl = format!("{}\n{}\n", tpl.trim_end(), policy);
+    }
+    if let Some(policy) = networkpolicy::render(mf, conf, reg).await? {
+        tpl = format!("{}\n{}\n", tpl.trim_end(), policy);
+    }
     if let Some(o) = &output {
         let pth = Path::new(".").join(o);
         debug!("Writing helm template for {} to {}", mf.name, pth.display());
@@ -131,14 +188,127 @@ pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String>
     Ok(tpl)
 }
 
+/// Split a rendered template into one file per kubernetes object
+///
+/// Files are named after the kind and name of their object, and helm's empty documents are dropped.
+/// With `kustomize`, a kustomization.yaml listing every file is generated as well,
+/// so the directory can be used as a kustomize base.
+pub fn template_files(tpl: &str, namespace: &str, kustomize: bool) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    let mut doc = String::new();
+    let mut docs = vec![];
+    for line in tpl.lines() {
+        if line.trim_end() == "---" {
+            docs.push(std::mem::take(&mut doc));
+        } else {
+            doc.push_str(line);
+            doc.push('\n');
+        }
+    }
+    docs.push(doc);
+    for doc in docs {
+        let obj: KubeObject = match serde_yaml::from_str(&doc) {
+            Ok(o) => o,
+            Err(e) => {
+                trace!("Skipping document without a kubernetes object ({}): {}", e, doc);
+                continue;
+            }
+        };
+        let kind = obj.types.kind.to_lowercase();
+        let name = obj.metadata.name.unwrap_or_else(|| "unnamed".into());
+        let mut filename = format!("{}-{}.yaml", kind, name);
+        let mut i = 1;
+        while files.contains_key(&filename) {
+            i += 1;
+            filename = format!("{}-{}-{}.yaml", kind, name, i);
+        }
+        files.insert(filename, doc.trim().to_string() + "\n");
+    }
+    if files.is_empty() {
+        bail!("template contains no kubernetes objects");
+    }
+    if kustomize {
+        let kustomization = Kustomization {
+            apiVersion: "kustomize.config.k8s.io/v1beta1".into(),
+            kind: "Kustomization".into(),
+            namespace: namespace.into(),
+            resources: files.keys().cloned().collect(),
+        };
+        files.insert(
+            "kustomization.yaml".into(),
+            serde_yaml::to_string(&kustomization)?,
+        );
+    }
+    Ok(files)
+}
+
+/// Write the `template_files` of a rendered template to a directory
+///
+/// Stale yaml files from earlier runs are removed,
+/// so objects that were dropped from the chart disappear from gitops repos as well.
+pub async fn write_template_dir(files: &BTreeMap<String, String>, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).await?;
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let pth = entry.path();
+        let stale = pth.extension().map_or(false, |e| e == "yaml")
+            && !files.contains_key(entry.file_name().to_string_lossy().as_ref());
+        if stale {
+            debug!("Removing stale {}", pth.display());
+            fs::remove_file(&pth).await?;
+        }
+    }
+    for (name, content) in files {
+        let pth = dir.join(name);
+        debug!("Writing {}", pth.display());
+        let mut f = File::create(&pth).await?;
+        f.write_all(content.as_bytes()).await?;
+        f.sync_data().await?;
+    }
+    info!("Wrote {} files to {}", files.len(), dir.display());
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Kustomization {
+    apiVersion: String,
+    kind: String,
+    namespace: String,
+    resources: Vec<String>,
+}
+
+/// Helm template flags setting `.Capabilities` to those of a cluster
+fn capability_args(caps: &ClusterCapabilities) -> Vec<String> {
+    let mut args = vec![];
+    if let Some(v) = &caps.kube_version {
+        args.push("--kube-version".into());
+        args.push(v.to_string());
+    }
+    for api in &caps.api_versions {
+        args.push("--api-versions".into());
+        args.push(api.clone());
+    }
+    args
+}
+
 /// Helper to validate the assumption of the charts
 ///
 /// This is an addon to checks done through `kubeval`.
 /// We don't validate kubernetes schemas in here, but we do validate consistency of:
 /// - labels: app.kubernetes.io/name, app.kubernetes.io/version, app.kubernetes.io/managed-by
 /// - ownerReferences (need ShipcatManifest, !controller, uid propagated, name correct)
-pub fn template_check(mf: &Manifest, reg: &Region, skipped: &[String], tpl: &str) -> Result<()> {
+/// - PrometheusRule labels matching the region's ruleSelector
+/// - apiVersions served by the region's clusters (when their capabilities are known)
+pub fn template_check(
+    mf: &Manifest,
+    conf: &Config,
+    reg: &Region,
+    skipped: &[String],
+    tpl: &str,
+) -> Result<()> {
+    let caps = conf.region_capabilities(reg);
     let mut invalids = vec![];
+    let mut generated_policy = false;
     for to in tpl.split("---") {
         let kind = match serde_yaml::from_str::<PartialObject>(&to) {
             Err(_) => {
@@ -164,17 +334,37 @@ pub fn template_check(mf: &Manifest, reg: &Region, skipped: &[String], tpl: &str
             .unwrap_or_else(|| format!("unset metadata.name from {}", kind));
 
         let tiller_ok = check_no_tiller_refs(&kind, &obj)?;
+        let selector_ok = kind != "PrometheusRule" || check_rule_selector(reg, &obj);
+        let mut policy_ok = true;
+        if kind == "NetworkPolicy" && networkpolicy::is_generated(mf) {
+            policy_ok = check_network_policy(mf, to);
+            generated_policy |= name == &networkpolicy::policy_name(mf);
+        }
+        let api_ok = match &caps {
+            Some(c) => check_api_version(c, &kind, &obj),
+            None => true,
+        };
         let ok = match reg.reconciliationMode {
             ReconciliationMode::CrdOwned => {
                 let owner_ok = check_owner_refs(mf, &kind, &obj)?;
                 let labels_ok = check_labels(mf, &kind, skipped, &obj)?;
                 labels_ok && owner_ok
             }
-        } && tiller_ok;
+        } && tiller_ok
+            && selector_ok
+            && policy_ok
+            && api_ok;
         if !ok {
             invalids.push(format!("{} {{ {} }}", kind, name));
         }
     }
+    if networkpolicy::is_generated(mf) && !generated_policy {
+        warn!(
+            "NetworkPolicy: missing generated {}",
+            networkpolicy::policy_name(mf)
+        );
+        invalids.push(format!("NetworkPolicy {{ {} }}", networkpolicy::policy_name(mf)));
+    }
     if !invalids.is_empty() {
         bail!("Invalid objects: {:?}", invalids);
     }
@@ -190,7 +380,7 @@ struct PartialObject {
 #[derive(Deserialize)]
 struct KubeObject {
     #[serde(flatten)]
-    _types: TypeMeta,
+    types: TypeMeta,
     metadata: ObjectMeta,
 }
 
@@ -281,6 +471,42 @@ fn check_owner_refs(mf: &Manifest, kind: &str, obj: &KubeObject) -> Result<bool>
     Ok(success)
 }
 
+// objects must use apis the cluster serves
+fn check_api_version(caps: &ClusterCapabilities, kind: &str, obj: &KubeObject) -> bool {
+    let api = &obj.types.api_version;
+    if caps.serves(api) {
+        debug!("{}: served apiVersion {}", kind, api);
+        true
+    } else {
+        warn!("{}: apiVersion {} is not served by the cluster", kind, api);
+        false
+    }
+}
+
+// prometheus rules must be picked up by the region's prometheus
+fn check_rule_selector(reg: &Region, obj: &KubeObject) -> bool {
+    let labels = obj.metadata.labels.clone().unwrap_or_default();
+    let missing = prometheusrule::missing_selector_labels(reg, &labels);
+    if !missing.is_empty() {
+        warn!(
+            "PrometheusRule: missing ruleSelector labels {}",
+            missing.join(", ")
+        );
+    }
+    missing.is_empty()
+}
+
+// network policies of services with generated policies must not restrict other services
+fn check_network_policy(mf: &Manifest, raw: &str) -> bool {
+    let ok = serde_yaml::from_str(raw)
+        .map(|policy| networkpolicy::selects_only_service(mf, &policy))
+        .unwrap_or(false);
+    if !ok {
+        warn!("NetworkPolicy: podSelector selects pods outside of {}", mf.name);
+    }
+    ok
+}
+
 // charts should not reference tiller
 fn check_no_tiller_refs(kind: &str, obj: &KubeObject) -> Result<bool> {
     let mut success = true;
@@ -302,3 +528,41 @@ fn check_no_tiller_refs(kind: &str, obj: &KubeObject) -> Result<bool> {
     }
     Ok(success)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::verify_chart_version;
+    use crate::{Manifest, Region};
+    use shipcat_definitions::manifest::split_chart;
+
+    #[test]
+    fn helm_chart_pins() {
+        assert_eq!(split_chart("base@2.3.1"), ("base", Some("2.3.1")));
+        assert_eq!(split_chart("base"), ("base", None));
+        let git = "git@github.com:org/base-chart.git?ref=v1.0.0";
+        assert_eq!(split_chart(git), (git, None));
+
+        let reg: Region = serde_yaml::from_str(
+            "name: dev-uk
+namespace: apps
+environment: dev
+cluster: kops-uk
+versioningScheme: Semver
+vault: {url: http://localhost:8200, folder: dev}
+allowedChartVersions:
+  base: [2.3.1]",
+        )
+        .unwrap();
+        let mut mf = Manifest::test("fake-ask");
+        mf.chart = Some("base@2.3.1".into());
+        assert!(verify_chart_version(&mf, &reg, Some("2.3.1")).is_ok());
+        assert!(verify_chart_version(&mf, &reg, Some("2.2.0")).is_err()); // old checkout
+        assert!(verify_chart_version(&mf, &reg, None).is_err());
+        // unpinned charts are checked against the region by their local version
+        mf.chart = Some("base".into());
+        assert!(verify_chart_version(&mf, &reg, Some("2.3.1")).is_ok());
+        assert!(verify_chart_version(&mf, &reg, Some("2.4.0")).is_err());
+        mf.chart = Some("custom".into());
+        assert!(verify_chart_version(&mf, &reg, Some("0.1.0")).is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+use std::process::Stdio;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use shipcat_definitions::{ApplyHook, ApplyHookStage, Config, Manifest};
+
+use super::{Result, ResultExt};
+
+/// Run all `applyHooks` from shipcat.conf registered for a stage
+///
+/// Hooks run in the order they are declared. Failures of optional hooks are only logged.
+pub async fn run(stage: ApplyHookStage, mf: &Manifest, conf: &Config, tfile: Option<&str>) -> Result<()> {
+    for h in conf.applyHooks.iter().filter(|h| h.stage == stage) {
+        debug!("Running {:?} hook {} for {}", stage, h.name, mf.name);
+        if let Err(e) = run_hook(h, mf, tfile).await {
+            if h.optional {
+                warn!("Ignoring failure of optional hook {}: {}", h.name, e);
+            } else {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_hook(hook: &ApplyHook, mf: &Manifest, tfile: Option<&str>) -> Result<()> {
+    if !mf.is_base() {
+        bail!(
+            "Refusing to pass the secrets of {} to hook {}",
+            mf.name,
+            hook.name
+        );
+    }
+    let data = serde_json::to_vec(mf)?;
+    let mut cmd = Command::new(&hook.command);
+    cmd.args(&hook.args)
+        .env("SHIPCAT_HOOK_STAGE", format!("{:?}", hook.stage))
+        .env("SHIPCAT_REGION", &mf.region)
+        .stdin(Stdio::piped());
+    if let Some(t) = tfile {
+        cmd.env("SHIPCAT_TEMPLATE_FILE", t);
+    }
+    let mut child = cmd
+        .spawn()
+        .chain_err(|| format!("Failed to start hook {} ({})", hook.name, hook.command))?;
+    {
+        // stdin is closed when dropped
+        let mut stdin = child.stdin.take().expect("hook stdin is piped");
+        stdin.write_all(&data).await?;
+    }
+    let status = child.await?;
+    if !status.success() {
+        bail!("Hook {} failed for {} ({})", hook.name, mf.name, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::Manifest;
+    use shipcat_definitions::{ApplyHook, ApplyHookStage};
+    use shipcat_filebacked::fixtures::ConfigBuilder;
+
+    fn hook(name: &str, script: &str, optional: bool) -> ApplyHook {
+        ApplyHook {
+            name: name.into(),
+            stage: ApplyHookStage::PreApply,
+            command: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            optional,
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_run_for_stage() {
+        let mf = Manifest::test("fake-svc");
+        let mut conf = ConfigBuilder::new().build();
+
+        // manifest json is passed on stdin
+        conf.applyHooks = vec![hook("stdin", "grep -q '\"name\":\"fake-svc\"'", false)];
+        assert!(run(ApplyHookStage::PreApply, &mf, &conf, None).await.is_ok());
+
+        // failures propagate unless optional
+        conf.applyHooks = vec![hook("fail", "exit 1", false)];
+        assert!(run(ApplyHookStage::PreApply, &mf, &conf, None).await.is_err());
+        assert!(run(ApplyHookStage::PostRollout, &mf, &conf, None).await.is_ok());
+        conf.applyHooks = vec![hook("fail", "exit 1", true)];
+        assert!(run(ApplyHookStage::PreApply, &mf, &conf, None).await.is_ok());
+    }
+}
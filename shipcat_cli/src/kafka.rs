@@ -0,0 +1,354 @@
+use kube::api::{Api, ListParams};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use super::{kubeapi, ErrorKind, Result};
+use shipcat_definitions::{
+    strimzi::{KafkaTopic, KafkaUser},
+    structs::{
+        kafkaresources::{AclDefinition, KafkaTopics},
+        KafkaResources,
+    },
+    Config, Region,
+};
+
+/// A difference between the kafka resources declared in manifests and those in the cluster
+#[derive(Debug, PartialEq)]
+pub enum KafkaDrift {
+    /// Declared by a service, but not in the cluster
+    MissingTopic { service: String, topic: String },
+    /// A partition count, replica count or config value that differs from the declaration
+    TopicMismatch {
+        service: String,
+        topic: String,
+        field: String,
+        declared: Option<String>,
+        live: Option<String>,
+    },
+    /// In the cluster, but not declared by any manifest
+    OrphanedTopic { topic: String },
+    /// Declared by a service, but not in the cluster
+    MissingUser { service: String, user: String },
+    /// ACLs of a user that are only declared, or only live
+    AclMismatch {
+        service: String,
+        user: String,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    /// In the cluster, but not declared by any manifest
+    OrphanedUser { user: String },
+}
+
+impl fmt::Display for KafkaDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cell = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".into());
+        match self {
+            KafkaDrift::MissingTopic { service, topic } => {
+                write!(f, "{}: topic {} is missing", service, topic)
+            }
+            KafkaDrift::TopicMismatch {
+                service,
+                topic,
+                field,
+                declared,
+                live,
+            } => write!(
+                f,
+                "{}: topic {} has {} {} (declared {})",
+                service,
+                topic,
+                field,
+                cell(live),
+                cell(declared)
+            ),
+            KafkaDrift::OrphanedTopic { topic } => write!(f, "topic {} is not in any manifest", topic),
+            KafkaDrift::MissingUser { service, user } => write!(f, "{}: user {} is missing", service, user),
+            KafkaDrift::AclMismatch {
+                service,
+                user,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "{}: user {} acls differ (missing: {:?}, unexpected: {:?})",
+                service, user, missing, unexpected
+            ),
+            KafkaDrift::OrphanedUser { user } => write!(f, "user {} is not in any manifest", user),
+        }
+    }
+}
+
+/// Topics internal to kafka or strimzi that no manifest declares
+fn is_internal_topic(name: &str) -> bool {
+    name.starts_with("__")
+}
+
+/// A declared ACL in the notation strimzi uses, with strimzi's defaults
+fn declared_acl(acl: &AclDefinition) -> String {
+    let enum_str = |v: serde_json::Value| v.as_str().map(String::from).unwrap_or_default();
+    let resource_type = enum_str(serde_json::to_value(&acl.resource_type).unwrap_or_default());
+    let pattern = enum_str(serde_json::to_value(&acl.pattern_type).unwrap_or_default());
+    let operation = enum_str(serde_json::to_value(&acl.operation).unwrap_or_default());
+    let pattern = if pattern.is_empty() {
+        "literal".into()
+    } else {
+        pattern
+    };
+    format!(
+        "{} {}:{} ({}) from {}",
+        operation, resource_type, acl.resource_name, pattern, acl.host
+    )
+}
+
+/// ACLs of a live user in the same notation as `declared_acl`
+fn live_acls(user: &KafkaUser) -> BTreeSet<String> {
+    let acls = user
+        .spec
+        .authorization
+        .as_ref()
+        .map(|a| a.acls.clone())
+        .unwrap_or_default();
+    acls.into_iter()
+        .map(|a| {
+            format!(
+                "{} {}:{} ({}) from {}",
+                a.operation,
+                a.resource.resourceType,
+                a.resource.name.unwrap_or_default(),
+                a.resource.patternType.unwrap_or_else(|| "literal".into()),
+                a.host.unwrap_or_else(|| "*".into())
+            )
+        })
+        .collect()
+}
+
+fn topic_drift(service: &str, declared: &KafkaTopics, live: &KafkaTopic) -> Vec<KafkaDrift> {
+    let mut res = vec![];
+    let mut fields = BTreeMap::new();
+    fields.insert(
+        "partitions".to_string(),
+        (
+            Some(declared.partitions.to_string()),
+            Some(live.spec.partitions.to_string()),
+        ),
+    );
+    fields.insert(
+        "replicas".to_string(),
+        (
+            Some(declared.replicas.to_string()),
+            Some(live.spec.replicas.to_string()),
+        ),
+    );
+    let live_config = live
+        .spec
+        .config
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                v.as_str().map(String::from).unwrap_or_else(|| v.to_string()),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    for k in declared.config.keys().chain(live_config.keys()) {
+        let values = (declared.config.get(k).cloned(), live_config.get(k).cloned());
+        fields.insert(format!("config.{}", k), values);
+    }
+    for (field, (d, l)) in fields {
+        if d != l {
+            res.push(KafkaDrift::TopicMismatch {
+                service: service.to_string(),
+                topic: declared.name.clone(),
+                field,
+                declared: d,
+                live: l,
+            });
+        }
+    }
+    res
+}
+
+/// Compare the kafka resources declared by services against the strimzi resources in the cluster
+///
+/// Topics of `eventStreams` are managed elsewhere, so they only count as declared.
+pub fn kafka_drift(
+    declared: &BTreeMap<String, KafkaResources>,
+    event_streams: &BTreeSet<String>,
+    topics: &[KafkaTopic],
+    users: &[KafkaUser],
+) -> Vec<KafkaDrift> {
+    let mut res = vec![];
+    let live_topics = topics
+        .iter()
+        .map(|t| (t.topic_name(), t))
+        .filter(|(name, _)| !is_internal_topic(name))
+        .collect::<BTreeMap<_, _>>();
+    let live_users = users
+        .iter()
+        .filter_map(|u| u.metadata.name.clone().map(|n| (n, u)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut known_topics = event_streams.clone();
+    let mut known_users = BTreeSet::new();
+    for (service, kr) in declared {
+        for topic in &kr.topics {
+            known_topics.insert(topic.name.clone());
+            match live_topics.get(&topic.name) {
+                Some(live) => res.extend(topic_drift(service, topic, live)),
+                None => res.push(KafkaDrift::MissingTopic {
+                    service: service.clone(),
+                    topic: topic.name.clone(),
+                }),
+            }
+        }
+        for user in &kr.users {
+            known_users.insert(user.name.clone());
+            let live = match live_users.get(&user.name) {
+                Some(u) => live_acls(u),
+                None => {
+                    res.push(KafkaDrift::MissingUser {
+                        service: service.clone(),
+                        user: user.name.clone(),
+                    });
+                    continue;
+                }
+            };
+            let acls = user.acls.iter().map(declared_acl).collect::<BTreeSet<_>>();
+            if acls != live {
+                res.push(KafkaDrift::AclMismatch {
+                    service: service.clone(),
+                    user: user.name.clone(),
+                    missing: acls.difference(&live).cloned().collect(),
+                    unexpected: live.difference(&acls).cloned().collect(),
+                });
+            }
+        }
+    }
+    for topic in live_topics.keys().filter(|t| !known_topics.contains(*t)) {
+        res.push(KafkaDrift::OrphanedTopic { topic: topic.clone() });
+    }
+    for user in live_users.keys().filter(|u| !known_users.contains(*u)) {
+        res.push(KafkaDrift::OrphanedUser { user: user.clone() });
+    }
+    res
+}
+
+/// Report drift between `kafkaResources` in manifests and the strimzi resources in a region
+///
+/// Prints one line per difference, and returns whether everything matched.
+pub async fn diff(conf: &Config, reg: &Region) -> Result<bool> {
+    let mut declared = BTreeMap::new();
+    let mut event_streams = BTreeSet::new();
+    for svc in shipcat_filebacked::available(conf, reg).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
+        event_streams.extend(mf.eventStreams.into_iter().map(|es| es.name));
+        if let Some(kr) = mf.kafkaResources {
+            declared.insert(mf.name, kr);
+        }
+    }
+
+    let client = kubeapi::make_client().await?;
+    let lp = ListParams::default();
+    let topic_api: Api<KafkaTopic> = Api::namespaced(client.clone(), &reg.namespace);
+    let topics = topic_api.list(&lp).await.map_err(ErrorKind::KubeError)?.items;
+    let user_api: Api<KafkaUser> = Api::namespaced(client, &reg.namespace);
+    let users = user_api.list(&lp).await.map_err(ErrorKind::KubeError)?.items;
+
+    let drift = kafka_drift(&declared, &event_streams, &topics, &users);
+    for d in &drift {
+        println!("{}", d);
+    }
+    if drift.is_empty() {
+        info!("kafka resources in {} match the manifests", reg.name);
+    }
+    Ok(drift.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kafka_drift, KafkaDrift};
+    use shipcat_definitions::{
+        strimzi::{KafkaTopic, KafkaTopicSpec, KafkaUser},
+        structs::KafkaResources,
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn kafka_topic_and_user_drift() {
+        let kr: KafkaResources = serde_yaml::from_str(
+            "topics:
+- name: orders
+  partitions: 3
+  replicas: 3
+  config:
+    retention.ms: \"604800000\"
+- name: payments
+  partitions: 1
+  replicas: 3
+users:
+- name: orders-writer
+  acls:
+  - resourceName: orders
+    resourceType: topic
+    operation: Write",
+        )
+        .unwrap();
+        let mut declared = BTreeMap::new();
+        declared.insert("fake-ask".to_string(), kr);
+        let event_streams = vec!["events".to_string()].into_iter().collect::<BTreeSet<_>>();
+
+        let mut config = BTreeMap::new();
+        config.insert("retention.ms".to_string(), serde_json::json!(604800000));
+        let spec = |partitions, config| KafkaTopicSpec {
+            topicName: None,
+            partitions,
+            replicas: 3,
+            config,
+        };
+        let topics = vec![
+            KafkaTopic::new("orders", spec(1, config)),
+            KafkaTopic::new("events", spec(1, BTreeMap::new())),
+            KafkaTopic::new("stale", spec(1, BTreeMap::new())),
+            KafkaTopic::new("consumer-offsets---84e7a678", KafkaTopicSpec {
+                topicName: Some("__consumer_offsets".into()),
+                ..spec(50, BTreeMap::new())
+            }),
+        ];
+        let users: Vec<KafkaUser> = vec![serde_json::from_value(serde_json::json!({
+            "apiVersion": "kafka.strimzi.io/v1beta1",
+            "kind": "KafkaUser",
+            "metadata": { "name": "orders-writer" },
+            "spec": { "authorization": { "type": "simple", "acls": [
+                { "resource": { "type": "topic", "name": "orders", "patternType": "literal" }, "operation": "Read" }
+            ]}}
+        }))
+        .unwrap()];
+
+        let drift = kafka_drift(&declared, &event_streams, &topics, &users);
+        assert_eq!(drift, vec![
+            KafkaDrift::TopicMismatch {
+                service: "fake-ask".into(),
+                topic: "orders".into(),
+                field: "partitions".into(),
+                declared: Some("3".into()),
+                live: Some("1".into()),
+            },
+            KafkaDrift::MissingTopic {
+                service: "fake-ask".into(),
+                topic: "payments".into(),
+            },
+            KafkaDrift::AclMismatch {
+                service: "fake-ask".into(),
+                user: "orders-writer".into(),
+                missing: vec!["Write topic:orders (literal) from *".into()],
+                unexpected: vec!["Read topic:orders (literal) from *".into()],
+            },
+            KafkaDrift::OrphanedTopic {
+                topic: "stale".into()
+            },
+        ]);
+    }
+}
@@ -5,11 +5,17 @@ use std::{
 
 use super::{
     structs::{
-        kongfig::{kongfig_apis, kongfig_consumers, Api, Certificate, Consumer, Plugin, Upstream},
-        Kong,
+        istio::{istio_gateway, istio_virtual_services},
+        kongfig::{
+            kongfig_apis, kongfig_consumers, kongfig_upstreams, Api, Certificate, Consumer, Plugin, Upstream,
+        },
+        Gateway, Kong, VirtualService,
     },
     Config, KongConfig, Region, Result,
 };
+use crate::kubeapi;
+use kube::api::Api as KubeApi;
+use shipcat_definitions::{status::CanaryStatus, ShipcatManifest};
 
 /// KongOutput matches the format expected by the Kong Configurator script
 #[derive(Serialize)]
@@ -35,15 +41,41 @@ impl KongfigOutput {
         KongfigOutput {
             host: data.kong.clone().config_url,
             headers: vec![],
+            upstreams: kongfig_upstreams(&data.apis, region),
             apis: kongfig_apis(data.apis, data.kong.clone(), region),
             consumers: kongfig_consumers(data.kong),
             plugins: vec![],
-            upstreams: vec![],
             certificates: vec![],
         }
     }
 }
 
+/// KongOutput as Istio ingress resources
+///
+/// One Gateway for the region, and a VirtualService per API.
+pub struct IstioOutput {
+    pub gateway: Gateway,
+    pub virtual_services: Vec<VirtualService>,
+}
+
+impl IstioOutput {
+    pub fn new(data: KongOutput, region: &Region) -> Self {
+        IstioOutput {
+            gateway: istio_gateway(&data.apis, region),
+            virtual_services: istio_virtual_services(data.apis, region),
+        }
+    }
+
+    /// Multi-document yaml stream for kubectl
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut docs = vec![serde_yaml::to_string(&self.gateway)?];
+        for vs in &self.virtual_services {
+            docs.push(serde_yaml::to_string(vs)?);
+        }
+        Ok(docs.join("\n"))
+    }
+}
+
 /// KongOutput in CRD form
 #[derive(Serialize)]
 struct KongCrdOutput {
@@ -69,13 +101,56 @@ impl KongCrdOutput {
     }
 }
 
-pub async fn generate_kong_output(conf: &Config, region: &Region) -> Result<KongOutput> {
+/// Find the active canaries of kong fronted services from their shipcatmanifest status
+///
+/// Services that have not been applied yet are ignored,
+/// and without access to the cluster the config is rendered without canaries.
+pub async fn active_canaries(conf: &Config, region: &Region) -> Result<BTreeMap<String, CanaryStatus>> {
+    let mut canaries = BTreeMap::new();
+    let client = match kubeapi::make_client().await {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Rendering kong config without canaries, no cluster access: {}", e);
+            return Ok(canaries);
+        }
+    };
+    let api: KubeApi<ShipcatManifest> = KubeApi::namespaced(client, &region.namespace);
+    for mf in shipcat_filebacked::available(conf, region).await? {
+        if mf.kong_apis.is_empty() {
+            continue;
+        }
+        let svc = mf.base.name;
+        match api.get(&svc).await {
+            Ok(crd) => {
+                if let Some(c) = crd.status.and_then(|s| s.canary) {
+                    debug!("{} has an active canary: {:?}", svc, c);
+                    canaries.insert(svc, c);
+                }
+            }
+            Err(e) => debug!("Ignoring canary status for {}: {}", svc, e),
+        }
+    }
+    Ok(canaries)
+}
+
+pub async fn generate_kong_output(
+    conf: &Config,
+    region: &Region,
+    canaries: &BTreeMap<String, CanaryStatus>,
+) -> Result<KongOutput> {
     let mut apis = BTreeMap::new();
     if let Some(kong) = &region.kong {
         // Generate list of APIs to feed to Kong
         for mf in shipcat_filebacked::available(conf, region).await? {
             debug!("Scanning service {:?}", mf);
-            for k in mf.kong_apis {
+            for mut k in mf.kong_apis {
+                k.canary = canaries.get(&mf.base.name).cloned();
+                if let Some(clash) = apis.insert(k.name.clone(), k) {
+                    bail!("A Kong API named {:?} is already defined", clash.name);
+                }
+            }
+            // workers are exposed through their own services, without canaries
+            for (_, k) in mf.worker_kong_apis {
                 if let Some(clash) = apis.insert(k.name.clone(), k) {
                     bail!("A Kong API named {:?} is already defined", clash.name);
                 }
@@ -103,11 +178,14 @@ pub enum KongOutputMode {
     Crd,
     /// Kongfig raw yaml
     Kongfig,
+    /// Istio Gateway and VirtualServices
+    Istio,
 }
 
 /// Generate Kong config from a filled in global config
 pub async fn output(conf: &Config, region: &Region, mode: KongOutputMode) -> Result<()> {
-    let data = generate_kong_output(conf, &region).await?;
+    let canaries = active_canaries(conf, region).await?;
+    let data = generate_kong_output(conf, region, &canaries).await?;
     let output = match mode {
         KongOutputMode::Crd => {
             let res = KongCrdOutput::new(&region.name, data);
@@ -117,6 +195,7 @@ pub async fn output(conf: &Config, region: &Region, mode: KongOutputMode) -> Res
             let res = KongfigOutput::new(data, region);
             serde_yaml::to_string(&res)?
         }
+        KongOutputMode::Istio => IstioOutput::new(data, region).to_yaml()?,
     };
     let _ = io::stdout().write(format!("{}\n", output).as_bytes());
     Ok(())
@@ -1,21 +1,31 @@
 use crate::{ErrorKind, Manifest, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use futures_timer::Delay;
 use k8s_openapi::api::{
-    apps::v1::{Deployment, ReplicaSet, StatefulSet},
-    core::v1::Pod,
+    apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    core::v1::{Event, Pod},
 };
 use kube::{
-    api::{Api, DeleteParams, ListParams, LogParams, Object, ObjectList, PatchParams, Resource},
+    api::{
+        Api, DeleteParams, ListParams, LogParams, Meta, Object, ObjectList, PatchParams, Resource, WatchEvent,
+    },
     client::APIClient,
+    runtime::Informer,
 };
+use serde::de::DeserializeOwned;
 use shipcat_definitions::{
     manifest::ShipcatManifest,
     status::{Applier, ManifestStatus},
+    structs::Container,
 };
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 /// Client creator
 ///
 /// TODO: embed inside shipcat::apply when needed for other things
-async fn make_client() -> Result<APIClient> {
+pub(crate) async fn make_client() -> Result<APIClient> {
     let config = if let Ok(cfg) = kube::config::incluster_config() {
         cfg
     } else {
@@ -25,10 +35,55 @@ async fn make_client() -> Result<APIClient> {
     };
     Ok(kube::client::APIClient::new(config))
 }
+
+/// Send the names of objects changed in an informer's watches to `tx`
+///
+/// Keeps polling in the background until the receiving end is dropped.
+fn forward_changes<K>(informer: Informer<K>, mut tx: mpsc::Sender<String>)
+where
+    K: Clone + DeserializeOwned + Meta + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let events = match informer.poll().await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to watch {}: {}", std::any::type_name::<K>(), e);
+                    Delay::new(Duration::from_secs(10)).await;
+                    continue;
+                }
+            };
+            futures::pin_mut!(events);
+            while let Some(ev) = events.next().await {
+                let name = match ev {
+                    Ok(WatchEvent::Added(o)) | Ok(WatchEvent::Modified(o)) | Ok(WatchEvent::Deleted(o)) => {
+                        Meta::name(&o)
+                    }
+                    Ok(WatchEvent::Error(e)) => {
+                        debug!("Watch error for {}: {:?}", std::any::type_name::<K>(), e);
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("Watch error for {}: {}", std::any::type_name::<K>(), e);
+                        continue;
+                    }
+                };
+                if tx.send(name).await.is_err() {
+                    return; // nobody is watching anymore
+                }
+            }
+        }
+    });
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MinimalManifest {
     pub name: String,
     pub version: String,
+    #[serde(default)]
+    pub sidecars: Vec<Container>,
+    #[serde(default)]
+    pub initContainers: Vec<Container>,
 }
 type MinimalMfCrd = Object<MinimalManifest, ManifestStatus>;
 
@@ -40,6 +95,7 @@ pub struct ShipKube {
     api: Api<ShipcatManifest>,
     name: String,
     namespace: String,
+    dry_run: bool,
 }
 
 /// Entry points for shipcat::apply, and shipcat::status
@@ -57,6 +113,7 @@ impl ShipKube {
             api,
             client,
             mfs,
+            dry_run: false,
         })
     }
 
@@ -64,6 +121,12 @@ impl ShipKube {
         Self::new_within(&mf.name, &mf.namespace).await
     }
 
+    /// Send crd changes as server-side dry runs that are validated but not persisted
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Apply a Manifest (e.g. it's CRD wrapper)
     pub async fn apply(&self, mf: Manifest) -> Result<bool> {
         assert!(mf.version.is_some()); // ensure crd is in right state w/o secrets
@@ -75,7 +138,7 @@ impl ShipKube {
         // TODO: use server side apply in 1.15
         // for now, shell out to kubectl
         use crate::kubectl;
-        kubectl::apply_resource(&svc, mfcrd, &ns).await
+        kubectl::apply_resource(&svc, mfcrd, &ns, self.dry_run).await
     }
 
     /// Full CRD fetcher
@@ -95,6 +158,14 @@ impl ShipKube {
         Ok(o)
     }
 
+    /// Image pull time recorded by the last tracked rollout
+    ///
+    /// Best effort; a missing status just means no history.
+    pub async fn last_image_pull(&self) -> Option<u32> {
+        let o = self.get_minimal().await.ok()?;
+        o.status?.summary?.last_image_pull_seconds
+    }
+
     /// Minimal CRD deleter
     pub async fn delete(&self) -> Result<()> {
         let dp = DeleteParams::default();
@@ -108,7 +179,10 @@ impl ShipKube {
 
     // helper to send a merge patch
     pub async fn patch(&self, data: &serde_json::Value) -> Result<()> {
-        let pp = PatchParams::default();
+        let pp = PatchParams {
+            dry_run: self.dry_run,
+            ..PatchParams::default()
+        };
         // Run this patch with a smaller deserialization surface via kube::Resource
         // kube::Api would force ShipcatManifest fully valid here
         // and this would prevent status updates during schema changes.
@@ -125,6 +199,35 @@ impl ShipKube {
         Ok(())
     }
 
+    /// Watch the pods, replicasets and crd of the service
+    ///
+    /// Receives the name of every changed object until the receiver is dropped.
+    pub fn watch_changes(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(64);
+        let by_app = ListParams {
+            label_selector: Some(format!("app={}", self.name)),
+            ..Default::default()
+        };
+        let by_name = ListParams {
+            field_selector: Some(format!("metadata.name={}", self.name)),
+            ..Default::default()
+        };
+        let ns = &self.namespace;
+        let pods = Resource::namespaced::<Pod>(ns);
+        let replicasets = Resource::namespaced::<ReplicaSet>(ns);
+        forward_changes::<Pod>(
+            Informer::new(self.client.clone(), by_app.clone(), pods),
+            tx.clone(),
+        );
+        forward_changes::<ReplicaSet>(
+            Informer::new(self.client.clone(), by_app, replicasets),
+            tx.clone(),
+        );
+        let mfs = self.mfs.clone();
+        forward_changes::<ShipcatManifest>(Informer::new(self.client.clone(), by_name, mfs), tx);
+        rx
+    }
+
     // helper to get pod data
     pub async fn get_pods(&self) -> Result<ObjectList<Pod>> {
         let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
@@ -159,6 +262,17 @@ impl ShipKube {
         Ok(logs)
     }
 
+    // helper to get events of a pod
+    pub async fn get_pod_events(&self, podname: &str) -> Result<ObjectList<Event>> {
+        let api: Api<Event> = Api::namespaced(self.client.clone(), &self.namespace);
+        let lp = ListParams {
+            field_selector: Some(format!("involvedObject.kind=Pod,involvedObject.name={}", podname)),
+            ..Default::default()
+        };
+        let events = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
+        Ok(events)
+    }
+
     // helper to get rs data
     pub async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>> {
         let api: Api<ReplicaSet> = Api::namespaced(self.client.clone(), &self.namespace);
@@ -247,4 +361,71 @@ impl ShipKube {
         let ssets = api.get(&self.name).await.map_err(ErrorKind::KubeError)?;
         Ok(ssets)
     }
+
+    // helper to get daemonset data
+    pub async fn get_daemonset(&self) -> Result<DaemonSet> {
+        let api: Api<DaemonSet> = Api::namespaced(self.client.clone(), &self.namespace);
+        let ds = api.get(&self.name).await.map_err(ErrorKind::KubeError)?;
+        Ok(ds)
+    }
+}
+
+/// The workload queries used to track and debug rollouts
+///
+/// Implemented by `ShipKube`, and by scripted scenarios in `shipcat::replay`.
+#[async_trait]
+pub trait WorkloadApi: Send + Sync {
+    async fn get_pods(&self) -> Result<ObjectList<Pod>>;
+    async fn get_pods_by_template_hash(&self, hash: &str) -> Result<ObjectList<Pod>>;
+    async fn get_pod_logs(&self, podname: &str) -> Result<String>;
+    async fn get_pod_events(&self, podname: &str) -> Result<ObjectList<Event>>;
+    async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>>;
+    async fn get_rs_by_template_hash(&self, hash: &str) -> Result<Option<ReplicaSet>>;
+    async fn get_rs_from_deploy(&self) -> Result<Option<ReplicaSet>>;
+    async fn get_deploy(&self) -> Result<Deployment>;
+    async fn get_statefulset(&self) -> Result<StatefulSet>;
+    async fn get_daemonset(&self) -> Result<DaemonSet>;
+}
+
+#[async_trait]
+impl WorkloadApi for ShipKube {
+    async fn get_pods(&self) -> Result<ObjectList<Pod>> {
+        ShipKube::get_pods(self).await
+    }
+
+    async fn get_pods_by_template_hash(&self, hash: &str) -> Result<ObjectList<Pod>> {
+        ShipKube::get_pods_by_template_hash(self, hash).await
+    }
+
+    async fn get_pod_logs(&self, podname: &str) -> Result<String> {
+        ShipKube::get_pod_logs(self, podname).await
+    }
+
+    async fn get_pod_events(&self, podname: &str) -> Result<ObjectList<Event>> {
+        ShipKube::get_pod_events(self, podname).await
+    }
+
+    async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>> {
+        ShipKube::get_rs(self).await
+    }
+
+    async fn get_rs_by_template_hash(&self, hash: &str) -> Result<Option<ReplicaSet>> {
+        ShipKube::get_rs_by_template_hash(self, hash).await
+    }
+
+    async fn get_rs_from_deploy(&self) -> Result<Option<ReplicaSet>> {
+        ShipKube::get_rs_from_deploy(self).await
+    }
+
+    async fn get_deploy(&self) -> Result<Deployment> {
+        ShipKube::get_deploy(self).await
+    }
+
+    async fn get_statefulset(&self) -> Result<StatefulSet> {
+        ShipKube::get_statefulset(self).await
+    }
+
+    async fn get_daemonset(&self) -> Result<DaemonSet> {
+        ShipKube::get_daemonset(self).await
+    }
 }
@@ -1,11 +1,12 @@
-use super::{ErrorKind, Manifest, Result};
+use super::{ErrorKind, Manifest, Region, Result};
+use crate::exec::executor;
 use kube::{
     api::{Api, PostParams},
     client::APIClient,
     config::load_kube_config,
 };
 use serde::Serialize;
-use tokio::process::Command;
+use shipcat_definitions::Environment;
 
 use k8s_openapi::api::authorization::v1::{
     ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
@@ -16,30 +17,31 @@ struct AccessReviewRequest {
     verb: String,
     resource: String,
     subresource: Option<String>,
+    group: Option<String>,
 }
 
 pub async fn kexec(args: Vec<String>) -> Result<()> {
     debug!("kubectl {}", args.join(" "));
-    let s = Command::new("kubectl").args(&args).status().await?;
-    if !s.success() {
-        bail!("Subprocess failure from kubectl: {}", s.code().unwrap_or(1001))
+    let s = executor().status("kubectl", &args).await?;
+    if !s.success {
+        bail!("Subprocess failure from kubectl: {}", s.code.unwrap_or(1001))
     }
     Ok(())
 }
 async fn kout(args: Vec<String>) -> Result<(String, bool)> {
     debug!("kubectl {}", args.join(" "));
-    let s = Command::new("kubectl").args(&args).output().await?;
-    let out: String = String::from_utf8_lossy(&s.stdout).into();
-    let err: String = String::from_utf8_lossy(&s.stderr).to_string().trim().into();
+    let s = executor().output("kubectl", &args).await?;
+    let out = s.stdout;
+    let err = s.stderr.trim();
     if !err.is_empty() {
         warn!("kubectl {} stderr: {}", args.join(" "), err);
     }
     // kubectl keeps returning opening and closing apostrophes - strip them:
     if out.len() > 2 && out.starts_with('\'') {
         let res = out.split('\'').collect::<Vec<_>>()[1];
-        return Ok((res.trim().into(), s.status.success()));
+        return Ok((res.trim().into(), s.success));
     }
-    Ok((out, s.status.success()))
+    Ok((out, s.success))
 }
 // fn get_kube_permissions(namespace: String) -> Result<Vec<ResourceRule>> {
 // let config = load_kube_config().expect("config failed to load");
@@ -69,7 +71,7 @@ async fn kout(args: Vec<String>) -> Result<(String, bool)> {
 // Ok(o.status.expect("expected rules").resource_rules)
 // }
 async fn kani(rr: AccessReviewRequest) -> Result<bool> {
-    let config = load_kube_config().await.expect("config failed to load");
+    let config = load_kube_config().await.map_err(ErrorKind::KubeError)?;
     let client = APIClient::new(config);
 
     let ssrr: Api<SelfSubjectAccessReview> = Api::all(client);
@@ -82,7 +84,7 @@ async fn kani(rr: AccessReviewRequest) -> Result<bool> {
             verb: Some(rr.verb),
             resource: Some(rr.resource),
             subresource: rr.subresource,
-            group: None,
+            group: rr.group,
             name: None,
             version: None,
         }),
@@ -100,6 +102,20 @@ async fn kani(rr: AccessReviewRequest) -> Result<bool> {
     Ok(status.allowed)
 }
 
+/// Check if the current kube user can perform an action on a resource
+///
+/// Resources outside the core api group must pass their `group`.
+pub async fn can_i(namespace: &str, verb: &str, resource: &str, group: Option<&str>) -> Result<bool> {
+    kani(AccessReviewRequest {
+        namespace: namespace.into(),
+        verb: verb.into(),
+        resource: resource.into(),
+        subresource: None,
+        group: group.map(String::from),
+    })
+    .await
+}
+
 /// CLI way to resolve kube context
 ///
 /// Should only be used from main.
@@ -140,6 +156,121 @@ pub async fn use_context(context: &str) -> Result<String> {
     Ok(res)
 }
 
+/// kubectl verbs that mutate cluster state or reach into running containers
+const DESTRUCTIVE_VERBS: [&str; 22] = [
+    "annotate",
+    "apply",
+    "attach",
+    "autoscale",
+    "cordon",
+    "cp",
+    "create",
+    "debug",
+    "delete",
+    "drain",
+    "edit",
+    "exec",
+    "expose",
+    "label",
+    "patch",
+    "port-forward",
+    "replace",
+    "run",
+    "scale",
+    "set",
+    "taint",
+    "uncordon",
+];
+
+/// kubectl rollout subverbs that mutate cluster state
+const DESTRUCTIVE_ROLLOUT_VERBS: [&str; 4] = ["pause", "restart", "resume", "undo"];
+
+/// Global kubectl flags that would escape the region being wrapped
+const CONTEXT_FLAGS: [&str; 4] = ["--context", "--cluster", "--kubeconfig", "--user"];
+
+/// Global kubectl flags taking a separate value argument
+const VALUE_FLAGS: [&str; 10] = [
+    "-n",
+    "--namespace",
+    "-l",
+    "--selector",
+    "-o",
+    "--output",
+    "-f",
+    "--filename",
+    "-c",
+    "--container",
+];
+
+/// Find the kubectl verb and its subverb in a list of arguments
+fn kubectl_verbs(args: &[String]) -> Vec<&str> {
+    let mut res = vec![];
+    let mut iter = args.iter();
+    while let Some(a) = iter.next() {
+        if VALUE_FLAGS.contains(&a.as_str()) {
+            iter.next();
+        } else if !a.starts_with('-') {
+            res.push(a.as_str());
+            if res.len() == 2 {
+                break;
+            }
+        }
+    }
+    res
+}
+
+/// The mutating kubectl verb (with subverb for rollouts) in a list of arguments
+fn destructive_verb(args: &[String]) -> Option<String> {
+    match kubectl_verbs(args).as_slice() {
+        ["rollout", sub, ..] if DESTRUCTIVE_ROLLOUT_VERBS.contains(sub) => Some(format!("rollout {}", sub)),
+        [verb, ..] if DESTRUCTIVE_VERBS.contains(verb) => Some(verb.to_string()),
+        _ => None,
+    }
+}
+
+/// Arguments for kubectl pinned to a context and namespace
+///
+/// Refuses to mutate a `prod` context unless `allow_prod` is set.
+fn wrapped_args(
+    context: &str,
+    namespace: &str,
+    prod: bool,
+    args: Vec<String>,
+    allow_prod: bool,
+) -> Result<Vec<String>> {
+    if let Some(f) = args.iter().find(|a| {
+        CONTEXT_FLAGS
+            .iter()
+            .any(|f| a == f || a.starts_with(&format!("{}=", f)))
+    }) {
+        bail!(
+            "{} cannot be overridden in shipcat kubectl - use -r to pick a region",
+            f
+        );
+    }
+    if let Some(verb) = destructive_verb(&args) {
+        if prod && !allow_prod {
+            bail!(
+                "Refusing to kubectl {} in prod context {} without --allow-prod",
+                verb,
+                context
+            );
+        }
+    }
+    let mut res = vec![
+        format!("--context={}", context),
+        format!("--namespace={}", namespace),
+    ];
+    res.extend(args);
+    Ok(res)
+}
+
+/// Run kubectl against the context and namespace of a region
+pub async fn wrap(context: &str, region: &Region, args: Vec<String>, allow_prod: bool) -> Result<()> {
+    let prod = region.environment == Environment::Prod;
+    kexec(wrapped_args(context, &region.namespace, prod, args, allow_prod)?).await
+}
+
 /// Shell into a pod associated with a workload
 pub async fn shell(mf: &Manifest, cmd: Option<Vec<&str>>) -> Result<()> {
     // TODO: kubectl auth can-i create pods/exec
@@ -185,6 +316,76 @@ pub async fn shell(mf: &Manifest, cmd: Option<Vec<&str>>) -> Result<()> {
     Ok(())
 }
 
+/// Run a shell script in a pod of a workload and capture its output
+pub async fn exec_script(mf: &Manifest, script: &str) -> Result<String> {
+    // kubectl exec -n=$ns deployment/$name -- sh -c $script
+    let args = vec![
+        "exec".into(),
+        format!("-n={}", mf.namespace),
+        format!("{}/{}", mf.workload.to_string(), mf.name),
+        "--".into(),
+        "sh".into(),
+        "-c".into(),
+        script.into(),
+    ];
+    match kout(args).await? {
+        (out, true) => Ok(out),
+        _ => bail!("Failed to exec into {}/{}", mf.workload.to_string(), mf.name),
+    }
+}
+
+/// Run a shell script in a throwaway pod and capture its output
+///
+/// The pod is removed once the script finishes, and killed after `deadline` seconds if set.
+pub async fn run_script(
+    ns: &str,
+    name: &str,
+    image: &str,
+    script: &str,
+    deadline: Option<u32>,
+) -> Result<String> {
+    // kubectl run $name -n=$ns --rm -i --restart=Never --image=$image --command -- sh -c $script
+    let mut args = vec![
+        "run".into(),
+        name.into(),
+        format!("-n={}", ns),
+        "--rm".into(),
+        "-i".into(),
+        "--quiet".into(),
+        "--restart=Never".into(),
+        format!("--image={}", image),
+    ];
+    if let Some(d) = deadline {
+        args.push(format!(
+            r#"--overrides={{"spec":{{"activeDeadlineSeconds":{}}}}}"#,
+            d
+        ));
+    }
+    args.extend(vec![
+        "--command".into(),
+        "--".into(),
+        "sh".into(),
+        "-c".into(),
+        script.into(),
+    ]);
+    match kout(args).await? {
+        (out, true) => Ok(out),
+        _ => bail!("Failed to run pod {} in {}", name, ns),
+    }
+}
+
+/// Roll the primary workload of a service back to its previous revision
+pub async fn rollout_undo(mf: &Manifest) -> Result<()> {
+    // kubectl rollout undo -n=$ns deployment/$name
+    let args = vec![
+        "rollout".into(),
+        "undo".into(),
+        format!("-n={}", mf.namespace),
+        format!("{}/{}", mf.workload.to_string(), mf.name),
+    ];
+    kexec(args).await
+}
+
 /// Port forward a port to localhost
 ///
 /// Useful because we have autocomplete on manifest names in shipcat
@@ -194,6 +395,7 @@ pub async fn port_forward(mf: &Manifest) -> Result<()> {
         verb: "create".into(),
         resource: "pods".into(),
         subresource: Some("portforward".into()),
+        group: None,
     };
 
     if !kani(access_request).await? {
@@ -246,10 +448,13 @@ pub async fn port_forward(mf: &Manifest) -> Result<()> {
 ///
 /// CRDs itself, Manifest and Config typically.
 /// Returns whether or not the file was configured
+///
+/// A dry run prints the diff against the cluster, and only applies server-side as a dry run.
 pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
     name: &str,
     data: K,
     ns: &str,
+    dry_run: bool,
 ) -> Result<bool> {
     use std::{
         fs::{self, File},
@@ -272,14 +477,22 @@ pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
         encoded
     );
 
+    if dry_run {
+        let (out, _, _) = diff(pth.clone(), ns).await?;
+        print!("{}", out);
+    }
+
     // Apply it using kubectl apply
     debug!("Applying {} CRD for {}", K::KIND, name);
-    let applyargs = vec![
+    let mut applyargs = vec![
         format!("-n={}", ns),
         "apply".into(),
         "-f".into(),
         datafile.clone(),
     ];
+    if dry_run {
+        applyargs.push("--dry-run=server".into());
+    }
     debug!("applying {} : {:?}", name, applyargs);
     let (out, status) = kout(applyargs.clone()).await?;
     print!("{}", out); // always print kube output from this
@@ -325,14 +538,15 @@ pub async fn diff(pth: PathBuf, ns: &str) -> Result<(String, String, bool)> {
     // need the error code here so re-implent - and discard stderr
     debug!("kubectl {}", args.join(" "));
 
-    let s = Command::new("kubectl").args(&args).output().await?;
-    let out: String = String::from_utf8_lossy(&s.stdout).into();
-    let err: String = String::from_utf8_lossy(&s.stderr).into();
-    trace!("out: {}, err: {}", out, err);
-    if err.contains("the dryRun alpha feature is disabled") {
-        bail!("kubectl diff is not supported in your cluster: {}", err.trim());
+    let s = executor().output("kubectl", &args).await?;
+    trace!("out: {}, err: {}", s.stdout, s.stderr);
+    if s.stderr.contains("the dryRun alpha feature is disabled") {
+        bail!(
+            "kubectl diff is not supported in your cluster: {}",
+            s.stderr.trim()
+        );
     }
-    Ok((out, err, s.status.success()))
+    Ok((s.stdout, s.stderr, s.success))
 }
 
 pub async fn find_redundant_manifests(ns: &str, svcs: &[String]) -> Result<Vec<String>> {
@@ -361,9 +575,35 @@ pub async fn get_running_version(svc: &str, ns: &str) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::{current_context, get_running_version};
+    use super::{current_context, get_running_version, wrapped_args};
     use dirs;
 
+    #[test]
+    fn kubectl_wrapped_args() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let res = wrapped_args("prod-uk", "apps", true, args(&["get", "pods"]), false).unwrap();
+        assert_eq!(
+            res,
+            args(&["--context=prod-uk", "--namespace=apps", "get", "pods"])
+        );
+
+        let delete = args(&["-n", "kube-system", "delete", "po", "x"]);
+        assert!(wrapped_args("prod-uk", "apps", true, delete.clone(), false).is_err());
+        assert!(wrapped_args("prod-uk", "apps", true, delete.clone(), true).is_ok());
+        assert!(wrapped_args("dev-uk", "apps", false, delete, false).is_ok());
+
+        let exec = args(&["exec", "-it", "x", "--", "sh"]);
+        assert!(wrapped_args("prod-uk", "apps", true, exec, false).is_err());
+        let undo = args(&["rollout", "undo", "deploy/x"]);
+        assert!(wrapped_args("prod-uk", "apps", true, undo, false).is_err());
+        let status = args(&["rollout", "status", "deploy/x"]);
+        assert!(wrapped_args("prod-uk", "apps", true, status, false).is_ok());
+
+        let escape = args(&["--context=dev-uk", "get", "po"]);
+        assert!(wrapped_args("prod-uk", "apps", true, escape, true).is_err());
+    }
+
     #[tokio::test]
     async fn validate_ctx() {
         let kubecfg = dirs::home_dir().unwrap().join(".kube").join("config");
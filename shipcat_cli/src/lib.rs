@@ -69,6 +69,10 @@ error_chain! {
             description("upgrade timed out")
             display("{} upgrade timed out waiting {}s for deployment(s) to come online", &svc, secs)
         }
+        SmokeTestFailure(svc: String, failed: usize) {
+            description("smoke tests failed")
+            display("{} of the smoke tests failed after rolling out {}", failed, &svc)
+        }
         SlackSendFailure(hook: String) {
             description("slack message send failed")
             display("Failed to send the slack message to '{}' ", &hook)
@@ -81,6 +85,14 @@ error_chain! {
             description("self-upgrade failed")
             display("self-upgrade: {}", s)
         }
+        GitopsFailure(repo: String) {
+            description("gitops write-back failed")
+            display("Failed to write back to github repo {}", repo)
+        }
+        PreflightFailure(check: String, reason: String) {
+            description("preflight check failed")
+            display("{} preflight check failed: {}", check, reason)
+        }
     }
 }
 
@@ -103,9 +115,15 @@ pub mod slack;
 /// Validation methods of manifests post merge
 pub mod validate;
 
+/// Policy rules from shipcat.conf evaluated against manifests
+pub mod lint;
+
 /// gdpr lister
 pub mod gdpr;
 
+/// External executables behind a swappable executor
+pub mod exec;
+
 /// A small CLI kubernetes interface
 pub mod kubectl;
 
@@ -115,12 +133,33 @@ pub mod kubeapi;
 /// A newer upgrade tracking interface
 pub mod track;
 
+/// Scripted rollout scenarios for the upgrade tracker
+pub mod replay;
+
 /// Status subcommand
 pub mod status;
 
+/// Squad scoped status, diff and apply
+pub mod team;
+
 /// Apply logic
 pub mod apply;
 
+/// External executables run during apply
+pub mod hooks;
+
+/// Smoke tests after rollouts
+pub mod smoketest;
+
+/// WASM validation plugins
+pub mod plugins;
+
+/// Ownership verification against an employee directory
+pub mod roster;
+
+/// Image verification against container registries
+pub mod registry;
+
 /// A small CLI helm template interface
 pub mod helm;
 
@@ -138,7 +177,10 @@ pub mod get;
 
 /// Top resource use
 pub mod top;
-pub use top::{OutputFormat, ResourceOrder};
+
+/// Manifest field usage
+pub mod stats;
+pub use top::{CostFormat, OutputFormat, ResourceOrder};
 
 /// Diffing module for values
 pub mod diff;
@@ -153,12 +195,78 @@ pub mod env;
 pub mod webhooks;
 pub use webhooks::UpgradeState;
 
+/// Grafana deploy annotations
+pub mod grafana;
+
+/// Deployment markers in grafana and sentry
+pub mod annotate;
+
+/// Drift between kafkaResources and the strimzi resources in a cluster
+pub mod kafka;
+
+/// Write-back of applied versions to the manifests repository
+pub mod gitops;
+
+/// Region export for pull based deployments
+pub mod export;
+
 /// Simple printers
 pub mod show;
 
+/// Comment preserving yaml edits
+pub mod edit;
+
+/// Region cloning from shipcat.conf
+pub mod clone;
+
+/// Sharding of cluster level mass operations across CI jobs
+pub mod shard;
+
 /// Cluster auth
 pub mod auth;
 
+/// Environment self-checks
+pub mod doctor;
+
+/// In-cluster reachability checks of infrastructure dependencies
+pub mod connectivity;
+
+/// Cluster health checks before mutating commands
+pub mod preflight;
+
+/// Region wide limits on concurrent rollouts
+pub mod queue;
+
+/// Version range checks of service dependencies
+pub mod depcheck;
+
+/// Ownership checks of mutating commands
+pub mod permissions;
+
+/// Change freezes enforced on applies
+pub mod freeze;
+
+/// Rotation of service secrets in vault
+pub mod rotate;
+
+/// Opt-in anonymous usage telemetry
+pub mod telemetry;
+
+/// PrometheusRule objects for prometheusAlerts
+pub mod prometheusrule;
+
+/// Egress policies for services with strictEgress
+pub mod egress;
+
+/// Ingress policies generated from dependencies
+pub mod networkpolicy;
+
+/// JSON schemas of manifests and config
+pub mod schema;
+
+/// In-cluster execution of applies requested through raftcat
+pub mod worker;
+
 /// Shipcat self upgrade
 #[cfg(feature = "self-upgrade")]
 pub mod upgrade;
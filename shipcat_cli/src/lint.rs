@@ -0,0 +1,227 @@
+use shipcat_definitions::{
+    structs::resources::ResourceRequirements, Environment, LintCheck, LintRule, LintSeverity,
+};
+use std::fmt;
+
+use super::{Config, Manifest, Region, Result};
+
+/// A lint rule violated by a manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: String,
+    pub severity: LintSeverity,
+    pub service: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            LintSeverity::Error => "error",
+            LintSeverity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}: {} [{}] {}",
+            self.service, severity, self.rule, self.message
+        )
+    }
+}
+
+/// The parts of a container the lint checks look at
+///
+/// The main container of a manifest is not a `Container`, so this covers both.
+struct Workload<'a> {
+    name: &'a str,
+    resources: Option<&'a ResourceRequirements<String>>,
+    http_port: Option<u32>,
+    probes: bool,
+    version: Option<&'a str>,
+}
+
+fn workloads(mf: &Manifest) -> Vec<Workload<'_>> {
+    let mut res = vec![Workload {
+        name: &mf.name,
+        resources: mf.resources.as_ref(),
+        http_port: mf.httpPort,
+        probes: mf.readinessProbe.is_some() && mf.livenessProbe.is_some(),
+        version: mf.version.as_deref(),
+    }];
+    for w in &mf.workers {
+        res.push(Workload {
+            name: &w.container.name,
+            resources: w.container.resources.as_ref(),
+            http_port: w.httpPort,
+            probes: w.container.readiness_probe.is_some() && w.container.liveness_probe.is_some(),
+            version: w.container.version.as_deref().or(mf.version.as_deref()),
+        });
+    }
+    for s in &mf.sidecars {
+        res.push(Workload {
+            name: &s.name,
+            resources: s.resources.as_ref(),
+            http_port: None,
+            probes: true,
+            version: s.version.as_deref(),
+        });
+    }
+    res
+}
+
+/// Messages for every violation of a check in a manifest
+fn violations(check: &LintCheck, mf: &Manifest) -> Result<Vec<String>> {
+    let mut res = vec![];
+    match check {
+        LintCheck::RequestsWithinLimits => {
+            for w in workloads(mf) {
+                let n = match w.resources {
+                    Some(r) => r.normalised()?,
+                    None => continue,
+                };
+                if n.requests.cpu > n.limits.cpu {
+                    res.push(format!("{} requests more cpu than its limit", w.name));
+                }
+                if n.requests.memory > n.limits.memory {
+                    res.push(format!("{} requests more memory than its limit", w.name));
+                }
+            }
+        }
+        LintCheck::HttpProbes => {
+            for w in workloads(mf) {
+                if w.http_port.is_some() && !w.probes {
+                    res.push(format!(
+                        "{} has an httpPort without readiness and liveness probes",
+                        w.name
+                    ));
+                }
+            }
+        }
+        LintCheck::NoLatestTag => {
+            for w in workloads(mf) {
+                if w.version == Some("latest") {
+                    res.push(format!("{} uses the latest tag", w.name));
+                }
+            }
+        }
+        LintCheck::LabelPattern { label, pattern } => match mf.labels.get(label) {
+            Some(v) if pattern.is_match(v) => {}
+            Some(v) => res.push(format!("label {}={} does not match {}", label, v, pattern)),
+            None => res.push(format!("label {} is not set", label)),
+        },
+    }
+    Ok(res)
+}
+
+/// Evaluate lint rules against a manifest
+///
+/// Rules restricted to other environments are skipped.
+pub fn findings(rules: &[LintRule], mf: &Manifest, env: &Environment) -> Result<Vec<Finding>> {
+    let mut res = vec![];
+    for r in rules {
+        if !r.environments.is_empty() && !r.environments.contains(env) {
+            continue;
+        }
+        for message in violations(&r.check, mf)? {
+            res.push(Finding {
+                rule: r.id.clone(),
+                severity: r.severity,
+                service: mf.name.clone(),
+                message,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// Lint services in a region against the `lintRules` in shipcat.conf
+///
+/// Lints every available service if none are given.
+/// Errors fail the lint, and so do warnings when they are denied.
+pub async fn lint(services: Vec<String>, conf: &Config, reg: &Region, deny_warnings: bool) -> Result<()> {
+    if conf.lintRules.is_empty() {
+        warn!("No lintRules defined in shipcat.conf");
+        return Ok(());
+    }
+    let services = if services.is_empty() {
+        shipcat_filebacked::available(conf, reg)
+            .await?
+            .into_iter()
+            .map(|mf| mf.base.name)
+            .collect()
+    } else {
+        services
+    };
+    let mut found = vec![];
+    for svc in services {
+        let mf = shipcat_filebacked::load_manifest(&svc, conf, reg)
+            .await?
+            .stub(reg)
+            .await?;
+        found.extend(findings(&conf.lintRules, &mf, &reg.environment)?);
+    }
+    for f in &found {
+        println!("{}", f);
+    }
+    let errors = found.iter().filter(|f| f.severity == LintSeverity::Error).count();
+    let warnings = found.len() - errors;
+    if errors > 0 || (deny_warnings && warnings > 0) {
+        bail!(
+            "Lint found {} errors and {} warnings in {}",
+            errors,
+            warnings,
+            reg.name
+        );
+    }
+    info!("Lint found {} warnings in {}", warnings, reg.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::findings;
+    use crate::Manifest;
+    use shipcat_definitions::{Environment, LintRule, LintSeverity};
+
+    #[test]
+    fn lint_rule_findings() {
+        let rules: Vec<LintRule> = serde_yaml::from_str(
+            r#"
+- id: cpu-within-limits
+  check: requestsWithinLimits
+  severity: error
+- id: http-probes
+  check: httpProbes
+  severity: warning
+- id: no-latest-in-prod
+  check: noLatestTag
+  severity: error
+  environments: [prod]
+- id: squad-label
+  check: labelPattern
+  label: squad
+  pattern: "^[a-z-]+$"
+  severity: warning
+"#,
+        )
+        .unwrap();
+        let mut mf = Manifest::test("fake-ask");
+        mf.version = Some("latest".into());
+        mf.httpPort = Some(8080);
+        mf.resources = Some(
+            serde_yaml::from_str("{requests: {cpu: 2, memory: 1Gi}, limits: {cpu: 1, memory: 1Gi}}").unwrap(),
+        );
+        mf.labels.insert("squad".into(), "Platform".into());
+
+        let found = findings(&rules, &mf, &Environment::Dev).unwrap();
+        let rules_hit = found.iter().map(|f| f.rule.as_str()).collect::<Vec<_>>();
+        assert_eq!(rules_hit, vec!["cpu-within-limits", "http-probes", "squad-label"]);
+        assert_eq!(found[0].severity, LintSeverity::Error);
+        assert_eq!(
+            found[1].to_string(),
+            "fake-ask: warning [http-probes] fake-ask has an httpPort without readiness and liveness probes"
+        );
+
+        let found = findings(&rules, &mf, &Environment::Prod).unwrap();
+        assert!(found.iter().any(|f| f.rule == "no-latest-in-prod"));
+    }
+}
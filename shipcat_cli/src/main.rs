@@ -3,7 +3,12 @@
 
 use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
 use shipcat::{kubeapi::ShipKube, *};
-use std::{process, str::FromStr};
+use std::{
+    path::Path,
+    process,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 fn print_error_debug(e: &Error) {
     use std::env;
@@ -44,6 +49,21 @@ fn build_cli() -> App<'static, 'static> {
             .long("strict-version-check")
             .global(true)
             .help("Fail on outdated versions"))
+        .arg(Arg::with_name("skip-sentry")
+            .long("skip-sentry")
+            .global(true)
+            .help("Leave FROM_SENTRY evars unset rather than provisioning sentry projects"))
+        .arg(Arg::with_name("audit-log")
+            .long("audit-log")
+            .takes_value(true)
+            .global(true)
+            .help("Append audit events to a json lines file, in addition to audit webhooks"))
+        .arg(Arg::with_name("break-glass")
+            .long("break-glass")
+            .takes_value(true)
+            .value_name("JUSTIFICATION")
+            .global(true)
+            .help("Skip ownership checks in an emergency, recording the justification in audit events"))
         .arg(Arg::with_name("region")
                 .short("r")
                 .long("region")
@@ -56,6 +76,21 @@ fn build_cli() -> App<'static, 'static> {
                 .required(true)
                 .help("Service name")))
 
+        .subcommand(SubCommand::with_name("replay")
+            .setting(AppSettings::Hidden)
+            .about("Replay a scripted rollout scenario through the rollout tracker")
+            .arg(Arg::with_name("tick")
+                .long("tick")
+                .takes_value(true)
+                .default_value("100")
+                .help("Milliseconds to wait per second of estimated rollout time"))
+            .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service name"))
+            .arg(Arg::with_name("scenario")
+                .required(true)
+                .help("Path to a scenario yaml file")))
+
         .subcommand(SubCommand::with_name("completions")
             .about("Generate autocompletion script for shipcat for the specified shell")
             .usage("This can be source using: $ source <(shipcat completions bash)")
@@ -64,6 +99,13 @@ fn build_cli() -> App<'static, 'static> {
                 .possible_values(&Shell::variants())
                 .help("Shell to generate completions for (zsh or bash)")))
 
+        .subcommand(SubCommand::with_name("schema")
+            .about("Print the JSON schema of manifests, overrides or shipcat.conf")
+            .arg(Arg::with_name("kind")
+                .required(true)
+                .possible_values(&["manifest", "overrides", "config"])
+                .help("File to describe")))
+
         .subcommand(SubCommand::with_name("shell")
             .about("Shell into pods for a service described in a manifest")
             .arg(Arg::with_name("service")
@@ -72,12 +114,55 @@ fn build_cli() -> App<'static, 'static> {
             .setting(AppSettings::TrailingVarArg)
             .arg(Arg::with_name("cmd").multiple(true)))
 
+        .subcommand(SubCommand::with_name("kubectl")
+            .alias("k")
+            .about("Run kubectl against the context and namespace of a region")
+            .usage("shipcat k -- get pods")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(Arg::with_name("allow-prod")
+                .long("allow-prod")
+                .help("Allow mutating kubectl verbs in prod regions"))
+            .arg(Arg::with_name("args")
+                .multiple(true)
+                .allow_hyphen_values(true)
+                .required(true)
+                .help("Arguments to pass to kubectl")))
+
         .subcommand(SubCommand::with_name("port-forward")
             .about("Port forwards a service to localhost")
             .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service name")))
 
+        .subcommand(SubCommand::with_name("check-connectivity")
+            .about("Check that the infraDependencies of a service are reachable from inside the cluster")
+            .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service name"))
+            .arg(Arg::with_name("exec")
+                .long("exec")
+                .help("Check from a running pod of the service instead of a throwaway pod"))
+            .arg(Arg::with_name("image")
+                .long("image")
+                .takes_value(true)
+                .conflicts_with("exec")
+                .default_value(shipcat::connectivity::DEFAULT_IMAGE)
+                .help("Image for the throwaway pod (needs sh, nc, and openssl for tls checks)")))
+
+        .subcommand(SubCommand::with_name("queue")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("status")
+                .about("Show rollouts holding or waiting for a slot in the region"))
+            .about("Inspect the rollout queue of a region"))
+
+        .subcommand(SubCommand::with_name("telemetry")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("status")
+                .about("Show whether telemetry is enabled and what it collects"))
+            .subcommand(SubCommand::with_name("off")
+                .about("Opt out of telemetry and discard unsent events"))
+            .about("Manage opt-in anonymous usage telemetry"))
+
         .subcommand(SubCommand::with_name("slack")
             .arg(Arg::with_name("url")
                 .short("u")
@@ -96,16 +181,59 @@ fn build_cli() -> App<'static, 'static> {
 
         .subcommand(SubCommand::with_name("validate")
               .arg(Arg::with_name("services")
-                .required(true)
+                .required_unless("git")
                 .multiple(true)
                 .help("Service names to validate"))
+              .arg(Arg::with_name("git")
+                .long("git")
+                .conflicts_with("services")
+                .help("Validate the services changed since the merge-base with master"))
               .arg(Arg::with_name("secrets")
                 .short("s")
                 .long("secrets")
                 .help("Verifies secrets exist everywhere"))
+              .arg(Arg::with_name("promql")
+                .long("promql")
+                .help("Lint the PromQL expressions of prometheusAlerts"))
+              .arg(Arg::with_name("live")
+                .long("live")
+                .requires("promql")
+                .help("Verify metrics referenced by alerts exist in the region's Prometheus"))
+              .arg(Arg::with_name("offline")
+                .long("offline")
+                .help("Verify ownership against a cached roster only"))
+              .arg(Arg::with_name("fix")
+                .long("fix")
+                .help("Fix mechanical issues in the manifest files before validating"))
+              .arg(Arg::with_name("images")
+                .long("images")
+                .help("Verify the pinned images and tags exist in their registries"))
+              .arg(Arg::with_name("allow-sunset")
+                .long("allow-sunset")
+                .help("Only warn about services that are past their sunset date"))
               .about("Validate the shipcat manifest"))
 
+        .subcommand(SubCommand::with_name("lint")
+              .arg(Arg::with_name("services")
+                .multiple(true)
+                .help("Service names to lint (all services in the region if omitted)"))
+              .arg(Arg::with_name("deny")
+                .long("deny")
+                .takes_value(true)
+                .possible_values(&["warnings"])
+                .help("Fail on warnings as well as errors"))
+              .about("Check manifests against the lintRules in shipcat.conf"))
+
         .subcommand(SubCommand::with_name("verify")
+            .arg(Arg::with_name("environment")
+                .short("e")
+                .long("environment")
+                .takes_value(true)
+                .conflicts_with("region")
+                .help("Verify all regions of an environment group"))
+            .arg(Arg::with_name("git")
+                .long("git")
+                .help("Only verify the services changed since the merge-base with master"))
             .about("Verify all manifests of a region"))
 
         .subcommand(SubCommand::with_name("secret")
@@ -122,10 +250,49 @@ fn build_cli() -> App<'static, 'static> {
                     .conflicts_with("services")
                     .help("Checks services changed in git only"))
                 .arg(Arg::with_name("regions")
-                    .required(true)
+                    .required_unless("environment")
                     .multiple(true)
                     .help("Regions to validate all enabled services for"))
+                .arg(Arg::with_name("environment")
+                    .short("e")
+                    .long("environment")
+                    .takes_value(true)
+                    .help("Validate all regions of an environment group"))
                 .about("Verify existence of secrets for entire regions"))
+            .subcommand(SubCommand::with_name("cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("purge")
+                    .about("Remove every cached secret"))
+                .about("Manage the encrypted secret cache enabled by SHIPCAT_SECRET_CACHE_KEY"))
+            .subcommand(SubCommand::with_name("rotate")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service using the secret"))
+                .arg(Arg::with_name("secret")
+                    .required(true)
+                    .help("Name of the IN_VAULT env var or secret file"))
+                .arg(Arg::with_name("stdin")
+                    .long("stdin")
+                    .required_unless("generate")
+                    .conflicts_with("generate")
+                    .help("Read the new value from stdin"))
+                .arg(Arg::with_name("generate")
+                    .long("generate")
+                    .takes_value(true)
+                    .value_name("BYTES")
+                    .help("Generate a random value from this many bytes"))
+                .arg(Arg::with_name("keep")
+                    .long("keep")
+                    .takes_value(true)
+                    .help("Number of versions vault keeps of the secret (kv2 engines only)"))
+                .arg(Arg::with_name("reason")
+                    .long("reason")
+                    .takes_value(true)
+                    .help("Why the secret is rotated (recorded on the restart)"))
+                .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .help("Do not wait for the restart to complete"))
+                .about("Write a new value of a secret to vault and restart the service"))
             .about("Secret interaction"))
 
         .subcommand(SubCommand::with_name("gdpr")
@@ -142,16 +309,94 @@ fn build_cli() -> App<'static, 'static> {
               .about("Reduce encoded info")
               .subcommand(SubCommand::with_name("images")
                 .help("Reduce encoded image info"))
+              .subcommand(SubCommand::with_name("charts")
+                .arg(Arg::with_name("environment")
+                    .short("e")
+                    .long("environment")
+                    .takes_value(true)
+                    .help("Only report the regions of an environment group"))
+                .help("Report the charts and chart versions of services across regions"))
               .subcommand(SubCommand::with_name("apistatus")
                 .help("Reduce encoded API info"))
               .subcommand(SubCommand::with_name("eventstreams")
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("shipcat")
+                    .possible_values(&["shipcat", "avro-registry"])
+                    .help("Output format, avro-registry prints schema registry subjects"))
                 .help("Reduce eventstreams info"))
               .subcommand(SubCommand::with_name("kafkausers")
                 .help("Reduce kafkaUser info"))
               .subcommand(SubCommand::with_name("kafkatopics")
                 .help("Reduce KafkaTopic info"))
+              .subcommand(SubCommand::with_name("teams")
+                .arg(Arg::with_name("environment")
+                    .short("e")
+                    .long("environment")
+                    .takes_value(true)
+                    .help("Only count services in the regions of an environment group"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .default_value("table")
+                    .possible_values(&["table", "json"])
+                    .help("Output format"))
+                .help("Report squads and tribes with the health of their service ownership"))
+              .subcommand(SubCommand::with_name("sunsets")
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .default_value("table")
+                    .possible_values(&["table", "json"])
+                    .help("Output format"))
+                .help("List services that are past or approaching their sunset date"))
+              .subcommand(SubCommand::with_name("resources")
+                .arg(Arg::with_name("world")
+                    .long("world")
+                    .help("Sum resource requests across all regions"))
+                .arg(Arg::with_name("environment")
+                    .short("e")
+                    .long("environment")
+                    .takes_value(true)
+                    .help("Sum resource requests across the regions of an environment group"))
+                .arg(Arg::with_name("squads")
+                    .long("squads")
+                    .conflicts_with("tribes")
+                    .help("Aggregate services by squad ownership"))
+                .arg(Arg::with_name("tribes")
+                    .long("tribes")
+                    .conflicts_with("squads")
+                    .help("Aggregate services by tribe ownership"))
+                .arg(Arg::with_name("upper")
+                    .short("u")
+                    .long("upper-bounds")
+                    .help("Use the upper bounds of autoscaling policies"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .default_value("table")
+                    .possible_values(&["table", "yaml"])
+                    .help("Output format to print. Yaml contains both resources as raw numbers."))
+                .arg(Arg::with_name("sort")
+                    .short("s")
+                    .long("sort")
+                    .takes_value(true)
+                    .possible_values(&["cpu", "memory"])
+                    .default_value("cpu")
+                    .help("Resource type to show in tables and sort by"))
+                .help("Break down resource requests into main workloads, workers, cronjobs and sidecars"))
               .subcommand(SubCommand::with_name("codeowners")
                 .help("Generate CODEOWNERS syntax for manifests based on team ownership"))
+              .subcommand(SubCommand::with_name("alert-routes")
+                .help("Generate Alertmanager routes for a region based on team ownership"))
+              .subcommand(SubCommand::with_name("latency-budgets")
+                .help("Sum dependency latency budgets along the dependency chains in a region"))
+              .subcommand(SubCommand::with_name("egress")
+                .help("Report services in a region still running with open egress"))
               .subcommand(SubCommand::with_name("vault-policy")
                 .arg(Arg::with_name("team")
                   .required(true)
@@ -169,8 +414,18 @@ fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("crd")
                 .long("crd")
                 .help("Produce an experimental custom resource values for this kubernetes region"))
+            .arg(Arg::with_name("istio")
+                .long("istio")
+                .conflicts_with("crd")
+                .help("Produce an Istio Gateway and VirtualServices instead of kong config"))
             .subcommand(SubCommand::with_name("config-url")
                 .help("Generate Kong config URL")))
+        // kafka resources
+        .subcommand(SubCommand::with_name("kafka")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Inspect the strimzi kafka resources of a region")
+            .subcommand(SubCommand::with_name("diff")
+                .about("Diff manifest kafkaResources against the region's KafkaTopics and KafkaUsers")))
         // Statuscake helper
         .subcommand(SubCommand::with_name("statuscake")
             .about("Generate Statuscake config"))
@@ -190,12 +445,40 @@ fn build_cli() -> App<'static, 'static> {
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .about("Perform cluster level recovery / reconcilation commands")
             .subcommand(SubCommand::with_name("diff")
+                .arg(Arg::with_name("num-jobs")
+                    .short("j")
+                    .long("num-jobs")
+                    .takes_value(true)
+                    .help("Number of services to diff at the same time (default 10)"))
+                .arg(Arg::with_name("shard")
+                    .long("shard")
+                    .takes_value(true)
+                    .conflicts_with("shard-report")
+                    .help("Only handle shard i of N (e.g. 1/4) of the services, and write a shard report"))
+                .arg(Arg::with_name("shard-report")
+                    .long("shard-report")
+                    .takes_value(true)
+                    .help("Verify that the shard reports in a directory covered every service"))
                 .about("Diff all services against the a region"))
             .subcommand(SubCommand::with_name("check")
+                .arg(Arg::with_name("num-jobs")
+                    .short("j")
+                    .long("num-jobs")
+                    .takes_value(true)
+                    .help("Number of services to template at the same time (default 100)"))
                 .arg(Arg::with_name("skip-kinds")
                     .long("skip-kinds")
                     .takes_value(true)
                     .help("Kinds to ignore strongest checks for (comma separated)"))
+                .arg(Arg::with_name("shard")
+                    .long("shard")
+                    .takes_value(true)
+                    .conflicts_with("shard-report")
+                    .help("Only handle shard i of N (e.g. 1/4) of the services, and write a shard report"))
+                .arg(Arg::with_name("shard-report")
+                    .long("shard-report")
+                    .takes_value(true)
+                    .help("Verify that the shard reports in a directory covered every service"))
                 .about("Check all service templates for a region"))
             .subcommand(SubCommand::with_name("crd")
                 .arg(Arg::with_name("num-jobs")
@@ -206,7 +489,34 @@ fn build_cli() -> App<'static, 'static> {
                 .subcommand(SubCommand::with_name("install")
                     .about("Install the Shipcat related CRDs"))
                 .subcommand(SubCommand::with_name("reconcile")
+                    .arg(Arg::with_name("skip-preflight")
+                        .long("skip-preflight")
+                        .help("Skip cluster health checks (emergencies only)"))
+                    .arg(Arg::with_name("override-freeze")
+                        .long("override-freeze")
+                        .takes_value(true)
+                        .value_name("REASON")
+                        .help("Reconcile during a change freeze, recording the reason in audit events"))
+                    .arg(Arg::with_name("shard")
+                        .long("shard")
+                        .takes_value(true)
+                        .conflicts_with("shard-report")
+                        .help("Only handle shard i of N (e.g. 1/4) of the services, and write a shard report"))
+                    .arg(Arg::with_name("shard-report")
+                        .long("shard-report")
+                        .takes_value(true)
+                        .help("Verify that the shard reports in a directory covered every service"))
+                    .arg(Arg::with_name("ordered")
+                        .long("ordered")
+                        .help("Apply dependencies before their dependents (for bootstrapping fresh clusters)"))
                     .about("Reconcile shipcat custom resource definitions with local state")))
+            .subcommand(SubCommand::with_name("worker")
+                .arg(Arg::with_name("num-jobs")
+                    .short("j")
+                    .long("num-jobs")
+                    .takes_value(true)
+                    .help("Number of worker threads used for reconciles (default 8)"))
+                .about("Execute applies and reconciles requested through raftcat"))
             .subcommand(SubCommand::with_name("vault-policy")
                 .arg(Arg::with_name("num-jobs")
                     .short("j")
@@ -216,6 +526,55 @@ fn build_cli() -> App<'static, 'static> {
                 .subcommand(SubCommand::with_name("reconcile")
                     .about("Reconcile vault policies with manifest state"))))
         // all the listers (hidden from cli output)
+        .subcommand(SubCommand::with_name("team")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Run status, diff or apply for all services owned by a squad")
+            .arg(Arg::with_name("squad")
+                .required(true)
+                .help("Squad from teams.yml owning the services"))
+            .arg(Arg::with_name("num-jobs")
+                .short("j")
+                .long("num-jobs")
+                .takes_value(true)
+                .help("Number of services to handle at the same time (default 10, or 4 for apply)"))
+            .subcommand(SubCommand::with_name("status")
+                .about("Show the rollout state of the squad's services"))
+            .subcommand(SubCommand::with_name("diff")
+                .about("Diff the squad's services against the region"))
+            .subcommand(SubCommand::with_name("apply")
+                .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .help("Do not wait for the services to roll out"))
+                .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Apply services even when they are up to date"))
+                .arg(Arg::with_name("skip-preflight")
+                    .long("skip-preflight")
+                    .help("Skip the cluster health checks before applying"))
+                .arg(Arg::with_name("override-freeze")
+                    .long("override-freeze")
+                    .takes_value(true)
+                    .value_name("REASON")
+                    .help("Apply during a change freeze, recording the reason in audit events"))
+                .about("Apply the squad's services to the region")))
+        .subcommand(SubCommand::with_name("export-region")
+            .about("Render every service in a region into a directory for a GitOps repository")
+            .arg(Arg::with_name("dir")
+                .required(true)
+                .help("Directory to write a folder per service to"))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only render services changed since this git reference"))
+            .arg(Arg::with_name("secrets")
+                .short("s")
+                .long("secrets")
+                .help("Use actual secrets from vault and export Secret objects"))
+            .arg(Arg::with_name("num-jobs")
+                .short("j")
+                .long("num-jobs")
+                .takes_value(true)
+                .help("Number of services to render at the same time (default 8)")))
         .subcommand(SubCommand::with_name("list-regions")
             .setting(AppSettings::Hidden)
             .about("list supported regions/clusters"))
@@ -231,6 +590,16 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to check"))
+              .arg(Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("Re-render the status as the pods, replicasets and crd of the service change"))
+              .arg(Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .possible_values(&["rolledout"])
+                .requires("watch")
+                .help("Stop watching when this condition is reached"))
               .about("Show kubernetes status for all the resources for a service"))
 
         .subcommand(SubCommand::with_name("version")
@@ -250,6 +619,9 @@ fn build_cli() -> App<'static, 'static> {
                 .short("s")
                 .long("secrets")
                 .help("Use actual secrets from vault"))
+              .arg(Arg::with_name("explain")
+                .long("explain")
+                .help("Show where defaulted values come from"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to generate values for"))
@@ -272,6 +644,18 @@ fn build_cli() -> App<'static, 'static> {
                 .takes_value(true)
                 .requires("check")
                 .help("Kinds to ignore strongest checks for (comma separated)"))
+              .arg(Arg::with_name("show-context")
+                .long("show-context")
+                .help("Print the context available to templates as yaml before rendering"))
+              .arg(Arg::with_name("output-dir")
+                .long("output-dir")
+                .takes_value(true)
+                .conflicts_with("check")
+                .help("Write every kubernetes object to its own file in this directory"))
+              .arg(Arg::with_name("kustomize")
+                .long("kustomize")
+                .requires("output-dir")
+                .help("Generate a kustomization.yaml in the output directory"))
               .arg(Arg::with_name("tag")
                 .long("tag")
                 .short("t")
@@ -293,15 +677,58 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("force")
                     .long("force")
                     .help("Apply template even if no changes are detected"))
+              .arg(Arg::with_name("skip-preflight")
+                    .long("skip-preflight")
+                    .help("Skip cluster health checks (emergencies only)"))
+              .arg(Arg::with_name("strict-deps")
+                    .long("strict-deps")
+                    .help("Refuse prod applies when running dependencies violate the declared version ranges"))
+              .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Print what would change using server-side dry runs, without changing the cluster"))
+              .arg(Arg::with_name("canary")
+                    .long("canary")
+                    .takes_value(true)
+                    .requires("tag")
+                    .conflicts_with("dry-run")
+                    .help("Deploy the tag as a canary receiving this percentage of traffic (e.g. 10%)"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to apply"))
             .about("Apply a service's configuration in kubernetes (through helm)"))
 
+        .subcommand(SubCommand::with_name("promote")
+              .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service whose canary to promote"))
+            .about("Upgrade a service to the version of its canary, and remove the canary"))
+
+        .subcommand(SubCommand::with_name("abort-canary")
+              .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service whose canary to remove"))
+            .about("Remove the canary of a service, keeping its current version"))
+
+        .subcommand(SubCommand::with_name("resume")
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service whose upgrade to resume"))
+            .about("Resume tracking an upgrade whose apply was interrupted"))
+
         .subcommand(SubCommand::with_name("restart")
               .arg(Arg::with_name("no-wait")
                     .long("no-wait")
                     .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("reason")
+                    .long("reason")
+                    .takes_value(true)
+                    .help("Why the service is restarted (required in prod)"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to restart"))
@@ -313,7 +740,20 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Service to delete"))
             .about("Delete a service's shipcatmanifest from kubernetes"))
 
+        .subcommand(SubCommand::with_name("annotate")
+              .arg(Arg::with_name("version")
+                .long("version")
+                .short("t")
+                .takes_value(true)
+                .required(true)
+                .help("Version that was deployed"))
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service that was deployed"))
+            .about("Post deployment markers for a service to grafana and sentry"))
+
         .subcommand(SubCommand::with_name("env")
+              .setting(AppSettings::SubcommandsNegateReqs)
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to generate an environment for"))
@@ -321,6 +761,18 @@ fn build_cli() -> App<'static, 'static> {
                 .short("s")
                 .long("secrets")
                 .help("Use actual secrets from vault"))
+              .subcommand(SubCommand::with_name("grep")
+                .arg(Arg::with_name("pattern")
+                    .required(true)
+                    .help("Regex matched against env var names and values"))
+                .arg(Arg::with_name("world")
+                    .long("world")
+                    .help("Search services in all regions"))
+                .arg(Arg::with_name("rewrite")
+                    .long("rewrite")
+                    .takes_value(true)
+                    .help("Rewrite the matching lines in the service files with a sed style s/old/new/"))
+                .about("Find env vars across services, with the files and lines setting them"))
               .about("Show env vars in a format that can be sourced in a shell"))
 
         .subcommand(SubCommand::with_name("diff")
@@ -328,6 +780,24 @@ fn build_cli() -> App<'static, 'static> {
                 .long("git")
                 .global(true)
                 .help("Comparing with master using a temporary git stash and git checkout"))
+              .arg(Arg::with_name("against")
+                .long("against")
+                .takes_value(true)
+                .conflicts_with("git")
+                .conflicts_with("with-region")
+                .conflicts_with("secrets")
+                .help("Comparing with the output at a git ref (branch, tag or sha) using a temporary worktree"))
+              .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .conflicts_with("all-regions")
+                .help("Print a json list of changed paths instead of a unified diff (not for kubectl diffs)"))
+              .arg(Arg::with_name("all-regions")
+                .long("all-regions")
+                .conflicts_with_all(&["git", "against", "with-region", "crd", "secrets"])
+                .help("Report the values that differ across every region of the service"))
               .arg(Arg::with_name("with-region")
                 .long("with-region")
                 .global(true)
@@ -355,8 +825,7 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Minify the diff context"))
               .arg(Arg::with_name("obfuscate")
                 .long("obfuscate")
-                .requires("secrets")
-                .help("Obfuscate secrets in the diff"))
+                .help("Mask secrets and sensitive env values in the diff"))
               .arg(Arg::with_name("secrets")
                 .long("secrets")
                 .short("s")
@@ -370,11 +839,32 @@ fn build_cli() -> App<'static, 'static> {
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .about("Run interactions on shipcat.conf")
             .subcommand(SubCommand::with_name("show")
+                .arg(Arg::with_name("resolved")
+                    .long("resolved")
+                    .help("Show the effective region after layering regions/_defaults.yml"))
                 .about("Show the config"))
             .subcommand(SubCommand::with_name("crd")
                 .about("Show the config in crd form for a region"))
             .subcommand(SubCommand::with_name("verify")
-                .about("Verify the parsed config")))
+                .about("Verify the parsed config"))
+            .subcommand(SubCommand::with_name("clone-region")
+                .about("Print a new region config derived from an existing region")
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Region to clone"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Name of the new region"))
+                .arg(Arg::with_name("sanitize")
+                    .long("sanitize")
+                    .help("Rewrite urls and replace secrets with IN_VAULT placeholders"))))
+
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Check that your environment is set up for shipcat"))
 
         .subcommand(SubCommand::with_name("login")
             .about("Login to a region (using teleport if possible)")
@@ -399,6 +889,12 @@ fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("world")
                 .long("world")
                 .help("Show resource requests across all regions"))
+            .arg(Arg::with_name("environment")
+                .short("e")
+                .long("environment")
+                .takes_value(true)
+                .conflicts_with("region")
+                .help("Show resource requests across the regions of an environment group"))
             .arg(Arg::with_name("squads")
                 .long("squads")
                 .conflicts_with("tribes")
@@ -413,7 +909,49 @@ fn build_cli() -> App<'static, 'static> {
                 .default_value("cpu")
                 .long("sort")
                 .short("s")
-                .help("Resource type to sort by")));
+                .help("Resource type to sort by")))
+        .subcommand(SubCommand::with_name("cost")
+            .about("Estimate monthly cost of resource requests from manifests on disk")
+            .arg(Arg::with_name("upper")
+                .short("u")
+                .long("upper-bounds")
+                .help("Use the upper bounds of autoscaling policies"))
+            .arg(Arg::with_name("output")
+                .takes_value(true)
+                .default_value("table")
+                .possible_values(&["table", "yaml", "csv"])
+                .long("output")
+                .short("o")
+                .help("Output format to print. Yaml and csv contain machine parseable numbers."))
+            .arg(Arg::with_name("world")
+                .long("world")
+                .help("Sum costs across all regions"))
+            .arg(Arg::with_name("environment")
+                .short("e")
+                .long("environment")
+                .takes_value(true)
+                .conflicts_with("region")
+                .help("Sum costs across the regions of an environment group"))
+            .arg(Arg::with_name("squads")
+                .long("squads")
+                .conflicts_with("tribes")
+                .help("Aggregate services by squad ownership"))
+            .arg(Arg::with_name("tribes")
+                .long("tribes")
+                .conflicts_with("squads")
+                .help("Aggregate services by tribe ownership")))
+        .subcommand(SubCommand::with_name("stats")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Usage statistics from manifests on disk")
+            .subcommand(SubCommand::with_name("fields")
+                .about("Count services using each manifest field across all regions")
+                .arg(Arg::with_name("output")
+                    .takes_value(true)
+                    .default_value("json")
+                    .possible_values(&["json", "csv"])
+                    .long("output")
+                    .short("o")
+                    .help("Output format to print"))));
 
     if cfg!(feature = "self-upgrade") {
         app = app.subcommand(SubCommand::with_name("self-upgrade")
@@ -440,11 +978,14 @@ async fn main() {
     }
 
     let name = args.subcommand_name().unwrap();
-    let _ = run(&args).await.map_err(|e| {
+    let started = Instant::now();
+    let res = run(&args).await;
+    shipcat::telemetry::record(&args, started.elapsed(), res.is_ok()).await;
+    if let Err(e) = res {
         error!("{} error: {}", name, e);
         print_error_debug(&e);
         process::exit(1);
-    });
+    }
     process::exit(0);
 }
 
@@ -460,6 +1001,15 @@ async fn run(args: &ArgMatches<'static>) -> Result<()> {
         .init()
         .unwrap();
     shipcat::init()?;
+    if args.is_present("skip-sentry") {
+        std::env::set_var(shipcat_definitions::sentry::SKIP_SENTRY_EVAR, "1");
+    }
+    if let Some(pth) = args.value_of("audit-log") {
+        std::env::set_var(shipcat::audit::AUDIT_LOG_EVAR, pth);
+    }
+    if let Some(why) = args.value_of("break-glass") {
+        std::env::set_var(shipcat::permissions::BREAK_GLASS_EVAR, why);
+    }
 
     // Ignore SIGPIPE errors to avoid having to use let _ = write! everywhere
     // See https://github.com/rust-lang/rust/issues/46016
@@ -551,6 +1101,9 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     } else if let Some(a) = args.subcommand_matches("list-services") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::list::services(&conf, &region).await;
+    } else if let Some(a) = args.subcommand_matches("doctor") {
+        // NB: does not resolve_config, reports why that would fail instead
+        return shipcat::doctor::run(a.value_of("region")).await;
     } else if let Some(a) = args.subcommand_matches("login") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::auth::login(&conf, &region, a.is_present("force")).await;
@@ -571,6 +1124,59 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 .map(void);
         }
 
+        if let Some(b) = a.subcommand_matches("charts") {
+            let rawconf = Config::read().await?;
+            let regions = match b.value_of("environment") {
+                Some(e) => rawconf.environment_regions(e)?,
+                None => rawconf.list_regions(),
+            };
+            return shipcat::get::charts(&rawconf, &regions).await.map(void);
+        }
+
+        if let Some(b) = a.subcommand_matches("teams") {
+            let rawconf = Config::read().await?;
+            let regions = match b.value_of("environment") {
+                Some(e) => rawconf.environment_regions(e)?,
+                None => rawconf.list_regions(),
+            };
+            let fmt = get::ReportFormat::from_str(b.value_of("output").unwrap())?;
+            return shipcat::get::teams(&rawconf, &regions, fmt).await.map(void);
+        }
+
+        if let Some(b) = a.subcommand_matches("sunsets") {
+            let rawconf = Config::read().await?;
+            let fmt = get::ReportFormat::from_str(b.value_of("output").unwrap())?;
+            return shipcat::get::sunsets(&rawconf, fmt).await.map(void);
+        }
+
+        if let Some(b) = a.subcommand_matches("resources") {
+            let grouping = if b.is_present("squads") {
+                top::WorkloadGrouping::Squad
+            } else if b.is_present("tribes") {
+                top::WorkloadGrouping::Tribe
+            } else {
+                top::WorkloadGrouping::Service
+            };
+            let sort = top::ResourceOrder::from_str(b.value_of("sort").unwrap())?;
+            let fmt = top::OutputFormat::from_str(b.value_of("output").unwrap())?;
+            let ub = b.is_present("upper");
+            return if b.is_present("world") || b.is_present("environment") {
+                let rawconf = Config::read().await?;
+                let regions = match b.value_of("environment") {
+                    Some(e) => rawconf.environment_regions(e)?,
+                    None => rawconf.list_regions(),
+                };
+                shipcat::top::world_workload_requests(grouping, sort, ub, fmt, &rawconf, &regions)
+                    .await
+                    .map(void)
+            } else {
+                let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+                shipcat::top::region_workload_requests(grouping, sort, ub, fmt, &conf, &region)
+                    .await
+                    .map(void)
+            };
+        }
+
         // resolve region from kube context here if unspecified
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         if let Some(_) = a.subcommand_matches("versions") {
@@ -589,11 +1195,21 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             let team = b.value_of("team").unwrap(); // required param
             return shipcat::get::vaultpolicy(&conf, &region, team).await.map(void);
         }
+        if let Some(_) = a.subcommand_matches("alert-routes") {
+            return shipcat::get::alert_routes(&conf, &region).await.map(void);
+        }
+        if let Some(_) = a.subcommand_matches("latency-budgets") {
+            return shipcat::get::latency_budgets(&conf, &region).await.map(void);
+        }
+        if let Some(_) = a.subcommand_matches("egress") {
+            return shipcat::get::egress(&conf, &region).await.map(void);
+        }
         if let Some(_) = a.subcommand_matches("apistatus") {
             return shipcat::get::apistatus(&conf, &region).await;
         }
-        if let Some(_) = a.subcommand_matches("eventstreams") {
-            return shipcat::get::eventstreams(&conf, &region).await;
+        if let Some(b) = a.subcommand_matches("eventstreams") {
+            let registry = b.value_of("format") == Some("avro-registry");
+            return shipcat::get::eventstreams(&conf, &region, registry).await;
         }
         if let Some(_) = a.subcommand_matches("kafkausers") {
             return shipcat::get::kafkausers(&conf, &region).await;
@@ -601,22 +1217,32 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         if let Some(_) = a.subcommand_matches("kafkatopics") {
             return shipcat::get::kafkatopics(&conf, &region).await;
         }
+    } else if let Some(a) = args.subcommand_matches("stats") {
+        if let Some(b) = a.subcommand_matches("fields") {
+            let fmt = stats::StatsFormat::from_str(b.value_of("output").unwrap())?;
+            let rawconf = Config::read().await?;
+            return shipcat::stats::fields(&rawconf, fmt).await.map(void);
+        }
     } else if let Some(a) = args.subcommand_matches("top") {
         let sort = top::ResourceOrder::from_str(a.value_of("sort").unwrap())?;
         let fmt = top::OutputFormat::from_str(a.value_of("output").unwrap())?;
         let ub = a.is_present("upper");
-        return if a.is_present("world") {
+        return if a.is_present("world") || a.is_present("environment") {
             let rawconf = Config::read().await?;
+            let regions = match a.value_of("environment") {
+                Some(e) => rawconf.environment_regions(e)?,
+                None => rawconf.list_regions(),
+            };
             if a.is_present("squads") {
-                shipcat::top::world_squad_requests(sort, ub, fmt, &rawconf)
+                shipcat::top::world_squad_requests(sort, ub, fmt, &rawconf, &regions)
                     .await
                     .map(void)
             } else if a.is_present("tribes") {
-                shipcat::top::world_tribe_requests(sort, ub, fmt, &rawconf)
+                shipcat::top::world_tribe_requests(sort, ub, fmt, &rawconf, &regions)
                     .await
                     .map(void)
             } else {
-                shipcat::top::world_requests(sort, ub, fmt, &rawconf)
+                shipcat::top::world_requests(sort, ub, fmt, &rawconf, &regions)
                     .await
                     .map(void)
             }
@@ -636,12 +1262,47 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                     .map(void)
             }
         };
+    } else if let Some(a) = args.subcommand_matches("cost") {
+        let grouping = if a.is_present("squads") {
+            top::WorkloadGrouping::Squad
+        } else if a.is_present("tribes") {
+            top::WorkloadGrouping::Tribe
+        } else {
+            top::WorkloadGrouping::Service
+        };
+        let fmt = top::CostFormat::from_str(a.value_of("output").unwrap())?;
+        let ub = a.is_present("upper");
+        return if a.is_present("world") || a.is_present("environment") {
+            let rawconf = Config::read().await?;
+            let names = match a.value_of("environment") {
+                Some(e) => rawconf.environment_regions(e)?,
+                None => rawconf.list_regions(),
+            };
+            let regions = names
+                .iter()
+                .filter_map(|r| rawconf.get_region_unchecked(r).cloned())
+                .collect::<Vec<_>>();
+            shipcat::top::cost(grouping, ub, fmt, &rawconf, &regions)
+                .await
+                .map(void)
+        } else {
+            let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+            shipcat::top::cost(grouping, ub, fmt, &conf, &[region])
+                .await
+                .map(void)
+        };
     } else if let Some(a) = args.subcommand_matches("config") {
         if let Some(_) = a.subcommand_matches("crd") {
             let (conf, _region) = resolve_config(a, ConfigState::Base).await?;
             // this only works with a given region
             return shipcat::show::config_crd(conf);
         }
+        if let Some(b) = a.subcommand_matches("show") {
+            if b.is_present("resolved") {
+                let (_conf, region) = resolve_config(a, ConfigState::Base).await?;
+                return shipcat::show::region(&region);
+            }
+        }
         // The others make sense without a region
         // Want to be able to verify full config when no kube context given!
         let conf = if a.is_present("region") {
@@ -651,6 +1312,11 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         };
         if let Some(_) = a.subcommand_matches("verify") {
             return shipcat::validate::config(conf);
+        } else if let Some(b) = a.subcommand_matches("clone-region") {
+            let from = b.value_of("from").unwrap();
+            let to = b.value_of("to").unwrap();
+            shipcat::clone::region(&conf, from, to, b.is_present("sanitize")).await?;
+            return Ok(());
         } else if let Some(_) = a.subcommand_matches("show") {
             return shipcat::show::config(conf);
         }
@@ -659,9 +1325,39 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     // helpers that can work without a kube region, but will shell out to kubectl if not passed
     // TODO: remove this
     else if let Some(a) = args.subcommand_matches("secret") {
+        if let Some(b) = a.subcommand_matches("cache") {
+            if b.subcommand_matches("purge").is_some() {
+                let removed = shipcat_definitions::secretcache::purge()?;
+                info!("Removed {} cached secrets", removed);
+                return Ok(());
+            }
+        }
+        if let Some(b) = a.subcommand_matches("rotate") {
+            let svc = b.value_of("service").unwrap();
+            let secret = b.value_of("secret").unwrap();
+            let value = match b.value_of("generate") {
+                Some(n) => shipcat::rotate::NewValue::Generated(n.parse()?),
+                None => shipcat::rotate::NewValue::Stdin,
+            };
+            let keep = b.value_of("keep").map(str::parse::<u32>).transpose()?;
+            let (conf, region) = resolve_config(b, ConfigState::Base).await?;
+            shipcat::permissions::enforce(svc, &conf, &region).await?;
+            let mf = shipcat_filebacked::load_manifest(svc, &conf, &region).await?;
+            let wait = !b.is_present("no-wait");
+            let reason = b.value_of("reason").map(String::from);
+            return shipcat::rotate::rotate(mf, &region, secret, value, keep, wait, reason).await;
+        }
         let rawconf = Config::read().await?;
         if let Some(b) = a.subcommand_matches("verify-region") {
-            let regions = b.values_of("regions").unwrap().map(String::from).collect();
+            let mut regions: Vec<String> = b
+                .values_of("regions")
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect();
+            if let Some(e) = b.value_of("environment") {
+                regions.extend(rawconf.environment_regions(e)?);
+            }
             // NB: this does a cheap verify of both Config and Manifest (vault list)
             return if b.is_present("git") {
                 shipcat::validate::secret_presence_git(&rawconf, regions).await
@@ -683,6 +1379,13 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     else if let Some(a) = args.subcommand_matches("status") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        if a.is_present("watch") {
+            let until = a
+                .value_of("until")
+                .map(status::WatchUntil::from_str)
+                .transpose()?;
+            return shipcat::status::watch(&svc, &conf, &region, until).await;
+        }
         return shipcat::status::show(&svc, &conf, &region).await;
     } else if let Some(a) = args.subcommand_matches("graph") {
         let dot = a.is_present("dot");
@@ -697,11 +1400,6 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             shipcat::graph::full(dot, &conf, &region).await.map(void)
         };
     } else if let Some(a) = args.subcommand_matches("validate") {
-        let services = a
-            .values_of("services")
-            .unwrap()
-            .map(String::from)
-            .collect::<Vec<_>>();
         // this only needs a kube context if you don't specify it
         let ss = if a.is_present("secrets") {
             ConfigState::Filtered
@@ -709,18 +1407,66 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             ConfigState::Base
         };
         let (conf, region) = resolve_config(a, ss).await?;
-        return shipcat::validate::manifest(services, &conf, &region, a.is_present("secrets")).await;
+        let services = if a.is_present("git") {
+            let available = shipcat_filebacked::available(&conf, &region)
+                .await?
+                .into_iter()
+                .map(|s| s.base.name);
+            let changed = shipcat::validate::git_changes();
+            let svcs = available
+                .filter(|s| changed.as_ref().map_or(true, |c| c.contains(s)))
+                .collect::<Vec<_>>();
+            if svcs.is_empty() {
+                info!("No services in {} changed", region.name);
+                return Ok(());
+            }
+            info!("Validating {} changed services: {}", svcs.len(), svcs.join(", "));
+            svcs
+        } else {
+            a.values_of("services")
+                .unwrap()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        if a.is_present("fix") {
+            shipcat::validate::fix(&services, &conf, &region).await?;
+        }
+        shipcat::validate::manifest(services.clone(), &conf, &region, a.is_present("secrets")).await?;
+        if a.is_present("promql") {
+            shipcat::validate::promql(services.clone(), &conf, &region, a.is_present("live")).await?;
+        }
+        if a.is_present("images") {
+            shipcat::validate::images(services.clone(), &conf, &region).await?;
+        }
+        shipcat::validate::sunset(services.clone(), &conf, &region, a.is_present("allow-sunset")).await?;
+        return shipcat::validate::roster(services, &conf, &region, a.is_present("offline")).await;
+    } else if let Some(a) = args.subcommand_matches("lint") {
+        let services = a
+            .values_of("services")
+            .map(|v| v.map(String::from).collect())
+            .unwrap_or_default();
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        let deny_warnings = a.value_of("deny") == Some("warnings");
+        return shipcat::lint::lint(services, &conf, &region, deny_warnings).await;
     } else if let Some(a) = args.subcommand_matches("verify") {
+        let changed = if a.is_present("git") {
+            shipcat::validate::git_changes()
+        } else {
+            None
+        };
         return if a.value_of("region").is_some() {
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-            shipcat::validate::regional_manifests(&conf, &region).await
+            shipcat::validate::regional_manifests(&conf, &region, changed.as_ref()).await
+        } else if let Some(e) = a.value_of("environment") {
+            shipcat::validate::environment_manifests(e, changed.as_ref()).await
         } else {
-            shipcat::validate::all_manifests().await
+            shipcat::validate::all_manifests(changed.as_ref()).await
         };
     } else if let Some(a) = args.subcommand_matches("values") {
         let svc = a.value_of("service").map(String::from).unwrap();
 
         let ss = if a.is_present("secrets") {
+            shipcat_definitions::secretcache::enable();
             ConfigState::Filtered
         } else {
             ConfigState::Base
@@ -738,12 +1484,21 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 .stub(&region)
                 .await?
         };
+        if a.is_present("explain") {
+            // as yaml comments to keep the output valid values
+            println!("# Defaulted values:");
+            for d in shipcat_filebacked::explain_defaults(&svc, &conf, &region).await? {
+                let value = serde_json::to_string(&d.value)?;
+                println!("#   {}: {} (from {})", d.key, value, d.source);
+            }
+        }
         mf.print()?;
         return Ok(());
     } else if let Some(a) = args.subcommand_matches("template") {
         let svc = a.value_of("service").map(String::from).unwrap();
 
         let ss = if a.is_present("secrets") {
+            shipcat_definitions::secretcache::enable();
             ConfigState::Filtered
         } else {
             ConfigState::Base
@@ -751,16 +1506,15 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ss).await?;
         let ver = a.value_of("tag").map(String::from);
 
+        let base = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
+        if a.is_present("show-context") {
+            println!("# template context for {} in {}", svc, region.name);
+            println!("{}", base.template_context_yaml(&region)?);
+        }
         let mut mf = if a.is_present("secrets") {
-            shipcat_filebacked::load_manifest(&svc, &conf, &region)
-                .await?
-                .complete(&region)
-                .await?
+            base.complete(&region).await?
         } else {
-            shipcat_filebacked::load_manifest(&svc, &conf, &region)
-                .await?
-                .stub(&region)
-                .await?
+            base.stub(&region).await?
         };
         mf.version = mf.version.or(ver);
         if a.is_present("current") {
@@ -773,7 +1527,7 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             mf.uid = Some("FAKE-GUID".to_string());
             mf.version = mf.version.or(Some("latest".to_string()));
         }
-        let tpl = shipcat::helm::template(&mf, None).await?;
+        let tpl = shipcat::helm::template(&mf, &conf, &region, None).await?;
         if a.is_present("check") {
             let skipped = a
                 .value_of("skip-kinds")
@@ -782,7 +1536,10 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 .map(String::from)
                 .filter(|s| !s.is_empty())
                 .collect::<Vec<_>>();
-            shipcat::helm::template_check(&mf, &region, &skipped, &tpl)?;
+            shipcat::helm::template_check(&mf, &conf, &region, &skipped, &tpl)?;
+        } else if let Some(dir) = a.value_of("output-dir") {
+            let files = shipcat::helm::template_files(&tpl, &region.namespace, a.is_present("kustomize"))?;
+            shipcat::helm::write_template_dir(&files, Path::new(dir)).await?;
         } else {
             println!("{}", tpl);
         }
@@ -793,6 +1550,24 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::show::manifest_crd(&svc, &conf, &region).await;
     } else if let Some(a) = args.subcommand_matches("env") {
+        if let Some(b) = a.subcommand_matches("grep") {
+            let pattern = b.value_of("pattern").unwrap();
+            let rewrite = match b.value_of("rewrite") {
+                Some(s) => Some(shipcat::env::Substitution::from_str(s)?),
+                None => None,
+            };
+            let (conf, regions) = if b.is_present("world") {
+                let rawconf = Config::read().await?;
+                let regions = rawconf.list_regions();
+                (rawconf, regions)
+            } else {
+                let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+                (conf, vec![region.name])
+            };
+            return shipcat::env::grep(pattern, &conf, &regions, rewrite.as_ref())
+                .await
+                .map(void);
+        }
         let svc = a.value_of("service").map(String::from).unwrap();
         let mock = !a.is_present("secrets");
         let config_state = if mock {
@@ -804,11 +1579,20 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         return shipcat::env::print_bash(&svc, &conf, &region, mock).await;
     } else if let Some(a) = args.subcommand_matches("diff") {
         let svc = a.value_of("service").map(String::from).unwrap();
-        let diff_exit = if a.is_present("crd") {
+        let fmt = diff::DiffFormat::from_str(a.value_of("format").unwrap())?;
+        let diff_exit = if let Some(reference) = a.value_of("against") {
+            // NB: renders both sides without secrets
+            let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+            shipcat::diff::vs_ref(&svc, &conf, &region, reference, a.is_present("crd"), fmt).await?
+        } else if a.is_present("all-regions") {
+            // NB: compares stubbed values, so needs neither secrets nor a kube context
+            let rawconf = Config::read().await?;
+            shipcat::diff::values_across_regions(&svc, &rawconf).await?
+        } else if a.is_present("crd") {
             // NB: no secrets in CRD
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
             if a.is_present("git") {
-                shipcat::diff::values_vs_git(&svc, &conf, &region).await?
+                shipcat::diff::values_vs_git(&svc, &conf, &region, fmt).await?
             } else {
                 shipcat::diff::values_vs_kubectl(&svc, &conf, &region).await?
             }
@@ -816,15 +1600,19 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             // special - serial git diff
             // does not support mocking (but also has no secrets)
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-            shipcat::diff::template_vs_git(&svc, &conf, &region).await?
+            shipcat::diff::template_vs_git(&svc, &conf, &region, fmt).await?
         } else if a.is_present("with-region") {
             // special - diff between two regions
             // does not support mocking (but also has no secrets)
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
             let with_region = a.value_of("with-region").unwrap();
             let (_ref_conf, ref_region) = Config::new(ConfigState::Base, with_region).await?;
-            shipcat::diff::values_vs_region(&svc, &conf, &region, &ref_region).await?
+            shipcat::diff::values_vs_region(&svc, &conf, &region, &ref_region, fmt).await?
         } else {
+            if fmt != diff::DiffFormat::Text {
+                // kubectl diff only produces text
+                return Err("--format json needs --git, --against or --with-region".into());
+            }
             let ss = if a.is_present("secrets") {
                 ConfigState::Filtered
             } else {
@@ -854,10 +1642,12 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 mf.uid = Some("FAKE-GUID".to_string());
                 mf.version = mf.version.or(Some("latest".to_string()));
             }
-            let diff = shipcat::diff::template_vs_kubectl(&mf).await?;
+            let diff = shipcat::diff::template_vs_kubectl(&mf, &conf, &region).await?;
             if let Some(mut out) = diff {
                 if a.is_present("obfuscate") {
-                    out = shipcat::diff::obfuscate_secrets(out, mf.get_secrets())
+                    let (secret_keys, secrets) = (mf.secret_config_keys(), mf.get_secrets());
+                    out =
+                        shipcat::diff::mask_secrets(&out, &conf.sensitive_env_regex(), &secret_keys, &secrets)
                 };
                 if a.is_present("minify") {
                     out = shipcat::diff::minify(&out)
@@ -869,6 +1659,12 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             }
         };
         process::exit(if diff_exit { 0 } else { 1 });
+    } else if let Some(a) = args.subcommand_matches("kafka") {
+        if a.subcommand_matches("diff").is_some() {
+            let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+            let matched = shipcat::kafka::diff(&conf, &region).await?;
+            process::exit(if matched { 0 } else { 1 });
+        }
     } else if let Some(a) = args.subcommand_matches("kong") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return if let Some(_b) = a.subcommand_matches("config-url") {
@@ -876,6 +1672,8 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         } else {
             let mode = if a.is_present("crd") {
                 kong::KongOutputMode::Crd
+            } else if a.is_present("istio") {
+                kong::KongOutputMode::Istio
             } else {
                 kong::KongOutputMode::Kongfig
             };
@@ -895,19 +1693,111 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let force = a.is_present("force");
         let ver = a.value_of("tag").map(String::from); // needed for some subcommands
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
-        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver)
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
+        if let Some(why) = a.value_of("override-freeze") {
+            std::env::set_var(shipcat::freeze::OVERRIDE_FREEZE_EVAR, why);
+        }
+        if !a.is_present("dry-run") {
+            shipcat::freeze::enforce(&conf, &region).await?;
+        }
+        if !a.is_present("skip-preflight") {
+            shipcat::preflight::run(&region, true).await?;
+        }
+        if a.is_present("strict-deps") {
+            let mf = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
+            shipcat::depcheck::strict(&mf, &region).await?;
+        }
+        if let Some(w) = a.value_of("canary") {
+            let weight = shipcat_definitions::structs::Canary::parse_weight(w)?;
+            let ver = ver.expect("clap requires a tag for canaries");
+            return shipcat::apply::canary(svc, weight, ver, &region, &conf, wait)
+                .await
+                .map(void);
+        }
+        let dry_run = a.is_present("dry-run");
+        if dry_run {
+            // lets template hooks skip side effects
+            std::env::set_var("SHIPCAT_DRY_RUN", "1");
+        }
+        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver, dry_run)
             .await
             .map(void);
+    } else if let Some(a) = args.subcommand_matches("promote") {
+        let svc = a.value_of("service").map(String::from).unwrap();
+        let (conf, region) = resolve_config(a, ConfigState::Filtered).await?;
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
+        let wait = !a.is_present("no-wait");
+        return shipcat::apply::promote(svc, &region, &conf, wait).await.map(void);
+    } else if let Some(a) = args.subcommand_matches("abort-canary") {
+        let svc = a.value_of("service").map(String::from).unwrap();
+        let (conf, region) = resolve_config(a, ConfigState::Filtered).await?;
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
+        let wait = !a.is_present("no-wait");
+        return shipcat::apply::abort_canary(svc, &region, &conf, wait)
+            .await
+            .map(void);
+    } else if let Some(a) = args.subcommand_matches("resume") {
+        let svc = a.value_of("service").map(String::from).unwrap();
+        let (conf, region) = resolve_config(a, ConfigState::Filtered).await?;
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
+        return shipcat::apply::resume(svc, &region, &conf).await.map(void);
     } else if let Some(a) = args.subcommand_matches("restart") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
         let mf = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
         let wait = !a.is_present("no-wait");
-        return shipcat::apply::restart(&mf, wait).await.map(void);
+        let reason = a.value_of("reason").map(String::from);
+        return shipcat::apply::restart(&mf, wait, reason).await.map(void);
     } else if let Some(a) = args.subcommand_matches("delete") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        shipcat::permissions::enforce(&svc, &conf, &region).await?;
         return shipcat::apply::delete(&svc, &region, &conf).await.map(void);
+    } else if let Some(a) = args.subcommand_matches("annotate") {
+        let svc = a.value_of("service").unwrap();
+        let version = a.value_of("version").unwrap();
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        return shipcat::annotate::manual(svc, version, &conf, &region)
+            .await
+            .map(void);
+    }
+    // team level commands
+    else if let Some(a) = args.subcommand_matches("team") {
+        let squad = a.value_of("squad").unwrap();
+        let jobs = a.value_of("num-jobs").map(str::parse::<usize>).transpose()?;
+        if a.subcommand_matches("status").is_some() {
+            let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+            return shipcat::team::status(squad, &conf, &region, jobs.unwrap_or(10)).await;
+        }
+        if a.subcommand_matches("diff").is_some() {
+            let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
+            return shipcat::team::diff(squad, &conf, &region, jobs.unwrap_or(10)).await;
+        }
+        if let Some(b) = a.subcommand_matches("apply") {
+            let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
+            if let Some(why) = b.value_of("override-freeze") {
+                std::env::set_var(shipcat::freeze::OVERRIDE_FREEZE_EVAR, why);
+            }
+            shipcat::freeze::enforce(&conf, &region).await?;
+            if !b.is_present("skip-preflight") {
+                shipcat::preflight::run(&region, true).await?;
+            }
+            let wait = !b.is_present("no-wait");
+            let force = b.is_present("force");
+            return shipcat::team::apply(squad, &conf, &region, jobs.unwrap_or(4), force, wait).await;
+        }
+    } else if let Some(a) = args.subcommand_matches("export-region") {
+        let secrets = a.is_present("secrets");
+        let ss = if secrets {
+            ConfigState::Filtered
+        } else {
+            ConfigState::Base
+        };
+        let (conf, region) = resolve_config(args, ss).await?;
+        let dir = Path::new(a.value_of("dir").unwrap());
+        let jobs = a.value_of("num-jobs").unwrap_or("8").parse()?;
+        return shipcat::export::export_region(&conf, &region, dir, a.value_of("since"), secrets, jobs).await;
     }
     // 4. cluster level commands
     else if let Some(a) = args.subcommand_matches("cluster") {
@@ -921,16 +1811,63 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             if let Some(_) = b.subcommand_matches("install") {
                 return shipcat::cluster::crd_install(&region_base).await;
             }
-            if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::mass_crd(&conf_sec, &conf_base, &region_base, jobs).await;
+            if let Some(c) = b.subcommand_matches("reconcile") {
+                if let Some(dir) = c.value_of("shard-report") {
+                    return shipcat::shard::report(Path::new(dir), "crd-reconcile", &conf_base, &region_base)
+                        .await;
+                }
+                let shard = c
+                    .value_of("shard")
+                    .map(shipcat::shard::Shard::from_str)
+                    .transpose()?;
+                if let Some(why) = c.value_of("override-freeze") {
+                    std::env::set_var(shipcat::freeze::OVERRIDE_FREEZE_EVAR, why);
+                }
+                shipcat::freeze::enforce(&conf_base, &region_base).await?;
+                if !c.is_present("skip-preflight") {
+                    shipcat::preflight::run(&region_base, true).await?;
+                }
+                let ordered = c.is_present("ordered");
+                return shipcat::cluster::mass_crd(
+                    &conf_sec,
+                    &conf_base,
+                    &region_base,
+                    jobs,
+                    shard.as_ref(),
+                    ordered,
+                )
+                .await;
             }
         }
-        if let Some(_b) = a.subcommand_matches("diff") {
+        if let Some(b) = a.subcommand_matches("worker") {
+            let (_, region) = resolve_config(args, ConfigState::Base).await?;
+            return shipcat::worker::run(shipcat::worker::WorkerConfig {
+                region: region.name,
+                n_workers: b.value_of("num-jobs").unwrap_or("8").parse()?,
+            })
+            .await;
+        }
+        if let Some(b) = a.subcommand_matches("diff") {
             let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
-            return shipcat::cluster::mass_diff(&conf, &region).await;
+            if let Some(dir) = b.value_of("shard-report") {
+                return shipcat::shard::report(Path::new(dir), "diff", &conf, &region).await;
+            }
+            let shard = b
+                .value_of("shard")
+                .map(shipcat::shard::Shard::from_str)
+                .transpose()?;
+            let jobs = b.value_of("num-jobs").unwrap_or("10").parse()?;
+            return shipcat::cluster::mass_diff(&conf, &region, jobs, shard.as_ref()).await;
         }
         if let Some(b) = a.subcommand_matches("check") {
             let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+            if let Some(dir) = b.value_of("shard-report") {
+                return shipcat::shard::report(Path::new(dir), "check", &conf, &region).await;
+            }
+            let shard = b
+                .value_of("shard")
+                .map(shipcat::shard::Shard::from_str)
+                .transpose()?;
             let skipped = b
                 .value_of("skip-kinds")
                 .unwrap_or_default()
@@ -938,7 +1875,9 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 .map(String::from)
                 .filter(|s| !s.is_empty())
                 .collect::<Vec<_>>();
-            return shipcat::cluster::mass_template_verify(&conf, &region, &skipped).await;
+            let jobs = b.value_of("num-jobs").unwrap_or("100").parse()?;
+            return shipcat::cluster::mass_template_verify(&conf, &region, &skipped, jobs, shard.as_ref())
+                .await;
         }
 
         if let Some(b) = a.subcommand_matches("vault-policy") {
@@ -973,6 +1912,15 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let res = shipcat::kubectl::get_running_version(&svc, &region.namespace).await?;
         println!("{}", res);
         return Ok(());
+    } else if let Some(a) = args.subcommand_matches("kubectl") {
+        let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+        let context = if args.is_present("region") {
+            shipcat::auth::context_name(&conf, &region)
+        } else {
+            kubectl::current_context().await?
+        };
+        let kargs = a.values_of("args").unwrap().map(String::from).collect();
+        return shipcat::kubectl::wrap(&context, &region, kargs, a.is_present("allow-prod")).await;
     } else if let Some(a) = args.subcommand_matches("port-forward") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
         let service = a.value_of("service").unwrap();
@@ -981,6 +1929,29 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             .stub(&region)
             .await?;
         return shipcat::kubectl::port_forward(&mf).await;
+    } else if let Some(a) = args.subcommand_matches("check-connectivity") {
+        let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
+        let service = a.value_of("service").unwrap();
+        let mf = shipcat_filebacked::load_manifest(service, &conf, &region)
+            .await?
+            .complete(&region)
+            .await?;
+        let image = a.value_of("image").unwrap();
+        return shipcat::connectivity::check(&mf, &region, a.is_present("exec"), image).await;
+    } else if let Some(a) = args.subcommand_matches("queue") {
+        let (_conf, region) = resolve_config(args, ConfigState::Base).await?;
+        if a.subcommand_matches("status").is_some() {
+            return shipcat::queue::status(&region).await;
+        }
+    } else if let Some(a) = args.subcommand_matches("schema") {
+        let kind = a.value_of("kind").unwrap().parse()?;
+        return shipcat::schema::print(kind);
+    } else if let Some(a) = args.subcommand_matches("telemetry") {
+        if a.subcommand_matches("status").is_some() {
+            return shipcat::telemetry::status().await;
+        } else if a.subcommand_matches("off").is_some() {
+            return shipcat::telemetry::off().await;
+        }
     } else if let Some(a) = args.subcommand_matches("debug") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
         let service = a.value_of("service").unwrap();
@@ -990,6 +1961,13 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             .await?;
         let s = ShipKube::new(&mf).await?;
         return shipcat::track::debug(&mf, &s).await;
+    } else if let Some(a) = args.subcommand_matches("replay") {
+        let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+        let service = a.value_of("service").unwrap();
+        let mf = shipcat_filebacked::load_manifest(service, &conf, &region).await?;
+        let pth = Path::new(a.value_of("scenario").unwrap());
+        let tick = Duration::from_millis(a.value_of("tick").unwrap().parse()?);
+        return shipcat::replay::run(&mf, pth, tick).await;
     }
     // these could technically forgo the kube dependency..
     else if let Some(a) = args.subcommand_matches("slack") {
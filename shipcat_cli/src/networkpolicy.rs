@@ -0,0 +1,144 @@
+use serde_json::{json, Value};
+
+use super::{
+    egress::{app_labels, metadata},
+    graph, Config, Manifest, Region, Result,
+};
+use shipcat_definitions::{region::NetworkPolicyConfig, structs::NetworkPolicyMode};
+
+/// Name of the generated ingress policy of a service
+pub fn policy_name(mf: &Manifest) -> String {
+    format!("{}-ingress", mf.name)
+}
+
+/// Whether the service's NetworkPolicies are generated by shipcat
+pub fn is_generated(mf: &Manifest) -> bool {
+    mf.networkPolicies == Some(NetworkPolicyMode::Generated)
+}
+
+/// Selector for the pods of a service
+fn pod_selector(mf: &Manifest) -> Value {
+    json!({ "matchExpressions": [{ "key": "app", "operator": "In", "values": app_labels(mf) }] })
+}
+
+fn namespace_selector(ns: &str) -> Value {
+    json!({ "matchLabels": { "kubernetes.io/metadata.name": ns } })
+}
+
+/// NetworkPolicy admitting ingress only from the dependents of a service
+fn ingress_policy(mf: &Manifest, dependents: &[Manifest], cfg: &NetworkPolicyConfig) -> Value {
+    let mut from = vec![];
+    for d in dependents {
+        let mut peer = json!({ "podSelector": pod_selector(d) });
+        if d.namespace != mf.namespace {
+            peer["namespaceSelector"] = namespace_selector(&d.namespace);
+        }
+        from.push(peer);
+    }
+    for ns in &cfg.allowedNamespaces {
+        from.push(json!({ "namespaceSelector": namespace_selector(ns) }));
+    }
+    // without any peers, an empty ingress list denies all ingress
+    let ingress = if from.is_empty() {
+        vec![]
+    } else {
+        vec![json!({ "from": from })]
+    };
+    json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": metadata(mf, &policy_name(mf)),
+        "spec": {
+            "podSelector": pod_selector(mf),
+            "policyTypes": ["Ingress"],
+            "ingress": ingress,
+        },
+    })
+}
+
+/// Render the ingress policy of a service with `networkPolicies: generated`
+///
+/// Dependents are the services in the region listing the service in their `dependencies`.
+/// Returns `None` for services without generated policies.
+pub async fn render(mf: &Manifest, conf: &Config, reg: &Region) -> Result<Option<String>> {
+    if !is_generated(mf) {
+        return Ok(None);
+    }
+    let dependents = graph::dependents(&mf.name, conf, reg).await?;
+    if dependents.is_empty() {
+        debug!(
+            "{} has no dependents, so its ingress policy only admits allowed namespaces",
+            mf.name
+        );
+    }
+    let cfg = reg.networkPolicy.clone().unwrap_or_default();
+    let policy = ingress_policy(mf, &dependents, &cfg);
+    Ok(Some(serde_yaml::to_string(&policy)?))
+}
+
+/// Whether a NetworkPolicy only selects pods of the service
+///
+/// A policy selecting other pods would restrict services that did not opt in.
+pub fn selects_only_service(mf: &Manifest, policy: &serde_yaml::Value) -> bool {
+    let apps = app_labels(mf);
+    let selector = &policy["spec"]["podSelector"];
+    if let Some(app) = selector["matchLabels"]["app"].as_str() {
+        return apps.iter().any(|a| a == app);
+    }
+    let exprs = match selector["matchExpressions"].as_sequence() {
+        Some(e) => e,
+        None => return false,
+    };
+    exprs.iter().any(|e| {
+        let values = e["values"].as_sequence().cloned().unwrap_or_default();
+        e["key"].as_str() == Some("app")
+            && e["operator"].as_str() == Some("In")
+            && !values.is_empty()
+            && values
+                .iter()
+                .all(|v| v.as_str().map_or(false, |v| apps.iter().any(|a| a == v)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ingress_policy, selects_only_service};
+    use crate::Manifest;
+    use shipcat_definitions::region::NetworkPolicyConfig;
+
+    #[test]
+    fn ingress_policy_from_dependents() {
+        let mf = Manifest::test("fake-storage");
+        let ask = Manifest::test("fake-ask");
+        let mut other = Manifest::test("fake-report");
+        other.namespace = "reporting".into();
+        let cfg = NetworkPolicyConfig {
+            allowedNamespaces: vec!["monitoring".into()],
+        };
+        let policy = ingress_policy(&mf, &[ask, other], &cfg);
+        assert_eq!(policy["metadata"]["name"], "fake-storage-ingress");
+        let from = &policy["spec"]["ingress"][0]["from"];
+        assert_eq!(
+            from[0]["podSelector"]["matchExpressions"][0]["values"][0],
+            "fake-ask"
+        );
+        assert!(from[0]["namespaceSelector"].is_null());
+        assert_eq!(
+            from[1]["namespaceSelector"]["matchLabels"]["kubernetes.io/metadata.name"],
+            "reporting"
+        );
+        assert_eq!(
+            from[2]["namespaceSelector"]["matchLabels"]["kubernetes.io/metadata.name"],
+            "monitoring"
+        );
+
+        let yaml = serde_yaml::to_string(&policy).unwrap();
+        let obj: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert!(selects_only_service(&mf, &obj));
+        assert!(!selects_only_service(&Manifest::test("fake-ask"), &obj));
+
+        // no dependents and no allowed namespaces denies all ingress
+        let closed = ingress_policy(&mf, &[], &NetworkPolicyConfig::default());
+        assert_eq!(closed["spec"]["ingress"].as_array().map(Vec::len), Some(0));
+    }
+}
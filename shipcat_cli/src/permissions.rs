@@ -0,0 +1,371 @@
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde::de::DeserializeOwned;
+use shipcat_definitions::{
+    teams::{Owners, Person},
+    IdentitySource, OidcConfig, PermissionsConfig,
+};
+use std::env;
+
+use super::{exec::executor, Config, Region, Result};
+
+/// Environment variable holding a break-glass justification
+///
+/// Set by `--break-glass`, and recorded in every audit event sent while it is set.
+pub const BREAK_GLASS_EVAR: &str = "SHIPCAT_BREAK_GLASS";
+
+/// Justification given to skip ownership checks, if any
+pub fn break_glass() -> Option<String> {
+    env::var(BREAK_GLASS_EVAR).ok().filter(|j| !j.trim().is_empty())
+}
+
+/// Who is invoking shipcat, as far as the identity source tells
+#[derive(Debug, Default, PartialEq)]
+pub struct Identity {
+    pub github: Option<String>,
+    pub email: Option<String>,
+}
+
+impl Identity {
+    /// The person in teams.yml with this github login or email
+    fn person<'a>(&self, owners: &'a Owners) -> Option<&'a Person> {
+        owners.people.values().find(|p| {
+            let github = match (&self.github, &p.github) {
+                (Some(id), Some(gh)) => id.eq_ignore_ascii_case(gh),
+                _ => false,
+            };
+            let email = self
+                .email
+                .as_ref()
+                .map_or(false, |e| e.eq_ignore_ascii_case(&p.email));
+            github || email
+        })
+    }
+}
+
+/// The user of an active `tsh status` session
+fn tsh_user(status: &str) -> Option<String> {
+    status
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Logged in as:"))
+        .map(|u| u.trim().to_string())
+        .filter(|u| !u.is_empty())
+}
+
+/// Header of a signed jwt
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Audiences of an id token, which may be a single string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Claims of an id token used to identify its user
+#[derive(Deserialize)]
+struct IdClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    email: Option<String>,
+}
+
+/// A public key of the issuer
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// The key set served at the `jwks_uri` of the issuer
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>> {
+    match base64::decode_config(segment, base64::URL_SAFE_NO_PAD) {
+        Ok(b) => Ok(b),
+        Err(e) => bail!("SHIPCAT_OIDC_TOKEN has an invalid segment: {}", e),
+    }
+}
+
+fn decode_json<T: DeserializeOwned>(segment: &str) -> Result<T> {
+    Ok(serde_json::from_slice(&decode_segment(segment)?)?)
+}
+
+/// Fetch the signing keys of an issuer through its discovery document
+async fn issuer_keys(oidc: &OidcConfig) -> Result<Jwks> {
+    let discovery = format!(
+        "{}/.well-known/openid-configuration",
+        oidc.issuer.trim_end_matches('/')
+    );
+    let res = reqwest::get(&discovery).await?.error_for_status()?;
+    let doc: serde_json::Value = serde_json::from_str(&res.text().await?)?;
+    let uri = match doc["jwks_uri"].as_str() {
+        Some(u) => u.to_string(),
+        None => bail!("{} has no jwks_uri", discovery),
+    };
+    let res = reqwest::get(&uri).await?.error_for_status()?;
+    Ok(serde_json::from_str(&res.text().await?)?)
+}
+
+/// The `email` claim of an id token, once verified against the keys of its issuer
+///
+/// Only RS256 signed tokens for the configured issuer and audience are accepted, until they expire.
+fn verified_email(token: &str, keys: &Jwks, oidc: &OidcConfig, now: i64) -> Result<String> {
+    let segments = token.split('.').collect::<Vec<_>>();
+    if segments.len() != 3 {
+        bail!("SHIPCAT_OIDC_TOKEN is not a jwt");
+    }
+    let header: JwtHeader = decode_json(segments[0])?;
+    if header.alg != "RS256" {
+        bail!(
+            "SHIPCAT_OIDC_TOKEN is signed with {}, only RS256 is supported",
+            header.alg
+        );
+    }
+    let key = keys
+        .keys
+        .iter()
+        .filter(|k| k.kty == "RSA")
+        .find(|k| header.kid.is_none() || k.kid == header.kid);
+    let (n, e) = match key.map(|k| (&k.n, &k.e)) {
+        Some((Some(n), Some(e))) => (decode_segment(n)?, decode_segment(e)?),
+        _ => bail!("SHIPCAT_OIDC_TOKEN is not signed by a key of {}", oidc.issuer),
+    };
+    let signature = decode_segment(segments[2])?;
+    let signed = format!("{}.{}", segments[0], segments[1]);
+    let public_key = RsaPublicKeyComponents { n, e };
+    if public_key
+        .verify(&RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), &signature)
+        .is_err()
+    {
+        bail!(
+            "SHIPCAT_OIDC_TOKEN signature does not match the keys of {}",
+            oidc.issuer
+        );
+    }
+
+    let claims: IdClaims = decode_json(segments[1])?;
+    if claims.iss.trim_end_matches('/') != oidc.issuer.trim_end_matches('/') {
+        bail!(
+            "SHIPCAT_OIDC_TOKEN was issued by {}, not {}",
+            claims.iss,
+            oidc.issuer
+        );
+    }
+    let audience = match &claims.aud {
+        Audience::One(a) => a == &oidc.audience,
+        Audience::Many(aud) => aud.contains(&oidc.audience),
+    };
+    if !audience {
+        bail!("SHIPCAT_OIDC_TOKEN was not issued for {}", oidc.audience);
+    }
+    if claims.exp <= now {
+        bail!("SHIPCAT_OIDC_TOKEN has expired");
+    }
+    match claims.email {
+        Some(e) => Ok(e),
+        None => bail!("SHIPCAT_OIDC_TOKEN has no email claim"),
+    }
+}
+
+async fn identity(cfg: &PermissionsConfig) -> Result<Identity> {
+    match &cfg.identity {
+        IdentitySource::Teleport => {
+            let s = executor().output("tsh", &["status".to_string()]).await?;
+            match tsh_user(&s.stdout) {
+                Some(u) => Ok(Identity {
+                    github: Some(u),
+                    email: None,
+                }),
+                None => bail!("No active teleport session to identify you with, run shipcat login"),
+            }
+        }
+        IdentitySource::Oidc => {
+            let token = match env::var("SHIPCAT_OIDC_TOKEN") {
+                Ok(t) => t,
+                Err(_) => bail!("SHIPCAT_OIDC_TOKEN must be set to identify you"),
+            };
+            let oidc = match &cfg.oidc {
+                Some(o) => o,
+                None => bail!("permissions need an oidc issuer to verify SHIPCAT_OIDC_TOKEN"),
+            };
+            let keys = issuer_keys(oidc).await?;
+            let now = chrono::Utc::now().timestamp();
+            Ok(Identity {
+                github: None,
+                email: Some(verified_email(&token, &keys, oidc, now)?),
+            })
+        }
+    }
+}
+
+/// Whether a person may mutate a service owned by a squad
+///
+/// Members and owners of the owning squad, and of any admin squad, are allowed.
+/// Services without a known owner are left to admins.
+pub fn allowed(cfg: &PermissionsConfig, owners: &Owners, person: &str, team: Option<&str>) -> bool {
+    team.into_iter()
+        .chain(cfg.adminSquads.iter().map(String::as_str))
+        .filter_map(|s| owners.squads.get(s))
+        .any(|s| s.members.iter().chain(s.owners.iter()).any(|m| m == person))
+}
+
+/// The squad owning a service, if its manifest says
+async fn owning_team(svc: &str, conf: &Config, reg: &Region) -> Option<String> {
+    match shipcat_filebacked::load_manifest(svc, conf, reg).await {
+        Ok(mf) => mf.metadata.map(|md| md.team),
+        Err(e) => {
+            debug!(
+                "No manifest for {} in {}, only admins may change it: {}",
+                svc, reg.name, e
+            );
+            None
+        }
+    }
+}
+
+/// Verify that the invoking user owns a service before mutating it
+///
+/// Only enforced in the `permissions.environments` of shipcat.conf.
+/// A `--break-glass` justification skips the check.
+pub async fn enforce(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
+    let cfg = match &conf.permissions {
+        Some(p) if p.environments.contains(&reg.environment) => p,
+        _ => return Ok(()),
+    };
+    if let Some(why) = break_glass() {
+        warn!("Skipping ownership checks of {} with break-glass: {}", svc, why);
+        return Ok(());
+    }
+    let team = owning_team(svc, conf, reg).await;
+    let id = identity(cfg).await?;
+    let person = match id.person(&conf.owners) {
+        Some(p) => p,
+        None => bail!("{:?} does not match anyone in teams.yml", id),
+    };
+    if !allowed(cfg, &conf.owners, &person.name, team.as_deref()) {
+        bail!(
+            "{} is not in the squad owning {} in {} (use --break-glass JUSTIFICATION in emergencies)",
+            person.name,
+            svc,
+            reg.name
+        );
+    }
+    debug!("{} may change {} in {}", person.name, svc, reg.name);
+    Ok(())
+}
+
+/// Verify that a person in teams.yml owns a service, or may change every service without one
+///
+/// For requests made on behalf of someone else, like the applies queued through raftcat.
+/// Only admins may change a whole region.
+pub async fn enforce_as(person: &str, svc: Option<&str>, conf: &Config, reg: &Region) -> Result<()> {
+    let cfg = match &conf.permissions {
+        Some(p) if p.environments.contains(&reg.environment) => p,
+        _ => return Ok(()),
+    };
+    if !conf.owners.people.contains_key(person) {
+        bail!("{} does not match anyone in teams.yml", person);
+    }
+    let team = match svc {
+        Some(s) => owning_team(s, conf, reg).await,
+        None => None,
+    };
+    let target = svc.unwrap_or("every service");
+    if !allowed(cfg, &conf.owners, person, team.as_deref()) {
+        bail!("{} may not change {} in {}", person, target, reg.name);
+    }
+    debug!("{} may change {} in {}", person, target, reg.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allowed, tsh_user, verified_email, Identity, Jwks};
+    use shipcat_definitions::{teams::Owners, OidcConfig, PermissionsConfig};
+
+    #[test]
+    fn permissions_ownership() {
+        let owners: Owners = serde_yaml::from_str(
+            r#"
+people:
+  jane.doe: {name: jane.doe, github: janedoe, slack: U1, email: jane.doe@babylonhealth.com}
+  john.doe: {name: john.doe, github: johndoe, slack: U2, email: john.doe@babylonhealth.com}
+  ops.person: {name: ops.person, slack: U3, email: ops.person@babylonhealth.com}
+squads:
+  ask:
+    name: ask
+    members: [jane.doe]
+    github: {team: ask}
+    slack: {}
+  platform:
+    name: platform
+    members: []
+    owners: [ops.person]
+    github: {team: platform}
+    slack: {}
+tribes: {}
+"#,
+        )
+        .unwrap();
+        let cfg: PermissionsConfig =
+            serde_yaml::from_str("{environments: [prod], adminSquads: [platform]}").unwrap();
+
+        assert!(allowed(&cfg, &owners, "jane.doe", Some("ask")));
+        assert!(!allowed(&cfg, &owners, "john.doe", Some("ask")));
+        assert!(allowed(&cfg, &owners, "ops.person", Some("ask")));
+        assert!(!allowed(&cfg, &owners, "jane.doe", None));
+
+        let status = "> Profile URL:  https://teleport.babylontech.co.uk:443\n  Logged in as: JaneDoe\n";
+        let id = Identity {
+            github: tsh_user(status),
+            email: None,
+        };
+        assert_eq!(id.person(&owners).unwrap().name, "jane.doe");
+    }
+
+    #[test]
+    fn permissions_verified_tokens() {
+        let keys: Jwks = serde_json::from_str(
+            r#"{"keys": [{"kty": "RSA", "kid": "k1", "alg": "RS256", "e": "AQAB", "n": "rUSQIRTNPHd-UKuz0gWtczRTC97B44gnlJ3WohpGgzygDF6xApnlzCtSBgsPT23bI27lFRQ3GhIgZ7W1yhTYCoSieDwBSU51ph_edZ3aPEOG3aMuHwcFaW3jDpSpjM6oHVbOVcB0tJDAFAZqGMrO2dm3X7JJnhQabs6bAvJy8aCBopSEMuv3pnGXdqWEikNwnCvOgAEo3bHfdgyl6x_jXmERlznqN6a9ePDKhVlrljJEIZR9Ed0MhjXaj3--xrnMt0OQUEN36OoKzPw6Bm6RiwjIY9r0M_tDmG_EDcav9bBcH8Z6h1_F60QP-1T_hHefbHxcZnB_TPkwS3hFhYrJSw"}]}"#,
+        )
+        .unwrap();
+        let mut oidc = OidcConfig {
+            issuer: "https://login.babylontech.co.uk/".into(),
+            audience: "shipcat".into(),
+        };
+        let now = 1_600_000_000;
+        // {"alg":"RS256","kid":"k1"}.{"iss":..,"aud":"shipcat","exp":4102444800,"email":"john.doe@.."}.signature
+        let header = "eyJhbGciOiJSUzI1NiIsImtpZCI6ImsxIn0";
+        let signature = "CqN6HysCH8X-PqgNC6dvbqr012Rt7BKvypcB0K-55ukIK9-KknLQZm37zw3yI-WU1GDKuXDaz26yXa6QCOvNwgCiVhjgnE5Wap-iW6-ueOdCm6bvQmmY_LJXQUNtcgb8FRZZyXMgftSHzN0asVsDDOoA2Ur_LdhbZOS2xLD01Y_atNY7paFuiKsoTWA_BLrD7g6QZ2ZHUg5B9bxK9T5gpXGHYCk5EGM_GkqVzyoEJmvYVQCOTJnYWiwDjt6Ape30UMQ_1kc3SfQQMmoTSaefUGcid1nl31uR4kRnHiXMOu37Iol8WYhYFZDABtux1xA7rpaJArzinFhcwye6e4Slsw";
+        let claims = "eyJpc3MiOiJodHRwczovL2xvZ2luLmJhYnlsb250ZWNoLmNvLnVrIiwiYXVkIjoic2hpcGNhdCIsImV4cCI6NDEwMjQ0NDgwMCwiZW1haWwiOiJqb2huLmRvZUBiYWJ5bG9uaGVhbHRoLmNvbSJ9";
+        let token = format!("{}.{}.{}", header, claims, signature);
+        assert_eq!(
+            verified_email(&token, &keys, &oidc, now).unwrap(),
+            "john.doe@babylonhealth.com"
+        );
+
+        // the same signature with jane.doe's email in the claims
+        let forged = "eyJpc3MiOiJodHRwczovL2xvZ2luLmJhYnlsb250ZWNoLmNvLnVrIiwiYXVkIjoic2hpcGNhdCIsImV4cCI6NDEwMjQ0NDgwMCwiZW1haWwiOiJqYW5lLmRvZUBiYWJ5bG9uaGVhbHRoLmNvbSJ9";
+        let forged = format!("{}.{}.{}", header, forged, signature);
+        assert!(verified_email(&forged, &keys, &oidc, now).is_err());
+        // unsigned tokens are rejected
+        let unsigned = format!("e30.{}.", claims);
+        assert!(verified_email(&unsigned, &keys, &oidc, now).is_err());
+        // as are expired tokens
+        assert!(verified_email(&token, &keys, &oidc, 4_102_444_800).is_err());
+        // and tokens for other clients
+        oidc.audience = "raftcat".into();
+        assert!(verified_email(&token, &keys, &oidc, now).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{fmt, fs, path::Path};
+use wasmi::{
+    Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, Module, ModuleImportResolver,
+    ModuleInstance, RuntimeArgs, RuntimeValue, Signature, Trap, ValueType,
+};
+
+use shipcat_definitions::{Config, Manifest, ValidationPlugin};
+
+use super::Result;
+
+/// Instructions a plugin may execute per manifest before it is stopped
+const PLUGIN_FUEL: u64 = 10_000_000;
+
+/// Severity of a plugin diagnostic
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel {
+    /// Fails validation
+    Error,
+    /// Reported, but passes validation
+    Warning,
+}
+
+/// A structured validation result from a plugin
+#[derive(Deserialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    pub message: String,
+}
+
+/// The `validationPlugins` from shipcat.conf, compiled once per run
+pub struct Plugins {
+    modules: Vec<(ValidationPlugin, Module)>,
+}
+
+impl Plugins {
+    /// Read and compile every plugin
+    pub fn load(conf: &Config) -> Result<Plugins> {
+        let mut modules = vec![];
+        for p in &conf.validationPlugins {
+            modules.push((p.clone(), compile(p)?));
+        }
+        Ok(Plugins { modules })
+    }
+
+    /// Run all plugins against a manifest
+    ///
+    /// Warnings are logged, and any errors fail the validation.
+    pub fn validate(&self, mf: &Manifest) -> Result<()> {
+        if self.modules.is_empty() {
+            return Ok(());
+        }
+        let data = serde_json::to_vec(mf)?;
+        let mut errs = vec![];
+        for (p, module) in &self.modules {
+            debug!("Running validation plugin {} for {}", p.name, mf.name);
+            for d in run_plugin(p, module, &data, PLUGIN_FUEL)? {
+                match d.level {
+                    DiagnosticLevel::Warning => warn!("{} ({}): {}", mf.name, p.name, d.message),
+                    DiagnosticLevel::Error => {
+                        error!("{} ({}): {}", mf.name, p.name, d.message);
+                        errs.push(d.message);
+                    }
+                }
+            }
+        }
+        if !errs.is_empty() {
+            bail!("{} failed {} validation plugin checks", mf.name, errs.len());
+        }
+        Ok(())
+    }
+}
+
+/// Read a plugin and meter its instructions
+///
+/// Every block of the plugin is made to call an injected `env.gas` import with its cost first.
+fn compile(plugin: &ValidationPlugin) -> Result<Module> {
+    let pth = Path::new(".").join(&plugin.path);
+    let buf = fs::read(&pth).map_err(|e| format!("Failed to read plugin {}: {}", pth.display(), e))?;
+    let raw: parity_wasm::elements::Module = parity_wasm::deserialize_buffer(&buf)
+        .map_err(|e| format!("Invalid plugin {}: {}", plugin.name, e))?;
+    let metered = pwasm_utils::inject_gas_counter(raw, &pwasm_utils::rules::Set::default())
+        .map_err(|_| format!("Failed to meter plugin {}", plugin.name))?;
+    let module = Module::from_parity_wasm_module(metered)
+        .map_err(|e| format!("Invalid plugin {}: {}", plugin.name, e))?;
+    Ok(module)
+}
+
+/// Error stopping a plugin that used up its fuel
+#[derive(Debug)]
+struct OutOfFuel(u64);
+
+impl fmt::Display for OutOfFuel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ran out of fuel after {} instructions", self.0)
+    }
+}
+
+impl HostError for OutOfFuel {}
+
+/// The injected `env.gas` import, which is the only import available to plugins
+struct Fuel {
+    given: u64,
+    left: u64,
+}
+
+impl ModuleImportResolver for Fuel {
+    fn resolve_func(
+        &self,
+        field: &str,
+        _signature: &Signature,
+    ) -> std::result::Result<FuncRef, wasmi::Error> {
+        if field != "gas" {
+            return Err(wasmi::Error::Instantiation(format!(
+                "Plugins cannot import env.{}",
+                field
+            )));
+        }
+        Ok(FuncInstance::alloc_host(
+            Signature::new(&[ValueType::I32][..], None),
+            0,
+        ))
+    }
+}
+
+impl Externals for Fuel {
+    fn invoke_index(
+        &mut self,
+        _index: usize,
+        args: RuntimeArgs,
+    ) -> std::result::Result<Option<RuntimeValue>, Trap> {
+        let cost: u32 = args.nth_checked(0)?;
+        match self.left.checked_sub(u64::from(cost)) {
+            Some(left) => {
+                self.left = left;
+                Ok(None)
+            }
+            None => Err(OutOfFuel(self.given).into()),
+        }
+    }
+}
+
+/// Describe a failed call into a plugin
+fn invoke_failure(plugin: &ValidationPlugin, export: &str, e: wasmi::Error) -> String {
+    match e.as_host_error() {
+        Some(h) => format!("Plugin {} {} in {}", plugin.name, h, export),
+        None => format!("Plugin {} failed to {}: {}", plugin.name, export, e),
+    }
+}
+
+/// Evaluate a plugin module against manifest json
+///
+/// A plugin module must export:
+/// - `memory`: its linear memory
+/// - `alloc(len: i32) -> i32`: a pointer to `len` writable bytes for the input
+/// - `validate(ptr: i32, len: i32) -> i64`: validates the input manifest json,
+///   returning a pointer (high 32 bits) and length (low 32 bits) of a json list of diagnostics
+///
+/// Plugins are sandboxed; no host functions are importable.
+/// Each manifest gets a fresh instance with `fuel` instructions to spend.
+fn run_plugin(plugin: &ValidationPlugin, module: &Module, data: &[u8], fuel: u64) -> Result<Vec<Diagnostic>> {
+    let mut fuel = Fuel {
+        given: fuel,
+        left: fuel,
+    };
+    let instance = ModuleInstance::new(module, &ImportsBuilder::new().with_resolver("env", &fuel))
+        .map_err(|e| format!("Failed to instantiate plugin {}: {}", plugin.name, e))?
+        .assert_no_start();
+    let memory = match instance.export_by_name("memory") {
+        Some(m) if m.as_memory().is_some() => m.as_memory().unwrap().clone(),
+        _ => bail!("Plugin {} does not export its memory", plugin.name),
+    };
+
+    let ptr = match instance
+        .invoke_export("alloc", &[RuntimeValue::I32(data.len() as i32)], &mut fuel)
+        .map_err(|e| invoke_failure(plugin, "alloc", e))?
+    {
+        Some(RuntimeValue::I32(p)) => p,
+        _ => bail!("Plugin {} alloc must return an i32 pointer", plugin.name),
+    };
+    memory
+        .set(ptr as u32, data)
+        .map_err(|e| format!("Plugin {} input does not fit: {}", plugin.name, e))?;
+
+    let args = [RuntimeValue::I32(ptr), RuntimeValue::I32(data.len() as i32)];
+    let packed = match instance
+        .invoke_export("validate", &args, &mut fuel)
+        .map_err(|e| invoke_failure(plugin, "validate", e))?
+    {
+        Some(RuntimeValue::I64(p)) => p as u64,
+        _ => bail!("Plugin {} validate must return an i64", plugin.name),
+    };
+    let (out_ptr, out_len) = ((packed >> 32) as u32, (packed & 0xffff_ffff) as usize);
+    let out = memory
+        .get(out_ptr, out_len)
+        .map_err(|e| format!("Plugin {} returned invalid output: {}", plugin.name, e))?;
+    let res = serde_json::from_slice(&out)?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, run_plugin, DiagnosticLevel};
+    use shipcat_definitions::ValidationPlugin;
+
+    #[test]
+    fn plugin_diagnostics() {
+        // NB: tests run in the crate root - fixtures are in the workspace tests dir
+        let plugin = ValidationPlugin {
+            name: "warn".into(),
+            path: "../tests/plugins/warn.wasm".into(),
+        };
+        let module = compile(&plugin).unwrap();
+        let res = run_plugin(&plugin, &module, b"{\"name\": \"fake-ask\"}", 1000).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].level, DiagnosticLevel::Warning);
+        assert_eq!(res[0].message, "fake-ask is fake");
+        // modules are reused across manifests
+        assert_eq!(run_plugin(&plugin, &module, b"{}", 1000).unwrap().len(), 1);
+
+        let missing = ValidationPlugin {
+            name: "missing".into(),
+            path: "../tests/plugins/missing.wasm".into(),
+        };
+        assert!(compile(&missing).is_err());
+
+        let endless = ValidationPlugin {
+            name: "loop".into(),
+            path: "../tests/plugins/loop.wasm".into(),
+        };
+        let module = compile(&endless).unwrap();
+        let err = run_plugin(&endless, &module, b"{}", 1000).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Plugin loop ran out of fuel after 1000 instructions in validate"
+        );
+    }
+}
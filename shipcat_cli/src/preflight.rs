@@ -0,0 +1,153 @@
+use k8s_openapi::{
+    api::core::v1::Namespace,
+    apiextensions_apiserver::pkg::apis::apiextensions::v1beta1::CustomResourceDefinition,
+    apimachinery::pkg::version::Info,
+};
+use kube::{api::Api, client::APIClient};
+
+use super::{kubeapi, ErrorKind, Region, Result, Webhook};
+use shipcat_definitions::SecretStoreConfig;
+
+/// Oldest kubernetes minor version we generate resources for
+const MIN_KUBE_MINOR: u32 = 14;
+
+/// CRDs (and the version of them) that shipcat apply and reconcile rely on
+const CRDS: [(&str, &str); 2] = [
+    ("shipcatmanifests.babylontech.co.uk", "v1"),
+    ("shipcatconfigs.babylontech.co.uk", "v1"),
+];
+
+/// CRD needed in regions with a `rolloutQueue`
+const QUEUE_CRD: (&str, &str) = ("shipcatrollouttickets.babylontech.co.uk", "v1");
+
+fn fail(check: &str, reason: String) -> ErrorKind {
+    ErrorKind::PreflightFailure(check.into(), reason)
+}
+
+/// Parse a kubernetes minor version (which can have a trailing `+` on managed clusters)
+fn parse_minor(minor: &str) -> Option<u32> {
+    minor.trim_end_matches('+').parse().ok()
+}
+
+async fn kube_api(client: &APIClient) -> Result<()> {
+    let req = http::Request::get("/version")
+        .body(vec![])
+        .map_err(|e| fail("kube api", e.to_string()))?;
+    let info: Info = client
+        .request(req)
+        .await
+        .map_err(|e| fail("kube api", format!("apiserver unreachable: {}", e)))?;
+    match parse_minor(&info.minor) {
+        Some(m) if info.major == "1" && m >= MIN_KUBE_MINOR => {
+            debug!("apiserver version {}", info.git_version);
+            Ok(())
+        }
+        _ => Err(fail(
+            "kube api",
+            format!(
+                "apiserver version {} is older than 1.{}",
+                info.git_version, MIN_KUBE_MINOR
+            ),
+        )
+        .into()),
+    }
+}
+
+async fn namespace(client: &APIClient, reg: &Region) -> Result<()> {
+    let api: Api<Namespace> = Api::all(client.clone());
+    api.get(&reg.namespace)
+        .await
+        .map_err(|e| fail("namespace", format!("{} not found: {}", reg.namespace, e)))?;
+    Ok(())
+}
+
+async fn crds(client: &APIClient, reg: &Region) -> Result<()> {
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    let queue = reg.rolloutQueue.as_ref().map(|_| QUEUE_CRD);
+    for (name, version) in CRDS.iter().chain(queue.iter()) {
+        let crd = api.get(name).await.map_err(|e| {
+            fail(
+                "crd",
+                format!("{} not installed (see shipcat cluster crd install): {}", name, e),
+            )
+        })?;
+        let served = crd
+            .spec
+            .versions
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|v| v.served)
+            .map(|v| v.name)
+            .chain(crd.spec.version)
+            .any(|v| &v == version);
+        if !served {
+            return Err(fail("crd", format!("{} does not serve version {}", name, version)).into());
+        }
+    }
+    Ok(())
+}
+
+async fn vault(reg: &Region) -> Result<()> {
+    // health endpoint is unauthenticated, and returns 200/429/472/473 for usable vaults
+    let url = format!("{}/v1/sys/health", reg.vault.url.trim_end_matches('/'));
+    let res = reqwest::get(&url)
+        .await
+        .map_err(|e| fail("vault", format!("{} unreachable: {}", reg.vault.url, e)))?;
+    let status = res.status().as_u16();
+    if status == 501 || status == 503 {
+        let reason = format!("{} is sealed or uninitialized ({})", reg.vault.url, status);
+        return Err(fail("vault", reason).into());
+    }
+    Ok(())
+}
+
+async fn webhooks(reg: &Region) -> Result<()> {
+    for wh in &reg.webhooks {
+        let url = match wh {
+            Webhook::Audit(h) => &h.url,
+        };
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or(443);
+        let resolved = tokio::net::lookup_host((host, port))
+            .await
+            .map(|mut addrs| addrs.next().is_some())
+            .unwrap_or(false);
+        if !resolved {
+            return Err(fail("webhook", format!("cannot resolve {}", url)).into());
+        }
+    }
+    Ok(())
+}
+
+/// Fast checks before commands that mutate a region
+///
+/// Verifies the cluster can take the change before anything is touched,
+/// failing with an error categorised by the check that failed.
+/// Vault is only checked when `secrets` are needed from it.
+pub async fn run(reg: &Region, secrets: bool) -> Result<()> {
+    let client = kubeapi::make_client()
+        .await
+        .map_err(|e| fail("kube api", e.to_string()))?;
+    kube_api(&client).await?;
+    namespace(&client, reg).await?;
+    crds(&client, reg).await?;
+    let uses_vault = matches!(reg.secretStore, None | Some(SecretStoreConfig::Vault));
+    if secrets && uses_vault {
+        vault(reg).await?;
+    }
+    webhooks(reg).await?;
+    debug!("preflight checks passed for {}", reg.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_minor;
+
+    #[test]
+    fn preflight_kube_minor() {
+        assert_eq!(parse_minor("14"), Some(14));
+        assert_eq!(parse_minor("15+"), Some(15));
+        assert_eq!(parse_minor("x"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use super::{Manifest, Region, Result};
+use shipcat_definitions::region::PrometheusRulesConfig;
+
+fn rules_config(reg: &Region) -> Option<&PrometheusRulesConfig> {
+    reg.prometheus.as_ref().and_then(|p| p.rules.as_ref())
+}
+
+/// Render a service's alerts and recording rules as one PrometheusRule object
+///
+/// Returns `None` in regions without `prometheus.rules`, or for services without rules.
+pub fn render(mf: &Manifest, reg: &Region) -> Result<Option<String>> {
+    match rules_config(reg) {
+        Some(cfg) if !mf.prometheusAlerts.is_empty() || !mf.sloRecordingRules.is_empty() => {
+            Ok(Some(rule(mf, cfg)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// PrometheusRule yaml for a service
+///
+/// The object is labelled for the region's Prometheus `ruleSelector`, and owned by the
+/// service's ShipcatManifest like the chart objects.
+fn rule(mf: &Manifest, cfg: &PrometheusRulesConfig) -> Result<String> {
+    let mut groups = vec![];
+    if !mf.sloRecordingRules.is_empty() {
+        let rules = mf
+            .sloRecordingRules
+            .iter()
+            .map(|r| json!({ "record": r.record, "expr": r.expr, "labels": r.labels }))
+            .collect::<Vec<_>>();
+        groups.push(json!({ "name": format!("{}.recording", mf.name), "rules": rules }));
+    }
+    if !mf.prometheusAlerts.is_empty() {
+        let rules = mf
+            .prometheusAlerts
+            .iter()
+            .map(|a| {
+                let mut labels = json!(a.labels);
+                labels["severity"] = json!(a.severity);
+                json!({
+                    "alert": a.name,
+                    "expr": a.expr,
+                    "for": a.min_duration,
+                    "labels": labels,
+                    "annotations": { "summary": a.summary, "description": a.description },
+                })
+            })
+            .collect::<Vec<_>>();
+        groups.push(json!({ "name": format!("{}.alerts", mf.name), "rules": rules }));
+    }
+
+    let mut labels = cfg.labels.clone();
+    labels.extend(mf.labels.clone());
+    labels.insert("app.kubernetes.io/name".into(), mf.name.clone());
+    labels.insert("app.kubernetes.io/managed-by".into(), "shipcat".into());
+    if let Some(v) = &mf.version {
+        labels.insert("app.kubernetes.io/version".into(), v.clone());
+    }
+    let rule = json!({
+        "apiVersion": "monitoring.coreos.com/v1",
+        "kind": "PrometheusRule",
+        "metadata": {
+            "name": mf.name,
+            "namespace": mf.namespace,
+            "labels": labels,
+            "ownerReferences": [{
+                "apiVersion": "babylontech.co.uk/v1",
+                "kind": "ShipcatManifest",
+                "name": mf.name,
+                "uid": mf.uid.clone().unwrap_or_default(),
+                "controller": false,
+            }],
+        },
+        "spec": { "groups": groups },
+    });
+    Ok(serde_yaml::to_string(&rule)?)
+}
+
+/// Region selector labels missing from a rendered PrometheusRule
+///
+/// Such rules would not be picked up by the region's Prometheus.
+pub fn missing_selector_labels(reg: &Region, labels: &BTreeMap<String, String>) -> Vec<String> {
+    match rules_config(reg) {
+        Some(cfg) => missing_labels(cfg, labels),
+        None => vec![],
+    }
+}
+
+fn missing_labels(cfg: &PrometheusRulesConfig, labels: &BTreeMap<String, String>) -> Vec<String> {
+    cfg.labels
+        .iter()
+        .filter(|(k, v)| labels.get(*k) != Some(v))
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{missing_labels, rule};
+    use crate::Manifest;
+    use shipcat_definitions::region::PrometheusRulesConfig;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn prometheus_rule_output() {
+        let mut mf = Manifest::test("fake-ask");
+        mf.version = Some("1.0.0".into());
+        mf.prometheusAlerts = vec![serde_yaml::from_str(
+            r#"
+name: HighErrorRate
+summary: errors
+description: too many errors
+expr: rate(errors_total[5m]) > 1
+min_duration: 5m
+severity: error
+"#,
+        )
+        .unwrap()];
+        let mut cfg = PrometheusRulesConfig::default();
+        cfg.labels.insert("role".into(), "alert-rules".into());
+
+        let out = rule(&mf, &cfg).unwrap();
+        let obj: serde_yaml::Value = serde_yaml::from_str(&out).unwrap();
+        assert_eq!(obj["kind"].as_str(), Some("PrometheusRule"));
+        let labels: BTreeMap<String, String> =
+            serde_yaml::from_value(obj["metadata"]["labels"].clone()).unwrap();
+        assert_eq!(labels["app.kubernetes.io/name"], "fake-ask");
+        assert!(missing_labels(&cfg, &labels).is_empty());
+        assert_eq!(missing_labels(&cfg, &BTreeMap::new()), vec!["role=alert-rules"]);
+
+        let alert = &obj["spec"]["groups"][0]["rules"][0];
+        assert_eq!(alert["alert"].as_str(), Some("HighErrorRate"));
+        assert_eq!(alert["for"].as_str(), Some("5m"));
+        assert_eq!(alert["labels"]["severity"].as_str(), Some("error"));
+        assert_eq!(alert["annotations"]["summary"].as_str(), Some("errors"));
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Duration, Utc};
+use futures_timer::Delay;
+use kube::api::{Api, DeleteParams, ListParams, PatchParams, PostParams};
+use serde_json::json;
+use shipcat_definitions::{queue::RolloutTicket, Manifest, Region, RolloutQueueConfig, ShipcatRolloutTicket};
+
+use super::{kubeapi, ErrorKind, Result};
+
+/// Seconds a waiting ticket counts for without being renewed
+const RENEW_SECONDS: i64 = 60;
+/// Seconds a running rollout keeps its slot beyond the estimated rollout time
+const ROLLOUT_GRACE_SECONDS: i64 = 300;
+/// Seconds between checks of the queue while waiting
+const POLL_SECONDS: u64 = 5;
+
+/// Where a rollout stands in the queue
+#[derive(Debug, PartialEq)]
+pub enum Place {
+    /// Holds one of the slots
+    Rolling,
+    /// Waiting, 1-indexed position behind the running rollouts
+    Queued(usize),
+    /// No live ticket
+    Absent,
+}
+
+/// Place of a service in an ordered queue
+pub fn place(queue: &[RolloutTicket], svc: &str, max_concurrent: u32) -> Place {
+    let slots = max_concurrent as usize;
+    match queue.iter().position(|t| t.service == svc) {
+        Some(i) if i < slots => Place::Rolling,
+        Some(i) => Place::Queued(i + 1 - slots),
+        None => Place::Absent,
+    }
+}
+
+async fn ticket_api(ns: &str) -> Result<Api<ShipcatRolloutTicket>> {
+    let client = kubeapi::make_client().await?;
+    Ok(Api::namespaced(client, ns))
+}
+
+async fn live_queue(api: &Api<ShipcatRolloutTicket>) -> Result<Vec<RolloutTicket>> {
+    let tickets = api
+        .list(&ListParams::default())
+        .await
+        .map_err(ErrorKind::KubeError)?;
+    let specs = tickets.items.into_iter().map(|t| t.spec).collect();
+    Ok(RolloutTicket::queue(specs, Utc::now()))
+}
+
+/// A rollout slot held in a region's rollout queue
+pub struct Slot {
+    api: Api<ShipcatRolloutTicket>,
+    name: String,
+}
+
+impl Slot {
+    async fn extend(&self, seconds: i64) -> Result<()> {
+        let expires = Utc::now() + Duration::seconds(seconds);
+        let patch = json!({ "spec": { "expires": expires } });
+        self.api
+            .patch(&self.name, &PatchParams::default(), serde_json::to_vec(&patch)?)
+            .await
+            .map_err(ErrorKind::KubeError)?;
+        Ok(())
+    }
+
+    /// Give the slot to the next rollout in the queue
+    ///
+    /// Best effort; an unreleased slot expires on its own.
+    pub async fn release(self) {
+        if let Err(e) = self.api.delete(&self.name, &DeleteParams::default()).await {
+            warn!("Failed to release rollout slot for {}: {}", self.name, e);
+        }
+    }
+}
+
+/// Wait for a rollout slot when the region limits concurrent rollouts
+///
+/// Returns `None` in regions without a `rolloutQueue`.
+/// While waiting, the queue position is shown on a progress spinner.
+pub async fn acquire(mf: &Manifest, region: &Region, version: &str) -> Result<Option<Slot>> {
+    let cfg = match &region.rolloutQueue {
+        Some(q) => q,
+        None => return Ok(None),
+    };
+    // tickets live in the region namespace so the queue spans every service
+    let api = ticket_api(&region.namespace).await?;
+    let now = Utc::now();
+    let ticket = ShipcatRolloutTicket::new(&mf.name, RolloutTicket {
+        service: mf.name.clone(),
+        version: version.into(),
+        requested: now,
+        expires: now + Duration::seconds(RENEW_SECONDS),
+    });
+    // a leftover ticket from an earlier apply of the service loses its place
+    let _ = api.delete(&mf.name, &DeleteParams::default()).await;
+    api.create(&PostParams::default(), &ticket)
+        .await
+        .map_err(ErrorKind::KubeError)?;
+    let slot = Slot {
+        api,
+        name: mf.name.clone(),
+    };
+    match wait_for_slot(&slot, mf, cfg).await {
+        Ok(()) => Ok(Some(slot)),
+        Err(e) => {
+            slot.release().await;
+            Err(e)
+        }
+    }
+}
+
+async fn wait_for_slot(slot: &Slot, mf: &Manifest, cfg: &RolloutQueueConfig) -> Result<()> {
+    use indicatif::{ProgressBar, ProgressStyle};
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::default_spinner().template("> {spinner} {prefix} ({elapsed}) {msg}"));
+    pb.set_prefix(&mf.name);
+
+    let started = Utc::now();
+    loop {
+        let queue = live_queue(&slot.api).await?;
+        match place(&queue, &mf.name, cfg.max_concurrent) {
+            Place::Rolling => {
+                pb.finish_and_clear();
+                let rollout = i64::from(mf.estimate_wait_time()) + ROLLOUT_GRACE_SECONDS;
+                return slot.extend(rollout).await;
+            }
+            Place::Queued(n) => {
+                let waiting = queue.len() - cfg.max_concurrent as usize;
+                pb.set_message(&format!("queued for rollout (position {} of {})", n, waiting));
+            }
+            Place::Absent => bail!("Rollout ticket for {} disappeared from the queue", mf.name),
+        }
+        if Utc::now() - started > Duration::seconds(cfg.max_wait_seconds.into()) {
+            pb.finish_and_clear();
+            bail!(
+                "Gave up waiting {}s for a rollout slot for {}",
+                cfg.max_wait_seconds,
+                mf.name
+            );
+        }
+        slot.extend(RENEW_SECONDS).await?;
+        pb.tick();
+        Delay::new(std::time::Duration::from_secs(POLL_SECONDS)).await;
+    }
+}
+
+fn format_age(since: DateTime<Utc>) -> String {
+    let secs = (Utc::now() - since).num_seconds().max(0);
+    format!("{}m{:02}s", secs / 60, secs % 60)
+}
+
+/// Print the rollout queue of a region
+pub async fn status(region: &Region) -> Result<()> {
+    let cfg = match &region.rolloutQueue {
+        Some(q) => q,
+        None => bail!("Region {} does not have a rolloutQueue", region.name),
+    };
+    let api = ticket_api(&region.namespace).await?;
+    let queue = live_queue(&api).await?;
+    println!(
+        "{} rollout slots in {}, {} in use",
+        cfg.max_concurrent,
+        region.name,
+        queue.len().min(cfg.max_concurrent as usize)
+    );
+    if queue.is_empty() {
+        return Ok(());
+    }
+    println!("{:<10} {:<30} {:<20} AGE", "STATE", "SERVICE", "VERSION");
+    for t in &queue {
+        let state = match place(&queue, &t.service, cfg.max_concurrent) {
+            Place::Rolling => "rolling".to_string(),
+            Place::Queued(n) => format!("queued #{}", n),
+            Place::Absent => continue,
+        };
+        println!(
+            "{:<10} {:<30} {:<20} {}",
+            state,
+            t.service,
+            t.version,
+            format_age(t.requested)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{place, Place};
+    use chrono::{Duration, Utc};
+    use shipcat_definitions::queue::RolloutTicket;
+
+    #[test]
+    fn rollout_queue_place() {
+        let now = Utc::now();
+        let queue = (0..4)
+            .map(|i| RolloutTicket {
+                service: format!("svc-{}", i),
+                version: "1.0.0".into(),
+                requested: now + Duration::seconds(i),
+                expires: now + Duration::seconds(60),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(place(&queue, "svc-0", 2), Place::Rolling);
+        assert_eq!(place(&queue, "svc-1", 2), Place::Rolling);
+        assert_eq!(place(&queue, "svc-2", 2), Place::Queued(1));
+        assert_eq!(place(&queue, "svc-3", 2), Place::Queued(2));
+        assert_eq!(place(&queue, "svc-4", 2), Place::Absent);
+    }
+}
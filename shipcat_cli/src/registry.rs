@@ -0,0 +1,243 @@
+use regex::Regex;
+use reqwest::{header, Client, RequestBuilder, StatusCode};
+use std::{collections::BTreeMap, env};
+
+use super::{exec::executor, Result};
+use shipcat_definitions::{Config, Manifest, RegistryConfig, RegistryKind};
+
+/// Manifest media types accepted when looking up a tag
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.v2+json, \
+                              application/vnd.docker.distribution.manifest.list.v2+json, \
+                              application/vnd.oci.image.manifest.v1+json, \
+                              application/vnd.oci.image.index.v1+json";
+
+/// An image reference split into its registry host and repository
+#[derive(Debug, PartialEq)]
+pub struct ImageRef {
+    pub host: String,
+    pub repository: String,
+}
+
+impl ImageRef {
+    /// Parse an image name (without tag) like docker does
+    ///
+    /// Names without a registry host are on docker hub, where official images live under `library/`.
+    pub fn parse(image: &str) -> ImageRef {
+        let mut parts = image.splitn(2, '/');
+        let first = parts.next().unwrap_or_default();
+        match parts.next() {
+            Some(rest) if first.contains('.') || first.contains(':') || first == "localhost" => ImageRef {
+                host: first.to_string(),
+                repository: rest.to_string(),
+            },
+            Some(_) => ImageRef {
+                host: "registry-1.docker.io".into(),
+                repository: image.to_string(),
+            },
+            None => ImageRef {
+                host: "registry-1.docker.io".into(),
+                repository: format!("library/{}", image),
+            },
+        }
+    }
+}
+
+/// Parameters of a `WWW-Authenticate: Bearer` challenge
+fn parse_challenge(header: &str) -> Option<BTreeMap<String, String>> {
+    if !header.trim_start().to_lowercase().starts_with("bearer ") {
+        return None;
+    }
+    let re = Regex::new(r#"(\w+)="([^"]*)""#).unwrap();
+    let params = re
+        .captures_iter(header)
+        .map(|c| (c[1].to_string(), c[2].to_string()))
+        .collect::<BTreeMap<_, _>>();
+    if params.contains_key("realm") {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+/// Run a credential helper and return its trimmed stdout
+async fn helper_output(program: &str, args: &[&str]) -> Result<String> {
+    let args = args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    debug!("{} {}", program, args.join(" "));
+    let s = executor().output(program, &args).await?;
+    if !s.success {
+        bail!(
+            "{} failed to produce registry credentials: {}",
+            program,
+            s.stderr.trim()
+        );
+    }
+    Ok(s.stdout.trim().to_string())
+}
+
+/// Basic credentials for a registry, if any
+async fn credentials(reg: Option<&RegistryConfig>) -> Result<Option<(String, String)>> {
+    let reg = match reg {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    match reg.kind {
+        RegistryKind::Generic | RegistryKind::Quay => {
+            let creds = reg.credentialsEnv.as_ref().and_then(|e| env::var(e).ok());
+            if let Some(c) = creds {
+                let mut split = c.splitn(2, ':');
+                let user = split.next().unwrap_or_default().to_string();
+                let pass = split.next().unwrap_or_default().to_string();
+                return Ok(Some((user, pass)));
+            }
+            Ok(None)
+        }
+        RegistryKind::Ecr => {
+            // {account}.dkr.ecr.{region}.amazonaws.com
+            let region = match reg.host.split('.').nth(3) {
+                Some(r) => r.to_string(),
+                None => bail!("Cannot infer the aws region of ecr registry {}", reg.host),
+            };
+            let pass = helper_output("aws", &["ecr", "get-login-password", "--region", &region]).await?;
+            Ok(Some(("AWS".into(), pass)))
+        }
+        RegistryKind::Gcr => {
+            let token = helper_output("gcloud", &["auth", "print-access-token"]).await?;
+            Ok(Some(("oauth2accesstoken".into(), token)))
+        }
+    }
+}
+
+/// Client checking image tags against registries
+///
+/// Follows the docker registry v2 token flow: an unauthenticated request is answered with
+/// a bearer challenge, which is exchanged for a pull token (using basic credentials if any).
+pub struct Registries {
+    client: Client,
+    configs: Vec<RegistryConfig>,
+}
+
+impl Registries {
+    pub fn new(conf: &Config) -> Result<Self> {
+        Ok(Registries {
+            client: Client::builder().user_agent("rust-reqwest/shipcat").build()?,
+            configs: conf.registries.clone(),
+        })
+    }
+
+    fn config(&self, host: &str) -> Option<&RegistryConfig> {
+        self.configs.iter().find(|r| r.host == host)
+    }
+
+    fn head(&self, img: &ImageRef, tag: &str) -> RequestBuilder {
+        let url = format!("https://{}/v2/{}/manifests/{}", img.host, img.repository, tag);
+        self.client.head(&url).header(header::ACCEPT, MANIFEST_TYPES)
+    }
+
+    /// Exchange a bearer challenge for a pull token
+    async fn token(&self, challenge: &BTreeMap<String, String>, img: &ImageRef) -> Result<String> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let scope = challenge
+            .get("scope")
+            .cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", img.repository));
+        let mut query = vec![("scope", scope)];
+        if let Some(service) = challenge.get("service") {
+            query.push(("service", service.clone()));
+        }
+        let mut req = self.client.get(&challenge["realm"]).query(&query);
+        if let Some((user, pass)) = credentials(self.config(&img.host)).await? {
+            req = req.basic_auth(user, Some(pass));
+        }
+        let res: TokenResponse = req.send().await?.error_for_status()?.json().await?;
+        match res.token.or(res.access_token) {
+            Some(t) => Ok(t),
+            None => bail!("{} returned no token for {}", challenge["realm"], img.repository),
+        }
+    }
+
+    /// Whether `image:tag` exists in its registry
+    pub async fn exists(&self, image: &str, tag: &str) -> Result<bool> {
+        let img = ImageRef::parse(image);
+        let mut res = self.head(&img, tag).send().await?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            let challenge = res
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(parse_challenge);
+            res = match challenge {
+                Some(c) => {
+                    let token = self.token(&c, &img).await?;
+                    self.head(&img, tag).bearer_auth(token).send().await?
+                }
+                // registries like ecr take basic credentials directly
+                None => match credentials(self.config(&img.host)).await? {
+                    Some((user, pass)) => self.head(&img, tag).basic_auth(user, Some(pass)).send().await?,
+                    None => bail!("{} requires credentials, but none are configured", img.host),
+                },
+            };
+        }
+        match res.status() {
+            s if s.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            s => bail!("{} answered {} for {}:{}", img.host, s, image, tag),
+        }
+    }
+}
+
+/// Images with pinned tags used by a manifest
+///
+/// The main image along with sidecars, init containers and cronjobs that set their own image.
+pub fn manifest_images(mf: &Manifest) -> Vec<(String, String)> {
+    let mut res = vec![];
+    if let (Some(image), Some(version)) = (&mf.image, &mf.version) {
+        res.push((image.clone(), version.clone()));
+    }
+    let containers = mf
+        .sidecars
+        .iter()
+        .chain(mf.initContainers.iter())
+        .chain(mf.cronJobs.iter().map(|cj| &cj.container));
+    for c in containers {
+        if let (Some(image), Some(version)) = (&c.image, &c.version) {
+            res.push((image.clone(), version.clone()));
+        }
+    }
+    res.sort();
+    res.dedup();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_challenge, ImageRef};
+
+    #[test]
+    fn registry_image_refs() {
+        let img = ImageRef::parse("quay.io/babylonhealth/fake-ask");
+        assert_eq!(img.host, "quay.io");
+        assert_eq!(img.repository, "babylonhealth/fake-ask");
+        let img = ImageRef::parse("localhost:5000/fake-ask");
+        assert_eq!(img.host, "localhost:5000");
+        let img = ImageRef::parse("bitnami/redis");
+        assert_eq!(img.host, "registry-1.docker.io");
+        assert_eq!(img.repository, "bitnami/redis");
+        assert_eq!(ImageRef::parse("nginx").repository, "library/nginx");
+    }
+
+    #[test]
+    fn registry_challenges() {
+        let c = parse_challenge(
+            r#"Bearer realm="https://quay.io/v2/auth",service="quay.io",scope="repository:a/b:pull""#,
+        )
+        .unwrap();
+        assert_eq!(c["realm"], "https://quay.io/v2/auth");
+        assert_eq!(c["service"], "quay.io");
+        assert_eq!(c["scope"], "repository:a/b:pull");
+        assert!(parse_challenge(r#"Basic realm="https://123.dkr.ecr.eu-west-2.amazonaws.com/""#).is_none());
+    }
+}
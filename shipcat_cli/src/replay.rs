@@ -0,0 +1,335 @@
+use async_trait::async_trait;
+use chrono::{Duration as ChronoDuration, Utc};
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    core::v1::{Event, Pod},
+};
+use kube::api::ObjectList;
+use serde_json::json;
+use std::{path::Path, sync::Mutex, time::Duration};
+
+use super::{kubeapi::WorkloadApi, track, ErrorKind, Result};
+use shipcat_definitions::{Manifest, PrimaryWorkload};
+
+/// A scripted rollout of a workload
+///
+/// Every status query of the workload moves the scenario to its next step,
+/// and the last step repeats once the scenario runs out.
+///
+/// ```yaml
+/// workload: Deployment
+/// hash: 5d8f7c9b4
+/// version: 1.2.0
+/// steps:
+/// - replicas: 2
+///   ready: 0
+///   message: ReplicaSet "fake-ask-5d8f7c9b4" is progressing.
+/// - replicas: 2
+///   ready: 1
+///   crashing: 1
+/// - replicas: 2
+///   ready: 2
+/// ```
+///
+/// With `pullSeconds`, new pods report image pulls taking that long,
+/// and steps can have pods still `pulling` the image.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scenario {
+    /// Kind of workload being rolled out
+    pub workload: PrimaryWorkload,
+    /// Pod template hash (or statefulset revision) of the new pods, unused for daemonsets
+    pub hash: String,
+    /// Version of the new pods
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// State of the rollout at each status query
+    pub steps: Vec<Step>,
+    /// How long each new pod takes to pull the image, if it is not on the node
+    #[serde(default, rename = "pullSeconds")]
+    pub pull_seconds: Option<u32>,
+}
+fn default_version() -> String {
+    "1.0.0".into()
+}
+
+/// State of a rollout at one point in time
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Step {
+    /// Pods created for the new version
+    pub replicas: i32,
+    /// How many of the new pods are ready
+    pub ready: i32,
+    /// How many of the new pods that are not ready are crashlooping
+    #[serde(default)]
+    pub crashing: i32,
+    /// How many of the new pods that are not ready or crashing are pulling the image
+    #[serde(default)]
+    pub pulling: i32,
+    /// Message of the Progressing condition
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Fail the status query with this error instead
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Scenario {
+    pub fn read(pth: &Path) -> Result<Scenario> {
+        let data = std::fs::read_to_string(pth)?;
+        let scenario: Scenario = serde_yaml::from_str(&data)?;
+        if scenario.steps.is_empty() {
+            bail!("Scenario {} has no steps", pth.display());
+        }
+        Ok(scenario)
+    }
+}
+
+/// A fake kube api replaying a `Scenario` for a service
+pub struct ScenarioKube {
+    name: String,
+    scenario: Scenario,
+    step: Mutex<usize>,
+}
+
+impl ScenarioKube {
+    pub fn new(name: &str, scenario: Scenario) -> Self {
+        ScenarioKube {
+            name: name.into(),
+            scenario,
+            step: Mutex::new(0),
+        }
+    }
+
+    fn current(&self) -> Step {
+        let idx = *self.step.lock().unwrap();
+        self.scenario.steps[idx].clone()
+    }
+
+    /// Return the current step, and move on to the next one
+    fn advance(&self) -> Result<Step> {
+        let mut idx = self.step.lock().unwrap();
+        let step = self.scenario.steps[*idx].clone();
+        *idx = std::cmp::min(*idx + 1, self.scenario.steps.len() - 1);
+        if let Some(e) = &step.error {
+            bail!("{}", e);
+        }
+        Ok(step)
+    }
+
+    fn image(&self) -> String {
+        format!("quay.io/babylonhealth/{}:{}", self.name, self.scenario.version)
+    }
+
+    fn replicaset(&self, step: &Step) -> Result<ReplicaSet> {
+        let rs = json!({
+            "metadata": {
+                "name": format!("{}-{}", self.name, self.scenario.hash),
+                "labels": { "app": self.name, "pod-template-hash": self.scenario.hash },
+                "annotations": { "deployment.kubernetes.io/revision": "1" },
+            },
+            "spec": {
+                "selector": {},
+                "template": { "spec": { "containers": [{ "name": self.name, "image": self.image() }] } },
+            },
+            "status": { "replicas": step.replicas, "readyReplicas": step.ready },
+        });
+        Ok(serde_json::from_value(rs)?)
+    }
+
+    fn pods(&self, step: &Step) -> Result<ObjectList<Pod>> {
+        let mut items = vec![];
+        for i in 0..step.replicas {
+            let ready = i < step.ready;
+            let crashing = !ready && i < step.ready + step.crashing;
+            let pulling = !ready && !crashing && i < step.ready + step.crashing + step.pulling;
+            let state = if crashing {
+                json!({ "waiting": { "reason": "CrashLoopBackOff" } })
+            } else if pulling {
+                json!({ "waiting": { "reason": "ContainerCreating" } })
+            } else {
+                json!({ "running": {} })
+            };
+            let pod = json!({
+                "metadata": {
+                    "name": format!("{}-{}-{}", self.name, self.scenario.hash, i),
+                    "creationTimestamp": Utc::now().to_rfc3339(),
+                    "labels": { "app": self.name, "pod-template-hash": self.scenario.hash },
+                },
+                "spec": { "containers": [{ "name": self.name, "image": self.image() }] },
+                "status": {
+                    "phase": "Running",
+                    "containerStatuses": [{
+                        "name": self.name,
+                        "image": self.image(),
+                        "imageID": if pulling { "" } else { "sha256:replayed" },
+                        "ready": ready,
+                        "restartCount": if crashing { 5 } else { 0 },
+                        "state": state,
+                    }],
+                },
+            });
+            items.push(serde_json::from_value(pod)?);
+        }
+        Ok(ObjectList {
+            metadata: Default::default(),
+            items,
+        })
+    }
+}
+
+#[async_trait]
+impl WorkloadApi for ScenarioKube {
+    async fn get_pods(&self) -> Result<ObjectList<Pod>> {
+        self.pods(&self.current())
+    }
+
+    async fn get_pods_by_template_hash(&self, hash: &str) -> Result<ObjectList<Pod>> {
+        if hash != self.scenario.hash {
+            return Ok(ObjectList {
+                metadata: Default::default(),
+                items: vec![],
+            });
+        }
+        self.pods(&self.current())
+    }
+
+    async fn get_pod_logs(&self, podname: &str) -> Result<String> {
+        Ok(format!("replayed logs for {}", podname))
+    }
+
+    async fn get_pod_events(&self, podname: &str) -> Result<ObjectList<Event>> {
+        let mut items = vec![];
+        if let Some(secs) = self.scenario.pull_seconds {
+            let step = self.current();
+            let idx: i32 = podname
+                .rsplit('-')
+                .next()
+                .and_then(|i| i.parse().ok())
+                .unwrap_or(0);
+            let pulling =
+                idx >= step.ready + step.crashing && idx < step.ready + step.crashing + step.pulling;
+            let done = Utc::now();
+            let start = done - ChronoDuration::seconds(secs.into());
+            let mut reasons = vec![("Pulling", start)];
+            if !pulling {
+                reasons.push(("Pulled", done));
+            }
+            for (reason, ts) in reasons {
+                let ev = json!({
+                    "metadata": { "name": format!("{}.{}", podname, reason.to_lowercase()) },
+                    "involvedObject": {
+                        "kind": "Pod",
+                        "name": podname,
+                        "fieldPath": format!("spec.containers{{{}}}", self.name),
+                    },
+                    "reason": reason,
+                    "firstTimestamp": ts.to_rfc3339(),
+                    "lastTimestamp": ts.to_rfc3339(),
+                });
+                items.push(serde_json::from_value(ev)?);
+            }
+        }
+        Ok(ObjectList {
+            metadata: Default::default(),
+            items,
+        })
+    }
+
+    async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>> {
+        Ok(ObjectList {
+            metadata: Default::default(),
+            items: vec![self.replicaset(&self.current())?],
+        })
+    }
+
+    async fn get_rs_by_template_hash(&self, hash: &str) -> Result<Option<ReplicaSet>> {
+        if hash != self.scenario.hash {
+            return Ok(None);
+        }
+        Ok(Some(self.replicaset(&self.current())?))
+    }
+
+    async fn get_rs_from_deploy(&self) -> Result<Option<ReplicaSet>> {
+        Ok(Some(self.replicaset(&self.current())?))
+    }
+
+    async fn get_deploy(&self) -> Result<Deployment> {
+        let step = self.advance()?;
+        let done = step.ready == step.replicas;
+        let reason = if done {
+            "NewReplicaSetAvailable"
+        } else {
+            "ReplicaSetUpdated"
+        };
+        let deploy = json!({
+            "metadata": {
+                "name": self.name,
+                "annotations": { "deployment.kubernetes.io/revision": "1" },
+            },
+            "status": {
+                "replicas": step.replicas,
+                "readyReplicas": step.ready,
+                "unavailableReplicas": step.replicas - step.ready,
+                "conditions": [{
+                    "type": "Progressing",
+                    "status": "True",
+                    "reason": reason,
+                    "message": step.message,
+                }],
+            },
+        });
+        Ok(serde_json::from_value(deploy)?)
+    }
+
+    async fn get_statefulset(&self) -> Result<StatefulSet> {
+        let step = self.advance()?;
+        let sts = json!({
+            "metadata": { "name": self.name },
+            "status": {
+                "replicas": step.replicas,
+                "readyReplicas": step.ready,
+                "currentReplicas": step.ready,
+                "updatedReplicas": step.replicas,
+                "updateRevision": self.scenario.hash,
+            },
+        });
+        Ok(serde_json::from_value(sts)?)
+    }
+
+    async fn get_daemonset(&self) -> Result<DaemonSet> {
+        let step = self.advance()?;
+        let ds = json!({
+            "metadata": { "name": self.name },
+            "status": {
+                "desiredNumberScheduled": step.replicas,
+                "currentNumberScheduled": step.replicas,
+                "updatedNumberScheduled": step.replicas,
+                "numberReady": step.ready,
+                "numberAvailable": step.ready,
+                "numberMisscheduled": 0,
+            },
+        });
+        Ok(serde_json::from_value(ds)?)
+    }
+}
+
+/// Replay a scenario file through the rollout tracker
+///
+/// Each second of estimated wait time takes a `tick`.
+pub async fn run(mf: &Manifest, pth: &Path, tick: Duration) -> Result<()> {
+    let scenario = Scenario::read(pth)?;
+    let mut mf = mf.clone();
+    mf.workload = scenario.workload.clone();
+    let kube = ScenarioKube::new(&mf.name, scenario);
+    let tracked = track::track_rollout(&mf, &kube, tick, None).await?;
+    if let Some(secs) = tracked.image_pull_seconds {
+        info!("longest image pull took {}s", secs);
+    }
+    if tracked.ok {
+        info!("successfully rolled out {}", mf.name);
+        Ok(())
+    } else {
+        let _ = track::debug(&mf, &kube).await;
+        Err(ErrorKind::UpgradeTimeout(mf.name.clone(), mf.estimate_wait_time()).into())
+    }
+}
@@ -0,0 +1,271 @@
+use chrono::Utc;
+use std::{collections::BTreeSet, env, path::PathBuf};
+use tokio::fs;
+
+use super::{ErrorKind, Result, ResultExt};
+use shipcat_definitions::{teams::Owners, Manifest, RosterConfig, RosterKind};
+
+/// Active people and squads according to an employee directory
+///
+/// This is also the format of a `json` kind roster endpoint:
+///
+/// ```json
+/// {
+///   "people": [{"email": "clux@babylonhealth.com", "active": true}],
+///   "squads": ["platform"]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Roster {
+    /// Everyone in the directory
+    pub people: Vec<RosterPerson>,
+    /// Names of squads in the directory
+    ///
+    /// Squads are not verified when the directory does not list any.
+    #[serde(default)]
+    pub squads: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RosterPerson {
+    pub email: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+fn default_active() -> bool {
+    true
+}
+
+/// A roster as cached on disk
+#[derive(Serialize, Deserialize)]
+struct CachedRoster {
+    url: String,
+    /// Unix timestamp of the fetch
+    fetched: i64,
+    roster: Roster,
+}
+
+/// Subset of a SCIM ListResponse
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimList<T> {
+    total_results: usize,
+    #[serde(rename = "Resources", default = "Vec::new")]
+    resources: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimUser {
+    user_name: String,
+    #[serde(default = "default_active")]
+    active: bool,
+    #[serde(default)]
+    emails: Vec<ScimValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimGroup {
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct ScimValue {
+    value: String,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("shipcat").join("roster.json"))
+}
+
+async fn read_cache(cfg: &RosterConfig) -> Option<CachedRoster> {
+    let data = fs::read_to_string(cache_path()?).await.ok()?;
+    let cached: CachedRoster = serde_json::from_str(&data).ok()?;
+    if cached.url == cfg.url {
+        Some(cached)
+    } else {
+        None
+    }
+}
+
+async fn write_cache(cfg: &RosterConfig, roster: Roster) -> Result<Roster> {
+    let cached = CachedRoster {
+        url: cfg.url.clone(),
+        fetched: Utc::now().timestamp(),
+        roster,
+    };
+    if let Some(pth) = cache_path() {
+        if let Some(dir) = pth.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(&pth, serde_json::to_vec(&cached)?).await?;
+    }
+    Ok(cached.roster)
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    let url = reqwest::Url::parse(url)?;
+    let mut req = reqwest::Client::new().get(url.clone());
+    if let Ok(token) = env::var("SHIPCAT_ROSTER_TOKEN") {
+        req = req.bearer_auth(token);
+    }
+    let res = req
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .chain_err(|| ErrorKind::Url(url.clone()))?;
+    Ok(res.json().await?)
+}
+
+/// Page through a SCIM resource listing
+async fn scim_list<T: serde::de::DeserializeOwned>(base: &str, resource: &str) -> Result<Vec<T>> {
+    let mut res = vec![];
+    loop {
+        // SCIM indexes are 1-based
+        let url = format!("{}/{}?startIndex={}&count=100", base, resource, res.len() + 1);
+        let page: ScimList<T> = get_json(&url).await?;
+        let done = page.resources.is_empty();
+        res.extend(page.resources);
+        if done || res.len() >= page.total_results {
+            return Ok(res);
+        }
+    }
+}
+
+async fn fetch(cfg: &RosterConfig) -> Result<Roster> {
+    debug!("Fetching roster from {}", cfg.url);
+    match cfg.kind {
+        RosterKind::Json => get_json(&cfg.url).await,
+        RosterKind::Scim => {
+            let base = cfg.url.trim_end_matches('/');
+            let mut people = vec![];
+            for u in scim_list::<ScimUser>(base, "Users").await? {
+                people.push(RosterPerson {
+                    email: u.user_name,
+                    active: u.active,
+                });
+                for e in u.emails {
+                    people.push(RosterPerson {
+                        email: e.value,
+                        active: u.active,
+                    });
+                }
+            }
+            let squads = scim_list::<ScimGroup>(base, "Groups")
+                .await?
+                .into_iter()
+                .map(|g| g.display_name)
+                .collect();
+            Ok(Roster { people, squads })
+        }
+    }
+}
+
+/// Load the roster, preferring a fresh cache
+///
+/// When `offline`, any cached roster is used regardless of age.
+/// Returns None when no roster is available offline.
+pub async fn load(cfg: &RosterConfig, offline: bool) -> Result<Option<Roster>> {
+    let cached = read_cache(cfg).await;
+    if offline {
+        if cached.is_none() {
+            warn!("No cached roster available offline - skipping ownership checks");
+        }
+        return Ok(cached.map(|c| c.roster));
+    }
+    if let Some(c) = cached {
+        if Utc::now().timestamp() - c.fetched < cfg.cacheTtl as i64 {
+            return Ok(Some(c.roster));
+        }
+    }
+    let roster = fetch(cfg)
+        .await
+        .chain_err(|| "Failed to fetch roster (use --offline to use a cached one)")?;
+    Ok(Some(write_cache(cfg, roster).await?))
+}
+
+impl Roster {
+    fn active_emails(&self) -> BTreeSet<String> {
+        self.people
+            .iter()
+            .filter(|p| p.active)
+            .map(|p| p.email.to_lowercase())
+            .collect()
+    }
+
+    /// Verify that the owners of a manifest are active and its squad exists
+    pub fn verify(&self, mf: &Manifest, owners: &Owners) -> Result<()> {
+        let md = mf
+            .metadata
+            .as_ref()
+            .expect("metadata must exist on every manifest");
+        let active = self.active_emails();
+        let mut errs = vec![];
+        for m in &md.maintainers {
+            if let Some(p) = owners.people.get(m) {
+                if !active.contains(&p.email.to_lowercase()) {
+                    errs.push(format!("maintainer {} is not an active employee", m));
+                }
+            }
+        }
+        for cc in &md.contacts {
+            if let Some(email) = &cc.email {
+                if !active.contains(&email.to_lowercase()) {
+                    errs.push(format!("contact {} is not an active employee", cc.name));
+                }
+            }
+        }
+        if !self.squads.is_empty() && !self.squads.contains(&md.team) {
+            errs.push(format!("squad {} does not exist in the roster", md.team));
+        }
+        for e in &errs {
+            error!("{}: {}", mf.name, e);
+        }
+        if !errs.is_empty() {
+            bail!("{} has {} orphaned owners", mf.name, errs.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Roster;
+    use shipcat_definitions::{teams::Owners, Manifest};
+
+    #[test]
+    fn roster_verify() {
+        let roster: Roster = serde_json::from_str(
+            r#"{"people": [
+                {"email": "Clux@babylonhealth.com"},
+                {"email": "leaver@babylonhealth.com", "active": false}
+            ], "squads": ["doves"]}"#,
+        )
+        .unwrap();
+        let owners: Owners = serde_yaml::from_str(
+            "people:
+  clux:
+    name: clux
+    slack: U1
+    email: clux@babylonhealth.com
+  leaver:
+    name: leaver
+    slack: U2
+    email: leaver@babylonhealth.com
+squads: {}
+tribes: {}",
+        )
+        .unwrap();
+        let mut mf = Manifest::test("fake-ask");
+        mf.metadata.as_mut().unwrap().maintainers = vec!["clux".into()];
+        assert!(roster.verify(&mf, &owners).is_ok());
+
+        mf.metadata.as_mut().unwrap().maintainers.push("leaver".into());
+        assert!(roster.verify(&mf, &owners).is_err());
+
+        mf.metadata.as_mut().unwrap().maintainers = vec![];
+        mf.metadata.as_mut().unwrap().team = "ravens".into();
+        assert!(roster.verify(&mf, &owners).is_err());
+    }
+}
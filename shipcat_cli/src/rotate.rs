@@ -0,0 +1,153 @@
+use shipcat_definitions::{
+    region::SecretStoreConfig,
+    secretcache, secretstore,
+    vault::{self, Vault},
+};
+use std::io::{self, Read};
+
+use super::{
+    apply,
+    webhooks::{self, UpgradeState},
+    Manifest, Region, Result,
+};
+
+/// Where the new value of a rotated secret comes from
+pub enum NewValue {
+    /// Read from stdin, without trailing newlines
+    Stdin,
+    /// Random bytes of the given length, url safe base64 encoded
+    Generated(usize),
+}
+
+impl NewValue {
+    fn resolve(&self) -> Result<String> {
+        match self {
+            NewValue::Stdin => read_value(io::stdin()),
+            NewValue::Generated(0) => bail!("Refusing to rotate to an empty secret"),
+            NewValue::Generated(bytes) => Ok(vault::generate_secret(*bytes)?),
+        }
+    }
+}
+
+fn read_value<R: Read>(mut input: R) -> Result<String> {
+    let mut value = String::new();
+    input.read_to_string(&mut value)?;
+    let value = value.trim_end_matches(&['\n', '\r'][..]);
+    if value.is_empty() {
+        bail!("Refusing to rotate to an empty secret");
+    }
+    Ok(value.to_string())
+}
+
+async fn write(client: &Vault, key: &str, value: &str, keep: Option<u32>) -> Result<Option<u32>> {
+    // limit versions first, so old values never outlive the limit
+    if let Some(n) = keep {
+        client.keep_versions(key, n).await?;
+    }
+    Ok(client.write(key, value).await?)
+}
+
+/// Information about a rotation that is sent to webhooks
+///
+/// Never contains the value of the secret.
+#[derive(Clone, Debug)]
+pub struct RotationInfo {
+    /// Name of service
+    pub name: String,
+    /// Name of the rotated secret
+    pub secret: String,
+    /// Region of the service
+    pub region: String,
+    /// Cluster serving the region
+    pub cluster: Option<String>,
+    /// Version written on KV v2 engines
+    pub version: Option<u32>,
+}
+
+/// Rotate a secret of a service and restart it to pick up the new value
+///
+/// Writes the value to the region's vault, optionally limits how many old versions vault keeps,
+/// then restarts the service. Audit events are sent for the rotation, but never the value.
+pub async fn rotate(
+    mut mf: Manifest,
+    reg: &Region,
+    secret: &str,
+    value: NewValue,
+    keep: Option<u32>,
+    wait: bool,
+    reason: Option<String>,
+) -> Result<()> {
+    match &reg.secretStore {
+        Some(SecretStoreConfig::Vault) | None => {}
+        Some(_) => bail!(
+            "Secrets can only be rotated in vault, {} uses another secretStore",
+            reg.name
+        ),
+    }
+    let in_env = mf
+        .get_env_vars()
+        .into_iter()
+        .any(|e| e.clone().vault_secrets().contains(secret));
+    let in_files = mf
+        .secretFiles
+        .get(secret)
+        .map_or(false, |v| secretstore::is_placeholder(v));
+    if !in_env && !in_files {
+        bail!("{} is not a secret of {} in {}", secret, mf.name, reg.name);
+    }
+    let value = value.resolve()?;
+    if in_files && base64::decode(&value).is_err() {
+        bail!("Secret file {} must be base64 encoded", secret);
+    }
+
+    let client = Vault::regional(&reg.vault)?;
+    let key = mf.secret_key(&reg.vault, secret);
+    let mut info = RotationInfo {
+        name: mf.name.clone(),
+        secret: secret.to_string(),
+        region: reg.name.clone(),
+        cluster: Some(reg.cluster.clone()),
+        version: None,
+    };
+    webhooks::rotation_event(&UpgradeState::Started, &info, reg).await;
+    match write(&client, &key, &value, keep).await {
+        Ok(v) => info.version = v,
+        Err(e) => {
+            webhooks::rotation_event(&UpgradeState::Failed, &info, reg).await;
+            return Err(e);
+        }
+    }
+    match info.version {
+        Some(v) => info!("Wrote version {} of {}", v, key),
+        None => info!("Wrote {}", key),
+    }
+    if let Err(e) = secretcache::forget(&reg.name, &key) {
+        warn!("Failed to drop the cached value of {}: {}", key, e);
+    }
+
+    let reason = reason.unwrap_or_else(|| format!("rotated {}", secret));
+    match apply::restart(&mf, wait, Some(reason)).await {
+        Ok(_) => {
+            webhooks::rotation_event(&UpgradeState::Completed, &info, reg).await;
+            Ok(())
+        }
+        Err(e) => {
+            webhooks::rotation_event(&UpgradeState::Failed, &info, reg).await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_value, NewValue};
+
+    #[test]
+    fn rotated_values_from_input() {
+        assert_eq!(read_value("hunter2\n".as_bytes()).unwrap(), "hunter2");
+        assert_eq!(read_value("hunter2\r\n".as_bytes()).unwrap(), "hunter2");
+        assert_eq!(read_value(" spaced ".as_bytes()).unwrap(), " spaced ");
+        assert!(read_value("\n".as_bytes()).is_err());
+        assert!(NewValue::Generated(0).resolve().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use schemars::{schema::RootSchema, schema_for};
+use std::str::FromStr;
+
+use super::{Config, Error, Result};
+
+/// Files shipcat can describe with a JSON schema
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaKind {
+    /// A service's `manifest.yml`
+    Manifest,
+    /// A service's region or environment overrides, like `dev-uk.yml`
+    Overrides,
+    /// `shipcat.conf`
+    Config,
+}
+
+impl FromStr for SchemaKind {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "manifest" => Ok(Self::Manifest),
+            "overrides" => Ok(Self::Overrides),
+            "config" => Ok(Self::Config),
+            _ => bail!("Schema kind must be manifest, overrides or config"),
+        }
+    }
+}
+
+/// The JSON schema of a kind of file
+pub fn generate(kind: SchemaKind) -> RootSchema {
+    match kind {
+        SchemaKind::Manifest => shipcat_filebacked::manifest_schema(),
+        SchemaKind::Overrides => shipcat_filebacked::overrides_schema(),
+        SchemaKind::Config => schema_for!(Config),
+    }
+}
+
+/// Print the JSON schema of a kind of file
+///
+/// Usable by editors for yaml validation and autocompletion:
+///
+/// ```sh
+/// shipcat schema manifest > manifest.schema.json
+/// ```
+pub fn print(kind: SchemaKind) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&generate(kind))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, SchemaKind};
+
+    #[test]
+    fn schema_properties() {
+        let mf = serde_json::to_value(generate(SchemaKind::Manifest)).unwrap();
+        let props = &mf["properties"];
+        // manifest fields and flattened overrides and defaults
+        assert!(props["name"].is_object());
+        assert!(props["httpPort"].is_object());
+        assert!(props["kongApis"].is_object());
+        assert!(props["smokeTests"].is_object());
+
+        let ovr = serde_json::to_value(generate(SchemaKind::Overrides)).unwrap();
+        assert!(ovr["properties"]["chartValues"].is_object());
+        assert!(ovr["properties"]["name"].is_null());
+
+        let conf = serde_json::to_value(generate(SchemaKind::Config)).unwrap();
+        assert!(conf["properties"]["regions"].is_object());
+        assert!(conf["definitions"]["Region"].is_object());
+    }
+
+    #[test]
+    fn schema_kinds() {
+        assert_eq!("overrides".parse::<SchemaKind>().unwrap(), SchemaKind::Overrides);
+        assert!("region".parse::<SchemaKind>().is_err());
+    }
+}
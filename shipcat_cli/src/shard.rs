@@ -0,0 +1,253 @@
+use sha2::{Digest, Sha256};
+use shipcat_definitions::{Config, Region};
+use shipcat_filebacked::SimpleManifest;
+use std::{
+    collections::BTreeSet,
+    convert::TryInto,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use super::{Error, Result};
+
+/// One of `count` disjoint slices of the services in a region
+///
+/// Services are assigned by a hash of their name, so every CI job
+/// given the same `count` agrees on which shard owns a service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    /// 1-indexed shard number
+    pub index: u32,
+    pub count: u32,
+}
+
+impl FromStr for Shard {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let parts = input.split('/').collect::<Vec<_>>();
+        let (index, count) = match parts.as_slice() {
+            [i, n] => match (i.parse::<u32>(), n.parse::<u32>()) {
+                (Ok(i), Ok(n)) => (i, n),
+                _ => bail!("Shard {} must be two numbers like 1/4", input),
+            },
+            _ => bail!("Shard {} must be of the form i/N", input),
+        };
+        if index == 0 || index > count {
+            bail!("Shard index must be between 1 and {} (got {})", count, index);
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Shard {
+    /// Whether a service belongs to this shard
+    pub fn contains(&self, svc: &str) -> bool {
+        let hash = Sha256::digest(svc.as_bytes());
+        let prefix: [u8; 8] = hash[..8].try_into().expect("sha256 is 32 bytes");
+        u64::from_be_bytes(prefix) % u64::from(self.count) == u64::from(self.index - 1)
+    }
+}
+
+/// Whether a service is handled when running an optional shard
+pub fn owns(shard: Option<&Shard>, svc: &str) -> bool {
+    match shard {
+        Some(s) => s.contains(svc),
+        None => true,
+    }
+}
+
+/// Names of the services in an optional shard
+pub fn select(svcs: &[SimpleManifest], shard: Option<&Shard>) -> Vec<String> {
+    svcs.iter()
+        .map(|mf| mf.base.name.clone())
+        .filter(|svc| owns(shard, svc))
+        .collect()
+}
+
+/// Services handled by one shard of a mass operation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardReport {
+    /// The cluster command that was sharded, e.g. `crd-reconcile`
+    pub command: String,
+    pub region: String,
+    pub index: u32,
+    pub count: u32,
+    /// Services the shard operated on
+    pub services: Vec<String>,
+    /// Whether the shard completed without errors
+    pub ok: bool,
+}
+
+impl ShardReport {
+    pub fn new(command: &str, reg: &Region, shard: &Shard, services: Vec<String>) -> Self {
+        ShardReport {
+            command: command.into(),
+            region: reg.name.clone(),
+            index: shard.index,
+            count: shard.count,
+            services,
+            ok: false,
+        }
+    }
+
+    fn file_prefix(command: &str, region: &str) -> String {
+        format!("shard-{}-{}-", command, region)
+    }
+
+    /// Write the report to the working directory for CI to collect
+    pub fn write(&self) -> Result<PathBuf> {
+        let pth = Path::new(".").join(format!(
+            "{}{}-of-{}.json",
+            Self::file_prefix(&self.command, &self.region),
+            self.index,
+            self.count
+        ));
+        std::fs::write(&pth, serde_json::to_string_pretty(self)?)?;
+        info!(
+            "Wrote shard {}/{} report to {}",
+            self.index,
+            self.count,
+            pth.display()
+        );
+        Ok(pth)
+    }
+}
+
+/// Verify that shard reports cover a set of services exactly once
+pub fn verify_coverage(reports: &[ShardReport], expected: &[String]) -> Result<()> {
+    let count = match reports.first() {
+        Some(r) => r.count,
+        None => bail!("No shard reports found"),
+    };
+    if reports.iter().any(|r| r.count != count) {
+        bail!("Shard reports disagree on the number of shards");
+    }
+    let indices = reports.iter().map(|r| r.index).collect::<BTreeSet<_>>();
+    let missing = (1..=count).filter(|i| !indices.contains(i)).collect::<Vec<_>>();
+    if !missing.is_empty() || indices.len() != reports.len() {
+        bail!(
+            "Expected one report for each of {} shards, missing {:?}",
+            count,
+            missing
+        );
+    }
+
+    let mut seen = BTreeSet::new();
+    for r in reports {
+        for svc in &r.services {
+            if !seen.insert(svc.clone()) {
+                bail!("{} was handled by more than one shard", svc);
+            }
+        }
+    }
+    let expected = expected.iter().cloned().collect::<BTreeSet<_>>();
+    let uncovered = expected.difference(&seen).collect::<Vec<_>>();
+    if !uncovered.is_empty() {
+        bail!("Services not handled by any shard: {:?}", uncovered);
+    }
+    let unknown = seen.difference(&expected).collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!("Shards handled services no longer in the region: {:?}", unknown);
+    }
+    let failed = reports
+        .iter()
+        .filter(|r| !r.ok)
+        .map(|r| r.index)
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        bail!("Shards {:?} did not complete successfully", failed);
+    }
+    Ok(())
+}
+
+/// Merge the shard reports of a sharded command in a directory
+///
+/// Fails unless every service available in the region was handled by exactly one shard,
+/// and every shard completed.
+pub async fn report(dir: &Path, command: &str, conf: &Config, reg: &Region) -> Result<()> {
+    let prefix = ShardReport::file_prefix(command, &reg.name);
+    let mut reports = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let pth = entry?.path();
+        let name = pth.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if name.starts_with(&prefix) && name.ends_with(".json") {
+            let data = std::fs::read_to_string(&pth)?;
+            let r: ShardReport = serde_json::from_str(&data)?;
+            reports.push(r);
+        }
+    }
+    reports.sort_by_key(|r| r.index);
+    let expected = shipcat_filebacked::available(conf, reg)
+        .await?
+        .into_iter()
+        .map(|mf| mf.base.name)
+        .collect::<Vec<_>>();
+    verify_coverage(&reports, &expected)?;
+    info!(
+        "{} shards of {} covered all {} services in {}",
+        reports.len(),
+        command,
+        expected.len(),
+        reg.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_coverage, Shard, ShardReport};
+    use std::str::FromStr;
+
+    fn report(shard: &Shard, svcs: &[String]) -> ShardReport {
+        ShardReport {
+            command: "diff".into(),
+            region: "dev-uk".into(),
+            index: shard.index,
+            count: shard.count,
+            services: svcs.iter().filter(|s| shard.contains(s)).cloned().collect(),
+            ok: true,
+        }
+    }
+
+    #[test]
+    fn shard_parse() {
+        assert_eq!(Shard::from_str("2/4").unwrap(), Shard { index: 2, count: 4 });
+        assert!(Shard::from_str("0/4").is_err());
+        assert!(Shard::from_str("5/4").is_err());
+        assert!(Shard::from_str("4").is_err());
+        assert!(Shard::from_str("a/b").is_err());
+    }
+
+    #[test]
+    fn shards_partition_services() {
+        let svcs = (0..200).map(|i| format!("svc-{}", i)).collect::<Vec<_>>();
+        let shards = (1..=4).map(|i| Shard { index: i, count: 4 }).collect::<Vec<_>>();
+        for s in &svcs {
+            assert_eq!(shards.iter().filter(|sh| sh.contains(s)).count(), 1);
+        }
+        // no shard is left empty with a reasonable amount of services
+        assert!(shards.iter().all(|sh| svcs.iter().any(|s| sh.contains(s))));
+        // assignment is stable
+        assert!(Shard { index: 1, count: 1 }.contains("fake-ask"));
+
+        let mut reports = shards.iter().map(|sh| report(sh, &svcs)).collect::<Vec<_>>();
+        assert!(verify_coverage(&reports, &svcs).is_ok());
+
+        let mut extra = svcs.clone();
+        extra.push("new-svc".into());
+        assert!(verify_coverage(&reports, &extra).is_err());
+
+        reports[1].ok = false;
+        assert!(verify_coverage(&reports, &svcs).is_err());
+        reports.pop();
+        assert!(verify_coverage(&reports, &svcs).is_err());
+    }
+}
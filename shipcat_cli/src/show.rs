@@ -9,6 +9,12 @@ pub fn config(conf: Config) -> Result<()> {
     Ok(())
 }
 
+/// Print the effective config of a single region
+pub fn region(reg: &Region) -> Result<()> {
+    println!("{}", serde_yaml::to_string(reg)?);
+    Ok(())
+}
+
 pub fn config_crd(conf: Config) -> Result<()> {
     if conf.has_all_regions() {
         bail!("The config crd needs to be for a single region only");
@@ -9,6 +9,7 @@ use std::{collections::BTreeMap, env};
 use super::{ErrorKind, Result};
 use crate::diff;
 use shipcat_definitions::{
+    status::ContainerChange,
     structs::{Contact, Metadata, NotificationMode},
     teams::{Owners, Person},
 };
@@ -36,6 +37,9 @@ pub struct Message {
 
     /// Optional version to send when not having code diffs
     pub version: Option<String>,
+
+    /// Version changes of sidecars and init containers
+    pub containers: Vec<ContainerChange>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -166,10 +170,16 @@ async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()
     let mut codeattach = None;
     if let Some(diff) = msg.code {
         // does the diff contain versions?
-        let is_version_only = if let Some((v1, v2)) = diff::infer_version_change(&diff) {
+        let is_version_only = if let Some((v1, v2)) = service_version_change(&diff, &msg.containers) {
             let lnk = create_github_compare_url(&md, (&v1, &v2));
             texts.push(lnk);
-            diff::is_version_only(&diff, (&v1, &v2))
+            let mut versions = vec![(v1.as_str(), v2.as_str())];
+            for c in &msg.containers {
+                if let (Some(old), Some(new)) = (&c.old, &c.new) {
+                    versions.push((old, new));
+                }
+            }
+            diff::is_versions_only(&diff, &versions)
         } else {
             false
         };
@@ -237,7 +247,35 @@ fn infer_metadata_single_link(md: &Metadata, ver: String) -> SlackTextContent {
     Link(SlackLink::new(&url, &short_ver(&ver)))
 }
 
+/// The version change of the service itself in a diff
+///
+/// Image changes of sidecars and init containers are not versions of the service.
+fn service_version_change(diff: &str, containers: &[ContainerChange]) -> Option<(String, String)> {
+    if containers.is_empty() {
+        return diff::infer_version_change(diff);
+    }
+    diff::infer_version_changes(diff).into_iter().find(|(v1, v2)| {
+        !containers
+            .iter()
+            .any(|c| c.old.as_deref() == Some(v1.as_str()) && c.new.as_deref() == Some(v2.as_str()))
+    })
+}
+
+/// Link to the changes in an upgrade
+///
+/// A compare url when the diff contains a version change, otherwise a link to the version.
+pub fn version_link(md: &Metadata, diff: Option<&str>, ver: &str, containers: &[ContainerChange]) -> String {
+    match diff.and_then(|d| service_version_change(d, containers)) {
+        Some((v1, v2)) => github_compare_url(md, (&v1, &v2)),
+        None => md.github_link_for_version(ver),
+    }
+}
+
 fn create_github_compare_url(md: &Metadata, vers: (&str, &str)) -> SlackTextContent {
+    Link(SlackLink::new(&github_compare_url(md, vers), &short_ver(vers.1)))
+}
+
+fn github_compare_url(md: &Metadata, vers: (&str, &str)) -> String {
     let (v0, v1) = if Version::parse(vers.0).is_ok() {
         let v0 = md.version_template(&vers.0).unwrap_or(vers.0.to_string());
         let v1 = md.version_template(&vers.1).unwrap_or(vers.1.to_string());
@@ -245,13 +283,12 @@ fn create_github_compare_url(md: &Metadata, vers: (&str, &str)) -> SlackTextCont
     } else {
         (vers.0.into(), vers.1.into())
     };
-    let url = if md.repo.contains("/tree/") {
+    if md.repo.contains("/tree/") {
         // subfolder specified in tree - cannot do nice compare url for that
         md.repo.clone()
     } else {
         format!("{}/compare/{}...{}", md.repo, v0, v1)
-    };
-    Link(SlackLink::new(&url, &short_ver(vers.1)))
+    }
 }
 
 fn contacts_to_text_content(contacts: &[Contact]) -> Vec<SlackTextContent> {
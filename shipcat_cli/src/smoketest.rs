@@ -0,0 +1,390 @@
+use regex::Regex;
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{graph, kubectl, Config, Manifest, Region, Result};
+use shipcat_definitions::{
+    status::{make_date, SmokeTestResult, SmokeTestRun},
+    structs::{HttpSmokeTest, JobSmokeTest, SmokeTest},
+};
+
+/// Image for the throwaway pod making in-cluster http checks
+///
+/// Needs `sh` and `curl`.
+pub const DEFAULT_IMAGE: &str = "curlimages/curl:7.72.0";
+
+/// Prefix of result lines printed by the check script
+const MARKER: &str = "shipcat-smoke";
+
+/// Seconds allowed for the throwaway pod of the http checks to start
+const POD_START_TIMEOUT: u32 = 60;
+
+/// Bytes of response bodies kept for matching
+const MAX_BODY: usize = 4096;
+
+/// An http smoke test against a url
+#[derive(Debug, Clone)]
+pub struct HttpCheck {
+    /// Service declaring the test
+    pub service: String,
+    /// Name of the test, suffixed with `-kong` for checks through kong
+    pub name: String,
+    pub url: String,
+    pub expect: HttpSmokeTest,
+}
+
+impl HttpCheck {
+    fn id(&self) -> String {
+        format!("{}/{}", self.service, self.name)
+    }
+
+    /// Compare a response against the expectations
+    pub fn evaluate(&self, response: Option<(u16, &str)>) -> SmokeTestResult {
+        let (passed, message) = match response {
+            None => (false, format!("no response from {}", self.url)),
+            Some((code, _)) if code != self.expect.status => (
+                false,
+                format!("{} returned {}, expected {}", self.url, code, self.expect.status),
+            ),
+            Some((code, body)) => match &self.expect.body {
+                // regexes are verified with the manifest
+                Some(re) if !Regex::new(re).map_or(false, |r| r.is_match(body)) => {
+                    (false, format!("{} body did not match '{}'", self.url, re))
+                }
+                _ => (true, format!("{} returned {}", self.url, code)),
+            },
+        };
+        SmokeTestResult {
+            service: self.service.clone(),
+            name: self.name.clone(),
+            passed,
+            message,
+        }
+    }
+}
+
+/// In-cluster url of a path on a service
+fn cluster_url(mf: &Manifest, path: &str) -> String {
+    format!("http://{}.{}.svc.cluster.local{}", mf.name, mf.namespace, path)
+}
+
+/// Public url of a path behind the service's main kong api
+fn kong_url(mf: &Manifest, reg: &Region, path: &str) -> Result<String> {
+    let api = match mf.kongApis.first() {
+        Some(k) => k,
+        None => bail!("{} has no kong api to smoke test", mf.name),
+    };
+    let host = match (api.hosts.first(), &reg.kong) {
+        (Some(h), _) => h.clone(),
+        (None, Some(k)) => k.base_url.clone(),
+        (None, None) => bail!(
+            "{} has no kong config to smoke test {} through",
+            reg.name,
+            mf.name
+        ),
+    };
+    let base = if host.contains("://") {
+        host
+    } else {
+        format!("https://{}", host)
+    };
+    let prefix = api.uris.clone().unwrap_or_default();
+    Ok(format!(
+        "{}{}{}",
+        base.trim_end_matches('/'),
+        prefix.trim_end_matches('/'),
+        path
+    ))
+}
+
+/// Http checks of a test, in-cluster first and through kong second
+fn http_checks(mf: &Manifest, reg: &Region, t: &SmokeTest, http: &HttpSmokeTest) -> Result<Vec<HttpCheck>> {
+    let mut res = vec![HttpCheck {
+        service: mf.name.clone(),
+        name: t.name.clone(),
+        url: cluster_url(mf, &http.path),
+        expect: http.clone(),
+    }];
+    if http.kong {
+        res.push(HttpCheck {
+            service: mf.name.clone(),
+            name: format!("{}-kong", t.name),
+            url: kong_url(mf, reg, &http.path)?,
+            expect: http.clone(),
+        });
+    }
+    Ok(res)
+}
+
+/// Shell script making the in-cluster checks from inside a pod
+///
+/// Prints a `shipcat-smoke <service/name> <status>` line per check,
+/// followed by the response body and a `shipcat-smoke-end <service/name>` line.
+pub fn script(checks: &[HttpCheck]) -> String {
+    let mut lines = vec![
+        "check() {".to_string(),
+        "  code=$(curl -s -o /tmp/body -w '%{http_code}' --max-time \"$3\" \"$2\")".into(),
+        format!("  echo \"{} $1 ${{code:-000}}\"", MARKER),
+        format!("  head -c {} /tmp/body 2>/dev/null; echo", MAX_BODY),
+        format!("  echo \"{}-end $1\"", MARKER),
+        "  rm -f /tmp/body".into(),
+        "}".into(),
+    ];
+    for c in checks {
+        lines.push(format!(
+            "check {} '{}' {}",
+            c.id(),
+            c.url,
+            c.expect.timeoutSeconds
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Statuses and bodies printed by the check script, by check id
+pub fn responses(output: &str) -> BTreeMap<String, (u16, String)> {
+    let mut res = BTreeMap::new();
+    let mut current: Option<(String, u16, Vec<&str>)> = None;
+    for l in output.lines() {
+        if let Some((id, code, mut body)) = current.take() {
+            if l == format!("{}-end {}", MARKER, id) {
+                res.insert(id, (code, body.join("\n").trim_end().to_string()));
+            } else {
+                body.push(l);
+                current = Some((id, code, body));
+            }
+            continue;
+        }
+        let parts = l.split_whitespace().collect::<Vec<_>>();
+        if let [m, id, code] = parts.as_slice() {
+            if *m == MARKER {
+                // curl reports 000 when it got no response
+                if let Ok(c) = code.parse::<u16>() {
+                    if c > 0 {
+                        current = Some((id.to_string(), c, vec![]));
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Run the in-cluster checks from a throwaway pod in the service's namespace
+async fn check_in_cluster(mf: &Manifest, checks: &[HttpCheck]) -> Vec<SmokeTestResult> {
+    if checks.is_empty() {
+        return vec![];
+    }
+    let mut pod = format!("shipcat-smoke-{}", mf.name);
+    pod.truncate(63);
+    let script = script(checks);
+    let deadline = checks.iter().map(|c| c.expect.timeoutSeconds).sum::<u32>() + POD_START_TIMEOUT;
+    let pod = pod.trim_end_matches('-');
+    let output = kubectl::run_script(&mf.namespace, pod, DEFAULT_IMAGE, &script, Some(deadline))
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to run smoke tests in {}: {}", mf.namespace, e);
+            String::new()
+        });
+    debug!("smoke test output: {}", output);
+    let responses = responses(&output);
+    checks
+        .iter()
+        .map(|c| c.evaluate(responses.get(&c.id()).map(|(code, body)| (*code, body.as_str()))))
+        .collect()
+}
+
+/// Run checks through kong from here
+async fn check_kong(checks: &[HttpCheck]) -> Result<Vec<SmokeTestResult>> {
+    let client = reqwest::Client::new();
+    let mut res = vec![];
+    for c in checks {
+        let timeout = Duration::from_secs(c.expect.timeoutSeconds.into());
+        let response = match client.get(&c.url).timeout(timeout).send().await {
+            Ok(r) => {
+                let code = r.status().as_u16();
+                let body = r.text().await.unwrap_or_default();
+                Some((code, body))
+            }
+            Err(e) => {
+                debug!("Failed to request {}: {}", c.url, e);
+                None
+            }
+        };
+        res.push(c.evaluate(response.as_ref().map(|(code, body)| (*code, body.as_str()))));
+    }
+    Ok(res)
+}
+
+/// Run a job to completion in a throwaway pod
+async fn check_job(mf: &Manifest, name: &str, job: &JobSmokeTest) -> SmokeTestResult {
+    let image = job.image.clone().unwrap_or_else(|| {
+        format!(
+            "{}:{}",
+            mf.image.clone().unwrap_or_default(),
+            mf.version.clone().unwrap_or_default()
+        )
+    });
+    let mut pod = format!("shipcat-smoke-{}-{}", mf.name, name);
+    pod.truncate(63);
+    let pod = pod.trim_end_matches('-');
+    let ran = kubectl::run_script(&mf.namespace, pod, &image, &job.script, Some(job.timeoutSeconds)).await;
+    let (passed, message) = match ran {
+        Ok(_) => (true, format!("job succeeded in {}", image)),
+        Err(e) => (false, format!("job failed or timed out in {}: {}", image, e)),
+    };
+    SmokeTestResult {
+        service: mf.name.clone(),
+        name: name.to_string(),
+        passed,
+        message,
+    }
+}
+
+/// Dependents that cannot serve requests without the service
+async fn critical_dependents(mf: &Manifest, conf: &Config, reg: &Region) -> Result<Vec<Manifest>> {
+    let dependents = graph::dependents(&mf.name, conf, reg).await?;
+    Ok(dependents
+        .into_iter()
+        .filter(|d| {
+            d.dependencies
+                .iter()
+                .any(|dep| dep.name == mf.name && dep.critical)
+        })
+        .collect())
+}
+
+/// Run the smoke tests of a rolled out service
+///
+/// Also runs the in-cluster http checks of dependents declaring the service as a `critical`
+/// dependency, since a broken rollout takes them down too.
+/// Returns `None` when there is nothing to test.
+pub async fn run(mf: &Manifest, conf: &Config, reg: &Region) -> Result<Option<SmokeTestRun>> {
+    let mut cluster_checks = vec![];
+    let mut kong_checks = vec![];
+    let mut jobs = vec![];
+    for t in mf.smokeTests.iter().flat_map(|st| &st.tests) {
+        if let Some(http) = &t.http {
+            let mut checks = http_checks(mf, reg, t, http)?.into_iter();
+            cluster_checks.extend(checks.next());
+            kong_checks.extend(checks);
+        }
+        if let Some(job) = &t.job {
+            jobs.push((t.name.clone(), job.clone()));
+        }
+    }
+    for d in critical_dependents(mf, conf, reg).await? {
+        for t in d.smokeTests.iter().flat_map(|st| &st.tests) {
+            if let Some(http) = &t.http {
+                cluster_checks.push(HttpCheck {
+                    service: d.name.clone(),
+                    name: t.name.clone(),
+                    url: cluster_url(&d, &http.path),
+                    expect: http.clone(),
+                });
+            }
+        }
+    }
+    if cluster_checks.is_empty() && kong_checks.is_empty() && jobs.is_empty() {
+        return Ok(None);
+    }
+
+    info!("Running smoke tests for {}", mf.name);
+    let mut results = check_in_cluster(mf, &cluster_checks).await;
+    results.extend(check_kong(&kong_checks).await?);
+    for (name, job) in &jobs {
+        results.push(check_job(mf, name, job).await);
+    }
+    for r in &results {
+        if r.passed {
+            info!("smoke test {}/{} passed: {}", r.service, r.name, r.message);
+        } else {
+            warn!("smoke test {}/{} failed: {}", r.service, r.name, r.message);
+        }
+    }
+    Ok(Some(SmokeTestRun {
+        version: mf.version.clone().unwrap_or_default(),
+        time: make_date(),
+        passed: results.iter().all(|r| r.passed),
+        results,
+    }))
+}
+
+/// One line summary of a smoke test run for notifications
+pub fn summary(run: &SmokeTestRun) -> String {
+    let failed = run
+        .results
+        .iter()
+        .filter(|r| !r.passed)
+        .map(|r| format!("`{}/{}`", r.service, r.name))
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        format!("{} smoke tests passed", run.results.len())
+    } else {
+        format!(
+            "{} of {} smoke tests failed: {}",
+            failed.len(),
+            run.results.len(),
+            failed.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{http_checks, responses, script, summary};
+    use crate::{Manifest, Region};
+    use shipcat_definitions::{status::SmokeTestRun, structs::SmokeTests};
+
+    #[test]
+    fn smoke_test_http_checks() {
+        let mut mf = Manifest::test("fake-ask");
+        mf.kongApis = vec![serde_yaml::from_str("{name: fake-ask, uris: /fake-ask}").unwrap()];
+        let tests: SmokeTests = serde_yaml::from_str(
+            "tests: [{name: health, http: {path: /health, body: '\"ok\"', kong: true}}]",
+        )
+        .unwrap();
+        let reg: Region = serde_yaml::from_str(
+            "name: dev-uk
+namespace: apps
+environment: dev
+cluster: kops-uk
+versioningScheme: Semver
+vault: {url: http://localhost:8200, folder: dev}
+kong:
+  base_url: dev.example.com
+  config_url: https://kong-admin.dev.example.com
+  kong_token_expiration: 1800
+  tcp_log: {enabled: false, host: '', port: ''}",
+        )
+        .unwrap();
+        let t = &tests.tests[0];
+        let checks = http_checks(&mf, &reg, t, t.http.as_ref().unwrap()).unwrap();
+        assert_eq!(checks[0].url, "http://fake-ask.apps.svc.cluster.local/health");
+        assert_eq!(checks[1].url, "https://dev.example.com/fake-ask/health");
+        assert_eq!(checks[1].name, "health-kong");
+        assert!(script(&checks[..1]).contains("check fake-ask/health 'http://fake-ask.apps"));
+
+        let out = "shipcat-smoke fake-ask/health 200\n{\"status\": \"ok\"}\n\
+                   shipcat-smoke-end fake-ask/health\n\
+                   shipcat-smoke fake-ask/health-kong 000\n\n\
+                   shipcat-smoke-end fake-ask/health-kong\n";
+        let res = responses(out);
+        let ok = res.get("fake-ask/health").map(|(c, b)| (*c, b.as_str()));
+        assert_eq!(ok, Some((200, "{\"status\": \"ok\"}")));
+        assert!(checks[0].evaluate(ok).passed);
+        assert!(!checks[0].evaluate(Some((200, "{\"status\": \"down\"}"))).passed);
+        assert!(!checks[0].evaluate(Some((503, "\"ok\""))).passed);
+        // no response is a failure
+        assert!(res.get("fake-ask/health-kong").is_none());
+        let failed = checks[1].evaluate(None);
+        assert!(!failed.passed);
+
+        let run = SmokeTestRun {
+            version: "1.0.0".into(),
+            time: "2020-01-01T00:00:00Z".into(),
+            passed: false,
+            results: vec![checks[0].evaluate(ok), failed],
+        };
+        assert_eq!(summary(&run), "1 of 2 smoke tests failed: `fake-ask/health-kong`");
+    }
+}
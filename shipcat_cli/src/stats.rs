@@ -0,0 +1,159 @@
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use shipcat_definitions::BaseManifest;
+use std::{collections::BTreeMap, str::FromStr};
+
+use super::{Config, Error, Manifest, Result};
+
+/// Fields set on every manifest, which are not worth counting
+const ALWAYS_SET: [&str; 8] = [
+    "name",
+    "region",
+    "environment",
+    "namespace",
+    "metadata",
+    "uid",
+    "secrets",
+    "state",
+];
+
+/// How to print field stats
+pub enum StatsFormat {
+    Json,
+    /// Rows of `scope,group,field,count`
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("Output format must be json or csv"),
+        }
+    }
+}
+
+/// Usage counts of manifest fields
+///
+/// Counts are of services in regions, after merging, so a service
+/// deployed to three regions counts up to three times.
+#[derive(Serialize, Default, Debug)]
+pub struct FieldStats {
+    /// Number of services in regions looked at
+    pub services: usize,
+    /// How many services set each field
+    pub fields: BTreeMap<String, usize>,
+    /// Field counts per owning team
+    pub teams: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Field counts per environment
+    pub environments: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// Whether a serialized field carries anything
+fn is_set(v: &Value) -> bool {
+    match v {
+        Value::Null | Value::Bool(false) => false,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+        _ => true,
+    }
+}
+
+/// Top level manifest fields that are set
+pub fn used_fields(mf: &Manifest) -> Result<Vec<String>> {
+    let mut fields: Vec<String> = match serde_json::to_value(mf)? {
+        Value::Object(o) => o
+            .into_iter()
+            .filter(|(k, v)| is_set(v) && !ALWAYS_SET.contains(&k.as_str()))
+            .map(|(k, _)| k)
+            .collect(),
+        _ => vec![],
+    };
+    // fields that are never passed on to charts
+    if mf.publiclyAccessible {
+        fields.push("publiclyAccessible".into());
+    }
+    if mf.kompass_plugin {
+        fields.push("kompassPlugin".into());
+    }
+    if mf.dataHandling.is_some() {
+        fields.push("dataHandling".into());
+    }
+    Ok(fields)
+}
+
+impl FieldStats {
+    fn add(&mut self, mf: &Manifest) -> Result<()> {
+        let team = mf.metadata.as_ref().map(|md| md.team.clone()).unwrap_or_default();
+        self.services += 1;
+        for f in used_fields(mf)? {
+            *self.fields.entry(f.clone()).or_default() += 1;
+            *self
+                .teams
+                .entry(team.clone())
+                .or_default()
+                .entry(f.clone())
+                .or_default() += 1;
+            *self
+                .environments
+                .entry(mf.environment.clone())
+                .or_default()
+                .entry(f)
+                .or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn to_csv(&self) -> String {
+        let mut rows = vec!["scope,group,field,count".to_string()];
+        for (f, n) in &self.fields {
+            rows.push(format!("total,,{},{}", f, n));
+        }
+        for (scope, groups) in &[("team", &self.teams), ("environment", &self.environments)] {
+            for (g, fields) in groups.iter() {
+                for (f, n) in fields {
+                    rows.push(format!("{},{},{},{}", scope, g, f, n));
+                }
+            }
+        }
+        rows.join("\n")
+    }
+}
+
+async fn load_world(base: BaseManifest, conf: &Config) -> Result<Vec<Manifest>> {
+    let mut mfs = vec![];
+    for r in &base.regions {
+        if let Some(reg) = conf.get_region_unchecked(r) {
+            let mf = shipcat_filebacked::load_manifest(&base.name, conf, reg).await?;
+            if !mf.disabled && !mf.external {
+                mfs.push(mf);
+            }
+        }
+    }
+    Ok(mfs)
+}
+
+/// Count the manifest fields used by services across all regions
+///
+/// This works on manifests on disk, and does NOT talk to kubernetes.
+pub async fn fields(conf: &Config, fmt: StatsFormat) -> Result<FieldStats> {
+    let all = shipcat_filebacked::all(conf).await?;
+    let mut buffered = stream::iter(all)
+        .map(|base| load_world(base, conf))
+        .buffer_unordered(100);
+    let mut stats = FieldStats::default();
+    while let Some(r) = buffered.next().await {
+        for mf in r? {
+            stats.add(&mf)?;
+        }
+    }
+    match fmt {
+        StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        StatsFormat::Csv => println!("{}", stats.to_csv()),
+    }
+    Ok(stats)
+}
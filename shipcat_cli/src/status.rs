@@ -1,7 +1,8 @@
-use crate::{kubeapi::ShipKube, track::PodSummary, Result};
+use crate::{kubeapi::ShipKube, track::PodSummary, Error, Result};
+use futures_timer::Delay;
 use k8s_openapi::api::core::v1::Pod;
-use shipcat_definitions::status::Condition;
-use std::convert::TryFrom;
+use shipcat_definitions::status::{format_time_since, Condition};
+use std::{convert::TryFrom, str::FromStr, time::Duration};
 
 fn format_condition(cond: &Condition) -> Result<String> {
     let mut s = String::from("");
@@ -40,16 +41,125 @@ fn format_pods(pods: Vec<Pod>) -> Result<()> {
     Ok(())
 }
 
-use crate::{Config, Region};
+use crate::{Config, Manifest, Region};
+use shipcat_definitions::manifest::ShipcatManifest;
+
+/// Conditions that end `shipcat status --watch`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchUntil {
+    /// The requested version has rolled out
+    RolledOut,
+}
+
+impl FromStr for WatchUntil {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "rolledout" => Ok(Self::RolledOut),
+            _ => bail!("Watch condition must be rolledout"),
+        }
+    }
+}
+
+impl WatchUntil {
+    fn reached(self, crd: &ShipcatManifest) -> bool {
+        match self {
+            WatchUntil::RolledOut => is_rolled_out(crd),
+        }
+    }
+}
+
+/// Whether the version requested in a crd has its last successful rollout
+pub fn is_rolled_out(crd: &ShipcatManifest) -> bool {
+    let stat = match &crd.status {
+        Some(s) => s,
+        None => return false,
+    };
+    let rolledout = stat
+        .conditions
+        .rolledout
+        .as_ref()
+        .map(|c| c.status)
+        .unwrap_or(false);
+    let successver = stat
+        .summary
+        .as_ref()
+        .and_then(|s| s.last_successful_rollout_version.as_ref());
+    rolledout && crd.spec.version.is_some() && successver == crd.spec.version.as_ref()
+}
+
 /// Entry point for `shipcat status`
 pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
     let mf = shipcat_filebacked::load_manifest(svc, conf, reg).await?;
+    if print_disabled(&mf, reg) {
+        return Ok(());
+    }
+    let api = ShipKube::new(&mf).await?;
+    print_status(&api, &mf, conf).await?;
+    Ok(())
+}
+
+/// Entry point for `shipcat status --watch`
+///
+/// Re-renders the status whenever the service's pods, replicasets or crd change,
+/// until interrupted or the `until` condition is reached.
+pub async fn watch(svc: &str, conf: &Config, reg: &Region, until: Option<WatchUntil>) -> Result<()> {
+    let mf = shipcat_filebacked::load_manifest(svc, conf, reg).await?;
+    if print_disabled(&mf, reg) {
+        return Ok(());
+    }
     let api = ShipKube::new(&mf).await?;
+    let mut changes = api.watch_changes();
+    loop {
+        print!("\x1B[2J\x1B[H"); // clear the screen and start at the top
+        let crd = print_status(&api, &mf, conf).await?;
+        if let Some(u) = until {
+            if u.reached(&crd) {
+                return Ok(());
+            }
+        }
+        tokio::select! {
+            changed = changes.recv() => {
+                if changed.is_none() {
+                    bail!("Lost the watch on {}", mf.name);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        // changes come in bursts, so let them settle before re-rendering
+        Delay::new(Duration::from_millis(500)).await;
+        while let Ok(name) = changes.try_recv() {
+            trace!("{} changed", name);
+        }
+    }
+}
+
+/// Print why a service is disabled in a region, if it is
+fn print_disabled(mf: &Manifest, reg: &Region) -> bool {
+    if let Some(d) = mf.disabled_in_region() {
+        print!(
+            "==> {} is disabled in {}: {}",
+            mf.name.to_uppercase(),
+            reg.name,
+            d.reason
+        );
+        if let Some(until) = d.until {
+            print!(" (until {})", until);
+        }
+        println!();
+        return true;
+    }
+    false
+}
+
+/// Print the status of a service and return its crd
+async fn print_status(api: &ShipKube, mf: &Manifest, conf: &Config) -> Result<ShipcatManifest> {
     let crd = api.get().await?;
     let pod_res = api.get_pods().await;
 
     let md = mf.metadata.clone().expect("need metadata");
-    let ver = crd.spec.version.expect("need version");
+    let ver = crd.spec.version.clone().expect("need version");
     let support = md.support.clone().unwrap();
     let link = md.github_link_for_version(&ver);
     // crazy terminal hyperlink escape codes with rust format {} parts:
@@ -83,7 +193,7 @@ pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
     println!();
 
     println!("==> CONDITIONS");
-    if let Some(stat) = crd.status {
+    if let Some(stat) = &crd.status {
         let conds = &stat.conditions;
         if let Some(gen) = &conds.generated {
             println!("Generated {}", format_condition(gen)?);
@@ -94,6 +204,21 @@ pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
         if let Some(ro) = &conds.rolledout {
             println!("RolledOut {}", format_condition(ro)?);
         }
+        if let Some(summary) = &stat.summary {
+            if let (Some(at), Some(reason)) = (&summary.last_restart, &summary.last_restart_reason) {
+                let mut s = match format_time_since(at) {
+                    Ok(when) => format!("{} ago", when),
+                    Err(_) => at.clone(),
+                };
+                if let Some(src) = &summary.last_restart_source {
+                    s += &format!(" via {}", src.name);
+                }
+                println!("Restarted {} ({})", s, reason);
+            }
+            if let Some(secs) = summary.last_image_pull_seconds {
+                println!("Image pull took {}s in the last rollout", secs);
+            }
+        }
     }
     println!();
 
@@ -111,5 +236,5 @@ pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
         });
         format_pods(pvec)?;
     }
-    Ok(())
+    Ok(crd)
 }
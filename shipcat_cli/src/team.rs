@@ -0,0 +1,239 @@
+use futures::stream::{self, StreamExt};
+use shipcat_definitions::{Config, Region};
+use std::future::Future;
+
+use super::Result;
+use crate::{
+    apply,
+    cluster::{self, DiffResult},
+    kubeapi::ShipKube,
+    permissions, status,
+};
+
+/// Services owned by a squad in a region
+///
+/// Ownership is the `metadata.team` of each manifest, which must match a squad in teams.yml.
+pub async fn services(squad: &str, conf: &Config, reg: &Region) -> Result<Vec<String>> {
+    if !conf.owners.squads.contains_key(squad) {
+        bail!("{} is not a squad in teams.yml", squad);
+    }
+    let mut svcs = shipcat_filebacked::available(conf, reg)
+        .await?
+        .into_iter()
+        .filter(|s| s.base.metadata.team == squad)
+        .map(|s| s.base.name)
+        .collect::<Vec<_>>();
+    if svcs.is_empty() {
+        bail!("{} owns no services in {}", squad, reg.name);
+    }
+    svcs.sort();
+    Ok(svcs)
+}
+
+/// Run an operation for every service, `n_workers` at a time
+///
+/// Progress is shown on a bar shared by all the services, or logged when `show_bar` is false
+/// (commands that draw their own progress bars would fight over the terminal).
+/// Results are returned in service order once every operation has finished.
+async fn run_all<T, F, Fut>(
+    prefix: &str,
+    svcs: Vec<String>,
+    n_workers: usize,
+    show_bar: bool,
+    op: F,
+) -> Vec<(String, Result<T>)>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    use indicatif::{ProgressBar, ProgressStyle};
+    let total = svcs.len();
+    let pb = if show_bar {
+        ProgressBar::new(total as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    pb.set_style(
+        ProgressStyle::default_bar().template("> {prefix} [{bar:30}] {pos}/{len} ({elapsed}) {msg}"),
+    );
+    pb.set_prefix(prefix);
+
+    let mut buffered = stream::iter(svcs)
+        .map(|svc| {
+            let fut = op(svc.clone());
+            async move { (svc, fut.await) }
+        })
+        .buffer_unordered(n_workers);
+    let mut res = vec![];
+    while let Some((svc, r)) = buffered.next().await {
+        pb.inc(1);
+        pb.set_message(&svc);
+        if !show_bar {
+            info!("{}: {} done ({}/{})", prefix, svc, res.len() + 1, total);
+        }
+        res.push((svc, r));
+    }
+    pb.finish_and_clear();
+    res.sort_by(|a, b| a.0.cmp(&b.0));
+    res
+}
+
+/// Log the failures among results and summarise them as an error
+fn check_failures<T>(action: &str, res: &[(String, Result<T>)]) -> Result<()> {
+    let mut failed = vec![];
+    for (svc, r) in res {
+        if let Err(e) = r {
+            error!("{}: {}", svc, e);
+            debug!("{:?}", e);
+            failed.push(svc.clone());
+        }
+    }
+    if !failed.is_empty() {
+        return Err(cluster::failure_summary(action, failed));
+    }
+    Ok(())
+}
+
+/// Rollout state of a service, as shown by `shipcat team status`
+struct ServiceStatus {
+    name: String,
+    requested: Option<String>,
+    running: Option<String>,
+    rolled_out: bool,
+    disabled: Option<String>,
+}
+
+impl ServiceStatus {
+    fn row(&self) -> String {
+        let state = if let Some(reason) = &self.disabled {
+            format!("Disabled ({})", reason)
+        } else if self.rolled_out {
+            "RolledOut".to_string()
+        } else {
+            "Pending".to_string()
+        };
+        let requested = self.requested.as_deref().unwrap_or("-");
+        let running = self.running.as_deref().unwrap_or("-");
+        format!("{:<40} {:<20} {:<20} {}", self.name, requested, running, state)
+    }
+}
+
+async fn status_of(svc: String, conf: &Config, reg: &Region) -> Result<ServiceStatus> {
+    let mf = shipcat_filebacked::load_manifest(&svc, conf, reg).await?;
+    let mut res = ServiceStatus {
+        name: svc,
+        requested: None,
+        running: None,
+        rolled_out: false,
+        disabled: mf.disabled_in_region().map(|d| d.reason.clone()),
+    };
+    if res.disabled.is_some() {
+        return Ok(res);
+    }
+    let crd = ShipKube::new(&mf).await?.get().await?;
+    res.rolled_out = status::is_rolled_out(&crd);
+    res.running = crd
+        .status
+        .as_ref()
+        .and_then(|s| s.summary.as_ref())
+        .and_then(|s| s.last_successful_rollout_version.clone());
+    res.requested = crd.spec.version;
+    Ok(res)
+}
+
+/// Entry point for `shipcat team {squad} status`
+///
+/// Prints one line per service with its requested and last successfully rolled out versions.
+pub async fn status(squad: &str, conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+    let svcs = services(squad, conf, reg).await?;
+    let prefix = format!("status {}", squad);
+    let res = run_all(&prefix, svcs, n_workers, true, |svc| status_of(svc, conf, reg)).await;
+    println!(
+        "{:<40} {:<20} {:<20} {}",
+        "SERVICE", "REQUESTED", "RUNNING", "STATE"
+    );
+    for (_, r) in &res {
+        if let Ok(s) = r {
+            println!("{}", s.row());
+        }
+    }
+    check_failures("get status of", &res)
+}
+
+/// Entry point for `shipcat team {squad} diff`
+///
+/// Diffs every service against the cluster, printing the masked and minified diffs together.
+pub async fn diff(squad: &str, conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+    assert!(conf.has_secrets());
+    let svcs = services(squad, conf, reg).await?;
+    let prefix = format!("diff {}", squad);
+    let res = run_all(&prefix, svcs, n_workers, true, |svc| {
+        cluster::diff_summary(svc, conf, reg)
+    })
+    .await;
+    let mut unchanged = vec![];
+    for (_, r) in &res {
+        match r {
+            Ok(DiffResult { name, diff: Some(d) }) => println!("==> {}\n{}\n", name, d),
+            Ok(DiffResult { name, diff: None }) => unchanged.push(name.clone()),
+            Err(_) => {}
+        }
+    }
+    if !unchanged.is_empty() {
+        println!("==> {} unchanged: {}", unchanged.len(), unchanged.join(", "));
+    }
+    check_failures("diff", &res)
+}
+
+/// Entry point for `shipcat team {squad} apply`
+///
+/// Applies every service the caller is allowed to, and prints which ones were upgraded.
+/// Rollouts are tracked with their own progress bars when waiting for them.
+pub async fn apply(
+    squad: &str,
+    conf: &Config,
+    reg: &Region,
+    n_workers: usize,
+    force: bool,
+    wait: bool,
+) -> Result<()> {
+    assert!(conf.has_secrets());
+    let svcs = services(squad, conf, reg).await?;
+    for svc in &svcs {
+        permissions::enforce(svc, conf, reg).await?;
+    }
+    let prefix = format!("apply {}", squad);
+    let res = run_all(&prefix, svcs, n_workers, !wait, |svc| {
+        apply::apply(svc, force, reg, conf, wait, None, false)
+    })
+    .await;
+    for (svc, r) in &res {
+        match r {
+            Ok(Some(ui)) => println!("{} upgraded to {}", svc, ui.version),
+            Ok(None) => println!("{} up to date", svc),
+            Err(_) => {}
+        }
+    }
+    check_failures("apply", &res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceStatus;
+
+    #[test]
+    fn team_status_rows() {
+        let mut s = ServiceStatus {
+            name: "fake-ask".into(),
+            requested: Some("1.2.0".into()),
+            running: Some("1.1.0".into()),
+            rolled_out: false,
+            disabled: None,
+        };
+        assert!(s.row().starts_with("fake-ask "));
+        assert!(s.row().ends_with(" Pending"));
+        assert!(s.row().contains("1.2.0"));
+        s.disabled = Some("Waiting for new database cluster".into());
+        assert!(s.row().ends_with("Disabled (Waiting for new database cluster)"));
+    }
+}
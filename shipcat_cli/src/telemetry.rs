@@ -0,0 +1,250 @@
+use clap::ArgMatches;
+use shipcat_definitions::{Config, Environment};
+use std::{path::PathBuf, time::Duration};
+use tokio::fs;
+
+use super::{ErrorKind, Result, ResultExt};
+
+/// Whether a command succeeded
+///
+/// Error messages are never sent, only which way the command went.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitCategory {
+    Success,
+    Failure,
+}
+
+/// Anonymous record of one shipcat invocation
+///
+/// This is everything telemetry ever sends. Argument values (services, versions,
+/// files, urls) are never recorded, only the names of the subcommands invoked.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    /// Subcommand path, e.g. `cluster crd reconcile`
+    command: String,
+    duration_ms: u64,
+    exit: ExitCategory,
+    /// Shipcat version
+    version: String,
+    /// Environment class of the region the command ran against
+    environment: Option<Environment>,
+}
+
+impl Event {
+    /// Create an event, refusing anything that does not look like a subcommand path
+    fn new(command: String, duration: Duration, ok: bool, environment: Option<Environment>) -> Result<Event> {
+        let valid = |w: &str| {
+            !w.is_empty()
+                && w.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        };
+        if !command.split(' ').all(valid) {
+            bail!("Refusing to record '{}' as a command name", command);
+        }
+        Ok(Event {
+            command,
+            duration_ms: duration.as_millis() as u64,
+            exit: if ok {
+                ExitCategory::Success
+            } else {
+                ExitCategory::Failure
+            },
+            version: env!("CARGO_PKG_VERSION").into(),
+            environment,
+        })
+    }
+}
+
+/// Subcommand names invoked, ignoring all argument values
+fn command_path(args: &ArgMatches<'_>) -> String {
+    let mut words = vec![];
+    let mut current = args;
+    while let (name, Some(sub)) = current.subcommand() {
+        words.push(name);
+        current = sub;
+    }
+    words.join(" ")
+}
+
+/// Region passed with `-r` to any of the invoked subcommands
+fn region_arg<'a>(args: &'a ArgMatches<'_>) -> Option<&'a str> {
+    let mut current = args;
+    loop {
+        if let Some(r) = current.value_of("region") {
+            return Some(r);
+        }
+        current = current.subcommand().1?;
+    }
+}
+
+fn profile_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("shipcat").join("profile.yml"))
+}
+
+fn batch_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("shipcat").join("telemetry.jsonl"))
+}
+
+/// The user's profile, kept as a raw mapping so unknown keys survive rewrites
+async fn read_profile() -> serde_yaml::Mapping {
+    let data = match profile_path() {
+        Some(pth) => fs::read_to_string(pth).await.unwrap_or_default(),
+        None => return serde_yaml::Mapping::new(),
+    };
+    serde_yaml::from_str(&data).unwrap_or_default()
+}
+
+/// Whether the user opted in with `telemetry: true` in their profile
+pub async fn enabled() -> bool {
+    let key = serde_yaml::Value::from("telemetry");
+    read_profile()
+        .await
+        .get(&key)
+        .and_then(serde_yaml::Value::as_bool)
+        == Some(true)
+}
+
+async fn pending() -> Vec<String> {
+    let data = match batch_path() {
+        Some(pth) => fs::read_to_string(pth).await.unwrap_or_default(),
+        None => return vec![],
+    };
+    data.lines().map(String::from).collect()
+}
+
+async fn append(event: &Event) -> Result<Vec<String>> {
+    let pth = match batch_path() {
+        Some(p) => p,
+        None => bail!("No cache directory to store telemetry in"),
+    };
+    let mut lines = pending().await;
+    lines.push(serde_json::to_string(event)?);
+    if let Some(dir) = pth.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(&pth, lines.join("\n") + "\n").await?;
+    Ok(lines)
+}
+
+async fn send(url: &str, lines: &[String]) -> Result<()> {
+    let url = reqwest::Url::parse(url)?;
+    let body = format!("[{}]", lines.join(","));
+    reqwest::Client::new()
+        .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .chain_err(|| ErrorKind::Url(url))?;
+    Ok(())
+}
+
+async fn try_record(args: &ArgMatches<'_>, elapsed: Duration, ok: bool) -> Result<()> {
+    if !enabled().await {
+        return Ok(());
+    }
+    // the endpoint lives in shipcat.conf, so events pile up outside a manifests checkout
+    let conf = Config::read().await.ok();
+    let environment = match (&conf, region_arg(args)) {
+        (Some(c), Some(r)) => c.get_region(r).ok().map(|r| r.environment),
+        _ => None,
+    };
+    let event = Event::new(command_path(args), elapsed, ok, environment)?;
+    let lines = append(&event).await?;
+    if let Some(tc) = conf.and_then(|c| c.telemetry) {
+        if lines.len() >= tc.batchSize {
+            send(&tc.url, &lines).await?;
+            if let Some(pth) = batch_path() {
+                fs::remove_file(pth).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record an invocation for users who opted in
+///
+/// Never fails the command; problems are only logged at debug level.
+pub async fn record(args: &ArgMatches<'_>, elapsed: Duration, ok: bool) {
+    if let Err(e) = try_record(args, elapsed, ok).await {
+        debug!("Failed to record telemetry: {}", e);
+    }
+}
+
+/// Print whether telemetry is enabled and what it collects
+pub async fn status() -> Result<()> {
+    let profile = profile_path()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    if enabled().await {
+        println!("telemetry is enabled (telemetry: true in {})", profile);
+    } else {
+        println!(
+            "telemetry is disabled (set telemetry: true in {} to opt in)",
+            profile
+        );
+    }
+    println!("{} events pending", pending().await.len());
+    println!("collected: command name, duration, exit category, shipcat version, region environment");
+    Ok(())
+}
+
+/// Opt out of telemetry and discard events not yet sent
+pub async fn off() -> Result<()> {
+    let pth = match profile_path() {
+        Some(p) => p,
+        None => bail!("No config directory to store the shipcat profile in"),
+    };
+    let mut profile = read_profile().await;
+    profile.insert("telemetry".into(), false.into());
+    if let Some(dir) = pth.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(&pth, serde_yaml::to_string(&profile)?).await?;
+    if let Some(batch) = batch_path() {
+        let _ = fs::remove_file(batch).await;
+    }
+    info!("Disabled telemetry in {}", pth.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use shipcat_definitions::Environment;
+    use std::time::Duration;
+
+    #[test]
+    fn telemetry_event_payload() {
+        let ev = Event::new(
+            "cluster crd reconcile".into(),
+            Duration::from_millis(1500),
+            false,
+            Some(Environment::Prod),
+        )
+        .unwrap();
+        let json = serde_json::to_value(&ev).unwrap();
+        let keys = json.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys, vec![
+            "command",
+            "durationMs",
+            "environment",
+            "exit",
+            "version"
+        ]);
+        assert_eq!(json["exit"], "failure");
+        assert_eq!(json["environment"], "prod");
+        assert_eq!(json["durationMs"], 1500);
+
+        // anything that is not a plain subcommand path is refused
+        let invalid = |cmd: &str| Event::new(cmd.into(), Duration::from_secs(1), true, None).is_err();
+        assert!(invalid("apply fake-ask --tag 1.2.3"));
+        assert!(invalid("values /home/user/secrets.yml"));
+        assert!(invalid("apply  "));
+        assert!(!invalid("values"));
+    }
+}
@@ -1,7 +1,10 @@
 use super::{Config, Error, Manifest, Region, Result};
 use futures::stream::{self, StreamExt};
-use shipcat_definitions::{math::ResourceTotals, BaseManifest};
-use std::{collections::BTreeMap, str::FromStr};
+use shipcat_definitions::{
+    math::{ResourceTotals, WorkloadTotals},
+    BaseManifest, PriceSheet, PricingConfig,
+};
+use std::{cmp::Reverse, collections::BTreeMap, str::FromStr};
 
 use generic_array::{typenum::U4, GenericArray};
 use size_format::{PointSeparated, PrefixType, SizeFormatter, SizeFormatterBinary};
@@ -47,7 +50,10 @@ async fn load_mf_req(svc: String, conf: &Config, reg: &Region) -> Result<(Manife
     Ok((mf, res))
 }
 
-async fn calculate_manifest_requests(conf: &Config, reg: &Region) -> Result<Vec<(Manifest, ResourceTotals)>> {
+pub(crate) async fn calculate_manifest_requests(
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<(Manifest, ResourceTotals)>> {
     let available = shipcat_filebacked::available(conf, &reg).await?;
     let mut buffered = stream::iter(available)
         .map(move |mf| load_mf_req(mf.base.name, conf, reg))
@@ -59,11 +65,15 @@ async fn calculate_manifest_requests(conf: &Config, reg: &Region) -> Result<Vec<
     Ok(mfs)
 }
 
-async fn load_mf_req_world(base: BaseManifest, conf: &Config) -> Result<Option<(Manifest, ResourceTotals)>> {
+async fn load_mf_req_world(
+    base: BaseManifest,
+    conf: &Config,
+    regions: &[String],
+) -> Result<Option<(Manifest, ResourceTotals)>> {
     let mut res = ResourceTotals::default();
     let mut first_mf = None;
     debug!("{} looping over {:?}", base.name, base.regions);
-    for r in &base.regions {
+    for r in base.regions.iter().filter(|r| regions.contains(r)) {
         if let Some(reg) = conf.get_region_unchecked(&r) {
             trace!("valid region: {}", reg.name);
             let mf = shipcat_filebacked::load_manifest(&base.name, &conf, &reg)
@@ -89,10 +99,13 @@ async fn load_mf_req_world(base: BaseManifest, conf: &Config) -> Result<Option<(
     }
 }
 
-async fn calculate_manifest_requests_world(conf: &Config) -> Result<Vec<(Manifest, ResourceTotals)>> {
+async fn calculate_manifest_requests_world(
+    conf: &Config,
+    regions: &[String],
+) -> Result<Vec<(Manifest, ResourceTotals)>> {
     let all = shipcat_filebacked::all(conf).await?;
     let mut buffered = stream::iter(all)
-        .map(|mf| load_mf_req_world(mf, conf))
+        .map(|mf| load_mf_req_world(mf, conf, regions))
         .buffer_unordered(100);
     let mut mfs = vec![];
     while let Some(r) = buffered.next().await {
@@ -105,7 +118,8 @@ async fn calculate_manifest_requests_world(conf: &Config) -> Result<Vec<(Manifes
 
 /// Resource top for a every region
 ///
-/// This presents an analytical solution to aggregate resource requests.
+/// This presents an analytical solution to aggregate resource requests
+/// across the given regions.
 /// It does NOT talk to kubernetes.
 ///
 /// It works out ResourceTotals based on Manifest properties analytically.
@@ -114,8 +128,9 @@ pub async fn world_requests(
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
+    regions: &[String],
 ) -> Result<Vec<(Manifest, ResourceTotals)>> {
-    let mfs = calculate_manifest_requests_world(conf).await?;
+    let mfs = calculate_manifest_requests_world(conf, regions).await?;
     let mfs = sort_and_print_resources(mfs, order, fmt, ub)?;
     Ok(mfs)
 }
@@ -342,8 +357,9 @@ pub async fn world_squad_requests(
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
+    regions: &[String],
 ) -> Result<Vec<(String, ResourceTotals)>> {
-    let mfs = calculate_manifest_requests_world(conf).await?;
+    let mfs = calculate_manifest_requests_world(conf, regions).await?;
     let team_requests = fold_manifests_by_squad(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub)?;
     Ok(sorted)
@@ -358,8 +374,9 @@ pub async fn world_tribe_requests(
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
+    regions: &[String],
 ) -> Result<Vec<(String, ResourceTotals)>> {
-    let mfs = calculate_manifest_requests_world(conf).await?;
+    let mfs = calculate_manifest_requests_world(conf, regions).await?;
     let team_requests = fold_manifests_by_tribe(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub)?;
     Ok(sorted)
@@ -450,3 +467,396 @@ fn sort_and_print_team_resources(
     }
     Ok(reqs)
 }
+
+/// How to aggregate a workload breakdown
+pub enum WorkloadGrouping {
+    Service,
+    Squad,
+    Tribe,
+}
+
+async fn load_mf_workloads(svc: String, conf: &Config, reg: &Region) -> Result<(Manifest, WorkloadTotals)> {
+    let mf = shipcat_filebacked::load_manifest(&svc, conf, reg)
+        .await?
+        .stub(reg)
+        .await?;
+    let res = mf.compute_workload_totals()?;
+    Ok((mf, res))
+}
+
+async fn calculate_workload_requests(conf: &Config, reg: &Region) -> Result<Vec<(Manifest, WorkloadTotals)>> {
+    let available = shipcat_filebacked::available(conf, reg).await?;
+    let mut buffered = stream::iter(available)
+        .map(move |mf| load_mf_workloads(mf.base.name, conf, reg))
+        .buffer_unordered(100);
+    let mut mfs = vec![];
+    while let Some(r) = buffered.next().await {
+        mfs.push(r?);
+    }
+    Ok(mfs)
+}
+
+async fn load_mf_workloads_world(
+    base: BaseManifest,
+    conf: &Config,
+    regions: &[String],
+) -> Result<Option<(Manifest, WorkloadTotals)>> {
+    let mut res = WorkloadTotals::default();
+    let mut first_mf = None;
+    for r in base.regions.iter().filter(|r| regions.contains(r)) {
+        if let Some(reg) = conf.get_region_unchecked(r) {
+            let mf = shipcat_filebacked::load_manifest(&base.name, conf, reg)
+                .await?
+                .stub(reg)
+                .await?;
+            if !mf.disabled && !mf.external {
+                res += mf.compute_workload_totals()?;
+                first_mf = Some(mf);
+            }
+        }
+    }
+    Ok(first_mf.map(|mf| (mf, res)))
+}
+
+async fn calculate_workload_requests_world(
+    conf: &Config,
+    regions: &[String],
+) -> Result<Vec<(Manifest, WorkloadTotals)>> {
+    let all = shipcat_filebacked::all(conf).await?;
+    let mut buffered = stream::iter(all)
+        .map(|mf| load_mf_workloads_world(mf, conf, regions))
+        .buffer_unordered(100);
+    let mut mfs = vec![];
+    while let Some(r) = buffered.next().await {
+        if let Some(v) = r? {
+            mfs.push(v);
+        }
+    }
+    Ok(mfs)
+}
+
+fn fold_workloads(
+    reqs: Vec<(Manifest, WorkloadTotals)>,
+    grouping: &WorkloadGrouping,
+) -> Vec<(String, WorkloadTotals)> {
+    let mut acc = BTreeMap::<String, WorkloadTotals>::new();
+    for (mf, res) in reqs {
+        let md = mf.metadata.as_ref().unwrap();
+        let key = match grouping {
+            WorkloadGrouping::Service => mf.name.clone(),
+            WorkloadGrouping::Squad => md.squad.clone().unwrap(),
+            WorkloadGrouping::Tribe => {
+                if let Some(tribe) = &md.tribe {
+                    tribe.clone()
+                } else {
+                    warn!("Could not find a matching tribe for {}", mf.name);
+                    continue;
+                }
+            }
+        };
+        *acc.entry(key).or_default() += res;
+    }
+    acc.into_iter().collect()
+}
+
+/// Requested cpu (in millicores) and memory (in Bytes) of a workload type
+#[derive(Serialize)]
+struct WorkloadRequest {
+    cpu: u64,
+    memory: u64,
+}
+
+impl WorkloadRequest {
+    fn new(r: &ResourceTotals, upper_bounds: bool) -> Self {
+        if upper_bounds {
+            WorkloadRequest {
+                cpu: (1000.0 * (r.base.requests.cpu + r.extra.requests.cpu)) as u64,
+                memory: (r.base.requests.memory + r.extra.requests.memory) as u64,
+            }
+        } else {
+            WorkloadRequest {
+                cpu: (1000.0 * r.base.requests.cpu) as u64,
+                memory: r.base.requests.memory as u64,
+            }
+        }
+    }
+
+    fn get(&self, order: &ResourceOrder) -> u64 {
+        match order {
+            ResourceOrder::Cpu => self.cpu,
+            ResourceOrder::Memory => self.memory,
+        }
+    }
+
+    fn format(&self, order: &ResourceOrder) -> String {
+        match order {
+            ResourceOrder::Cpu => format!(
+                "{:.0}",
+                SizeFormatter::<u64, Millicores, PointSeparated>::new(self.cpu)
+            ),
+            ResourceOrder::Memory => format!("{:.0}", SizeFormatterBinary::new(self.memory)),
+        }
+    }
+}
+
+/// Breakdown of requests per workload type, as printed by `shipcat get resources`
+#[derive(Serialize)]
+struct WorkloadOutput {
+    name: String,
+    main: WorkloadRequest,
+    workers: WorkloadRequest,
+    cronjobs: WorkloadRequest,
+    sidecars: WorkloadRequest,
+    total: WorkloadRequest,
+}
+
+impl WorkloadOutput {
+    fn new(name: String, w: &WorkloadTotals, upper_bounds: bool) -> Self {
+        WorkloadOutput {
+            name,
+            main: WorkloadRequest::new(&w.main, upper_bounds),
+            workers: WorkloadRequest::new(&w.workers, upper_bounds),
+            cronjobs: WorkloadRequest::new(&w.cronjobs, upper_bounds),
+            sidecars: WorkloadRequest::new(&w.sidecars, upper_bounds),
+            total: WorkloadRequest::new(&w.total(), upper_bounds),
+        }
+    }
+
+    /// Percentage of the total request spent on sidecars
+    fn sidecar_share(&self, order: &ResourceOrder) -> u64 {
+        match self.total.get(order) {
+            0 => 0,
+            total => (100 * self.sidecars.get(order) + total / 2) / total,
+        }
+    }
+}
+
+fn sort_and_print_workloads(
+    reqs: Vec<(String, WorkloadTotals)>,
+    grouping: &WorkloadGrouping,
+    order: ResourceOrder,
+    formatting: OutputFormat,
+    upper_bounds: bool,
+) -> Result<Vec<(String, WorkloadTotals)>> {
+    let mut output = reqs
+        .iter()
+        .map(|(name, w)| WorkloadOutput::new(name.clone(), w, upper_bounds))
+        .collect::<Vec<_>>();
+    output.sort_by_key(|o| Reverse(o.total.get(&order)));
+
+    match formatting {
+        OutputFormat::Table => {
+            let header = match grouping {
+                WorkloadGrouping::Service => "SERVICE",
+                WorkloadGrouping::Squad => "SQUAD",
+                WorkloadGrouping::Tribe => "TRIBE",
+            };
+            println!(
+                "{0:<45} {1:<8} {2:<8} {3:<8} {4:<8} {5:<8} SIDECAR%",
+                header, "MAIN", "WORKERS", "CRONJOBS", "SIDECARS", "TOTAL"
+            );
+            for o in &output {
+                println!(
+                    "{0:<45} {1:width$} {2:width$} {3:width$} {4:width$} {5:width$} {6}%",
+                    o.name,
+                    o.main.format(&order),
+                    o.workers.format(&order),
+                    o.cronjobs.format(&order),
+                    o.sidecars.format(&order),
+                    o.total.format(&order),
+                    o.sidecar_share(&order),
+                    width = 8,
+                );
+            }
+        }
+        OutputFormat::Yaml => {
+            println!("{}", serde_yaml::to_string(&output)?);
+        }
+    }
+    Ok(reqs)
+}
+
+/// Resource requests per workload type for a single region
+///
+/// Splits the requests of region_requests into the main workload, workers, cronjobs and sidecars,
+/// aggregated per service, squad or tribe.
+pub async fn region_workload_requests(
+    grouping: WorkloadGrouping,
+    order: ResourceOrder,
+    ub: bool,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<(String, WorkloadTotals)>> {
+    let mfs = calculate_workload_requests(conf, reg).await?;
+    let reqs = fold_workloads(mfs, &grouping);
+    sort_and_print_workloads(reqs, &grouping, order, fmt, ub)
+}
+
+/// Resource requests per workload type for every region
+///
+/// Same data as region_workload_requests, but summed across the given regions.
+pub async fn world_workload_requests(
+    grouping: WorkloadGrouping,
+    order: ResourceOrder,
+    ub: bool,
+    fmt: OutputFormat,
+    conf: &Config,
+    regions: &[String],
+) -> Result<Vec<(String, WorkloadTotals)>> {
+    let mfs = calculate_workload_requests_world(conf, regions).await?;
+    let reqs = fold_workloads(mfs, &grouping);
+    sort_and_print_workloads(reqs, &grouping, order, fmt, ub)
+}
+
+/// How to print cost estimates
+pub enum CostFormat {
+    /// Human readable table with a total
+    Table,
+    /// Yaml with raw numbers in milli-cores, Bytes and the currency
+    Yaml,
+    /// Csv with the same numbers as yaml, for spreadsheets
+    Csv,
+}
+
+impl FromStr for CostFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "table" => Ok(Self::Table),
+            "yaml" => Ok(Self::Yaml),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("Cost format must be table, yaml or csv"),
+        }
+    }
+}
+
+/// Estimated monthly cost of a service, squad or tribe
+#[derive(Serialize, Default)]
+struct CostOutput {
+    name: String,
+    /// Requested millicores
+    cpu: u64,
+    /// Requested Bytes
+    memory: u64,
+    /// Monthly cost in the configured currency
+    monthly: f64,
+}
+
+/// Prices of a region, from the pricing api when configured
+async fn region_prices(pricing: &PricingConfig, region: &str) -> Result<PriceSheet> {
+    match &pricing.url {
+        Some(url) => {
+            debug!("fetching prices for {} from {}", region, url);
+            let sheet = reqwest::Client::new()
+                .get(url)
+                .query(&[("region", region)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(sheet)
+        }
+        None => Ok(pricing.sheet(region)),
+    }
+}
+
+fn cost_key(mf: &Manifest, grouping: &WorkloadGrouping) -> Option<String> {
+    let md = mf.metadata.as_ref().unwrap();
+    match grouping {
+        WorkloadGrouping::Service => Some(mf.name.clone()),
+        WorkloadGrouping::Squad => md.squad.clone(),
+        WorkloadGrouping::Tribe => {
+            if md.tribe.is_none() {
+                warn!("Could not find a matching tribe for {}", mf.name);
+            }
+            md.tribe.clone()
+        }
+    }
+}
+
+fn print_costs(
+    output: &[CostOutput],
+    grouping: &WorkloadGrouping,
+    currency: &str,
+    fmt: CostFormat,
+) -> Result<()> {
+    match fmt {
+        CostFormat::Table => {
+            let header = match grouping {
+                WorkloadGrouping::Service => "SERVICE",
+                WorkloadGrouping::Squad => "SQUAD",
+                WorkloadGrouping::Tribe => "TRIBE",
+            };
+            let monthly = format!("MONTHLY ({})", currency);
+            println!("{0:<45} {1:<8} {2:<8} {3}", header, "CPU", "MEMORY", monthly);
+            for o in output {
+                println!(
+                    "{0:<45} {1:width$} {2:width$} {3:.2}",
+                    o.name,
+                    format!(
+                        "{:.0}",
+                        SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
+                    ),
+                    format!("{:.0}", SizeFormatterBinary::new(o.memory)),
+                    o.monthly,
+                    width = 8,
+                );
+            }
+            let total: f64 = output.iter().map(|o| o.monthly).sum();
+            println!("{0:<63} {1:.2}", "TOTAL", total);
+        }
+        CostFormat::Yaml => {
+            println!("{}", serde_yaml::to_string(&output)?);
+        }
+        CostFormat::Csv => {
+            println!("name,cpu,memory,monthly");
+            for o in output {
+                println!("{},{},{},{:.2}", o.name, o.cpu, o.memory, o.monthly);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Estimated monthly cost of resource requests in the given regions
+///
+/// Every region is priced with its own price sheet from the `pricing` config,
+/// and the costs are summed per service, squad or tribe.
+/// Like the other tops, this is computed from manifests and does NOT talk to kubernetes.
+pub async fn cost(
+    grouping: WorkloadGrouping,
+    ub: bool,
+    fmt: CostFormat,
+    conf: &Config,
+    regions: &[Region],
+) -> Result<Vec<(String, f64)>> {
+    let pricing = match &conf.pricing {
+        Some(p) => p,
+        None => bail!("shipcat.conf needs a pricing config to estimate costs"),
+    };
+    let mut acc = BTreeMap::<String, CostOutput>::new();
+    for reg in regions {
+        let prices = region_prices(pricing, &reg.name).await?;
+        for (mf, res) in calculate_manifest_requests(conf, reg).await? {
+            let key = match cost_key(&mf, &grouping) {
+                Some(k) => k,
+                None => continue,
+            };
+            let req = WorkloadRequest::new(&res, ub);
+            let e = acc.entry(key.clone()).or_insert_with(|| CostOutput {
+                name: key,
+                ..Default::default()
+            });
+            e.cpu += req.cpu;
+            e.memory += req.memory;
+            e.monthly += res.monthly_cost(&prices, ub);
+        }
+    }
+    let mut output = acc.into_iter().map(|(_, o)| o).collect::<Vec<_>>();
+    output.sort_by(|o1, o2| o2.monthly.partial_cmp(&o1.monthly).unwrap());
+    print_costs(&output, &grouping, &pricing.currency, fmt)?;
+    Ok(output.into_iter().map(|o| (o.name, o.monthly)).collect())
+}
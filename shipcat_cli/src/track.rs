@@ -1,13 +1,16 @@
 //- kubeapi module to track upgrades
-use crate::{kubeapi::ShipKube, slack::short_ver, Result};
+use crate::{kubeapi::WorkloadApi, slack::short_ver, Result};
 use chrono::{Duration, Utc};
+use futures::future::join_all;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use k8s_openapi::api::{
-    apps::v1::{Deployment, ReplicaSet, StatefulSet},
-    core::v1::Pod,
+    apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    core::v1::{Event, Pod},
 };
 use kube::api::{Meta, ObjectList};
 use shipcat_definitions::{Manifest, PrimaryWorkload};
 use std::{
+    collections::BTreeMap,
     convert::{TryFrom, TryInto},
     fmt::{self, Debug},
 };
@@ -162,10 +165,11 @@ impl TryFrom<ReplicaSet> for ReplicaSetSummary {
 }
 
 /// Debug why a workload is in the state it is in
-pub async fn debug(mf: &Manifest, kube: &ShipKube) -> Result<()> {
+pub async fn debug(mf: &Manifest, kube: &dyn WorkloadApi) -> Result<()> {
     match mf.workload {
         PrimaryWorkload::Deployment => debug_deployment(kube).await,
         PrimaryWorkload::Statefulset => debug_statefulset(kube).await,
+        PrimaryWorkload::DaemonSet => debug_daemonset(kube).await,
     }
 }
 
@@ -174,7 +178,7 @@ pub async fn debug(mf: &Manifest, kube: &ShipKube) -> Result<()> {
 /// Finds active replicasets (with pods in them)
 /// Debugs the pods in each replicaset
 /// Tails the logs from each broken pod
-async fn debug_deployment(kube: &ShipKube) -> Result<()> {
+async fn debug_deployment(kube: &dyn WorkloadApi) -> Result<()> {
     for rs in kube.get_rs().await? {
         if let Ok(r) = ReplicaSetSummary::try_from(rs) {
             // NB: ^ ignore replicasets that didn't parse
@@ -190,7 +194,7 @@ async fn debug_deployment(kube: &ShipKube) -> Result<()> {
     Ok(())
 }
 
-async fn debug_statefulset(kube: &ShipKube) -> Result<()> {
+async fn debug_statefulset(kube: &dyn WorkloadApi) -> Result<()> {
     // For now, just list the pods as if there were no replicaset to worry about
     let pods = kube.get_pods().await?;
     info!("Statefulset contains:");
@@ -198,7 +202,14 @@ async fn debug_statefulset(kube: &ShipKube) -> Result<()> {
     Ok(())
 }
 
-async fn debug_pods(pods: ObjectList<Pod>, kube: &ShipKube) -> Result<()> {
+async fn debug_daemonset(kube: &dyn WorkloadApi) -> Result<()> {
+    let pods = kube.get_pods().await?;
+    info!("Daemonset contains:");
+    debug_pods(pods, kube).await?;
+    Ok(())
+}
+
+async fn debug_pods(pods: ObjectList<Pod>, kube: &dyn WorkloadApi) -> Result<()> {
     for pod in pods {
         let podstate = PodSummary::try_from(pod)?;
         println!("{:?}", podstate);
@@ -305,6 +316,148 @@ impl TryFrom<StatefulSet> for StatefulSummary {
     }
 }
 
+/// A summary of a DaemonSet's status
+#[derive(Debug)]
+pub struct DaemonSummary {
+    /// Nodes that should run the daemon
+    pub desired: i32,
+    /// Nodes running the latest pod template
+    pub updated: i32,
+    pub ready: i32,
+    pub available: i32,
+}
+
+impl TryFrom<DaemonSet> for DaemonSummary {
+    type Error = crate::Error;
+
+    /// Helper to convert the openapi DaemonSet to the useful info
+    fn try_from(d: DaemonSet) -> Result<DaemonSummary> {
+        if let Some(status) = d.status {
+            Ok(DaemonSummary {
+                desired: status.desired_number_scheduled,
+                updated: status.updated_number_scheduled.unwrap_or(0),
+                ready: status.number_ready,
+                available: status.number_available.unwrap_or(0),
+            })
+        } else {
+            bail!("Missing daemonset status object")
+        }
+    }
+}
+
+/// Image pull state of the main container in a pod
+#[derive(Debug, Clone, PartialEq)]
+pub enum PullState {
+    /// Not scheduled, or no pull started yet
+    Waiting,
+    Pulling,
+    /// Pulled, with how long it took in seconds (if the image was not already on the node)
+    Pulled(Option<u32>),
+    /// Pull failing with a container waiting reason like `ImagePullBackOff`
+    Failed(String),
+}
+
+impl PullState {
+    /// Infer the pull state of a container from its pod and the pod's events
+    pub fn from_pod(pod: &Pod, events: &[Event], container: &str) -> PullState {
+        let field = format!("spec.containers{{{}}}", container);
+        let find = |reason: &str| {
+            events.iter().find(|e| {
+                e.reason.as_deref() == Some(reason)
+                    && e.involved_object.field_path.as_deref() == Some(field.as_str())
+            })
+        };
+        let status = pod
+            .status
+            .as_ref()
+            .and_then(|s| s.container_statuses.as_ref())
+            .and_then(|cs| cs.iter().find(|c| c.name == container));
+        let waiting = status
+            .and_then(|c| c.state.as_ref())
+            .and_then(|s| s.waiting.as_ref())
+            .and_then(|w| w.reason.clone());
+        if let Some(reason) = waiting {
+            if reason == "ErrImagePull" || reason == "ImagePullBackOff" {
+                return PullState::Failed(reason);
+            }
+        }
+        match (find("Pulling"), find("Pulled")) {
+            (Some(start), Some(done)) => {
+                let secs = match (&start.first_timestamp, &done.last_timestamp) {
+                    (Some(s), Some(d)) => d.0.signed_duration_since(s.0).num_seconds().try_into().ok(),
+                    _ => None,
+                };
+                PullState::Pulled(secs)
+            }
+            (Some(_), None) => PullState::Pulling,
+            // image already present on the node
+            (None, Some(_)) => PullState::Pulled(None),
+            (None, None) => {
+                if status.iter().any(|c| !c.image_id.is_empty()) {
+                    PullState::Pulled(None)
+                } else {
+                    PullState::Waiting
+                }
+            }
+        }
+    }
+}
+
+/// Image pulls of the pods in a rollout
+#[derive(Debug, Default)]
+pub struct PullTracker {
+    pods: BTreeMap<String, PullState>,
+}
+
+impl PullTracker {
+    /// Update pull states of the pods of a replicaset
+    ///
+    /// Pods that finished pulling are not checked again.
+    pub async fn poll(&mut self, kube: &dyn WorkloadApi, hash: &str, container: &str) -> Result<()> {
+        for pod in kube.get_pods_by_template_hash(hash).await? {
+            let name = Meta::name(&pod);
+            if let Some(PullState::Pulled(_)) = self.pods.get(&name) {
+                continue;
+            }
+            let events = kube.get_pod_events(&name).await?;
+            let state = PullState::from_pod(&pod, &events.items, container);
+            self.pods.insert(name, state);
+        }
+        Ok(())
+    }
+
+    /// Progress bar message while any pod is pulling or failing to pull
+    pub fn message(&self) -> Option<String> {
+        let failed = self.pods.values().find_map(|s| match s {
+            PullState::Failed(r) => Some(r),
+            _ => None,
+        });
+        if let Some(reason) = failed {
+            return Some(format!("image pull failing ({})", reason));
+        }
+        if self.pods.values().any(|s| *s == PullState::Pulling) {
+            let pulled = self
+                .pods
+                .values()
+                .filter(|s| matches!(s, PullState::Pulled(_)))
+                .count();
+            return Some(format!("pulling image (node {}/{})", pulled + 1, self.pods.len()));
+        }
+        None
+    }
+
+    /// The longest pull seen, in seconds
+    pub fn longest(&self) -> Option<u32> {
+        self.pods
+            .values()
+            .filter_map(|s| match s {
+                PullState::Pulled(secs) => *secs,
+                _ => None,
+            })
+            .max()
+    }
+}
+
 #[derive(Debug)]
 struct RolloutResult {
     progress: u32,
@@ -314,7 +467,11 @@ struct RolloutResult {
 }
 
 /// Check if a rollout has completed
-async fn rollout_status(mf: &Manifest, kube: &ShipKube, hash: &Option<String>) -> Result<RolloutResult> {
+async fn rollout_status(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    hash: &Option<String>,
+) -> Result<RolloutResult> {
     match mf.workload {
         PrimaryWorkload::Deployment => {
             // Get root data from Deployment status
@@ -408,30 +565,243 @@ async fn rollout_status(mf: &Manifest, kube: &ShipKube, hash: &Option<String>) -
                 ok,
             })
         }
+        PrimaryWorkload::DaemonSet => {
+            let ds = kube.get_daemonset().await?;
+            let d = DaemonSummary::try_from(ds)?;
+            debug!("{}: {:?}", mf.name, d);
+
+            // every node runs the new template, and all of them are available
+            let ok = d.updated == d.desired && d.available == d.desired && d.ready == d.desired;
+            let message = if ok {
+                None
+            } else {
+                Some("DaemonSet update in progress".to_string())
+            };
+            // NB: ready also counts pods of the old template, so cap progress by the updated pods
+            Ok(RolloutResult {
+                progress: std::cmp::max(0, std::cmp::min(d.updated, d.ready))
+                    .try_into()
+                    .expect("ds progress >= 0"),
+                expected: std::cmp::max(0, d.desired).try_into().expect("ds.desired >= 0"),
+                message,
+                ok,
+            })
+        }
     }
 }
 
+/// Outcome of tracking a rollout
+#[derive(Debug)]
+pub struct TrackedRollout {
+    /// Whether the rollout completed within the estimated wait time
+    pub ok: bool,
+    /// Longest image pull on a node, in seconds
+    pub image_pull_seconds: Option<u32>,
+    /// Workloads that did not roll out in time
+    pub failed: Vec<String>,
+}
+
 /// Track the rollout of the main workload
-pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
+///
+/// The wait time is estimated with the image pull time of the previous rollout, if known.
+pub async fn workload_rollout(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    track_rollout(mf, kube, std::time::Duration::from_millis(1000), last_pull).await
+}
+
+/// Track the rollout of the canary Deployment of a service
+///
+/// The canary is tracked as a Deployment of its own, so `kube` must query the canary by name.
+pub async fn canary_rollout(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    workload_rollout(&canary_manifest(mf)?, kube, last_pull).await
+}
+
+/// The canary of a manifest as a fixed size Deployment named after the canary
+fn canary_manifest(mf: &Manifest) -> Result<Manifest> {
+    let canary = match &mf.canary {
+        Some(c) => c,
+        None => bail!("{} has no canary to track", mf.name),
+    };
+    let mut cmf = mf.clone();
+    cmf.name = canary.name.clone();
+    cmf.version = Some(canary.version.clone());
+    cmf.workload = PrimaryWorkload::Deployment;
+    cmf.replicaCount = Some(canary.replicaCount);
+    cmf.autoScaling = None;
+    Ok(cmf)
+}
+
+/// The workers of a manifest as fixed size Deployments named after the workers
+///
+/// Workers with `trackRollout: false` are left out.
+pub fn worker_manifests(mf: &Manifest) -> Vec<Manifest> {
+    mf.workers
+        .iter()
+        .filter(|w| w.trackRollout)
+        .map(|w| {
+            let mut wmf = mf.clone();
+            wmf.name = w.container.name.clone();
+            wmf.workload = PrimaryWorkload::Deployment;
+            wmf.replicaCount = Some(w.replicaCount);
+            wmf.autoScaling = w.autoScaling.clone();
+            wmf.health = None;
+            wmf.readinessProbe = w.container.readiness_probe.clone();
+            wmf.workers = vec![];
+            wmf
+        })
+        .collect()
+}
+
+/// Track the rollout of the main workload along with its workers
+///
+/// `workers` pairs the manifests from `worker_manifests` with apis querying each worker by name.
+pub async fn service_rollout(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    workers: &[(Manifest, Box<dyn WorkloadApi>)],
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    track_rollouts(
+        mf,
+        kube,
+        workers,
+        std::time::Duration::from_millis(1000),
+        last_pull,
+    )
+    .await
+}
+
+/// Track the main workload and workers in parallel, with a progress bar each
+///
+/// The rollout is only ok when every workload rolled out.
+pub async fn track_rollouts(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    workers: &[(Manifest, Box<dyn WorkloadApi>)],
+    tick: std::time::Duration,
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    if workers.is_empty() {
+        return track_rollout(mf, kube, tick, last_pull).await;
+    }
+    // without a terminal there is nothing to draw, so there is nothing to join either
+    let mp = if ProgressDrawTarget::stderr().is_hidden() {
+        None
+    } else {
+        Some(MultiProgress::new())
+    };
+    track_with_progress(mp, mf, kube, workers, tick, last_pull).await
+}
+
+async fn track_with_progress(
+    mp: Option<MultiProgress>,
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    workers: &[(Manifest, Box<dyn WorkloadApi>)],
+    tick: std::time::Duration,
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    let bar = || match &mp {
+        Some(mp) => mp.add(ProgressBar::new(0)),
+        None => ProgressBar::hidden(),
+    };
+    let main = track_with_bar(mf, kube, tick, last_pull, bar());
+    let others = join_all(
+        workers
+            .iter()
+            .map(|(wmf, wk)| track_with_bar(wmf, wk.as_ref(), tick, last_pull, bar())),
+    );
+    // bars are drawn from a blocking thread until every one of them is finished
+    let drawer = mp.map(|mp| tokio::task::spawn_blocking(move || mp.join()));
+    let (main, others) = futures::join!(main, others);
+    if let Some(d) = drawer {
+        let _ = d.await;
+    }
+
+    let mut tracked = main?;
+    for tr in others {
+        tracked.failed.extend(tr?.failed);
+    }
+    tracked.ok = tracked.failed.is_empty();
+    Ok(tracked)
+}
+
+/// Track a rollout, waiting a `tick` for every second of estimated wait time
+///
+/// Lets replayed scenarios run faster than real time.
+pub async fn track_rollout(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    tick: std::time::Duration,
+    last_pull: Option<u32>,
+) -> Result<TrackedRollout> {
+    track_with_bar(
+        mf,
+        kube,
+        tick,
+        last_pull,
+        ProgressBar::new(mf.min_replicas().into()),
+    )
+    .await
+}
+
+/// Track a rollout on a progress bar, which is finished however tracking ends
+///
+/// Bars are finished with a redraw, unlike `abandon`, so that a `MultiProgress` sees them finish.
+async fn track_with_bar(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    tick: std::time::Duration,
+    last_pull: Option<u32>,
+    pb: ProgressBar,
+) -> Result<TrackedRollout> {
+    let res = poll_rollout(mf, kube, tick, last_pull, &pb).await;
+    if res.is_err() {
+        pb.finish_at_current_pos();
+    }
+    res
+}
+
+async fn poll_rollout(
+    mf: &Manifest,
+    kube: &dyn WorkloadApi,
+    tick: std::time::Duration,
+    last_pull: Option<u32>,
+    pb: &ProgressBar,
+) -> Result<TrackedRollout> {
     use futures_timer::Delay;
-    use indicatif::{ProgressBar, ProgressStyle};
     let minimum = mf.min_replicas();
-    let waittime = mf.estimate_wait_time();
-    let one_sec = std::time::Duration::from_millis(1000);
+    let mut waittime = mf.estimate_wait_time_with_pull(last_pull);
 
     match rollout_status(mf, kube, &None).await {
         Ok(rr) => {
             if rr.ok {
-                return Ok(true);
+                pb.finish_and_clear();
+                return Ok(TrackedRollout {
+                    ok: true,
+                    image_pull_seconds: None,
+                    failed: vec![],
+                });
             } else {
                 debug!("Ignoring rollout failure right after upgrade")
             }
+            // daemonsets replace one node at a time by default
+            if let PrimaryWorkload::DaemonSet = mf.workload {
+                waittime *= std::cmp::max(1, rr.expected);
+            }
         }
         Err(e) => warn!("Ignoring rollout failure right after upgrade: {}", e),
     };
 
-    Delay::new(one_sec).await;
-    // TODO: Don't count until image has been pulled + handle unscheduleble - #96
+    Delay::new(tick).await;
+    // TODO: handle unscheduleble - #96
 
     info!(
         "Waiting {}s for {:?} {} to rollout (not ready yet)",
@@ -461,10 +831,11 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
                 hash = Some(ur);
             }
         }
+        // daemonsets are tracked through their updated pods per node
+        PrimaryWorkload::DaemonSet => {}
     }
 
-    // TODO: create progress bar above this fn so we can use MultiProgressBar in cluster.rs
-    let pb = ProgressBar::new(minimum.into());
+    pb.set_length(minimum.into());
     pb.set_style(
         ProgressStyle::default_bar()
             .template("> {bar:40.green/black} {prefix} {pos}/{len} ({elapsed}) {msg}"),
@@ -475,7 +846,7 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
             PrimaryWorkload::Deployment => {
                 pb.set_prefix(&format!("{}-{}", mf.name, h));
             }
-            PrimaryWorkload::Statefulset => {
+            PrimaryWorkload::Statefulset | PrimaryWorkload::DaemonSet => {
                 pb.set_prefix(h); // statefulset hash already prefixes name
             }
         }
@@ -483,6 +854,7 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
         pb.set_prefix(&mf.name);
     }
 
+    let mut pulls = PullTracker::default();
     for i in 1..20 {
         trace!("poll iteration {}", i);
         let mut waited = 0;
@@ -490,19 +862,245 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
         while waited < waittime / 20 {
             waited += 1;
             trace!("sleep 1s (waited {})", waited);
-            Delay::new(one_sec).await;
+            Delay::new(tick).await;
         }
         let rr = rollout_status(mf, kube, &hash).await?;
         debug!("RR: {:?}", rr);
-        if let Some(msg) = rr.message {
+        // slow image pulls make rollouts look stuck, so say when they are happening
+        let mut message = rr.message;
+        if let (PrimaryWorkload::Deployment, Some(h)) = (&mf.workload, &hash) {
+            match pulls.poll(kube, h, &mf.name).await {
+                Ok(()) => message = pulls.message().or(message),
+                Err(e) => debug!("Failed to check image pulls of {}: {}", mf.name, e),
+            }
+        }
+        if let Some(msg) = message {
             pb.set_message(&msg);
         }
         pb.set_length(rr.expected.into()); // sometimes a replicaset resizes
         pb.set_position(rr.progress.into());
         if rr.ok {
             pb.finish_at_current_pos();
-            return Ok(true);
+            return Ok(TrackedRollout {
+                ok: true,
+                image_pull_seconds: pulls.longest(),
+                failed: vec![],
+            });
+        }
+    }
+    // timeout
+    pb.finish_at_current_pos();
+    Ok(TrackedRollout {
+        ok: false,
+        image_pull_seconds: pulls.longest(),
+        failed: vec![mf.name.clone()],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canary_manifest, rollout_status, track_rollout, track_rollouts, track_with_progress,
+        worker_manifests, PullTracker,
+    };
+    use crate::{
+        kubeapi::WorkloadApi,
+        replay::{Scenario, ScenarioKube, Step},
+    };
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+    use shipcat_definitions::{structs::Canary, Manifest, PrimaryWorkload};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const TICK: Duration = Duration::from_millis(1);
+    /// Rollouts of workers are tracked in well under this
+    const LIMIT: Duration = Duration::from_secs(30);
+
+    fn manifest(workload: PrimaryWorkload) -> Manifest {
+        let mut mf = Manifest::test("fake-ask");
+        mf.replicaCount = Some(2);
+        mf.workload = workload;
+        mf
+    }
+
+    fn kube(workload: PrimaryWorkload, steps: Vec<(i32, i32)>) -> ScenarioKube {
+        ScenarioKube::new("fake-ask", scenario(workload, steps))
+    }
+
+    fn scenario(workload: PrimaryWorkload, steps: Vec<(i32, i32)>) -> Scenario {
+        let steps = steps
+            .into_iter()
+            .map(|(replicas, ready)| Step {
+                replicas,
+                ready,
+                ..Step::default()
+            })
+            .collect();
+        Scenario {
+            workload,
+            hash: "5d8f7c9b4".into(),
+            version: "1.0.0".into(),
+            steps,
+            pull_seconds: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn track_deployment_rollout() {
+        let mf = manifest(PrimaryWorkload::Deployment);
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 0), (2, 1), (2, 2)]);
+        assert!(track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+
+        // never getting past one ready pod times out
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 0), (2, 1)]);
+        assert!(!track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn track_rollout_progress() {
+        let mf = manifest(PrimaryWorkload::Deployment);
+        // replicaset scaled up during the rollout (e.g. by an hpa)
+        let k = kube(PrimaryWorkload::Deployment, vec![(3, 2)]);
+        let hash = Some("5d8f7c9b4".to_string());
+        let rr = rollout_status(&mf, &k, &hash).await.unwrap();
+        assert_eq!((rr.progress, rr.expected, rr.ok), (2, 3, false));
+
+        // without a tracked replicaset we estimate from the deployment
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 1)]);
+        let rr = rollout_status(&mf, &k, &None).await.unwrap();
+        assert_eq!((rr.progress, rr.expected, rr.ok), (0, 2, false));
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 2)]);
+        assert!(rollout_status(&mf, &k, &None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn track_canary_rollout() {
+        let mut mf = manifest(PrimaryWorkload::Deployment);
+        mf.replicaCount = Some(4);
+        assert!(canary_manifest(&mf).is_err());
+
+        mf.canary = Some(Canary::new("fake-ask", "1.1.0".into(), 25, 4));
+        let cmf = canary_manifest(&mf).unwrap();
+        assert_eq!(cmf.name, "fake-ask-canary");
+        assert_eq!(cmf.min_replicas(), 1);
+
+        // one ready canary pod is enough, regardless of the main replicas
+        let scenario: Scenario = serde_yaml::from_str(
+            "workload: Deployment
+hash: 7b9c6d5f8
+version: 1.1.0
+steps:
+- {replicas: 1, ready: 0}
+- {replicas: 1, ready: 1}",
+        )
+        .unwrap();
+        let k = ScenarioKube::new("fake-ask-canary", scenario);
+        assert!(track_rollout(&cmf, &k, TICK, None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn track_worker_rollouts() {
+        let mut mf = manifest(PrimaryWorkload::Deployment);
+        mf.workers = vec![
+            serde_yaml::from_str("{name: consumer, replicaCount: 1}").unwrap(),
+            serde_yaml::from_str("{name: migrator, replicaCount: 1, trackRollout: false}").unwrap(),
+        ];
+        let wmfs = worker_manifests(&mf);
+        assert_eq!(wmfs.len(), 1);
+        assert_eq!((wmfs[0].name.as_str(), wmfs[0].min_replicas()), ("consumer", 1));
+
+        let worker = |steps: Vec<(i32, i32)>| -> Vec<(Manifest, Box<dyn WorkloadApi>)> {
+            let k = ScenarioKube::new("consumer", scenario(PrimaryWorkload::Deployment, steps));
+            vec![(wmfs[0].clone(), Box::new(k))]
+        };
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 0), (2, 2)]);
+        let tracked = timeout(
+            LIMIT,
+            track_rollouts(&mf, &k, &worker(vec![(1, 0), (1, 1)]), TICK, None),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(tracked.ok);
+
+        // a crash looping worker fails the rollout even though the main workload is fine
+        let k = kube(PrimaryWorkload::Deployment, vec![(2, 0), (2, 2)]);
+        let tracked = timeout(LIMIT, track_rollouts(&mf, &k, &worker(vec![(1, 0)]), TICK, None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!tracked.ok);
+        assert_eq!(tracked.failed, vec!["consumer".to_string()]);
+
+        // bars of a MultiProgress are joined once every rollout finished, even failing ones
+        for steps in &[vec![(1, 0), (1, 1)], vec![(1, 0)]] {
+            let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+            let k = kube(PrimaryWorkload::Deployment, vec![(2, 0), (2, 2)]);
+            let workers = worker(steps.clone());
+            let tracking = track_with_progress(Some(mp), &mf, &k, &workers, TICK, None);
+            assert!(timeout(LIMIT, tracking).await.unwrap().is_ok());
         }
     }
-    Ok(false) // timeout
+
+    #[tokio::test]
+    async fn track_statefulset_rollout() {
+        let mf = manifest(PrimaryWorkload::Statefulset);
+        let k = kube(PrimaryWorkload::Statefulset, vec![(1, 0), (2, 1), (2, 2)]);
+        assert!(track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+
+        let k = kube(PrimaryWorkload::Statefulset, vec![(1, 0), (2, 1)]);
+        assert!(!track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn track_daemonset_rollout() {
+        let mut mf = manifest(PrimaryWorkload::DaemonSet);
+        mf.replicaCount = None;
+        // three nodes, one of them never getting ready
+        let k = kube(PrimaryWorkload::DaemonSet, vec![(3, 1), (3, 2), (3, 3)]);
+        assert!(track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+
+        let k = kube(PrimaryWorkload::DaemonSet, vec![(3, 1), (3, 2)]);
+        assert!(!track_rollout(&mf, &k, TICK, None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn track_rollout_kube_failure() {
+        let mf = manifest(PrimaryWorkload::Deployment);
+        let scenario: Scenario = serde_yaml::from_str(
+            "workload: Deployment
+hash: 5d8f7c9b4
+steps:
+- {replicas: 2, ready: 0}
+- {replicas: 2, ready: 1, crashing: 1}
+- {replicas: 2, ready: 1, error: apiserver unavailable}",
+        )
+        .unwrap();
+        let k = ScenarioKube::new("fake-ask", scenario);
+        assert!(track_rollout(&mf, &k, TICK, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn track_rollout_image_pulls() {
+        let mf = manifest(PrimaryWorkload::Deployment);
+        let scenario: Scenario = serde_yaml::from_str(
+            "workload: Deployment
+hash: 5d8f7c9b4
+pullSeconds: 95
+steps:
+- {replicas: 2, ready: 0, pulling: 2}
+- {replicas: 2, ready: 0, pulling: 1}
+- {replicas: 2, ready: 2}",
+        )
+        .unwrap();
+        let k = ScenarioKube::new("fake-ask", scenario);
+        let mut pulls = PullTracker::default();
+        pulls.poll(&k, "5d8f7c9b4", "fake-ask").await.unwrap();
+        assert_eq!(pulls.message().unwrap(), "pulling image (node 1/2)");
+        assert_eq!(pulls.longest(), None);
+
+        let tracked = track_rollout(&mf, &k, TICK, Some(95)).await.unwrap();
+        assert!(tracked.ok);
+        assert_eq!(tracked.image_pull_seconds, Some(95));
+    }
 }
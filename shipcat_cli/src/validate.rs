@@ -1,6 +1,18 @@
-use super::{Config, Manifest, Region, Result};
-use crate::{error_chain::ChainedError, git};
+use super::{Config, ErrorKind, Manifest, Region, Result, ResultExt};
+use crate::{
+    depcheck,
+    edit::YamlEditor,
+    error_chain::ChainedError,
+    git, plugins,
+    registry::{self, Registries},
+    roster, top,
+};
 use futures::stream::{self, StreamExt};
+use shipcat_definitions::{math::ResourceTotals, secretstore::is_placeholder, structs::SunsetState};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
 
 async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Manifest> {
     let mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
@@ -15,21 +27,60 @@ async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Man
 ///
 /// This is meant to replace `shipcat validate ..all_services`
 /// This does not check secrets.
-pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
-    let available = shipcat_filebacked::available(conf, &reg).await?;
+/// With `only`, just those services are validated, and checked against each other.
+pub async fn regional_manifests(conf: &Config, reg: &Region, only: Option<&BTreeSet<String>>) -> Result<()> {
+    let all = shipcat_filebacked::all_metadata(conf, &reg)
+        .await?
+        .into_iter()
+        .filter(|s| only.map_or(true, |o| o.contains(&s.base.name)))
+        .collect::<Vec<_>>();
 
+    let mut errs = vec![];
+    // services disabled in this region are not available, but their disables can expire
+    for mf in all.iter().map(|s| &s.base) {
+        for d in mf.disabled_in.iter().filter(|d| d.region == reg.name) {
+            if let Err(e) = d.verify(&mf.name, &mf.regions) {
+                errs.push(e.into());
+            }
+        }
+    }
+
+    let available = all.into_iter().filter(|s| s.enabled && !s.external);
     let mut buffered = stream::iter(available)
         .map(move |mf| verify_manifest(mf.base.name, &conf, &reg))
         .buffer_unordered(16);
 
-    let mut errs = vec![];
     let mut used_stream_names = vec![];
     let mut used_topic_names = vec![];
     let mut used_user_names = vec![];
+    let mut versions = BTreeMap::new();
+    let mut ranged = vec![];
+    let mut totals = vec![];
+    let mut strict_mtls = BTreeSet::new();
+    let mut meshless = vec![];
     while let Some(r) = buffered.next().await {
         match r {
             Err(e) => errs.push(e),
             Ok(mf) => {
+                if reg.resourceBudgets.is_some() && !mf.external {
+                    match mf.compute_resource_totals() {
+                        Ok(t) => totals.push((team_of(&mf), t)),
+                        Err(e) => errs.push(e.into()),
+                    }
+                }
+                if let Some(v) = &mf.version {
+                    versions.insert(mf.name.clone(), v.clone());
+                }
+                if mf.dependencies.iter().any(|d| d.version.is_some()) {
+                    ranged.push(mf.clone());
+                }
+                if mf.requires_mtls() {
+                    strict_mtls.insert(mf.name.clone());
+                }
+                if !mf.uses_mesh_sidecar() {
+                    let deps = mf.dependencies.iter().map(|d| d.name.clone()).collect::<Vec<_>>();
+                    meshless.push((mf.name.clone(), deps));
+                }
                 // uniqueness validation
                 for es in mf.eventStreams {
                     if used_stream_names.contains(&es.name) {
@@ -62,6 +113,24 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         }
     }
 
+    // dependency version ranges against the versions declared in this region
+    for mf in &ranged {
+        for v in depcheck::violations(mf, &versions) {
+            warn!("{}", v);
+        }
+    }
+
+    // strict mtls services only accept clients in the mesh
+    for (svc, deps) in &meshless {
+        for d in deps.iter().filter(|d| strict_mtls.contains(*d)) {
+            let msg = format!(
+                "{} depends on {} which requires mtls, but does not run the mesh sidecar",
+                svc, d
+            );
+            errs.push(msg.into());
+        }
+    }
+
     if !errs.is_empty() {
         for e in &errs {
             error!("{}", e.display_chain());
@@ -69,14 +138,48 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         }
         bail!("Invalid shipcat data in {} files", errs.len());
     }
+    check_budgets(reg, &totals, None)?;
     // TODO: cross reference uniqueness values here
     Ok(())
 }
 
-async fn verify_region(r: String) -> Result<()> {
+fn team_of(mf: &Manifest) -> String {
+    mf.metadata.as_ref().map(|md| md.team.clone()).unwrap_or_default()
+}
+
+fn check_budgets(reg: &Region, totals: &[(String, ResourceTotals)], teams: Option<&[String]>) -> Result<()> {
+    if let Some(budgets) = &reg.resourceBudgets {
+        let exceeded = budgets.exceeded(totals, teams)?;
+        for e in &exceeded {
+            error!("{} in {}", e, reg.name);
+        }
+        if !exceeded.is_empty() {
+            bail!("{} resource budgets exceeded in {}", exceeded.len(), reg.name);
+        }
+    }
+    Ok(())
+}
+
+/// Check the resource budgets of a region against the requests of its services
+///
+/// With `teams`, only the budgets of those teams are checked along with the region total.
+pub async fn resource_budgets(conf: &Config, reg: &Region, teams: Option<&[String]>) -> Result<()> {
+    if reg.resourceBudgets.is_none() {
+        return Ok(());
+    }
+    let totals = top::calculate_manifest_requests(conf, reg)
+        .await?
+        .into_iter()
+        .filter(|(mf, _)| !mf.external)
+        .map(|(mf, t)| (team_of(&mf), t))
+        .collect::<Vec<_>>();
+    check_budgets(reg, &totals, teams)
+}
+
+async fn verify_region(r: String, only: Option<&BTreeSet<String>>) -> Result<()> {
     use crate::ConfigState;
     let (conf, region) = Config::new(ConfigState::Base, &r).await?;
-    regional_manifests(&conf, &region).await?;
+    regional_manifests(&conf, &region, only).await?;
     Ok(())
 }
 
@@ -84,9 +187,23 @@ async fn verify_region(r: String) -> Result<()> {
 ///
 /// This is meant to replace a for loop over shipcat list-regions
 /// This does not check secrets
-pub async fn all_manifests() -> Result<()> {
+pub async fn all_manifests(only: Option<&BTreeSet<String>>) -> Result<()> {
     let regions = Config::read().await?.list_regions();
-    let mut buffered = stream::iter(regions).map(verify_region).buffer_unordered(4);
+    regions_manifests(regions, only).await
+}
+
+/// Validate all manifests in a service directory for the regions of an environment group
+///
+/// This does not check secrets
+pub async fn environment_manifests(env: &str, only: Option<&BTreeSet<String>>) -> Result<()> {
+    let regions = Config::read().await?.environment_regions(env)?;
+    regions_manifests(regions, only).await
+}
+
+async fn regions_manifests(regions: Vec<String>, only: Option<&BTreeSet<String>>) -> Result<()> {
+    let mut buffered = stream::iter(regions)
+        .map(|r| verify_region(r, only))
+        .buffer_unordered(4);
 
     let mut errs = vec![];
     while let Some(r) = buffered.next().await {
@@ -112,6 +229,8 @@ pub async fn all_manifests() -> Result<()> {
 /// vault locations serverside (which require vault credentials).
 pub async fn manifest(services: Vec<String>, conf: &Config, reg: &Region, secrets: bool) -> Result<()> {
     conf.verify()?; // this should work even with a limited config!
+    let plugins = plugins::Plugins::load(conf)?;
+    let mut teams = vec![];
     for svc in services {
         debug!("validating {} for {}", svc, reg.name);
         let mf = if secrets {
@@ -126,11 +245,184 @@ pub async fn manifest(services: Vec<String>, conf: &Config, reg: &Region, secret
                 .await?
         };
         mf.verify(conf, reg)?;
+        plugins.validate(&mf)?;
+        let versions = depcheck::declared_versions(&mf, conf, reg).await?;
+        for v in depcheck::violations(&mf, &versions) {
+            warn!("{}", v);
+        }
+        teams.push(team_of(&mf));
         debug!("validated {} for {}", svc, reg.name);
     }
+    resource_budgets(conf, reg, Some(&teams)).await
+}
+
+/// Verify that the pinned images of services exist in their registries
+///
+/// Catches typos in tags and images that were never pushed before they reach an apply.
+/// Services without a pinned version are skipped, as their tag is only known at apply time.
+pub async fn images(services: Vec<String>, conf: &Config, reg: &Region) -> Result<()> {
+    let registries = Registries::new(conf)?;
+    let mut missing = vec![];
+    for svc in services {
+        let mf = shipcat_filebacked::load_manifest(&svc, conf, reg)
+            .await?
+            .stub(reg)
+            .await?;
+        if mf.version.is_none() {
+            warn!(
+                "{} has no version pinned in {}, not checking its image",
+                svc, reg.name
+            );
+        }
+        for (image, tag) in registry::manifest_images(&mf) {
+            if registries.exists(&image, &tag).await? {
+                debug!("{}: {}:{} exists", svc, image, tag);
+            } else {
+                error!("{}: {}:{} does not exist", svc, image, tag);
+                missing.push(format!("{}:{}", image, tag));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        bail!("Missing images: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Check services against their planned sunset
+///
+/// Warns when a sunset is less than 30 days away, and fails once it has passed
+/// unless `allow_sunset` is set, so retired services do not linger unnoticed.
+pub async fn sunset(services: Vec<String>, conf: &Config, reg: &Region, allow_sunset: bool) -> Result<()> {
+    for svc in services {
+        let mf = shipcat_filebacked::load_metadata(&svc, conf, reg).await?;
+        let sunset = match &mf.base.metadata.sunset {
+            Some(s) => s,
+            None => continue,
+        };
+        if let Some(r) = &sunset.replacement {
+            let exists = shipcat_filebacked::all_unchecked(conf)
+                .await?
+                .iter()
+                .any(|b| &b.name == r && b.name != svc);
+            if !exists {
+                bail!("{} names an unknown service {} as its replacement", svc, r);
+            }
+        }
+        match sunset.state() {
+            SunsetState::Scheduled => {}
+            SunsetState::Approaching => warn!(
+                "{} reaches its sunset in {} days on {}",
+                svc,
+                sunset.days_left(),
+                sunset.date
+            ),
+            SunsetState::Past if allow_sunset => warn!("{} is past its sunset on {}", svc, sunset.date),
+            SunsetState::Past => bail!(
+                "{} is past its sunset on {} - retire it or pass --allow-sunset",
+                svc,
+                sunset.date
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Verify the ownership of services against the configured roster
+///
+/// Catches maintainers and contacts that have left, and squads that no longer exist.
+/// With `offline`, a cached roster is used if available, otherwise the check is skipped.
+pub async fn roster(services: Vec<String>, conf: &Config, reg: &Region, offline: bool) -> Result<()> {
+    let rcfg = match &conf.roster {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    let roster = match roster::load(rcfg, offline).await? {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    for svc in services {
+        let mf = shipcat_filebacked::load_manifest(&svc, conf, reg)
+            .await?
+            .stub(reg)
+            .await?;
+        roster.verify(&mf, &conf.owners)?;
+    }
+    Ok(())
+}
+
+/// Lint the PromQL of all prometheusAlerts of a service
+///
+/// Parses every alert expression and, when `live` is set, asks the region's Prometheus
+/// whether every referenced metric has any series, to catch typos that would otherwise
+/// ship as alerts that can never fire.
+pub async fn promql(services: Vec<String>, conf: &Config, reg: &Region, live: bool) -> Result<()> {
+    let prom_url = if live {
+        match &reg.prometheus {
+            Some(p) => Some(p.url.trim_end_matches('/').to_string()),
+            None => bail!("Region {} has no prometheus configured for --live", reg.name),
+        }
+    } else {
+        None
+    };
+    let mut errs = vec![];
+    for svc in services {
+        debug!("linting promql of {} for {}", svc, reg.name);
+        let mf = shipcat_filebacked::load_manifest(&svc, conf, reg)
+            .await?
+            .stub(reg)
+            .await?;
+        // series generated by our own recording rules only exist after the first deploy
+        let recorded = mf
+            .sloRecordingRules
+            .iter()
+            .map(|r| r.record.clone())
+            .collect::<Vec<_>>();
+        for alert in &mf.prometheusAlerts {
+            let metrics = match alert.metric_names() {
+                Ok(m) => m,
+                Err(e) => {
+                    errs.push(format!("{}: {}", svc, e));
+                    continue;
+                }
+            };
+            if let Some(url) = &prom_url {
+                for m in metrics.iter().filter(|m| !recorded.contains(m)) {
+                    if !prometheus_has_series(url, m).await? {
+                        errs.push(format!(
+                            "{}: alert {} references metric {} which has no series in {}",
+                            svc, alert.name, m, reg.name
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    if !errs.is_empty() {
+        for e in &errs {
+            error!("{}", e);
+        }
+        bail!("Invalid prometheusAlerts in {} places", errs.len());
+    }
     Ok(())
 }
 
+/// Whether a Prometheus instance has any series for a metric name
+async fn prometheus_has_series(url: &str, metric: &str) -> Result<bool> {
+    let endpoint = reqwest::Url::parse(&format!("{}/api/v1/series", url))?;
+    let res: serde_json::Value = reqwest::Client::new()
+        .get(endpoint.clone())
+        .query(&[("match[]", metric)])
+        .send()
+        .await
+        .chain_err(|| ErrorKind::Url(endpoint.clone()))?
+        .error_for_status()
+        .chain_err(|| ErrorKind::Url(endpoint.clone()))?
+        .json()
+        .await?;
+    Ok(res["data"].as_array().map(|d| !d.is_empty()).unwrap_or(false))
+}
+
 /// Validate the secrets exists in all regions
 ///
 /// This is one of very few functions not validating a single kube context,
@@ -143,7 +435,7 @@ pub async fn secret_presence_full(conf: &Config, regions: Vec<String>) -> Result
         for svc in shipcat_filebacked::available(conf, &reg).await? {
             let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, &reg).await?;
             debug!("validating secrets for {} in {}", &svc.base.name, r);
-            mf.verify_secrets_exist(&reg.vault).await?;
+            mf.verify_secrets_exist(&reg).await?;
         }
     }
     Ok(())
@@ -166,7 +458,7 @@ pub async fn secret_presence_explicit(svcs: Vec<String>, conf: &Config, regions:
                     continue;
                 }
                 debug!("validating secrets for {} in {}", &svc, r);
-                mf.verify_secrets_exist(&reg.vault).await?;
+                mf.verify_secrets_exist(&reg).await?;
             }
         }
     }
@@ -201,13 +493,206 @@ pub async fn secret_presence_git(conf: &Config, regions: Vec<String>) -> Result<
                     continue;
                 }
                 debug!("validating secrets for {} in {}", &svc, r);
-                mf.verify_secrets_exist(&reg.vault).await?;
+                mf.verify_secrets_exist(&reg).await?;
             }
         }
     }
     Ok(())
 }
 
+/// A mechanical correction made by `shipcat validate --fix`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    /// Zero indexed line of the edited file
+    pub line: usize,
+    pub description: String,
+}
+
+/// Container lists in manifests whose entries are named
+const CONTAINER_LISTS: [&str; 4] = ["workers", "sidecars", "initContainers", "cronJobs"];
+
+/// Uppercase the keys of every `env` block
+///
+/// Secrets are left alone, as their key is also their vault path.
+fn fix_env_keys(ed: &mut YamlEditor, fixes: &mut Vec<Fix>) {
+    for i in 0..ed.len() {
+        match ed.key_line(i) {
+            Some(kl) if kl.key == "env" && kl.value.is_empty() => {}
+            _ => continue,
+        }
+        let block = ed.block(i);
+        let child = match ed.child_indent(&block) {
+            Some(c) => c,
+            None => continue,
+        };
+        let entries = block
+            .filter_map(|j| ed.key_line(j).map(|kl| (j, kl)))
+            .filter(|(_, kl)| kl.indent == child && !kl.item)
+            .collect::<Vec<_>>();
+        for (j, kl) in &entries {
+            let upper = kl.key.to_uppercase();
+            if upper == kl.key || is_placeholder(&kl.value) || entries.iter().any(|(_, o)| o.key == upper) {
+                continue;
+            }
+            ed.rename_key(*j, &upper);
+            fixes.push(Fix {
+                line: *j,
+                description: format!("renamed env {} to {}", kl.key, upper),
+            });
+        }
+    }
+}
+
+/// Trim leading and trailing dashes from container names
+fn fix_container_names(ed: &mut YamlEditor, fixes: &mut Vec<Fix>) {
+    for list in &CONTAINER_LISTS {
+        let i = match ed.find_top_level(list) {
+            Some(i) => i,
+            None => continue,
+        };
+        let block = ed.block(i);
+        let child = match ed.child_indent(&block) {
+            Some(c) => c,
+            None => continue,
+        };
+        for j in block {
+            let kl = match ed.key_line(j) {
+                Some(kl) if kl.item && kl.key == "name" && kl.indent == child + 2 => kl,
+                _ => continue,
+            };
+            let name = kl.value.trim_matches(|c| c == '"' || c == '\'');
+            let trimmed = name.trim_matches('-');
+            if trimmed != name && !trimmed.is_empty() {
+                ed.set_value(j, trimmed);
+                fixes.push(Fix {
+                    line: j,
+                    description: format!("renamed {} entry {} to {}", list, name, trimmed),
+                });
+            }
+        }
+    }
+}
+
+/// Sort the `regions` list, unless it has comments that would move around
+fn fix_regions_order(ed: &mut YamlEditor, fixes: &mut Vec<Fix>) {
+    let i = match ed.find_top_level("regions") {
+        Some(i) => i,
+        None => return,
+    };
+    let block = ed.block(i);
+    let mut items = vec![];
+    for j in block.clone() {
+        let line = ed.line(j);
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with("- ") || trimmed.contains('#') {
+            return;
+        }
+        let indent = line[..line.len() - trimmed.len()].to_string();
+        items.push((indent, trimmed[2..].trim().to_string()));
+    }
+    let mut sorted = items.iter().map(|(_, r)| r.clone()).collect::<Vec<_>>();
+    sorted.sort();
+    if items.iter().map(|(_, r)| r).eq(sorted.iter()) {
+        return;
+    }
+    for ((j, (indent, _)), r) in block.zip(items.iter()).zip(sorted) {
+        ed.set_line(j, format!("{}- {}", indent, r));
+    }
+    fixes.push(Fix {
+        line: i,
+        description: "sorted regions".into(),
+    });
+}
+
+/// Set the chart when no manifest or config default sets it
+fn fix_missing_chart(ed: &mut YamlEditor, chart: &str, fixes: &mut Vec<Fix>) {
+    if ed.find_top_level("chart").is_some() {
+        return;
+    }
+    let at = ed.find_top_level("name").map_or(ed.len(), |i| ed.block(i).end);
+    ed.insert_line(at, format!("chart: {}", chart));
+    fixes.push(Fix {
+        line: at,
+        description: format!("set chart to {}", chart),
+    });
+}
+
+/// Apply the mechanical fixes to the source of a manifest file
+///
+/// Fixes are line edits, so comments and formatting are kept.
+/// A `chart` is only added when passed, which should be when nothing sets one.
+pub fn fix_source(source: &str, chart: Option<&str>) -> (String, Vec<Fix>) {
+    let mut ed = YamlEditor::new(source);
+    let mut fixes = vec![];
+    fix_env_keys(&mut ed, &mut fixes);
+    fix_container_names(&mut ed, &mut fixes);
+    fix_regions_order(&mut ed, &mut fixes);
+    if let Some(c) = chart {
+        fix_missing_chart(&mut ed, c, &mut fixes);
+    }
+    if fixes.is_empty() {
+        return (source.to_string(), fixes);
+    }
+    fixes.sort_by_key(|f| f.line);
+    (ed.contents(), fixes)
+}
+
+/// The chart to fall back to when a manifest lacks one
+///
+/// Only when the manifests repository has a single chart is this unambiguous.
+async fn sole_chart() -> Result<Option<String>> {
+    let mut charts = vec![];
+    let mut entries = tokio::fs::read_dir(Path::new(".").join("charts")).await?;
+    while let Some(e) = entries.next_entry().await? {
+        if e.file_type().await?.is_dir() {
+            charts.push(e.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(if charts.len() == 1 { charts.pop() } else { None })
+}
+
+/// Fix mechanical validation failures in the files of services
+///
+/// Corrects env key casing, stray dashes in container names, region ordering,
+/// and a missing chart, then prints what was changed.
+/// Anything needing a judgement call is left for `validate` to report.
+pub async fn fix(services: &[String], conf: &Config, reg: &Region) -> Result<usize> {
+    let mut count = 0;
+    for svc in services {
+        let dir = Path::new(".").join("services").join(svc);
+        if !dir.is_dir() {
+            bail!("Service folder {} does not exist", dir.display());
+        }
+        let chart = match shipcat_filebacked::load_manifest(svc, conf, reg).await {
+            Ok(mf) if mf.chart.is_none() => sole_chart().await?,
+            _ => None,
+        };
+        let mut files = vec![];
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(e) = entries.next_entry().await? {
+            let pth = e.path();
+            if pth.extension().iter().any(|x| *x == "yml") {
+                files.push(pth);
+            }
+        }
+        files.sort();
+        for pth in files {
+            let source = tokio::fs::read_to_string(&pth).await?;
+            let is_manifest = pth.file_name().iter().any(|f| *f == "manifest.yml");
+            let (fixed, fixes) = fix_source(&source, chart.as_deref().filter(|_| is_manifest));
+            if fixes.is_empty() {
+                continue;
+            }
+            tokio::fs::write(&pth, fixed).await?;
+            for f in &fixes {
+                println!("{}:{}: {}", pth.display(), f.line + 1, f.description);
+            }
+            count += fixes.len();
+        }
+    }
+    Ok(count)
+}
+
 /// A config verifier
 ///
 /// This works with Base configs and File configs
@@ -217,6 +702,22 @@ pub fn config(conf: Config) -> Result<()> {
     Ok(())
 }
 
+/// Services changed since the merge-base with master
+///
+/// Returns None when every service needs validating, because shared files like
+/// `shipcat.conf`, charts or templates changed, or because git failed.
+pub fn git_changes() -> Option<BTreeSet<String>> {
+    let diff = git::merge_base().and_then(|base| git::diff_filenames(&base));
+    match diff {
+        Ok(d) => git::changed_services(&d),
+        Err(e) => {
+            warn!("Error from git: {}", e);
+            warn!("Falling back to a full validate");
+            None
+        }
+    }
+}
+
 // Dumb git diff helper that matches normal service files:
 //
 // Effectively checks:
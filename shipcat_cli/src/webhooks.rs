@@ -1,21 +1,13 @@
 use super::{Config, Region, Webhook};
-use crate::{apply::UpgradeInfo, audit, slack, Result};
-
-/// The different states an upgrade can be in
-#[derive(Serialize, PartialEq, Clone)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum UpgradeState {
-    /// Before action
-    Pending,
-    /// Action was cancelled before start
-    Cancelled,
-    // Action has started
-    Started,
-    /// No errors
-    Completed,
-    /// Errors
-    Failed,
-}
+use crate::{
+    apply::UpgradeInfo,
+    audit::{self, AuditLog},
+    rotate::RotationInfo,
+    slack, smoketest, Result,
+};
+pub use shipcat_definitions::status::UpgradeState;
+use shipcat_definitions::{template, DEFAULT_UPGRADE_TEMPLATE};
+use tera::Context;
 
 pub fn ensure_requirements(reg: &Region) -> Result<()> {
     for wh in &reg.webhooks {
@@ -31,18 +23,27 @@ pub async fn reconcile_event(us: UpgradeState, reg: &Region) {
     for wh in &reg.webhooks {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
-                Webhook::Audit(h) => audit::reconciliation(&us, &reg.name, &h, whc).await,
+                Webhook::Audit(h) => audit::reconciliation(&us, &reg.name, h, whc).await,
             };
             if let Err(e) = res {
                 warn!("Failed to notify about reconciliation event: {}", e)
             }
         }
     }
+    if let Some(log) = AuditLog::regional(reg) {
+        if let Err(e) = audit::reconciliation(&us, &reg.name, &log, AuditLog::context()).await {
+            warn!("Failed to log reconciliation event: {}", e)
+        }
+    }
 }
 
 /// Throw events to configured webhooks
 pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, conf: &Config) {
     debug!("Apply event: {:?}", info);
+    if info.dry_run {
+        debug!("Not sending events for a dry run of {}", info.name);
+        return;
+    }
     // Webhooks defined in shipcat.conf for the region:
     for wh in &reg.webhooks {
         if let Ok(whc) = wh.get_configuration() {
@@ -50,7 +51,7 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
                 Webhook::Audit(h) => {
                     match us {
                         UpgradeState::Started | UpgradeState::Completed | UpgradeState::Failed => {
-                            audit::apply(&us, &info, &h, whc).await
+                            audit::apply(&us, info, h, whc).await
                         }
                         _ => Ok(()), // audit only sends Started / Failed / Completed
                     }
@@ -61,23 +62,28 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             }
         }
     }
+    if let Some(log) = AuditLog::regional(reg) {
+        if let UpgradeState::Started | UpgradeState::Completed | UpgradeState::Failed = us {
+            if let Err(e) = audit::apply(&us, info, &log, AuditLog::context()).await {
+                warn!("Failed to log apply event: {}", e)
+            }
+        }
+    }
     // slack notifications:
-    let (color, text) = match us {
-        UpgradeState::Completed => ("good", format!("applied `{}` in `{}`", info.name, info.region)),
-        UpgradeState::Failed => (
-            "danger",
-            format!("failed to apply `{}` in `{}`", info.name, info.region),
-        ),
-        _ => (
-            "good",
-            format!(
-                "action state: {}",
-                serde_json::to_string(&us).unwrap_or("unknown".into())
-            ),
-        ),
-    };
     match us {
         UpgradeState::Completed | UpgradeState::Failed => {
+            let color = if us == UpgradeState::Completed {
+                "good"
+            } else {
+                "danger"
+            };
+            let mut text = upgrade_text(&us, info, conf);
+            for c in &info.containers {
+                text = format!("{}\n{}", text, c.describe());
+            }
+            if let Some(run) = &info.smokeTests {
+                text = format!("{}\n{}", text, smoketest::summary(run));
+            }
             let _ = slack::send(
                 slack::Message {
                     text,
@@ -86,6 +92,7 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
                     version: Some(info.version.clone()),
                     mode: info.slackMode.clone(),
                     metadata: info.metadata.clone(),
+                    containers: info.containers.clone(),
                 },
                 &conf.owners,
             )
@@ -95,6 +102,34 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
     }
 }
 
+/// Render the text of an upgrade notification from `slack.upgradeTemplate`
+///
+/// Falls back to the default template if the configured one fails to render.
+fn upgrade_text(us: &UpgradeState, info: &UpgradeInfo, conf: &Config) -> String {
+    let mut ctx = Context::new();
+    ctx.insert("service", &info.name);
+    ctx.insert("state", us);
+    ctx.insert("region", &info.region);
+    ctx.insert("cluster", &info.cluster);
+    ctx.insert("version", &info.version);
+    ctx.insert("metadata", &info.metadata);
+    ctx.insert(
+        "link",
+        &slack::version_link(
+            &info.metadata,
+            info.diff.as_deref(),
+            &info.version,
+            &info.containers,
+        ),
+    );
+    ctx.insert("duration", &info.duration.map(|d| d.as_secs()));
+    ctx.insert("containers", &info.containers);
+    template::one_off(conf.slack.upgrade_template(), &ctx).unwrap_or_else(|e| {
+        warn!("Failed to render slack.upgradeTemplate: {}", e);
+        template::one_off(DEFAULT_UPGRADE_TEMPLATE, &ctx).expect("default upgrade template renders")
+    })
+}
+
 /// Throw events to configured webhooks
 ///
 /// This is the new version for shipcat apply module
@@ -104,13 +139,18 @@ pub async fn delete_event(us: &UpgradeState, info: &UpgradeInfo, reg: &Region, c
     for wh in &reg.webhooks {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
-                Webhook::Audit(h) => audit::deletion(&us, &info, &h, whc).await,
+                Webhook::Audit(h) => audit::deletion(us, info, h, whc).await,
             };
             if let Err(e) = res {
                 warn!("Failed to notify about delete event: {}", e)
             }
         }
     }
+    if let Some(log) = AuditLog::regional(reg) {
+        if let Err(e) = audit::deletion(us, info, &log, AuditLog::context()).await {
+            warn!("Failed to log delete event: {}", e)
+        }
+    }
     // slack notifies when we start the deletion only
     #[allow(clippy::single_match)] // no PartialEq for UpgradeState
     match us {
@@ -125,6 +165,7 @@ pub async fn delete_event(us: &UpgradeState, info: &UpgradeInfo, reg: &Region, c
                     version: Some(info.version.clone()),
                     mode: info.slackMode.clone(),
                     metadata: info.metadata.clone(),
+                    containers: vec![],
                 },
                 &conf.owners,
             )
@@ -133,3 +174,58 @@ pub async fn delete_event(us: &UpgradeState, info: &UpgradeInfo, reg: &Region, c
         _ => {}
     };
 }
+
+/// Throw secret rotation events to configured webhooks
+///
+/// Only audited; the restart that follows a rotation is not announced on slack.
+pub async fn rotation_event(us: &UpgradeState, info: &RotationInfo, reg: &Region) {
+    debug!("Rotation event: {:?}", info);
+    for wh in &reg.webhooks {
+        if let Ok(whc) = wh.get_configuration() {
+            let res = match wh {
+                Webhook::Audit(h) => audit::rotation(us, info, h, whc).await,
+            };
+            if let Err(e) = res {
+                warn!("Failed to notify about rotation event: {}", e)
+            }
+        }
+    }
+    if let Some(log) = AuditLog::regional(reg) {
+        if let Err(e) = audit::rotation(us, info, &log, AuditLog::context()).await {
+            warn!("Failed to log rotation event: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upgrade_text, UpgradeState};
+    use crate::{apply::UpgradeInfo, Manifest};
+    use shipcat_filebacked::fixtures::ConfigBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn upgrade_text_templating() {
+        let mut info = UpgradeInfo::new(&Manifest::test("fake-svc"));
+        let mut conf = ConfigBuilder::new().build();
+        // default template matches the original notification text
+        let text = upgrade_text(&UpgradeState::Completed, &info, &conf);
+        assert_eq!(text, "applied `fake-svc` in `dev-uk`");
+        let text = upgrade_text(&UpgradeState::Failed, &info, &conf);
+        assert_eq!(text, "failed to apply `fake-svc` in `dev-uk`");
+
+        conf.slack.upgradeTemplate = Some(
+            "{{ service }}={{ version }} in {{ cluster }}{% if duration %} ({{ duration }}s){% endif %}"
+                .into(),
+        );
+        info.cluster = Some("kind-shipcat".into());
+        info.duration = Some(Duration::from_secs(42));
+        let text = upgrade_text(&UpgradeState::Completed, &info, &conf);
+        assert_eq!(text, "fake-svc=1.0.0 in kind-shipcat (42s)");
+
+        // broken templates fall back to the default
+        conf.slack.upgradeTemplate = Some("{{ missing }}".into());
+        let text = upgrade_text(&UpgradeState::Completed, &info, &conf);
+        assert_eq!(text, "applied `fake-svc` in `dev-uk`");
+    }
+}
@@ -0,0 +1,121 @@
+use futures_timer::Delay;
+use kube::api::{Api, DeleteParams, ListParams};
+use shipcat_definitions::{request::ApplyRequest, Config, ConfigState, Region, ShipcatApplyRequest};
+use std::time::Duration;
+
+use super::{kubeapi, ErrorKind, Result};
+use crate::{apply, cluster, freeze, git, permissions};
+
+/// Seconds between checks for new requests
+const POLL_SECONDS: u64 = 10;
+
+/// Requests waiting in a region, in the order they should be executed
+async fn pending(api: &Api<ShipcatApplyRequest>) -> Result<Vec<ApplyRequest>> {
+    let requests = api
+        .list(&ListParams::default())
+        .await
+        .map_err(ErrorKind::KubeError)?;
+    let specs = requests.items.into_iter().map(|r| r.spec).collect();
+    Ok(ApplyRequest::queue(specs))
+}
+
+/// Settings of a worker
+pub struct WorkerConfig {
+    /// Region the worker runs in
+    pub region: String,
+    pub n_workers: usize,
+}
+
+/// Configs of the manifests checkout, with and without secrets
+struct Checkout {
+    conf_sec: Config,
+    conf_base: Config,
+    region_sec: Region,
+    region_base: Region,
+}
+
+impl Checkout {
+    /// Pull the latest manifests and load their configs
+    async fn refresh(region: &str) -> Result<Checkout> {
+        git::pull()?;
+        let (conf_sec, region_sec) = Config::new(ConfigState::Filtered, region).await?;
+        let (conf_base, region_base) = Config::new(ConfigState::Base, region).await?;
+        Ok(Checkout {
+            conf_sec,
+            conf_base,
+            region_sec,
+            region_base,
+        })
+    }
+}
+
+/// Execute a single request
+///
+/// The requester must be allowed to change the service, or the whole region for reconciles.
+/// Failed applies are also reported through webhooks and the conditions of the manifest.
+async fn execute(req: ApplyRequest, co: &Checkout, n_workers: usize) -> Result<()> {
+    permissions::enforce_as(
+        &req.requester,
+        req.service.as_deref(),
+        &co.conf_sec,
+        &co.region_sec,
+    )
+    .await?;
+    freeze::enforce(&co.conf_sec, &co.region_sec).await?;
+    match req.service {
+        Some(svc) => {
+            info!("Applying {} for {}", svc, req.requester);
+            apply::apply(svc, false, &co.region_sec, &co.conf_sec, true, req.version, false).await?;
+        }
+        None => {
+            info!("Reconciling {} for {}", co.region_base.name, req.requester);
+            cluster::mass_crd(
+                &co.conf_sec,
+                &co.conf_base,
+                &co.region_base,
+                n_workers,
+                None,
+                false,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Execute the apply and reconcile requests made through raftcat
+///
+/// Requests run one at a time, oldest first, and are deleted once done whether they succeeded or not.
+/// The manifests checkout is pulled before each request, and requests stay queued when that fails.
+pub async fn run(wc: WorkerConfig) -> Result<()> {
+    let (_, region) = Config::new(ConfigState::Base, &wc.region).await?;
+    let client = kubeapi::make_client().await?;
+    let api: Api<ShipcatApplyRequest> = Api::namespaced(client, &region.namespace);
+    info!("Waiting for apply requests in {}", region.name);
+    loop {
+        let requests = match pending(&api).await {
+            Ok(rs) => rs,
+            Err(e) => {
+                warn!("Failed to list apply requests: {}", e);
+                vec![]
+            }
+        };
+        for req in requests {
+            let name = req.object_name();
+            let checkout = match Checkout::refresh(&wc.region).await {
+                Ok(co) => co,
+                Err(e) => {
+                    warn!("Failed to refresh the manifests checkout: {}", e);
+                    break;
+                }
+            };
+            if let Err(e) = execute(req, &checkout, wc.n_workers).await {
+                warn!("Request {} failed: {}", name, e);
+            }
+            if let Err(e) = api.delete(&name, &DeleteParams::default()).await {
+                warn!("Failed to delete request {}: {}", name, e);
+            }
+        }
+        Delay::new(Duration::from_secs(POLL_SECONDS)).await;
+    }
+}
@@ -1,5 +1,10 @@
 use semver::Version;
-use std::{collections::BTreeSet, env, fs, path::Path, sync::Once};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::Path,
+    sync::Once,
+};
 
 static START: Once = Once::new();
 
@@ -35,7 +40,7 @@ pub fn setup() {
     });
 }
 
-use shipcat_definitions::{Config, ConfigState, Environment}; // Product
+use shipcat_definitions::{structs::Dependency, Config, ConfigState, Environment}; // Product
 
 #[tokio::test]
 async fn config_test() {
@@ -58,6 +63,20 @@ async fn config_cr_settings_test() {
     assert!(conf.print().is_ok());
 }
 
+#[tokio::test]
+async fn config_environment_groups() {
+    setup();
+    let conf = Config::read().await.unwrap();
+    // explicit group, without the ops region
+    assert_eq!(conf.environment_regions("dev").unwrap(), vec![
+        "dev-uk",
+        "dev-global"
+    ]);
+    // falls back to regions in the environment
+    assert_eq!(conf.environment_regions("preprod").unwrap(), vec!["preprod-uk"]);
+    assert!(conf.environment_regions("prod").is_err());
+}
+
 #[tokio::test]
 async fn config_defaults_test() {
     setup();
@@ -134,6 +153,182 @@ async fn get_codeowners() {
     assert_eq!(cos[1], "/services/fake-ask/ @babylonhealth/o11y @clux");
 }
 
+#[tokio::test]
+async fn get_alert_routes() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let routes = get::alert_routes(&conf, &reg).await.unwrap();
+
+    assert_eq!(routes.routes.len(), 1); // only fake-ask has alerts
+    let r = &routes.routes[0];
+    assert_eq!(r.matchers["team"], "observability");
+    assert_eq!(r.receiver, "observability-slack");
+    assert_eq!(r.routes[0].matchers["page"], "true");
+    assert_eq!(r.routes[0].receiver, "observability-pagerduty");
+    assert_eq!(routes.receivers[0].slack_configs[0]["channel"], "CA04UJ8S0");
+
+    let mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
+        .await
+        .unwrap();
+    let labels = &mf.prometheusAlerts[0].labels;
+    assert_eq!(labels["team"], "observability");
+    assert_eq!(labels["tribe"], "platform-engineering");
+    assert_eq!(labels["page"], "false"); // warnings do not page in dev-uk
+}
+
+#[tokio::test]
+async fn get_latency_budgets() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let budgets = get::latency_budgets(&conf, &reg).await.unwrap();
+
+    assert_eq!(budgets.budgetMs, Some(150));
+    assert_eq!(budgets.chains.len(), 1); // only fake-ask has a budgeted dependency
+    let chain = &budgets.chains[0];
+    assert_eq!(chain.services, vec!["fake-ask", "fake-storage"]);
+    assert_eq!(chain.budgetMs, 200);
+    assert!(chain.critical);
+    assert!(chain.exceeded);
+}
+
+#[tokio::test]
+async fn get_egress() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let report = get::egress(&conf, &reg).await.unwrap();
+
+    assert_eq!(report.strict, vec!["fake-storage"]);
+    assert_eq!(report.open, vec!["fake-ask"]);
+}
+
+#[test]
+fn get_latency_chains() {
+    let dep = |name: &str, ms: Option<u32>, critical: bool| Dependency {
+        name: name.into(),
+        latencyBudgetMs: ms,
+        critical,
+        ..Dependency::default()
+    };
+    let mut deps = BTreeMap::new();
+    deps.insert("a".to_string(), vec![
+        dep("b", Some(50), true),
+        dep("d", Some(20), false),
+    ]);
+    deps.insert("b".to_string(), vec![
+        dep("c", Some(30), true),
+        dep("d", None, true),
+    ]);
+    deps.insert("c".to_string(), vec![dep("b", Some(10), true)]); // cycle
+    deps.insert("d".to_string(), vec![]);
+
+    let chains = get::latency_chains(&deps);
+    let paths: Vec<_> = chains.iter().map(|c| c.services.join(",")).collect();
+    assert_eq!(paths, vec!["a,b,c", "a,d", "c,b"]);
+    assert_eq!(chains[0].budgetMs, 80);
+    assert!(chains[0].critical);
+    assert!(!chains[1].critical);
+    assert_eq!(chains[2].budgetMs, 10);
+}
+
+#[test]
+fn get_lagging_charts() {
+    let cv = |chart: &str, version: Option<&str>| get::ChartVersion {
+        chart: chart.into(),
+        version: version.map(String::from),
+    };
+    let mut defaults = BTreeMap::new();
+    defaults.insert(
+        "dev-uk".to_string(),
+        cv("git@github.com:org/base-chart.git", Some("v1.2.0")),
+    );
+    defaults.insert(
+        "dev-us".to_string(),
+        cv("git@github.com:org/base-chart.git", Some("1.10.0")),
+    );
+    defaults.insert(
+        "dev-ca".to_string(),
+        cv("git@github.com:org/base-chart.git", Some("master")),
+    );
+    defaults.insert("dev-ops".to_string(), cv("base", Some("0.1.0")));
+    defaults.insert("dev-global".to_string(), cv("base", None));
+
+    let (latest, lagging) = get::lagging_charts(&defaults);
+    assert_eq!(latest["git@github.com:org/base-chart.git"], "1.10.0");
+    assert_eq!(latest["base"], "0.1.0");
+    assert_eq!(lagging, btree_set!["dev-uk".to_string()]);
+}
+
+#[tokio::test]
+async fn get_teams_ownership() {
+    setup();
+    let conf = Config::read().await.unwrap();
+    let mut mfs = shipcat_filebacked::all_unchecked(&conf).await.unwrap();
+    let mut ghost = mfs[0].clone();
+    ghost.name = "ghost".into();
+    ghost.metadata.team = "ghosts".into();
+    ghost.metadata.squad = None;
+    mfs.push(ghost);
+
+    let regions = vec!["dev-uk".to_string(), "dev-ops".to_string()];
+    let report = get::ownership(&conf, &mfs, &regions);
+    let squad = &report.squads["observability"];
+    assert_eq!(squad.tribe.as_deref(), Some("platform-engineering"));
+    assert_eq!(squad.services["dev-uk"], 3);
+    assert_eq!(squad.services["dev-ops"], 1);
+    assert_eq!(squad.missingRunbook.len(), 4); // no test service has a runbook
+    assert!(squad.missingSupport.is_empty()); // the squad's channel is used
+    assert_eq!(report.tribes["platform-engineering"].services["dev-uk"], 3);
+    assert_eq!(report.orphaned["ghost"], "ghosts");
+}
+
+#[tokio::test]
+async fn stats_fields() {
+    setup();
+    let conf = Config::read().await.unwrap();
+    let stats = shipcat::stats::fields(&conf, shipcat::stats::StatsFormat::Csv)
+        .await
+        .unwrap();
+
+    assert_eq!(stats.services, 3); // fake-ask, fake-storage and out-of-region
+    assert_eq!(stats.fields["sidecars"], 2);
+    assert_eq!(stats.fields["workers"], 1); // only fake-ask
+    assert_eq!(stats.fields["dataHandling"], 1); // only fake-storage
+    assert!(!stats.fields.contains_key("name"));
+    assert_eq!(stats.teams["observability"]["workers"], 1);
+    assert_eq!(stats.environments["dev"]["sidecars"], 2);
+}
+
+#[tokio::test]
+async fn config_clone_region() {
+    setup();
+    let conf = Config::read().await.unwrap();
+    let clone = shipcat::clone::region(&conf, "dev-uk", "staging-uk", true)
+        .await
+        .unwrap();
+    let reg = &clone.region;
+    assert_eq!(reg["name"].as_str(), Some("staging-uk"));
+    assert_eq!(reg["vault"]["folder"].as_str(), Some("staging-uk"));
+    assert_eq!(
+        reg["kong"]["config_url"].as_str(),
+        Some("admin.staging.something.domain.com")
+    );
+    assert_eq!(reg["webhooks"][0]["token"].as_str(), Some("IN_VAULT"));
+    assert_eq!(reg["kong"]["kong_token_expiration"].as_u64(), Some(1800));
+    // secrets are not leaked in the report
+    assert!(!clone.report().contains("secretsauce"));
+    assert!(clone.report().contains("+ webhooks.0.token: IN_VAULT"));
+
+    let unsanitized = shipcat::clone::region(&conf, "dev-uk", "staging-uk", false)
+        .await
+        .unwrap();
+    assert!(unsanitized.changes.is_empty());
+    assert_eq!(unsanitized.region["vault"]["folder"].as_str(), Some("dev-uk"));
+
+    assert!(shipcat::clone::region(&conf, "dev-uk", "dev-global", true)
+        .await
+        .is_err());
+}
+
 #[tokio::test]
 async fn manifest_test() {
     setup();
@@ -13,9 +13,48 @@ async fn helm_template() -> Result<()> {
         .stub(&reg)
         .await?;
 
-    let res = helm::template(&mf, None).await?;
+    let res = helm::template(&mf, &conf, &reg, None).await?;
 
     // verify we have deferred to helm for templating
     assert!(res.contains("image: \"quay.io/babylonhealth/fake-ask:1.6.0\""));
     Ok(())
 }
+
+#[test]
+fn helm_template_files() -> Result<()> {
+    let tpl = r#"---
+# Source: base/templates/service.yaml
+apiVersion: v1
+kind: Service
+metadata:
+  name: fake-ask
+---
+# Source: base/templates/deployment.yaml
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: fake-ask
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: fake-ask
+---
+"#;
+    let files = helm::template_files(tpl, "dev", false)?;
+    assert_eq!(files.keys().collect::<Vec<_>>(), vec![
+        "deployment-fake-ask-2.yaml",
+        "deployment-fake-ask.yaml",
+        "service-fake-ask.yaml",
+    ]);
+    assert!(files["service-fake-ask.yaml"].starts_with("# Source: base/templates/service.yaml\n"));
+
+    let files = helm::template_files(tpl, "dev", true)?;
+    let kustomization: serde_yaml::Value = serde_yaml::from_str(&files["kustomization.yaml"])?;
+    assert_eq!(kustomization["kind"].as_str(), Some("Kustomization"));
+    assert_eq!(kustomization["namespace"].as_str(), Some("dev"));
+    assert_eq!(kustomization["resources"].as_sequence().unwrap().len(), 3);
+
+    assert!(helm::template_files("---\n# Source: empty.yaml\n", "dev", true).is_err());
+    Ok(())
+}
@@ -1,11 +1,13 @@
 mod common;
 use crate::common::setup;
 
-use shipcat::kong::{generate_kong_output, KongfigOutput};
+use shipcat::kong::{generate_kong_output, IstioOutput, KongfigOutput};
 use shipcat_definitions::{
+    status::CanaryStatus,
     structs::kongfig::{ApiPlugin, ConsumerCredentials, HeadersQueryBody, PluginBase},
     Config, ConfigState,
 };
+use std::collections::BTreeMap;
 
 macro_rules! plugin_attributes {
     ( $name:expr, $plugin:expr, $type:path ) => {
@@ -31,8 +33,9 @@ macro_rules! assert_plugin_removed {
 async fn kong_test() {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
-    let kongrs = generate_kong_output(&conf, &reg).await.unwrap(); // kong exists in region
+    let kongrs = generate_kong_output(&conf, &reg, &BTreeMap::new()).await.unwrap(); // kong exists in region
     let mut output = KongfigOutput::new(kongrs, &reg);
+    assert!(output.upstreams.is_empty()); // no canaries
 
     assert_eq!(output.host, "admin.dev.something.domain.com");
 
@@ -174,11 +177,111 @@ async fn kong_test() {
     );
     assert_plugin_removed!("RateLimiting", api.plugins.remove(0), ApiPlugin::RateLimiting);
     assert_plugin_removed!("UserRateLimit", api.plugins.remove(0), ApiPlugin::UserRateLimit);
+
+    // deprecation notice
+    let attr = plugin_attributes!(
+        "ResponseTransformer",
+        api.plugins.remove(0),
+        ApiPlugin::ResponseTransformer
+    );
+    assert_eq!(
+        attr.config.add.headers,
+        Some(vec![
+            "Deprecation: true".to_string(),
+            "Link: <https://developer.example.com/fake-storage/v2>; rel=\"sunset\"".to_string(),
+            "Sunset: Fri, 31 Jan 2020 00:00:00 GMT".to_string(),
+        ])
+    );
+
     assert_upstream_header_transform(api.plugins.remove(0), "fake-storage");
 
     assert!(api.plugins.is_empty());
 }
 
+#[tokio::test]
+async fn kong_canary_test() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut canaries = BTreeMap::new();
+    canaries.insert("fake-ask".to_string(), CanaryStatus {
+        service: "fake-ask-canary".into(),
+        version: Some("1.1.0".into()),
+        weight: 10,
+    });
+    let kongrs = generate_kong_output(&conf, &reg, &canaries).await.unwrap();
+    let output = KongfigOutput::new(kongrs, &reg);
+
+    assert_eq!(output.upstreams.len(), 1);
+    let upstream = &output.upstreams[0];
+    assert_eq!(upstream.name, "fake-ask.upstream");
+    assert_eq!(upstream.targets[0].target, "fake-ask.dev.svc.cluster.local:80");
+    assert_eq!(upstream.targets[0].attributes.weight, 90);
+    assert_eq!(
+        upstream.targets[1].target,
+        "fake-ask-canary.dev.svc.cluster.local:80"
+    );
+    assert_eq!(upstream.targets[1].attributes.weight, 10);
+
+    assert_eq!(output.apis[0].attributes.upstream_url, "http://fake-ask.upstream");
+    // services without canaries are untouched
+    assert_eq!(
+        output.apis[1].attributes.upstream_url,
+        "http://fake-storage.dev.svc.cluster.local"
+    );
+}
+
+#[tokio::test]
+async fn kong_istio_test() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut canaries = BTreeMap::new();
+    canaries.insert("fake-ask".to_string(), CanaryStatus {
+        service: "fake-ask-canary".into(),
+        version: Some("1.1.0".into()),
+        weight: 10,
+    });
+    let kongrs = generate_kong_output(&conf, &reg, &canaries).await.unwrap();
+    let output = IstioOutput::new(kongrs, &reg);
+
+    // fake-storage has no hosts so the gateway accepts every host
+    assert_eq!(output.gateway.metadata.name, "shipcat-kong-dev-uk");
+    assert_eq!(output.gateway.spec.servers[0].hosts, vec!["*".to_string()]);
+    assert_eq!(output.virtual_services.len(), 2);
+
+    let vs = &output.virtual_services[0];
+    assert_eq!(vs.metadata.name, "fake-ask");
+    assert_eq!(vs.spec.hosts, vec![
+        "fake-ask.dev.something.domain.com".to_string(),
+        "fake.example.com".to_string(),
+    ]);
+    assert_eq!(vs.spec.gateways, vec!["shipcat-kong-dev-uk".to_string()]);
+    assert_eq!(
+        vs.metadata.annotations["shipcat.babylontech.co.uk/required-scopes"],
+        "internal"
+    );
+    let route = &vs.spec.http[0];
+    assert_eq!(route.matches[0].uri.prefix, "/ai-auth");
+    assert!(route.rewrite.is_none()); // no strip_uri and preserve_host
+    assert_eq!(route.timeout, "30s");
+    assert_eq!(route.route[0].destination.host, "fake-ask.dev.svc.cluster.local");
+    assert_eq!(route.route[0].weight, 90);
+    assert_eq!(
+        route.route[1].destination.host,
+        "fake-ask-canary.dev.svc.cluster.local"
+    );
+    assert_eq!(route.route[1].weight, 10);
+
+    let vs = &output.virtual_services[1];
+    assert_eq!(vs.spec.hosts, vec!["*".to_string()]);
+    let route = &vs.spec.http[0];
+    assert_eq!(route.route.len(), 1);
+    let response = route.headers.as_ref().unwrap().response.as_ref().unwrap();
+    assert_eq!(response.add["Deprecation"], "true");
+
+    let yaml = output.to_yaml().unwrap();
+    assert_eq!(yaml.matches("kind: VirtualService").count(), 2);
+}
+
 #[cfg(test)]
 fn assert_upstream_header_transform(plugin: ApiPlugin, service: &str) {
     let attr = plugin_attributes!("RequestTransformer", plugin, ApiPlugin::RequestTransformer);
@@ -0,0 +1,39 @@
+use shipcat_definitions::{region::SentryConfig, sentry::Sentry};
+
+fn sentry(team: Option<&str>) -> Sentry {
+    let config = SentryConfig {
+        url: mockito::server_url(),
+        organization: "sentry".into(),
+        team: team.map(String::from),
+    };
+    Sentry::new(config, "admintoken".into())
+}
+
+#[tokio::test]
+async fn sentry_dsn_of_existing_project() {
+    let keys = mockito::mock("GET", "/api/0/projects/sentry/fake-ask/keys/")
+        .match_header("Authorization", "Bearer admintoken")
+        .with_body(
+            r#"[
+                {"isActive": false, "dsn": {"public": "https://old@sentry/1"}},
+                {"isActive": true, "dsn": {"public": "https://new@sentry/1"}}
+            ]"#,
+        )
+        .expect(1)
+        .create();
+    let create = mockito::mock("POST", mockito::Matcher::Any).expect(0).create();
+    let sentry = sentry(Some("platform"));
+    assert_eq!(sentry.dsn("fake-ask").await.unwrap(), "https://new@sentry/1");
+    // second lookup is cached
+    assert_eq!(sentry.dsn("fake-ask").await.unwrap(), "https://new@sentry/1");
+    keys.assert();
+    create.assert();
+}
+
+#[tokio::test]
+async fn sentry_dsn_needs_team_to_create_project() {
+    let _keys = mockito::mock("GET", "/api/0/projects/sentry/fake-storage/keys/")
+        .with_status(404)
+        .create();
+    assert!(sentry(None).dsn("fake-storage").await.is_err());
+}
@@ -1,7 +1,7 @@
 mod common;
 use crate::common::setup;
-use shipcat::slack::{env_channel, send, send_dumb, DumbMessage, Message};
-use shipcat_definitions::{structs::NotificationMode, Config, ConfigState};
+use shipcat::slack::{env_channel, send, send_dumb, version_link, DumbMessage, Message};
+use shipcat_definitions::{status::ContainerChange, structs::NotificationMode, Config, ConfigState};
 
 // integration temporarily disabled
 #[tokio::test]
@@ -34,6 +34,7 @@ async fn slack_test() {
 -  image: \"blah:e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19\"
 +  image: \"blah:d4f01f5143643e75d9cc2d5e3221e82a9e1c12e5\""
                 )),
+                containers: vec![],
             },
             &conf.owners,
         )
@@ -54,6 +55,7 @@ async fn slack_test() {
 -  image: \"blah:abc12345678\"
 +  image: \"blah:abc23456789\""
                 )),
+                containers: vec![],
             },
             &conf.owners,
         )
@@ -61,3 +63,47 @@ async fn slack_test() {
         .unwrap();
     }
 }
+
+#[tokio::test]
+async fn slack_sidecar_versions() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mf = shipcat_filebacked::load_metadata("fake-ask", &conf, &reg)
+        .await
+        .unwrap();
+    let md = mf.base.metadata;
+
+    let diff = "fake-ask, Deployment (apps/v1) has changed:
+-         image: \"envoyproxy/envoy:v1.14.1\"
++         image: \"envoyproxy/envoy:v1.15.0\"
+-         image: \"quay.io/babylonhealth/fake-ask:1.0.6\"
++         image: \"quay.io/babylonhealth/fake-ask:1.0.7\"";
+    let containers = vec![
+        ContainerChange {
+            name: "envoy".into(),
+            init: false,
+            old: Some("v1.14.1".into()),
+            new: Some("v1.15.0".into()),
+        },
+        ContainerChange {
+            name: "migrate".into(),
+            init: true,
+            old: None,
+            new: Some("1.0.0".into()),
+        },
+    ];
+    // the compare link is for the service, not the sidecar that changed first
+    assert_eq!(
+        version_link(&md, Some(diff), "1.0.7", &containers),
+        "https://github.com/babylonhealth/shipcat/compare/prefix-1.0.6-suffix...prefix-1.0.7-suffix"
+    );
+    assert_eq!(
+        version_link(&md, Some(diff), "1.0.7", &[]),
+        "https://github.com/babylonhealth/shipcat/compare/v1.14.1...v1.15.0"
+    );
+    assert_eq!(containers[0].describe(), "sidecar `envoy` v1.14.1 -> v1.15.0");
+    assert_eq!(
+        containers[1].describe(),
+        "init container `migrate` added at 1.0.0"
+    );
+}
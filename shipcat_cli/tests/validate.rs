@@ -2,7 +2,7 @@ mod common;
 use crate::common::setup;
 
 use shipcat::validate::manifest as validate;
-use shipcat_definitions::{Config, ConfigState};
+use shipcat_definitions::{Config, ConfigState, Manifest};
 
 #[tokio::test]
 async fn validate_test() {
@@ -13,3 +13,76 @@ async fn validate_test() {
     let res2 = validate(vec!["fake-storage".into(), "fake-ask".into()], &conf, &reg, false).await;
     assert!(res2.is_ok())
 }
+
+#[tokio::test]
+async fn validate_chart_values() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = Manifest::test("fake-storage");
+    mf.chartValues = serde_yaml::from_str("ingress:\n  annotations:\n    foo: bar").unwrap();
+    assert!(mf.verify_chart_values(&conf, &reg).is_ok());
+    mf.chartValues = serde_yaml::from_str("ingress:\n  enabled: true").unwrap();
+    assert!(mf.verify_chart_values(&conf, &reg).is_err());
+}
+
+#[tokio::test]
+async fn validate_disabled_regions() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap()
+        .stub(&reg)
+        .await
+        .unwrap();
+    mf.regions.push("dev-global".into());
+    mf.disabledIn =
+        serde_yaml::from_str("- region: dev-global\n  reason: migrating\n  until: 2000-01-01").unwrap();
+    // expired disables of other regions are left to their regional validation
+    assert!(mf.verify(&conf, &reg).is_ok());
+    mf.disabledIn[0].region = "dev-uk".into();
+    assert!(mf.verify(&conf, &reg).is_err());
+}
+
+#[test]
+fn validate_fix_source() {
+    use shipcat::validate::fix_source;
+    let source = "name: svc # keep me
+regions:
+- dev-uk
+- dev-global
+env:
+  java_opts: -Xmx1g
+  db_password: IN_VAULT
+sidecars:
+- name: redis-
+workers:
+- name: consumer
+  env:
+    mode: fast
+";
+    let expected = "name: svc # keep me
+chart: base
+regions:
+- dev-global
+- dev-uk
+env:
+  JAVA_OPTS: -Xmx1g
+  db_password: IN_VAULT
+sidecars:
+- name: redis
+workers:
+- name: consumer
+  env:
+    MODE: fast
+";
+    let (fixed, fixes) = fix_source(source, Some("base"));
+    assert_eq!(fixed, expected);
+    assert_eq!(fixes.len(), 5);
+
+    // comments in the region list stay where they are
+    let commented = "name: svc\nregions:\n- dev-uk\n# soon\n- dev-global\n";
+    let (fixed, fixes) = fix_source(commented, None);
+    assert_eq!(fixed, commented);
+    assert!(fixes.is_empty());
+}
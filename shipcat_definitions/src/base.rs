@@ -1,6 +1,6 @@
 use std::fmt;
 
-use super::structs::Metadata;
+use super::structs::{DisabledRegion, Metadata};
 
 /// Subset of a service manifest without any region-level defaults/overrides.
 #[derive(Clone)]
@@ -9,6 +9,8 @@ pub struct BaseManifest {
     pub name: String,
     pub metadata: Metadata,
     pub regions: Vec<String>,
+    /// Regions the service is temporarily disabled in
+    pub disabled_in: Vec<DisabledRegion>,
 }
 
 impl fmt::Debug for BaseManifest {
@@ -1,20 +1,25 @@
 #![allow(non_snake_case)]
 
 use kube_derive::CustomResource;
+use regex::Regex;
+use schemars::JsonSchema;
 use semver::Version;
 use std::collections::{BTreeMap, BTreeSet};
 
 use crate::teams;
 #[allow(unused_imports)] use std::path::{Path, PathBuf};
 
-#[allow(unused_imports)] use super::{Error, Result};
+#[allow(unused_imports)] use super::Error;
+/// The crate `Result`, also usable with two parameters by the `JsonSchema` derive of `serde_regex` fields
+type Result<T, E = Error> = std::result::Result<T, E>;
 use crate::{
-    region::{Environment, Region},
+    freeze::FreezeConfig,
+    region::{Environment, Region, SecretStoreConfig},
     states::ConfigState,
 };
 
 /// Kubernetes cluster information
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cluster {
     /// Name of the cluster
@@ -29,9 +34,69 @@ pub struct Cluster {
     pub clustername: Option<String>,
     /// What regions this cluster control (perhaps not exclusively)
     pub regions: Vec<String>,
+    /// Kubernetes version and apis served by the cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<ClusterCapabilities>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Kubernetes version and api versions served by a cluster
+///
+/// Passed to `helm template` so charts can pick apiVersions with `.Capabilities`,
+/// and checked against rendered objects in `shipcat cluster check`.
+///
+/// ```yaml
+/// clusters:
+///   kops-uk:
+///     name: kops-uk
+///     api: https://api.kube.uk.some.domain
+///     regions: [dev-uk]
+///     capabilities:
+///       kubeVersion: 1.19.4
+///       apiVersions:
+///       - apps/v1
+///       - autoscaling/v2beta2
+///       - networking.k8s.io/v1
+///       - policy/v1beta1
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ClusterCapabilities {
+    /// Kubernetes version of the api server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub kube_version: Option<Version>,
+    /// Api group versions served, like `autoscaling/v2beta2`
+    ///
+    /// The core `v1` api is always served.
+    #[serde(default)]
+    pub api_versions: BTreeSet<String>,
+}
+
+impl ClusterCapabilities {
+    /// Whether objects of an apiVersion can be created
+    pub fn serves(&self, api_version: &str) -> bool {
+        api_version == "v1" || self.api_versions.contains(api_version)
+    }
+
+    /// Capabilities shared with another cluster
+    fn intersect(&self, other: &ClusterCapabilities) -> ClusterCapabilities {
+        let kube_version = match (&self.kube_version, &other.kube_version) {
+            (Some(a), Some(b)) => Some(a.min(b).clone()),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        ClusterCapabilities {
+            kube_version,
+            api_versions: self
+                .api_versions
+                .intersection(&other.api_versions)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Location {
     /// Location name
@@ -44,24 +109,468 @@ pub struct Location {
     pub local_region: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GithubParameters {
     /// Organisation name
     pub organisation: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SlackParameters {
     /// Team name (T...)
     pub team: String,
+
+    /// Tera template for the text of upgrade notifications
+    ///
+    /// Has access to `service`, `state`, `region`, `cluster`, `version`, `metadata`,
+    /// `link` (version diff or release link), `duration` (rollout seconds, if waited for)
+    /// and `containers` (sidecar and init container version changes, each with `name`, `init`,
+    /// `old` and `new`). Changed containers are also listed below the text.
+    /// Defaults to `DEFAULT_UPGRADE_TEMPLATE`.
+    ///
+    /// ```yaml
+    /// slack:
+    ///   team: T1111111
+    ///   upgradeTemplate: |
+    ///     {% if state == "COMPLETED" %}:rocket:{% else %}:fire:{% endif %} `{{ service }}` {{ version }} in `{{ region }}`
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgradeTemplate: Option<String>,
+}
+
+/// The text of upgrade notifications when no `slack.upgradeTemplate` is set
+pub const DEFAULT_UPGRADE_TEMPLATE: &str =
+    "{% if state == \"COMPLETED\" %}applied{% else %}failed to apply{% endif %} `{{ service }}` in `{{ region }}`";
+
+/// Environment variable names masked in diffs when no `sensitiveEnvRegex` is set
+pub const DEFAULT_SENSITIVE_ENV: &str = "(?i)(secret|password|passwd|token|credential|api_?key|private_?key)";
+
+impl SlackParameters {
+    /// The upgrade notification template in use
+    pub fn upgrade_template(&self) -> &str {
+        self.upgradeTemplate
+            .as_deref()
+            .unwrap_or(DEFAULT_UPGRADE_TEMPLATE)
+    }
+}
+
+/// Stage of `shipcat apply` an `ApplyHook` runs at
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ApplyHookStage {
+    /// Before the kubernetes yaml is templated
+    PreTemplate,
+    /// After the kubernetes yaml is templated (the file is in `SHIPCAT_TEMPLATE_FILE`)
+    PostTemplate,
+    /// Before the templated yaml is applied
+    PreApply,
+    /// After the rollout of the service succeeded (only when waiting for rollouts)
+    PostRollout,
+}
+
+/// An external executable run at a stage of `shipcat apply`
+///
+/// The executable gets the manifest as JSON (without secrets) on stdin,
+/// and `SHIPCAT_HOOK_STAGE` + `SHIPCAT_REGION` as evars.
+/// A failing hook aborts the apply unless it is `optional`.
+///
+/// ```yaml
+/// applyHooks:
+/// - name: cmdb
+///   stage: postRollout
+///   command: ./hooks/update-cmdb
+///   optional: true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ApplyHook {
+    /// Name of the hook
+    pub name: String,
+    /// Stage to run the hook at
+    pub stage: ApplyHookStage,
+    /// Executable to run
+    pub command: String,
+    /// Arguments to pass to the executable
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Only warn if the hook fails
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// A WASM module with organisation specific manifest validation
+///
+/// The module is given the completed manifest as JSON during `shipcat validate`
+/// and `shipcat cluster check`, and returns a list of diagnostics.
+/// Plugins are stopped after ten million instructions per manifest.
+///
+/// ```yaml
+/// validationPlugins:
+/// - name: naming-rules
+///   path: plugins/naming_rules.wasm
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ValidationPlugin {
+    /// Name of the plugin
+    pub name: String,
+    /// Path to the WASM module (relative to the manifests repository)
+    pub path: String,
+}
+
+/// Severity of a lint rule
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    /// Reported, and fails `shipcat lint --deny warnings`
+    Warning,
+    /// Fails `shipcat lint`
+    Error,
+}
+
+/// The check a lint rule performs
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(tag = "check", rename_all = "camelCase")]
+pub enum LintCheck {
+    /// Resource requests of every container are within their limits
+    RequestsWithinLimits,
+    /// Containers with an `httpPort` have readiness and liveness probes
+    HttpProbes,
+    /// Images are not deployed with the `latest` tag
+    NoLatestTag,
+    /// A label is set and matches a pattern
+    LabelPattern {
+        label: String,
+        #[serde(with = "serde_regex")]
+        #[schemars(with = "String")]
+        pattern: Regex,
+    },
+}
+
+/// A policy rule evaluated against manifests by `shipcat lint`
+///
+/// ```yaml
+/// lintRules:
+/// - id: no-latest-in-prod
+///   check: noLatestTag
+///   severity: error
+///   environments: [prod]
+/// - id: team-label
+///   check: labelPattern
+///   label: squad
+///   pattern: "^[a-z-]+$"
+///   severity: warning
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct LintRule {
+    /// Identifier reported with every finding
+    pub id: String,
+    /// Severity of findings
+    pub severity: LintSeverity,
+    /// Check to perform
+    #[serde(flatten)]
+    pub check: LintCheck,
+    /// Environments the rule applies in (all if empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<Environment>,
+}
+
+/// A substring replacement applied when cloning a region
+///
+/// Used by `shipcat config clone-region --sanitize` to point urls
+/// of a cloned region at the new environment.
+///
+/// ```yaml
+/// regionCloneRewrites:
+/// - from: .prod.something.domain.com
+///   to: .staging.something.domain.com
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RegionRewrite {
+    /// Substring to replace
+    pub from: String,
+    /// Replacement
+    pub to: String,
+}
+
+/// Format of a `RosterConfig` endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RosterKind {
+    /// A SCIM 2.0 base url serving `/Users` and `/Groups`
+    Scim,
+    /// A plain json document with `people` and `squads`
+    Json,
+}
+
+/// An employee directory to verify manifest ownership against
+///
+/// Used by `shipcat validate` to check that maintainers and contacts are still active,
+/// and that squads exist. A bearer token is read from `SHIPCAT_ROSTER_TOKEN` if set.
+///
+/// ```yaml
+/// roster:
+///   kind: scim
+///   url: https://directory.babylontech.co.uk/scim/v2
+///   cacheTtl: 86400
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RosterConfig {
+    /// Format of the endpoint
+    pub kind: RosterKind,
+    /// Url of the endpoint
+    pub url: String,
+    /// Seconds a fetched roster is cached for
+    #[serde(default = "default_roster_ttl")]
+    pub cacheTtl: u64,
+}
+
+fn default_roster_ttl() -> u64 {
+    24 * 60 * 60
+}
+
+/// Where `shipcat` finds the identity of the invoking user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum IdentitySource {
+    /// The github login of the active `tsh` session
+    Teleport,
+    /// The `email` claim of an id token in `SHIPCAT_OIDC_TOKEN`, signed by the `oidc` issuer
+    Oidc,
+}
+
+impl Default for IdentitySource {
+    fn default() -> Self {
+        IdentitySource::Teleport
+    }
+}
+
+/// The OpenID Connect provider issuing `SHIPCAT_OIDC_TOKEN`
+///
+/// Tokens must be signed by one of the keys of the issuer, and be meant for the audience.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct OidcConfig {
+    /// Issuer url, serving `/.well-known/openid-configuration`
+    pub issuer: String,
+    /// Client id the tokens are issued for
+    pub audience: String,
+}
+
+/// Ownership checks for mutating commands
+///
+/// In the listed `environments`, `apply`, `restart` and `delete` are only allowed
+/// for members of the squad owning the service, or of one of the `adminSquads`.
+/// Users are matched against people in teams.yml by github login or email.
+/// `--break-glass JUSTIFICATION` skips the check and records the justification in audit events.
+///
+/// ```yaml
+/// permissions:
+///   environments: [prod]
+///   identity: teleport
+///   adminSquads: [platform]
+/// ```
+///
+/// With `identity: oidc`, the `oidc` issuer verifying id tokens must be set.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PermissionsConfig {
+    /// Environments to enforce ownership in
+    pub environments: Vec<Environment>,
+    /// Where to find the identity of the user
+    #[serde(default)]
+    pub identity: IdentitySource,
+    /// Issuer of id tokens for the `oidc` identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oidc: Option<OidcConfig>,
+    /// Squads allowed to mutate any service
+    #[serde(default)]
+    pub adminSquads: Vec<String>,
+}
+
+/// Endpoint collecting anonymous shipcat usage from users who opted in
+///
+/// Only command names, durations, exit categories, shipcat versions and environment
+/// classes are sent. See `shipcat telemetry status`.
+///
+/// ```yaml
+/// telemetry:
+///   url: https://shipcat-telemetry.babylontech.co.uk/events
+///   batchSize: 20
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct TelemetryConfig {
+    /// Url events are POSTed to
+    pub url: String,
+    /// Number of recorded events to send at once
+    #[serde(default = "default_telemetry_batch")]
+    pub batchSize: usize,
+}
+
+fn default_telemetry_batch() -> usize {
+    20
+}
+
+/// How credentials for a container registry are obtained
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RegistryKind {
+    /// Docker registry token flow, with `user:password` read from `credentialsEnv` if set
+    Generic,
+    /// Quay token flow, with robot account `user:password` read from `credentialsEnv` if set
+    Quay,
+    /// Password from `aws ecr get-login-password` in the region of the registry host
+    Ecr,
+    /// Access token from `gcloud auth print-access-token`
+    Gcr,
+}
+
+impl Default for RegistryKind {
+    fn default() -> Self {
+        RegistryKind::Generic
+    }
+}
+
+/// A container registry that images are verified against
+///
+/// Used by `shipcat validate --images` to check that manifest images and tags exist.
+/// Images on hosts without a registry entry are checked anonymously.
+///
+/// ```yaml
+/// registries:
+/// - host: quay.io
+///   kind: quay
+///   credentialsEnv: QUAY_CREDENTIALS
+/// - host: 123456789012.dkr.ecr.eu-west-2.amazonaws.com
+///   kind: ecr
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RegistryConfig {
+    /// Registry host as used in image names
+    pub host: String,
+    /// How to authenticate against the registry
+    #[serde(default)]
+    pub kind: RegistryKind,
+    /// Environment variable with `user:password` credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentialsEnv: Option<String>,
+}
+
+/// Write-back of applied versions to the manifests repository
+///
+/// After a successful `shipcat apply -t VERSION` in one of the `environments`,
+/// the version is pinned in `services/{name}/{region}.yml` through the GitHub api,
+/// so that git stays the source of truth. Requires `GITHUB_SHIPCAT_TOKEN` with push access.
+///
+/// ```yaml
+/// gitops:
+///   repo: manifests
+///   branch: master
+///   pullRequest: true
+///   environments: [prod]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct GitopsConfig {
+    /// Name of the manifests repository in the github organisation
+    pub repo: String,
+    /// Branch to commit to, or to open pull requests against
+    #[serde(default = "default_gitops_branch")]
+    pub branch: String,
+    /// Open a pull request rather than committing to `branch` directly
+    #[serde(default)]
+    pub pullRequest: bool,
+    /// Environments to write back versions for
+    #[serde(default = "default_gitops_environments")]
+    pub environments: Vec<Environment>,
+}
+
+fn default_gitops_branch() -> String {
+    "master".into()
+}
+fn default_gitops_environments() -> Vec<Environment> {
+    vec![Environment::Prod]
+}
+
+/// Hourly prices of requested resources
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PriceSheet {
+    /// Price of one requested vCPU per hour
+    pub cpuHour: f64,
+    /// Price of one requested GiB of memory per hour
+    pub memoryGibHour: f64,
+}
+
+/// Prices used by `shipcat cost` to estimate monthly spend from resource requests
+///
+/// The top level prices apply to every region without an entry in `regions`.
+/// With a `url`, the price sheet of each region is fetched from a pricing api
+/// as `{url}?region={region}` instead, which must answer with a `PriceSheet` as json.
+///
+/// ```yaml
+/// pricing:
+///   currency: USD
+///   cpuHour: 0.0316
+///   memoryGibHour: 0.0042
+///   regions:
+///     prod-uk: { cpuHour: 0.0354, memoryGibHour: 0.0047 }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PricingConfig {
+    /// Currency of the prices, only used for display
+    #[serde(default = "default_pricing_currency")]
+    pub currency: String,
+    /// Price of one requested vCPU per hour
+    pub cpuHour: f64,
+    /// Price of one requested GiB of memory per hour
+    pub memoryGibHour: f64,
+    /// Price overrides per region
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub regions: BTreeMap<String, PriceSheet>,
+    /// Pricing api to fetch price sheets from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+fn default_pricing_currency() -> String {
+    "USD".into()
+}
+
+impl PricingConfig {
+    /// Configured prices for a region
+    pub fn sheet(&self, region: &str) -> PriceSheet {
+        self.regions.get(region).cloned().unwrap_or_else(|| PriceSheet {
+            cpuHour: self.cpuHour,
+            memoryGibHour: self.memoryGibHour,
+        })
+    }
+
+    fn verify(&self) -> Result<()> {
+        let defaults = PriceSheet {
+            cpuHour: self.cpuHour,
+            memoryGibHour: self.memoryGibHour,
+        };
+        for p in self.regions.values().chain(Some(&defaults)) {
+            if p.cpuHour < 0.0 || p.memoryGibHour < 0.0 {
+                bail!("pricing cannot contain negative prices");
+            }
+        }
+        Ok(())
+    }
 }
 
 // ----------------------------------------------------------------------------------
 
 /// Main manifest, serializable from shipcat.conf
-#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[kube(
     group = "babylontech.co.uk",
     kind = "ShipcatConfig",
@@ -74,11 +583,100 @@ pub struct Config {
     /// Global defaults for the manifests (used by shipcat_filebacked only)
     #[serde(default)]
     #[cfg(feature = "filesystem")]
+    #[schemars(with = "serde_json::Value")]
     pub defaults: serde_yaml::Value,
 
+    /// Per-environment defaults for the manifests (used by shipcat_filebacked only)
+    ///
+    /// Layered over the global `defaults`, and under region defaults.
+    /// Useful for settings that differ by environment rather than by region:
+    ///
+    /// ```yaml
+    /// environmentDefaults:
+    ///   dev:
+    ///     probeTimings:
+    ///       initialDelaySeconds: 10
+    ///     rollingUpdate:
+    ///       maxUnavailable: 50%
+    ///   prod:
+    ///     probeTimings:
+    ///       failureThreshold: 5
+    ///     rollingUpdate:
+    ///       maxUnavailable: 0
+    ///       maxSurge: 25%
+    /// ```
+    #[serde(default)]
+    #[cfg(feature = "filesystem")]
+    #[schemars(with = "BTreeMap<Environment, serde_json::Value>")]
+    pub environmentDefaults: BTreeMap<Environment, serde_yaml::Value>,
+
+    /// Named sidecars that manifests can start from (used by shipcat_filebacked only)
+    ///
+    /// Manifests reference a profile with `sidecars: [{profile: telegraf}]`,
+    /// and can override any of its container properties next to the reference.
+    /// The sidecar is named after the profile unless the profile or manifest names it.
+    ///
+    /// ```yaml
+    /// sidecarProfiles:
+    ///   telegraf:
+    ///     image: telegraf
+    ///     version: 1.14.0
+    ///     resources:
+    ///       requests: {cpu: 50m, memory: 64Mi}
+    ///       limits: {cpu: 200m, memory: 128Mi}
+    /// ```
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg(feature = "filesystem")]
+    #[schemars(with = "BTreeMap<String, serde_json::Value>")]
+    pub sidecarProfiles: BTreeMap<String, serde_yaml::Value>,
+
+    /// Named region overlays that regions can `extends`
+    ///
+    /// Layered over `regions/_defaults.yml`, and under the region itself.
+    /// Overlays can extend other overlays, with the closer overlay taking precedence:
+    ///
+    /// ```yaml
+    /// regionDefaults:
+    ///   base:
+    ///     vault:
+    ///       url: https://vault.babylontech.co.uk:8200
+    ///   prod:
+    ///     extends: base
+    ///     versioningScheme: Semver
+    /// regions:
+    /// - name: prod-uk
+    ///   extends: prod
+    /// ```
+    #[serde(default, skip_serializing)]
+    #[cfg(feature = "filesystem")]
+    #[schemars(with = "BTreeMap<String, serde_json::Value>")]
+    pub regionDefaults: BTreeMap<String, serde_yaml::Value>,
+
+    /// Rewrites applied to strings of regions cloned with `--sanitize`
+    ///
+    /// Applied in order, after the source region name is replaced by the new name.
+    #[serde(default)]
+    #[cfg(feature = "filesystem")]
+    pub regionCloneRewrites: Vec<RegionRewrite>,
+
     /// Cluster definitions
     pub clusters: BTreeMap<String, Cluster>,
 
+    /// Environment groups, e.g. prod -> [prod-uk, prod-eu]
+    ///
+    /// Commands taking `-e` expand groups to their member regions.
+    /// Environments without a group expand to the regions in that environment.
+    ///
+    /// ```yaml
+    /// environmentGroups:
+    ///   prod:
+    ///   - prod-uk
+    ///   - prod-eu
+    /// ```
+    #[serde(default)]
+    #[cfg(feature = "filesystem")]
+    pub environmentGroups: BTreeMap<String, Vec<String>>,
+
     /// Context aliases, e.g. prod-uk-green -> prod-uk
     #[serde(default)]
     pub contextAliases: BTreeMap<String, String>,
@@ -106,9 +704,74 @@ pub struct Config {
     #[serde(default)]
     pub allowedCustomMetadata: BTreeSet<String>,
 
+    /// Allowed `chartValues` paths per environment
+    ///
+    /// Dot separated paths that manifests may set raw chart values under.
+    #[serde(default)]
+    pub allowedChartValues: BTreeMap<Environment, Vec<String>>,
+
     /// Shipcat version pins
+    #[schemars(with = "BTreeMap<Environment, String>")]
     pub versions: BTreeMap<Environment, Version>,
 
+    /// External executables to run during `shipcat apply`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub applyHooks: Vec<ApplyHook>,
+
+    /// WASM modules validating manifests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validationPlugins: Vec<ValidationPlugin>,
+
+    /// Policy rules for `shipcat lint`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lintRules: Vec<LintRule>,
+
+    /// Employee directory to verify ownership against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roster: Option<RosterConfig>,
+
+    /// Ownership checks for mutating commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<PermissionsConfig>,
+
+    /// Write-back of applied versions to git
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gitops: Option<GitopsConfig>,
+
+    /// Change freezes enforced by `shipcat apply`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freezes: Option<FreezeConfig>,
+
+    /// Container registries to verify images against
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub registries: Vec<RegistryConfig>,
+
+    /// Usage telemetry endpoint for users who opted in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry: Option<TelemetryConfig>,
+
+    /// Resource prices for cost estimates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingConfig>,
+
+    /// End-to-end latency budget of dependency chains in milliseconds
+    ///
+    /// Chains of dependencies whose `latencyBudgetMs` add up to more than this
+    /// are flagged by `shipcat get latency-budgets`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latencyBudgetMs: Option<u32>,
+
+    /// Names of environment variables whose values are masked in diffs
+    ///
+    /// Defaults to `DEFAULT_SENSITIVE_ENV` when unset.
+    ///
+    /// ```yaml
+    /// sensitiveEnvRegex: "(?i)(secret|password|token|dsn)"
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_regex")]
+    #[schemars(with = "Option<String>")]
+    pub sensitiveEnvRegex: Option<Regex>,
+
     /// Owners of services, squads, tribes
     ///
     /// Populated from teams.yml
@@ -138,6 +801,21 @@ impl Config {
             }
         }
 
+        #[cfg(feature = "filesystem")]
+        for (g, regions) in &self.environmentGroups {
+            for r in regions {
+                if !self.has_region(r) && self.state == ConfigState::File {
+                    bail!("environment group {} contains undefined region {}", g, r);
+                }
+            }
+        }
+
+        if let Some(p) = &self.permissions {
+            if p.identity == IdentitySource::Oidc && p.oidc.is_none() {
+                bail!("permissions with an oidc identity need the `oidc` issuer verifying tokens");
+            }
+        }
+
         for (k, v) in &self.contextAliases {
             // all contextAlias values must exist as defined regions
             if !self.has_region(v) {
@@ -160,7 +838,22 @@ impl Config {
             if !self.clusters.keys().any(|c| c == &r.cluster) {
                 bail!("Region {} served by missing cluster '{}'", r.name, r.cluster);
             }
-            r.vault.verify(&r.name)?;
+            let uses_vault = matches!(r.secretStore, None | Some(SecretStoreConfig::Vault));
+            r.vault.verify(&r.name, uses_vault)?;
+            if let Some(s) = &r.secretStore {
+                s.verify(&r.name)?;
+            }
+            if let Some(e) = &r.egress {
+                e.verify(&r.name)?;
+            }
+            if let Some(q) = &r.rolloutQueue {
+                if q.max_concurrent == 0 {
+                    bail!("rolloutQueue in {} needs a maxConcurrent of at least 1", r.name);
+                }
+            }
+            if let Some(b) = &r.resourceBudgets {
+                b.verify(&r.name)?;
+            }
             for v in r.base_urls.values() {
                 if v.ends_with('/') {
                     bail!("A base_url must not end with a slash");
@@ -174,6 +867,51 @@ impl Config {
                 used_kong_urls.push(kong.config_url.clone());
             }
         }
+
+        let mut used_hook_names = vec![];
+        for h in &self.applyHooks {
+            if h.command.is_empty() {
+                bail!("applyHook {} needs a command", h.name);
+            }
+            if used_hook_names.contains(&h.name) {
+                bail!("Cannot reuse applyHook name {}", h.name);
+            }
+            used_hook_names.push(h.name.clone());
+        }
+
+        let mut used_rule_ids = vec![];
+        for r in &self.lintRules {
+            if used_rule_ids.contains(&r.id) {
+                bail!("Cannot reuse lintRule id {}", r.id);
+            }
+            used_rule_ids.push(r.id.clone());
+        }
+
+        if let Some(p) = &self.pricing {
+            p.verify()?;
+            #[cfg(feature = "filesystem")]
+            for r in p.regions.keys() {
+                if !self.has_region(r) && self.state == ConfigState::File {
+                    bail!("pricing defines prices for undefined region {}", r);
+                }
+            }
+        }
+
+        if let Some(f) = &self.freezes {
+            f.verify()?;
+            #[cfg(feature = "filesystem")]
+            for r in f.windows.iter().flat_map(|w| &w.regions) {
+                if !self.has_region(r) && self.state == ConfigState::File {
+                    bail!("freezes reference undefined region {}", r);
+                }
+            }
+        }
+
+        if let Some(tpl) = &self.slack.upgradeTemplate {
+            if let Err(e) = tera::Tera::default().add_raw_template("upgrade", tpl) {
+                bail!("slack.upgradeTemplate is not a valid template: {}", e);
+            }
+        }
         Ok(())
     }
 
@@ -235,11 +973,39 @@ impl Config {
         Ok(())
     }
 
+    /// The regex for environment variable names that are masked in diffs
+    pub fn sensitive_env_regex(&self) -> Regex {
+        self.sensitiveEnvRegex
+            .clone()
+            .unwrap_or_else(|| Regex::new(DEFAULT_SENSITIVE_ENV).unwrap())
+    }
+
     /// Helper for small utils that don't need the full struct
     pub fn list_regions(&self) -> Vec<String> {
         self.regions.iter().map(|r| r.name.clone()).collect()
     }
 
+    /// Regions of an environment group
+    ///
+    /// Falls back to all regions of the environment with that name.
+    #[cfg(feature = "filesystem")]
+    pub fn environment_regions(&self, env: &str) -> Result<Vec<String>> {
+        assert!(self.has_all_regions());
+        if let Some(regions) = self.environmentGroups.get(env) {
+            return Ok(regions.clone());
+        }
+        let regions = self
+            .regions
+            .iter()
+            .filter(|r| r.environment.to_string() == env)
+            .map(|r| r.name.clone())
+            .collect::<Vec<_>>();
+        if regions.is_empty() {
+            bail!("No environment group or regions for environment {}", env);
+        }
+        Ok(regions)
+    }
+
     /// Fill secrets from vault on a Base config for a known to exist region
     ///
     /// This will use the HTTP api of Vault using the configuration parameters.
@@ -333,6 +1099,21 @@ impl Config {
         self.regions.clone()
     }
 
+    /// Capabilities of the clusters serving a region
+    ///
+    /// During blue/green failovers a region is served by more than one cluster,
+    /// so only what every cluster declaring capabilities serves is included.
+    pub fn region_capabilities(&self, region: &Region) -> Option<ClusterCapabilities> {
+        self.clusters
+            .values()
+            .filter(|c| c.regions.contains(&region.name))
+            .filter_map(|c| c.capabilities.clone())
+            .fold(None, |acc, c| match acc {
+                None => Some(c),
+                Some(a) => Some(a.intersect(&c)),
+            })
+    }
+
     /// Find the Cluster struct that owns this Region
     pub fn find_owning_cluster(&self, region: &Region) -> Option<Cluster> {
         for c in self.clusters.values() {
@@ -429,6 +1210,9 @@ impl Config {
     }
 
     /// Read a config file in an arbitrary path
+    ///
+    /// Regions are layered on top of the `regionDefaults` they extend,
+    /// and `regions/_defaults.yml` if it exists.
     async fn read_from(pwd: &PathBuf) -> Result<Config> {
         use tokio::fs;
         let mpath = pwd.join("shipcat.conf");
@@ -437,7 +1221,26 @@ impl Config {
             bail!("Config file {} does not exist", mpath.display())
         }
         let data = fs::read_to_string(&mpath).await?;
-        let res = serde_yaml::from_str(&data)?;
+        let mut raw: serde_yaml::Value = serde_yaml::from_str(&data)?;
+
+        let dpath = pwd.join("regions").join("_defaults.yml");
+        let defaults = if dpath.exists() {
+            trace!("Using region defaults in {}", dpath.display());
+            let ddata = fs::read_to_string(&dpath).await?;
+            Some(serde_yaml::from_str(&ddata)?)
+        } else {
+            None
+        };
+        let overlays: BTreeMap<String, serde_yaml::Value> = match raw.get("regionDefaults") {
+            Some(o) => serde_yaml::from_value(o.clone())?,
+            None => BTreeMap::new(),
+        };
+        if let Some(regions) = raw.get_mut("regions").and_then(|r| r.as_sequence_mut()) {
+            for r in regions.iter_mut() {
+                *r = layer_region(r.clone(), &overlays, defaults.as_ref())?;
+            }
+        }
+        let res = serde_yaml::from_value(raw)?;
         Ok(res)
     }
 
@@ -490,9 +1293,145 @@ impl Config {
     }
 }
 
+/// Layer a raw region over the overlays it `extends` and the region defaults
+///
+/// Precedence from lowest to highest: `defaults`, the furthest overlay in the `extends` chain,
+/// ..., the overlay named by the region, and finally the region itself.
+#[cfg(feature = "filesystem")]
+fn layer_region(
+    region: serde_yaml::Value,
+    overlays: &BTreeMap<String, serde_yaml::Value>,
+    defaults: Option<&serde_yaml::Value>,
+) -> Result<serde_yaml::Value> {
+    use merge::Merge;
+    let name = region["name"].as_str().unwrap_or("unnamed region").to_string();
+    // collect the extends chain from the closest overlay
+    let mut chain: Vec<&str> = vec![];
+    let mut next = region["extends"].as_str();
+    while let Some(o) = next {
+        if chain.contains(&o) {
+            bail!(
+                "Region {} has an extends cycle through regionDefaults.{}",
+                name,
+                o
+            );
+        }
+        let overlay = match overlays.get(o) {
+            Some(ov) => ov,
+            None => bail!("Region {} extends undefined regionDefaults.{}", name, o),
+        };
+        chain.push(o);
+        next = overlay["extends"].as_str();
+    }
+    let mut layered = YamlLayer(defaults.cloned().unwrap_or(serde_yaml::Value::Null));
+    for o in chain.into_iter().rev() {
+        layered = layered.merge(YamlLayer(overlays[o].clone()));
+    }
+    Ok(layered.merge(YamlLayer(region)).0)
+}
+
+/// A yaml document that deep merges into another
+///
+/// Mappings are merged key by key, anything else (including lists) is replaced.
+#[cfg(feature = "filesystem")]
+struct YamlLayer(serde_yaml::Value);
+
+#[cfg(feature = "filesystem")]
+impl merge::Merge for YamlLayer {
+    fn merge(self, other: Self) -> Self {
+        use serde_yaml::Value;
+        match (self.0, other.0) {
+            (Value::Mapping(mut base), Value::Mapping(over)) => {
+                for (k, v) in over {
+                    let merged = match base.remove(&k) {
+                        Some(b) => YamlLayer(b).merge(YamlLayer(v)).0,
+                        None => v,
+                    };
+                    base.insert(k, merged);
+                }
+                YamlLayer(Value::Mapping(base))
+            }
+            (_, over) => YamlLayer(over),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::region::VersionScheme;
+
+    #[cfg(feature = "filesystem")]
+    #[test]
+    fn region_defaults_layering() {
+        use super::YamlLayer;
+        use merge::Merge;
+        let defaults = serde_yaml::from_str(
+            "versioningScheme: Semver\nkong:\n  config_url: https://kong.example\n  tcp_log: {enabled: true}\nlocations: [london]",
+        )
+        .unwrap();
+        let region =
+            serde_yaml::from_str("name: dev-uk\nkong:\n  tcp_log: {enabled: false}\nlocations: [berlin]")
+                .unwrap();
+        let merged = YamlLayer(defaults).merge(YamlLayer(region)).0;
+        assert_eq!(merged["name"].as_str(), Some("dev-uk"));
+        assert_eq!(merged["versioningScheme"].as_str(), Some("Semver"));
+        assert_eq!(
+            merged["kong"]["config_url"].as_str(),
+            Some("https://kong.example")
+        );
+        assert_eq!(merged["kong"]["tcp_log"]["enabled"].as_bool(), Some(false));
+        assert_eq!(merged["locations"][0].as_str(), Some("berlin"));
+        assert!(merged["locations"].get(1).is_none());
+    }
+
+    #[cfg(feature = "filesystem")]
+    #[test]
+    fn region_overlay_precedence() {
+        use super::layer_region;
+        use std::collections::BTreeMap;
+        let base = "versioningScheme: Semver\nkong:\n  config_url: https://base\n  tcp_log: {enabled: true}";
+        let prod = "extends: base\nkong:\n  config_url: https://prod\nlocations: [london]";
+        let mut overlays = BTreeMap::new();
+        overlays.insert("base".to_string(), serde_yaml::from_str(base).unwrap());
+        overlays.insert("prod".to_string(), serde_yaml::from_str(prod).unwrap());
+        let defaults = "versioningScheme: GitShaOrSemver\nnamespace: apps\nlocations: [berlin]";
+        let defaults = serde_yaml::from_str(defaults).unwrap();
+        let region = "name: prod-uk\nextends: prod\nkong: {tcp_log: {enabled: false}}";
+        let region = serde_yaml::from_str(region).unwrap();
+
+        let merged = layer_region(region, &overlays, Some(&defaults)).unwrap();
+        assert_eq!(merged["namespace"].as_str(), Some("apps")); // defaults file
+        assert_eq!(merged["versioningScheme"].as_str(), Some("Semver")); // base over defaults
+        assert_eq!(merged["kong"]["config_url"].as_str(), Some("https://prod")); // prod over base
+        assert_eq!(merged["kong"]["tcp_log"]["enabled"].as_bool(), Some(false)); // region over all
+        assert_eq!(merged["locations"][0].as_str(), Some("london"));
+        assert_eq!(merged["extends"].as_str(), Some("prod"));
+
+        // regions without extends only get the defaults
+        let plain = serde_yaml::from_str("name: dev-uk").unwrap();
+        let merged = layer_region(plain, &overlays, Some(&defaults)).unwrap();
+        assert_eq!(merged["versioningScheme"].as_str(), Some("GitShaOrSemver"));
+        assert!(merged["kong"].is_null());
+    }
+
+    #[cfg(feature = "filesystem")]
+    #[test]
+    fn region_overlay_errors() {
+        use super::layer_region;
+        use std::collections::BTreeMap;
+        let mut overlays = BTreeMap::new();
+        overlays.insert("a".to_string(), serde_yaml::from_str("extends: b").unwrap());
+        overlays.insert("b".to_string(), serde_yaml::from_str("extends: a").unwrap());
+
+        let cyclic = serde_yaml::from_str("name: dev-uk\nextends: a").unwrap();
+        let err = layer_region(cyclic, &overlays, None).unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+
+        let missing = serde_yaml::from_str("name: dev-uk\nextends: c").unwrap();
+        let err = layer_region(missing, &overlays, None).unwrap_err();
+        assert!(err.to_string().contains("undefined regionDefaults.c"));
+    }
+
     #[test]
     fn version_validate_test() {
         let scheme = VersionScheme::GitShaOrSemver;
@@ -509,4 +1448,31 @@ mod tests {
             .verify("e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19")
             .is_err());
     }
+
+    #[test]
+    fn cluster_capabilities_intersect() {
+        use super::ClusterCapabilities;
+        let blue: ClusterCapabilities =
+            serde_yaml::from_str("kubeVersion: 1.19.4\napiVersions: [apps/v1, autoscaling/v2beta2]").unwrap();
+        let green: ClusterCapabilities =
+            serde_yaml::from_str("kubeVersion: 1.23.1\napiVersions: [apps/v1, autoscaling/v2]").unwrap();
+        let both = blue.intersect(&green);
+        assert_eq!(both.kube_version.as_ref().unwrap().to_string(), "1.19.4");
+        assert!(both.serves("apps/v1"));
+        assert!(both.serves("v1"));
+        assert!(!both.serves("autoscaling/v2"));
+        assert!(!both.serves("autoscaling/v2beta2"));
+    }
+
+    #[test]
+    fn config_and_region_schemas() {
+        use super::{Config, Region};
+        let conf = serde_json::to_value(schemars::schema_for!(Config)).unwrap();
+        assert!(conf["properties"]["regions"].is_object());
+        assert_eq!(conf["properties"]["sensitiveEnvRegex"]["type"][0], "string");
+
+        let reg = serde_json::to_value(schemars::schema_for!(Region)).unwrap();
+        assert!(reg["properties"]["vault"].is_object());
+        assert_eq!(reg["properties"]["destinationRuleHostRegex"]["type"][0], "string");
+    }
 }
@@ -1,4 +1,7 @@
-use super::{config::ShipcatConfig, manifest::ShipcatManifest, Manifest};
+use super::{
+    config::ShipcatConfig, manifest::ShipcatManifest, queue::ShipcatRolloutTicket,
+    request::ShipcatApplyRequest, Manifest,
+};
 use crate::{config::Config, states::ManifestState};
 
 // We are < 1.17 so use v1beta1
@@ -8,7 +11,14 @@ use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1beta1 as a
 pub fn gen_all_crds() -> Vec<CustomResourceDefinition> {
     let shipcatManifest = ShipcatManifest::crd();
     let shipcatConfig = ShipcatConfig::crd();
-    vec![shipcatConfig, shipcatManifest]
+    let shipcatRolloutTicket = ShipcatRolloutTicket::crd();
+    let shipcatApplyRequest = ShipcatApplyRequest::crd();
+    vec![
+        shipcatConfig,
+        shipcatManifest,
+        shipcatRolloutTicket,
+        shipcatApplyRequest,
+    ]
 }
 
 impl From<Manifest> for ShipcatManifest {
@@ -1,3 +1,7 @@
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject},
+};
 use serde::de::{value::SeqAccessDeserializer, Deserialize, Deserializer, Error, SeqAccess, Visitor};
 use std::{fmt, marker::PhantomData};
 
@@ -40,6 +44,16 @@ where
     deserializer.deserialize_any(CommaSeparatedString(PhantomData))
 }
 
+/// Schema of fields using `comma_separated_string`
+pub fn comma_separated_string_schema(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = SchemaObject::default();
+    schema.subschemas().any_of = Some(vec![
+        gen.subschema_for::<String>(),
+        gen.subschema_for::<Vec<String>>(),
+    ]);
+    schema.into()
+}
+
 #[cfg(test)]
 mod comma_separated_string_tests {
     use super::CommaSeparatedString;
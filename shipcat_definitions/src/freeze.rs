@@ -0,0 +1,332 @@
+use super::{Environment, Region, Result};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use schemars::JsonSchema;
+
+/// Longest scheduled freeze, bounding how far back schedules are searched
+const MAX_FREEZE_MINUTES: u32 = 31 * 24 * 60;
+
+/// Change freezes enforced by `shipcat apply`
+///
+/// Applies to a frozen region fail unless given `--override-freeze REASON`,
+/// which is recorded in audit events. Besides the scheduled `windows`, ad-hoc freezes
+/// are read from the `configMap` in the namespace of the region.
+///
+/// ```yaml
+/// freezes:
+///   windows:
+///   - name: weekend
+///     schedule: "0 17 * * 5"
+///     durationMinutes: 3840
+///     environments: [prod]
+///     reason: No prod changes over the weekend
+///   - name: black-friday
+///     start: 2026-11-27T00:00:00Z
+///     end: 2026-11-30T23:59:00Z
+///     regions: [prod-uk]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct FreezeConfig {
+    /// Scheduled freezes
+    #[serde(default)]
+    pub windows: Vec<FreezeWindow>,
+    /// ConfigMap with ad-hoc freezes, one per key
+    #[serde(default = "default_freeze_configmap")]
+    pub configMap: String,
+}
+
+fn default_freeze_configmap() -> String {
+    "shipcat-freezes".into()
+}
+
+/// A scheduled freeze
+///
+/// Either recurring, starting at every time of a cron `schedule` and lasting `durationMinutes`,
+/// or a one-off freeze from `start` until `end`. Without `environments` or `regions`
+/// the freeze applies everywhere.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct FreezeWindow {
+    /// Name of the freeze
+    pub name: String,
+    /// Cron schedule of the start of the freeze in UTC
+    ///
+    /// Fields are minute, hour, day of month, month and day of week (0 is Sunday),
+    /// supporting `*`, lists, ranges and steps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// Length of a scheduled freeze
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durationMinutes: Option<u32>,
+    /// Start of a one-off freeze
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    /// End of a one-off freeze
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<DateTime<Utc>>,
+    /// Environments the freeze applies to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<Environment>,
+    /// Regions the freeze applies to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
+    /// Why changes are frozen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl FreezeWindow {
+    fn verify(&self) -> Result<()> {
+        match (&self.schedule, &self.start, &self.end) {
+            (Some(s), None, None) => {
+                Cron::parse(s)?;
+                match self.durationMinutes {
+                    Some(d) if d > 0 && d <= MAX_FREEZE_MINUTES => {}
+                    _ => bail!(
+                        "freeze {} needs a durationMinutes between 1 and {}",
+                        self.name,
+                        MAX_FREEZE_MINUTES
+                    ),
+                }
+            }
+            (None, Some(start), Some(end)) => {
+                if end <= start {
+                    bail!("freeze {} must end after it starts", self.name);
+                }
+                if self.durationMinutes.is_some() {
+                    bail!("freeze {} sets durationMinutes without a schedule", self.name);
+                }
+            }
+            _ => bail!("freeze {} needs either a schedule or a start and end", self.name),
+        }
+        Ok(())
+    }
+
+    /// Whether the freeze applies to a region
+    pub fn applies_to(&self, region: &Region) -> bool {
+        (self.environments.is_empty() && self.regions.is_empty())
+            || self.environments.contains(&region.environment)
+            || self.regions.contains(&region.name)
+    }
+
+    /// Whether the freeze is in effect at a point in time
+    pub fn active_at(&self, now: DateTime<Utc>) -> Result<bool> {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            return Ok(start <= now && now < end);
+        }
+        let (schedule, minutes) = match (&self.schedule, self.durationMinutes) {
+            (Some(s), Some(d)) => (Cron::parse(s)?, d),
+            _ => return Ok(false),
+        };
+        // any start within the duration before now
+        let minute = now.date().and_hms(now.hour(), now.minute(), 0);
+        Ok((0..minutes).any(|m| schedule.matches(minute - Duration::minutes(m.into()))))
+    }
+
+    /// Name and reason of the freeze
+    pub fn describe(&self) -> String {
+        match &self.reason {
+            Some(r) => format!("{}: {}", self.name, r),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl FreezeConfig {
+    pub fn verify(&self) -> Result<()> {
+        let mut used_names = vec![];
+        for w in &self.windows {
+            w.verify()?;
+            if used_names.contains(&w.name) {
+                bail!("Cannot reuse freeze name {}", w.name);
+            }
+            used_names.push(w.name.clone());
+        }
+        Ok(())
+    }
+
+    /// Scheduled freezes in effect in a region
+    pub fn active(&self, region: &Region, now: DateTime<Utc>) -> Result<Vec<&FreezeWindow>> {
+        let mut active = vec![];
+        for w in self.windows.iter().filter(|w| w.applies_to(region)) {
+            if w.active_at(now)? {
+                active.push(w);
+            }
+        }
+        Ok(active)
+    }
+}
+
+/// An ad-hoc freeze from a key of the freeze ConfigMap
+///
+/// Values are either a plain reason, freezing until the key is removed,
+/// or yaml with a `reason` and an `until` time.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AdhocFreeze {
+    pub reason: String,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AdhocFreeze {
+    pub fn parse(value: &str) -> AdhocFreeze {
+        serde_yaml::from_str(value).unwrap_or_else(|_| AdhocFreeze {
+            reason: value.trim().into(),
+            until: None,
+        })
+    }
+
+    pub fn active_at(&self, now: DateTime<Utc>) -> bool {
+        self.until.map_or(true, |u| now < u)
+    }
+}
+
+/// A parsed five field cron schedule
+struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    /// Whether both the day of month and the day of week are restricted
+    either_day: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Cron> {
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!("cron schedule '{}' needs 5 fields", expr);
+        }
+        let weekdays = cron_field(fields[4], 0, 7)?.into_iter().map(|d| d % 7).collect();
+        Ok(Cron {
+            minutes: cron_field(fields[0], 0, 59)?,
+            hours: cron_field(fields[1], 0, 23)?,
+            days: cron_field(fields[2], 1, 31)?,
+            months: cron_field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn matches(&self, t: DateTime<Utc>) -> bool {
+        let day = self.days.contains(&t.day());
+        let weekday = self.weekdays.contains(&t.weekday().num_days_from_sunday());
+        let day_ok = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day_ok
+            && self.minutes.contains(&t.minute())
+            && self.hours.contains(&t.hour())
+            && self.months.contains(&t.month())
+    }
+}
+
+/// Values of a cron field like `*`, `1,15`, `1-5` or `*/10`
+fn cron_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>> {
+    let mut values = vec![];
+    for part in field.split(',') {
+        let mut stepped = part.splitn(2, '/');
+        let range = stepped.next().unwrap_or_default();
+        let step = match stepped.next() {
+            Some(s) => s.parse::<usize>()?,
+            None => 1,
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else {
+            let mut bounds = range.splitn(2, '-');
+            let lo = bounds.next().unwrap_or_default().parse::<u32>()?;
+            let hi = match bounds.next() {
+                Some(h) => h.parse::<u32>()?,
+                None => lo,
+            };
+            (lo, hi)
+        };
+        if step == 0 || lo < min || hi > max || lo > hi {
+            bail!("cron field '{}' is not within {}-{}", field, min, max);
+        }
+        values.extend((lo..=hi).step_by(step));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdhocFreeze, FreezeConfig};
+    use crate::{Environment, Region};
+    use chrono::{DateTime, Utc};
+
+    fn at(t: &str) -> DateTime<Utc> {
+        t.parse().unwrap()
+    }
+
+    #[test]
+    fn freeze_windows() {
+        let cfg: FreezeConfig = serde_yaml::from_str(
+            r#"
+windows:
+- name: weekend
+  schedule: "0 17 * * 5"
+  durationMinutes: 3840
+  environments: [prod]
+  reason: No prod changes over the weekend
+- name: black-friday
+  start: 2026-11-27T00:00:00Z
+  end: 2026-11-30T00:00:00Z
+  regions: [prod-uk]
+"#,
+        )
+        .unwrap();
+        assert!(cfg.verify().is_ok());
+        assert_eq!(cfg.configMap, "shipcat-freezes");
+
+        let mut reg = Region::default();
+        reg.name = "prod-uk".into();
+        reg.environment = Environment::Prod;
+        let active = |reg: &Region, t: &str| {
+            let windows = cfg.active(reg, at(t)).unwrap();
+            windows.iter().map(|w| w.name.clone()).collect::<Vec<_>>()
+        };
+        // friday 2026-10-16 17:00 until monday 09:00
+        assert!(active(&reg, "2026-10-16T16:59:00Z").is_empty());
+        assert_eq!(active(&reg, "2026-10-16T17:00:00Z"), vec!["weekend"]);
+        assert_eq!(active(&reg, "2026-10-19T08:59:59Z"), vec!["weekend"]);
+        assert!(active(&reg, "2026-10-19T09:00:00Z").is_empty());
+        assert_eq!(active(&reg, "2026-11-28T12:00:00Z"), vec![
+            "weekend",
+            "black-friday"
+        ]);
+        assert_eq!(active(&reg, "2026-11-27T12:00:00Z"), vec!["black-friday"]);
+
+        reg.name = "dev-uk".into();
+        reg.environment = Environment::Dev;
+        assert!(active(&reg, "2026-11-28T12:00:00Z").is_empty());
+    }
+
+    #[test]
+    fn freeze_verify() {
+        let verify = |yaml: &str| serde_yaml::from_str::<FreezeConfig>(yaml).unwrap().verify();
+        assert!(verify("windows: [{name: a, schedule: '0 17 * * 5'}]").is_err()); // no duration
+        assert!(verify("windows: [{name: a, schedule: '0 25 * * *', durationMinutes: 60}]").is_err());
+        assert!(verify("windows: [{name: a, schedule: '*/15 9-17 1,15 * 1-5', durationMinutes: 5}]").is_ok());
+        assert!(verify("windows: [{name: a, start: 2026-11-27T00:00:00Z}]").is_err()); // no end
+        let twice = "windows: [{name: a, start: 2026-11-27T00:00:00Z, end: 2026-11-28T00:00:00Z}, \
+                     {name: a, schedule: '0 0 * * *', durationMinutes: 60}]";
+        assert!(verify(twice).is_err());
+    }
+
+    #[test]
+    fn freeze_adhoc() {
+        let plain = AdhocFreeze::parse("Investigating the payments outage\n");
+        assert_eq!(plain.reason, "Investigating the payments outage");
+        assert!(plain.active_at(Utc::now()));
+
+        let until = AdhocFreeze::parse("{reason: migration, until: 2026-10-17T18:00:00Z}");
+        assert_eq!(until.reason, "migration");
+        assert!(until.active_at(at("2026-10-17T17:59:00Z")));
+        assert!(!until.active_at(at("2026-10-17T18:00:00Z")));
+    }
+}
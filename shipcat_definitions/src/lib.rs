@@ -6,6 +6,7 @@
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
 #[macro_use] extern crate maplit;
+#[macro_use] extern crate lazy_static;
 
 #[macro_use] extern crate error_chain; // bail and error_chain macro
 error_chain! {
@@ -48,9 +49,9 @@ error_chain! {
             description("could not access URL")
             display("could not access URL '{}'", &url)
         }
-        InvalidTemplate(svc: String) {
+        InvalidTemplate(svc: String, tpl: String, reason: String) {
             description("invalid template")
-            display("service '{}' has invalid templates", svc)
+            display("service '{}' has an invalid template '{}': {}", svc, tpl, reason)
         }
         InvalidOneOffTemplate(tpl: String) {
             description("invalid template")
@@ -77,10 +78,18 @@ error_chain! {
 
 /// Config with regional data
 pub mod region;
-pub use crate::region::{Environment, KongConfig, ReconciliationMode, Region, VaultConfig, VersionScheme};
+pub use crate::region::{
+    Environment, KongConfig, ReconciliationMode, Region, RolloutQueueConfig, SecretStoreConfig, VaultConfig,
+    VaultEngine, VersionScheme,
+};
 /// Master config with cross-region data
 pub mod config;
-pub use crate::config::{Cluster, Config, ConfigFallback, ShipcatConfig};
+pub use crate::config::{
+    ApplyHook, ApplyHookStage, Cluster, ClusterCapabilities, Config, ConfigFallback, GitopsConfig,
+    IdentitySource, LintCheck, LintRule, LintSeverity, OidcConfig, PermissionsConfig, PriceSheet,
+    PricingConfig, RegionRewrite, RegistryConfig, RegistryKind, RosterConfig, RosterKind, ShipcatConfig,
+    TelemetryConfig, ValidationPlugin, DEFAULT_SENSITIVE_ENV, DEFAULT_UPGRADE_TEMPLATE,
+};
 
 /// Structs for the manifest
 pub mod structs;
@@ -98,6 +107,21 @@ pub mod teams;
 mod crds;
 pub use crate::crds::gen_all_crds;
 
+/// Rollout queue tickets
+pub mod queue;
+pub use crate::queue::ShipcatRolloutTicket;
+
+/// Change freeze windows
+pub mod freeze;
+pub use crate::freeze::FreezeConfig;
+
+/// Applies requested through raftcat
+pub mod request;
+pub use crate::request::ShipcatApplyRequest;
+
+/// Strimzi kafka resources as found in the cluster
+pub mod strimzi;
+
 /// Status objects
 pub mod status;
 pub use status::ManifestStatus;
@@ -118,4 +142,15 @@ pub mod template;
 pub mod vault;
 pub use crate::vault::Vault;
 
+/// Secret backends resolving `IN_VAULT` and `IN_SECRETSTORE` placeholders
+pub mod secretstore;
+pub use crate::secretstore::SecretBackend;
+
+/// Encrypted on-disk cache of secrets
+#[cfg(feature = "filesystem")]
+pub mod secretcache;
+
+/// Sentry project provisioning for `FROM_SENTRY` env vars
+pub mod sentry;
+
 pub mod deserializers;
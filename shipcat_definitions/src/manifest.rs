@@ -1,12 +1,15 @@
-use crate::vault::Vault;
+use crate::secretstore::{self, SecretBackend};
+use futures::stream::{self, StreamExt};
 use kube_derive::CustomResource;
 use regex::Regex;
+use semver::Version;
 use std::collections::{BTreeMap, BTreeSet};
 
 use super::Result;
 use crate::{
     config::Config,
     region::{Region, VaultConfig},
+    sentry::{self, FROM_SENTRY},
     states::{ManifestState, PrimaryWorkload},
     ManifestStatus,
 };
@@ -14,14 +17,18 @@ use crate::{
 // All structs come from the structs directory
 use super::structs::{
     autoscaling::AutoScaling,
+    mtls::SIDECAR_INJECT_KEY,
     newrelic::Newrelic,
     security::DataHandling,
     sentry::Sentry,
+    serviceaccount::AWS_ROLE_ARN_KEY,
     tolerations::Tolerations,
     volume::{Volume, VolumeMount},
-    ConfigMap, Container, CronJob, Dependency, DestinationRule, EnvVars, EventStream, Gate, HealthCheck,
-    HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode, PersistentVolume, Port,
-    Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate, SecurityContext, VaultOpts, Worker,
+    Canary, ConfigMap, Container, CronJob, Dependency, DestinationRule, DisabledRegion, EnvVars, EventStream,
+    Gate, HealthCheck, HostAlias, InfraDependency, Kafka, KafkaResources, Kong, LifeCycle, Metadata, Mtls,
+    MtlsMode, NetworkPolicyMode, NotificationMode, PersistentVolume, PodDisruptionBudget, Port, Probe,
+    PrometheusAlert, PrometheusRecordingRule, Rbac, ResourceRequirements, RollingUpdate, SecurityContext,
+    ServiceAccount, ServiceOptions, Slo, SmokeTests, VaultOpts, Worker,
 };
 
 /// Main manifest, serializable from manifest.yml or the shipcat CRD.
@@ -35,6 +42,7 @@ use super::structs::{
     status = "ManifestStatus",
     printcolumn = r#"{"name":"Kong", "jsonPath": ".spec.kong_apis[*].uris", "type": "string", "description": "The URI where the service is available through kong"}"#,
     printcolumn = r#"{"name":"Version", "jsonPath": ".spec.version", "type": "string", "description": "The version of the service that is deployed"}"#,
+    printcolumn = r#"{"name":"Sidecars", "jsonPath": ".spec.sidecars[*].version", "type": "string", "description": "The versions of the sidecars that are deployed"}"#,
     printcolumn = r#"{"name":"Team", "jsonPath": ".spec.metadata.team", "type": "string", "description": "The team that owns the service"}"#
 )]
 #[kube(apiextensions = "v1beta1")] // kubernetes < 1.16
@@ -105,6 +113,21 @@ pub struct Manifest {
     #[serde(default, skip_serializing)]
     pub regions: Vec<String>,
 
+    /// Regions this service is temporarily disabled in
+    ///
+    /// Unlike removing a region from `regions`, this keeps the region listed,
+    /// but excludes the service from reconciles in that region.
+    /// Validation fails once the `until` date has passed.
+    ///
+    /// ```yaml
+    /// disabledIn:
+    /// - region: dev-uk
+    ///   reason: "Waiting for new database cluster"
+    ///   until: 2020-06-01
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabledIn: Vec<DisabledRegion>,
+
     /// Important contacts and other metadata for the service
     ///
     /// Particular uses:
@@ -139,6 +162,13 @@ pub struct Manifest {
     /// ```yaml
     /// chart: custom
     /// ```
+    ///
+    /// Local charts can be pinned to the `version` in their `Chart.yaml`,
+    /// so that checkouts with other versions of the chart refuse to template the service:
+    ///
+    /// ```yaml
+    /// chart: base@2.3.1
+    /// ```
     #[serde(default)]
     pub chart: Option<String>,
 
@@ -382,6 +412,44 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<Dependency>,
 
+    /// Infrastructure dependencies
+    ///
+    /// Databases and brokers the service connects to, checkable from inside the cluster
+    /// with `shipcat check-connectivity`.
+    ///
+    /// ```yaml
+    /// infraDependencies:
+    /// - kind: postgres
+    ///   env: DATABASE_URL
+    /// - kind: redis
+    ///   baseUrl: redis
+    ///   tls: true
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub infraDependencies: Vec<InfraDependency>,
+
+    /// Restrict egress to what the service declares
+    ///
+    /// Generates an egress policy only allowing DNS, the service's `dependencies`
+    /// and `infraDependencies`, and the region's `egress.allowed` endpoints.
+    ///
+    /// ```yaml
+    /// strictEgress: true
+    /// ```
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strictEgress: bool,
+
+    /// Restrict ingress to the services that depend on this one
+    ///
+    /// With `generated`, a NetworkPolicy only admits traffic from services listing this
+    /// service in their `dependencies`, and from the region's `networkPolicy.allowedNamespaces`.
+    ///
+    /// ```yaml
+    /// networkPolicies: generated
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networkPolicies: Option<NetworkPolicyMode>,
+
     /// Destination Rules
     ///
     /// The intention here is that implementations will examine requests to determine if they
@@ -399,6 +467,20 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub destinationRules: Option<Vec<DestinationRule>>,
 
+    /// Mutual TLS policy for traffic to the service
+    ///
+    /// Renders a PeerAuthentication and a DestinationRule for the istio mesh.
+    /// Services depending on a service in `strict` mode must also run the mesh sidecar,
+    /// either through their own `mtls`, or a `sidecar.istio.io/inject: "true"` pod annotation or label.
+    ///
+    /// ```yaml
+    /// mtls:
+    ///   mode: strict
+    ///   excludedPorts: [9102]
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtls: Option<Mtls>,
+
     /// Worker `Deployment` objects to additionally include
     ///
     /// These are more flexible than `sidecars`, because they scale independently of
@@ -511,9 +593,26 @@ pub struct Manifest {
     ///       name: cpu
     ///       targetAverageUtilization: 60
     /// ```
+    ///
+    /// Scaling rates can be limited with `behavior`, and event driven scaling through
+    /// `keda` renders a KEDA `ScaledObject` instead of the HPA.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub autoScaling: Option<AutoScaling>,
 
+    /// `PodDisruptionBudget` parameters for kubernetes
+    ///
+    /// Limits how many pods of the main workload node drains can evict at once.
+    /// Straight from [kubernetes disruption budgets](https://kubernetes.io/docs/concepts/workloads/pods/disruptions/).
+    /// Exactly one of `minAvailable` or `maxUnavailable` must be set.
+    /// Defaults to a budget derived from `replicaCount` or `autoScaling` when not set.
+    ///
+    /// ```yaml
+    /// podDisruptionBudget:
+    ///   minAvailable: 50%
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podDisruptionBudget: Option<PodDisruptionBudget>,
+
     /// Toleration parameters for kubernetes
     ///
     /// Bind a service to a particular type of kube `Node`.
@@ -631,6 +730,21 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub serviceAnnotations: BTreeMap<String, String>,
 
+    /// Traffic policy for the `Service` object
+    ///
+    /// Session affinity, external traffic policy and load balancer connection draining.
+    /// Use these rather than the equivalent `serviceAnnotations`.
+    ///
+    /// ```yaml
+    /// service:
+    ///   type: LoadBalancer
+    ///   sessionAffinity: ClientIP
+    ///   externalTrafficPolicy: Local
+    ///   drainTimeoutSeconds: 30
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<ServiceOptions>,
+
     /// Metadata Annotations for pod spec templates in deployments, and cron jobs
     ///
     /// https://kubernetes.io/docs/concepts/overview/working-with-objects/annotations/
@@ -711,6 +825,18 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub rbac: Vec<Rbac>,
 
+    /// Kubernetes ServiceAccount of the pods
+    ///
+    /// Binds the pods to a cloud identity through IRSA or workload identity.
+    /// Identities must be allowed by the region's `workloadIdentity`.
+    ///
+    /// ```yaml
+    /// serviceAccount:
+    ///   roleArn: arn:aws:iam::123456789012:role/orders
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serviceAccount: Option<ServiceAccount>,
+
     /// Kafka / EventStream configuration
     ///
     /// A list of resources that will interact with the Kafka-operator CRD /
@@ -809,6 +935,53 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub upgradeNotifications: Option<NotificationMode>,
 
+    /// Service level objectives
+    ///
+    /// Generates burn rate alerts into `prometheusAlerts`,
+    /// and the recording rules they need into `sloRecordingRules`.
+    ///
+    /// ```yaml
+    /// slos:
+    ///   availability: 99.9
+    ///   window: 30d
+    ///   latency:
+    ///   - percentile: 99
+    ///     threshold: 500ms
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slos: Option<Slo>,
+
+    /// Smoke tests run after a successful rollout
+    ///
+    /// Failing smoke tests fail the apply, and roll the workload back with `rollback`.
+    ///
+    /// ```yaml
+    /// smokeTests:
+    ///   rollback: true
+    ///   tests:
+    ///   - name: health
+    ///     http:
+    ///       path: /health
+    ///       body: ok
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smokeTests: Option<SmokeTests>,
+
+    /// Raw chart values passed through to the chart
+    ///
+    /// An escape hatch for chart values shipcat does not model.
+    /// These end up under the `chartValues` key in the helm values,
+    /// and every path must be allowed for the environment via `allowedChartValues` in the config.
+    ///
+    /// ```yaml
+    /// chartValues:
+    ///   ingress:
+    ///     annotations:
+    ///       nginx.ingress.kubernetes.io/proxy-body-size: 8m
+    /// ```
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub chartValues: BTreeMap<String, serde_yaml::Value>,
+
     // ------------------------------------------------------------------------
     // Output variables
     //
@@ -850,6 +1023,14 @@ pub struct Manifest {
     )]
     pub uid: Option<String>,
 
+    /// Active canary injected into the helm chart
+    ///
+    /// Set from the shipcatmanifest status while a canary is active.
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub canary: Option<Canary>,
+
     /// Raw secrets from environment variables.
     ///
     /// The `env` map fills in secrets in this via the `vault` client.
@@ -874,6 +1055,8 @@ pub struct Manifest {
     /// ```yaml
     /// workload: Statefulset
     /// ```
+    ///
+    /// A `DaemonSet` runs a pod on every node, so it cannot set `replicaCount` or `autoScaling`.
     #[serde(default)]
     pub workload: PrimaryWorkload,
 
@@ -890,6 +1073,34 @@ pub struct Manifest {
     /// ```
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub prometheusAlerts: Vec<PrometheusAlert>,
+
+    /// Prometheus recording rules generated from `slos`
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub sloRecordingRules: Vec<PrometheusRecordingRule>,
+}
+
+/// Number of secrets read from the secret store at the same time
+const SECRET_READ_CONCURRENCY: usize = 8;
+
+/// Read the secrets `keys` under a folder of the secret store concurrently
+async fn read_secrets(
+    client: &dyn SecretBackend,
+    folder: &str,
+    keys: BTreeSet<String>,
+) -> Result<Vec<(String, String)>> {
+    stream::iter(keys)
+        .map(|k| async move {
+            let key = format!("{}/{}", folder, k);
+            client.read(&key).await.map(|v| (k, v))
+        })
+        .buffer_unordered(SECRET_READ_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
 }
 
 impl Manifest {
@@ -916,6 +1127,30 @@ impl Manifest {
         Ok(self)
     }
 
+    /// The temporary disable entry for the current region (if any)
+    pub fn disabled_in_region(&self) -> Option<&DisabledRegion> {
+        self.disabledIn.iter().find(|d| d.region == self.region)
+    }
+
+    /// Whether the pods run the istio mesh sidecar
+    ///
+    /// An explicit `sidecar.istio.io/inject` pod annotation or label wins over `mtls`.
+    pub fn uses_mesh_sidecar(&self) -> bool {
+        let explicit = self
+            .podAnnotations
+            .get(SIDECAR_INJECT_KEY)
+            .or_else(|| self.labels.get(SIDECAR_INJECT_KEY));
+        match explicit {
+            Some(v) => v == "true",
+            None => self.mtls.is_some(),
+        }
+    }
+
+    /// Whether the service only accepts mutual TLS traffic
+    pub fn requires_mtls(&self) -> bool {
+        self.mtls.as_ref().map_or(false, |m| m.mode == MtlsMode::Strict)
+    }
+
     /// Verifies the "destinationRules" manifest entries if they are configured
     ///
     /// It is erroneous to define destination rules without configuring the corresponding region's
@@ -933,6 +1168,71 @@ impl Manifest {
         Ok(())
     }
 
+    /// Verifies the "chartValues" manifest entries against the config allowlist
+    ///
+    /// Every leaf path (dot separated) must be equal to, or nested under,
+    /// an allowed path for the region's environment.
+    pub fn verify_chart_values(&self, conf: &Config, region: &Region) -> Result<()> {
+        if self.chartValues.is_empty() {
+            return Ok(());
+        }
+        let allowed = conf
+            .allowedChartValues
+            .get(&region.environment)
+            .cloned()
+            .unwrap_or_default();
+        let mut paths = vec![];
+        for (k, v) in &self.chartValues {
+            collect_value_paths(k.clone(), v, &mut paths);
+        }
+        for p in paths {
+            let permitted = allowed
+                .iter()
+                .any(|a| &p == a || p.starts_with(&format!("{}.", a)));
+            if !permitted {
+                bail!(
+                    "chartValues path {} is not allowed in {} environments",
+                    p,
+                    region.environment.to_string()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies a pinned chart version against the region's allowed chart versions
+    pub fn verify_chart_pin(&self, region: &Region) -> Result<()> {
+        let (name, pin) = match &self.chart {
+            Some(c) => split_chart(c),
+            None => return Ok(()),
+        };
+        if let Some(v) = pin {
+            if Version::parse(v).is_err() {
+                bail!("chart {} is pinned to an invalid version {}", name, v);
+            }
+            if let Some(allowed) = region.allowedChartVersions.get(name) {
+                if !allowed.iter().any(|a| a == v) {
+                    bail!("chart {}@{} is not allowed in {}", name, v, region.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies that a DaemonSet leaves its scaling to the nodes
+    pub fn verify_daemonset(&self) -> Result<()> {
+        if self.replicaCount.is_some() {
+            bail!("DaemonSet {} cannot set replicaCount", self.name);
+        }
+        if self.autoScaling.is_some() {
+            bail!("DaemonSet {} cannot set autoScaling", self.name);
+        }
+        if self.podDisruptionBudget.is_some() {
+            bail!("DaemonSet {} cannot set a podDisruptionBudget", self.name);
+        }
+        Ok(())
+    }
+
     /// Verify assumptions about manifest
     ///
     /// Assumes the manifest has been populated with `implicits`
@@ -949,6 +1249,13 @@ impl Manifest {
         }
 
         self.verify_destination_rules(region)?;
+        self.verify_chart_values(conf, region)?;
+        self.verify_chart_pin(region)?;
+
+        // disables of other regions are reported by regional validation
+        if let Some(d) = self.disabled_in_region() {
+            d.verify(&self.name, &self.regions)?;
+        }
 
         // TODO: remove?
         if let Some(ref dh) = self.dataHandling {
@@ -979,6 +1286,20 @@ impl Manifest {
                 bail!("[Migration plan] `publiclyAccessible` and `gate.public` must be equal");
             }
         }
+        for w in &self.workers {
+            if w.gate.is_some() && w.kong.is_none() {
+                bail!(
+                    "Worker {} can't have a `gate` configuration without a `kong` one",
+                    w.container.name
+                );
+            }
+            if w.kong.is_some() && w.httpPort.is_none() {
+                bail!(
+                    "Worker {} needs an httpPort to be exposed through kong",
+                    w.container.name
+                );
+            }
+        }
 
         // run the `Verify` trait on all imported structs
         // mandatory structs first
@@ -992,7 +1313,30 @@ impl Manifest {
         for d in &self.dependencies {
             d.verify()?;
         }
+        let mut infra_names = BTreeSet::new();
+        for d in &self.infraDependencies {
+            d.verify(&self.env, region)?;
+            if !infra_names.insert(d.name()) {
+                bail!(
+                    "infraDependencies has more than one dependency named {}",
+                    d.name()
+                );
+            }
+        }
 
+        if let Some(a) = &self.autoScaling {
+            a.verify()?;
+        }
+        if let Some(m) = &self.mtls {
+            let ports = self.httpPort.iter().chain(self.ports.iter().map(|p| &p.port));
+            m.verify(&ports.cloned().collect::<Vec<_>>())?;
+            if !self.uses_mesh_sidecar() {
+                bail!(
+                    "mtls needs the mesh sidecar, but {} disables it",
+                    SIDECAR_INJECT_KEY
+                );
+            }
+        }
         for ha in &self.hostAliases {
             ha.verify()?;
         }
@@ -1002,6 +1346,15 @@ impl Manifest {
         for r in &self.rbac {
             r.verify()?;
         }
+        if let Some(sa) = &self.serviceAccount {
+            sa.verify(region)?;
+        }
+        if self.podAnnotations.contains_key(AWS_ROLE_ARN_KEY) {
+            bail!(
+                "{} is a ServiceAccount annotation, use serviceAccount.roleArn instead",
+                AWS_ROLE_ARN_KEY
+            );
+        }
         for pv in &self.persistentVolumes {
             pv.verify()?;
         }
@@ -1022,15 +1375,50 @@ impl Manifest {
         for pa in &self.prometheusAlerts {
             pa.verify(&self.name)?;
         }
+        if let Some(slo) = &self.slos {
+            slo.verify(&self.name)?;
+        }
+        if let Some(st) = &self.smokeTests {
+            st.verify(&self.name, self.httpPort.is_some(), !self.kongApis.is_empty())?;
+        }
         // misc minor properties
-        if self.replicaCount.unwrap() == 0 {
+        if let PrimaryWorkload::DaemonSet = self.workload {
+            self.verify_daemonset()?;
+        } else if self.replicaCount.unwrap() == 0 {
             bail!("Need replicaCount to be at least 1");
         }
-        if let Some(ref ru) = &self.rollingUpdate {
-            ru.verify(self.replicaCount.unwrap())?;
+        if let (Some(ru), Some(replicas)) = (&self.rollingUpdate, self.replicaCount) {
+            ru.verify(replicas)?;
+        }
+        if let Some(pdb) = &self.podDisruptionBudget {
+            let replicas = match &self.autoScaling {
+                Some(hpa) => hpa.minReplicas,
+                None => self.replicaCount.unwrap(),
+            };
+            pdb.verify(replicas, self.rollingUpdate.as_ref())?;
+        }
+        if let Some(svc) = &self.service {
+            svc.verify(self.httpPort, &self.serviceAnnotations)?;
         }
 
         self.env.verify()?;
+        let uses_sentry = self
+            .sidecars
+            .iter()
+            .map(|s| &s.env)
+            .chain(self.workers.iter().map(|w| &w.container.env))
+            .chain(self.cronJobs.iter().map(|c| &c.container.env))
+            .chain(self.initContainers.iter().map(|i| &i.env))
+            .chain(std::iter::once(&self.env))
+            .any(|e| e.plain.values().any(|v| v == FROM_SENTRY));
+        if uses_sentry && region.sentry.is_none() {
+            bail!(
+                "{} uses {} but {} has no sentry config",
+                self.name,
+                FROM_SENTRY,
+                region.name
+            );
+        }
 
         // internal errors - implicits set these!
         if self.image.is_none() {
@@ -1073,6 +1461,11 @@ impl Manifest {
         format!("{}/{}", reg, svc)
     }
 
+    /// Key of one of the service's secrets in the region's secret store
+    pub fn secret_key(&self, vc: &VaultConfig, name: &str) -> String {
+        format!("{}/{}", self.get_vault_path(vc), name)
+    }
+
     // Get EnvVars for all containers, workers etc. for this Manifest.
     pub fn get_env_vars(&mut self) -> Vec<&mut EnvVars> {
         let mut envs = Vec::new();
@@ -1092,13 +1485,36 @@ impl Manifest {
         envs
     }
 
-    /// Populate placeholder fields with secrets from vault
+    /// Populate `FROM_SENTRY` env vars with the DSN of the service's Sentry project
+    ///
+    /// Projects are created when missing. With `--skip-sentry` the env vars are dropped instead.
+    pub async fn sentry_dsns(&mut self, client: &dyn SecretBackend, reg: &Region) -> Result<()> {
+        let skip = sentry::skipped();
+        let mut keys = BTreeSet::new();
+        for e in &mut self.get_env_vars() {
+            if skip {
+                let plain = std::mem::take(&mut e.plain);
+                e.plain = plain.into_iter().filter(|(_, v)| v != FROM_SENTRY).collect();
+            } else {
+                keys.append(&mut e.sentry_secrets());
+            }
+        }
+        if skip || keys.is_empty() {
+            return Ok(());
+        }
+        let dsn = sentry::resolve_dsn(&self.name, reg, client).await?;
+        for k in keys {
+            self.secrets.insert(k, dsn.clone());
+        }
+        Ok(())
+    }
+
+    /// Populate placeholder fields with secrets from the region's secret store
     ///
-    /// This will use the HTTP api of Vault using the configuration parameters
-    /// in the `Config`.
-    pub async fn secrets(&mut self, client: &Vault, vc: &VaultConfig) -> Result<()> {
+    /// Secrets are read from the `vault.folder` of the region in either backend.
+    pub async fn secrets(&mut self, client: &dyn SecretBackend, vc: &VaultConfig) -> Result<()> {
         let pth = self.get_vault_path(vc);
-        debug!("Injecting secrets from vault {} ({:?})", pth, client.mode());
+        debug!("Injecting secrets from {} ({:?})", pth, client.mode());
 
         let mut vault_secrets = BTreeSet::new();
         let mut template_secrets = BTreeMap::new();
@@ -1123,19 +1539,23 @@ impl Manifest {
         }
 
         // Lookup values for each secret in vault.
-        for k in vault_secrets {
-            let vkey = format!("{}/{}", pth, k);
-            self.secrets.insert(k.to_string(), client.read(&vkey).await?);
+        for (k, v) in read_secrets(client, &pth, vault_secrets).await? {
+            self.secrets.insert(k, v);
         }
 
         self.secrets.append(&mut template_secrets);
 
         // do the same for secret secrets
-        for (k, v) in &mut self.secretFiles {
-            if v == "IN_VAULT" {
-                let vkey = format!("{}/{}", pth, k);
-                *v = client.read(&vkey).await?;
-            }
+        let placeholders = self
+            .secretFiles
+            .iter()
+            .filter(|(_, v)| secretstore::is_placeholder(v))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for (k, v) in read_secrets(client, &pth, placeholders).await? {
+            self.secretFiles.insert(k, v);
+        }
+        for (k, v) in &self.secretFiles {
             // sanity check; secretFiles are assumed base64 verify we can decode
             if base64::decode(v).is_err() {
                 bail!("Secret {} is not base64 encoded", k);
@@ -1153,10 +1573,23 @@ impl Manifest {
             secrets.push(s.clone());
             secrets.push(base64::encode(s));
         }
+        // config files with secrets can end up base64 encoded in Secret objects
+        if let Some(cfg) = &self.configs {
+            for f in cfg.files.iter().filter(|f| f.secret) {
+                secrets.extend(f.value.as_ref().map(base64::encode));
+            }
+        }
         secrets
     }
 
-    pub async fn verify_secrets_exist(&self, vc: &VaultConfig) -> Result<()> {
+    /// Config map keys of config files templated with secrets
+    ///
+    /// Lets diffs mask config files that interpolate secrets.
+    pub fn secret_config_keys(&self) -> Vec<String> {
+        self.configs.as_ref().map(|c| c.secret_keys()).unwrap_or_default()
+    }
+
+    pub async fn verify_secrets_exist(&self, reg: &Region) -> Result<()> {
         use std::collections::HashSet;
         // what are we requesting
         // TODO: Use envvars directly
@@ -1165,14 +1598,14 @@ impl Manifest {
             .plain
             .clone()
             .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
+            .filter(|(_, v)| secretstore::is_placeholder(v))
             .map(|(k, _)| k)
             .collect::<HashSet<_>>();
         let files = self
             .secretFiles
             .clone()
             .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
+            .filter(|(_, v)| secretstore::is_placeholder(v))
             .map(|(k, _)| k)
             .collect::<HashSet<_>>();
         let expected = keys.union(&files).cloned().collect::<HashSet<_>>();
@@ -1181,8 +1614,8 @@ impl Manifest {
         }
 
         // what we have
-        let v = Vault::regional(vc)?;
-        let secpth = self.get_vault_path(vc);
+        let v = reg.secret_store()?;
+        let secpth = self.get_vault_path(&reg.vault);
 
         // list secrets; fail immediately if folder is empty
         let found = match v.list(&secpth).await {
@@ -1199,12 +1632,38 @@ impl Manifest {
         // compare sets
         let missing = expected.difference(&found).collect::<Vec<_>>();
         if !missing.is_empty() {
-            bail!("Missing secrets: {:?} not found in vault {}", missing, secpth);
+            bail!("Missing secrets: {:?} not found in {}", missing, secpth);
         }
         Ok(())
     }
 }
 
+/// Collect dot separated paths to all leaf values in a yaml value
+fn collect_value_paths(prefix: String, value: &serde_yaml::Value, paths: &mut Vec<String>) {
+    match value {
+        serde_yaml::Value::Mapping(m) if !m.is_empty() => {
+            for (k, v) in m {
+                let key = k.as_str().map(String::from).unwrap_or_else(|| format!("{:?}", k));
+                collect_value_paths(format!("{}.{}", prefix, key), v, paths);
+            }
+        }
+        _ => paths.push(prefix),
+    }
+}
+
+/// Split a `chart` into its name and pinned version
+///
+/// Local charts are pinned as `name@version`. Git charts carry their own `?ref=` and are never split.
+pub fn split_chart(chart: &str) -> (&str, Option<&str>) {
+    if chart.starts_with("git@") {
+        return (chart, None);
+    }
+    match chart.find('@') {
+        Some(idx) => (&chart[..idx], Some(&chart[idx + 1..])),
+        None => (chart, None),
+    }
+}
+
 // Cross-crate test manifest creator
 impl Manifest {
     pub fn test(name: &str) -> Manifest {
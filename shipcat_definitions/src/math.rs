@@ -1,12 +1,17 @@
 use super::{
     structs::{rollingupdate::RollingUpdate, ResourceRequirements},
-    Manifest, Result,
+    Manifest, PriceSheet, PrimaryWorkload, Result,
 };
+use schemars::JsonSchema;
+use std::ops::AddAssign;
+
+/// Hours in an average month, as billed by cloud providers
+pub const HOURS_PER_MONTH: f64 = 730.0;
 
 /// Total resource usage for a Manifest
 ///
 /// Accounting for workers, replicas, sidecars, and autoscaling policies for these.
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, JsonSchema)]
 pub struct ResourceTotals {
     /// Sum of basic resource structs (ignoring autoscaling limits)
     pub base: ResourceRequirements<f64>,
@@ -22,6 +27,19 @@ impl ResourceTotals {
         self
     }
 
+    /// Monthly cost of the requests at the given prices
+    ///
+    /// Uses the autoscaling ceilings when `upper_bounds` is set.
+    pub fn monthly_cost(&self, prices: &PriceSheet, upper_bounds: bool) -> f64 {
+        let (mut cpu, mut memory) = (self.base.requests.cpu, self.base.requests.memory);
+        if upper_bounds {
+            cpu += self.extra.requests.cpu;
+            memory += self.extra.requests.memory;
+        }
+        let gib = memory / (1024.0 * 1024.0 * 1024.0);
+        (cpu * prices.cpuHour + gib * prices.memoryGibHour) * HOURS_PER_MONTH
+    }
+
     /// Compute daily cost lower + upper bounds based on instance cost
     ///
     /// Assumes the resource totals have been normalise first!
@@ -49,10 +67,63 @@ impl ResourceTotals {
     }
 }
 
+/// Resource usage of a Manifest split by the workloads running it
+///
+/// Sums up to the `ResourceTotals` of the manifest, plus cronjobs and autoscaled sidecars.
+#[derive(Serialize, Default)]
+pub struct WorkloadTotals {
+    /// The main deployment or statefulset
+    pub main: ResourceTotals,
+    /// Worker deployments
+    pub workers: ResourceTotals,
+    /// A single run of every cronjob
+    pub cronjobs: ResourceTotals,
+    /// Sidecars of the main workload and every worker
+    pub sidecars: ResourceTotals,
+}
+
+impl AddAssign for ResourceTotals {
+    fn add_assign(&mut self, other: Self) {
+        self.base += other.base;
+        self.extra += other.extra;
+    }
+}
+
+impl AddAssign for WorkloadTotals {
+    fn add_assign(&mut self, other: Self) {
+        self.main += other.main;
+        self.workers += other.workers;
+        self.cronjobs += other.cronjobs;
+        self.sidecars += other.sidecars;
+    }
+}
+
+impl WorkloadTotals {
+    /// Sum of every workload type
+    pub fn total(&self) -> ResourceTotals {
+        let mut res = ResourceTotals::default();
+        for w in &[&self.main, &self.workers, &self.cronjobs, &self.sidecars] {
+            res.base += w.base.clone();
+            res.extra += w.extra.clone();
+        }
+        res
+    }
+}
+
 /// Calculations done based on values in manifests
 ///
 /// These generally assume that `verify` has passed on all manifests.
 impl Manifest {
+    /// Replicas of the main workload when it is not autoscaled
+    ///
+    /// DaemonSets count as a single replica, as the number of nodes is not known here.
+    fn fixed_replicas(&self) -> Option<u32> {
+        match self.workload {
+            PrimaryWorkload::DaemonSet => Some(1),
+            _ => self.replicaCount,
+        }
+    }
+
     /// Compute minimum replicas
     ///
     /// Used to `estimate_rollout_iterations` for a rollout.
@@ -60,7 +131,7 @@ impl Manifest {
         if let Some(ref hpa) = self.autoScaling {
             hpa.minReplicas
         } else {
-            self.replicaCount.unwrap() // verify ensures we have one of these
+            self.fixed_replicas().unwrap() // verify ensures we have one of these
         }
     }
 
@@ -80,11 +151,24 @@ impl Manifest {
     ///
     /// Was used by helm, now used by the internal upgrade wait time.
     pub fn estimate_wait_time(&self) -> u32 {
+        self.estimate_wait_time_with_pull(None)
+    }
+
+    /// Estimate how long to wait for a kube rolling upgrade given a previous image pull time
+    ///
+    /// A pull time observed during an earlier rollout replaces the `imageSize` guess.
+    pub fn estimate_wait_time_with_pull(&self, pull_seconds: Option<u32>) -> u32 {
         // TODO: handle install case elsewhere..
-        if let Some(size) = self.imageSize {
+        let pulltimeestimate = match pull_seconds {
+            // same leeway as the readiness delay, as pulls vary between nodes
+            Some(pull) => Some(std::cmp::max(60, (f64::from(pull) * 1.5).ceil() as u32)),
             // 512 default => extra 90s wait, then 90s per half gig...
             // TODO: smoothen..
-            let pulltimeestimate = std::cmp::max(60, ((f64::from(size) * 90.0) / 512.0) as u32);
+            None => self
+                .imageSize
+                .map(|size| std::cmp::max(60, ((f64::from(size) * 90.0) / 512.0) as u32)),
+        };
+        if let Some(pulltimeestimate) = pulltimeestimate {
             let rollout_iterations = self.estimate_rollout_iterations();
             // println!("estimating wait for {} cycle rollout: size={} (est={})", rollout_iterations, size, pulltimeestimate);
 
@@ -119,7 +203,7 @@ impl Manifest {
         if let Some(ref ascale) = self.autoScaling {
             base += res.clone() * ascale.minReplicas;
             extra += res * (ascale.maxReplicas - ascale.minReplicas);
-        } else if let Some(rc) = self.replicaCount {
+        } else if let Some(rc) = self.fixed_replicas() {
             // can trust the replicaCount here
             base += res * rc;
             for s in &self.sidecars {
@@ -149,12 +233,55 @@ impl Manifest {
         }
         Ok(ResourceTotals { base, extra })
     }
+
+    /// Compute the resource usage of a service per workload type
+    ///
+    /// Unlike `compute_resource_totals`, sidecars follow the autoscaling of the main workload,
+    /// so the overhead of sidecars is visible at the upper bounds as well.
+    pub fn compute_workload_totals(&self) -> Result<WorkloadTotals> {
+        let mut res = WorkloadTotals::default();
+        let main = self.resources.clone().unwrap().normalised()?; // exists by verify
+        let mut sidecars: ResourceRequirements<f64> = ResourceRequirements::default();
+        for s in &self.sidecars {
+            if let Some(ref scrsc) = s.resources {
+                sidecars += scrsc.normalised()?;
+            }
+        }
+        if let Some(ref ascale) = self.autoScaling {
+            let extra = ascale.maxReplicas - ascale.minReplicas;
+            res.main.base += main.clone() * ascale.minReplicas;
+            res.main.extra += main * extra;
+            res.sidecars.base += sidecars.clone() * ascale.minReplicas;
+            res.sidecars.extra += sidecars.clone() * extra;
+        } else if let Some(rc) = self.fixed_replicas() {
+            res.main.base += main * rc;
+            res.sidecars.base += sidecars.clone() * rc;
+        } else {
+            bail!("{} does not have replicaCount", self.name);
+        }
+        for w in &self.workers {
+            if let Some(resources) = &w.container.resources {
+                res.workers.base += resources.normalised()? * w.replicaCount;
+            }
+            // NB: workers get the same sidecars!
+            res.sidecars.base += sidecars.clone() * w.replicaCount;
+        }
+        for cj in &self.cronJobs {
+            if let Some(resources) = &cj.container.resources {
+                res.cronjobs.base += resources.normalised()?;
+            }
+        }
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Manifest;
-    use crate::structs::HealthCheck;
+    use crate::{
+        structs::{resources::Resources, Container, CronJob, HealthCheck, ResourceRequirements},
+        PriceSheet, PrimaryWorkload,
+    };
 
     #[test]
     fn mf_wait_time_check() {
@@ -192,5 +319,100 @@ mod tests {
         });
         mf.replicaCount = Some(1);
         assert_eq!(mf.estimate_wait_time(), 990); // lots of leeway here just in case
+
+        // observed pulls replace the size guess
+        mf.health = Some(HealthCheck {
+            uri: "/".into(),
+            wait: 60,
+            ..Default::default()
+        });
+        assert_eq!(mf.estimate_wait_time_with_pull(Some(200)), 390); // 60*1.5 + 200*1.5
+        assert_eq!(mf.estimate_wait_time_with_pull(Some(10)), 150); // at least 60s to pull
+        mf.imageSize = None;
+        assert_eq!(mf.estimate_wait_time_with_pull(Some(200)), 390);
+        assert_eq!(mf.estimate_wait_time(), 300);
+    }
+
+    fn cpu_request(cpu: &str) -> ResourceRequirements<String> {
+        ResourceRequirements {
+            requests: Resources {
+                cpu: cpu.into(),
+                memory: "1G".into(),
+            },
+            limits: Resources {
+                cpu: cpu.into(),
+                memory: "1G".into(),
+            },
+        }
+    }
+
+    #[test]
+    fn mf_workload_totals() {
+        let mut mf = Manifest::default();
+        mf.resources = Some(cpu_request("1"));
+        mf.replicaCount = Some(2);
+        mf.sidecars = vec![Container {
+            resources: Some(cpu_request("100m")),
+            ..Default::default()
+        }];
+        mf.cronJobs = vec![CronJob {
+            container: Container {
+                resources: Some(cpu_request("500m")),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let wl = mf.compute_workload_totals().unwrap();
+        assert_eq!(wl.main.base.requests.cpu, 2.0);
+        assert!((wl.sidecars.base.requests.cpu - 0.2).abs() < 1e-9);
+        assert_eq!(wl.cronjobs.base.requests.cpu, 0.5);
+        assert_eq!(wl.workers.base.requests.cpu, 0.0);
+
+        // without cronjobs, the breakdown sums up to the totals
+        mf.cronJobs = vec![];
+        let total = mf.compute_workload_totals().unwrap().total();
+        let rt = mf.compute_resource_totals().unwrap();
+        assert!((total.base.requests.cpu - rt.base.requests.cpu).abs() < 1e-9);
+        assert_eq!(total.base.requests.memory, rt.base.requests.memory);
+    }
+
+    #[test]
+    fn mf_daemonset_replicas() {
+        let mut mf = Manifest::default();
+        mf.name = "node-agent".into();
+        mf.workload = PrimaryWorkload::DaemonSet;
+        mf.resources = Some(cpu_request("100m"));
+        // counted per node
+        assert_eq!(mf.min_replicas(), 1);
+        let rt = mf.compute_resource_totals().unwrap();
+        assert!((rt.base.requests.cpu - 0.1).abs() < 1e-9);
+        assert!(mf.verify_daemonset().is_ok());
+
+        mf.replicaCount = Some(2);
+        assert!(mf.verify_daemonset().is_err());
+    }
+
+    #[test]
+    fn mf_monthly_cost() {
+        let mut mf = Manifest::default();
+        mf.resources = Some(ResourceRequirements {
+            requests: Resources {
+                cpu: "500m".into(),
+                memory: "2Gi".into(),
+            },
+            limits: Resources {
+                cpu: "1".into(),
+                memory: "2Gi".into(),
+            },
+        });
+        mf.replicaCount = Some(2);
+        let prices = PriceSheet {
+            cpuHour: 0.04,
+            memoryGibHour: 0.005,
+        };
+        let rt = mf.compute_resource_totals().unwrap();
+        // 1 core and 4Gi for 730 hours
+        assert!((rt.monthly_cost(&prices, false) - 43.8).abs() < 1e-9);
+        assert_eq!(rt.monthly_cost(&prices, true), rt.monthly_cost(&prices, false));
     }
 }
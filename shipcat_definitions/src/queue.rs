@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+use kube_derive::CustomResource;
+
+/// A rollout holding, or waiting for, a slot in a region's rollout queue
+///
+/// There is at most one ticket per service. Slots go to the oldest `requested` tickets
+/// that have not `expired`, so a crashed apply frees its slot without cleanup.
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[kube(
+    group = "babylontech.co.uk",
+    kind = "ShipcatRolloutTicket",
+    version = "v1",
+    namespaced,
+    printcolumn = r#"{"name":"Version", "jsonPath": ".spec.version", "type": "string", "description": "The version being rolled out"}"#,
+    printcolumn = r#"{"name":"Requested", "jsonPath": ".spec.requested", "type": "date", "description": "When the rollout asked for a slot"}"#
+)]
+#[kube(apiextensions = "v1beta1")] // kubernetes < 1.16
+pub struct RolloutTicket {
+    pub service: String,
+    pub version: String,
+    /// When the rollout asked for a slot
+    pub requested: DateTime<Utc>,
+    /// When the ticket stops counting unless renewed
+    pub expires: DateTime<Utc>,
+}
+
+impl RolloutTicket {
+    /// Live tickets in queue order
+    ///
+    /// Ties on request time are broken by service name so every client agrees.
+    pub fn queue(mut tickets: Vec<RolloutTicket>, now: DateTime<Utc>) -> Vec<RolloutTicket> {
+        tickets.retain(|t| t.expires > now);
+        tickets.sort_by(|a, b| (a.requested, &a.service).cmp(&(b.requested, &b.service)));
+        tickets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RolloutTicket;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn rollout_queue_order() {
+        let now = Utc::now();
+        let ticket = |svc: &str, requested: i64, expires: i64| RolloutTicket {
+            service: svc.into(),
+            version: "1.0.0".into(),
+            requested: now - Duration::seconds(requested),
+            expires: now + Duration::seconds(expires),
+        };
+        let tickets = vec![
+            ticket("late", 10, 60),
+            ticket("b-early", 30, 60),
+            ticket("a-early", 30, 60),
+            ticket("crashed", 100, -5),
+        ];
+        let queue = RolloutTicket::queue(tickets, now);
+        let order = queue.iter().map(|t| t.service.as_str()).collect::<Vec<_>>();
+        assert_eq!(order, vec!["a-early", "b-early", "late"]);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::structs::kong::Kong;
-use std::{collections::BTreeMap, env};
+use schemars::JsonSchema;
+use std::{collections::BTreeMap, env, ops::Not, path::PathBuf};
 
 use regex::Regex;
 
@@ -8,15 +9,21 @@ use semver::Version;
 use url::Url;
 use uuid::Uuid;
 
-#[allow(unused_imports)] use super::{BaseManifest, ConfigState, Result, Vault};
+#[allow(unused_imports)] use super::{BaseManifest, ConfigState, Error};
+/// The crate `Result`, also usable with two parameters by the `JsonSchema` derive of `serde_regex` fields
+type Result<T, E = Error> = std::result::Result<T, E>;
+use crate::secretstore::{self, SecretBackend};
 
-use super::structs::Authorization;
+use super::{
+    math::ResourceTotals,
+    structs::{prometheusalert::PrometheusAlertSeverity, resources::Resources, Authorization},
+};
 
 /// Versioning Scheme used in region
 ///
 /// This is valdiated strictly using `shipcat validate` when versions are found in manifests.
 /// Otherwise, it's validated on upgrade time (via `shipcat apply`) when it's passed.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum VersionScheme {
     /// Version must be valid semver (no leading v)
     ///
@@ -58,24 +65,121 @@ impl VersionScheme {
 }
 
 /// Vault configuration for a region
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct VaultConfig {
     /// Vault url up to and including port
+    ///
+    /// Only optional in regions using another `secretStore`.
+    #[serde(default)]
     pub url: String,
     /// Root folder under secret/
     ///
     /// Typically, the name of the region to disambiguate.
     pub folder: String,
+    /// Version of the key-value secrets engine mounted at secret/
+    ///
+    /// ```yaml
+    /// vault:
+    ///   url: https://vault.babylontech.co.uk:8200
+    ///   folder: dev-uk
+    ///   engine: kv2
+    /// ```
+    #[serde(default)]
+    pub engine: VaultEngine,
 }
 
-impl VaultConfig {
+/// Key-value secrets engine versions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VaultEngine {
+    /// Unversioned secrets under `secret/<path>`
+    Kv1,
+    /// Versioned secrets under `secret/data/<path>`, listed under `secret/metadata/<path>`
+    Kv2,
+}
+
+impl Default for VaultEngine {
+    fn default() -> Self {
+        VaultEngine::Kv1
+    }
+}
+
+/// Secret backend of a region
+///
+/// Placeholders are resolved from `{vault.folder}/{service}/{KEY}` keys in every backend.
+/// Secrets Manager secrets are named by these keys, under an optional `prefix`,
+/// and hold their value in their `SecretString`.
+///
+/// ```yaml
+/// secretStore:
+///   backend: awsSecretsManager
+///   region: eu-west-2
+///   prefix: shipcat/
+/// ```
+///
+/// Sops files hold the `KEY`s of a service, for regions without a secret service:
+///
+/// ```yaml
+/// secretStore:
+///   backend: sops
+///   file: secrets.dev-uk.yml
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "backend", rename_all = "camelCase")]
+pub enum SecretStoreConfig {
+    /// The region's `vault`
+    Vault,
+    /// AWS Secrets Manager, using credentials from the standard AWS evars
+    #[serde(rename_all = "camelCase")]
+    AwsSecretsManager {
+        /// AWS region of the secrets
+        region: String,
+        /// Prefix of all secret names
+        #[serde(default)]
+        prefix: String,
+    },
+    /// Sops encrypted yaml files in service folders, decrypted with the `sops` binary
+    Sops {
+        /// Name of the files in service folders
+        #[serde(default = "default_sops_file")]
+        file: String,
+    },
+}
+
+fn default_sops_file() -> String {
+    "secrets.yml".into()
+}
+
+impl SecretStoreConfig {
     pub fn verify(&self, region: &str) -> Result<()> {
-        if self.url == "" {
+        match self {
+            SecretStoreConfig::AwsSecretsManager { region: aws, .. } => {
+                if aws.is_empty() {
+                    bail!("Need to set the AWS region of the secretStore in {}", region);
+                }
+            }
+            SecretStoreConfig::Sops { file } => {
+                if file.is_empty() || file.contains('/') {
+                    bail!(
+                        "The sops file of the secretStore in {} must be a plain file name",
+                        region
+                    );
+                }
+            }
+            SecretStoreConfig::Vault => {}
+        }
+        Ok(())
+    }
+}
+
+impl VaultConfig {
+    pub fn verify(&self, region: &str, needs_url: bool) -> Result<()> {
+        if needs_url && self.url.is_empty() {
             bail!("Need to set vault url for {}", region);
         }
-        if self.folder == "" {
+        if self.folder.is_empty() {
             bail!("Need to set the vault folder for {}", region);
         }
         if self.folder.contains('/') {
@@ -114,7 +218,7 @@ impl VaultConfig {
 //}
 
 /// Kafka configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KafkaConfig {
     /// Broker urls in "hostname:port" format.
@@ -140,7 +244,7 @@ pub struct KafkaConfig {
 }
 
 /// Webhook types that shipcat might trigger after actions
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(tag = "name", deny_unknown_fields, rename_all = "snake_case")]
 pub enum Webhook {
     /// Audit webhook details
@@ -148,17 +252,33 @@ pub enum Webhook {
 }
 
 /// Where / how to send audited events
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct AuditWebhook {
     /// Endpoint
+    #[schemars(with = "String")]
     pub url: Url,
     /// Credential
     pub token: String,
 }
 
+/// Local file audited events are appended to
+///
+/// Every event is one json line, for regions without a reachable audit service.
+///
+/// ```yaml
+/// auditLog:
+///   path: /var/log/shipcat/audit.jsonl
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct AuditLogConfig {
+    /// File to append events to
+    pub path: PathBuf,
+}
+
 /// Configure how CRs will be deployed on a region
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct CRSettings {
     #[serde(rename = "config")]
@@ -168,7 +288,7 @@ pub struct CRSettings {
 // ----------------------------------------------------------------------------------
 
 /// Kong configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongConfig {
     /// Base URL to use (e.g. uk.dev.babylontech.co.uk)
@@ -183,12 +303,15 @@ pub struct KongConfig {
     pub jwt_consumers: BTreeMap<String, KongJwtConsumer>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub internal_ips_whitelist: Vec<String>,
+    /// Kong APIs not owned by a service manifest
+    ///
+    /// Workers exposing their own http APIs should declare `kong` on the worker instead.
     #[serde(default, skip_serializing)]
     pub extra_apis: BTreeMap<String, Kong>,
 }
 
 /// StatusCake configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct StatuscakeConfig {
     /// Contact Group that will be used if tests go down
@@ -200,7 +323,7 @@ pub struct StatuscakeConfig {
 }
 
 /// Logz.io configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct LogzIoConfig {
     /// Base URL to use (e.g. https://app-eu.logz.io/#/dashboard/kibana/dashboard)
@@ -210,7 +333,7 @@ pub struct LogzIoConfig {
 }
 
 /// Grafana details for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GrafanaConfig {
     /// Base URL to use (e.g. https://dev-grafana.ops.babylontech.co.uk)
@@ -219,12 +342,378 @@ pub struct GrafanaConfig {
     pub services_dashboard_id: String,
 }
 
+/// Prometheus details for a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PrometheusConfig {
+    /// Base URL of the Prometheus API (e.g. https://dev-prometheus.ops.babylontech.co.uk)
+    pub url: String,
+
+    /// Render `prometheusAlerts` as PrometheusRule objects
+    ///
+    /// Without this, alerts are only passed to charts through the values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<PrometheusRulesConfig>,
+}
+
+/// PrometheusRule output for a region's Prometheus operator
+///
+/// ```yaml
+/// prometheus:
+///   url: https://dev-prometheus.ops.babylontech.co.uk
+///   rules:
+///     labels:
+///       prometheus: k8s
+///       role: alert-rules
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PrometheusRulesConfig {
+    /// Labels matched by the `ruleSelector` of the region's Prometheus
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Kind of policy objects generated for services with `strictEgress`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EgressPolicyKind {
+    /// A kubernetes NetworkPolicy (hosts of infra dependencies can not be matched)
+    NetworkPolicy,
+    /// A CiliumNetworkPolicy (hosts are matched with `toFQDNs`)
+    Cilium,
+}
+
+impl Default for EgressPolicyKind {
+    fn default() -> Self {
+        EgressPolicyKind::NetworkPolicy
+    }
+}
+
+/// An external endpoint that services with `strictEgress` may reach
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct EgressEndpoint {
+    /// Name of the endpoint for reports
+    pub name: String,
+    /// Address range of the endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cidr: Option<String>,
+    /// Hostname of the endpoint (cilium policies only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Ports to allow, or every port when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<u16>,
+}
+
+/// Egress lockdown for services with `strictEgress`
+///
+/// ```yaml
+/// egress:
+///   policy: cilium
+///   allowed:
+///   - name: sentry
+///     host: sentry.io
+///     ports: [443]
+///   - name: vpc
+///     cidr: 10.0.0.0/8
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct EgressConfig {
+    /// Kind of policy objects to generate
+    #[serde(default)]
+    pub policy: EgressPolicyKind,
+    /// External endpoints every service with strict egress may reach
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<EgressEndpoint>,
+}
+
+impl EgressConfig {
+    pub fn verify(&self, region: &str) -> Result<()> {
+        for e in &self.allowed {
+            match (&e.cidr, &e.host) {
+                (Some(cidr), None) => {
+                    if !cidr.contains('/') {
+                        bail!(
+                            "egress endpoint {} in {} needs a cidr like 10.0.0.0/8",
+                            e.name,
+                            region
+                        );
+                    }
+                }
+                (None, Some(_)) => {
+                    if self.policy != EgressPolicyKind::Cilium {
+                        bail!(
+                            "egress endpoint {} in {} can only use a host with cilium",
+                            e.name,
+                            region
+                        );
+                    }
+                }
+                _ => bail!(
+                    "egress endpoint {} in {} needs exactly one of cidr or host",
+                    e.name,
+                    region
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ingress lockdown for services with `networkPolicies: generated`
+///
+/// ```yaml
+/// networkPolicy:
+///   allowedNamespaces: [ingress-nginx, monitoring]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyConfig {
+    /// Namespaces that may reach every service, like ingress controllers or prometheus
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowedNamespaces: Vec<String>,
+}
+
+/// Cloud identities that services may bind their ServiceAccount to
+///
+/// Entries can end in a `*` to allow every identity with that prefix.
+///
+/// ```yaml
+/// workloadIdentity:
+///   allowedRoleArns:
+///   - arn:aws:iam::123456789012:role/dev-*
+///   allowedGcpServiceAccounts:
+///   - orders@dev-project.iam.gserviceaccount.com
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct WorkloadIdentityConfig {
+    /// IAM roles services may assume through IRSA
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowedRoleArns: Vec<String>,
+    /// Google service accounts services may act as through workload identity
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowedGcpServiceAccounts: Vec<String>,
+}
+
+/// Alert routing policy for a region
+///
+/// ```yaml
+/// alertRouting:
+///   pagerSeverities: [error]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct AlertRoutingConfig {
+    /// Alert severities that page the owning squad
+    #[serde(default)]
+    pub pager_severities: Vec<PrometheusAlertSeverity>,
+}
+
+/// Limits on concurrent rollouts in a region
+///
+/// Rollouts beyond the limit wait in a queue of `ShipcatRolloutTicket` objects,
+/// in the order they were requested.
+///
+/// ```yaml
+/// rolloutQueue:
+///   maxConcurrent: 3
+///   maxWaitSeconds: 1800
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RolloutQueueConfig {
+    /// Number of rollouts allowed to run at the same time
+    pub max_concurrent: u32,
+    /// Seconds a rollout waits for a slot before giving up
+    #[serde(default = "default_queue_wait")]
+    pub max_wait_seconds: u32,
+}
+
+fn default_queue_wait() -> u32 {
+    1800
+}
+
+/// Budgets for the resource requests of services in a region
+///
+/// Requests are summed like `shipcat top`, and checked by `shipcat verify`,
+/// `shipcat validate` and `shipcat cluster check`.
+///
+/// ```yaml
+/// resourceBudgets:
+///   total:
+///     cpu: "200"
+///     memory: 800Gi
+///   teams:
+///     platform:
+///       cpu: "20"
+///       memory: 64Gi
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ResourceBudgets {
+    /// Budget for all services in the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<Resources<String>>,
+    /// Budgets for the services of a squad
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub teams: BTreeMap<String, Resources<String>>,
+    /// Count autoscaling ceilings against the budgets rather than minimum replicas
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub upper_bounds: bool,
+}
+
+impl ResourceBudgets {
+    pub fn verify(&self, region: &str) -> Result<()> {
+        if let Some(b) = &self.total {
+            if let Err(e) = b.normalised() {
+                bail!("resourceBudgets total in {} is invalid: {}", region, e);
+            }
+        }
+        for (team, b) in &self.teams {
+            if let Err(e) = b.normalised() {
+                bail!("resourceBudgets for {} in {} is invalid: {}", team, region, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Budgets exceeded by the resource totals of services, paired with their team
+    ///
+    /// With `teams`, only those team budgets are checked along with the total.
+    pub fn exceeded(
+        &self,
+        totals: &[(String, ResourceTotals)],
+        teams: Option<&[String]>,
+    ) -> Result<Vec<String>> {
+        let mut sums: BTreeMap<&str, Resources<f64>> = BTreeMap::new();
+        let mut total = Resources {
+            cpu: 0.0,
+            memory: 0.0,
+        };
+        for (team, t) in totals {
+            let mut req = t.base.requests.clone();
+            if self.upper_bounds {
+                req.cpu += t.extra.requests.cpu;
+                req.memory += t.extra.requests.memory;
+            }
+            let sum = sums.entry(team).or_insert(Resources {
+                cpu: 0.0,
+                memory: 0.0,
+            });
+            sum.cpu += req.cpu;
+            sum.memory += req.memory;
+            total.cpu += req.cpu;
+            total.memory += req.memory;
+        }
+        let mut res = vec![];
+        if let Some(b) = &self.total {
+            res.extend(over_budget("all services", &total, &b.normalised()?));
+        }
+        for (team, b) in &self.teams {
+            if teams.map_or(false, |ts| !ts.contains(team)) {
+                continue;
+            }
+            if let Some(sum) = sums.get(team.as_str()) {
+                res.extend(over_budget(&format!("team {}", team), sum, &b.normalised()?));
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn over_budget(owner: &str, used: &Resources<f64>, budget: &Resources<f64>) -> Vec<String> {
+    let gib = 1024.0 * 1024.0 * 1024.0;
+    let mut res = vec![];
+    if used.cpu > budget.cpu {
+        res.push(format!(
+            "{} requests {:.2} cores of a {:.2} core budget",
+            owner, used.cpu, budget.cpu
+        ));
+    }
+    if used.memory > budget.memory {
+        res.push(format!(
+            "{} requests {:.2}GiB of memory of a {:.2}GiB budget",
+            owner,
+            used.memory / gib,
+            budget.memory / gib
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod test_budgets {
+    use super::ResourceBudgets;
+    use crate::{math::ResourceTotals, structs::ResourceRequirements};
+
+    fn totals(cpu: f64, memory: f64) -> ResourceTotals {
+        let mut base = ResourceRequirements::default();
+        base.requests.cpu = cpu;
+        base.requests.memory = memory;
+        let mut extra = ResourceRequirements::default();
+        extra.requests.cpu = cpu;
+        ResourceTotals { base, extra }
+    }
+
+    #[test]
+    fn region_resource_budgets() {
+        let mut budgets: ResourceBudgets = serde_yaml::from_str(
+            "total: {cpu: '4', memory: 8Gi}
+teams:
+  platform: {cpu: 1500m, memory: 4Gi}
+  payments: {cpu: '1', memory: 1Gi}",
+        )
+        .unwrap();
+        assert!(budgets.verify("dev-uk").is_ok());
+        let gib = 1024.0 * 1024.0 * 1024.0;
+        let usage = vec![
+            ("platform".to_string(), totals(1.0, 2.0 * gib)),
+            ("platform".to_string(), totals(1.0, 1.0 * gib)),
+            ("payments".to_string(), totals(0.5, 0.5 * gib)),
+        ];
+        let exceeded = budgets.exceeded(&usage, None).unwrap();
+        assert_eq!(exceeded, vec![
+            "team platform requests 2.00 cores of a 1.50 core budget"
+        ]);
+        // only the total and payments are checked for payments services
+        let teams = vec!["payments".to_string()];
+        assert!(budgets.exceeded(&usage, Some(&teams)).unwrap().is_empty());
+
+        budgets.upper_bounds = true;
+        let exceeded = budgets.exceeded(&usage, None).unwrap();
+        assert_eq!(exceeded.len(), 2);
+        assert!(exceeded[0].starts_with("all services requests 5.00 cores"));
+
+        budgets.teams.get_mut("payments").unwrap().memory = "lots".into();
+        assert!(budgets.verify("dev-uk").is_err());
+    }
+}
+
 /// Sentry details for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SentryConfig {
     /// Base URL to use (e.g. https://dev-uk-sentry.ops.babylontech.co.uk)
     pub url: String,
+    /// Organization slug that service projects live in
+    #[serde(default = "default_sentry_organization")]
+    pub organization: String,
+    /// Team slug owning projects created for `FROM_SENTRY` evars
+    ///
+    /// Without a team, `FROM_SENTRY` only resolves DSNs of existing projects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+fn default_sentry_organization() -> String {
+    "sentry".into()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -241,14 +730,14 @@ pub struct KongOauthConsumer {
     pub username: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongJwtConsumer {
     pub kid: String,
     pub public_key: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongTcpLogConfig {
     pub enabled: bool,
@@ -264,14 +753,14 @@ impl KongConfig {
 
 /// Defaults for services in this region
 // TODO: This should be ManifestDefaults from shipcat_filebacked
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultConfig {
     pub kong: DefaultKongConfig,
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultKongConfig {
@@ -281,10 +770,10 @@ pub struct DefaultKongConfig {
 }
 
 impl Webhook {
-    async fn secrets(&mut self, vault: &Vault, region: &str) -> Result<()> {
+    async fn secrets(&mut self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(h) => {
-                if h.token == "IN_VAULT" {
+                if secretstore::is_placeholder(&h.token) {
                     let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
                     h.token = vault.read(&vkey).await?;
                 }
@@ -293,7 +782,7 @@ impl Webhook {
         Ok(())
     }
 
-    async fn verify_secrets_exist(&self, vault: &Vault, region: &str) -> Result<()> {
+    async fn verify_secrets_exist(&self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(_h) => {
                 let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
@@ -305,31 +794,9 @@ impl Webhook {
     }
 
     pub fn get_configuration(&self) -> Result<BTreeMap<String, String>> {
-        let mut whc = BTreeMap::default();
-        match self {
+        let whc = match self {
             Webhook::Audit(_h) => {
-                whc.insert(
-                    "SHIPCAT_AUDIT_CONTEXT_ID".into(),
-                    env::var("SHIPCAT_AUDIT_CONTEXT_ID").unwrap_or_else(|_| Uuid::new_v4().to_string()),
-                );
-
-                // if we are on jenkins
-                if let (Ok(url), Ok(revision), Ok(_)) = (
-                    env::var("BUILD_URL"),
-                    env::var("GIT_COMMIT"),
-                    env::var("BUILD_NUMBER"),
-                ) {
-                    whc.insert("SHIPCAT_AUDIT_REVISION".into(), revision);
-                    whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), url);
-                }
-
-                // shipcat evars
-                if let Ok(url) = env::var("SHIPCAT_AUDIT_CONTEXT_LINK") {
-                    whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), url);
-                }
-                if let Ok(revision) = env::var("SHIPCAT_AUDIT_REVISION") {
-                    whc.insert("SHIPCAT_AUDIT_REVISION".into(), revision);
-                }
+                let whc = audit_context();
 
                 // strict requirements
                 if !whc.contains_key("SHIPCAT_AUDIT_REVISION") {
@@ -337,8 +804,9 @@ impl Webhook {
                 }
 
                 debug!("Audit webhook config {:?}", whc);
+                whc
             }
-        }
+        };
 
         // TODO: when slack webhook is cfged, require this:
         // slack::have_credentials()?;
@@ -347,6 +815,37 @@ impl Webhook {
     }
 }
 
+/// Context of audited events inferred from the environment
+///
+/// Uses the jenkins build url and commit if present, overridden by `SHIPCAT_AUDIT_*` evars.
+/// The context id is random unless `SHIPCAT_AUDIT_CONTEXT_ID` is set.
+pub fn audit_context() -> BTreeMap<String, String> {
+    let mut whc = BTreeMap::default();
+    whc.insert(
+        "SHIPCAT_AUDIT_CONTEXT_ID".into(),
+        env::var("SHIPCAT_AUDIT_CONTEXT_ID").unwrap_or_else(|_| Uuid::new_v4().to_string()),
+    );
+
+    // if we are on jenkins
+    if let (Ok(url), Ok(revision), Ok(_)) = (
+        env::var("BUILD_URL"),
+        env::var("GIT_COMMIT"),
+        env::var("BUILD_NUMBER"),
+    ) {
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), revision);
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), url);
+    }
+
+    // shipcat evars
+    if let Ok(url) = env::var("SHIPCAT_AUDIT_CONTEXT_LINK") {
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), url);
+    }
+    if let Ok(revision) = env::var("SHIPCAT_AUDIT_REVISION") {
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), revision);
+    }
+    whc
+}
+
 #[cfg(test)]
 mod test_webhooks {
     use super::{AuditWebhook, Webhook};
@@ -397,7 +896,7 @@ mod test_webhooks {
 // ----------------------------------------------------------------------------------
 
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Environment {
     /// Production environment
@@ -432,7 +931,7 @@ impl ToString for Environment {
 // ----------------------------------------------------------------------------------
 
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum ReconciliationMode {
     /// Shipcat owned, CRD based decision
     ///
@@ -453,12 +952,17 @@ impl Default for ReconciliationMode {
 ///
 /// Either it's a pure kubernetes context with a namespace and a cluster,
 /// or it's an abstract concept with many associated real kubernetes contexts.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Region {
     /// Name of region
     pub name: String,
+    /// Name of the `regionDefaults` overlay this region is layered on
+    ///
+    /// Resolved when reading the config.
+    #[serde(default, skip_serializing)]
+    pub extends: Option<String>,
     /// Kubernetes namespace
     pub namespace: String,
     /// Environment (e.g. `dev` or `staging`)
@@ -500,10 +1004,43 @@ pub struct Region {
     pub kafka: KafkaConfig,
     /// Vault configuration for the region
     pub vault: VaultConfig,
+    /// Secret backend for the region, if not vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secretStore: Option<SecretStoreConfig>,
     /// Logz.io configuration for the region
     pub logzio: Option<LogzIoConfig>,
     /// Grafana details for the region
     pub grafana: Option<GrafanaConfig>,
+    /// Prometheus details for the region
+    pub prometheus: Option<PrometheusConfig>,
+    /// Alert routing policy for the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alertRouting: Option<AlertRoutingConfig>,
+    /// Egress lockdown for services with `strictEgress`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<EgressConfig>,
+    /// Ingress lockdown for services with generated network policies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networkPolicy: Option<NetworkPolicyConfig>,
+    /// Cloud identities services may bind their ServiceAccount to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workloadIdentity: Option<WorkloadIdentityConfig>,
+    /// Rollout concurrency limits for the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolloutQueue: Option<RolloutQueueConfig>,
+    /// Budgets for the resource requests of services in the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resourceBudgets: Option<ResourceBudgets>,
+    /// Chart versions services may use, by chart name
+    ///
+    /// Charts without an entry can be used at any version.
+    ///
+    /// ```yaml
+    /// allowedChartVersions:
+    ///   base: ["2.3.1", "2.4.0"]
+    /// ```
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub allowedChartVersions: BTreeMap<String, Vec<String>>,
     /// Sentry URL for the region
     pub sentry: Option<SentryConfig>,
     /// List of locations the region serves
@@ -512,6 +1049,9 @@ pub struct Region {
     /// All webhooks
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub webhooks: Vec<Webhook>,
+    /// Audit log file for the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auditLog: Option<AuditLogConfig>,
     /// CRD tuning
     pub customResources: Option<CRSettings>,
 
@@ -527,28 +1067,30 @@ pub struct Region {
     // TODO: Rename to `defaults` after removing legacy field
     #[serde(skip_serializing, default)]
     #[cfg(feature = "filesystem")]
+    #[schemars(with = "Option<serde_json::Value>")]
     pub defaultsV2: Option<serde_yaml::Value>,
 
     /// The regular expression used to verify destination rules' regions
     #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_regex")]
+    #[schemars(with = "Option<String>")]
     pub destinationRuleHostRegex: Option<Regex>,
 }
 
 impl Region {
     // Internal secret populator for Config::new
     pub async fn secrets(&mut self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
+        let v = self.secret_store()?;
         for wh in self.webhooks.iter_mut() {
-            wh.secrets(&v, &self.name).await?;
+            wh.secrets(&*v, &self.name).await?;
         }
         Ok(())
     }
 
     // Entry point for region verifier
     pub async fn verify_secrets_exist(&self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
+        let v = self.secret_store()?;
         for wh in &self.webhooks {
-            wh.verify_secrets_exist(&v, &self.name).await?;
+            wh.verify_secrets_exist(&*v, &self.name).await?;
         }
         Ok(())
     }
@@ -581,8 +1123,9 @@ impl Region {
     pub fn sentry_url(&self, slug: &str) -> Option<String> {
         self.sentry.clone().map(|s| {
             format!(
-                "{sentry_base_url}/sentry/{slug}",
+                "{sentry_base_url}/{organization}/{slug}",
                 sentry_base_url = s.url,
+                organization = s.organization,
                 slug = slug
             )
         })
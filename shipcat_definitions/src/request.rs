@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use kube_derive::CustomResource;
+
+/// An apply of a service, or a reconcile of the region, requested through raftcat
+///
+/// Executed by `shipcat cluster worker`, which deletes the request once done.
+/// Progress is reported through the conditions of the `ShipcatManifest` status,
+/// like for any other apply.
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[kube(
+    group = "babylontech.co.uk",
+    kind = "ShipcatApplyRequest",
+    version = "v1",
+    namespaced,
+    printcolumn = r#"{"name":"Service", "jsonPath": ".spec.service", "type": "string", "description": "The service to apply"}"#,
+    printcolumn = r#"{"name":"Requester", "jsonPath": ".spec.requester", "type": "string", "description": "Who asked for the apply"}"#,
+    printcolumn = r#"{"name":"Requested", "jsonPath": ".spec.requested", "type": "date", "description": "When the apply was requested"}"#
+)]
+#[kube(apiextensions = "v1beta1")] // kubernetes < 1.16
+pub struct ApplyRequest {
+    /// Service to apply, or none to reconcile every service in the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Version to apply instead of the version in the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Who asked for the apply, as named by the raftcat token used
+    pub requester: String,
+    pub requested: DateTime<Utc>,
+}
+
+impl ApplyRequest {
+    /// Name of the request object
+    ///
+    /// There is one per service, so a service cannot be queued twice.
+    pub fn object_name(&self) -> String {
+        Self::name_for(self.service.as_deref())
+    }
+
+    /// Name of the request object for a service, or the region reconcile
+    pub fn name_for(service: Option<&str>) -> String {
+        match service {
+            Some(svc) => format!("apply-{}", svc),
+            None => "reconcile".into(),
+        }
+    }
+
+    /// Requests in the order they should be executed
+    pub fn queue(mut requests: Vec<ApplyRequest>) -> Vec<ApplyRequest> {
+        requests.sort_by(|a, b| (a.requested, a.object_name()).cmp(&(b.requested, b.object_name())));
+        requests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApplyRequest;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn apply_request_queue() {
+        let now = Utc::now();
+        let request = |svc: Option<&str>, age: i64| ApplyRequest {
+            service: svc.map(String::from),
+            version: None,
+            requester: "someone".into(),
+            requested: now - Duration::seconds(age),
+        };
+        let requests = vec![
+            request(Some("late"), 10),
+            request(None, 30),
+            request(Some("early"), 30),
+        ];
+        let queue = ApplyRequest::queue(requests);
+        let order = queue.iter().map(ApplyRequest::object_name).collect::<Vec<_>>();
+        assert_eq!(order, vec!["apply-early", "reconcile", "apply-late"]);
+    }
+}
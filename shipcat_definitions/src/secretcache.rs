@@ -0,0 +1,278 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN},
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{Error, Result};
+use crate::{secretstore::SecretBackend, vault::Mode};
+
+/// Evar with the base64 encoded 256 bit key encrypting the cache, enabling it when set
+pub const SECRET_CACHE_KEY_EVAR: &str = "SHIPCAT_SECRET_CACHE_KEY";
+/// Evar with the number of seconds a cached secret is used for
+pub const SECRET_CACHE_TTL_EVAR: &str = "SHIPCAT_SECRET_CACHE_TTL";
+/// Evar overriding the directory of the cache
+pub const SECRET_CACHE_DIR_EVAR: &str = "SHIPCAT_SECRET_CACHE_DIR";
+
+const DEFAULT_TTL_SECONDS: i64 = 3600;
+
+/// Whether this process reads secrets through the cache
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Read secrets through the cache for the rest of the process, when it is configured
+///
+/// Only templating commands enable it; applies always read the secret store.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether `enable` was called
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Directory of the secret cache
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var(SECRET_CACHE_DIR_EVAR) {
+        return Ok(dir.into());
+    }
+    match dirs::cache_dir() {
+        Some(d) => Ok(d.join("shipcat").join("secrets")),
+        None => bail!("No cache directory for secrets, set {}", SECRET_CACHE_DIR_EVAR),
+    }
+}
+
+/// Identifier of a cached secret, binding it to its region and path
+fn entry_id(region: &str, key: &str) -> String {
+    format!("{}/{}", region, key)
+}
+
+/// File name of a cached secret
+fn file_name(id: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, id.as_bytes());
+    hex::encode(hash.as_ref())
+}
+
+/// Drop the cached value of a secret, like after rotating it
+pub fn forget(region: &str, key: &str) -> Result<()> {
+    let pth = cache_dir()?.join(file_name(&entry_id(region, key)));
+    if pth.exists() {
+        fs::remove_file(pth)?;
+    }
+    Ok(())
+}
+
+/// Remove every cached secret, returning how many there were
+pub fn purge() -> Result<usize> {
+    let dir = cache_dir()?;
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in fs::read_dir(&dir)? {
+        fs::remove_file(entry?.path())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// A cached secret value, encrypted as a whole on disk
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    value: String,
+    /// Version of the secret, for backends keeping versions
+    #[serde(default)]
+    version: Option<u32>,
+    fetched: DateTime<Utc>,
+}
+
+/// Encrypted on-disk cache of secret values
+///
+/// Entries are sealed with AES-256-GCM, bound to their secret path,
+/// and stored in files named by a digest of the path. Values are never written in plaintext.
+/// Entries of versioned secrets are only used while they match the latest version.
+pub struct SecretCache {
+    dir: PathBuf,
+    key: LessSafeKey,
+    ttl: Duration,
+}
+
+impl SecretCache {
+    /// The cache configured through evars, if enabled
+    pub fn from_evars() -> Result<Option<SecretCache>> {
+        let encoded = match env::var(SECRET_CACHE_KEY_EVAR) {
+            Ok(k) => k,
+            Err(_) => return Ok(None),
+        };
+        let ttl = match env::var(SECRET_CACHE_TTL_EVAR) {
+            Ok(s) => s.parse()?,
+            Err(_) => DEFAULT_TTL_SECONDS,
+        };
+        let cache = SecretCache::new(cache_dir()?, &encoded, Duration::seconds(ttl))?;
+        Ok(Some(cache))
+    }
+
+    fn new(dir: PathBuf, encoded_key: &str, ttl: Duration) -> Result<SecretCache> {
+        let raw = base64::decode(encoded_key.trim()).unwrap_or_default();
+        let key = match UnboundKey::new(&aead::AES_256_GCM, &raw) {
+            Ok(k) => LessSafeKey::new(k),
+            Err(_) => bail!("{} must be a base64 encoded 32 byte key", SECRET_CACHE_KEY_EVAR),
+        };
+        Ok(SecretCache { dir, key, ttl })
+    }
+
+    fn file(&self, id: &str) -> PathBuf {
+        self.dir.join(file_name(id))
+    }
+
+    /// An unexpired value of a version, if it is cached
+    ///
+    /// Entries that cannot be decrypted, like ones sealed with an old key, count as missing.
+    fn get(&self, id: &str, version: Option<u32>, now: DateTime<Utc>) -> Option<String> {
+        let mut data = fs::read(self.file(id)).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let mut sealed = data.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&data).ok()?;
+        let aad = Aad::from(id.as_bytes());
+        let plain = match self.key.open_in_place(nonce, aad, &mut sealed) {
+            Ok(p) => p,
+            Err(_) => {
+                debug!("Ignoring cached {} that could not be decrypted", id);
+                return None;
+            }
+        };
+        let entry: Entry = serde_json::from_slice(plain).ok()?;
+        if entry.version == version && now - entry.fetched < self.ttl {
+            Some(entry.value)
+        } else {
+            None
+        }
+    }
+
+    fn put(&self, id: &str, value: &str, version: Option<u32>, now: DateTime<Utc>) -> Result<()> {
+        let mut data = serde_json::to_vec(&Entry {
+            value: value.into(),
+            version,
+            fetched: now,
+        })?;
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::from("Failed to generate a nonce"))?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(id.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| Error::from("Failed to encrypt secret"))?;
+
+        fs::create_dir_all(&self.dir)?;
+        // write and rename, so that concurrent readers never see partial entries
+        let pth = self.file(id);
+        let tmp = pth.with_extension(uuid::Uuid::new_v4().to_string());
+        fs::write(&tmp, [&nonce[..], &data[..]].concat())?;
+        fs::rename(&tmp, &pth)?;
+        Ok(())
+    }
+
+    /// Read secrets of a region through the cache
+    pub fn wrap(self, backend: Box<dyn SecretBackend>, region: &str) -> CachedSecrets {
+        CachedSecrets {
+            backend,
+            cache: self,
+            region: region.into(),
+        }
+    }
+}
+
+/// A secret backend whose reads go through a `SecretCache`
+pub struct CachedSecrets {
+    backend: Box<dyn SecretBackend>,
+    cache: SecretCache,
+    region: String,
+}
+
+#[async_trait]
+impl SecretBackend for CachedSecrets {
+    async fn read(&self, key: &str) -> Result<String> {
+        let version = match self.backend.version(key).await {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("Reading {} uncached without its version: {}", key, e);
+                return self.backend.read(key).await;
+            }
+        };
+        let id = entry_id(&self.region, key);
+        let now = Utc::now();
+        if let Some(value) = self.cache.get(&id, version, now) {
+            debug!("Using cached secret {}", key);
+            return Ok(value);
+        }
+        let value = self.backend.read(key).await?;
+        if let Err(e) = self.cache.put(&id, &value, version, now) {
+            warn!("Failed to cache secret {}: {}", key, e);
+        }
+        Ok(value)
+    }
+
+    async fn version(&self, key: &str) -> Result<Option<u32>> {
+        self.backend.version(key).await
+    }
+
+    async fn list(&self, folder: &str) -> Result<Vec<String>> {
+        self.backend.list(folder).await
+    }
+
+    fn mode(&self) -> Mode {
+        self.backend.mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretCache;
+    use chrono::{Duration, Utc};
+    use std::fs;
+
+    #[test]
+    fn secret_cache_roundtrip() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let key = base64::encode(&[7u8; 32][..]);
+        let cache = SecretCache::new(dir.clone(), &key, Duration::hours(1)).unwrap();
+        let now = Utc::now();
+
+        let id = "dev-uk/dev-uk/fake-ask/FAKE_SECRET";
+        assert_eq!(cache.get(id, None, now), None);
+        cache.put(id, "hunter2", None, now).unwrap();
+        assert_eq!(cache.get(id, None, now).unwrap(), "hunter2");
+        assert_eq!(cache.get(id, None, now + Duration::hours(2)), None); // expired
+
+        // versioned secrets are only served at the version they were cached at
+        cache.put(id, "hunter3", Some(3), now).unwrap();
+        assert_eq!(cache.get(id, Some(3), now).unwrap(), "hunter3");
+        assert_eq!(cache.get(id, Some(4), now), None);
+        assert_eq!(cache.get(id, None, now), None);
+
+        // sealed to its path, and never in plaintext
+        let data = fs::read(cache.file(id)).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("hunter3"));
+        fs::copy(cache.file(id), cache.file("dev-uk/other")).unwrap();
+        assert_eq!(cache.get("dev-uk/other", Some(3), now), None);
+
+        let old_key = base64::encode(&[8u8; 32][..]);
+        let rotated = SecretCache::new(dir.clone(), &old_key, Duration::hours(1)).unwrap();
+        assert_eq!(rotated.get(id, Some(3), now), None);
+        assert!(SecretCache::new(dir.clone(), "c2hvcnQ=", Duration::hours(1)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
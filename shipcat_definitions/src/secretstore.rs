@@ -0,0 +1,631 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ring::{digest, hmac};
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::process::Command;
+
+use super::{Error, ErrorKind, Result, ResultExt};
+use crate::{
+    region::{Region, SecretStoreConfig},
+    vault::{Mode, Vault},
+};
+
+/// Placeholder for a secret resolved from the region's secret store
+///
+/// `IN_VAULT` works the same way; it just predates other backends.
+pub const IN_SECRETSTORE: &str = "IN_SECRETSTORE";
+
+/// Whether a value is a placeholder for a secret from the region's secret store
+pub fn is_placeholder(value: &str) -> bool {
+    value == "IN_VAULT" || value == IN_SECRETSTORE
+}
+
+/// A place that secret placeholders are resolved from
+///
+/// Keys are slash separated paths, like `{folder}/{service}/{KEY}`.
+#[async_trait]
+pub trait SecretBackend: Send + Sync {
+    /// Read the value of a secret
+    async fn read(&self, key: &str) -> Result<String>;
+
+    /// List the names of the secrets directly in a folder
+    async fn list(&self, folder: &str) -> Result<Vec<String>>;
+
+    /// Latest version of a secret, for backends keeping versions
+    async fn version(&self, _key: &str) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Whether real secrets or dummy values are returned
+    fn mode(&self) -> Mode;
+}
+
+#[async_trait]
+impl SecretBackend for Vault {
+    async fn read(&self, key: &str) -> Result<String> {
+        Vault::read(self, key).await
+    }
+
+    async fn list(&self, folder: &str) -> Result<Vec<String>> {
+        Vault::list(self, folder).await
+    }
+
+    async fn version(&self, key: &str) -> Result<Option<u32>> {
+        Vault::current_version(self, key).await
+    }
+
+    fn mode(&self) -> Mode {
+        Vault::mode(self)
+    }
+}
+
+impl Region {
+    /// The secret backend of the region
+    ///
+    /// Vault, unless another `secretStore` is configured.
+    /// Reads go through the encrypted secret cache once it is enabled, if `SHIPCAT_SECRET_CACHE_KEY` is set.
+    pub fn secret_store(&self) -> Result<Box<dyn SecretBackend>> {
+        let store: Box<dyn SecretBackend> = match &self.secretStore {
+            Some(SecretStoreConfig::AwsSecretsManager { region, prefix }) => {
+                Box::new(SecretsManager::new(region, prefix, Mode::Standard)?)
+            }
+            Some(SecretStoreConfig::Sops { file }) => Box::new(Sops::new(file, Mode::Standard)),
+            Some(SecretStoreConfig::Vault) | None => Box::new(Vault::regional(&self.vault)?),
+        };
+        #[cfg(feature = "filesystem")]
+        {
+            if crate::secretcache::enabled() {
+                if let Some(cache) = crate::secretcache::SecretCache::from_evars()? {
+                    return Ok(Box::new(cache.wrap(store, &self.name)));
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    /// A secret backend of the region returning dummy values
+    pub fn mocked_secret_store(&self) -> Result<Box<dyn SecretBackend>> {
+        Ok(match &self.secretStore {
+            Some(SecretStoreConfig::AwsSecretsManager { region, prefix }) => {
+                Box::new(SecretsManager::new(region, prefix, Mode::Mocked)?)
+            }
+            Some(SecretStoreConfig::Sops { file }) => Box::new(Sops::new(file, Mode::Mocked)),
+            Some(SecretStoreConfig::Vault) | None => Box::new(Vault::mocked(&self.vault)?),
+        })
+    }
+}
+
+/// AWS credentials signing Secrets Manager requests
+#[derive(Clone)]
+struct AwsCredentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Credentials from the standard evars, or from a web identity like IRSA on EKS
+    async fn resolve(client: &reqwest::Client, region: &str) -> Result<AwsCredentials> {
+        if let Some(creds) = AwsCredentials::from_evars() {
+            return Ok(creds);
+        }
+        match (env::var("AWS_ROLE_ARN"), env::var("AWS_WEB_IDENTITY_TOKEN_FILE")) {
+            (Ok(role), Ok(file)) => AwsCredentials::from_web_identity(client, region, &role, &file).await,
+            _ => bail!(
+                "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or AWS_ROLE_ARN and AWS_WEB_IDENTITY_TOKEN_FILE \
+                 must be set to use AWS Secrets Manager"
+            ),
+        }
+    }
+
+    fn from_evars() -> Option<AwsCredentials> {
+        match (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+            (Ok(access_key), Ok(secret_key)) => Some(AwsCredentials {
+                access_key,
+                secret_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Temporary credentials of a role, assumed with the web identity token in a file
+    async fn from_web_identity(
+        client: &reqwest::Client,
+        region: &str,
+        role: &str,
+        token_file: &str,
+    ) -> Result<AwsCredentials> {
+        let token = tokio::fs::read_to_string(token_file)
+            .await
+            .chain_err(|| format!("Failed to read AWS_WEB_IDENTITY_TOKEN_FILE {}", token_file))?;
+        let session = env::var("AWS_ROLE_SESSION_NAME").unwrap_or_else(|_| "shipcat".into());
+        let endpoint = reqwest::Url::parse(&format!("https://sts.{}.amazonaws.com/", region))?;
+        let mut url = endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("Action", "AssumeRoleWithWebIdentity")
+            .append_pair("Version", "2011-06-15")
+            .append_pair("RoleArn", role)
+            .append_pair("RoleSessionName", &session)
+            .append_pair("WebIdentityToken", token.trim());
+        debug!("AssumeRoleWithWebIdentity {} at {}", role, endpoint);
+
+        // NB: errors only mention the endpoint, as the url contains the token
+        let mkerr = || ErrorKind::Url(endpoint.clone());
+        let res = client.get(url).send().await.chain_err(mkerr)?;
+        if !res.status().is_success() {
+            let status = res.status().to_owned();
+            debug!(
+                "AssumeRoleWithWebIdentity failed: {}",
+                res.text().await.unwrap_or_default()
+            );
+            let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
+            return Err(err).chain_err(mkerr);
+        }
+        assumed_role_credentials(&res.text().await?)
+    }
+}
+
+/// Text of the first element with a tag in an xml document
+///
+/// Only meant for the flat text fields of AWS query api responses.
+fn xml_text<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let len = body[start..].find(&format!("</{}>", tag))?;
+    Some(&body[start..start + len])
+}
+
+/// Credentials of an `AssumeRoleWithWebIdentity` response
+fn assumed_role_credentials(body: &str) -> Result<AwsCredentials> {
+    match (
+        xml_text(body, "AccessKeyId"),
+        xml_text(body, "SecretAccessKey"),
+        xml_text(body, "SessionToken"),
+    ) {
+        (Some(access_key), Some(secret_key), Some(token)) => Ok(AwsCredentials {
+            access_key: access_key.trim().into(),
+            secret_key: secret_key.trim().into(),
+            session_token: Some(token.trim().into()),
+        }),
+        _ => bail!("AssumeRoleWithWebIdentity returned no credentials"),
+    }
+}
+
+/// AWS Secrets Manager client
+///
+/// Secrets are named by their key, under an optional prefix, and hold their value in `SecretString`.
+pub struct SecretsManager {
+    client: reqwest::Client,
+    region: String,
+    prefix: String,
+    /// Credentials, resolved on the first request
+    credentials: Mutex<Option<AwsCredentials>>,
+    mode: Mode,
+}
+
+impl SecretsManager {
+    fn new(region: &str, prefix: &str, mode: Mode) -> Result<SecretsManager> {
+        Ok(SecretsManager {
+            client: reqwest::Client::new(),
+            region: region.into(),
+            prefix: prefix.into(),
+            credentials: Mutex::new(None),
+            mode,
+        })
+    }
+
+    async fn credentials(&self) -> Result<AwsCredentials> {
+        if let Some(c) = self.credentials.lock().unwrap().clone() {
+            return Ok(c);
+        }
+        let creds = AwsCredentials::resolve(&self.client, &self.region).await?;
+        *self.credentials.lock().unwrap() = Some(creds.clone());
+        Ok(creds)
+    }
+
+    fn host(&self) -> String {
+        format!("secretsmanager.{}.amazonaws.com", self.region)
+    }
+
+    /// Call a Secrets Manager api action with a signed json request
+    async fn call(&self, action: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        if self.mode == Mode::Mocked {
+            bail!("Cannot call AWS Secrets Manager in mocked mode");
+        }
+        let creds = self.credentials().await?;
+        let url = reqwest::Url::parse(&format!("https://{}/", self.host()))?;
+        let body = serde_json::to_string(&body)?;
+        let target = format!("secretsmanager.{}", action);
+        debug!("POST {} {}", url, target);
+
+        let mut req = self.client.post(url.clone()).body(body.clone());
+        for (k, v) in sign(&creds, &self.region, &self.host(), &target, &body, Utc::now()) {
+            req = req.header(k.as_str(), v);
+        }
+        let mkerr = || ErrorKind::Url(url.clone());
+        let res = req.send().await.chain_err(mkerr)?;
+        if !res.status().is_success() {
+            let status = res.status().to_owned();
+            debug!("{} failed: {}", target, res.text().await.unwrap_or_default());
+            let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
+            return Err(err).chain_err(mkerr);
+        }
+        Ok(serde_json::from_str(&res.text().await?)?)
+    }
+}
+
+#[async_trait]
+impl SecretBackend for SecretsManager {
+    async fn read(&self, key: &str) -> Result<String> {
+        let name = format!("{}{}", self.prefix, key);
+        if self.mode == Mode::Mocked {
+            // same arbitrary base64 encoded value as a mocked vault
+            return Ok("aGVsbG8gd29ybGQ=".into());
+        }
+        let res = self
+            .call("GetSecretValue", serde_json::json!({ "SecretId": name }))
+            .await
+            .chain_err(|| ErrorKind::SecretNotAccessible(name.clone()))?;
+        match res["SecretString"].as_str() {
+            Some(s) => Ok(s.to_string()),
+            None => bail!("Secret {} has no SecretString", name),
+        }
+    }
+
+    async fn list(&self, folder: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}{}/", self.prefix, folder.trim_end_matches('/'));
+        let mut names = vec![];
+        let mut next: Option<String> = None;
+        loop {
+            let mut body = serde_json::json!({ "Filters": [{ "Key": "name", "Values": [prefix] }] });
+            if let Some(token) = &next {
+                body["NextToken"] = serde_json::json!(token);
+            }
+            let res = self.call("ListSecrets", body).await?;
+            for s in res["SecretList"].as_array().into_iter().flatten() {
+                if let Some(name) = s["Name"].as_str().and_then(|n| n.strip_prefix(&prefix)) {
+                    // skip secrets in sub folders like vault does
+                    if !name.contains('/') {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            next = res["NextToken"].as_str().map(String::from);
+            if next.is_none() {
+                break;
+            }
+        }
+        Ok(names)
+    }
+
+    fn mode(&self) -> Mode {
+        self.mode.clone()
+    }
+}
+
+/// Secrets in sops encrypted yaml files next to the manifests
+///
+/// Keys `{folder}/{service}/{KEY}` are read from `services/{service}/{file}`,
+/// and region wide `shipcat` secrets from `{file}` at the root of the manifests repository.
+/// Files are decrypted with the `sops` binary, so its keys must be available locally.
+pub struct Sops {
+    file: String,
+    mode: Mode,
+    /// Decrypted files, so every file is only decrypted once
+    decrypted: Mutex<BTreeMap<PathBuf, BTreeMap<String, String>>>,
+}
+
+impl Sops {
+    fn new(file: &str, mode: Mode) -> Sops {
+        Sops {
+            file: file.into(),
+            mode,
+            decrypted: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The file holding the secrets of a `{folder}/{service}` folder
+    fn file_of(&self, folder: &str) -> Result<PathBuf> {
+        match folder.trim_matches('/').split('/').collect::<Vec<_>>().as_slice() {
+            [_, "shipcat"] => Ok(PathBuf::from(&self.file)),
+            [_, svc] => Ok(Path::new("services").join(svc).join(&self.file)),
+            _ => bail!("Secret folder {} must be of the form folder/service", folder),
+        }
+    }
+
+    async fn decrypt(&self, pth: &Path) -> Result<BTreeMap<String, String>> {
+        let cached = self.decrypted.lock().unwrap().get(pth).cloned();
+        if let Some(values) = cached {
+            return Ok(values);
+        }
+        debug!("sops --decrypt {}", pth.display());
+        let out = Command::new("sops")
+            .arg("--decrypt")
+            .arg(pth)
+            .output()
+            .await
+            .chain_err(|| "Failed to run sops, is it installed?")?;
+        if !out.status.success() {
+            bail!(
+                "sops could not decrypt {}: {}",
+                pth.display(),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        let values = sops_values(&String::from_utf8_lossy(&out.stdout))?;
+        self.decrypted
+            .lock()
+            .unwrap()
+            .insert(pth.to_path_buf(), values.clone());
+        Ok(values)
+    }
+}
+
+/// Secret values of a sops file, without its metadata
+///
+/// Keys are not encrypted by sops, so this also lists the secrets of an encrypted file.
+fn sops_values(data: &str) -> Result<BTreeMap<String, String>> {
+    let doc: BTreeMap<String, serde_yaml::Value> = serde_yaml::from_str(data)?;
+    let mut res = BTreeMap::new();
+    for (k, v) in doc.into_iter().filter(|(k, _)| k != "sops") {
+        let value = match v {
+            serde_yaml::Value::String(s) => s,
+            serde_yaml::Value::Number(n) => n.to_string(),
+            serde_yaml::Value::Bool(b) => b.to_string(),
+            _ => bail!("sops secret {} must be a string", k),
+        };
+        res.insert(k, value);
+    }
+    Ok(res)
+}
+
+#[async_trait]
+impl SecretBackend for Sops {
+    async fn read(&self, key: &str) -> Result<String> {
+        let parts = key.trim_end_matches('/').rsplitn(2, '/').collect::<Vec<_>>();
+        let (folder, name) = match parts.as_slice() {
+            [name, folder] => (folder.to_string(), name.to_string()),
+            _ => bail!("Secret key {} must be of the form folder/service/KEY", key),
+        };
+        let pth = self.file_of(&folder)?;
+        if self.mode == Mode::Mocked {
+            // same arbitrary base64 encoded value as a mocked vault
+            return Ok("aGVsbG8gd29ybGQ=".into());
+        }
+        match self.decrypt(&pth).await?.remove(&name) {
+            Some(v) => Ok(v),
+            None => Err(ErrorKind::SecretNotAccessible(key.into()).into()),
+        }
+    }
+
+    async fn list(&self, folder: &str) -> Result<Vec<String>> {
+        if !folder.trim_matches('/').contains('/') {
+            // like vault, a region folder contains a folder per service
+            let mut services = vec![];
+            for entry in std::fs::read_dir("services")? {
+                let entry = entry?;
+                if entry.path().join(&self.file).is_file() {
+                    services.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+            services.sort();
+            return Ok(services);
+        }
+        let pth = self.file_of(folder)?;
+        let data = tokio::fs::read_to_string(&pth)
+            .await
+            .chain_err(|| format!("Failed to read sops file {}", pth.display()))?;
+        Ok(sops_values(&data)?.keys().cloned().collect())
+    }
+
+    fn mode(&self) -> Mode {
+        self.mode.clone()
+    }
+}
+
+fn sha256_hex(data: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, data.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data.as_bytes()).as_ref().to_vec()
+}
+
+/// Key for signatures of one day, region and service
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date);
+    let k = hmac_sha256(&k, region);
+    let k = hmac_sha256(&k, service);
+    hmac_sha256(&k, "aws4_request")
+}
+
+/// Headers of a Signature Version 4 signed json api request
+fn sign(
+    creds: &AwsCredentials,
+    region: &str,
+    host: &str,
+    target: &str,
+    body: &str,
+    now: DateTime<Utc>,
+) -> Vec<(String, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let mut headers = vec![
+        (
+            "content-type".to_string(),
+            "application/x-amz-json-1.1".to_string(),
+        ),
+        ("host".to_string(), host.to_string()),
+        ("x-amz-date".to_string(), amz_date.clone()),
+    ];
+    if let Some(token) = &creds.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    headers.push(("x-amz-target".to_string(), target.to_string()));
+
+    let canonical_headers = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{}\n{}",
+        canonical_headers,
+        signed_headers,
+        sha256_hex(body)
+    );
+    let scope = format!("{}/{}/secretsmanager/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(&canonical_request)
+    );
+    let key = signing_key(&creds.secret_key, &date, region, "secretsmanager");
+    let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+    headers.push((
+        "authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            creds.access_key, scope, signed_headers, signature
+        ),
+    ));
+    // reqwest sets the host header itself
+    headers.retain(|(k, _)| k != "host");
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assumed_role_credentials, is_placeholder, sign, signing_key, sops_values, AwsCredentials, Mode, Sops,
+    };
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    #[test]
+    fn secretstore_aws_signing() {
+        // example from the aws signature version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+
+        let creds = AwsCredentials {
+            access_key: "AKIDEXAMPLE".into(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let headers = sign(
+            &creds,
+            "eu-west-2",
+            "secretsmanager.eu-west-2.amazonaws.com",
+            "secretsmanager.GetSecretValue",
+            r#"{"SecretId":"dev-uk/fake-ask/FAKE_SECRET"}"#,
+            Utc.ymd(2020, 6, 1).and_hms(12, 0, 0),
+        );
+        let auth = &headers.iter().find(|(k, _)| k == "authorization").unwrap().1;
+        assert_eq!(
+            auth,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20200601/eu-west-2/secretsmanager/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-target, \
+             Signature=c101b439abbf6dc7bcc6986197a79dfcb928c93342b9d9f35d754229c8d7aa36"
+        );
+        assert!(headers.iter().all(|(k, _)| k != "host"));
+
+        // signatures match botocore's SigV4Auth for the same requests
+        let creds = AwsCredentials {
+            session_token: Some("FQoGZXIvYXdzEXAMPLETOKEN".into()),
+            ..creds
+        };
+        let headers = sign(
+            &creds,
+            "eu-west-2",
+            "secretsmanager.eu-west-2.amazonaws.com",
+            "secretsmanager.GetSecretValue",
+            r#"{"SecretId":"dev-uk/fake-ask/FAKE_SECRET"}"#,
+            Utc.ymd(2020, 6, 1).and_hms(12, 0, 0),
+        );
+        let auth = &headers.iter().find(|(k, _)| k == "authorization").unwrap().1;
+        assert_eq!(
+            auth,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20200601/eu-west-2/secretsmanager/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-security-token;x-amz-target, \
+             Signature=9b632a7dc0ae805a970d717c5efa5fd94087d87ade8ae3343fb76c88230b8c6c"
+        );
+
+        assert!(is_placeholder("IN_VAULT"));
+        assert!(is_placeholder("IN_SECRETSTORE"));
+        assert!(!is_placeholder("in_vault"));
+    }
+
+    #[test]
+    fn secretstore_aws_web_identity() {
+        // trimmed example from the AssumeRoleWithWebIdentity documentation
+        let body = r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <SubjectFromWebIdentityToken>amzn1.account.AF6RHO7KZU5XRVQJGXK6HB56KR2A</SubjectFromWebIdentityToken>
+    <Credentials>
+      <SessionToken>AQoDYXdzEE0a8ANXXXXXXXXNO1ewxE5TijQyp+IEXAMPLE</SessionToken>
+      <SecretAccessKey>wJalrXUtnFEMI/K7MDENG/bPxRfiCYzEXAMPLEKEY</SecretAccessKey>
+      <Expiration>2014-10-24T23:00:23Z</Expiration>
+      <AccessKeyId>ASgeIAIOSFODNN7EXAMPLE</AccessKeyId>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#;
+        let creds = assumed_role_credentials(body).unwrap();
+        assert_eq!(creds.access_key, "ASgeIAIOSFODNN7EXAMPLE");
+        assert_eq!(creds.secret_key, "wJalrXUtnFEMI/K7MDENG/bPxRfiCYzEXAMPLEKEY");
+        assert_eq!(
+            creds.session_token.unwrap(),
+            "AQoDYXdzEE0a8ANXXXXXXXXNO1ewxE5TijQyp+IEXAMPLE"
+        );
+        assert!(assumed_role_credentials("<Error><Code>AccessDenied</Code></Error>").is_err());
+    }
+
+    #[test]
+    fn secretstore_sops_files() {
+        let sops = Sops::new("secrets.yml", Mode::Standard);
+        assert_eq!(
+            sops.file_of("dev-uk/fake-ask").unwrap(),
+            PathBuf::from("services/fake-ask/secrets.yml")
+        );
+        assert_eq!(
+            sops.file_of("dev-uk/shipcat").unwrap(),
+            PathBuf::from("secrets.yml")
+        );
+        assert!(sops.file_of("dev-uk").is_err());
+
+        let encrypted = "FAKE_SECRET: ENC[AES256_GCM,data:abc,type:str]
+FAKE_NUMBER: ENC[AES256_GCM,data:123,type:int]
+sops:
+  version: 3.6.1
+  mac: ENC[AES256_GCM,data:def,type:str]";
+        let keys = sops_values(encrypted)
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["FAKE_NUMBER", "FAKE_SECRET"]);
+        let decrypted = sops_values("FAKE_SECRET: hunter2\nFAKE_NUMBER: 123").unwrap();
+        assert_eq!(decrypted["FAKE_NUMBER"], "123");
+        assert!(sops_values("NESTED: {a: b}").is_err());
+    }
+}
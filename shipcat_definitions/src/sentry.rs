@@ -0,0 +1,199 @@
+use std::{collections::BTreeMap, env, sync::Mutex};
+
+use super::{Error, ErrorKind, Result, ResultExt};
+use crate::{
+    region::{Region, SentryConfig},
+    secretstore::SecretBackend,
+    vault::Mode,
+};
+
+/// Env var value replaced with the service's Sentry DSN at secret-resolution time
+pub const FROM_SENTRY: &str = "FROM_SENTRY";
+
+/// Evar that leaves `FROM_SENTRY` env vars unset rather than contacting Sentry
+pub const SKIP_SENTRY_EVAR: &str = "SHIPCAT_SKIP_SENTRY";
+
+/// DSN handed out when completing stubbed manifests
+const MOCKED_DSN: &str = "https://0000000000000000@sentry.invalid/0";
+
+/// DSNs resolved by this process, keyed by project url
+static DSN_CACHE: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Whether `FROM_SENTRY` resolution was turned off (via `--skip-sentry`)
+pub fn skipped() -> bool {
+    env::var(SKIP_SENTRY_EVAR).is_ok()
+}
+
+/// Client key of a Sentry project
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProjectKey {
+    dsn: KeyDsn,
+    #[serde(default)]
+    is_active: bool,
+}
+
+#[derive(Deserialize)]
+struct KeyDsn {
+    public: String,
+}
+
+/// Sentry api client authenticated with the region's admin token
+pub struct Sentry {
+    client: reqwest::Client,
+    config: SentryConfig,
+    token: String,
+}
+
+impl Sentry {
+    pub fn new(config: SentryConfig, token: String) -> Sentry {
+        Sentry {
+            client: reqwest::Client::new(),
+            config,
+            token,
+        }
+    }
+
+    /// Initialize from a region's sentry config and the admin token in vault
+    ///
+    /// The token is read from `{region}/shipcat/SENTRY_ADMIN_TOKEN`.
+    pub async fn regional(reg: &Region, vault: &dyn SecretBackend) -> Result<Sentry> {
+        let config = match &reg.sentry {
+            Some(s) => s.clone(),
+            None => bail!(
+                "Region {} has no sentry config to resolve {}",
+                reg.name,
+                FROM_SENTRY
+            ),
+        };
+        let vkey = format!("{}/shipcat/SENTRY_ADMIN_TOKEN", reg.name);
+        let token = vault.read(&vkey).await?;
+        Ok(Sentry::new(config, token))
+    }
+
+    fn api_url(&self, path: &str) -> Result<reqwest::Url> {
+        let base = format!("{}/api/0/", self.config.url.trim_end_matches('/'));
+        Ok(reqwest::Url::parse(&base)?.join(path)?)
+    }
+
+    /// Active client keys of a project, or `None` if the project does not exist
+    async fn project_keys(&self, slug: &str) -> Result<Option<Vec<ProjectKey>>> {
+        let url = self.api_url(&format!("projects/{}/{}/keys/", self.config.organization, slug))?;
+        debug!("GET {}", url);
+        let res = self
+            .client
+            .get(url.clone())
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .chain_err(|| ErrorKind::Url(url.clone()))?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            let err: Error = ErrorKind::UnexpectedHttpStatus(res.status()).into();
+            return Err(err).chain_err(|| ErrorKind::Url(url));
+        }
+        let keys: Vec<ProjectKey> = serde_json::from_str(&res.text().await?)?;
+        Ok(Some(keys.into_iter().filter(|k| k.is_active).collect()))
+    }
+
+    async fn post(&self, url: reqwest::Url, body: serde_json::Value) -> Result<()> {
+        debug!("POST {}", url);
+        let res = self
+            .client
+            .post(url.clone())
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .await
+            .chain_err(|| ErrorKind::Url(url.clone()))?;
+        if !res.status().is_success() {
+            let err: Error = ErrorKind::UnexpectedHttpStatus(res.status()).into();
+            return Err(err).chain_err(|| ErrorKind::Url(url));
+        }
+        Ok(())
+    }
+
+    async fn create_project(&self, slug: &str, team: &str) -> Result<()> {
+        let url = self.api_url(&format!("teams/{}/{}/projects/", self.config.organization, team))?;
+        info!("Creating sentry project {} for team {}", slug, team);
+        let body = serde_json::json!({ "name": slug, "slug": slug });
+        self.post(url, body).await
+    }
+
+    /// Mark a version of a service as deployed to an environment
+    ///
+    /// Creates the release `{slug}@{version}` in the service's project (if missing),
+    /// then records a deploy of it. Returns false when the service has no sentry project.
+    pub async fn deploy(&self, slug: &str, version: &str, environment: &str) -> Result<bool> {
+        if self.project_keys(slug).await?.is_none() {
+            return Ok(false);
+        }
+        let org = &self.config.organization;
+        let release = release_name(slug, version);
+        let url = self.api_url(&format!("organizations/{}/releases/", org))?;
+        let body = serde_json::json!({ "version": release, "projects": [slug] });
+        self.post(url, body).await?;
+        let url = self.api_url(&format!("organizations/{}/releases/{}/deploys/", org, release))?;
+        let body = serde_json::json!({ "environment": environment });
+        self.post(url, body).await?;
+        Ok(true)
+    }
+
+    /// DSN of a service's project, creating the project if it is missing
+    pub async fn dsn(&self, slug: &str) -> Result<String> {
+        let cache_key = format!("{}/{}/{}", self.config.url, self.config.organization, slug);
+        if let Some(dsn) = DSN_CACHE.lock().unwrap().get(&cache_key) {
+            return Ok(dsn.clone());
+        }
+        let keys = match self.project_keys(slug).await? {
+            Some(keys) => keys,
+            None => {
+                let team = match &self.config.team {
+                    Some(t) => t,
+                    None => bail!(
+                        "Sentry project {} does not exist, and no sentry team is configured to create it",
+                        slug
+                    ),
+                };
+                self.create_project(slug, team).await?;
+                self.project_keys(slug).await?.unwrap_or_default()
+            }
+        };
+        let dsn = match keys.into_iter().next() {
+            Some(k) => k.dsn.public,
+            None => bail!("Sentry project {} has no active client keys", slug),
+        };
+        DSN_CACHE.lock().unwrap().insert(cache_key, dsn.clone());
+        Ok(dsn)
+    }
+}
+
+/// Sentry release name of a service version
+///
+/// Releases are shared across an organization, so they are prefixed by the project.
+pub fn release_name(slug: &str, version: &str) -> String {
+    format!("{}@{}", slug, version)
+}
+
+/// Resolve the DSN for a service
+///
+/// Stubbed manifests get a placeholder DSN without contacting Sentry.
+pub async fn resolve_dsn(svc: &str, reg: &Region, vault: &dyn SecretBackend) -> Result<String> {
+    if vault.mode() == Mode::Mocked {
+        return Ok(MOCKED_DSN.into());
+    }
+    Sentry::regional(reg, vault).await?.dsn(svc).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::release_name;
+
+    #[test]
+    fn sentry_release_names() {
+        assert_eq!(release_name("fake-ask", "1.6.0"), "fake-ask@1.6.0");
+    }
+}
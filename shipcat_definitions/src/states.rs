@@ -1,10 +1,13 @@
-use super::{vault::Vault, Manifest, Region, Result};
+use super::{Manifest, Region, Result};
+use schemars::JsonSchema;
 
 /// Type of primary workload that is associated with the Manifest
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum PrimaryWorkload {
     Deployment,
     Statefulset,
+    /// One pod on every node, like log shippers and node agents
+    DaemonSet,
 }
 
 impl ToString for PrimaryWorkload {
@@ -85,16 +88,18 @@ impl Manifest {
     async fn upgrade(mut self, reg: &Region, state: ManifestState) -> Result<Self> {
         assert_eq!(self.state, ManifestState::Base); // sanity
         let v = match state {
-            ManifestState::Completed => Vault::regional(&reg.vault)?,
-            ManifestState::Stubbed => Vault::mocked(&reg.vault)?,
+            ManifestState::Completed => reg.secret_store()?,
+            ManifestState::Stubbed => reg.mocked_secret_store()?,
             _ => bail!("Can only upgrade a Base manifest to Completed or Stubbed"),
         };
         // replace one-off templates in evar strings with values
         // note that this happens before secrets because:
         // secrets may be injected at this step from the Region
         self.template_evars(reg)?;
+        // sentry dsns end up alongside the vault secrets
+        self.sentry_dsns(&*v, reg).await?;
         // secrets before configs (.j2 template files use raw secret values)
-        self.secrets(&v, &reg.vault).await?;
+        self.secrets(&*v, &reg.vault).await?;
 
         // templates last
         self.template_configs(reg)?;
@@ -121,7 +126,7 @@ impl Manifest {
 /// Various states a Config can exist in depending on resolution.
 ///
 /// Within shipcat, this is used to optimize speed of accessors.
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
 pub enum ConfigState {
     /// A filtered config for a specific region, with resolved secrets
     Filtered,
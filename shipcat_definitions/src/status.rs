@@ -1,11 +1,33 @@
-use super::Result;
+use super::{structs::Container, Result};
 use chrono::{SecondsFormat, Utc};
+use schemars::JsonSchema;
 
 pub fn make_date() -> String {
     // Format == `1996-12-19T16:39:57-08:00`, but we hardcode Utc herein.
     Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
 }
 
+/// Human readable time elapsed since an RFC3339 date string
+pub fn format_time_since(ts: &str) -> Result<String> {
+    use chrono::{DateTime, Duration};
+    let last = ts.parse::<DateTime<Utc>>()?;
+    let diff: Duration = Utc::now() - last;
+    let days = diff.num_days();
+    let hours = diff.num_hours();
+    let mins = diff.num_minutes();
+    let diff_fmt = if days >= 1 {
+        let plural = if days > 1 { "s" } else { "" };
+        format!("{} day{}", days, plural)
+    } else if hours >= 1 {
+        let plural = if hours > 1 { "s" } else { "" };
+        format!("{} hour{}", hours, plural)
+    } else {
+        let plural = if mins > 1 { "s" } else { "" };
+        format!("{} minute{}", mins, plural)
+    };
+    Ok(diff_fmt)
+}
+
 /// Status object for shipcatmanifests crd
 ///
 /// All fields optional, but we try to ensure all fields exist.
@@ -18,9 +40,173 @@ pub struct ManifestStatus {
     /// A more easily readable summary of why the conditions are what they are
     #[serde(default)]
     pub summary: Option<ConditionSummary>,
+    /// Active canary deployment receiving a share of the traffic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanaryStatus>,
+    /// State of the last upgrade, written at every transition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade: Option<UpgradeStatus>,
+    /// Most recent smoke test runs after rollouts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub smoke_tests: Vec<SmokeTestRun>,
+    /// Most recent finished rollouts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rollouts: Vec<RolloutRecord>,
     /* TODO: vault secret hash
-     * MAYBE: kong status?
-     * MAYBE: canary status? */
+     * MAYBE: kong status? */
+}
+
+/// Traffic split for an active canary
+///
+/// Written by the canary rollout process, and read by `shipcat kong`
+/// to split traffic for services fronted by kong.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CanaryStatus {
+    /// Name of the kubernetes service fronting the canary pods
+    pub service: String,
+    /// Percentage of traffic sent to the canary
+    pub weight: u32,
+    /// Version running in the canary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// The different states an upgrade can be in
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpgradeState {
+    /// Before action
+    Pending,
+    /// Action was cancelled before start
+    Cancelled,
+    // Action has started
+    Started,
+    /// No errors
+    Completed,
+    /// Errors
+    Failed,
+}
+
+/// Progress of an upgrade through the `UpgradeState` machine
+///
+/// Persisted so `shipcat resume` can pick up a rollout whose apply process died.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpgradeStatus {
+    pub state: UpgradeState,
+    /// Version being upgraded to
+    pub version: String,
+    /// Whether the version is rolled out as a canary
+    #[serde(default)]
+    pub canary: bool,
+    /// Date string (RFC3339) of when the upgrade started applying
+    #[serde(default)]
+    pub started: Option<String>,
+    /// Date string (RFC3339) of the last transition
+    pub last_transition: String,
+    /// Originator of the upgrade
+    #[serde(default)]
+    pub source: Option<Applier>,
+    /// Sidecars and init containers whose versions the upgrade changes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<ContainerChange>,
+}
+
+/// Version change of a sidecar or init container in an upgrade
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerChange {
+    pub name: String,
+    /// Whether this is an init container rather than a sidecar
+    #[serde(default)]
+    pub init: bool,
+    /// Version before the upgrade, unless the container is new
+    #[serde(default)]
+    pub old: Option<String>,
+    /// Version after the upgrade, unless the container was removed
+    #[serde(default)]
+    pub new: Option<String>,
+}
+
+impl ContainerChange {
+    /// Containers that were added, removed, or changed version between two sets of containers
+    pub fn between(init: bool, old: &[Container], new: &[Container]) -> Vec<ContainerChange> {
+        let mut changes = vec![];
+        for c in new {
+            let before = old.iter().find(|o| o.name == c.name);
+            let (old_version, new_version) =
+                (before.and_then(Container::version_or_image), c.version_or_image());
+            if before.is_none() || old_version != new_version {
+                changes.push(ContainerChange {
+                    name: c.name.clone(),
+                    init,
+                    old: old_version,
+                    new: new_version,
+                });
+            }
+        }
+        for o in old.iter().filter(|o| !new.iter().any(|c| c.name == o.name)) {
+            changes.push(ContainerChange {
+                name: o.name.clone(),
+                init,
+                old: o.version_or_image(),
+                new: None,
+            });
+        }
+        changes
+    }
+
+    /// Human readable summary, like ``sidecar `envoy` v1.14.1 -> v1.15.0``
+    pub fn describe(&self) -> String {
+        let kind = if self.init { "init container" } else { "sidecar" };
+        match (&self.old, &self.new) {
+            (Some(o), Some(n)) => format!("{} `{}` {} -> {}", kind, self.name, o, n),
+            (None, Some(n)) => format!("{} `{}` added at {}", kind, self.name, n),
+            (Some(_), None) => format!("{} `{}` removed", kind, self.name),
+            (None, None) => format!("{} `{}` changed", kind, self.name),
+        }
+    }
+}
+
+/// A finished rollout
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutRecord {
+    /// Version that was rolled out
+    pub version: String,
+    /// Date string (RFC3339) of when the rollout finished
+    pub time: String,
+    /// Whether the rollout succeeded
+    pub succeeded: bool,
+    /// Id of the grafana annotation marking the rollout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<u64>,
+}
+
+/// Smoke tests run after a rollout
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SmokeTestRun {
+    /// Version that was rolled out
+    pub version: String,
+    /// Date string (RFC3339) of when the tests finished
+    pub time: String,
+    /// Whether every test passed
+    pub passed: bool,
+    pub results: Vec<SmokeTestResult>,
+}
+
+/// Outcome of a single smoke test
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SmokeTestResult {
+    /// Service declaring the test (the rolled out service or one of its dependents)
+    pub service: String,
+    pub name: String,
+    pub passed: bool,
+    /// What was checked, or why it failed
+    pub message: String,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
@@ -93,6 +279,24 @@ pub struct ConditionSummary {
     /// Last version that was successfully rolled out
     #[serde(default)]
     pub last_successful_rollout_version: Option<String>,
+
+    /// Date string (RFC3339) of when the workloads were last restarted
+    #[serde(default)]
+    pub last_restart: Option<String>,
+
+    /// Reason given for the last restart
+    #[serde(default)]
+    pub last_restart_reason: Option<String>,
+
+    /// Originator of the last restart
+    #[serde(default)]
+    pub last_restart_source: Option<Applier>,
+
+    /// Longest image pull on a node during the last tracked rollout, in seconds
+    ///
+    /// Used to estimate the wait time of the next rollout.
+    #[serde(default)]
+    pub last_image_pull_seconds: Option<u32>,
 }
 
 /// Condition
@@ -159,24 +363,7 @@ impl Condition {
     }
 
     pub fn format_last_transition(&self) -> Result<String> {
-        use chrono::{DateTime, Duration};
-        let old_ts = &self.last_transition;
-        let last = old_ts.parse::<DateTime<Utc>>()?;
-        let diff: Duration = Utc::now() - last;
-        let days = diff.num_days();
-        let hours = diff.num_hours();
-        let mins = diff.num_minutes();
-        let diff_fmt = if days >= 1 {
-            let plural = if days > 1 { "s" } else { "" };
-            format!("{} day{}", days, plural)
-        } else if hours >= 1 {
-            let plural = if hours > 1 { "s" } else { "" };
-            format!("{} hour{}", hours, plural)
-        } else {
-            let plural = if mins > 1 { "s" } else { "" };
-            format!("{} minute{}", mins, plural)
-        };
-        Ok(diff_fmt)
+        format_time_since(&self.last_transition)
     }
 
     pub fn html_list_item(&self) -> Result<String> {
@@ -206,8 +393,57 @@ impl Condition {
 
 #[cfg(test)]
 mod tests {
-    use super::{Applier, Condition};
+    use super::{Applier, Condition, ContainerChange, ManifestStatus, UpgradeState};
+    use crate::structs::Container;
     use chrono::{prelude::*, Utc};
+
+    #[test]
+    fn upgrade_status_format() {
+        let status: ManifestStatus = serde_yaml::from_str(
+            r#"
+upgrade:
+  state: STARTED
+  version: 1.2.0
+  lastTransition: "2020-04-01T03:00:00Z"
+  started: "2020-04-01T03:00:00Z"
+"#,
+        )
+        .unwrap();
+        let upgrade = status.upgrade.unwrap();
+        assert_eq!(upgrade.state, UpgradeState::Started);
+        assert!(!upgrade.canary);
+        // statuses written before upgrades were recorded still parse
+        let old: ManifestStatus = serde_yaml::from_str("conditions: {}").unwrap();
+        assert!(old.upgrade.is_none());
+    }
+
+    #[test]
+    fn container_changes() {
+        let container = |name: &str, version: &str| Container {
+            name: name.into(),
+            image: Some("envoyproxy/envoy".into()),
+            version: Some(version.into()),
+            ..Container::default()
+        };
+        let old = vec![container("envoy", "v1.14.1"), container("statsd", "1.0")];
+        let new = vec![
+            container("envoy", "v1.15.0"),
+            container("statsd", "1.0"),
+            container("vault", "2"),
+        ];
+        let changes = ContainerChange::between(false, &old, &new);
+        let summary = changes.iter().map(ContainerChange::describe).collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "sidecar `envoy` v1.14.1 -> v1.15.0",
+            "sidecar `vault` added at 2"
+        ]);
+
+        let removed = ContainerChange::between(true, &new, &old);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[1].describe(), "init container `vault` removed");
+        assert!(ContainerChange::between(false, &old, &old).is_empty());
+    }
+
     #[test]
     #[ignore]
     fn check_conditions() {
@@ -0,0 +1,77 @@
+use kube_derive::CustomResource;
+use std::collections::BTreeMap;
+
+/// A topic managed by the strimzi topic operator
+///
+/// Only the fields shipcat compares against `kafkaResources` are read.
+/// [Strimzi Kafka Topic CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/topic-operator/04-Crd-kafkatopic.yaml)
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[kube(
+    group = "kafka.strimzi.io",
+    kind = "KafkaTopic",
+    version = "v1beta1",
+    namespaced
+)]
+#[kube(apiextensions = "v1beta1")]
+pub struct KafkaTopicSpec {
+    /// Name of the topic in kafka when it cannot be the object name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topicName: Option<String>,
+    #[serde(default)]
+    pub partitions: i32,
+    #[serde(default)]
+    pub replicas: i32,
+    /// Topic config, with values as strings or numbers
+    #[serde(default)]
+    pub config: BTreeMap<String, serde_json::Value>,
+}
+
+/// A user managed by the strimzi user operator
+///
+/// [Strimzi Kafka User CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/user-operator/04-Crd-kafkauser.yaml)
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[kube(
+    group = "kafka.strimzi.io",
+    kind = "KafkaUser",
+    version = "v1beta1",
+    namespaced
+)]
+#[kube(apiextensions = "v1beta1")]
+pub struct KafkaUserSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<KafkaUserAuthorization>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KafkaUserAuthorization {
+    #[serde(default)]
+    pub acls: Vec<KafkaUserAcl>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KafkaUserAcl {
+    pub resource: KafkaAclResource,
+    pub operation: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KafkaAclResource {
+    #[serde(rename = "type")]
+    pub resourceType: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patternType: Option<String>,
+}
+
+impl KafkaTopic {
+    /// Name of the topic in kafka
+    pub fn topic_name(&self) -> String {
+        self.spec
+            .topicName
+            .clone()
+            .unwrap_or_else(|| self.metadata.name.clone().unwrap_or_default())
+    }
+}
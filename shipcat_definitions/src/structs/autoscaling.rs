@@ -4,9 +4,11 @@
 
 use super::Result;
 use k8s_openapi::api::autoscaling::v2beta2::MetricSpec;
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
 
 /// Configuration parameters for HorizontalPodAutoScaler
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct AutoScaling {
     pub minReplicas: u32,
     pub maxReplicas: u32,
@@ -21,7 +23,148 @@ pub struct AutoScaling {
     /// If not set, the default metric will be set to 80% average CPU utilization.
     ///
     /// The maximum replica count across all metrics will be used.
+    #[serde(default)]
+    #[schemars(with = "Vec<serde_json::Value>")]
     pub metrics: Vec<MetricSpec>,
+
+    /// Scaling policies for scaling up and down (HPA v2 behavior)
+    ///
+    /// Needs kubernetes 1.18 or later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<ScalingBehavior>,
+
+    /// Event driven scaling through KEDA
+    ///
+    /// Renders a KEDA ScaledObject instead of a HorizontalPodAutoscaler,
+    /// and cannot be combined with `metrics`.
+    ///
+    /// ```yaml
+    /// autoScaling:
+    ///   minReplicas: 0
+    ///   maxReplicas: 10
+    ///   keda:
+    ///     cooldownPeriod: 300
+    ///     triggers:
+    ///     - type: rabbitmq
+    ///       metadata:
+    ///         queueName: orders
+    ///         queueLength: "20"
+    ///       authenticationRef:
+    ///         name: rabbitmq-auth
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keda: Option<Keda>,
+}
+
+/// Scaling behavior of a HorizontalPodAutoscaler in each direction
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ScalingBehavior {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaleUp: Option<ScalingRules>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaleDown: Option<ScalingRules>,
+}
+
+/// Scaling policies for one direction
+///
+/// ```yaml
+/// scaleDown:
+///   stabilizationWindowSeconds: 300
+///   policies:
+///   - type: Percent
+///     value: 10
+///     periodSeconds: 60
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ScalingRules {
+    /// Seconds of past recommendations considered before scaling (at most an hour)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stabilizationWindowSeconds: Option<u32>,
+    /// Which policy is used when several apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selectPolicy: Option<ScalingPolicySelect>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<ScalingPolicy>,
+}
+
+/// A limit on how much can be scaled within a period
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ScalingPolicy {
+    #[serde(rename = "type")]
+    pub policyType: ScalingPolicyType,
+    pub value: u32,
+    /// Period the policy applies over (at most 30 minutes)
+    pub periodSeconds: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum ScalingPolicyType {
+    Pods,
+    Percent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum ScalingPolicySelect {
+    Max,
+    Min,
+    Disabled,
+}
+
+/// KEDA ScaledObject parameters
+///
+/// Straight from [KEDA scaling deployments](https://keda.sh/docs/2.0/concepts/scaling-deployments/).
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Keda {
+    /// Seconds between checks of the triggers (KEDA defaults to 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pollingInterval: Option<u32>,
+    /// Seconds after the last active trigger before scaling to `minReplicas` (KEDA defaults to 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldownPeriod: Option<u32>,
+    pub triggers: Vec<KedaTrigger>,
+}
+
+/// An event source that KEDA scales on
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct KedaTrigger {
+    /// Scaler type, like `rabbitmq`, `kafka` or `aws-sqs-queue`
+    #[serde(rename = "type")]
+    pub triggerType: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Scaler specific parameters
+    pub metadata: BTreeMap<String, String>,
+    /// TriggerAuthentication holding the credentials of the event source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticationRef: Option<KedaAuthenticationRef>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct KedaAuthenticationRef {
+    pub name: String,
+}
+
+impl ScalingRules {
+    fn verify(&self, direction: &str) -> Result<()> {
+        if self.stabilizationWindowSeconds.map_or(false, |s| s > 3600) {
+            bail!("{} stabilizationWindowSeconds cannot exceed 3600", direction);
+        }
+        for p in &self.policies {
+            if p.value == 0 {
+                bail!("{} policies need a value above 0", direction);
+            }
+            if p.periodSeconds == 0 || p.periodSeconds > 1800 {
+                bail!("{} policies need a periodSeconds between 1 and 1800", direction);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl AutoScaling {
@@ -29,6 +172,66 @@ impl AutoScaling {
         if self.minReplicas > self.maxReplicas {
             bail!("maxReplicas must be > minReplicas");
         }
+        if let Some(b) = &self.behavior {
+            if let Some(up) = &b.scaleUp {
+                up.verify("scaleUp")?;
+            }
+            if let Some(down) = &b.scaleDown {
+                down.verify("scaleDown")?;
+            }
+        }
+        if let Some(k) = &self.keda {
+            if !self.metrics.is_empty() {
+                bail!("autoScaling cannot use both metrics and keda triggers");
+            }
+            if k.triggers.is_empty() {
+                bail!("autoScaling with keda needs at least one trigger");
+            }
+        } else if self.minReplicas == 0 {
+            bail!("minReplicas can only be 0 when scaling with keda");
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AutoScaling;
+
+    #[test]
+    fn autoscaling_behavior_and_keda() {
+        let mut hpa: AutoScaling = serde_yaml::from_str(
+            "minReplicas: 2
+maxReplicas: 6
+behavior:
+  scaleDown:
+    stabilizationWindowSeconds: 300
+    policies:
+    - type: Percent
+      value: 10
+      periodSeconds: 60",
+        )
+        .unwrap();
+        assert!(hpa.verify().is_ok());
+        let rules = hpa.behavior.as_mut().and_then(|b| b.scaleDown.as_mut()).unwrap();
+        rules.policies[0].periodSeconds = 3600;
+        assert!(hpa.verify().is_err());
+
+        let mut keda: AutoScaling = serde_yaml::from_str(
+            "minReplicas: 0
+maxReplicas: 10
+keda:
+  triggers:
+  - type: rabbitmq
+    metadata:
+      queueName: orders
+      queueLength: \"20\"",
+        )
+        .unwrap();
+        assert!(keda.verify().is_ok());
+        keda.keda.as_mut().unwrap().triggers.clear();
+        assert!(keda.verify().is_err());
+        keda.keda = None;
+        assert!(keda.verify().is_err()); // minReplicas 0 needs keda
+    }
+}
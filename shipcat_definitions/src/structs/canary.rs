@@ -0,0 +1,72 @@
+use super::Result;
+
+/// Canary deployment of a new version next to the main workload
+///
+/// Injected into the chart while a canary started by `shipcat apply --canary` is active.
+/// Charts render it as a Deployment and Service named after the canary,
+/// and kong splits traffic between the main and canary services by `weight`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Canary {
+    /// Name of the canary Deployment, Service and container
+    pub name: String,
+    /// Version aka. tag of the docker image the canary runs
+    pub version: String,
+    /// Percentage of traffic sent to the canary
+    pub weight: u32,
+    /// Replicas of the canary Deployment
+    ///
+    /// The same share of the main workload's replicas, and at least one.
+    pub replicaCount: u32,
+}
+
+impl Canary {
+    /// Canary of a service running `replicas` replicas in its main workload
+    pub fn new(svc: &str, version: String, weight: u32, replicas: u32) -> Canary {
+        let share = (f64::from(replicas) * f64::from(weight) / 100.0).ceil() as u32;
+        Canary {
+            name: Canary::name_for(svc),
+            version,
+            weight,
+            replicaCount: std::cmp::max(1, share),
+        }
+    }
+
+    /// Name of the canary resources of a service
+    pub fn name_for(svc: &str) -> String {
+        format!("{}-canary", svc)
+    }
+
+    /// Parse a traffic percentage like `10%`
+    ///
+    /// Canaries must take some, but not all of the traffic.
+    pub fn parse_weight(s: &str) -> Result<u32> {
+        let digits = s.trim_end_matches('%');
+        let weight: u32 = match digits.parse() {
+            Ok(w) => w,
+            Err(_) => bail!("Canary weight '{}' is not a percentage like 10%", s),
+        };
+        if weight == 0 || weight >= 100 {
+            bail!("Canary weight must be between 1% and 99%, got {}", s);
+        }
+        Ok(weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Canary;
+
+    #[test]
+    fn canary_weight_and_replicas() {
+        assert_eq!(Canary::parse_weight("10%").unwrap(), 10);
+        assert_eq!(Canary::parse_weight("25").unwrap(), 25);
+        assert!(Canary::parse_weight("0%").is_err());
+        assert!(Canary::parse_weight("100%").is_err());
+        assert!(Canary::parse_weight("ten%").is_err());
+
+        let c = Canary::new("fake-ask", "1.1.0".into(), 10, 4);
+        assert_eq!(c.name, "fake-ask-canary");
+        assert_eq!(c.replicaCount, 1);
+        assert_eq!(Canary::new("fake-ask", "1.1.0".into(), 50, 5).replicaCount, 3);
+    }
+}
@@ -1,4 +1,5 @@
 use super::Result;
+use schemars::JsonSchema;
 
 /// ConfigMap
 ///
@@ -6,7 +7,7 @@ use super::Result;
 /// Deals with automatic mounting into the pods.
 ///
 /// Only one of these is supported.
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct ConfigMap {
     /// Container-local directory path where configs are available
@@ -18,7 +19,7 @@ pub struct ConfigMap {
 /// ConfigMapped File
 ///
 /// Files that are mounted under the parent `mount` path.
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct ConfigMappedFile {
     /// Name of file to template (from service repo paths)
@@ -30,6 +31,11 @@ pub struct ConfigMappedFile {
     /// This is usually filled in internally by to help out Helm a bit
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub value: Option<String>,
+    /// Whether the templated value contains secrets
+    ///
+    /// Set when templating so diffs can mask the file.
+    #[serde(skip)]
+    pub secret: bool,
 }
 
 impl ConfigMap {
@@ -53,4 +59,13 @@ impl ConfigMap {
         // TODO: verify file exists? done later anyway
         Ok(())
     }
+
+    /// Config map keys of files templated with secrets
+    pub fn secret_keys(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|f| f.secret)
+            .map(|f| f.dest.clone())
+            .collect()
+    }
 }
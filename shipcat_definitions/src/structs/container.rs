@@ -38,3 +38,10 @@ pub struct Container {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub volume_mounts: Vec<VolumeMount>,
 }
+
+impl Container {
+    /// Version of the container, or its image when the tag is part of it
+    pub fn version_or_image(&self) -> Option<String> {
+        self.version.clone().or_else(|| self.image.clone())
+    }
+}
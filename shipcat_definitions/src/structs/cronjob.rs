@@ -1,7 +1,8 @@
 use super::Container;
+use schemars::JsonSchema;
 use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct JobVolumeClaim {
     /// The cron job name
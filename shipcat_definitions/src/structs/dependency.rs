@@ -1,10 +1,12 @@
 use super::Result;
-use std::path::Path;
+use schemars::JsonSchema;
+use semver::VersionReq;
+use std::{ops::Not, path::Path};
 
 /// Supported dependency protocols
 ///
 /// Forces lowercase values of this enum to be used
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyProtocol {
     /// HTTP REST dependency
@@ -24,8 +26,16 @@ impl Default for DependencyProtocol {
     }
 }
 
+/// How the NetworkPolicies of a service are made
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPolicyMode {
+    /// Only admit ingress from services declaring the service as a dependency
+    Generated,
+}
+
 /// Dependency of a service
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Dependency {
     /// Name of service relied upon (used to goto dependent manifest)
@@ -40,6 +50,27 @@ pub struct Dependency {
     pub protocol: DependencyProtocol,
     /// Intent behind dependency - for manifest level descriptiveness
     pub intent: Option<String>,
+    /// Latency budget for calls to the dependency in milliseconds
+    ///
+    /// Used to sum up latencies along dependency chains in `shipcat get latency-budgets`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latencyBudgetMs: Option<u32>,
+    /// Whether the service cannot serve requests without the dependency
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub critical: bool,
+    /// Range of dependency versions the service is compatible with
+    ///
+    /// ```yaml
+    /// dependencies:
+    /// - name: auth
+    ///   version: ">=2.3"
+    /// ```
+    ///
+    /// Checked against the dependency's declared version in `shipcat validate`,
+    /// and against its running version in `shipcat apply --strict-deps`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub version: Option<VersionReq>,
 }
 
 fn default_api_version() -> String {
@@ -0,0 +1,86 @@
+use super::Result;
+use chrono::{NaiveDate, Utc};
+use schemars::JsonSchema;
+
+/// A temporary disable of a service in a single region
+///
+/// Keeps the region in `regions` (and thus its history) while excluding the
+/// service from reconciles in that region.
+///
+/// ```yaml
+/// disabledIn:
+/// - region: dev-uk
+///   reason: "Waiting for new database cluster"
+///   until: 2020-06-01
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct DisabledRegion {
+    /// Region the service is disabled in
+    pub region: String,
+    /// Human readable reason for disabling the service
+    pub reason: String,
+    /// Date the disable is expected to be lifted by
+    ///
+    /// Validation fails once this date has passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<NaiveDate>,
+}
+
+impl DisabledRegion {
+    /// Whether the disable has outlived its `until` date
+    pub fn is_expired(&self) -> bool {
+        match self.until {
+            Some(u) => u < Utc::today().naive_utc(),
+            None => false,
+        }
+    }
+
+    pub fn verify(&self, svc: &str, regions: &[String]) -> Result<()> {
+        if !regions.contains(&self.region) {
+            bail!(
+                "{} is disabledIn {} which is not in its list of regions",
+                svc,
+                self.region
+            );
+        }
+        if self.reason.is_empty() {
+            bail!("{} needs a reason for being disabled in {}", svc, self.region);
+        }
+        if self.is_expired() {
+            bail!(
+                "{} disable in {} expired on {} ({}) - re-enable it or extend the date",
+                svc,
+                self.region,
+                self.until.unwrap(),
+                self.reason
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisabledRegion;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn disabled_region_expiry() {
+        let regions = vec!["dev-uk".to_string()];
+        let mut dr: DisabledRegion =
+            serde_yaml::from_str("region: dev-uk\nreason: migrating databases\nuntil: 2999-01-01").unwrap();
+        assert_eq!(dr.until, Some(NaiveDate::from_ymd(2999, 1, 1)));
+        assert!(!dr.is_expired());
+        assert!(dr.verify("fake-ask", &regions).is_ok());
+
+        dr.until = Some(NaiveDate::from_ymd(2000, 1, 1));
+        assert!(dr.is_expired());
+        assert!(dr.verify("fake-ask", &regions).is_err());
+
+        dr.until = None;
+        assert!(dr.verify("fake-ask", &regions).is_ok());
+        dr.region = "prod-uk".into();
+        assert!(dr.verify("fake-ask", &regions).is_err());
+    }
+}
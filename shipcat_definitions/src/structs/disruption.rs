@@ -1,73 +1,130 @@
-/// Source for PodDisruptionBudget representation
-///
-/// Ultimately, this is left unused and unimported due to significant limitations in kube.
-/// In particular; https://github.com/kubernetes/kubernetes/issues/45398
-/// which causes us to not reliably be able to change them.
-///
-/// This work is left here in case it becomes useful.
-/// Users may wish to look at rollingupdate.rs instead, which has a useful alternative.
-
-use super::{Result};
-
-// Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum AvailabilityPolicy {
-    Percentage(String),
-    Unsigned(u32),
-}
-// Kube has a weird hybrid type for this intstr.IntOrString: IntVal | StrVal
-// if it's a string, then '[0-9]+%!' has to parse
-impl AvailabilityPolicy {
-    fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
-        match self {
-            AvailabilityPolicy::Unsigned(ref n) => {
-                if n > &maxNumber {
-                    bail!("Cannot have {} set higher than replicaCount {}", name, maxNumber);
-                }
-            },
-            AvailabilityPolicy::Percentage(s) => {
-                if !s.ends_with('%') {
-                    bail!("{} must end with a '%' sign", name);
-                }
-                let digits = s.chars().take_while(|ch| *ch != '%').collect::<String>();
-                let res : u32 = digits.parse()?;
-                if res > 100 {
-                    bail!("Percentage value for {} cannot exceed 100", name);
-                }
-            }
-        }
-        Ok(())
-    }
-}
+use super::{rollingupdate::AvailabilityPolicy, Result, RollingUpdate};
+use schemars::JsonSchema;
 
 /// PodDisruptionBudget representation
 ///
-/// Users need to set exactly one of these to pass validation.
+/// Limits how many pods of the main workload voluntary disruptions (like node drains)
+/// can take down at once. Users need to set exactly one of these to pass validation.
 /// The values are "how many replicas" when integer values are used,
 /// and "what percentage of total replicas" when a % is added to the string.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct DisruptionBudget {
+///
+/// NB: changing a budget requires kubernetes 1.15 (https://github.com/kubernetes/kubernetes/issues/45398)
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PodDisruptionBudget {
+    /// How many replicas or percentage of replicas that must stay available
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub minAvailable: Option<AvailabilityPolicy>,
+    /// How many replicas or percentage of replicas that can be evicted at once
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub maxUnavailable: Option<AvailabilityPolicy>,
 }
 
-impl DisruptionBudget {
-     pub fn verify(&self, replicas: u32) -> Result<()> {
+impl PodDisruptionBudget {
+    /// Default budget for a service with `replicas` replicas
+    ///
+    /// Autoscaled services use a percentage to follow the replica count.
+    /// Singletons can still be evicted, as anything stricter would block node drains.
+    pub fn derived(replicas: u32, autoscaled: bool) -> Self {
+        let maxUnavailable = if autoscaled {
+            AvailabilityPolicy::Percentage("25%".into())
+        } else {
+            AvailabilityPolicy::Unsigned(std::cmp::max(1, replicas / 4))
+        };
+        PodDisruptionBudget {
+            minAvailable: None,
+            maxUnavailable: Some(maxUnavailable),
+        }
+    }
+
+    /// How many pods can be evicted at once with `replicas` replicas
+    ///
+    /// Percentages are rounded up like kubernetes does for disruption budgets.
+    fn allowed_disruptions(&self, replicas: u32) -> u32 {
+        if let Some(ma) = &self.minAvailable {
+            replicas.saturating_sub(ma.to_replicas_ceil(replicas))
+        } else if let Some(mu) = &self.maxUnavailable {
+            mu.to_replicas_ceil(replicas)
+        } else {
+            0
+        }
+    }
+
+    pub fn verify(&self, replicas: u32, rollingUpdate: Option<&RollingUpdate>) -> Result<()> {
         if self.minAvailable.is_none() && self.maxUnavailable.is_none() {
-            bail!("Need to set one of minAvailable or maxUnavailable in disruptionBudget");
+            bail!("Need to set one of minAvailable or maxUnavailable in podDisruptionBudget");
         }
         if self.minAvailable.is_some() && self.maxUnavailable.is_some() {
-            bail!("Cannot set both minAvailable and maxUnavailable in disruptionBudget");
+            bail!("Cannot set both minAvailable and maxUnavailable in podDisruptionBudget");
         }
-        if let Some(ref ma) = &self.minAvailable {
+        if let Some(ma) = &self.minAvailable {
             ma.verify("minAvailable", replicas)?;
         }
-        if let Some(ref mu) = &self.maxUnavailable {
+        if let Some(mu) = &self.maxUnavailable {
             mu.verify("maxUnavailable", replicas)?;
         }
+        let allowed = self.allowed_disruptions(replicas);
+        if allowed == 0 {
+            bail!(
+                "podDisruptionBudget allows no evictions with {} replicas, which blocks node drains",
+                replicas
+            );
+        }
+        // drains should not take down more pods than rollouts do
+        if let Some(mu) = rollingUpdate.and_then(|ru| ru.maxUnavailable.as_ref()) {
+            let rollout = std::cmp::max(1, mu.to_replicas_ceil(replicas));
+            if allowed > rollout {
+                bail!(
+                    "podDisruptionBudget allows {} evictions, more than the {} unavailable in rollingUpdate",
+                    allowed,
+                    rollout
+                );
+            }
+        }
         Ok(())
-     }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AvailabilityPolicy, PodDisruptionBudget};
+    use crate::structs::RollingUpdate;
+
+    #[test]
+    fn disruption_budget_defaults() {
+        let ru = RollingUpdate::default();
+        for replicas in 1..20 {
+            let pdb = PodDisruptionBudget::derived(replicas, false);
+            assert!(pdb.verify(replicas, Some(&ru)).is_ok());
+            let pdb = PodDisruptionBudget::derived(replicas, true);
+            assert!(pdb.verify(replicas, Some(&ru)).is_ok());
+        }
+        let pdb = PodDisruptionBudget::derived(8, false);
+        assert_eq!(pdb.allowed_disruptions(8), 2);
+    }
+
+    #[test]
+    fn disruption_budget_verify() {
+        // singletons must stay evictable
+        let pdb = PodDisruptionBudget {
+            minAvailable: Some(AvailabilityPolicy::Unsigned(1)),
+            maxUnavailable: None,
+        };
+        assert!(pdb.verify(1, None).is_err());
+        assert!(pdb.verify(2, None).is_ok());
+
+        // cannot be more lenient than rolling updates
+        let pdb = PodDisruptionBudget {
+            minAvailable: None,
+            maxUnavailable: Some(AvailabilityPolicy::Percentage("50%".into())),
+        };
+        assert!(pdb.verify(8, None).is_ok());
+        assert!(pdb.verify(8, Some(&RollingUpdate::default())).is_err());
+
+        let both = PodDisruptionBudget {
+            minAvailable: Some(AvailabilityPolicy::Unsigned(1)),
+            maxUnavailable: Some(AvailabilityPolicy::Unsigned(1)),
+        };
+        assert!(both.verify(4, None).is_err());
+    }
 }
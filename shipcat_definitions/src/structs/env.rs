@@ -1,10 +1,13 @@
 use super::Result;
+use crate::{secretstore, sentry::FROM_SENTRY};
 use std::collections::{BTreeMap, BTreeSet};
 
 /// Environment variables to inject
 ///
 /// These have a few special convenience behaviours:
 /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/KEY
+/// "IN_SECRETSTORE" values is the same, but reads as intended in regions not using vault
+/// "FROM_SENTRY" values is replaced with the DSN of the service's Sentry project
 /// One off `tera` templates are calculated with a limited template context
 ///
 /// IN_VAULT secrets will all be put in a single kubernetes `Secret` object.
@@ -18,6 +21,9 @@ use std::collections::{BTreeMap, BTreeSet};
 ///   # vault lookup:
 ///   DATABASE_URL: IN_VAULT
 ///
+///   # sentry project dsn:
+///   SENTRY_DSN: FROM_SENTRY
+///
 ///   # templated evars:
 ///   INTERNAL_AUTH_URL: "{{ base_urls.services }}/auth/internal"
 /// ```
@@ -52,7 +58,7 @@ impl EnvVars {
     }
 
     fn is_vault_secret(value: &str) -> bool {
-        value == "IN_VAULT"
+        secretstore::is_placeholder(value)
     }
 
     fn template_secret_value(value: &str) -> Option<String> {
@@ -73,7 +79,7 @@ impl EnvVars {
         Ok(())
     }
 
-    // Remove variables with a value "IN_VAULT", mark them as a secret and return them.
+    // Remove variables with a value "IN_VAULT" or "IN_SECRETSTORE", mark them as a secret and return them.
     pub fn vault_secrets(&mut self) -> BTreeSet<String> {
         let mut plain = BTreeMap::new();
         let mut vs = BTreeSet::new();
@@ -89,6 +95,21 @@ impl EnvVars {
         vs
     }
 
+    // Remove variables with a value "FROM_SENTRY", mark them as a secret and return them.
+    pub fn sentry_secrets(&mut self) -> BTreeSet<String> {
+        let keys = self
+            .plain
+            .iter()
+            .filter(|(_, v)| v.as_str() == FROM_SENTRY)
+            .map(|(k, _)| k.to_string())
+            .collect::<BTreeSet<_>>();
+        for k in &keys {
+            self.plain.remove(k);
+            self.secrets.insert(k.to_string());
+        }
+        keys
+    }
+
     // Remove secrets generated from templates from the plain variables, mark them as a secret and return them.
     pub fn template_secrets(&mut self) -> BTreeMap<String, String> {
         let mut plain = BTreeMap::new();
@@ -1,14 +1,166 @@
 use super::Result;
-use std::collections::BTreeMap;
+use schemars::JsonSchema;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+/// Key and value schemas of events on a stream
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct EventDefinition {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// Schema formats understood by the schema registry
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SchemaType {
+    Avro,
+    Json,
+}
+
+const AVRO_PRIMITIVES: &[&str] = &[
+    "null", "boolean", "int", "long", "float", "double", "bytes", "string",
+];
+const JSON_SCHEMA_TYPES: &[&str] = &[
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+impl SchemaType {
+    /// Detect and validate the format of a schema
+    ///
+    /// Schemas declaring `$schema`, or with an `object` type, are JSON schemas.
+    /// Anything else must be a valid Avro schema.
+    pub fn of(schema: &str) -> Result<SchemaType> {
+        let v: Value = match serde_json::from_str(schema) {
+            Ok(v) => v,
+            Err(e) => bail!("schema is not valid json: {}", e),
+        };
+        let is_json_schema = v.get("$schema").is_some() || v.get("type") == Some(&Value::from("object"));
+        if is_json_schema {
+            verify_json_schema(&v)?;
+            Ok(SchemaType::Json)
+        } else {
+            verify_avro(&v, &mut BTreeSet::new())?;
+            Ok(SchemaType::Avro)
+        }
+    }
+}
+
+/// Name of an Avro named type, registering it for later references
+fn avro_name(o: &serde_json::Map<String, Value>, named: &mut BTreeSet<String>) -> Result<String> {
+    let name = match o.get("name").and_then(Value::as_str) {
+        Some(n) if !n.is_empty() => n.to_string(),
+        _ => bail!("avro {} types need a name", o["type"]),
+    };
+    if let Some(ns) = o.get("namespace").and_then(Value::as_str) {
+        named.insert(format!("{}.{}", ns, name));
+    }
+    named.insert(name.clone());
+    Ok(name)
+}
+
+fn verify_avro(schema: &Value, named: &mut BTreeSet<String>) -> Result<()> {
+    match schema {
+        Value::String(t) => {
+            if !AVRO_PRIMITIVES.contains(&t.as_str()) && !named.contains(t) {
+                bail!("unknown avro type {}", t);
+            }
+        }
+        Value::Array(branches) => {
+            if branches.is_empty() {
+                bail!("avro unions need at least one type");
+            }
+            for b in branches {
+                verify_avro(b, named)?;
+            }
+        }
+        Value::Object(o) => match o.get("type") {
+            Some(Value::String(t)) => match t.as_str() {
+                "record" | "error" => {
+                    let name = avro_name(o, named)?;
+                    let fields = match o.get("fields").and_then(Value::as_array) {
+                        Some(f) => f,
+                        None => bail!("avro record {} needs a list of fields", name),
+                    };
+                    for f in fields {
+                        if f.get("name").and_then(Value::as_str).is_none() {
+                            bail!("avro record {} has a field without a name", name);
+                        }
+                        match f.get("type") {
+                            Some(ft) => verify_avro(ft, named)?,
+                            None => bail!("avro record {} has a field without a type", name),
+                        }
+                    }
+                }
+                "enum" => {
+                    let name = avro_name(o, named)?;
+                    match o.get("symbols").and_then(Value::as_array) {
+                        Some(s) if !s.is_empty() && s.iter().all(Value::is_string) => {}
+                        _ => bail!("avro enum {} needs a list of symbols", name),
+                    }
+                }
+                "fixed" => {
+                    let name = avro_name(o, named)?;
+                    if o.get("size").and_then(Value::as_u64).is_none() {
+                        bail!("avro fixed {} needs a size", name);
+                    }
+                }
+                "array" => match o.get("items") {
+                    Some(i) => verify_avro(i, named)?,
+                    None => bail!("avro arrays need items"),
+                },
+                "map" => match o.get("values") {
+                    Some(v) => verify_avro(v, named)?,
+                    None => bail!("avro maps need values"),
+                },
+                // primitives with attributes (like logicalType), or references
+                _ => verify_avro(&Value::from(t.as_str()), named)?,
+            },
+            Some(t) => verify_avro(t, named)?,
+            None => bail!("avro schema objects need a type"),
+        },
+        _ => bail!("{} is not an avro schema", schema),
+    }
+    Ok(())
+}
+
+fn verify_json_schema(schema: &Value) -> Result<()> {
+    let o = match schema.as_object() {
+        Some(o) => o,
+        // `true` and `false` are valid schemas
+        None if schema.is_boolean() => return Ok(()),
+        None => bail!("{} is not a json schema", schema),
+    };
+    let valid_type = |t: &Value| t.as_str().map_or(false, |t| JSON_SCHEMA_TYPES.contains(&t));
+    match o.get("type") {
+        None => {}
+        Some(Value::Array(ts)) if ts.iter().all(valid_type) => {}
+        Some(t) if valid_type(t) => {}
+        Some(t) => bail!("invalid json schema type {}", t),
+    }
+    if let Some(props) = o.get("properties") {
+        match props.as_object() {
+            Some(p) => {
+                for v in p.values() {
+                    verify_json_schema(v)?;
+                }
+            }
+            None => bail!("json schema properties must be an object"),
+        }
+    }
+    if let Some(items) = o.get("items") {
+        verify_json_schema(items)?;
+    }
+    if let Some(req) = o.get("required") {
+        if !req.as_array().map_or(false, |r| r.iter().all(Value::is_string)) {
+            bail!("json schema required must be a list of property names");
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EventStream {
     pub name: String,
@@ -36,3 +188,33 @@ impl EventStream {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SchemaType;
+
+    #[test]
+    fn eventstream_schema_types() {
+        let record = r#"{"type": "record", "name": "Order", "namespace": "com.babylon", "fields": [
+            {"name": "id", "type": "string"},
+            {"name": "next", "type": ["null", "com.babylon.Order"]},
+            {"name": "at", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "tags", "type": {"type": "array", "items": "string"}}
+        ]}"#;
+        assert_eq!(SchemaType::of(record).unwrap(), SchemaType::Avro);
+        assert_eq!(SchemaType::of(r#""string""#).unwrap(), SchemaType::Avro);
+
+        let json = r#"{"$schema": "http://json-schema.org/draft-07/schema#", "type": "object",
+            "properties": {"id": {"type": "string"}}, "required": ["id"]}"#;
+        assert_eq!(SchemaType::of(json).unwrap(), SchemaType::Json);
+
+        // not json, unknown avro types, and broken json schemas are rejected
+        assert!(SchemaType::of("com.babylon.Order").is_err());
+        assert!(
+            SchemaType::of(r#"{"type": "record", "name": "A", "fields": [{"name": "b", "type": "B"}]}"#)
+                .is_err()
+        );
+        assert!(SchemaType::of(r#"{"type": "enum", "name": "E", "symbols": []}"#).is_err());
+        assert!(SchemaType::of(r#"{"type": "object", "properties": {"id": {"type": "text"}}}"#).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use super::{EnvVars, Region, Result};
+use schemars::JsonSchema;
+use std::{fmt, ops::Not};
+
+/// Supported kinds of infrastructure dependencies
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InfraKind {
+    Postgres,
+    Redis,
+    Rabbitmq,
+}
+
+impl InfraKind {
+    /// Port used when the host source does not include one
+    pub fn default_port(&self) -> u16 {
+        match self {
+            InfraKind::Postgres => 5432,
+            InfraKind::Redis => 6379,
+            InfraKind::Rabbitmq => 5672,
+        }
+    }
+}
+
+impl fmt::Display for InfraKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Infrastructure a service needs to reach, like its database
+///
+/// The host is read from one of the service's environment variables,
+/// or from one of the region's `base_urls`, and may be a plain host,
+/// a `host:port` pair, or a url.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct InfraDependency {
+    pub kind: InfraKind,
+    /// Name to report the dependency under, defaults to the kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Environment variable holding the host or url of the dependency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// Key in the region's `base_urls` holding the host or url of the dependency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseUrl: Option<String>,
+    /// Port to use when the host source does not specify one
+    ///
+    /// Defaults to the standard port of the kind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Whether the dependency must complete a TLS handshake
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub tls: bool,
+}
+
+impl InfraDependency {
+    /// Name the dependency is reported under
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.kind.to_string())
+    }
+
+    /// Port to use when the host source does not specify one
+    pub fn default_port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.kind.default_port())
+    }
+
+    pub fn verify(&self, env: &EnvVars, region: &Region) -> Result<()> {
+        match (&self.env, &self.baseUrl) {
+            (Some(e), None) => {
+                if !env.plain.contains_key(e) && !env.secrets.contains(e) {
+                    bail!("infraDependency {} uses undefined env var {}", self.name(), e);
+                }
+            }
+            (None, Some(b)) => {
+                if !region.base_urls.contains_key(b) {
+                    bail!(
+                        "infraDependency {} uses base_url {} not defined in {}",
+                        self.name(),
+                        b,
+                        region.name
+                    );
+                }
+            }
+            _ => bail!(
+                "infraDependency {} needs exactly one of env or baseUrl",
+                self.name()
+            ),
+        }
+        let valid_name = self
+            .name()
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            bail!(
+                "infraDependency name {} must be alphanumeric with dashes",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+}
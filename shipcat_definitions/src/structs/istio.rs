@@ -0,0 +1,333 @@
+use crate::{
+    structs::{Authentication, Kong},
+    Region,
+};
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Istio networking structs
+/// https://istio.io/latest/docs/reference/config/networking/
+const ISTIO_API_VERSION: &str = "networking.istio.io/v1beta1";
+
+/// Annotation prefix for kong settings without an istio equivalent
+const ANNOTATION_PREFIX: &str = "shipcat.babylontech.co.uk";
+
+#[derive(Serialize, Clone, Debug)]
+pub struct IstioMetadata {
+    pub name: String,
+    pub namespace: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Gateway {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: IstioMetadata,
+    pub spec: GatewaySpec,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GatewaySpec {
+    pub selector: BTreeMap<String, String>,
+    pub servers: Vec<GatewayServer>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GatewayServer {
+    pub port: GatewayPort,
+    pub hosts: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GatewayPort {
+    pub number: u16,
+    pub name: String,
+    pub protocol: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VirtualService {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: IstioMetadata,
+    pub spec: VirtualServiceSpec,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VirtualServiceSpec {
+    pub hosts: Vec<String>,
+    pub gateways: Vec<String>,
+    pub http: Vec<HttpRoute>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HttpRoute {
+    #[serde(rename = "match", skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<HttpMatchRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<HttpRewrite>,
+    pub route: Vec<HttpRouteDestination>,
+    pub timeout: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Headers>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HttpMatchRequest {
+    pub uri: StringMatch,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StringMatch {
+    pub prefix: String,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HttpRewrite {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HttpRouteDestination {
+    pub destination: Destination,
+    pub weight: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Destination {
+    pub host: String,
+    pub port: PortSelector,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PortSelector {
+    pub number: u16,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Headers {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<HeaderOperations>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<HeaderOperations>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HeaderOperations {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+}
+
+/// Name of the gateway fronting every API in a region
+pub fn istio_gateway_name(region: &Region) -> String {
+    format!("shipcat-kong-{}", region.name)
+}
+
+/// Format kong's millisecond timeouts as protobuf durations
+fn duration(ms: u32) -> String {
+    if ms % 1000 == 0 {
+        format!("{}s", ms / 1000)
+    } else {
+        format!("{}.{:03}s", ms / 1000, ms % 1000)
+    }
+}
+
+/// Hosts an API is routed on, where no hosts means every host like in kong
+fn api_hosts(api: &Kong) -> Vec<String> {
+    if api.hosts.is_empty() {
+        vec!["*".into()]
+    } else {
+        api.hosts.clone()
+    }
+}
+
+/// Gateway accepting traffic for the hosts of every API
+pub fn istio_gateway(from: &BTreeMap<String, Kong>, region: &Region) -> Gateway {
+    let mut hosts = from.values().flat_map(api_hosts).collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+    if hosts.iter().any(|h| h == "*") {
+        hosts = vec!["*".into()];
+    }
+    Gateway {
+        apiVersion: ISTIO_API_VERSION.into(),
+        kind: "Gateway".into(),
+        metadata: IstioMetadata {
+            name: istio_gateway_name(region),
+            namespace: region.namespace.clone(),
+            annotations: BTreeMap::new(),
+        },
+        spec: GatewaySpec {
+            selector: btreemap! { "istio".into() => "ingressgateway".into() },
+            servers: vec![GatewayServer {
+                port: GatewayPort {
+                    number: 80,
+                    name: "http".into(),
+                    protocol: "HTTP".into(),
+                },
+                hosts,
+            }],
+        },
+    }
+}
+
+/// Kong settings that istio cannot express, kept as annotations for the ingress policies
+fn api_annotations(api: &Kong) -> BTreeMap<String, String> {
+    let mut res = BTreeMap::new();
+    let mut annotate = |k: &str, v: String| {
+        res.insert(format!("{}/{}", ANNOTATION_PREFIX, k), v);
+    };
+    if api.internal {
+        annotate("internal", "true".into());
+        if !api.additional_internal_ips.is_empty() {
+            annotate("additional-internal-ips", api.additional_internal_ips.join(","));
+        }
+    }
+    if api.publiclyAccessible {
+        annotate("publicly-accessible", "true".into());
+    }
+    if let Some(a) = &api.authorization {
+        annotate("auth", "jwt".into());
+        annotate("allowed-audiences", a.allowed_audiences.join(","));
+        annotate("required-scopes", a.required_scopes.join(","));
+        annotate("allow-anonymous", a.allow_anonymous.to_string());
+        annotate("allow-invalid-tokens", a.allow_invalid_tokens.to_string());
+        annotate("allow-cookies", a.allow_cookies.to_string());
+    } else if let Some(Authentication::Jwt) = api.auth {
+        annotate("auth", "jwt".into());
+    }
+    res
+}
+
+/// Weighted destinations of an API, splitting traffic to an active canary
+fn api_destinations(name: &str, api: &Kong, url: &Url, region: &Region) -> Vec<HttpRouteDestination> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let stable = Destination {
+        host: url.host_str().unwrap_or(name).to_string(),
+        port: PortSelector { number: port },
+    };
+    if let Some(c) = &api.canary {
+        let weight = c.weight.min(100);
+        vec![
+            HttpRouteDestination {
+                destination: stable,
+                weight: 100 - weight,
+            },
+            HttpRouteDestination {
+                destination: Destination {
+                    host: format!("{}.{}.svc.cluster.local", c.service, region.namespace),
+                    port: PortSelector { number: port },
+                },
+                weight,
+            },
+        ]
+    } else {
+        vec![HttpRouteDestination {
+            destination: stable,
+            weight: 100,
+        }]
+    }
+}
+
+/// A VirtualService per API routing the same hosts and uris kong does
+///
+/// APIs whose upstream_url cannot be parsed are skipped with a warning.
+pub fn istio_virtual_services(from: BTreeMap<String, Kong>, region: &Region) -> Vec<VirtualService> {
+    let mut vss = vec![];
+    for (k, v) in from {
+        let url = match Url::parse(&v.upstream_url) {
+            Ok(u) if u.host_str().is_some() => u,
+            _ => {
+                warn!("Cannot route {} with upstream_url {}", k, v.upstream_url);
+                continue;
+            }
+        };
+        let prefixes = v.uris.as_ref().map_or(vec![], |uris| {
+            uris.split(',')
+                .map(str::trim)
+                .filter(|u| !u.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        });
+        let mut rewrite = HttpRewrite::default();
+        if v.strip_uri && !prefixes.is_empty() {
+            rewrite.uri = Some(url.path().to_string());
+        } else if url.path() != "/" {
+            warn!("Ignoring the upstream_url path of {} without strip_uri", k);
+        }
+        if !v.preserve_host {
+            rewrite.authority = url.host_str().map(String::from);
+        }
+
+        let mut response = HeaderOperations {
+            add: v.add_headers.clone(),
+            ..HeaderOperations::default()
+        };
+        if let Some(d) = &v.deprecation {
+            response.add.extend(d.headers());
+        }
+        let mut request = HeaderOperations::default();
+        if let Some(us) = &v.upstream_service {
+            request.set.insert("Upstream-Service".into(), us.clone());
+        }
+        let headers = if request.set.is_empty() && response.add.is_empty() {
+            None
+        } else {
+            Some(Headers {
+                request: Some(request).filter(|r| !r.set.is_empty()),
+                response: Some(response).filter(|r| !r.add.is_empty()),
+            })
+        };
+
+        let route = HttpRoute {
+            matches: prefixes
+                .into_iter()
+                .map(|prefix| HttpMatchRequest {
+                    uri: StringMatch { prefix },
+                })
+                .collect(),
+            rewrite: if rewrite.uri.is_some() || rewrite.authority.is_some() {
+                Some(rewrite)
+            } else {
+                None
+            },
+            route: api_destinations(&k, &v, &url, region),
+            timeout: duration(v.upstream_read_timeout.unwrap_or(30000)),
+            headers,
+        };
+        vss.push(VirtualService {
+            apiVersion: ISTIO_API_VERSION.into(),
+            kind: "VirtualService".into(),
+            metadata: IstioMetadata {
+                name: k.clone(),
+                namespace: region.namespace.clone(),
+                annotations: api_annotations(&v),
+            },
+            spec: VirtualServiceSpec {
+                hosts: api_hosts(&v),
+                gateways: vec![istio_gateway_name(region)],
+                http: vec![route],
+            },
+        });
+    }
+    vss
+}
+
+#[cfg(test)]
+mod tests {
+    use super::duration;
+
+    #[test]
+    fn istio_durations() {
+        assert_eq!(duration(30000), "30s");
+        assert_eq!(duration(1500), "1.500s");
+        assert_eq!(duration(60), "0.060s");
+    }
+}
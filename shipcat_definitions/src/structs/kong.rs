@@ -1,10 +1,15 @@
+use chrono::{NaiveDate, Utc};
+use schemars::JsonSchema;
 use std::{collections::BTreeMap, ops::Not};
 
 use super::Authorization;
-use crate::deserializers::comma_separated_string;
+use crate::{
+    deserializers::{comma_separated_string, comma_separated_string_schema},
+    status::CanaryStatus,
+};
 
 /// Kong setup for a service
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Kong {
@@ -47,6 +52,7 @@ pub struct Kong {
         skip_serializing_if = "Vec::is_empty",
         deserialize_with = "comma_separated_string"
     )]
+    #[schemars(schema_with = "comma_separated_string_schema")]
     pub hosts: Vec<String>,
 
     pub auth: Option<Authentication>,
@@ -119,14 +125,70 @@ pub struct Kong {
 
     pub ip_rate_limits: Option<KongRateLimit>,
     pub user_rate_limits: Option<KongRateLimit>,
+
+    /// Deprecation notice for the API
+    ///
+    /// Adds `Deprecation`, `Sunset` and `Link` headers to every response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecation: Option<KongDeprecation>,
+
+    /// Active canary of the service behind this API
+    ///
+    /// Populated from the shipcatmanifest status when generating kong config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub canary: Option<CanaryStatus>,
 }
 
 fn preserve_host_default() -> bool {
     true
 }
 
+/// Deprecation of a Kong API ahead of its removal
+///
+/// ```yaml
+/// deprecation:
+///   sunset: 2021-01-31
+///   link: https://developer.example.com/migrations/v2
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct KongDeprecation {
+    /// Date after which the API can be removed
+    pub sunset: NaiveDate,
+    /// Documentation for consumers migrating off the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl KongDeprecation {
+    /// Whether the API has outlived its `sunset` date
+    pub fn is_past_sunset(&self) -> bool {
+        self.sunset < Utc::today().naive_utc()
+    }
+
+    /// Response headers announcing the deprecation
+    ///
+    /// Sunset is an HTTP-date as per RFC 8594.
+    pub fn headers(&self) -> BTreeMap<String, String> {
+        let mut headers = BTreeMap::new();
+        headers.insert("Deprecation".into(), "true".into());
+        headers.insert(
+            "Sunset".into(),
+            self.sunset
+                .and_hms(0, 0, 0)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        );
+        if let Some(link) = &self.link {
+            headers.insert("Link".into(), format!("<{}>; rel=\"sunset\"", link));
+        }
+        headers
+    }
+}
+
 /// Cors plugin data
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cors {
     pub credentials: bool,
@@ -140,7 +202,7 @@ pub struct Cors {
 }
 
 /// Babylon Auth Header plugin data
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct BabylonAuthHeader {
     pub auth_service: String,
@@ -149,7 +211,7 @@ pub struct BabylonAuthHeader {
     pub http_timeout_msec: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct KongRateLimit {
     pub per_second: Option<u32>,
     pub per_minute: Option<u32>,
@@ -157,9 +219,25 @@ pub struct KongRateLimit {
     pub per_day: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum Authentication {
     None,
     Jwt,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::KongDeprecation;
+
+    #[test]
+    fn deprecation_headers() {
+        let d: KongDeprecation =
+            serde_yaml::from_str("sunset: 2020-01-31\nlink: https://example.com/v2").unwrap();
+        assert!(d.is_past_sunset());
+        let headers = d.headers();
+        assert_eq!(headers["Deprecation"], "true");
+        assert_eq!(headers["Sunset"], "Fri, 31 Jan 2020 00:00:00 GMT");
+        assert_eq!(headers["Link"], "<https://example.com/v2>; rel=\"sunset\"");
+    }
+}
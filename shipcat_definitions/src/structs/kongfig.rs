@@ -6,6 +6,7 @@ use crate::{
 };
 use serde::ser::{Serialize, SerializeMap, Serializer};
 use std::collections::BTreeMap;
+use url::Url;
 
 /// Kongfig structs
 /// https://github.com/mybuilder/kongfig
@@ -531,9 +532,13 @@ pub fn kongfig_apis(from: BTreeMap<String, Kong>, config: KongConfig, region: &R
         }
 
         // If enabled: ResponseTransformer to add headers
-        if !v.add_headers.is_empty() {
+        let mut add_headers = v.add_headers;
+        if let Some(d) = &v.deprecation {
+            add_headers.extend(d.headers());
+        }
+        if !add_headers.is_empty() {
             plugins.push(ApiPlugin::ResponseTransformer(PluginBase::new(
-                ResponseTransformerPluginConfig::new(v.add_headers),
+                ResponseTransformerPluginConfig::new(add_headers),
             )));
         }
 
@@ -559,7 +564,11 @@ pub fn kongfig_apis(from: BTreeMap<String, Kong>, config: KongConfig, region: &R
                 upstream_connect_timeout: v.upstream_connect_timeout.unwrap_or(30000),
                 upstream_read_timeout: v.upstream_read_timeout.unwrap_or(30000),
                 upstream_send_timeout: v.upstream_send_timeout.unwrap_or(30000),
-                upstream_url: v.upstream_url,
+                upstream_url: if v.canary.is_some() {
+                    canary_upstream_url(&k, &v.upstream_url)
+                } else {
+                    v.upstream_url
+                },
                 ..Default::default()
             },
         });
@@ -567,6 +576,66 @@ pub fn kongfig_apis(from: BTreeMap<String, Kong>, config: KongConfig, region: &R
     apis
 }
 
+/// Name of the upstream splitting traffic for an API with an active canary
+fn canary_upstream_name(api: &str) -> String {
+    format!("{}.upstream", api)
+}
+
+/// Point an upstream_url at the canary upstream instead of the stable service host
+fn canary_upstream_url(api: &str, upstream_url: &str) -> String {
+    match Url::parse(upstream_url) {
+        Ok(u) if u.host_str().is_some() => {
+            upstream_url.replacen(u.host_str().unwrap(), &canary_upstream_name(api), 1)
+        }
+        _ => upstream_url.to_string(),
+    }
+}
+
+/// Weighted upstreams for every API with an active canary
+///
+/// Traffic is split between the stable upstream_url host and the canary service
+/// in the same namespace, according to the canary weight.
+pub fn kongfig_upstreams(from: &BTreeMap<String, Kong>, region: &Region) -> Vec<Upstream> {
+    let mut upstreams = vec![];
+    for (k, v) in from {
+        let canary = if let Some(c) = &v.canary {
+            c
+        } else {
+            continue;
+        };
+        let url = match Url::parse(&v.upstream_url) {
+            Ok(u) if u.host_str().is_some() => u,
+            _ => {
+                warn!(
+                    "Cannot split traffic for {} with upstream_url {}",
+                    k, v.upstream_url
+                );
+                continue;
+            }
+        };
+        let port = url.port_or_known_default().unwrap_or(80);
+        let weight = canary.weight.min(100);
+        upstreams.push(Upstream {
+            name: canary_upstream_name(k),
+            targets: vec![
+                UpstreamTarget {
+                    target: format!("{}:{}", url.host_str().unwrap(), port),
+                    attributes: UpstreamTargetAttributes { weight: 100 - weight },
+                },
+                UpstreamTarget {
+                    target: format!(
+                        "{}.{}.svc.cluster.local:{}",
+                        canary.service, region.namespace, port
+                    ),
+                    attributes: UpstreamTargetAttributes { weight },
+                },
+            ],
+            attributes: UpstreamAttributes { slots: 1000 },
+        });
+    }
+    upstreams
+}
+
 pub fn kongfig_consumers(k: KongConfig) -> Vec<Consumer> {
     let mut consumers: Vec<Consumer> = k
         .jwt_consumers
@@ -616,8 +685,29 @@ pub struct JwtCredentialsAttributes {
 #[derive(Serialize, Debug, Clone, Default)]
 pub struct Plugin {}
 
+/// Load balancer used to split traffic between a stable and a canary service
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Upstream {
+    pub name: String,
+    pub targets: Vec<UpstreamTarget>,
+    pub attributes: UpstreamAttributes,
+}
+
 #[derive(Serialize, Debug, Clone, Default)]
-pub struct Upstream {}
+pub struct UpstreamAttributes {
+    pub slots: u32,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct UpstreamTarget {
+    pub target: String,
+    pub attributes: UpstreamTargetAttributes,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct UpstreamTargetAttributes {
+    pub weight: u32,
+}
 
 #[derive(Serialize, Debug, Clone, Default)]
 pub struct Certificate {}
@@ -1,17 +1,18 @@
 use crate::teams::Owners;
 use regex::Regex;
+use schemars::JsonSchema;
 use std::{
     collections::{BTreeMap, BTreeSet},
     ops::{Deref, DerefMut},
 };
 
-use super::Result;
+use super::{Result, Sunset};
 use crate::config::SlackParameters;
 
 /// Legacy contact data
 ///
 /// This property is being phased out in favour of .maintainer
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Contact {
     /// Free text name
     pub name: String,
@@ -49,7 +50,7 @@ impl Contact {
 }
 
 /// Slack channel verifier
-#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug, JsonSchema)]
 pub struct SlackChannel(String);
 impl SlackChannel {
     pub fn new(chan: &str) -> Self {
@@ -90,7 +91,7 @@ impl DerefMut for SlackChannel {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Language {
     Rust,
@@ -122,7 +123,7 @@ pub enum Language {
 /// context:
 ///   name: consultations
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Context {
     /// name of parent context
@@ -210,6 +211,10 @@ pub struct Metadata {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub dpsia: Vec<String>,
 
+    /// Planned retirement of the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<Sunset>,
+
     // TODO: generate swagger docs url from region and service name
     /// Custom metadata, keys defined in the Config
     #[serde(flatten)]
@@ -309,6 +314,9 @@ impl Metadata {
         for dpsia in &self.dpsia {
             self.verify_hyperlink(&dpsia, "dpsia")?;
         }
+        if let Some(s) = &self.sunset {
+            s.verify(owners)?;
+        }
 
         for k in self.custom.keys() {
             if !allowedCustomMetadata.contains(k) {
@@ -357,6 +365,7 @@ mod tests {
                 notifications: Option::None,
                 alerts: Option::None,
             },
+            pagerduty: Option::None,
         });
         owners
     }
@@ -10,7 +10,11 @@ pub use super::{ErrorKind, Result, ResultExt};
 // Structs that exist in the manifest
 
 mod dependency;
-pub use self::dependency::{Dependency, DependencyProtocol};
+pub use self::dependency::{Dependency, DependencyProtocol, NetworkPolicyMode};
+
+/// Infrastructure dependencies
+pub mod infradependency;
+pub use self::infradependency::InfraDependency;
 
 /// DestinationRule struct
 mod destinationrule;
@@ -19,9 +23,17 @@ pub use self::destinationrule::DestinationRule;
 mod worker;
 pub use self::worker::Worker;
 
+/// Temporary per-region disables
+mod disabledregion;
+pub use self::disabledregion::DisabledRegion;
+
+/// Planned service retirements
+mod sunset;
+pub use self::sunset::{Sunset, SunsetState, SUNSET_WARNING_DAYS};
+
 /// Kong configs
 pub mod kong;
-pub use self::kong::{Authentication, BabylonAuthHeader, Cors, Kong, KongRateLimit};
+pub use self::kong::{Authentication, BabylonAuthHeader, Cors, Kong, KongDeprecation, KongRateLimit};
 
 pub mod authorization;
 pub use self::authorization::Authorization;
@@ -34,6 +46,10 @@ pub use self::gate::Gate;
 pub mod kongfig;
 pub use self::kongfig::{Api, Certificate, Consumer, Plugin, Upstream};
 
+/// Istio ingress configs
+pub mod istio;
+pub use self::istio::{Gateway, VirtualService};
+
 /// Kafka configs
 pub mod kafka;
 pub use self::kafka::Kafka;
@@ -63,10 +79,16 @@ mod hostalias;
 pub use self::hostalias::HostAlias;
 /// Kubernetes health check probes
 mod probes;
-pub use self::probes::Probe;
+pub use self::probes::{Exec, HttpGet, HttpHeader, Probe, TcpSocket};
 /// Kubernetes rolling-update settings
 pub mod rollingupdate;
 pub use self::rollingupdate::RollingUpdate;
+/// Kubernetes pod disruption budgets
+mod disruption;
+pub use self::disruption::PodDisruptionBudget;
+/// Canary deployments next to the main workload
+mod canary;
+pub use self::canary::Canary;
 /// Kubernetes horizontal pod autoscaler
 pub mod autoscaling;
 /// Kubernetes container lifecycle events
@@ -102,6 +124,18 @@ pub use self::port::Port;
 pub mod rbac;
 pub use self::rbac::Rbac;
 
+/// Kubernetes service accounts and their cloud identities
+pub mod serviceaccount;
+pub use self::serviceaccount::ServiceAccount;
+
+/// Kubernetes service traffic policy
+pub mod service;
+pub use self::service::ServiceOptions;
+
+/// Service mesh mutual TLS
+pub mod mtls;
+pub use self::mtls::{Mtls, MtlsMode};
+
 // PersistentVolume
 mod persistentvolume;
 pub use self::persistentvolume::PersistentVolume;
@@ -115,10 +149,18 @@ pub use notifications::NotificationMode;
 
 // EventStreams / Kafka related struct
 mod eventstream;
-pub use self::eventstream::EventStream;
+pub use self::eventstream::{EventDefinition, EventStream, SchemaType};
 
 pub mod kafkaresources;
 pub use self::kafkaresources::KafkaResources;
 
 pub mod prometheusalert;
 pub use self::prometheusalert::PrometheusAlert;
+
+/// Service level objectives
+pub mod slo;
+pub use self::slo::{PrometheusRecordingRule, Slo};
+
+/// Smoke tests after rollouts
+pub mod smoketest;
+pub use self::smoketest::{HttpSmokeTest, JobSmokeTest, SmokeTest, SmokeTests};
@@ -0,0 +1,76 @@
+use super::Result;
+use schemars::JsonSchema;
+
+/// Annotation (or label) that decides whether istio injects its sidecar into a pod
+pub const SIDECAR_INJECT_KEY: &str = "sidecar.istio.io/inject";
+
+/// Mutual TLS policy for traffic to a service in the mesh
+///
+/// Renders a PeerAuthentication for the service's pods, and a DestinationRule
+/// making clients use istio's mutual TLS. Services with `mtls` run the mesh sidecar.
+///
+/// ```yaml
+/// mtls:
+///   mode: strict
+///   excludedPorts: [9102]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Mtls {
+    pub mode: MtlsMode,
+    /// Ports that accept plain text in strict mode, like metrics scraped from outside the mesh
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludedPorts: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MtlsMode {
+    /// Only accept mutual TLS traffic, so clients must be in the mesh
+    Strict,
+    /// Accept both mutual TLS and plain text traffic while clients migrate
+    Permissive,
+}
+
+impl Mtls {
+    /// Verify excluded ports against the ports the service listens on
+    pub fn verify(&self, ports: &[u32]) -> Result<()> {
+        if self.mode == MtlsMode::Permissive && !self.excludedPorts.is_empty() {
+            bail!("mtls excludedPorts only apply to strict mode");
+        }
+        for p in &self.excludedPorts {
+            if !ports.contains(p) {
+                bail!("mtls excludes port {} which the service does not listen on", p);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mtls, MtlsMode, SIDECAR_INJECT_KEY};
+    use crate::Manifest;
+
+    #[test]
+    fn mtls_excluded_ports() {
+        let mut mtls: Mtls = serde_yaml::from_str("mode: strict\nexcludedPorts: [9102]").unwrap();
+        assert_eq!(mtls.mode, MtlsMode::Strict);
+        assert!(mtls.verify(&[8080, 9102]).is_ok());
+        assert!(mtls.verify(&[8080]).is_err());
+        mtls.mode = MtlsMode::Permissive;
+        assert!(mtls.verify(&[8080, 9102]).is_err());
+    }
+
+    #[test]
+    fn mtls_mesh_sidecar() {
+        let mut mf = Manifest::test("fake-ask");
+        assert!(!mf.uses_mesh_sidecar());
+        mf.mtls = Some(serde_yaml::from_str("mode: strict").unwrap());
+        assert!(mf.uses_mesh_sidecar());
+        assert!(mf.requires_mtls());
+        mf.podAnnotations
+            .insert(SIDECAR_INJECT_KEY.into(), "false".into());
+        assert!(!mf.uses_mesh_sidecar());
+    }
+}
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use std::collections::BTreeMap;
 
 use super::metadata::SlackChannel;
@@ -36,7 +37,7 @@ pub struct NewrelicAlert {
 /// NewRelic AlertPolicy attribute that we configure once per Application (service@region) monitored
 ///
 /// Details available at [this link](https://docs.newrelic.com/docs/alerts/new-relic-alerts/configuring-alert-policies/specify-when-new-relic-creates-incidents#preference-options)
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum NewrelicIncidentPreference {
     /// Only one incident will be open at a time for the entire policy. This is the default.
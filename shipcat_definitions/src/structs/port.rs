@@ -1,4 +1,6 @@
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use schemars::JsonSchema;
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PortProtocol {
     Tcp,
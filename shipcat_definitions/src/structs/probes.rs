@@ -1,6 +1,7 @@
 use super::Result;
+use schemars::JsonSchema;
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct HttpGet {
     /// Uri path to GET (i.e. / or /health)
@@ -16,41 +17,41 @@ fn http_get_default_port() -> String {
     "http".into()
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct HttpHeader {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Exec {
     /// Command to execute in the container
     pub command: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct TcpSocket {
     pub port: String,
 }
 
 /// Liveness or readiness Probe
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Probe {
     /// Http Get probe
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    httpGet: Option<HttpGet>,
+    pub httpGet: Option<HttpGet>,
 
     /// Shell exec probe
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    exec: Option<Exec>,
+    pub exec: Option<Exec>,
 
     /// Tcp Socket probe
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    tcpSocket: Option<TcpSocket>,
+    pub tcpSocket: Option<TcpSocket>,
 
     /// How long to wait before kube performs first probe
     #[serde(default = "initial_delay_seconds_default")]
@@ -1,12 +1,16 @@
 use super::Result;
+use crate::{region::AlertRoutingConfig, teams::Owners};
 use inflector::cases::pascalcase::is_pascal_case;
+use prometheus_parser::Expression;
 use regex::Regex;
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
 
 /// Data describing one Prometheus alert.
 ///
 /// This roughly corresponds to a Rule object in the Prometheus Operator API spec:
 /// https://github.com/coreos/prometheus-operator/blob/master/Documentation/api.md#rule
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct PrometheusAlert {
     /// Name of the alert
     ///
@@ -39,13 +43,21 @@ pub struct PrometheusAlert {
     ///
     /// Corresponds to how urgently it should be actioned if it were in production.
     pub severity: PrometheusAlertSeverity,
+
+    /// Routing labels of the alert
+    ///
+    /// Derived from the owning squad in `metadata.team` and the region's `alertRouting`.
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub labels: BTreeMap<String, String>,
 }
 
 /// Alert severity enumeration.
 ///
 /// Represents the set of alert severities we allow in our Prometheus alerts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
-#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum PrometheusAlertSeverity {
     /// Warning severity
     ///
@@ -85,4 +97,77 @@ impl PrometheusAlert {
 
         Ok(())
     }
+
+    /// Attach routing labels for the owning squad
+    ///
+    /// Alerts are labelled with their `team` (squad), `tribe` (when known), and whether
+    /// they should `page` according to the severities the region pages for.
+    pub fn route(&mut self, team: &str, owners: &Owners, routing: Option<&AlertRoutingConfig>) {
+        self.labels.insert("team".into(), team.into());
+        if let Some(t) = owners.tribe_of(team) {
+            self.labels.insert("tribe".into(), t.name.clone());
+        }
+        let page = match routing {
+            Some(r) => r.pager_severities.contains(&self.severity),
+            None => false,
+        };
+        self.labels.insert("page".into(), page.to_string());
+    }
+
+    /// Names of all metrics selected in the alert expression
+    ///
+    /// Sorted and deduplicated. Fails if the expression does not parse.
+    pub fn metric_names(&self) -> Result<Vec<String>> {
+        let expr = match prometheus_parser::parse_expr(&self.expr) {
+            Ok(e) => e,
+            Err(e) => bail!("Prometheus alert expression for {} invalid: {:?}", self.name, e),
+        };
+        let mut names = vec![];
+        collect_metric_names(&expr, &mut names);
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+}
+
+fn collect_metric_names(expr: &Expression, names: &mut Vec<String>) {
+    match expr {
+        Expression::Selector(s) => {
+            if let Some(m) = &s.metric {
+                names.push(m.clone());
+            }
+        }
+        Expression::Group(g) => collect_metric_names(&g.expression, names),
+        Expression::Function(f) => {
+            for a in &f.args {
+                collect_metric_names(a, names);
+            }
+        }
+        Expression::Operator(o) => {
+            collect_metric_names(&o.lhs, names);
+            collect_metric_names(&o.rhs, names);
+        }
+        Expression::Float(_) | Expression::String(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrometheusAlert;
+
+    #[test]
+    fn alert_metric_names() {
+        let alert: PrometheusAlert = serde_yaml::from_str(
+            r#"
+name: HighErrorRate
+summary: errors
+description: too many errors
+expr: sum(rate(http_requests_total{code=~"5.."}[5m])) / sum(rate(http_requests_total[5m])) > 0.1 and up == 1
+min_duration: 5m
+severity: warning
+"#,
+        )
+        .unwrap();
+        assert_eq!(alert.metric_names().unwrap(), vec!["http_requests_total", "up"]);
+    }
 }
@@ -1,4 +1,5 @@
 use super::Result;
+use schemars::JsonSchema;
 use std::ops::{Add, AddAssign, Mul};
 
 // Kubernetes resouce structs
@@ -10,7 +11,7 @@ use std::ops::{Add, AddAssign, Mul};
 // implemented to be a bit more useful, as well as some to convert between them.
 
 /// Kubernetes resource requests or limit
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Resources<T> {
     /// CPU request string
@@ -23,7 +24,7 @@ pub struct Resources<T> {
 /// Kubernetes resources
 ///
 /// This can be inlined straight into a container spec at the moment
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct ResourceRequirements<T> {
     /// Resource requests for k8s
@@ -32,17 +33,21 @@ pub struct ResourceRequirements<T> {
     pub limits: Resources<T>,
 }
 
+impl Resources<String> {
+    /// Convert shorthand strings to raw number of cores and Bytes of memory
+    pub fn normalised(&self) -> Result<Resources<f64>> {
+        Ok(Resources {
+            memory: parse_memory(&self.memory)?,
+            cpu: parse_cpu(&self.cpu)?,
+        })
+    }
+}
+
 impl ResourceRequirements<String> {
     /// Convert shorthand strings to raw number of cores and Bytes of memory
     pub fn normalised(&self) -> Result<ResourceRequirements<f64>> {
-        let requests = Resources {
-            memory: parse_memory(&self.requests.memory.to_string())?,
-            cpu: parse_cpu(&self.requests.cpu.to_string())?,
-        };
-        let limits = Resources {
-            memory: parse_memory(&self.limits.memory.to_string())?,
-            cpu: parse_cpu(&self.limits.cpu.to_string())?,
-        };
+        let requests = self.requests.normalised()?;
+        let limits = self.limits.normalised()?;
         Ok(ResourceRequirements { requests, limits })
     }
 }
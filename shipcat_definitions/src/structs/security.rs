@@ -1,11 +1,12 @@
 use super::Result;
 use regex::Regex;
+use schemars::JsonSchema;
 use std::path::Path;
 
 /// What sensitive data is managed and how
 ///
 /// See https://engineering.ops.babylontech.co.uk/docs/principles-security/
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DataHandling {
     /// Where and how data is stored
@@ -27,7 +28,7 @@ impl DataHandling {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct InformationClassificationData {
     /// The highest information classification for data this service processes
@@ -35,7 +36,7 @@ pub struct InformationClassificationData {
 }
 
 /// Possible levels of information classification of the data stored in the data store.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum InformationClassification {
     StrictlyConfidential,
@@ -52,7 +53,7 @@ impl Default for InformationClassification {
 }
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DataStore {
     /// Storage type (one of "MySQL", "DynamoDB", "S3", "File", "Kafka")
@@ -111,7 +112,7 @@ impl DataStore {
 }
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DataField {
     /// Canonical name of the data field
@@ -133,7 +134,7 @@ pub struct DataField {
 }
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DataProcess {
     /// Canonical field name
@@ -0,0 +1,124 @@
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+
+use super::Result;
+
+/// Annotation prefix for AWS load balancer connection draining
+///
+/// Set from `drainTimeoutSeconds` rather than through `serviceAnnotations`.
+pub const DRAINING_ANNOTATION_PREFIX: &str =
+    "service.beta.kubernetes.io/aws-load-balancer-connection-draining";
+
+/// Kubernetes `Service` types
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub enum ServiceType {
+    ClusterIP,
+    NodePort,
+    LoadBalancer,
+}
+
+impl Default for ServiceType {
+    fn default() -> Self {
+        ServiceType::ClusterIP
+    }
+}
+
+/// How requests from a client are spread across pods
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub enum SessionAffinity {
+    None,
+    ClientIP,
+}
+
+/// Whether external traffic is routed to node-local pods only
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub enum ExternalTrafficPolicy {
+    Cluster,
+    Local,
+}
+
+/// Traffic policy of the `Service` fronting the main workload
+///
+/// A small subset of the [kubernetes service spec](https://kubernetes.io/docs/concepts/services-networking/service/),
+/// plus connection draining for load balancers.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ServiceOptions {
+    /// Type of the `Service`, defaults to `ClusterIP`
+    #[serde(default, rename = "type")]
+    pub serviceType: ServiceType,
+
+    /// Route requests from the same client ip to the same pod
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sessionAffinity: Option<SessionAffinity>,
+
+    /// Preserve client ips by only routing to pods on the receiving node
+    ///
+    /// Only meaningful for `NodePort` and `LoadBalancer` services.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub externalTrafficPolicy: Option<ExternalTrafficPolicy>,
+
+    /// Seconds a load balancer keeps connections to deregistered pods open
+    ///
+    /// Only meaningful for `LoadBalancer` services.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drainTimeoutSeconds: Option<u32>,
+}
+
+impl ServiceOptions {
+    /// Verify the options make sense for the service type and the manifest's annotations
+    pub fn verify(&self, httpPort: Option<u32>, annotations: &BTreeMap<String, String>) -> Result<()> {
+        if httpPort.is_none() {
+            bail!("service options require an httpPort to create a Service");
+        }
+        if self.externalTrafficPolicy.is_some() && self.serviceType == ServiceType::ClusterIP {
+            bail!("service.externalTrafficPolicy needs a NodePort or LoadBalancer service type");
+        }
+        if let Some(t) = self.drainTimeoutSeconds {
+            if self.serviceType != ServiceType::LoadBalancer {
+                bail!("service.drainTimeoutSeconds needs a LoadBalancer service type");
+            }
+            if t == 0 || t > 3600 {
+                bail!("service.drainTimeoutSeconds must be between 1 and 3600");
+            }
+            if let Some(k) = annotations
+                .keys()
+                .find(|k| k.starts_with(DRAINING_ANNOTATION_PREFIX))
+            {
+                bail!(
+                    "serviceAnnotations {} conflicts with service.drainTimeoutSeconds",
+                    k
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExternalTrafficPolicy, ServiceOptions, ServiceType, DRAINING_ANNOTATION_PREFIX};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn verify_service_options() {
+        let mut annotations = BTreeMap::new();
+        let mut opts = ServiceOptions {
+            externalTrafficPolicy: Some(ExternalTrafficPolicy::Local),
+            ..Default::default()
+        };
+        assert!(opts.verify(Some(8080), &annotations).is_err());
+        opts.serviceType = ServiceType::NodePort;
+        assert!(opts.verify(Some(8080), &annotations).is_ok());
+        assert!(opts.verify(None, &annotations).is_err());
+
+        opts.drainTimeoutSeconds = Some(30);
+        assert!(opts.verify(Some(8080), &annotations).is_err());
+        opts.serviceType = ServiceType::LoadBalancer;
+        assert!(opts.verify(Some(8080), &annotations).is_ok());
+
+        let key = format!("{}-timeout", DRAINING_ANNOTATION_PREFIX);
+        annotations.insert(key, "30".into());
+        assert!(opts.verify(Some(8080), &annotations).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use super::{Region, Result};
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+
+/// ServiceAccount annotation that makes EKS inject credentials for an IAM role
+pub const AWS_ROLE_ARN_KEY: &str = "eks.amazonaws.com/role-arn";
+/// ServiceAccount annotation that binds it to a Google service account on GKE
+pub const GCP_SERVICE_ACCOUNT_KEY: &str = "iam.gke.io/gcp-service-account";
+
+/// Kubernetes ServiceAccount the pods of a service run as
+///
+/// Cloud identities are bound through `roleArn` (IRSA on EKS) or `gcpServiceAccount`
+/// (workload identity on GKE), and must be allowed by the region's `workloadIdentity`.
+///
+/// ```yaml
+/// serviceAccount:
+///   roleArn: arn:aws:iam::123456789012:role/orders
+///   annotations:
+///     eks.amazonaws.com/sts-regional-endpoints: "true"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ServiceAccount {
+    /// Whether the chart creates the ServiceAccount, rather than use an existing one
+    #[serde(default = "default_create")]
+    pub create: bool,
+    /// Name of the ServiceAccount, defaulting to the service name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// IAM role the pods assume through IRSA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roleArn: Option<String>,
+    /// Google service account the pods act as through workload identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gcpServiceAccount: Option<String>,
+    /// Other annotations of the ServiceAccount
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+fn default_create() -> bool {
+    true
+}
+
+/// Whether an identity is in an allowlist, where entries may end in a `*` wildcard
+fn allowed(identity: &str, allowlist: &[String]) -> bool {
+    allowlist.iter().any(|a| match a.strip_suffix('*') {
+        Some(prefix) => identity.starts_with(prefix),
+        None => a == identity,
+    })
+}
+
+impl ServiceAccount {
+    pub fn verify(&self, region: &Region) -> Result<()> {
+        if !self.create && self.name.is_none() {
+            bail!("serviceAccount needs a name when it is not created");
+        }
+        for key in &[AWS_ROLE_ARN_KEY, GCP_SERVICE_ACCOUNT_KEY] {
+            if self.annotations.contains_key(*key) {
+                bail!(
+                    "serviceAccount annotation {} is set through roleArn or gcpServiceAccount",
+                    key
+                );
+            }
+        }
+        let wi = region.workloadIdentity.clone().unwrap_or_default();
+        if let Some(arn) = &self.roleArn {
+            if !arn.starts_with("arn:aws:iam::") || !arn.contains(":role/") {
+                bail!("serviceAccount roleArn {} is not an IAM role arn", arn);
+            }
+            if !allowed(arn, &wi.allowedRoleArns) {
+                bail!("serviceAccount roleArn {} is not allowed in {}", arn, region.name);
+            }
+        }
+        if let Some(gsa) = &self.gcpServiceAccount {
+            if !gsa.ends_with(".iam.gserviceaccount.com") {
+                bail!(
+                    "serviceAccount gcpServiceAccount {} is not a service account email",
+                    gsa
+                );
+            }
+            if !allowed(gsa, &wi.allowedGcpServiceAccounts) {
+                bail!(
+                    "serviceAccount gcpServiceAccount {} is not allowed in {}",
+                    gsa,
+                    region.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceAccount;
+    use crate::Region;
+
+    #[test]
+    fn service_account_identities() {
+        let mut reg = Region::default();
+        reg.name = "dev-uk".into();
+        reg.workloadIdentity = Some(
+            serde_yaml::from_str(
+                "allowedRoleArns:
+- arn:aws:iam::123456789012:role/dev-*
+allowedGcpServiceAccounts:
+- orders@dev.iam.gserviceaccount.com",
+            )
+            .unwrap(),
+        );
+        let mut sa: ServiceAccount =
+            serde_yaml::from_str("roleArn: arn:aws:iam::123456789012:role/dev-orders").unwrap();
+        assert!(sa.create);
+        assert!(sa.verify(&reg).is_ok());
+
+        sa.roleArn = Some("arn:aws:iam::123456789012:role/prod-orders".into());
+        assert!(sa.verify(&reg).is_err()); // not in the allowlist
+        sa.roleArn = None;
+
+        sa.gcpServiceAccount = Some("orders@dev.iam.gserviceaccount.com".into());
+        assert!(sa.verify(&reg).is_ok());
+        sa.gcpServiceAccount = Some("payments@dev.iam.gserviceaccount.com".into());
+        assert!(sa.verify(&reg).is_err());
+        sa.gcpServiceAccount = None;
+
+        sa.annotations
+            .insert(super::AWS_ROLE_ARN_KEY.into(), "arn:aws:iam::1:role/x".into());
+        assert!(sa.verify(&reg).is_err());
+        sa.annotations.clear();
+
+        sa.create = false;
+        assert!(sa.verify(&reg).is_err()); // existing service accounts need a name
+        sa.name = Some("shared".into());
+        assert!(sa.verify(&reg).is_ok());
+    }
+}
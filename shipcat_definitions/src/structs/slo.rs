@@ -0,0 +1,264 @@
+use super::{
+    prometheusalert::{PrometheusAlert, PrometheusAlertSeverity},
+    Result,
+};
+use regex::Regex;
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+
+/// Service level objectives for a service
+///
+/// Generates multi-window, multi-burn-rate alerts (as `prometheusAlerts`)
+/// and the recording rules they rely on (as `sloRecordingRules`).
+///
+/// Assumes the service exposes a request counter `{metric}_total` with a `code` label,
+/// and a request duration histogram `{metric}_duration_seconds` (for latency targets).
+///
+/// ```yaml
+/// slos:
+///   availability: 99.9
+///   window: 30d
+///   latency:
+///   - percentile: 99
+///     threshold: 500ms
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Slo {
+    /// Percentage of requests that must succeed (non-5xx)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub availability: Option<f64>,
+
+    /// Latency targets as percentiles of requests faster than a threshold
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub latency: Vec<LatencyTarget>,
+
+    /// Error budget window
+    #[serde(default = "default_window")]
+    pub window: String,
+
+    /// Prefix of the request metrics exposed by the service
+    #[serde(default = "default_metric")]
+    pub metric: String,
+}
+
+/// A latency objective
+///
+/// `percentile`% of requests must complete within `threshold`.
+/// The threshold must correspond to a histogram bucket.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct LatencyTarget {
+    /// Percentage of requests that must be faster than the threshold
+    pub percentile: f64,
+    /// Duration threshold (e.g. `250ms` or `1s`)
+    pub threshold: String,
+}
+
+/// A Prometheus recording rule
+///
+/// Corresponds to a recording Rule object in the Prometheus Operator API spec.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrometheusRecordingRule {
+    /// Name of the resulting time series
+    pub record: String,
+    /// PromQL expression to record
+    pub expr: String,
+    /// Labels to add to the recorded series
+    pub labels: BTreeMap<String, String>,
+}
+
+fn default_window() -> String {
+    "30d".into()
+}
+fn default_metric() -> String {
+    "http_requests".into()
+}
+
+/// Burn rate alert windows as (long window, short window, burn rate factor, severity)
+///
+/// From the multi-window, multi-burn-rate recommendations in the SRE workbook.
+const BURN_RATES: [(&str, &str, f64, &str); 4] = [
+    ("1h", "5m", 14.4, "FastBurn"),
+    ("6h", "30m", 6.0, "FastBurn"),
+    ("1d", "2h", 3.0, "SlowBurn"),
+    ("3d", "6h", 1.0, "SlowBurn"),
+];
+
+/// All windows we need recording rules for
+const RULE_WINDOWS: [&str; 7] = ["5m", "30m", "1h", "2h", "6h", "1d", "3d"];
+
+impl LatencyTarget {
+    /// Threshold in seconds (for matching histogram buckets)
+    fn threshold_seconds(&self) -> Result<f64> {
+        let re = Regex::new(r"^(\d+(?:\.\d+)?)(ms|s)$").unwrap();
+        if let Some(caps) = re.captures(&self.threshold) {
+            let num: f64 = caps[1].parse()?;
+            Ok(if &caps[2] == "ms" { num / 1000.0 } else { num })
+        } else {
+            bail!(
+                "Latency threshold {} must be like '250ms' or '1s'",
+                self.threshold
+            )
+        }
+    }
+
+    /// Name used for labels and alert names, e.g. `p99`
+    fn slo_name(&self) -> String {
+        format!("p{}", self.percentile.to_string().replace('.', ""))
+    }
+}
+
+impl Slo {
+    pub fn verify(&self, svc: &str) -> Result<()> {
+        if self.availability.is_none() && self.latency.is_empty() {
+            bail!("slos for {} must declare an availability or latency target", svc);
+        }
+        if let Some(a) = self.availability {
+            if a <= 0.0 || a >= 100.0 {
+                bail!("slos.availability for {} must be a percentage below 100", svc);
+            }
+        }
+        for l in &self.latency {
+            if l.percentile <= 0.0 || l.percentile >= 100.0 {
+                bail!(
+                    "slos.latency percentile for {} must be a percentage below 100",
+                    svc
+                );
+            }
+            l.threshold_seconds()?;
+        }
+        if !Regex::new(r"^\d+[dw]$").unwrap().is_match(&self.window) {
+            bail!(
+                "slos.window for {} must be in days or weeks (like '30d' or '4w')",
+                svc
+            );
+        }
+        Ok(())
+    }
+
+    /// Objectives as (slo label, target percentage, error ratio expr with a `WINDOW` placeholder)
+    fn objectives(&self, svc: &str) -> Result<Vec<(String, f64, String)>> {
+        let mut res = vec![];
+        if let Some(a) = self.availability {
+            let expr = format!(
+                "sum(rate({m}_total{{service=\"{s}\",code=~\"5..\"}}[WINDOW])) / \
+                 sum(rate({m}_total{{service=\"{s}\"}}[WINDOW]))",
+                m = self.metric,
+                s = svc
+            );
+            res.push(("availability".to_string(), a, expr));
+        }
+        for l in &self.latency {
+            let expr = format!(
+                "1 - (sum(rate({m}_duration_seconds_bucket{{service=\"{s}\",le=\"{le}\"}}[WINDOW])) / \
+                 sum(rate({m}_duration_seconds_count{{service=\"{s}\"}}[WINDOW])))",
+                m = self.metric,
+                s = svc,
+                le = l.threshold_seconds()?
+            );
+            res.push((l.slo_name(), l.percentile, expr));
+        }
+        Ok(res)
+    }
+
+    /// Recording rules for the error ratio of every objective over every window
+    pub fn recording_rules(&self, svc: &str) -> Result<Vec<PrometheusRecordingRule>> {
+        let mut rules = vec![];
+        for (slo, _, expr) in self.objectives(svc)? {
+            for w in &RULE_WINDOWS {
+                let mut labels = BTreeMap::new();
+                labels.insert("service".to_string(), svc.to_string());
+                labels.insert("slo".to_string(), slo.clone());
+                rules.push(PrometheusRecordingRule {
+                    record: format!("slo:sli_error:ratio_rate{}", w),
+                    expr: expr.replace("WINDOW", w),
+                    labels,
+                });
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Multi-window, multi-burn-rate alerts for every objective
+    pub fn alerts(&self, svc: &str) -> Result<Vec<PrometheusAlert>> {
+        use inflector::cases::pascalcase::to_pascal_case;
+        let mut alerts = vec![];
+        for (slo, target, _) in self.objectives(svc)? {
+            let budget = (100.0 - target) / 100.0;
+            for speed in &["FastBurn", "SlowBurn"] {
+                let conditions = BURN_RATES
+                    .iter()
+                    .filter(|(_, _, _, s)| s == speed)
+                    .map(|(long, short, factor, _)| {
+                        format!(
+                            "(slo:sli_error:ratio_rate{l}{{service=\"{s}\",slo=\"{o}\"}} > ({f} * {b}) \
+                             and slo:sli_error:ratio_rate{sh}{{service=\"{s}\",slo=\"{o}\"}} > ({f} * {b}))",
+                            l = long,
+                            sh = short,
+                            s = svc,
+                            o = slo,
+                            f = factor,
+                            b = budget
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let (severity, min_duration) = if *speed == "FastBurn" {
+                    (PrometheusAlertSeverity::Error, "2m")
+                } else {
+                    (PrometheusAlertSeverity::Warning, "15m")
+                };
+                alerts.push(PrometheusAlert {
+                    name: format!("{}Slo{}", to_pascal_case(&slo), speed),
+                    summary: format!("{} is burning its {} error budget too fast", svc, slo),
+                    description: format!(
+                        "{} is consuming its {} error budget ({}% over {}) at a rate that will exhaust it early.",
+                        svc, slo, target, self.window
+                    ),
+                    expr: conditions.join(" or "),
+                    min_duration: min_duration.into(),
+                    severity,
+                    labels: BTreeMap::new(),
+                });
+            }
+        }
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slo;
+
+    #[test]
+    fn slo_rule_generation() {
+        let slo: Slo =
+            serde_yaml::from_str("availability: 99.9\nlatency:\n- percentile: 99\n  threshold: 500ms\n")
+                .unwrap();
+        assert!(slo.verify("fake-ask").is_ok());
+        assert_eq!(slo.window, "30d");
+
+        let rules = slo.recording_rules("fake-ask").unwrap();
+        assert_eq!(rules.len(), 14); // 2 objectives x 7 windows
+        assert!(rules[0].expr.contains("[5m]"));
+        assert!(rules[7].expr.contains("le=\"0.5\""));
+
+        let alerts = slo.alerts("fake-ask").unwrap();
+        assert_eq!(alerts.len(), 4);
+        assert_eq!(alerts[0].name, "AvailabilitySloFastBurn");
+        assert_eq!(alerts[3].name, "P99SloSlowBurn");
+        for a in alerts {
+            assert!(a.verify("fake-ask").is_ok(), "{} verifies", a.name);
+        }
+    }
+
+    #[test]
+    fn slo_verify() {
+        let slo: Slo = serde_yaml::from_str("window: 30d").unwrap();
+        assert!(slo.verify("fake-ask").is_err());
+        let slo: Slo = serde_yaml::from_str("availability: 100").unwrap();
+        assert!(slo.verify("fake-ask").is_err());
+        let slo: Slo = serde_yaml::from_str("latency:\n- percentile: 95\n  threshold: fast\n").unwrap();
+        assert!(slo.verify("fake-ask").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use super::Result;
+use regex::Regex;
+use schemars::JsonSchema;
+use std::{collections::BTreeSet, ops::Not};
+
+/// Smoke tests run by `shipcat apply` after a successful rollout
+///
+/// Http checks hit the service's in-cluster url (and optionally its kong route),
+/// jobs run a script to completion in a throwaway pod.
+///
+/// ```yaml
+/// smokeTests:
+///   rollback: true
+///   tests:
+///   - name: health
+///     http:
+///       path: /health
+///       body: '"status":\s*"ok"'
+///       kong: true
+///   - name: e2e
+///     job:
+///       script: ./e2e.sh --smoke
+///       timeoutSeconds: 600
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SmokeTests {
+    /// Checks to run, in order
+    #[serde(default)]
+    pub tests: Vec<SmokeTest>,
+
+    /// Roll the workload back to its previous revision when a smoke test fails
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub rollback: bool,
+}
+
+/// A single smoke test, either an http check or a job
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SmokeTest {
+    /// Name of the test, shown in results
+    pub name: String,
+
+    /// Http request to the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpSmokeTest>,
+
+    /// Script run to completion in a throwaway pod
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobSmokeTest>,
+}
+
+/// Http smoke test
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct HttpSmokeTest {
+    /// Path to request, e.g. `/health`
+    pub path: String,
+
+    /// Expected status code
+    #[serde(default = "default_status")]
+    pub status: u16,
+
+    /// Regex the response body must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// Also request the path through the service's kong route
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub kong: bool,
+
+    /// Seconds to wait for a response
+    #[serde(default = "default_http_timeout")]
+    pub timeoutSeconds: u32,
+}
+
+/// Job smoke test
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct JobSmokeTest {
+    /// Shell script that must exit successfully
+    pub script: String,
+
+    /// Image to run the script in
+    ///
+    /// Defaults to the image and version of the service being rolled out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// Seconds the pod may run for, including pulling the image
+    #[serde(default = "default_job_timeout")]
+    pub timeoutSeconds: u32,
+}
+
+fn default_status() -> u16 {
+    200
+}
+fn default_http_timeout() -> u32 {
+    10
+}
+fn default_job_timeout() -> u32 {
+    300
+}
+
+impl SmokeTests {
+    /// Verify the tests of a service
+    ///
+    /// Http checks need the service to expose an `httpPort`, and `kong` checks a kong api.
+    pub fn verify(&self, svc: &str, has_http: bool, has_kong: bool) -> Result<()> {
+        if self.tests.is_empty() {
+            bail!("smokeTests for {} must declare at least one test", svc);
+        }
+        // names end up in pod names and script output
+        let name_re = Regex::new(r"^[0-9a-z\-]{1,30}$").unwrap();
+        let mut names = BTreeSet::new();
+        for t in &self.tests {
+            if !name_re.is_match(&t.name) {
+                bail!(
+                    "smokeTests name '{}' of {} must be short, lower case and dashed",
+                    t.name,
+                    svc
+                );
+            }
+            if !names.insert(&t.name) {
+                bail!("smokeTests of {} has more than one test named {}", svc, t.name);
+            }
+            match (&t.http, &t.job) {
+                (Some(h), None) => h.verify(svc, &t.name, has_http, has_kong)?,
+                (None, Some(j)) => {
+                    if j.script.trim().is_empty() {
+                        bail!("smokeTests job {} of {} has an empty script", t.name, svc);
+                    }
+                    if j.timeoutSeconds == 0 || j.timeoutSeconds > 3600 {
+                        bail!(
+                            "smokeTests job {} of {} needs a timeoutSeconds up to 3600",
+                            t.name,
+                            svc
+                        );
+                    }
+                }
+                _ => bail!(
+                    "smokeTests {} of {} must have exactly one of http or job",
+                    t.name,
+                    svc
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HttpSmokeTest {
+    fn verify(&self, svc: &str, name: &str, has_http: bool, has_kong: bool) -> Result<()> {
+        if !has_http {
+            bail!("smokeTests http check {} needs {} to have an httpPort", name, svc);
+        }
+        if self.kong && !has_kong {
+            bail!(
+                "smokeTests http check {} goes through kong, but {} has no kong api",
+                name,
+                svc
+            );
+        }
+        // paths end up in a shell script, so keep them boring
+        let path_re = Regex::new(r"^/[0-9A-Za-z\-._~/?=&%]*$").unwrap();
+        if !path_re.is_match(&self.path) {
+            bail!(
+                "smokeTests http check {} of {} has an invalid path {}",
+                name,
+                svc,
+                self.path
+            );
+        }
+        if self.status < 100 || self.status > 599 {
+            bail!(
+                "smokeTests http check {} of {} expects an invalid status",
+                name,
+                svc
+            );
+        }
+        if self.timeoutSeconds == 0 || self.timeoutSeconds > 60 {
+            bail!(
+                "smokeTests http check {} of {} needs a timeoutSeconds up to 60",
+                name,
+                svc
+            );
+        }
+        if let Some(b) = &self.body {
+            if let Err(e) = Regex::new(b) {
+                bail!(
+                    "smokeTests http check {} of {} has an invalid body regex: {}",
+                    name,
+                    svc,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmokeTests;
+
+    #[test]
+    fn smoke_test_verify() {
+        let tests: SmokeTests = serde_yaml::from_str(
+            "tests:
+- name: health
+  http: {path: /health, body: ok, kong: true}
+- name: e2e
+  job: {script: ./e2e.sh}",
+        )
+        .unwrap();
+        assert_eq!(tests.tests[0].http.as_ref().unwrap().status, 200);
+        assert_eq!(tests.tests[0].http.as_ref().unwrap().timeoutSeconds, 10);
+        assert_eq!(tests.tests[1].job.as_ref().unwrap().timeoutSeconds, 300);
+        assert!(tests.verify("fake-ask", true, true).is_ok());
+        assert!(tests.verify("fake-ask", true, false).is_err());
+        assert!(tests.verify("fake-ask", false, true).is_err());
+
+        let both: SmokeTests =
+            serde_yaml::from_str("tests: [{name: both, http: {path: /}, job: {script: 'true'}}]").unwrap();
+        assert!(both.verify("fake-ask", true, false).is_err());
+        let bad_regex: SmokeTests =
+            serde_yaml::from_str("tests: [{name: health, http: {path: /health, body: '('}}]").unwrap();
+        assert!(bad_regex.verify("fake-ask", true, false).is_err());
+        let bad_path: SmokeTests =
+            serde_yaml::from_str("tests: [{name: health, http: {path: '/$(reboot)'}}]").unwrap();
+        assert!(bad_path.verify("fake-ask", true, false).is_err());
+        let slow: SmokeTests =
+            serde_yaml::from_str("tests: [{name: e2e, job: {script: 'true', timeoutSeconds: 0}}]").unwrap();
+        assert!(slow.verify("fake-ask", true, false).is_err());
+    }
+}
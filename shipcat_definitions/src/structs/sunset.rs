@@ -0,0 +1,94 @@
+use super::Result;
+use crate::teams::Owners;
+use chrono::{NaiveDate, Utc};
+use schemars::JsonSchema;
+use std::fmt;
+
+/// Days before a sunset date where the sunset is considered approaching
+pub const SUNSET_WARNING_DAYS: i64 = 30;
+
+/// A planned retirement of a service
+///
+/// Validation warns while the date is approaching, and fails once it has passed.
+///
+/// ```yaml
+/// metadata:
+///   sunset:
+///     date: 2020-06-01
+///     replacement: fake-ask-v2
+///     acknowledgedBy: clux
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Sunset {
+    /// Date after which the service should no longer run
+    pub date: NaiveDate,
+    /// Service taking over the responsibilities of this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    /// Person in teams.yml acknowledging the sunset on behalf of the owners
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledgedBy: Option<String>,
+}
+
+/// Where a service is in relation to its sunset date
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum SunsetState {
+    Scheduled,
+    Approaching,
+    Past,
+}
+
+impl fmt::Display for SunsetState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SunsetState::Scheduled => write!(f, "Scheduled"),
+            SunsetState::Approaching => write!(f, "Approaching"),
+            SunsetState::Past => write!(f, "Past"),
+        }
+    }
+}
+
+impl Sunset {
+    /// Days left until the sunset date, negative once it has passed
+    pub fn days_left(&self) -> i64 {
+        (self.date - Utc::today().naive_utc()).num_days()
+    }
+
+    pub fn state(&self) -> SunsetState {
+        match self.days_left() {
+            d if d < 0 => SunsetState::Past,
+            d if d <= SUNSET_WARNING_DAYS => SunsetState::Approaching,
+            _ => SunsetState::Scheduled,
+        }
+    }
+
+    pub fn verify(&self, owners: &Owners) -> Result<()> {
+        if let Some(p) = &self.acknowledgedBy {
+            if !owners.people.contains_key(p) {
+                bail!(
+                    "Sunset acknowledged by {} who does not match a person in teams.yml",
+                    p
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sunset, SunsetState};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn sunset_states() {
+        let mut s: Sunset = serde_yaml::from_str("date: 2999-01-01\nreplacement: fake-ask-v2").unwrap();
+        assert_eq!(s.state(), SunsetState::Scheduled);
+        s.date = Utc::today().naive_utc() + Duration::days(10);
+        assert_eq!(s.days_left(), 10);
+        assert_eq!(s.state(), SunsetState::Approaching);
+        s.date = Utc::today().naive_utc() - Duration::days(1);
+        assert_eq!(s.state(), SunsetState::Past);
+    }
+}
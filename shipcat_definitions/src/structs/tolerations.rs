@@ -1,14 +1,15 @@
 use super::Result;
+use schemars::JsonSchema;
 
 /// Operator for a toleraton
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub enum Operator {
     Exists,
     Equal,
 }
 
 /// Effect of a toleration
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 pub enum Effect {
     NoSchedule,
     NoExecute,
@@ -21,7 +22,7 @@ impl Default for Effect {
 }
 
 /// Kubernetes Tolerations parameters for a service
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Tolerations {
     /// What key does the toleration apply to?
     #[serde(default, skip_serializing_if = "Option::is_none")]
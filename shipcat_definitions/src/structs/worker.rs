@@ -1,4 +1,4 @@
-use super::{autoscaling::AutoScaling, Container};
+use super::{autoscaling::AutoScaling, Container, Gate, Kong};
 use std::collections::BTreeMap;
 
 /// Worker for a service
@@ -20,6 +20,23 @@ pub struct Worker {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub httpPort: Option<u32>,
 
+    /// Kong API exposing the worker's own `httpPort`
+    ///
+    /// The worker gets a separate `{service}-{worker}` Service, and the API is named after it.
+    ///
+    /// ```yaml
+    /// kong:
+    ///   uris: /webapp-admin
+    ///   authorization:
+    ///     allowed_audiences: [admin.babylontech.co.uk]
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kong: Option<Kong>,
+
+    /// Gate config for the worker's Kong API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gate: Option<Gate>,
+
     /// Common properties for all types of container
     #[serde(flatten)]
     pub container: Container,
@@ -34,4 +51,19 @@ pub struct Worker {
     /// ```
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub podAnnotations: BTreeMap<String, String>,
+
+    /// Whether applies wait for the worker to roll out along with the main workload
+    #[serde(default = "default_track_rollout")]
+    pub trackRollout: bool,
+}
+
+fn default_track_rollout() -> bool {
+    true
+}
+
+impl Worker {
+    /// Name of the Service (and Kong API) exposing the worker
+    pub fn service_name(&self, service: &str) -> String {
+        format!("{}-{}", service, self.container.name)
+    }
 }
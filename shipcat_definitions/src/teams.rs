@@ -1,12 +1,13 @@
 use super::Result;
 use crate::structs::SlackChannel;
+use schemars::JsonSchema;
 use std::{
     collections::BTreeMap,
     path::{Path, PathBuf},
 };
 
 /// Information on one human
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Person {
     /// Name in "firstname.lastname" format (must match filename)
     pub name: String,
@@ -19,7 +20,7 @@ pub struct Person {
 }
 
 /// Information about a Squad of humans
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Squad {
     /// Dash-separated, lower-case name of the squad
     pub name: String,
@@ -32,10 +33,15 @@ pub struct Squad {
     pub github: GithubTeams,
     /// Slack channels for the squad
     pub slack: SlackSet,
+    /// Alertmanager receiver paging the squad (e.g. a PagerDuty service)
+    ///
+    /// Without this, paging alerts are routed to slack only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagerduty: Option<String>,
 }
 
 /// Information about a Tribe of squads
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Tribe {
     /// Dash-separated, lower-case name of the tribe
     pub name: String,
@@ -51,7 +57,7 @@ pub struct Tribe {
 ///
 /// Contains all data from all 4 folders in a EWOK_TEAMS_DIR
 /// All entries are sorted by filename (.name properties)
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct Owners {
     /// All people in people/{key}.toml
     pub people: BTreeMap<String, Person>,
@@ -79,6 +85,11 @@ impl Owners {
         let res = serde_yaml::from_str(&data)?;
         Ok(res)
     }
+
+    /// Find the tribe a squad belongs to
+    pub fn tribe_of(&self, squad: &str) -> Option<&Tribe> {
+        self.tribes.values().find(|t| t.squads.iter().any(|s| s == squad))
+    }
 }
 
 /// A set of slack channels
@@ -88,7 +99,7 @@ impl Owners {
 ///
 /// If neither notifications or alerts have been specified, these will end up in
 /// your internal or support channel.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SlackSet {
     /// An internal slack channel for humans (no notifications)
     ///
@@ -110,7 +121,7 @@ pub struct SlackSet {
 }
 
 /// A set of github teams
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GithubTeams {
     /// Team name on github in lowercase, dash-separated form
     pub team: String,
@@ -1,6 +1,7 @@
+use regex::Regex;
 use std::{collections::HashMap, iter};
 
-use super::{ErrorKind, Result, ResultExt};
+use super::{Error, ErrorKind, Result, ResultExt};
 use tera::{self, try_get_value, Context, Tera, Value};
 
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
@@ -51,6 +52,57 @@ pub fn render_file_data(data: String, context: &Context) -> Result<String> {
     Ok(xs.join("\n"))
 }
 
+/// Describe why a template failed to render
+///
+/// Includes the name of a missing variable, and the line and column of the failure
+/// (as reported by the parser, or of the first use of a missing variable).
+pub fn describe_error(err: &Error, tpl: &str) -> String {
+    let msgs = err.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    let missing_re = Regex::new(r"Variable `([^`]+)` not found").unwrap();
+    let pos_re = Regex::new(r"--> (\d+):(\d+)").unwrap();
+
+    let missing = msgs
+        .iter()
+        .find_map(|m| missing_re.captures(m))
+        .map(|c| c[1].to_string());
+    let position = msgs
+        .iter()
+        .find_map(|m| pos_re.captures(m))
+        .map(|c| (c[1].to_string(), c[2].to_string()))
+        .or_else(|| {
+            let var = missing.as_ref()?;
+            tpl.lines().enumerate().find_map(|(i, l)| {
+                l.find(var.as_str())
+                    .map(|col| ((i + 1).to_string(), (col + 1).to_string()))
+            })
+        });
+
+    let reason = match &missing {
+        Some(var) => format!("variable `{}` not found in context", var),
+        // innermost error has the actual reason (on a `= ` line for parse errors)
+        None => {
+            let inner = msgs.last().map(String::as_str).unwrap_or("unknown error");
+            inner
+                .lines()
+                .find_map(|l| l.trim().strip_prefix("= "))
+                .unwrap_or(inner)
+                .to_string()
+        }
+    };
+    match position {
+        Some((line, col)) => format!("line {}, column {}: {}", line, col, reason),
+        None => reason,
+    }
+}
+
+/// Render a named template file with an `InvalidTemplate` error describing failures
+fn render_template_file(svc: &str, name: &str, data: String, context: &Context) -> Result<String> {
+    render_file_data(data.clone(), context).map_err(|e| {
+        let reason = describe_error(&e, &data);
+        Error::with_chain(e, ErrorKind::InvalidTemplate(svc.into(), name.into(), reason))
+    })
+}
+
 /// One off template
 pub fn one_off(tpl: &str, ctx: &Context) -> Result<String> {
     let mut tera = Tera::default();
@@ -87,15 +139,48 @@ impl Manifest {
         Ok(ctx)
     }
 
+    /// The template context with every secret value replaced by a distinct placeholder
+    ///
+    /// Lets `template_configs` tell which files are rendered from secrets.
+    fn make_secret_probe_context(&self, reg: &Region) -> Result<Context> {
+        let mut ctx = self.make_template_context(reg)?;
+        let mut full_env = self.env.plain.clone();
+        for (i, k) in self.secrets.keys().enumerate() {
+            full_env.insert(k.clone(), format!("SHIPCAT_SECRET_PROBE_{}", i));
+        }
+        ctx.insert("env", &full_env);
+        Ok(ctx)
+    }
+
+    /// The template context as yaml, with secret values masked
+    ///
+    /// Used by `shipcat template --show-context` to debug templates.
+    pub fn template_context_yaml(&self, reg: &Region) -> Result<String> {
+        let mut ctx = self.make_template_context(reg)?.as_json()?;
+        for k in self.secrets.keys() {
+            ctx["env"][k] = "<secret>".into();
+        }
+        Ok(serde_yaml::to_string(&ctx)?)
+    }
+
     /// Replace template in values with template result inplace
     pub fn template_configs(&mut self, reg: &Region) -> Result<()> {
         let ctx = self.make_template_context(reg)?;
+        let probe = if self.secrets.is_empty() {
+            None
+        } else {
+            Some(self.make_secret_probe_context(reg)?)
+        };
         if let Some(ref mut cfg) = self.configs {
             for f in &mut cfg.files {
                 if let Some(ref mut v) = f.value {
                     let data: String = v.clone();
-                    let svc = self.name.clone();
-                    *v = render_file_data(data, &ctx).chain_err(|| ErrorKind::InvalidTemplate(svc))?;
+                    *v = render_template_file(&self.name, &f.name, data.clone(), &ctx)?;
+                    // track files rendered from secret context for diff masking
+                    // a file uses secrets if rendering it without their values changes it
+                    f.secret = probe
+                        .as_ref()
+                        .map_or(false, |p| render_file_data(data, p).ok().as_ref() != Some(v));
                 } else {
                     bail!("configs must be read first - missing {}", f.name); // internal error
                 }
@@ -114,6 +199,22 @@ impl Manifest {
     }
 }
 
+// helpers for regional override files
+impl Region {
+    /// Render a templated override file (`overrides.yml.j2`) for this region
+    ///
+    /// Only region level variables are available as no manifest has been built yet.
+    /// Manifest level templates (like evars) must be escaped with `{% raw %}`.
+    pub fn template_overrides(&self, svc: &str, tpl: String) -> Result<String> {
+        let mut ctx = Context::new();
+        ctx.insert("service", svc);
+        ctx.insert("region", &self.name);
+        ctx.insert("environment", &self.environment.to_string());
+        ctx.insert("base_urls", &self.base_urls);
+        render_template_file(svc, "overrides.yml.j2", tpl, &ctx)
+    }
+}
+
 // helpers for env vars
 use super::structs::EnvVars;
 impl EnvVars {
@@ -148,15 +249,81 @@ impl VaultConfig {
     pub async fn template(&self, owned_mfs: Vec<String>, env: Environment) -> Result<String> {
         let mut ctx = Context::new();
         ctx.insert("folder", &self.folder);
+        ctx.insert("engine", &self.engine);
         ctx.insert("team_owned_services", &owned_mfs);
 
-        let tpl = if env == Environment::Prod {
-            read_arbitrary_template_file("vault", "team-policy-prod.hcl").await?
+        let name = if env == Environment::Prod {
+            "team-policy-prod.hcl"
         } else {
-            read_arbitrary_template_file("vault", "team-policy.hcl").await?
+            "team-policy.hcl"
+        };
+        let tpl = read_arbitrary_template_file("vault", name).await?;
+        render_template_file("vault", &format!("{}.j2", name), tpl, &ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_error, render_file_data};
+    use crate::{
+        structs::{ConfigMap, ConfigMappedFile},
+        Manifest, Region,
+    };
+    use tera::Context;
+
+    #[test]
+    fn template_error_description() {
+        let mut ctx = Context::new();
+        ctx.insert("service", "fake-ask");
+
+        let tpl = "name: {{ service }}\nurl: {{ base_urls.services }}\n";
+        let err = render_file_data(tpl.into(), &ctx).unwrap_err();
+        assert_eq!(
+            describe_error(&err, tpl),
+            "line 2, column 9: variable `base_urls.services` not found in context"
+        );
+
+        let tpl = "name: {{ service }}\nport: {{ 8080 | }}\n";
+        let err = render_file_data(tpl.into(), &ctx).unwrap_err();
+        assert_eq!(
+            describe_error(&err, tpl),
+            "line 2, column 17: expected an identifier (must start with a-z)"
+        );
+    }
+
+    #[test]
+    fn template_configs_track_secret_use() {
+        let file = |name: &str, tpl: &str| ConfigMappedFile {
+            name: format!("{}.j2", name),
+            dest: name.into(),
+            value: Some(tpl.into()),
+            secret: false,
         };
-        let res =
-            render_file_data(tpl, &ctx).chain_err(|| ErrorKind::InvalidTemplate("vault-template".into()))?;
-        Ok(res)
+        let mut mf = Manifest::default();
+        mf.name = "fake-ask".into();
+        mf.env.plain.insert("LOG_LEVEL".into(), "true".into());
+        mf.secrets.insert("DB_PASSWORD".into(), "hunter2".into());
+        mf.secrets.insert("FEATURE_FLAG".into(), "true".into());
+        mf.configs = Some(ConfigMap {
+            mount: "/config/".into(),
+            files: vec![
+                file("database.yml", "password: {{ env.DB_PASSWORD }}\n"),
+                file("logging.yml", "verbose: {{ env.LOG_LEVEL }}\n"),
+                file(
+                    "flags.yml",
+                    "{% if env.FEATURE_FLAG == \"true\" %}enabled: true{% endif %}\n",
+                ),
+            ],
+        });
+        mf.template_configs(&Region::default()).unwrap();
+
+        let files = &mf.configs.unwrap().files;
+        assert_eq!(files[0].value.as_ref().unwrap(), "password: hunter2");
+        assert!(files[0].secret);
+        // a plain value that happens to equal a secret does not make the file secret
+        assert_eq!(files[1].value.as_ref().unwrap(), "verbose: true");
+        assert!(!files[1].secret);
+        // secrets used without being interpolated still count
+        assert!(files[2].secret);
     }
 }
@@ -1,7 +1,25 @@
-use std::{collections::BTreeMap, env};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    collections::BTreeMap,
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 use super::{Error, ErrorKind, Result, ResultExt};
-use crate::region::VaultConfig;
+use crate::region::{VaultConfig, VaultEngine};
+
+lazy_static! {
+    /// Client shared by every Vault, so that connections are reused across manifests
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+    /// Secrets recently read by this process, and when they were read, by url
+    static ref SECRET_CACHE: Mutex<BTreeMap<String, (String, Instant)>> = Mutex::new(BTreeMap::new());
+}
+
+/// How long a secret read by this process is reused for
+///
+/// Long enough for a run to read each secret once, short enough for long-running workers to see changes.
+const SECRET_CACHE_TTL: Duration = Duration::from_secs(60);
 
 fn default_addr() -> Result<String> {
     env::var("VAULT_ADDR").map_err(|_| ErrorKind::MissingVaultAddr.into())
@@ -59,9 +77,42 @@ struct Secret {
     /// NB: If we put String instead of SecretValue we discard integer-like values
     data: BTreeMap<String, SecretValue>,
     // How long this secret will remain valid for, in seconds.
+    #[serde(default)]
     lease_duration: u64,
 }
 
+/// Secret data retrieved from a KV v2 engine
+///
+/// The key-value pairs are nested one level deeper, next to the version metadata.
+#[derive(Debug, Deserialize)]
+struct VersionedSecret {
+    data: Secret,
+}
+
+/// Metadata of the version created by a KV v2 write
+#[derive(Debug, Deserialize)]
+struct WrittenVersion {
+    version: u32,
+}
+
+/// Response to a KV v2 write
+#[derive(Debug, Deserialize)]
+struct WriteResponse {
+    data: WrittenVersion,
+}
+
+/// Metadata of a secret in a KV v2 engine
+#[derive(Debug, Deserialize)]
+struct SecretMetadata {
+    current_version: u32,
+}
+
+/// Response to a KV v2 metadata read
+#[derive(Debug, Deserialize)]
+struct MetadataResponse {
+    data: SecretMetadata,
+}
+
 /// List data retrieved from Vault when listing available secrets
 #[derive(Debug, Deserialize)]
 struct ListSecrets {
@@ -78,6 +129,8 @@ pub struct Vault {
     token: String,
     /// Vault operation mode
     mode: Mode,
+    /// Version of the key-value engine at secret/
+    engine: VaultEngine,
 }
 
 /// Vault usage mode
@@ -93,24 +146,31 @@ impl Vault {
     /// Initialize using the same evars or token files that the `vault` CLI uses
     pub fn from_evars() -> Result<Vault> {
         Vault::new(
-            reqwest::Client::new(),
+            CLIENT.clone(),
             &default_addr()?,
             default_token()?,
             Mode::Standard,
+            VaultEngine::Kv1,
         )
     }
 
     /// Initialize using VAULT_TOKEN evar + addr from the Region
     pub fn regional(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Standard)
+        Vault::new(
+            CLIENT.clone(),
+            &vc.url,
+            default_token()?,
+            Mode::Standard,
+            vc.engine,
+        )
     }
 
     /// Initialize using dummy values and return garbage
     pub fn mocked(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Mocked)
+        Vault::new(CLIENT.clone(), &vc.url, default_token()?, Mode::Mocked, vc.engine)
     }
 
-    fn new<U, S>(client: reqwest::Client, addr: U, token: S, mode: Mode) -> Result<Vault>
+    fn new<U, S>(client: reqwest::Client, addr: U, token: S, mode: Mode, engine: VaultEngine) -> Result<Vault>
     where
         U: reqwest::IntoUrl,
         S: Into<String>,
@@ -120,6 +180,7 @@ impl Vault {
             client,
             addr,
             mode,
+            engine,
             token: token.into(),
         })
     }
@@ -129,7 +190,13 @@ impl Vault {
     }
 
     // The actual HTTP GET logic
-    async fn get_secret(&self, path: &str) -> Result<Secret> {
+    async fn get_secret(&self, path: &str) -> Result<BTreeMap<String, SecretValue>> {
+        let body = self.get(path).await?;
+        secret_data(self.engine, &body)
+    }
+
+    // HTTP GET, returning the response body
+    async fn get(&self, path: &str) -> Result<String> {
         let url = self.addr.join(&format!("v1/{}", path))?;
         debug!("GET {}", url);
 
@@ -150,15 +217,39 @@ impl Vault {
             return Err(err).chain_err(&mkerr);
         }
 
-        let body = res.text().await?;
-        Ok(serde_json::from_str(&body)?)
+        Ok(res.text().await?)
+    }
+
+    // The actual HTTP POST logic, returning the response body
+    async fn post(&self, path: &str, body: &serde_json::Value) -> Result<String> {
+        let url = self.addr.join(&format!("v1/{}", path))?;
+        debug!("POST {}", url);
+
+        let mkerr = || ErrorKind::Url(url.clone());
+        let res = self
+            .client
+            .post(url.clone())
+            .header("X-Vault-Token", self.token.clone())
+            .body(serde_json::to_vec(body)?)
+            .send()
+            .await
+            .chain_err(&mkerr)?;
+
+        if !res.status().is_success() {
+            let status = res.status().to_owned();
+            let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
+            return Err(err).chain_err(&mkerr);
+        }
+        Ok(res.text().await?)
     }
 
     /// List secrets
     ///
     /// Does a HTTP LIST on the folder a service is in and returns the keys
     pub async fn list(&self, path: &str) -> Result<Vec<String>> {
-        let url = self.addr.join(&format!("v1/secret/{}?list=true", path))?;
+        let url = self
+            .addr
+            .join(&format!("v1/{}?list=true", list_path(self.engine, path)))?;
         debug!("LIST {}", url);
 
         let mkerr = || ErrorKind::Url(url.clone());
@@ -198,11 +289,26 @@ impl Vault {
 
     /// Read secret from a Vault via an authenticated HTTP GET (or memory cache)
     pub async fn read(&self, key: &str) -> Result<String> {
-        let pth = format!("secret/{}", key);
+        self.read_version(key, None).await
+    }
+
+    /// Read a specific version of a secret
+    ///
+    /// Only KV v2 engines keep versions; `None` reads the latest version.
+    /// Values are reused for a minute, so each path is fetched once per run rather than once per manifest.
+    pub async fn read_version(&self, key: &str, version: Option<u32>) -> Result<String> {
+        let pth = read_path(self.engine, key, version)?;
         if self.mode == Mode::Mocked {
             // arbitrary base64 encoded value so it's compatible with everything
             return Ok("aGVsbG8gd29ybGQ=".into());
         }
+        let cache_key = self.addr.join(&format!("v1/{}", pth))?.to_string();
+        if let Some((v, read)) = SECRET_CACHE.lock().unwrap().get(&cache_key) {
+            if read.elapsed() < SECRET_CACHE_TTL {
+                debug!("Using cached {}", pth);
+                return Ok(v.clone());
+            }
+        }
 
         let secret = self
             .get_secret(&pth)
@@ -211,19 +317,167 @@ impl Vault {
 
         // NB: Currently assume each path in vault has a single `value`
         // Read the value key (which should exist)
-        secret
-            .data
+        let value: String = secret
             .get("value")
-            .ok_or_else(|| ErrorKind::InvalidSecretForm(pth).into())
-            .map(|v| v.clone().into())
+            .ok_or_else(|| Error::from(ErrorKind::InvalidSecretForm(pth)))?
+            .clone()
+            .into();
+        SECRET_CACHE
+            .lock()
+            .unwrap()
+            .insert(cache_key, (value.clone(), Instant::now()));
+        Ok(value)
+    }
+
+    /// Latest version of a secret
+    ///
+    /// Only KV v2 engines keep versions, so this is `None` for kv1 engines.
+    pub async fn current_version(&self, key: &str) -> Result<Option<u32>> {
+        if self.engine == VaultEngine::Kv1 || self.mode == Mode::Mocked {
+            return Ok(None);
+        }
+        let pth = format!("secret/metadata/{}", key);
+        let body = self
+            .get(&pth)
+            .await
+            .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+        let metadata: MetadataResponse = serde_json::from_str(&body)?;
+        Ok(Some(metadata.data.current_version))
+    }
+
+    /// Write a new value of a secret
+    ///
+    /// Returns the version created on KV v2 engines.
+    pub async fn write(&self, key: &str, value: &str) -> Result<Option<u32>> {
+        if self.mode == Mode::Mocked {
+            bail!("Cannot write {} to a mocked vault", key);
+        }
+        let (pth, body) = write_request(self.engine, key, value);
+        let res = self
+            .post(&pth, &body)
+            .await
+            .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+
+        // forget the old value if this process read it
+        let read = read_path(self.engine, key, None)?;
+        let cache_key = self.addr.join(&format!("v1/{}", read))?.to_string();
+        SECRET_CACHE.lock().unwrap().remove(&cache_key);
+
+        Ok(match self.engine {
+            VaultEngine::Kv1 => None,
+            VaultEngine::Kv2 => Some(serde_json::from_str::<WriteResponse>(&res)?.data.version),
+        })
+    }
+
+    /// Limit how many versions of a secret are kept
+    ///
+    /// Only KV v2 engines keep versions; older versions are deleted by vault on the next write.
+    pub async fn keep_versions(&self, key: &str, versions: u32) -> Result<()> {
+        if self.engine == VaultEngine::Kv1 {
+            bail!("Cannot keep versions of {} in a kv1 vault engine", key);
+        }
+        if self.mode == Mode::Mocked {
+            bail!("Cannot write {} to a mocked vault", key);
+        }
+        let pth = format!("secret/metadata/{}", key);
+        self.post(&pth, &serde_json::json!({ "max_versions": versions }))
+            .await
+            .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+        Ok(())
+    }
+}
+
+/// Generate a random secret value
+///
+/// Uses url safe base64 so that values can be put in connection strings unescaped.
+pub fn generate_secret(bytes: usize) -> Result<String> {
+    let mut buf = vec![0u8; bytes];
+    SystemRandom::new()
+        .fill(&mut buf)
+        .map_err(|_| Error::from("Failed to generate a secret"))?;
+    Ok(base64::encode_config(&buf, base64::URL_SAFE_NO_PAD))
+}
+
+/// Api path and body writing the value of a secret
+fn write_request(engine: VaultEngine, key: &str, value: &str) -> (String, serde_json::Value) {
+    match engine {
+        VaultEngine::Kv1 => (format!("secret/{}", key), serde_json::json!({ "value": value })),
+        VaultEngine::Kv2 => (
+            format!("secret/data/{}", key),
+            serde_json::json!({ "data": { "value": value } }),
+        ),
     }
 }
 
+/// Api path of a secret under the engine mount
+fn read_path(engine: VaultEngine, key: &str, version: Option<u32>) -> Result<String> {
+    Ok(match (engine, version) {
+        (VaultEngine::Kv1, None) => format!("secret/{}", key),
+        (VaultEngine::Kv1, Some(v)) => bail!("Cannot read version {} of {} from a kv1 vault engine", v, key),
+        (VaultEngine::Kv2, None) => format!("secret/data/{}", key),
+        (VaultEngine::Kv2, Some(v)) => format!("secret/data/{}?version={}", key, v),
+    })
+}
+
+/// Api path listing the secrets in a folder under the engine mount
+fn list_path(engine: VaultEngine, folder: &str) -> String {
+    match engine {
+        VaultEngine::Kv1 => format!("secret/{}", folder),
+        VaultEngine::Kv2 => format!("secret/metadata/{}", folder),
+    }
+}
+
+/// Key-value pairs of a secret read response
+fn secret_data(engine: VaultEngine, body: &str) -> Result<BTreeMap<String, SecretValue>> {
+    Ok(match engine {
+        VaultEngine::Kv1 => serde_json::from_str::<Secret>(body)?.data,
+        VaultEngine::Kv2 => serde_json::from_str::<VersionedSecret>(body)?.data.data,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Vault;
+    use super::{generate_secret, list_path, read_path, secret_data, write_request, Vault};
+    use crate::region::VaultEngine;
     use base64;
 
+    #[test]
+    fn kv_engine_paths() {
+        let key = "dev-uk/fake-ask/FAKE_SECRET";
+        assert_eq!(
+            read_path(VaultEngine::Kv1, key, None).unwrap(),
+            format!("secret/{}", key)
+        );
+        assert!(read_path(VaultEngine::Kv1, key, Some(2)).is_err());
+        assert_eq!(
+            read_path(VaultEngine::Kv2, key, Some(2)).unwrap(),
+            format!("secret/data/{}?version=2", key)
+        );
+        assert_eq!(list_path(VaultEngine::Kv2, "dev-uk"), "secret/metadata/dev-uk");
+
+        let kv1 = r#"{"lease_duration": 2764800, "data": {"value": "hello"}}"#;
+        let kv2 = r#"{"lease_duration": 0, "data": {"data": {"value": -2}, "metadata": {"version": 3}}}"#;
+        let value = |engine, body| -> String { secret_data(engine, body).unwrap()["value"].clone().into() };
+        assert_eq!(value(VaultEngine::Kv1, kv1), "hello");
+        assert_eq!(value(VaultEngine::Kv2, kv2), "-2");
+    }
+
+    #[test]
+    fn kv_engine_writes() {
+        let key = "dev-uk/fake-ask/FAKE_SECRET";
+        let (pth, body) = write_request(VaultEngine::Kv1, key, "hello");
+        assert_eq!(pth, format!("secret/{}", key));
+        assert_eq!(body["value"], "hello");
+        let (pth, body) = write_request(VaultEngine::Kv2, key, "hello");
+        assert_eq!(pth, format!("secret/data/{}", key));
+        assert_eq!(body["data"]["value"], "hello");
+
+        let generated = generate_secret(32).unwrap();
+        assert_eq!(generated.len(), 43);
+        assert!(!generated.contains(|c| c == '/' || c == '+' || c == '='));
+        assert_ne!(generated, generate_secret(32).unwrap());
+    }
+
     #[tokio::test]
     async fn get_dev_secret() {
         let client = Vault::from_evars().unwrap();
@@ -1,16 +1,17 @@
 use merge::Merge;
+use schemars::JsonSchema;
 
 use shipcat_definitions::{
     structs::{CronJob, JobVolumeClaim},
     Result,
 };
 
-use crate::util::{Build, RelaxedString, Require};
+use crate::util::{Build, EnvironmentFilter, RelaxedString, Require};
 use std::collections::BTreeMap;
 
 use super::source::{ContainerBuildParams, ContainerSource};
 
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Deserialize, Merge, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase")]
 pub struct CronJobSource {
     pub schedule: Option<String>,
@@ -21,6 +22,9 @@ pub struct CronJobSource {
 
     #[serde(flatten)]
     pub container: ContainerSource,
+
+    #[serde(flatten)]
+    pub environments: EnvironmentFilter,
 }
 
 impl Build<CronJob, ContainerBuildParams> for CronJobSource {
@@ -19,5 +19,5 @@ mod worker;
 pub use cronjob::CronJobSource;
 pub use initcontainer::InitContainerSource;
 pub use port::PortSource;
-pub use sidecar::SidecarSource;
+pub use sidecar::{SidecarBuildParams, SidecarSource};
 pub use worker::WorkerSource;
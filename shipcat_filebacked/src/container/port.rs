@@ -1,4 +1,5 @@
 use regex::Regex;
+use schemars::JsonSchema;
 
 use shipcat_definitions::{
     structs::port::{Port, PortProtocol},
@@ -7,7 +8,7 @@ use shipcat_definitions::{
 
 use crate::util::Build;
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, Clone, Default, JsonSchema)]
 pub struct PortName(String);
 
 impl Build<String, ()> for PortName {
@@ -24,7 +25,7 @@ impl Build<String, ()> for PortName {
     }
 }
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct PortSource {
     /// Name of the port
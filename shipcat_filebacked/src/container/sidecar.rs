@@ -1,13 +1,47 @@
+use merge::Merge;
+use schemars::JsonSchema;
 use shipcat_definitions::{structs::Container, Result};
+use std::collections::BTreeMap;
 
-use super::source::{ContainerBuildParams, ContainerSource};
-use crate::util::Build;
+use super::source::{ContainerBuildParams, ContainerName, ContainerSource};
+use crate::util::{Build, EnvironmentFilter};
 
-#[derive(Deserialize, Clone, Default)]
-pub struct SidecarSource(ContainerSource);
+/// A sidecar container, optionally merged onto a profile from `sidecarProfiles`
+#[derive(Deserialize, Clone, Default, JsonSchema)]
+pub struct SidecarSource {
+    /// Name of the sidecar profile in shipcat.conf to start from
+    #[serde(default)]
+    pub profile: Option<String>,
 
-impl Build<Container, ContainerBuildParams> for SidecarSource {
-    fn build(self, params: &ContainerBuildParams) -> Result<Container> {
-        self.0.build(params)
+    #[serde(flatten)]
+    pub container: ContainerSource,
+
+    #[serde(flatten)]
+    pub environments: EnvironmentFilter,
+}
+
+pub struct SidecarBuildParams<'a> {
+    pub container: &'a ContainerBuildParams,
+    pub profiles: &'a BTreeMap<String, serde_yaml::Value>,
+}
+
+impl<'a> Build<Container, SidecarBuildParams<'a>> for SidecarSource {
+    fn build(self, params: &SidecarBuildParams<'a>) -> Result<Container> {
+        let container = match self.profile {
+            None => self.container,
+            Some(p) => {
+                let profile = match params.profiles.get(&p) {
+                    Some(v) => v.clone(),
+                    None => bail!("Sidecar profile {} is not defined in sidecarProfiles", p),
+                };
+                let mut base: ContainerSource = match serde_yaml::from_value(profile) {
+                    Ok(c) => c,
+                    Err(e) => bail!("Sidecar profile {} did not parse: {}", p, e),
+                };
+                base.name = base.name.or_else(|| Some(ContainerName(p)));
+                base.merge(self.container)
+            }
+        };
+        container.build(params.container)
     }
 }
@@ -1,24 +1,34 @@
 use merge::Merge;
+use schemars::JsonSchema;
 
 use shipcat_definitions::{
-    structs::{autoscaling::AutoScaling, Worker},
+    structs::{autoscaling::AutoScaling, Gate, Kong, Worker},
     Result,
 };
 
 use super::source::{ContainerBuildParams, ContainerSource};
-use crate::util::{Build, RelaxedString, Require};
+use crate::{
+    kong::{KongApisBuildParams, KongApisSource, KongSource},
+    util::{Build, EnvironmentFilter, RelaxedString, Require},
+};
 use std::collections::BTreeMap;
 
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Deserialize, Merge, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase")]
 pub struct WorkerSource {
     pub replica_count: Option<u32>,
     pub auto_scaling: Option<AutoScaling>,
     pub http_port: Option<u32>,
     pub pod_annotations: BTreeMap<String, RelaxedString>,
+    pub kong: Option<KongSource>,
+    pub gate: Option<Gate>,
+    pub track_rollout: Option<bool>,
 
     #[serde(flatten)]
     pub container: ContainerSource,
+
+    #[serde(flatten)]
+    pub environments: EnvironmentFilter,
 }
 
 impl Build<Worker, ContainerBuildParams> for WorkerSource {
@@ -32,6 +42,31 @@ impl Build<Worker, ContainerBuildParams> for WorkerSource {
             autoScaling: self.auto_scaling,
             httpPort: self.http_port,
             podAnnotations: self.pod_annotations.build(&())?,
+            // built with the service's other apis in `build_kong`
+            kong: None,
+            gate: self.gate,
+            trackRollout: self.track_rollout.unwrap_or(true),
         })
     }
 }
+
+impl WorkerSource {
+    /// Build the Kong API of a worker, keyed by the worker name
+    ///
+    /// The API is named `{service}-{worker}` and points at the worker's own Service.
+    pub fn build_kong(
+        &self,
+        apis: &KongApisSource,
+        params: &KongApisBuildParams,
+    ) -> Result<Option<(String, Kong)>> {
+        let (name, source) = match (&self.container.name, &self.kong) {
+            (Some(n), Some(k)) => (n.clone().build(&())?, k.clone()),
+            _ => return Ok(None),
+        };
+        let mut api = apis.build_worker_api(&name, source, params)?;
+        if let Some(g) = &self.gate {
+            api.publiclyAccessible = g.public;
+        }
+        Ok(Some((name, api)))
+    }
+}
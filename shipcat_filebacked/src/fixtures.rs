@@ -0,0 +1,371 @@
+use serde_yaml::{Mapping, Value};
+
+use shipcat_definitions::{Config, Manifest, Region, Result};
+
+use crate::manifest::{ManifestOverrides, ManifestSource};
+
+fn yaml(data: &str) -> Value {
+    serde_yaml::from_str(data).expect("fixture is valid yaml")
+}
+
+/// Builder of service manifests for tests
+///
+/// The manifest and its overrides go through the same merge pipeline
+/// as services loaded from a manifests repository.
+///
+/// ```ignore
+/// let mf = ManifestBuilder::new("svc")
+///     .with_kong("uris: /svc")
+///     .with_overrides("replicaCount: 3")
+///     .build(&conf, &region)
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct ManifestBuilder {
+    source: Mapping,
+    overrides: Vec<Value>,
+}
+
+impl ManifestBuilder {
+    /// A minimal valid manifest owned by the observability team
+    pub fn new(name: &str) -> Self {
+        ManifestBuilder {
+            source: Mapping::new(),
+            overrides: vec![],
+        }
+        .with("name", name)
+        .with("regions", "[dev-uk]")
+        .with(
+            "metadata",
+            "{team: observability, repo: 'https://github.com/babylonhealth/shipcat'}",
+        )
+    }
+
+    /// Set a top level key of `manifest.yml` to a yaml value
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.source.insert(key.into(), yaml(value));
+        self
+    }
+
+    pub fn with_kong(self, kong: &str) -> Self {
+        self.with("kong", kong)
+    }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        let env = Value::from("env");
+        if !self.source.contains_key(&env) {
+            self.source.insert(env.clone(), Value::Mapping(Mapping::new()));
+        }
+        if let Some(Value::Mapping(m)) = self.source.get_mut(&env) {
+            m.insert(key.into(), value.into());
+        }
+        self
+    }
+
+    /// Layer overrides on top, like an environment or region file would
+    pub fn with_overrides(mut self, overrides: &str) -> Self {
+        self.overrides.push(yaml(overrides));
+        self
+    }
+
+    /// The merged manifest source, before it is built
+    pub fn merged(&self, conf: &Config, reg: &Region) -> Result<ManifestSource> {
+        let source: ManifestSource = serde_yaml::from_value(Value::Mapping(self.source.clone()))?;
+        let mut merged = source.with_defaults(conf, reg)?;
+        for o in &self.overrides {
+            let overrides: ManifestOverrides = serde_yaml::from_value(o.clone())?;
+            merged = merged.merge_overrides(overrides);
+        }
+        Ok(merged)
+    }
+
+    pub async fn build(&self, conf: &Config, reg: &Region) -> Result<Manifest> {
+        self.merged(conf, reg)?.build(&(conf.clone(), reg.clone())).await
+    }
+}
+
+/// Builder of a shipcat config for tests
+///
+/// Starts out without clusters or regions, so nothing is read from disk.
+///
+/// ```ignore
+/// let conf = ConfigBuilder::new()
+///     .with("clusters", "{kind: {name: kind, api: 'https://localhost:6443', regions: [dev-uk]}}")
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct ConfigBuilder {
+    source: Mapping,
+}
+
+impl ConfigBuilder {
+    /// A minimal valid config
+    pub fn new() -> Self {
+        ConfigBuilder {
+            source: Mapping::new(),
+        }
+        .with("clusters", "{}")
+        .with("regions", "[]")
+        .with("slack", "{team: T1}")
+        .with("github", "{organisation: babylonhealth}")
+        .with("versions", "{}")
+    }
+
+    /// Set a top level key of `shipcat.conf` to a yaml value
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.source.insert(key.into(), yaml(value));
+        self
+    }
+
+    pub fn build(&self) -> Config {
+        serde_yaml::from_value(Value::Mapping(self.source.clone())).expect("fixture is a valid config")
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder of a region for tests
+#[derive(Clone)]
+pub struct RegionBuilder {
+    source: Mapping,
+}
+
+impl RegionBuilder {
+    /// A minimal dev region using a local vault
+    pub fn new(name: &str) -> Self {
+        RegionBuilder {
+            source: Mapping::new(),
+        }
+        .with("name", name)
+        .with("namespace", "dev")
+        .with("environment", "dev")
+        .with("cluster", "kind-shipcat")
+        .with("versioningScheme", "GitShaOrSemver")
+        .with("vault", "{url: 'http://localhost:8200', folder: dev}")
+    }
+
+    /// Set a key of the region to a yaml value
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.source.insert(key.into(), yaml(value));
+        self
+    }
+
+    pub fn build(&self) -> Region {
+        serde_yaml::from_value(Value::Mapping(self.source.clone())).expect("fixture is a valid region")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_yaml_snapshot;
+    use std::{env, fs, path::Path};
+
+    use super::ManifestBuilder;
+    use crate::manifest::ManifestSource;
+    use shipcat_definitions::{Config, Region};
+
+    async fn setup() -> (Config, Region) {
+        let pwd = env::current_dir().unwrap();
+        let pth = fs::canonicalize(Path::new(&pwd).join("..").join("tests")).unwrap();
+        std::env::set_current_dir(pth).unwrap();
+        let conf = Config::read().await.unwrap();
+        let region = conf.get_region("dev-uk").unwrap();
+        (conf, region)
+    }
+
+    #[tokio::test]
+    async fn snapshot_fake_ask() {
+        let (conf, region) = setup().await;
+        let mf = ManifestSource::load_manifest("fake-ask", &conf, &region)
+            .await
+            .unwrap();
+        assert_yaml_snapshot!(mf);
+    }
+
+    #[tokio::test]
+    async fn snapshot_fake_storage() {
+        let (conf, region) = setup().await;
+        let mf = ManifestSource::load_manifest("fake-storage", &conf, &region)
+            .await
+            .unwrap();
+        assert_yaml_snapshot!(mf);
+    }
+
+    #[tokio::test]
+    async fn snapshot_builder_kong() {
+        let (conf, region) = setup().await;
+        let mf = ManifestBuilder::new("kongsvc")
+            .with("httpPort", "8080")
+            .with_kong("{uris: /kongsvc, authorization: {allow_anonymous: true}}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        assert_yaml_snapshot!(mf);
+    }
+
+    #[tokio::test]
+    async fn snapshot_builder_overrides() {
+        let (conf, region) = setup().await;
+        let mf = ManifestBuilder::new("worker")
+            .with("replicaCount", "1")
+            .with_env("MODE", "base")
+            .with_env("LOG_LEVEL", "info")
+            .with_overrides("{replicaCount: 3, env: {MODE: environment}}")
+            .with_overrides("{version: 1.2.3, env: {EXTRA: region}}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        assert_eq!(mf.replicaCount, Some(3));
+        assert_eq!(&mf.env.plain["MODE"], "environment");
+        assert_eq!(&mf.env.plain["LOG_LEVEL"], "info");
+        assert_yaml_snapshot!(mf);
+    }
+
+    #[tokio::test]
+    async fn builder_worker_kong() {
+        let (conf, region) = setup().await;
+        let worker = "{name: admin, replicaCount: 1, httpPort: 9000, gate: {public: true}, \
+                      kong: {uris: /kongsvc-admin, authorization: {allowed_audiences: [internal]}}}";
+        let builder = ManifestBuilder::new("kongsvc").with("workers", &format!("[{}]", worker));
+        let mf = builder.build(&conf, &region).await.unwrap();
+        let worker = &mf.workers[0];
+        assert_eq!(worker.service_name(&mf.name), "kongsvc-admin");
+        let api = worker.kong.as_ref().unwrap();
+        assert_eq!(api.name, "kongsvc-admin");
+        assert_eq!(api.upstream_url, "http://kongsvc-admin.dev.svc.cluster.local");
+        assert!(api.publiclyAccessible); // from the worker gate
+        assert!(mf.kongApis.is_empty());
+
+        let simple = builder
+            .merged(&conf, &region)
+            .unwrap()
+            .build_simple(&conf, &region)
+            .unwrap();
+        assert!(simple.kong_apis.is_empty());
+        assert_eq!(simple.worker_kong_apis["admin"].name, "kongsvc-admin");
+    }
+
+    #[tokio::test]
+    async fn builder_environment_filters() {
+        let (conf, region) = setup().await;
+        let builder = ManifestBuilder::new("filtered")
+            .with(
+                "workers",
+                "[{name: all, replicaCount: 1}, {name: staged, replicaCount: 1, enabledIn: [preprod]}]",
+            )
+            .with(
+                "cronJobs",
+                "[{name: cleanup, schedule: '0 * * * *', disabledIn: [dev]}]",
+            )
+            .with(
+                "sidecars",
+                "[{name: proxy, image: envoy, version: 1.14.1, enabledIn: [dev]}]",
+            )
+            .with(
+                "kongApis",
+                "{filtered: {uris: /filtered, authorization: {allowed_audiences: [internal]}}, \
+                 filtered-beta: {uris: /filtered-beta, enabledIn: [preprod]}}",
+            );
+        let mf = builder.build(&conf, &region).await.unwrap();
+        assert_eq!(mf.workers.len(), 1);
+        assert_eq!(mf.workers[0].container.name, "all");
+        assert!(mf.cronJobs.is_empty());
+        assert_eq!(mf.sidecars[0].name, "proxy");
+        assert_eq!(mf.kongApis.len(), 1);
+        assert_eq!(mf.kongApis[0].name, "filtered");
+
+        let unknown = ManifestBuilder::new("filtered")
+            .with(
+                "workers",
+                "[{name: staged, replicaCount: 1, enabledIn: [staging]}]",
+            )
+            .build(&conf, &region)
+            .await;
+        assert!(unknown.is_err()); // no region is in staging
+
+        let both = ManifestBuilder::new("filtered")
+            .with(
+                "cronJobs",
+                "[{name: c, schedule: '0 * * * *', enabledIn: [dev], disabledIn: [preprod]}]",
+            )
+            .build(&conf, &region)
+            .await;
+        assert!(both.is_err());
+    }
+
+    #[tokio::test]
+    async fn builder_environment_defaults() {
+        let (conf, region) = setup().await;
+        let mf = ManifestBuilder::new("probed")
+            .with("readinessProbe", "{httpGet: {path: /ready}, timeoutSeconds: 3}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        let probe = mf.readinessProbe.unwrap();
+        assert_eq!(probe.initialDelaySeconds, 10); // dev environment default
+        assert_eq!(probe.timeoutSeconds, 3); // set on the probe
+        assert_eq!(probe.periodSeconds, 5); // builtin
+        assert!(mf.rollingUpdate.is_some()); // dev environment default
+
+        let mf = ManifestBuilder::new("unrolled")
+            .with("rollingUpdate", "{maxUnavailable: 1}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        let ru = serde_yaml::to_string(&mf.rollingUpdate).unwrap();
+        assert!(!ru.contains("maxSurge")); // manifests replace the whole default
+    }
+
+    #[tokio::test]
+    async fn builder_disruption_budget() {
+        let (conf, region) = setup().await;
+        let mf = ManifestBuilder::new("scaled")
+            .with("autoScaling", "{minReplicas: 4, maxReplicas: 8, metrics: []}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        let pdb = serde_yaml::to_string(&mf.podDisruptionBudget).unwrap();
+        assert!(pdb.contains("maxUnavailable: 25%")); // follows the autoscaler
+
+        let mf = ManifestBuilder::new("budgeted")
+            .with("podDisruptionBudget", "{minAvailable: 1}")
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        let pdb = mf.podDisruptionBudget.unwrap();
+        assert!(pdb.maxUnavailable.is_none());
+        assert!(pdb.verify(2, mf.rollingUpdate.as_ref()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn builder_sidecar_profiles() {
+        let (mut conf, region) = setup().await;
+        let telegraf = "{image: telegraf, version: 1.14.0, env: {INTERVAL: 10s, PORT: '8125'}}";
+        conf.sidecarProfiles
+            .insert("telegraf".into(), serde_yaml::from_str(telegraf).unwrap());
+        let mf = ManifestBuilder::new("sidecarred")
+            .with(
+                "sidecars",
+                "[{profile: telegraf, version: 1.15.0, env: {PORT: '8126'}}]",
+            )
+            .build(&conf, &region)
+            .await
+            .unwrap();
+        let sc = &mf.sidecars[0];
+        assert_eq!(sc.name, "telegraf"); // named after the profile
+        assert_eq!(sc.image, Some("telegraf".into()));
+        assert_eq!(sc.version, Some("1.15.0".into())); // overridden by the service
+        assert_eq!(sc.env.plain["INTERVAL"], "10s");
+        assert_eq!(sc.env.plain["PORT"], "8126");
+
+        let missing = ManifestBuilder::new("sidecarred")
+            .with("sidecars", "[{profile: statsd}]")
+            .build(&conf, &region)
+            .await;
+        assert!(missing.is_err());
+    }
+}
@@ -1,17 +1,18 @@
 use merge::Merge;
+use schemars::JsonSchema;
 use std::collections::BTreeMap;
 
 use shipcat_definitions::{
-    structs::{Authentication, Authorization, BabylonAuthHeader, Cors, Kong, KongRateLimit},
-    KongConfig, Region, Result,
+    structs::{Authentication, Authorization, BabylonAuthHeader, Cors, Kong, KongDeprecation, KongRateLimit},
+    Environment, KongConfig, Region, Result,
 };
 
 use super::{
     authorization::AuthorizationSource,
-    util::{Build, Enabled, EnabledMap},
+    util::{Build, Enabled, EnabledMap, EnvironmentFilter},
 };
 
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default)]
 pub struct KongApisSource {
     /// Default values to merge into every API
@@ -93,7 +94,26 @@ impl KongApisSource {
     }
 }
 
-#[derive(Deserialize, Default, Merge, Clone)]
+impl KongApisSource {
+    /// Build the Kong API of a worker, with the defaults of the service's APIs
+    pub fn build_worker_api(
+        &self,
+        worker: &str,
+        api: KongSource,
+        params: &KongApisBuildParams,
+    ) -> Result<Kong> {
+        let name = format!("{}-{}", params.service, worker);
+        debug!("Building Kong API {} for worker {}", &name, worker);
+        self.defaults.clone().merge(api).build(&KongBuildParams {
+            name: name.clone(),
+            service: name,
+            region: params.region.clone(),
+            kong: params.kong.clone(),
+        })
+    }
+}
+
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct KongSource {
     pub upstream_url: Option<String>,
@@ -121,6 +141,23 @@ pub struct KongSource {
 
     pub ip_rate_limits: Enabled<KongRateLimitSource>,
     pub user_rate_limits: Enabled<KongRateLimitSource>,
+
+    pub deprecation: Option<KongDeprecation>,
+
+    /// Environments to build the API in (see `EnvironmentFilter`)
+    #[serde(rename = "enabledIn")]
+    pub enabled_in: Option<Vec<Environment>>,
+    #[serde(rename = "disabledIn")]
+    pub disabled_in: Option<Vec<Environment>>,
+}
+
+impl KongSource {
+    pub fn environments(&self) -> EnvironmentFilter {
+        EnvironmentFilter {
+            enabled_in: self.enabled_in.clone(),
+            disabled_in: self.disabled_in.clone(),
+        }
+    }
 }
 
 struct KongBuildParams {
@@ -181,6 +218,8 @@ impl Build<Kong, KongBuildParams> for KongSource {
 
             ip_rate_limits: self.ip_rate_limits.build(&())?,
             user_rate_limits: self.user_rate_limits.build(&())?,
+            deprecation: self.deprecation,
+            canary: None,
         })
     }
 }
@@ -223,7 +262,7 @@ impl KongSource {
     }
 }
 
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct KongRateLimitSource {
     pub per_second: Option<u32>,
@@ -12,11 +12,18 @@ mod sentry_source;
 mod simple;
 pub use crate::simple::SimpleManifest;
 mod kong;
+mod probe;
 
 mod load;
+pub use crate::load::DefaultedValue;
 mod util;
 
-use manifest::ManifestSource;
+/// Builders of manifests, configs and regions for tests
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+
+use manifest::{ManifestOverrides, ManifestSource};
+use schemars::{schema::RootSchema, schema_for};
 use shipcat_definitions::{BaseManifest, Config, Manifest, Region, Result};
 
 pub async fn load_manifest(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
@@ -31,6 +38,36 @@ pub async fn all(conf: &Config) -> Result<Vec<BaseManifest>> {
     ManifestSource::all(conf).await
 }
 
+/// Like `all`, but without requiring the teams of services to be squads in teams.yml
+pub async fn all_unchecked(conf: &Config) -> Result<Vec<BaseManifest>> {
+    ManifestSource::all_unchecked(conf).await
+}
+
 pub async fn available(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
     ManifestSource::available(conf, reg).await
 }
+
+/// Like `available`, but including services that are disabled or external in the region
+pub async fn all_metadata(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
+    ManifestSource::all_metadata(conf, reg).await
+}
+
+/// Explain where the defaultable values of a service manifest come from
+pub async fn explain_defaults(service: &str, conf: &Config, reg: &Region) -> Result<Vec<DefaultedValue>> {
+    ManifestSource::explain_defaults(service, conf, reg).await
+}
+
+/// The chart used in a region by services not setting their own
+pub fn default_chart(conf: &Config, reg: &Region) -> Result<Option<String>> {
+    ManifestSource::default_chart(conf, reg)
+}
+
+/// JSON schema of service manifests (`manifest.yml`)
+pub fn manifest_schema() -> RootSchema {
+    schema_for!(ManifestSource)
+}
+
+/// JSON schema of region and environment overrides (`dev-uk.yml`, `prod.yml`, ...)
+pub fn overrides_schema() -> RootSchema {
+    schema_for!(ManifestOverrides)
+}
@@ -1,12 +1,15 @@
 use std::path::{Path, PathBuf};
 
 use merge::Merge;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 use shipcat_definitions::{Config, ErrorKind, Manifest, Region, Result, ResultExt};
 use walkdir::WalkDir;
 
 use super::{authorization::AuthorizationSource, util::Enabled, BaseManifest, SimpleManifest};
-use crate::manifest::{ManifestDefaults, ManifestOverrides, ManifestSource};
+use crate::{
+    manifest::{ManifestDefaults, ManifestOverrides, ManifestSource},
+    probe::ProbeTimingsSource,
+};
 
 impl ManifestSource {
     pub async fn load_manifest(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
@@ -28,21 +31,20 @@ impl ManifestSource {
     }
 
     async fn load_merged(service: &str, conf: &Config, reg: &Region) -> Result<Self> {
+        Self::load_service(service, reg).await?.with_defaults(conf, reg)
+    }
+
+    /// Merge the files of a service for a region, without any defaults
+    async fn load_service(service: &str, reg: &Region) -> Result<Self> {
         let dir = Self::services_dir().join(service);
 
         if !dir.exists() {
             bail!("Service folder {} does not exist", dir.display())
         }
 
-        let builtin_defaults = ManifestDefaults::builtin();
-        let global_defaults = ManifestDefaults::from_global(conf)?;
-        let regional_defaults = ManifestDefaults::from_region(reg)?;
-        let defaults = builtin_defaults.merge(global_defaults.merge(regional_defaults));
-
         let source_path = Self::services_dir().join(service).join("manifest.yml");
         debug!("Loading service manifest from {:?}", source_path);
-        let source: ManifestSource = read_from(&source_path).await?;
-        let mut manifest = defaults.merge_source(source);
+        let mut manifest: ManifestSource = read_from(&source_path).await?;
 
         let env_path = dir.join(format!("{}.yml", reg.environment.to_string()));
         if env_path.is_file() {
@@ -51,6 +53,13 @@ impl ManifestSource {
             manifest = manifest.merge_overrides(env);
         }
 
+        let template_path = dir.join("overrides.yml.j2");
+        if template_path.is_file() {
+            debug!("Loading templated service overrides from {:?}", template_path);
+            let templated: ManifestOverrides = read_template_from(&template_path, service, reg).await?;
+            manifest = manifest.merge_overrides(templated);
+        }
+
         let region_path = dir.join(format!("{}.yml", reg.name));
         if region_path.is_file() {
             debug!("Loading service overrides from {:?}", region_path);
@@ -61,6 +70,64 @@ impl ManifestSource {
         Ok(manifest)
     }
 
+    /// Merge a service manifest over the builtin, global, environment and regional defaults
+    pub(crate) fn with_defaults(self, conf: &Config, reg: &Region) -> Result<Self> {
+        let defaults = ManifestDefaults::layers(conf, reg)?
+            .into_iter()
+            .fold(ManifestDefaults::default(), |acc, (_, d)| acc.merge(d));
+        Ok(defaults.merge_source(self))
+    }
+
+    /// The chart used in a region by services not setting their own
+    pub fn default_chart(conf: &Config, reg: &Region) -> Result<Option<String>> {
+        let layers = ManifestDefaults::layers(conf, reg)?;
+        Ok(layers.into_iter().filter_map(|(_, d)| d.chart).last())
+    }
+
+    /// Explain where the defaultable values of a service come from
+    pub async fn explain_defaults(service: &str, conf: &Config, reg: &Region) -> Result<Vec<DefaultedValue>> {
+        let svc = Self::load_service(service, reg).await?;
+        let mut layers = ManifestDefaults::layers(conf, reg)?;
+        layers.push((format!("services/{}", service), svc.overrides.defaults.clone()));
+
+        let mut out = vec![];
+        explain(&mut out, "imagePrefix", &layers, |d| d.image_prefix.clone())?;
+        explain(&mut out, "chart", &layers, |d| d.chart.clone())?;
+        explain(&mut out, "replicaCount", &layers, |d| d.replica_count)?;
+        explain(&mut out, "rollingUpdate", &layers, |d| d.rolling_update.clone())?;
+
+        type Timing = fn(&ProbeTimingsSource) -> Option<u32>;
+        let timings: [(&str, Timing); 5] = [
+            ("initialDelaySeconds", |t| t.initial_delay_seconds),
+            ("periodSeconds", |t| t.period_seconds),
+            ("successThreshold", |t| t.success_threshold),
+            ("failureThreshold", |t| t.failure_threshold),
+            ("timeoutSeconds", |t| t.timeout_seconds),
+        ];
+        let probes = vec![
+            ("readinessProbe", &svc.overrides.readiness_probe),
+            ("livenessProbe", &svc.overrides.liveness_probe),
+        ];
+        for (name, probe) in probes {
+            let probe = match probe {
+                Some(p) => p,
+                None => continue,
+            };
+            // timings set on the probe itself win over all defaults
+            let mut probe_layers = layers.clone();
+            let own = ManifestDefaults {
+                probe_timings: probe.timings.clone(),
+                ..ManifestDefaults::default()
+            };
+            probe_layers.push((format!("services/{}", service), own));
+            for (timing, get) in &timings {
+                let key = format!("{}.{}", name, timing);
+                explain(&mut out, &key, &probe_layers, |d| get(&d.probe_timings))?;
+            }
+        }
+        Ok(out)
+    }
+
     fn all_names() -> Vec<String> {
         let mut res: Vec<_> = WalkDir::new(&ManifestSource::services_dir())
             .min_depth(1)
@@ -97,17 +164,37 @@ impl ManifestSource {
         Ok(all)
     }
 
+    /// Base manifests of every service, including those whose team is not in teams.yml
+    pub async fn all_unchecked(conf: &Config) -> Result<Vec<BaseManifest>> {
+        let mut all = vec![];
+        for service in Self::all_names() {
+            let source_path = Self::services_dir().join(&service).join("manifest.yml");
+            let source: ManifestSource = read_from(&source_path)
+                .await
+                .chain_err(|| ErrorKind::InvalidManifest(service.clone()))?;
+            let manifest = source
+                .build_base_unchecked(conf)
+                .chain_err(|| ErrorKind::InvalidManifest(service.clone()))?;
+            all.push(manifest);
+        }
+        Ok(all)
+    }
+
     pub async fn available(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
-        let mut available = vec![];
+        let all = Self::all_metadata(conf, reg).await?;
+        Ok(all.into_iter().filter(|mf| mf.enabled && !mf.external).collect())
+    }
+
+    /// Simple manifests of every service for a region, including disabled and external ones
+    pub async fn all_metadata(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
+        let mut all = vec![];
         for service in Self::all_names() {
             let manifest = Self::load_metadata(&service, conf, reg)
                 .await
                 .chain_err(|| ErrorKind::InvalidManifest(service.clone()))?;
-            if manifest.enabled && !manifest.external {
-                available.push(manifest);
-            }
+            all.push(manifest);
         }
-        Ok(available)
+        Ok(all)
     }
 
     fn services_dir() -> PathBuf {
@@ -115,11 +202,52 @@ impl ManifestSource {
     }
 }
 
+/// A value that can come from defaults, and the layer that set it
+#[derive(Serialize, Debug)]
+pub struct DefaultedValue {
+    /// Key of the value in the manifest
+    pub key: String,
+    pub value: serde_yaml::Value,
+    /// Layer the value was taken from
+    pub source: String,
+}
+
+/// Record the value of a field from the last layer that sets it
+fn explain<T: Serialize>(
+    out: &mut Vec<DefaultedValue>,
+    key: &str,
+    layers: &[(String, ManifestDefaults)],
+    field: impl Fn(&ManifestDefaults) -> Option<T>,
+) -> Result<()> {
+    if let Some((source, value)) = layers.iter().filter_map(|(n, d)| field(d).map(|v| (n, v))).last() {
+        out.push(DefaultedValue {
+            key: key.into(),
+            value: serde_yaml::to_value(value)?,
+            source: source.clone(),
+        });
+    }
+    Ok(())
+}
+
 impl ManifestDefaults {
+    /// Layers of defaults for a region, from the lowest priority to the highest
+    fn layers(conf: &Config, reg: &Region) -> Result<Vec<(String, Self)>> {
+        Ok(vec![
+            ("builtin".into(), Self::builtin()),
+            ("shipcat.conf defaults".into(), Self::from_global(conf)?),
+            (
+                format!("shipcat.conf environmentDefaults.{}", reg.environment.to_string()),
+                Self::from_environment(conf, reg)?,
+            ),
+            (format!("region {} defaults", reg.name), Self::from_region(reg)?),
+        ])
+    }
+
     fn builtin() -> Self {
         let mut defaults = Self::default();
         defaults.kong_apis.defaults.ip_rate_limits.enabled = Some(false);
         defaults.kong_apis.defaults.user_rate_limits.enabled = Some(false);
+        defaults.probe_timings = ProbeTimingsSource::builtin();
         defaults
     }
 
@@ -130,6 +258,20 @@ impl ManifestDefaults {
         }
     }
 
+    fn from_environment(conf: &Config, reg: &Region) -> Result<Self> {
+        match conf.environmentDefaults.get(&reg.environment) {
+            None => Ok(Self::default()),
+            Some(defaults) => match serde_yaml::from_value(defaults.clone()) {
+                Err(e) => bail!(
+                    "Environment {} defaults did not parse as YAML: {}",
+                    reg.environment.to_string(),
+                    e
+                ),
+                Ok(d) => Ok(d),
+            },
+        }
+    }
+
     fn from_region(reg: &Region) -> Result<Self> {
         // TODO: Remove Region#defaults and Region#env
         Ok(
@@ -195,6 +337,21 @@ async fn read_from<T: DeserializeOwned>(path: &PathBuf) -> Result<T> {
     }
 }
 
+/// Read a yaml file after rendering it as a template for the region
+async fn read_template_from<T: DeserializeOwned>(path: &PathBuf, service: &str, reg: &Region) -> Result<T> {
+    use tokio::fs;
+    trace!("Reading manifest template in {}", path.display());
+    let tpl = fs::read_to_string(&path).await?;
+    let data = reg.template_overrides(service, tpl)?;
+    if data.trim().is_empty() {
+        bail!("Manifest file {} rendered empty for {}", path.display(), reg.name);
+    }
+    match serde_yaml::from_str(&data) {
+        Err(e) => bail!("Manifest file {} did not parse as YAML: {}", path.display(), e),
+        Ok(d) => Ok(d),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, fs, path::Path};
@@ -219,6 +376,9 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(manifest.name, "fake-ask".to_string());
+        // from overrides.yml.j2
+        assert_eq!(&manifest.env.plain["REGION_NAME"], "dev-uk");
+        assert_eq!(&manifest.env.plain["STATUS_URL"], "https://woot.com/status");
     }
 
     #[tokio::test]
@@ -236,6 +396,28 @@ mod tests {
         assert_eq!(manifest.image, Some("quay.io/babylonhealth/fake-ask".into()));
     }
 
+    #[tokio::test]
+    async fn explain_fake_ask() {
+        setup();
+
+        let conf = Config::read().await.unwrap();
+        let region = conf.get_region("dev-uk").unwrap();
+
+        let explained = ManifestSource::explain_defaults("fake-ask", &conf, &region)
+            .await
+            .unwrap();
+        let source = |key: &str| {
+            explained
+                .iter()
+                .find(|d| d.key == key)
+                .map(|d| d.source.clone())
+                .unwrap()
+        };
+        assert_eq!(source("chart"), "shipcat.conf defaults");
+        assert_eq!(source("replicaCount"), "services/fake-ask");
+        assert_eq!(source("rollingUpdate"), "shipcat.conf environmentDefaults.dev");
+    }
+
     #[tokio::test]
     async fn all() {
         setup();
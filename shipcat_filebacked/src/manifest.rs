@@ -1,7 +1,8 @@
 #![allow(non_snake_case)]
 
 use merge::Merge;
-use std::collections::BTreeMap;
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, BTreeSet};
 
 use shipcat_definitions::{
     structs::{
@@ -10,9 +11,11 @@ use shipcat_definitions::{
         security::DataHandling,
         tolerations::Tolerations,
         volume::Volume,
-        ConfigMap, Dependency, DestinationRule, EventStream, Gate, HealthCheck, HostAlias, Kafka,
-        KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe, PrometheusAlert,
-        Rbac, RollingUpdate, SecurityContext, VaultOpts, VolumeMount,
+        ConfigMap, Dependency, DestinationRule, DisabledRegion, EventStream, Gate, HealthCheck, HostAlias,
+        InfraDependency, Kafka, KafkaResources, LifeCycle, Metadata, Mtls, NetworkPolicyMode,
+        NotificationMode, PersistentVolume, PodDisruptionBudget, PrometheusAlert, PrometheusRecordingRule,
+        Rbac, RollingUpdate, SecurityContext, ServiceAccount, ServiceOptions, Slo, SmokeTests, Sunset,
+        VaultOpts, VolumeMount,
     },
     BaseManifest, Config, Manifest, PrimaryWorkload, Region, Result,
 };
@@ -20,17 +23,19 @@ use shipcat_definitions::{
 use super::{
     container::{
         ContainerBuildParams, CronJobSource, EnvVarsSource, ImageNameSource, ImageTagSource,
-        InitContainerSource, PortSource, ResourceRequirementsSource, SidecarSource, WorkerSource,
+        InitContainerSource, PortSource, ResourceRequirementsSource, SidecarBuildParams, SidecarSource,
+        WorkerSource,
     },
     kong::{KongApisBuildParams, KongApisSource, KongSource},
     newrelic_source::NewrelicSource,
+    probe::{ProbeSource, ProbeTimingsSource},
     sentry_source::SentrySource,
-    util::{Build, Enabled, RelaxedString, Require},
+    util::{Build, Enabled, EnvironmentFilter, RelaxedString, Require},
     SimpleManifest,
 };
 
 /// Helper for optional string/list of string structs
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum OneOrMany<T> {
     One(T),
@@ -43,7 +48,7 @@ impl<T> Default for OneOrMany<T> {
     }
 }
 
-#[derive(Deserialize, Default, Clone)]
+#[derive(Deserialize, Default, Clone, JsonSchema)]
 #[serde(default)]
 pub struct MetadataSource {
     pub repo: String,
@@ -69,6 +74,7 @@ pub struct MetadataSource {
     pub releasePlan: Option<String>,
     pub threatModel: OneOrMany<String>,
     pub dpsia: OneOrMany<String>,
+    pub sunset: Option<Sunset>,
 
     // TODO: generate swagger docs url from region and service name
     /// Custom metadata, keys defined in the Config
@@ -77,13 +83,14 @@ pub struct MetadataSource {
 }
 
 /// Main manifest, deserialized from `manifest.yml`
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase")]
 pub struct ManifestSource {
     pub name: Option<String>,
     pub external: bool,
     pub disabled: bool,
     pub regions: Vec<String>,
+    pub disabled_in: Vec<DisabledRegion>,
     pub metadata: Option<MetadataSource>,
 
     #[serde(flatten)]
@@ -91,7 +98,7 @@ pub struct ManifestSource {
 }
 
 /// Manifest overrides, deserialized from `dev-uk.yml`/`prod.yml` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestOverrides {
     pub workload: Option<PrimaryWorkload>,
@@ -112,14 +119,18 @@ pub struct ManifestOverrides {
     pub external_port: Option<u32>,
     pub health: Option<HealthCheck>,
     pub dependencies: Option<Vec<Dependency>>,
+    pub infra_dependencies: Option<Vec<InfraDependency>>,
+    pub strict_egress: Option<bool>,
+    pub network_policies: Option<NetworkPolicyMode>,
     pub destination_rules: Option<Vec<DestinationRule>>,
+    pub mtls: Option<Mtls>,
     pub workers: Option<Vec<WorkerSource>>,
     pub sidecars: Option<Vec<SidecarSource>>,
-    pub readiness_probe: Option<Probe>,
-    pub liveness_probe: Option<Probe>,
+    pub readiness_probe: Option<ProbeSource>,
+    pub liveness_probe: Option<ProbeSource>,
     pub lifecycle: Option<LifeCycle>,
-    pub rolling_update: Option<RollingUpdate>,
     pub auto_scaling: Option<AutoScaling>,
+    pub pod_disruption_budget: Option<PodDisruptionBudget>,
     pub tolerations: Option<Vec<Tolerations>>,
     pub host_aliases: Option<Vec<HostAlias>>,
     pub init_containers: Option<Vec<InitContainerSource>>,
@@ -128,12 +139,14 @@ pub struct ManifestOverrides {
     pub persistent_volumes: Option<Vec<PersistentVolume>>,
     pub cron_jobs: Option<Vec<CronJobSource>>,
     pub service_annotations: BTreeMap<String, String>,
+    pub service: Option<ServiceOptions>,
     pub pod_annotations: BTreeMap<String, RelaxedString>,
     pub labels: BTreeMap<String, RelaxedString>,
     pub gate: Option<Gate>,
     pub kafka: Option<Kafka>,
     pub source_ranges: Option<Vec<String>>,
     pub rbac: Option<Vec<Rbac>>,
+    pub service_account: Option<ServiceAccount>,
     pub sentry: Option<SentrySource>,
     pub event_streams: Option<Vec<EventStream>>,
     pub kafka_resources: Option<KafkaResources>,
@@ -142,13 +155,17 @@ pub struct ManifestOverrides {
     pub newrelic: NewrelicSource,
     pub upgrade_notifications: Option<NotificationMode>,
     pub prometheus_alerts: Option<Vec<PrometheusAlert>>,
+    pub slos: Option<Slo>,
+    pub smoke_tests: Option<SmokeTests>,
+    #[schemars(with = "BTreeMap<String, serde_json::Value>")]
+    pub chart_values: BTreeMap<String, serde_yaml::Value>,
 
     #[serde(flatten)]
     pub defaults: ManifestDefaults,
 }
 
 /// Global/regional manifest defaults, deserialized from `shipcat.conf` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestDefaults {
     pub image_prefix: Option<String>,
@@ -158,25 +175,52 @@ pub struct ManifestDefaults {
     pub kong_apis: KongApisSource,
     // TODO: Migrate to kong_apis
     pub kong: Enabled<KongSource>,
+    pub rolling_update: Option<RollingUpdate>,
+    /// Timings for probes that do not set their own
+    pub probe_timings: ProbeTimingsSource,
 }
 
 // impl Build<Manifest, (Config, Region)> - but no need to have this as a trait
 impl ManifestSource {
     /// Build a Manifest from a ManifestSource, validating and mutating properties.
     pub async fn build(self, (conf, region): &(Config, Region)) -> Result<Manifest> {
-        let simple = self.build_simple(conf, region)?;
+        let mut simple = self.build_simple(conf, region)?;
         let name = simple.base.name;
         let data_handling = self.build_data_handling();
         let kafka = self.build_kafka(&name, region);
         let configs = self.build_configs(&name).await?;
+        let (prometheus_alerts, slo_recording_rules) =
+            self.build_prometheus_rules(&name, &simple.base.metadata.team, conf, region)?;
 
-        let overrides = self.overrides;
+        let overrides = self.overrides.in_environment(conf, region)?;
         let defaults = overrides.defaults;
 
         let container_build_params = ContainerBuildParams {
             main_envs: defaults.env.clone(),
         };
 
+        let sidecar_build_params = SidecarBuildParams {
+            container: &container_build_params,
+            profiles: &conf.sidecarProfiles,
+        };
+
+        let mut workers = overrides
+            .workers
+            .unwrap_or_default()
+            .build(&container_build_params)?;
+        for w in &mut workers {
+            w.kong = simple.worker_kong_apis.remove(&w.container.name);
+        }
+
+        let replicas = match &overrides.auto_scaling {
+            Some(hpa) => Some(hpa.minReplicas),
+            None => defaults.replica_count,
+        };
+        let autoscaled = overrides.auto_scaling.is_some();
+        let pod_disruption_budget = overrides
+            .pod_disruption_budget
+            .or_else(|| replicas.map(|r| PodDisruptionBudget::derived(r, autoscaled)));
+
         let team_notifications = simple
             .base
             .metadata
@@ -194,6 +238,7 @@ impl ManifestSource {
             disabled: self.disabled,
             // TODO: Must be non-empty
             regions: simple.base.regions,
+            disabledIn: simple.base.disabled_in,
             // TODO: Make metadata non-optional
             metadata: Some(simple.base.metadata),
             chart: defaults.chart,
@@ -215,20 +260,22 @@ impl ManifestSource {
             externalPort: overrides.external_port,
             health: overrides.health,
             dependencies: overrides.dependencies.unwrap_or_default(),
+            infraDependencies: overrides.infra_dependencies.unwrap_or_default(),
+            strictEgress: overrides.strict_egress.unwrap_or_default(),
+            networkPolicies: overrides.network_policies,
             destinationRules: overrides.destination_rules,
-            workers: overrides
-                .workers
-                .unwrap_or_default()
-                .build(&container_build_params)?,
+            mtls: overrides.mtls,
+            workers,
             sidecars: overrides
                 .sidecars
                 .unwrap_or_default()
-                .build(&container_build_params)?,
-            readinessProbe: overrides.readiness_probe,
-            livenessProbe: overrides.liveness_probe,
+                .build(&sidecar_build_params)?,
+            readinessProbe: overrides.readiness_probe.build(&defaults.probe_timings)?,
+            livenessProbe: overrides.liveness_probe.build(&defaults.probe_timings)?,
             lifecycle: overrides.lifecycle,
-            rollingUpdate: overrides.rolling_update,
+            rollingUpdate: defaults.rolling_update,
             autoScaling: overrides.auto_scaling,
+            podDisruptionBudget: pod_disruption_budget,
             tolerations: overrides.tolerations.unwrap_or_default(),
             hostAliases: overrides.host_aliases.unwrap_or_default(),
             initContainers: overrides
@@ -243,6 +290,7 @@ impl ManifestSource {
                 .unwrap_or_default()
                 .build(&container_build_params)?,
             serviceAnnotations: overrides.service_annotations,
+            service: overrides.service,
             podAnnotations: overrides.pod_annotations.build(&())?,
             labels: overrides.labels.build(&())?,
             kongApis: simple.kong_apis,
@@ -250,6 +298,7 @@ impl ManifestSource {
             kafka: kafka,
             sourceRanges: overrides.source_ranges.unwrap_or_default(),
             rbac: overrides.rbac.unwrap_or_default(),
+            serviceAccount: overrides.service_account,
             newrelic: overrides.newrelic.build(&team_notifications)?,
             sentry: overrides
                 .sentry
@@ -258,14 +307,19 @@ impl ManifestSource {
             eventStreams: overrides.event_streams.unwrap_or_default(),
             kafkaResources: overrides.kafka_resources,
             upgradeNotifications: Default::default(),
+            chartValues: overrides.chart_values,
             region: region.name.clone(),
             environment: region.environment.to_string(),
             namespace: region.namespace.clone(),
             uid: Default::default(),
+            canary: Default::default(),
             secrets: Default::default(),
             state: Default::default(),
             workload: overrides.workload.unwrap_or_default(),
-            prometheusAlerts: overrides.prometheus_alerts.unwrap_or_default(),
+            slos: overrides.slos,
+            smokeTests: overrides.smoke_tests,
+            prometheusAlerts: prometheus_alerts,
+            sloRecordingRules: slo_recording_rules,
         })
     }
 }
@@ -274,43 +328,63 @@ impl ManifestSource {
     pub fn build_simple(&self, conf: &Config, region: &Region) -> Result<SimpleManifest> {
         let base = self.build_base(conf)?;
 
-        let overrides = self.overrides.clone();
+        let overrides = self.overrides.clone().in_environment(conf, region)?;
         let defaults = overrides.defaults;
-        let kong_apis = if let Some(k) = &region.kong {
-            defaults.kong_apis.build(&KongApisBuildParams {
+        let (kong_apis, worker_kong_apis) = if let Some(k) = &region.kong {
+            let params = KongApisBuildParams {
                 service: base.name.to_string(),
                 region: region.clone(),
                 kong: k.clone(),
                 single_api: defaults.kong,
-            })?
+            };
+            let mut worker_apis = BTreeMap::new();
+            for w in overrides.workers.iter().flatten() {
+                if let Some((name, api)) = w.build_kong(&defaults.kong_apis, &params)? {
+                    worker_apis.insert(name, api);
+                }
+            }
+            (defaults.kong_apis.build(&params)?, worker_apis)
         } else {
             // NB: this drops kong entries on the floor if region.kong is None
-            vec![]
+            (vec![], BTreeMap::new())
         };
 
         Ok(SimpleManifest {
             region: region.name.to_string(),
 
-            enabled: !self.disabled && base.regions.contains(&region.name),
+            enabled: !self.disabled
+                && base.regions.contains(&region.name)
+                && !base.disabled_in.iter().any(|d| d.region == region.name),
             external: self.external,
 
             // TODO: Make image non-optional
             image: Some(self.build_image(&base.name)?),
             version: overrides.version.build(&())?,
             kong_apis,
+            worker_kong_apis,
             base,
         })
     }
 
     pub fn build_base(&self, conf: &Config) -> Result<BaseManifest> {
+        self.build_base_with(self.build_metadata(conf)?)
+    }
+
+    /// Build the base manifest without requiring its team to be a squad in teams.yml
+    pub fn build_base_unchecked(&self, conf: &Config) -> Result<BaseManifest> {
+        self.build_base_with(self.build_metadata_unchecked(conf))
+    }
+
+    fn build_base_with(&self, metadata: Metadata) -> Result<BaseManifest> {
         // TODO: Remove and use folder name
         let name = self.name.clone().require("name")?;
-        let metadata = self.build_metadata(conf)?;
         let regions = self.regions.clone();
+        let disabled_in = self.disabled_in.clone();
 
         Ok(BaseManifest {
             name,
             regions,
+            disabled_in,
             metadata,
         })
     }
@@ -330,7 +404,26 @@ impl ManifestSource {
 
     fn build_metadata(&self, conf: &Config) -> Result<Metadata> {
         let name = self.name.as_ref().expect("manifest name");
-        let mut md = self.metadata.clone().require("metadata")?;
+        self.metadata.as_ref().require("metadata")?;
+        let md = self.build_metadata_unchecked(conf);
+        if md.squad.is_none() {
+            bail!(
+                "{}: metadata.team '{}' must match a squad in teams.yml",
+                name,
+                md.team
+            )
+        }
+
+        // teams.yml needs to have these specified
+        if md.notifications.is_none() || md.support.is_none() {
+            bail!("Need a notification and support channel for {}", md.team);
+        }
+        Ok(md)
+    }
+
+    /// Metadata with the squad's channels filled in, when the team is a squad in teams.yml
+    fn build_metadata_unchecked(&self, conf: &Config) -> Metadata {
+        let mut md = self.metadata.clone().unwrap_or_default();
 
         if let Some(s) = conf.owners.squads.get(&md.team) {
             md.squad = Some(s.name.clone());
@@ -346,20 +439,9 @@ impl ManifestSource {
             if md.notifications.is_none() {
                 md.notifications = s.slack.notifications.as_ref().map(Clone::clone);
             }
-        } else {
-            bail!(
-                "{}: metadata.team '{}' must match a squad in teams.yml",
-                name,
-                md.team
-            )
         }
 
-        // teams.yml needs to have these specified
-        if md.notifications.is_none() || md.support.is_none() {
-            bail!("Need a notification and support channel for {}", md.team);
-        }
-
-        Ok(Metadata {
+        Metadata {
             repo: md.repo,
             team: md.team,
             context: md.context,
@@ -385,8 +467,9 @@ impl ManifestSource {
                 OneOrMany::One(x) => vec![x],
                 OneOrMany::Many(xs) => xs,
             },
+            sunset: md.sunset,
             custom: md.custom,
-        })
+        }
     }
 
     // TODO: Extract DataHandlingSource
@@ -407,6 +490,25 @@ impl ManifestSource {
         })
     }
 
+    fn build_prometheus_rules(
+        &self,
+        service: &str,
+        team: &str,
+        conf: &Config,
+        region: &Region,
+    ) -> Result<(Vec<PrometheusAlert>, Vec<PrometheusRecordingRule>)> {
+        let mut alerts = self.overrides.prometheus_alerts.clone().unwrap_or_default();
+        let mut rules = vec![];
+        if let Some(slo) = &self.overrides.slos {
+            alerts.extend(slo.alerts(service)?);
+            rules = slo.recording_rules(service)?;
+        }
+        for a in &mut alerts {
+            a.route(team, &conf.owners, region.alertRouting.as_ref());
+        }
+        Ok((alerts, rules))
+    }
+
     // TODO: Extract ConfigsSource
     async fn build_configs(&self, service: &str) -> Result<Option<ConfigMap>> {
         let original = &self.overrides.configs;
@@ -452,6 +554,30 @@ async fn read_template_file(svc: &str, tmpl: &str) -> Result<String> {
     Ok(data)
 }
 
+impl ManifestOverrides {
+    /// Strip the workers, sidecars, cronJobs and kongApis filtered out of a region's environment
+    fn in_environment(mut self, conf: &Config, region: &Region) -> Result<Self> {
+        let known = conf
+            .get_regions()
+            .into_iter()
+            .map(|r| r.environment)
+            .collect::<BTreeSet<_>>();
+        let env = &region.environment;
+        self.workers = EnvironmentFilter::retain(self.workers, env, &known, |w| &w.environments)?;
+        self.sidecars = EnvironmentFilter::retain(self.sidecars, env, &known, |s| &s.environments)?;
+        self.cron_jobs = EnvironmentFilter::retain(self.cron_jobs, env, &known, |c| &c.environments)?;
+
+        let mut apis = vec![];
+        for (name, api) in self.defaults.kong_apis.apis {
+            if api.item.environments().allows(env, &known)? {
+                apis.push((name, api));
+            }
+        }
+        self.defaults.kong_apis.apis = apis.into_iter().collect();
+        Ok(self)
+    }
+}
+
 impl ManifestDefaults {
     pub(crate) fn merge_source(self, mut other: ManifestSource) -> ManifestSource {
         other.overrides.defaults = self.merge(other.overrides.defaults);
@@ -503,4 +629,13 @@ mod tests {
         expected_env.insert("c", "override-c");
         assert_eq!(merged.env, expected_env.into());
     }
+
+    #[test]
+    fn manifest_schemas() {
+        let mf = serde_json::to_value(crate::manifest_schema()).unwrap();
+        assert!(mf["properties"]["name"].is_object());
+        assert!(mf["properties"]["kongApis"].is_object());
+        let ovr = serde_json::to_value(crate::overrides_schema()).unwrap();
+        assert!(ovr["properties"]["name"].is_null());
+    }
 }
@@ -0,0 +1,72 @@
+use merge::Merge;
+use schemars::JsonSchema;
+
+use shipcat_definitions::{
+    structs::{Exec, HttpGet, Probe, TcpSocket},
+    Result,
+};
+
+use crate::util::{Build, Require};
+
+/// Probe timings, which can be defaulted per environment
+///
+/// ```yaml
+/// probeTimings:
+///   initialDelaySeconds: 10
+///   failureThreshold: 5
+/// ```
+#[derive(Deserialize, Default, Merge, Clone, JsonSchema)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ProbeTimingsSource {
+    pub initial_delay_seconds: Option<u32>,
+    pub period_seconds: Option<u32>,
+    pub success_threshold: Option<u32>,
+    pub failure_threshold: Option<u32>,
+    pub timeout_seconds: Option<u32>,
+}
+
+impl ProbeTimingsSource {
+    /// Timings used when nothing else is configured
+    ///
+    /// Kubernetes defaults, except for a longer initial delay and a shorter period.
+    pub fn builtin() -> Self {
+        ProbeTimingsSource {
+            initial_delay_seconds: Some(30),
+            period_seconds: Some(5),
+            success_threshold: Some(1),
+            failure_threshold: Some(3),
+            timeout_seconds: Some(1),
+        }
+    }
+}
+
+/// Liveness or readiness probe, with timings falling back to defaults
+#[derive(Deserialize, Default, Clone, JsonSchema)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ProbeSource {
+    pub http_get: Option<HttpGet>,
+    pub exec: Option<Exec>,
+    pub tcp_socket: Option<TcpSocket>,
+
+    #[serde(flatten)]
+    pub timings: ProbeTimingsSource,
+}
+
+/// Builds the probe, using default `ProbeTimingsSource` for unset timings.
+impl Build<Probe, ProbeTimingsSource> for ProbeSource {
+    fn build(self, defaults: &ProbeTimingsSource) -> Result<Probe> {
+        let timings = ProbeTimingsSource::builtin()
+            .merge(defaults.clone())
+            .merge(self.timings);
+        Ok(Probe {
+            httpGet: self.http_get,
+            exec: self.exec,
+            tcpSocket: self.tcp_socket,
+            initialDelaySeconds: timings.initial_delay_seconds.require("initialDelaySeconds")?,
+            periodSeconds: timings.period_seconds.require("periodSeconds")?,
+            successThreshold: timings.success_threshold.require("successThreshold")?,
+            failureThreshold: timings.failure_threshold.require("failureThreshold")?,
+            timeoutSeconds: timings.timeout_seconds.require("timeoutSeconds")?,
+        })
+    }
+}
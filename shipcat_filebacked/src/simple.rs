@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt};
 
 use shipcat_definitions::{structs::Kong, BaseManifest};
 
@@ -14,6 +14,8 @@ pub struct SimpleManifest {
     pub version: Option<String>,
     pub image: Option<String>,
     pub kong_apis: Vec<Kong>,
+    /// Kong APIs of workers, by worker name
+    pub worker_kong_apis: BTreeMap<String, Kong>,
 }
 
 impl fmt::Debug for SimpleManifest {
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use std::collections::BTreeMap;
 
 use merge::Merge;
@@ -23,10 +24,10 @@ use super::Build;
 ///     value: 3
 /// bar: ~
 /// ```
-#[derive(Deserialize, Default, Clone, PartialEq, Merge)]
+#[derive(Deserialize, Default, Clone, PartialEq, Merge, JsonSchema)]
 #[cfg_attr(test, derive(Debug, Copy))]
 #[serde(default, deny_unknown_fields)]
-pub struct Enabled<T: Merge> {
+pub struct Enabled<T: Merge + Default> {
     pub enabled: Option<bool>,
 
     #[serde(flatten)]
@@ -34,7 +35,7 @@ pub struct Enabled<T: Merge> {
 }
 
 /// Builds the inner struct unless enabled is explicitly false.
-impl<S: Build<B, P> + Merge, B, P> Build<Option<B>, P> for Enabled<S> {
+impl<S: Build<B, P> + Merge + Default, B, P> Build<Option<B>, P> for Enabled<S> {
     fn build(self, params: &P) -> Result<Option<B>> {
         match self.enabled {
             Some(false) => Ok(None),
@@ -46,7 +47,7 @@ impl<S: Build<B, P> + Merge, B, P> Build<Option<B>, P> for Enabled<S> {
 /// EnabledMap is a map where each value is wrapped in an Enabled.
 ///
 /// It can be built into a map which flattens the Enabled wrappers, so disabled values are excluded.
-#[derive(Deserialize, Default, Clone, PartialEq)]
+#[derive(Deserialize, Default, Clone, PartialEq, JsonSchema)]
 #[cfg_attr(test, derive(Debug))]
 pub struct EnabledMap<K: Clone + std::hash::Hash + Ord, V: Clone + Default + Merge>(BTreeMap<K, Enabled<V>>);
 
@@ -56,6 +57,14 @@ impl<K: Clone + std::hash::Hash + Ord, V: Clone + Default + Merge> EnabledMap<K,
     }
 }
 
+impl<K: Clone + std::hash::Hash + Ord, V: Clone + Default + Merge> std::iter::FromIterator<(K, Enabled<V>)>
+    for EnabledMap<K, V>
+{
+    fn from_iter<I: IntoIterator<Item = (K, Enabled<V>)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
 impl<K: Clone + std::hash::Hash + Ord, V: Clone + Default + Merge> IntoIterator for EnabledMap<K, V> {
     type IntoIter = std::collections::btree_map::IntoIter<K, Enabled<V>>;
     type Item = (K, Enabled<V>);
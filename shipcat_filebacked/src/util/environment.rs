@@ -0,0 +1,109 @@
+use merge::Merge;
+use schemars::JsonSchema;
+use std::collections::BTreeSet;
+
+use shipcat_definitions::{Environment, Result};
+
+/// EnvironmentFilter limits a sub-resource to some environments.
+///
+/// ```yaml
+/// workers:
+/// - name: reconciler
+///   enabledIn: [prod]
+/// cronJobs:
+/// - name: cleanup
+///   disabledIn: [prod, preprod]
+/// ```
+///
+/// Only one of `enabledIn` and `disabledIn` can be set.
+#[derive(Deserialize, Default, Clone, PartialEq, Merge, JsonSchema)]
+#[cfg_attr(test, derive(Debug))]
+#[serde(default, rename_all = "camelCase")]
+pub struct EnvironmentFilter {
+    pub enabled_in: Option<Vec<Environment>>,
+    pub disabled_in: Option<Vec<Environment>>,
+}
+
+impl EnvironmentFilter {
+    /// Whether the filter lets an item into an environment
+    ///
+    /// Every named environment must be one of the `known` environments of the regions.
+    pub fn allows(&self, env: &Environment, known: &BTreeSet<Environment>) -> Result<bool> {
+        for e in self.enabled_in.iter().chain(self.disabled_in.iter()).flatten() {
+            if !known.contains(e) {
+                bail!("Environment {} is not used by any region", e.to_string());
+            }
+        }
+        match (&self.enabled_in, &self.disabled_in) {
+            (Some(_), Some(_)) => bail!("enabledIn and disabledIn are mutually exclusive"),
+            (Some(envs), None) => Ok(envs.contains(env)),
+            (None, Some(envs)) => Ok(!envs.contains(env)),
+            (None, None) => Ok(true),
+        }
+    }
+
+    /// Keep the items of a list that the filters let into an environment
+    pub fn retain<T, F>(
+        items: Option<Vec<T>>,
+        env: &Environment,
+        known: &BTreeSet<Environment>,
+        filter: F,
+    ) -> Result<Option<Vec<T>>>
+    where
+        F: Fn(&T) -> &EnvironmentFilter,
+    {
+        let items = match items {
+            Some(items) => items,
+            None => return Ok(None),
+        };
+        let mut kept = vec![];
+        for item in items {
+            if filter(&item).allows(env, known)? {
+                kept.push(item);
+            }
+        }
+        Ok(Some(kept))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvironmentFilter;
+    use shipcat_definitions::Environment;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn allows() {
+        let known = vec![Environment::Dev, Environment::Prod]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let everywhere = EnvironmentFilter::default();
+        assert!(everywhere.allows(&Environment::Dev, &known).unwrap());
+
+        let prod_only = EnvironmentFilter {
+            enabled_in: Some(vec![Environment::Prod]),
+            disabled_in: None,
+        };
+        assert!(prod_only.allows(&Environment::Prod, &known).unwrap());
+        assert!(!prod_only.allows(&Environment::Dev, &known).unwrap());
+
+        let not_prod = EnvironmentFilter {
+            enabled_in: None,
+            disabled_in: Some(vec![Environment::Prod]),
+        };
+        assert!(!not_prod.allows(&Environment::Prod, &known).unwrap());
+        assert!(not_prod.allows(&Environment::Dev, &known).unwrap());
+
+        let both = EnvironmentFilter {
+            enabled_in: Some(vec![Environment::Prod]),
+            disabled_in: Some(vec![Environment::Dev]),
+        };
+        assert!(both.allows(&Environment::Prod, &known).is_err());
+
+        let unknown = EnvironmentFilter {
+            enabled_in: Some(vec![Environment::Staging]),
+            disabled_in: None,
+        };
+        assert!(unknown.allows(&Environment::Dev, &known).is_err());
+    }
+}
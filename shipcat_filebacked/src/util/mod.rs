@@ -1,9 +1,11 @@
 mod build;
 mod enabled;
+mod environment;
 mod relaxedstring;
 mod require;
 
 pub use build::Build;
 pub use enabled::{Enabled, EnabledMap};
+pub use environment::EnvironmentFilter;
 pub use relaxedstring::RelaxedString;
 pub use require::Require;
@@ -0,0 +1,8 @@
+;; Source of loop.wasm: a validation plugin that never returns
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32)
+    i32.const 4096)
+  (func (export "validate") (param i32 i32) (result i64)
+    (loop $spin (br $spin))
+    i64.const 0))
@@ -0,0 +1,10 @@
+;; Source of warn.wasm: a validation plugin that always returns a single warning
+(module
+  (memory (export "memory") 1)
+  ;; input is written at a fixed offset
+  (func (export "alloc") (param i32) (result i32)
+    i32.const 4096)
+  ;; output pointer 2048 in the high bits, output length 50 in the low bits
+  (func (export "validate") (param i32 i32) (result i64)
+    i64.const 8796093022258)
+  (data (i32.const 2048) "[{\"level\":\"warning\",\"message\":\"fake-ask is fake\"}]"))